@@ -0,0 +1,51 @@
+//! Optional request fields and the protocol version that introduced each of them. A field is
+//! only sent to plugins whose negotiated protocol supports it, see `system_info`.
+
+/// Protocol of the plugins that don't report a `protocol_version`.
+pub const DEFAULT_PROTOCOL_VERSION: u32 = 1;
+/// Latest protocol this host speaks.
+pub const CURRENT_PROTOCOL_VERSION: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+  /// `format` of `stream_answer_v2` and `complete_text_v2`.
+  ResponseFormat,
+  /// `metadata` of `complete_text_v2`.
+  CompletionMetadata,
+  /// `filter` of `similarity_search`.
+  SearchFilter,
+  /// `score_threshold` and `max_context_tokens` of `create_chat`, and `rag` of
+  /// `stream_answer_v2`. `top_k` of `create_chat` is always sent.
+  RagOptions,
+  /// Prompts of the completion types added after the first protocol, see
+  /// [CompleteTextType::prompt_capability](crate::types::CompleteTextType::prompt_capability).
+  /// Older plugins answer them like [CompleteTextType::AskAI](crate::types::CompleteTextType::AskAI).
+  CompletionPrompts,
+  /// `score` of each `similarity_search` result.
+  SearchScores,
+  /// `skip_retrieval` of the `rag` of `stream_answer_v2`.
+  SkipRetrieval,
+  /// `report` of `embed_file`, answered with an
+  /// [EmbedReport](crate::types::EmbedReport) instead of failing on the first chunk.
+  EmbedReport,
+}
+
+impl Capability {
+  /// The first protocol version that accepts the field.
+  pub fn since(&self) -> u32 {
+    match self {
+      Capability::ResponseFormat => 1,
+      Capability::CompletionMetadata => 2,
+      Capability::SearchFilter => 1,
+      Capability::RagOptions => 3,
+      Capability::CompletionPrompts => 4,
+      Capability::SearchScores => 5,
+      Capability::SkipRetrieval => 5,
+      Capability::EmbedReport => 6,
+    }
+  }
+
+  pub fn is_supported_by(&self, protocol_version: u32) -> bool {
+    protocol_version >= self.since()
+  }
+}
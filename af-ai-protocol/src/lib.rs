@@ -0,0 +1,5 @@
+pub mod capability;
+pub mod method;
+pub mod parser;
+pub mod stream;
+pub mod types;
@@ -0,0 +1,92 @@
+//! Names of the methods sent in the `method` field of a `handle` request.
+//!
+//! A method whose params or reply changed incompatibly gets a new name with a version suffix, and
+//! the previous name keeps working for plugins that predate it.
+
+/// Reserved key of the params of any method, holding the id the host traces the operation with.
+pub const TRACE_ID_KEY: &str = "trace_id";
+/// Reserved key of the params of the chat methods, holding the model to use instead of the one of
+/// the plugin settings.
+pub const MODEL_NAME_KEY: &str = "model_name";
+
+pub const SYSTEM_INFO: &str = "system_info";
+/// Answered at once, to check that the plugin is alive while a stream is silent. Any reply, even
+/// an error, tells that it is.
+pub const PING: &str = "ping";
+pub const SET_LOG_LEVEL: &str = "set_log_level";
+/// Changes settings of the running plugin without restarting it, such as `model_name`.
+pub const UPDATE_SETTINGS: &str = "update_settings";
+/// Names of the models the Ollama server has, as `{"data": ["llama3.1:latest", ...]}`.
+pub const LIST_MODELS: &str = "list_models";
+/// What the model of `{"model": name}` supports, as `{"data": ...}` of a
+/// [crate::types::ModelCapabilities].
+pub const MODEL_CAPABILITIES: &str = "model_capabilities";
+
+pub const CREATE_CHAT: &str = "create_chat";
+pub const CLOSE_CHAT: &str = "close_chat";
+/// Whether the plugin has a chat, e.g. one created before the plugin restarted.
+pub const CHAT_EXISTS: &str = "chat_exists";
+/// The chats the plugin has, as `{"data": [...]}` of [crate::types::ChatSummary] objects, or of
+/// chat ids for plugins that only know those.
+pub const LIST_CHATS: &str = "list_chats";
+pub const ANSWER: &str = "answer";
+/// Answers like `answer`, with the chunks retrieved for the answer, as `{"data": ...}` of a
+/// [crate::types::AnswerWithSources].
+pub const ANSWER_WITH_SOURCES: &str = "answer_with_sources";
+/// Streams the answer as raw text.
+pub const STREAM_ANSWER: &str = "stream_answer";
+/// Streams the answer as JSON frames, see [crate::stream].
+pub const STREAM_ANSWER_V2: &str = "stream_answer_v2";
+pub const CONTINUE_ANSWER: &str = "continue_answer";
+pub const WARM_UP: &str = "warm_up";
+pub const RELATED_QUESTION: &str = "related_question";
+/// Streams the related questions of the latest answer, one `{"content": ...}` JSON string per
+/// frame, see [crate::types::RelatedQuestionOptions].
+pub const RELATED_QUESTION_STREAM: &str = "related_question_stream";
+pub const SUGGEST_QUESTIONS: &str = "suggest_questions";
+pub const GET_CHAT_HISTORY: &str = "get_chat_history";
+pub const CHAT_SUMMARY: &str = "chat_summary";
+/// Forgets the turns of a chat after the first `keep_turns`, a turn being a question and its
+/// answer.
+pub const TRUNCATE_CHAT: &str = "truncate_chat";
+/// Replaces the first `turns` turns of a chat with a summary of them, keeping the following
+/// turns as they are.
+pub const REPLACE_HISTORY_PREFIX: &str = "replace_history_prefix";
+/// Records a message of a given [crate::types::MessageRole] in the history of a chat, without
+/// generating an answer.
+pub const APPEND_MESSAGE: &str = "append_message";
+
+/// Streams the completion as raw text.
+pub const COMPLETE_TEXT: &str = "complete_text";
+/// Streams the completion as JSON frames, see [crate::stream].
+pub const COMPLETE_TEXT_V2: &str = "complete_text_v2";
+/// Reworks a previous completion with a new instruction, streaming `complete_text_v2` frames.
+pub const COMPLETE_TEXT_FOLLOWUP: &str = "complete_text_followup";
+pub const DATABASE_SUMMARY: &str = "database_summary";
+pub const DATABASE_TRANSLATE: &str = "database_translate";
+/// Answers a question over a set of rows, see [crate::types::DatabaseQueryAnswer].
+pub const DATABASE_QUERY: &str = "database_query";
+/// Streams each cell of the row once it is translated, see [crate::types::TranslatedCell].
+pub const DATABASE_TRANSLATE_STREAM: &str = "database_translate_stream";
+
+pub const EMBED_FILE: &str = "embed_file";
+pub const EMBED_TEXT: &str = "embed_text";
+pub const GEN_EMBEDDINGS: &str = "gen_embeddings";
+pub const DELETE_DOCUMENTS: &str = "delete_documents";
+pub const SIMILARITY_SEARCH: &str = "similarity_search";
+pub const VS_EXPORT: &str = "vs_export";
+pub const VS_RELOAD: &str = "vs_reload";
+/// Counts the documents and chunks of the vector store, see [crate::types::VectorStoreCounts].
+pub const VS_STATS: &str = "vs_stats";
+/// Reclaims the space of the deleted chunks of the vector store. Sent again after a compaction
+/// was interrupted, so it must finish or undo a partial one.
+pub const VS_COMPACT: &str = "vs_compact";
+/// Reads stored vectors back by metadata filter, a page at a time, see
+/// [crate::types::StoredEmbeddingPage].
+pub const VS_GET: &str = "vs_get";
+/// Closes the vector store and opens the one of `persist_directory` instead, creating it when
+/// the directory is empty.
+pub const VS_SWITCH: &str = "vs_switch";
+/// Rewrites the vector store from the `from` store format into the `to` one, streaming
+/// [crate::types::MigrationProgress] frames, see [crate::types::PluginInfo::store_format_version].
+pub const VS_MIGRATE: &str = "vs_migrate";
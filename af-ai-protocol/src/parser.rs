@@ -0,0 +1,485 @@
+use crate::stream::{answer_text, STREAM_ANSWER_KEY, STREAM_METADATA_KEY};
+use crate::types::{
+  AnswerWithSources, ChatSummary, DatabaseQueryAnswer, EmbedReport, LocalAITranslateRowResponse,
+  MigrationProgress, ModelCapabilities, SearchPage, SearchResult, StoredEmbedding,
+  StoredEmbeddingPage, VectorStoreCounts,
+};
+use af_plugin::core::parser::ResponseParser;
+use af_plugin::core::stream::BackpressureReport;
+use af_plugin::error::RemoteError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue, Value};
+
+/// Reply of `answer` and `chat_summary`: the text in `data`.
+pub struct ChatResponseParser;
+impl ResponseParser for ChatResponseParser {
+  type ValueType = String;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| data.as_str())
+      .map(String::from)
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+pub struct DataJsonParser;
+impl ResponseParser for DataJsonParser {
+  type ValueType = Value;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .cloned()
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Frames of the v1 streams, `stream_answer` and `complete_text`.
+pub struct ChatStreamResponseParser;
+impl ResponseParser for ChatStreamResponseParser {
+  type ValueType = Bytes;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .as_str()
+      .map(|message| Bytes::from(message.to_string()))
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+
+  fn coalesce(prev: &mut Self::ValueType, next: Self::ValueType) -> Option<Self::ValueType> {
+    let mut merged = Vec::with_capacity(prev.len() + next.len());
+    merged.extend_from_slice(prev);
+    merged.extend_from_slice(&next);
+    *prev = Bytes::from(merged);
+    None
+  }
+}
+
+/// Frames of the v2 streams, sent as JSON strings, see [crate::stream].
+pub struct JsonStringToJsonObject;
+impl ResponseParser for JsonStringToJsonObject {
+  type ValueType = serde_json::Value;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .as_str()
+      .and_then(|s| serde_json::from_str(s).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+
+  /// Only frames that carry nothing but answer text are merged.
+  fn coalesce(prev: &mut Self::ValueType, next: Self::ValueType) -> Option<Self::ValueType> {
+    let next_text = match answer_text(&next) {
+      Some(text) => text.to_string(),
+      None => return Some(next),
+    };
+    match prev
+      .as_object_mut()
+      .filter(|map| map.len() == 1)
+      .and_then(|map| map.get_mut(STREAM_ANSWER_KEY))
+    {
+      Some(Value::String(prev_text)) => {
+        prev_text.push_str(&next_text);
+        None
+      },
+      _ => Some(next),
+    }
+  }
+
+  fn backpressure_frame(report: &BackpressureReport) -> Option<Self::ValueType> {
+    Some(json!({ STREAM_METADATA_KEY: { "backpressure": report } }))
+  }
+}
+
+/// Frames of `related_question_stream`: a JSON string holding one question as `content`, like the
+/// items of `related_question`.
+pub struct RelatedQuestionStreamParser;
+impl ResponseParser for RelatedQuestionStreamParser {
+  type ValueType = String;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .as_str()
+      .and_then(|s| serde_json::from_str::<JsonValue>(s).ok())
+      .and_then(|frame| frame.get("content")?.as_str().map(String::from))
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Reply of `related_question` and `suggest_questions`.
+pub struct ChatRelatedQuestionsResponseParser;
+impl ResponseParser for ChatRelatedQuestionsResponseParser {
+  type ValueType = Vec<String>;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| data.as_array())
+      .map(|array| {
+        array
+          .iter()
+          .flat_map(|item| {
+            item
+              .get("content")
+              .map(|s| s.as_str().map(|s| s.to_string()))?
+          })
+          .collect()
+      })
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+pub struct DatabaseSummaryResponseParser;
+impl ResponseParser for DatabaseSummaryResponseParser {
+  type ValueType = String;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| data.as_str())
+      .map(|s| s.to_string())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+pub struct DatabaseTranslateResponseParser;
+impl ResponseParser for DatabaseTranslateResponseParser {
+  type ValueType = LocalAITranslateRowResponse;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| LocalAITranslateRowResponse::deserialize(data).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+pub struct DatabaseQueryResponseParser;
+impl ResponseParser for DatabaseQueryResponseParser {
+  type ValueType = DatabaseQueryAnswer;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| DatabaseQueryAnswer::deserialize(data).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+pub struct SimilaritySearchResponseParse;
+impl ResponseParser for SimilaritySearchResponseParse {
+  type ValueType = Vec<String>;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    if json.is_object() {
+      if let Some(data) = json.get("data") {
+        if let Some(array) = data.as_array() {
+          let mut result = Vec::new();
+          for item in array {
+            if let Some(value) = item.as_str() {
+              result.push(value.to_string());
+            } else {
+              return Err(RemoteError::ParseResponse(json));
+            }
+          }
+          return Ok(result);
+        }
+      }
+    }
+    Err(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Parses a `similarity_search` reply whose `data` holds strings, or objects with a `content`,
+/// a `score` and the `metadata` of the content. `total` is only set when the plugin reports it.
+pub struct SimilaritySearchPageParse;
+impl ResponseParser for SimilaritySearchPageParse {
+  type ValueType = SearchPage;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    let items = match json.get("data").and_then(|data| data.as_array()) {
+      Some(items) => items,
+      None => return Err(RemoteError::ParseResponse(json)),
+    };
+    let mut results = Vec::with_capacity(items.len());
+    for item in items {
+      match search_result(item) {
+        Some(result) => results.push(result),
+        None => return Err(RemoteError::ParseResponse(json)),
+      }
+    }
+    let total = json
+      .get("total")
+      .and_then(|total| total.as_u64())
+      .map(|total| total as usize);
+    Ok(SearchPage { results, total })
+  }
+}
+
+fn search_result(item: &JsonValue) -> Option<SearchResult> {
+  if let Some(content) = item.as_str() {
+    return Some(SearchResult {
+      content: content.to_string(),
+      score: None,
+      metadata: Default::default(),
+    });
+  }
+  let content = ["content", "page_content", "text"]
+    .iter()
+    .find_map(|key| item.get(*key).and_then(|v| v.as_str()))?;
+  let score = ["score", "relevance_score"]
+    .iter()
+    .find_map(|key| item.get(*key).and_then(|v| v.as_f64()));
+  let metadata = item
+    .get("metadata")
+    .and_then(|metadata| metadata.as_object())
+    .map(|metadata| metadata.clone().into_iter().collect())
+    .unwrap_or_default();
+  Some(SearchResult {
+    content: content.to_string(),
+    score,
+    metadata,
+  })
+}
+
+/// Reply of `vs_get`: `{"data": {"items": [...], "next_cursor": "..."}}`.
+///
+/// The `vector` of an item is an array of numbers, or, to keep pages of large vectors small,
+/// `{"dtype": "float32" | "float64", "base64": "..."}` holding the little endian values.
+pub struct StoredEmbeddingPageParse;
+impl ResponseParser for StoredEmbeddingPageParse {
+  type ValueType = StoredEmbeddingPage;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    let data = match json.get("data") {
+      Some(data) => data,
+      None => return Err(RemoteError::ParseResponse(json)),
+    };
+    let items = match data.get("items").and_then(|items| items.as_array()) {
+      Some(items) => items,
+      None => return Err(RemoteError::ParseResponse(json)),
+    };
+    let mut embeddings = Vec::with_capacity(items.len());
+    for item in items {
+      match stored_embedding(item) {
+        Some(embedding) => embeddings.push(embedding),
+        None => return Err(RemoteError::ParseResponse(json)),
+      }
+    }
+    let next_cursor = data
+      .get("next_cursor")
+      .and_then(|cursor| cursor.as_str())
+      .map(String::from);
+    Ok(StoredEmbeddingPage {
+      embeddings,
+      next_cursor,
+    })
+  }
+}
+
+fn stored_embedding(item: &JsonValue) -> Option<StoredEmbedding> {
+  let metadata = match item.get("metadata") {
+    Some(JsonValue::Object(metadata)) => metadata.clone().into_iter().collect(),
+    None | Some(JsonValue::Null) => Default::default(),
+    Some(_) => return None,
+  };
+  Some(StoredEmbedding {
+    id: item.get("id")?.as_str()?.to_string(),
+    vector: vector(item.get("vector")?)?,
+    metadata,
+    content_hash: item
+      .get("content_hash")
+      .and_then(|hash| hash.as_str())
+      .map(String::from),
+  })
+}
+
+fn vector(value: &JsonValue) -> Option<Vec<f64>> {
+  if let Some(values) = value.as_array() {
+    return values.iter().map(|value| value.as_f64()).collect();
+  }
+  let bytes = STANDARD.decode(value.get("base64")?.as_str()?).ok()?;
+  match value.get("dtype")?.as_str()? {
+    "float32" => {
+      let chunks = bytes.chunks_exact(4);
+      if !chunks.remainder().is_empty() {
+        return None;
+      }
+      Some(
+        chunks
+          .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as f64)
+          .collect(),
+      )
+    },
+    "float64" => {
+      let chunks = bytes.chunks_exact(8);
+      if !chunks.remainder().is_empty() {
+        return None;
+      }
+      Some(
+        chunks
+          .map(|chunk| {
+            let mut value = [0; 8];
+            value.copy_from_slice(chunk);
+            f64::from_le_bytes(value)
+          })
+          .collect(),
+      )
+    },
+    _ => None,
+  }
+}
+
+pub struct EmbeddingResponseParse;
+impl ResponseParser for EmbeddingResponseParse {
+  type ValueType = Vec<Vec<f64>>;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    if json.is_object() {
+      if let Some(embeddings) = json.get("data") {
+        if let Some(array) = embeddings.as_array() {
+          let mut result = Vec::new();
+          for item in array {
+            if let Some(inner_array) = item.as_array() {
+              let mut inner_result = Vec::new();
+              for num in inner_array {
+                if let Some(value) = num.as_f64() {
+                  inner_result.push(value);
+                } else {
+                  return Err(RemoteError::ParseResponse(json));
+                }
+              }
+              result.push(inner_result);
+            } else {
+              return Err(RemoteError::ParseResponse(json));
+            }
+          }
+          return Ok(result);
+        }
+      }
+    }
+    Err(RemoteError::ParseResponse(json))
+  }
+}
+
+pub struct VectorStoreExportParse;
+impl ResponseParser for VectorStoreExportParse {
+  type ValueType = u64;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| data.get("item_count"))
+      .and_then(|count| count.as_u64())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Reply of `vs_stats`: the [VectorStoreCounts] in `data`.
+pub struct VectorStoreStatsParse;
+impl ResponseParser for VectorStoreStatsParse {
+  type ValueType = VectorStoreCounts;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| VectorStoreCounts::deserialize(data).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Reply of `answer_with_sources`: the [AnswerWithSources] in `data`. Sources without a score
+/// are kept.
+pub struct AnswerWithSourcesParse;
+impl ResponseParser for AnswerWithSourcesParse {
+  type ValueType = AnswerWithSources;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| AnswerWithSources::deserialize(data).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Reply of `model_capabilities`: the [ModelCapabilities] in `data`.
+pub struct ModelCapabilitiesParse;
+impl ResponseParser for ModelCapabilitiesParse {
+  type ValueType = ModelCapabilities;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| ModelCapabilities::deserialize(data).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Reply of `embed_file` with `report`: the [EmbedReport] in `data`.
+pub struct EmbedReportParse;
+impl ResponseParser for EmbedReportParse {
+  type ValueType = EmbedReport;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .get("data")
+      .and_then(|data| EmbedReport::deserialize(data).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
+
+/// Reply of `list_chats`: the chats in `data`, each a [ChatSummary] object or a chat id. Fields
+/// of the wrong type are left out, and entries without a chat id are skipped.
+pub struct ChatListParse;
+impl ResponseParser for ChatListParse {
+  type ValueType = Vec<ChatSummary>;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    let Some(chats) = json.get("data").and_then(|data| data.as_array()) else {
+      return Err(RemoteError::ParseResponse(json));
+    };
+    Ok(chats.iter().filter_map(chat_summary).collect())
+  }
+}
+
+fn chat_summary(chat: &JsonValue) -> Option<ChatSummary> {
+  if let Some(chat_id) = chat.as_str() {
+    return Some(ChatSummary {
+      chat_id: chat_id.to_string(),
+      ..Default::default()
+    });
+  }
+  let text = |key: &str| {
+    chat
+      .get(key)
+      .and_then(|value| value.as_str())
+      .map(String::from)
+  };
+  Some(ChatSummary {
+    chat_id: text("chat_id")?,
+    created_at: text("created_at"),
+    last_activity: text("last_activity"),
+    message_count: chat
+      .get("message_count")
+      .and_then(|count| count.as_u64())
+      .and_then(|count| u32::try_from(count).ok()),
+  })
+}
+
+/// Frames of `vs_migrate`: a JSON string holding a [MigrationProgress].
+pub struct MigrationProgressParser;
+impl ResponseParser for MigrationProgressParser {
+  type ValueType = MigrationProgress;
+
+  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    json
+      .as_str()
+      .and_then(|s| serde_json::from_str(s).ok())
+      .ok_or(RemoteError::ParseResponse(json))
+  }
+}
@@ -0,0 +1,17 @@
+use serde_json::Value;
+
+/// Key of the answer text in a v2 stream frame.
+pub const STREAM_ANSWER_KEY: &str = "1";
+/// Key of the metadata in a v2 stream frame.
+pub const STREAM_METADATA_KEY: &str = "0";
+/// Key of the comment on the answer, such as an explanation of a fix, in a v2 stream frame.
+pub const STREAM_COMMENT_KEY: &str = "4";
+
+/// The text of a frame that carries nothing but answer text.
+pub fn answer_text(frame: &Value) -> Option<&str> {
+  frame
+    .as_object()
+    .filter(|map| map.len() == 1)
+    .and_then(|map| map.get(STREAM_ANSWER_KEY))
+    .and_then(|value| value.as_str())
+}
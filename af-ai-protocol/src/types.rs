@@ -0,0 +1,472 @@
+use crate::capability::{Capability, DEFAULT_PROTOCOL_VERSION};
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Reply of `system_info`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginInfo {
+  pub version: String,
+  /// Protocol the plugin speaks, see [crate::capability].
+  #[serde(default = "default_protocol_version")]
+  pub protocol_version: u32,
+  /// Methods the plugin handles, see [crate::method]. `None` for plugins that predate the
+  /// report, which may handle any method.
+  #[serde(default)]
+  pub supported_methods: Option<Vec<String>>,
+  /// Layout of the vector stores the plugin writes, changed when a new version can't open the
+  /// stores of an older one. `None` for plugins that predate store versioning.
+  #[serde(default)]
+  pub store_format_version: Option<u32>,
+}
+
+fn default_protocol_version() -> u32 {
+  DEFAULT_PROTOCOL_VERSION
+}
+
+/// A message of a chat, as returned by `get_chat_history`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+  /// `human` or `ai`.
+  pub role: String,
+  pub content: String,
+}
+
+/// What a model of the Ollama server supports, as returned by `model_capabilities`. Fields the
+/// plugin leaves out are unsupported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelCapabilities {
+  /// Answers following the JSON schema given as `format`.
+  #[serde(default)]
+  pub supports_json_format: bool,
+  #[serde(default)]
+  pub supports_tools: bool,
+  /// Images in the messages.
+  #[serde(default)]
+  pub supports_vision: bool,
+  /// Context window of the model, in tokens.
+  #[serde(default)]
+  pub max_context: Option<u32>,
+}
+
+/// An answer and the chunks it was retrieved from, as returned by `answer_with_sources`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnswerWithSources {
+  pub answer: String,
+  #[serde(default)]
+  pub sources: Vec<RagSource>,
+}
+
+/// A chunk retrieved for an answer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RagSource {
+  /// Metadata the chunk was embedded with.
+  #[serde(default)]
+  pub metadata: HashMap<String, serde_json::Value>,
+  #[serde(alias = "content", alias = "page_content")]
+  pub chunk_text: String,
+  /// Similarity of the chunk to the question, when the plugin sends it.
+  #[serde(default)]
+  pub score: Option<f64>,
+  /// The chunk was found by searching the chat for the question, not reported by the plugin, so
+  /// it may not be one the answer was retrieved from.
+  #[serde(default)]
+  pub approximate: bool,
+}
+
+/// A chat of the plugin, as returned by `list_chats`. Plugins that only know the ids of their
+/// chats leave the other fields out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatSummary {
+  pub chat_id: String,
+  /// When the chat was created, as an RFC 3339 timestamp.
+  pub created_at: Option<String>,
+  /// When the chat was last asked or answered, as an RFC 3339 timestamp.
+  pub last_activity: Option<String>,
+  /// Messages in the history of the chat.
+  pub message_count: Option<u32>,
+}
+
+/// Who a message of `append_message` comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+  /// Instructions for the model.
+  System,
+  User,
+  /// An answer, e.g. of a conversation imported from elsewhere.
+  Assistant,
+  /// Something the model should know about, such as a page being renamed, that is neither a
+  /// question nor an instruction.
+  Context,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum CompleteTextType {
+  ImproveWriting = 1,
+  SpellingAndGrammar = 2,
+  MakeShorter = 3,
+  MakeLonger = 4,
+  ContinueWriting = 5,
+  Explain = 6,
+  AskAI = 7,
+  Custom = 8,
+}
+
+impl From<u8> for CompleteTextType {
+  fn from(value: u8) -> Self {
+    match value {
+      1 => CompleteTextType::ImproveWriting,
+      2 => CompleteTextType::SpellingAndGrammar,
+      3 => CompleteTextType::MakeShorter,
+      4 => CompleteTextType::MakeLonger,
+      5 => CompleteTextType::ContinueWriting,
+      6 => CompleteTextType::Explain,
+      7 => CompleteTextType::AskAI,
+      8 => CompleteTextType::Custom,
+      _ => CompleteTextType::AskAI,
+    }
+  }
+}
+
+impl CompleteTextType {
+  /// The capability a plugin needs to have its own prompt for this type, if any.
+  pub fn prompt_capability(&self) -> Option<Capability> {
+    match self {
+      CompleteTextType::ContinueWriting | CompleteTextType::Explain => {
+        Some(Capability::CompletionPrompts)
+      },
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LocalAITranslateRowData {
+  pub cells: Vec<LocalAITranslateItem>,
+  pub language: String,
+  pub include_header: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LocalAITranslateItem {
+  pub title: String,
+  pub content: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalAITranslateRowResponse {
+  pub items: Vec<HashMap<String, String>>,
+}
+
+/// How the values of a database column are written, so the model can compare them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+  Text,
+  Number,
+  Date,
+  Checkbox,
+  SingleSelect,
+  MultiSelect,
+  Url,
+}
+
+/// A column of the rows sent to `database_query`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnDef {
+  pub name: String,
+  pub field_type: FieldType,
+}
+
+/// Reply of `database_query`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseQueryAnswer {
+  pub answer: String,
+  /// Indices of the rows the question selects, such as the books rated above 7, in the order
+  /// the rows were sent. `None` when the question doesn't select rows.
+  #[serde(default)]
+  pub matching_row_indices: Option<Vec<usize>>,
+}
+
+/// A frame of `database_translate_stream`: the cell at `index` of the row, translated.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranslatedCell {
+  pub title: String,
+  pub content: String,
+  pub index: usize,
+}
+
+/// Most chunks retrieved per question, see [RagOptions::validate].
+pub const MAX_RAG_TOP_K: u32 = 50;
+
+/// How many embedded chunks the plugin retrieves to answer a question of a chat, sent with
+/// `create_chat` and, to override them for one question, with `stream_answer_v2`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RagOptions {
+  /// Chunks retrieved per question.
+  pub top_k: u32,
+  /// Chunks scoring below are not used.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub score_threshold: Option<f64>,
+  /// Most tokens of retrieved chunks added to the prompt. Chunks past the budget are dropped,
+  /// lowest scoring first.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_context_tokens: Option<u32>,
+  /// Answer this question without retrieving any chunk, e.g. when none is relevant to it.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub skip_retrieval: bool,
+}
+
+impl Default for RagOptions {
+  fn default() -> Self {
+    Self {
+      top_k: 2,
+      score_threshold: None,
+      max_context_tokens: None,
+      skip_retrieval: false,
+    }
+  }
+}
+
+impl RagOptions {
+  pub fn new(top_k: u32) -> Self {
+    Self {
+      top_k,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_score_threshold(mut self, score_threshold: f64) -> Self {
+    self.score_threshold = Some(score_threshold);
+    self
+  }
+
+  pub fn with_max_context_tokens(mut self, max_context_tokens: u32) -> Self {
+    self.max_context_tokens = Some(max_context_tokens);
+    self
+  }
+
+  /// The options of a question, `self`, with the fields it leaves unset taken from the options
+  /// of its chat. The `top_k` and `skip_retrieval` of the question always win.
+  pub fn merged(&self, chat: &RagOptions) -> RagOptions {
+    RagOptions {
+      top_k: self.top_k,
+      score_threshold: self.score_threshold.or(chat.score_threshold),
+      max_context_tokens: self.max_context_tokens.or(chat.max_context_tokens),
+      skip_retrieval: self.skip_retrieval,
+    }
+  }
+
+  /// Rejects a zero `top_k` or a threshold that is not a number, and lowers `top_k` to
+  /// [MAX_RAG_TOP_K], as larger values only fill the context window with unrelated chunks.
+  pub fn validate(mut self) -> Result<Self, PluginError> {
+    if self.top_k == 0 {
+      return Err(PluginError::InvalidRagOptions(
+        "top_k must be at least 1".to_string(),
+      ));
+    }
+    if self
+      .score_threshold
+      .is_some_and(|threshold| !threshold.is_finite())
+    {
+      return Err(PluginError::InvalidRagOptions(
+        "score_threshold must be a finite number".to_string(),
+      ));
+    }
+    if self.max_context_tokens == Some(0) {
+      return Err(PluginError::InvalidRagOptions(
+        "max_context_tokens must be at least 1".to_string(),
+      ));
+    }
+    self.top_k = self.top_k.min(MAX_RAG_TOP_K);
+    Ok(self)
+  }
+}
+
+/// Sampling options of one question, sent with `answer` as `options`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuestionOptions {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub temperature: Option<f32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub seed: Option<u64>,
+}
+
+impl QuestionOptions {
+  pub fn with_temperature(mut self, temperature: f32) -> Self {
+    self.temperature = Some(temperature);
+    self
+  }
+
+  pub fn with_seed(mut self, seed: u64) -> Self {
+    self.seed = Some(seed);
+    self
+  }
+
+  /// Whether the same question always gets the same answer: a seed is set or the temperature
+  /// is zero.
+  pub fn is_deterministic(&self) -> bool {
+    self.seed.is_some() || self.temperature == Some(0.0)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.temperature.is_none() && self.seed.is_none()
+  }
+}
+
+/// Options of `related_question_stream`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedQuestionOptions {
+  /// Questions streamed at most.
+  pub count: usize,
+}
+
+impl Default for RelatedQuestionOptions {
+  fn default() -> Self {
+    Self { count: 3 }
+  }
+}
+
+impl RelatedQuestionOptions {
+  pub fn new(count: usize) -> Self {
+    Self { count }
+  }
+}
+
+/// Paging of `similarity_search`, see [SearchPage].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchOptions {
+  /// Results per page.
+  pub top_k: usize,
+  /// Results scoring below are dropped.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub min_score: Option<f64>,
+  /// Results skipped before the page, e.g. `top_k` for the second page.
+  pub offset: usize,
+}
+
+impl Default for SearchOptions {
+  fn default() -> Self {
+    Self {
+      top_k: 10,
+      min_score: None,
+      offset: 0,
+    }
+  }
+}
+
+impl SearchOptions {
+  pub fn new(top_k: usize) -> Self {
+    Self {
+      top_k,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_min_score(mut self, min_score: f64) -> Self {
+    self.min_score = Some(min_score);
+    self
+  }
+
+  pub fn with_offset(mut self, offset: usize) -> Self {
+    self.offset = offset;
+    self
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+  pub content: String,
+  /// Similarity to the query, higher is closer. Plugins replying with plain strings send none.
+  pub score: Option<f64>,
+  /// Metadata the content was embedded with, when the plugin sends it.
+  #[serde(default)]
+  pub metadata: HashMap<String, serde_json::Value>,
+}
+
+/// A vector read back from the vector store with `vs_get`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredEmbedding {
+  pub id: String,
+  pub vector: Vec<f64>,
+  pub metadata: HashMap<String, serde_json::Value>,
+  /// Hash of the embedded content, when the plugin stored one.
+  pub content_hash: Option<String>,
+}
+
+/// A page of `vs_get` results. The next page is read by sending `next_cursor` back, until it is
+/// `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoredEmbeddingPage {
+  pub embeddings: Vec<StoredEmbedding>,
+  pub next_cursor: Option<String>,
+}
+
+/// A page of `similarity_search` results, by descending score.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchPage {
+  pub results: Vec<SearchResult>,
+  /// Results matching the query across all pages, when known.
+  pub total: Option<usize>,
+}
+
+/// Reply of `vs_stats`. Deleted chunks take space on disk until `vs_compact` reclaims it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct VectorStoreCounts {
+  pub total_documents: u64,
+  pub total_chunks: u64,
+  #[serde(default)]
+  pub deleted_chunks: u64,
+}
+
+/// Reply of `embed_file` with `report`: the chunks of the file that were indexed and the ones
+/// that failed, e.g. a page of a PDF that is a scanned image.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbedReport {
+  pub chunks_indexed: usize,
+  pub chunks_failed: usize,
+  #[serde(default)]
+  pub failures: Vec<ChunkFailure>,
+}
+
+impl EmbedReport {
+  /// Report of a plugin that indexes a file whole or not at all, counting the file as one chunk.
+  pub fn whole_file() -> Self {
+    Self {
+      chunks_indexed: 1,
+      ..Default::default()
+    }
+  }
+
+  /// Same as [EmbedReport::whole_file], for a file the plugin failed to index.
+  pub fn whole_file_failed(reason: String) -> Self {
+    Self {
+      chunks_indexed: 0,
+      chunks_failed: 1,
+      failures: vec![ChunkFailure { index: 0, reason }],
+    }
+  }
+
+  /// Whether every chunk of the file was indexed.
+  pub fn is_complete(&self) -> bool {
+    self.chunks_failed == 0
+  }
+}
+
+/// A chunk of a file that couldn't be indexed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkFailure {
+  /// Position of the chunk in the file, from 0.
+  pub index: usize,
+  pub reason: String,
+}
+
+/// Frame of `vs_migrate`: the documents of the vector store rewritten in the new store format so
+/// far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationProgress {
+  pub migrated: u64,
+  pub total: u64,
+}
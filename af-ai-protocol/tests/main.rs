@@ -0,0 +1 @@
+mod protocol_test;
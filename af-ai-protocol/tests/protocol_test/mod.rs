@@ -0,0 +1,211 @@
+use af_ai_protocol::capability::{Capability, CURRENT_PROTOCOL_VERSION, DEFAULT_PROTOCOL_VERSION};
+use af_ai_protocol::parser::{
+  AnswerWithSourcesParse, ChatListParse, EmbedReportParse, MigrationProgressParser,
+  ModelCapabilitiesParse, VectorStoreStatsParse,
+};
+use af_ai_protocol::types::{
+  AnswerWithSources, ChatSummary, ChunkFailure, EmbedReport, MigrationProgress, ModelCapabilities,
+  PluginInfo, RagSource, VectorStoreCounts,
+};
+use af_plugin::core::parser::ResponseParser;
+use serde_json::json;
+
+#[test]
+fn plugin_info_protocol_version_test() {
+  let info: PluginInfo = serde_json::from_value(json!({ "version": "0.1.0" })).unwrap();
+  assert_eq!(info.protocol_version, DEFAULT_PROTOCOL_VERSION);
+  assert_eq!(info.store_format_version, None);
+
+  let info: PluginInfo =
+    serde_json::from_value(json!({ "version": "0.2.0", "protocol_version": 2 })).unwrap();
+  assert_eq!(info.protocol_version, 2);
+
+  let info: PluginInfo =
+    serde_json::from_value(json!({ "version": "0.4.0", "store_format_version": 3 })).unwrap();
+  assert_eq!(info.store_format_version, Some(3));
+}
+
+#[test]
+fn capability_test() {
+  for capability in [
+    Capability::ResponseFormat,
+    Capability::CompletionMetadata,
+    Capability::SearchFilter,
+    Capability::RagOptions,
+    Capability::CompletionPrompts,
+    Capability::SearchScores,
+    Capability::SkipRetrieval,
+    Capability::EmbedReport,
+  ] {
+    assert!(capability.is_supported_by(CURRENT_PROTOCOL_VERSION));
+  }
+  assert!(Capability::ResponseFormat.is_supported_by(DEFAULT_PROTOCOL_VERSION));
+  assert!(Capability::SearchFilter.is_supported_by(DEFAULT_PROTOCOL_VERSION));
+  assert!(!Capability::CompletionMetadata.is_supported_by(DEFAULT_PROTOCOL_VERSION));
+  assert!(!Capability::RagOptions.is_supported_by(2));
+  assert!(!Capability::CompletionPrompts.is_supported_by(3));
+  assert!(!Capability::SearchScores.is_supported_by(4));
+  assert!(!Capability::EmbedReport.is_supported_by(5));
+}
+
+#[test]
+fn vector_store_stats_parse_test() {
+  let counts = VectorStoreStatsParse::parse_json(json!({ "data": {
+    "total_documents": 3,
+    "total_chunks": 42,
+    "deleted_chunks": 7,
+  } }))
+  .unwrap();
+  assert_eq!(
+    counts,
+    VectorStoreCounts {
+      total_documents: 3,
+      total_chunks: 42,
+      deleted_chunks: 7,
+    }
+  );
+
+  // Plugins that don't track deletions leave the field out.
+  let counts = VectorStoreStatsParse::parse_json(json!({ "data": {
+    "total_documents": 1,
+    "total_chunks": 2,
+  } }))
+  .unwrap();
+  assert_eq!(counts.deleted_chunks, 0);
+
+  assert!(VectorStoreStatsParse::parse_json(json!({ "data": { "total_chunks": 2 } })).is_err());
+  assert!(VectorStoreStatsParse::parse_json(json!({})).is_err());
+}
+
+#[test]
+fn embed_report_parse_test() {
+  let report = EmbedReportParse::parse_json(json!({ "data": {
+    "chunks_indexed": 19,
+    "chunks_failed": 1,
+    "failures": [{ "index": 4, "reason": "no text on page 5" }],
+  } }))
+  .unwrap();
+  assert_eq!(
+    report,
+    EmbedReport {
+      chunks_indexed: 19,
+      chunks_failed: 1,
+      failures: vec![ChunkFailure {
+        index: 4,
+        reason: "no text on page 5".to_string(),
+      }],
+    }
+  );
+  assert!(!report.is_complete());
+  assert!(EmbedReport::whole_file().is_complete());
+
+  // Plugins without the report reply like before.
+  assert!(EmbedReportParse::parse_json(json!({})).is_err());
+}
+
+#[test]
+fn migration_progress_parse_test() {
+  let progress =
+    MigrationProgressParser::parse_json(json!(r#"{"migrated":40,"total":120}"#)).unwrap();
+  assert_eq!(
+    progress,
+    MigrationProgress {
+      migrated: 40,
+      total: 120,
+    }
+  );
+  assert!(MigrationProgressParser::parse_json(json!({ "migrated": 40 })).is_err());
+}
+
+#[test]
+fn chat_list_parse_test() {
+  let chats = ChatListParse::parse_json(json!({
+    "data": [
+      {
+        "chat_id": "fruits",
+        "created_at": "2026-10-01T08:00:00Z",
+        "last_activity": "2026-10-02T09:30:00Z",
+        "message_count": 12,
+      },
+      { "chat_id": "vegetables", "message_count": "many" },
+      "nuts",
+      { "created_at": "2026-10-01T08:00:00Z" },
+    ]
+  }))
+  .unwrap();
+  assert_eq!(
+    chats,
+    vec![
+      ChatSummary {
+        chat_id: "fruits".to_string(),
+        created_at: Some("2026-10-01T08:00:00Z".to_string()),
+        last_activity: Some("2026-10-02T09:30:00Z".to_string()),
+        message_count: Some(12),
+      },
+      ChatSummary {
+        chat_id: "vegetables".to_string(),
+        ..Default::default()
+      },
+      ChatSummary {
+        chat_id: "nuts".to_string(),
+        ..Default::default()
+      },
+    ]
+  );
+  assert!(ChatListParse::parse_json(json!({ "data": "fruits" })).is_err());
+}
+
+#[test]
+fn model_capabilities_parse_test() {
+  let capabilities = ModelCapabilitiesParse::parse_json(json!({
+    "data": { "supports_json_format": true, "supports_vision": true, "max_context": 8192 }
+  }))
+  .unwrap();
+  assert_eq!(
+    capabilities,
+    ModelCapabilities {
+      supports_json_format: true,
+      supports_tools: false,
+      supports_vision: true,
+      max_context: Some(8192),
+    }
+  );
+  assert!(ModelCapabilitiesParse::parse_json(json!({ "data": "llama3.1" })).is_err());
+}
+
+#[test]
+fn answer_with_sources_parse_test() {
+  let answer = AnswerWithSourcesParse::parse_json(json!({
+    "data": {
+      "answer": "Bananas are yellow.",
+      "sources": [
+        {
+          "metadata": { "source_id": "bananas" },
+          "chunk_text": "Bananas turn yellow when they ripen.",
+          "score": 0.82
+        },
+        { "content": "Apples are red." }
+      ]
+    }
+  }))
+  .unwrap();
+  assert_eq!(
+    answer,
+    AnswerWithSources {
+      answer: "Bananas are yellow.".to_string(),
+      sources: vec![
+        RagSource {
+          metadata: [("source_id".to_string(), json!("bananas"))].into(),
+          chunk_text: "Bananas turn yellow when they ripen.".to_string(),
+          score: Some(0.82),
+          approximate: false,
+        },
+        RagSource {
+          chunk_text: "Apples are red.".to_string(),
+          ..Default::default()
+        },
+      ],
+    }
+  );
+  assert!(AnswerWithSourcesParse::parse_json(json!({ "data": "Bananas are yellow." })).is_err());
+}
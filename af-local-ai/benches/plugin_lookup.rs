@@ -0,0 +1,127 @@
+//! Looks up the running plugin the way every operation of `OllamaAIPlugin` does, against the
+//! lookup it replaced: the plugin id locked, then searched in the plugin manager.
+//!
+//! Run with `cargo bench -p af-local-ai --features bench`.
+
+use af_local_ai::ollama_plugin::{OllamaAIPlugin, OllamaPluginConfig};
+use af_plugin::manager::PluginManager;
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde_json::json;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// Tasks looking up the plugin at the same time, like the small requests the UI fires together.
+const CONCURRENT_TASKS: usize = 8;
+/// Lookups of each task, so spawning the tasks doesn't dominate the measure.
+const LOOKUPS_PER_TASK: usize = 1_000;
+
+/// Runs `lookup` [LOOKUPS_PER_TASK] times in each of [CONCURRENT_TASKS] tasks.
+async fn concurrently<F, Fut>(lookup: F)
+where
+  F: Fn() -> Fut,
+  Fut: Future<Output = ()> + Send + 'static,
+{
+  let tasks = (0..CONCURRENT_TASKS)
+    .map(|_| {
+      let lookups = (0..LOOKUPS_PER_TASK).map(|_| lookup()).collect::<Vec<_>>();
+      tokio::spawn(async move {
+        for lookup in lookups {
+          lookup.await;
+        }
+      })
+    })
+    .collect::<Vec<_>>();
+  for task in tasks {
+    task.await.unwrap();
+  }
+}
+
+fn start_fake_plugin(
+  runtime: &Runtime,
+  dir: &tempfile::TempDir,
+  plugin_manager: Arc<PluginManager>,
+) -> Arc<OllamaAIPlugin> {
+  let fake_plugin = PathBuf::from(env!("CARGO_BIN_EXE_fake_plugin"));
+  let exec_path = dir.path().join(fake_plugin.file_name().unwrap());
+  std::fs::copy(&fake_plugin, &exec_path).unwrap();
+  let scenario = json!({
+    "methods": { "system_info": [{ "result": { "data": { "version": "fake" } } }] },
+  });
+  std::fs::write(
+    dir.path().join("fake_plugin_scenario.json"),
+    scenario.to_string(),
+  )
+  .unwrap();
+
+  let config = OllamaPluginConfig::new(
+    exec_path,
+    String::new(),
+    "fake-chat-model".to_string(),
+    "fake-embedding-model".to_string(),
+    None,
+  )
+  .unwrap()
+  .with_keep_alive_interval(None);
+  let plugin = Arc::new(OllamaAIPlugin::new(plugin_manager));
+  runtime.block_on(plugin.init_plugin(config)).unwrap();
+  plugin
+}
+
+fn plugin_lookup(c: &mut Criterion) {
+  let runtime = tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .build()
+    .unwrap();
+  let dir = tempfile::tempdir().unwrap();
+  let plugin_manager = Arc::new(PluginManager::new());
+  let plugin = start_fake_plugin(&runtime, &dir, plugin_manager.clone());
+  let plugin_id = plugin.get_plugin_running_state().plugin_id().unwrap();
+
+  // The lookup before the plugin was kept in a slot: the id locked, then searched.
+  let locked_id = Arc::new(tokio::sync::Mutex::new(Some(plugin_id)));
+  let previous_lookup = move || {
+    let locked_id = locked_id.clone();
+    let plugin_manager = plugin_manager.clone();
+    async move {
+      let id = (*locked_id.lock().await).unwrap();
+      plugin_manager.get_plugin(id).await.unwrap()
+    }
+  };
+
+  let mut group = c.benchmark_group("plugin_lookup");
+  group.bench_function("get_ai_plugin", |b| {
+    b.to_async(&runtime)
+      .iter(|| async { plugin.get_ai_plugin().await.unwrap() })
+  });
+  group.bench_function("previous_lookup", |b| {
+    b.to_async(&runtime).iter(&previous_lookup)
+  });
+  group.bench_function("get_ai_plugin_concurrent", |b| {
+    b.to_async(&runtime).iter(|| {
+      concurrently(|| {
+        let plugin = plugin.clone();
+        async move {
+          plugin.get_ai_plugin().await.unwrap();
+        }
+      })
+    })
+  });
+  group.bench_function("previous_lookup_concurrent", |b| {
+    b.to_async(&runtime).iter(|| {
+      concurrently(|| {
+        let lookup = previous_lookup.clone();
+        async move {
+          lookup().await;
+        }
+      })
+    })
+  });
+  group.finish();
+
+  runtime.block_on(plugin.destroy_plugin()).unwrap();
+}
+
+criterion_group!(benches, plugin_lookup);
+criterion_main!(benches);
@@ -1,25 +1,171 @@
-use crate::ollama_plugin::PluginInfo;
+use crate::diff::{DiffSpan, STREAM_DIFF_KEY};
+use crate::ollama_plugin::LogLevel;
+use crate::rate_limit::RateLimiter;
+use crate::retry::Retrier;
+use crate::scheduler::Priority;
+use crate::slow_request::SlowRequestMonitor;
+use crate::summary::SummaryLength;
+use af_ai_protocol::method::{self, MODEL_NAME_KEY, TRACE_ID_KEY};
+pub use af_ai_protocol::parser::{
+  AnswerWithSourcesParse, ChatListParse, ChatRelatedQuestionsResponseParser, ChatResponseParser,
+  ChatStreamResponseParser, DataJsonParser, DatabaseQueryResponseParser,
+  DatabaseSummaryResponseParser, DatabaseTranslateResponseParser, EmbedReportParse,
+  JsonStringToJsonObject, ModelCapabilitiesParse, RelatedQuestionStreamParser,
+};
+pub use af_ai_protocol::stream::{STREAM_ANSWER_KEY, STREAM_COMMENT_KEY, STREAM_METADATA_KEY};
+pub use af_ai_protocol::types::{
+  AnswerWithSources, ChatSummary, ChunkFailure, ColumnDef, CompleteTextType, DatabaseQueryAnswer,
+  EmbedReport, FieldType, LocalAITranslateItem, LocalAITranslateRowData,
+  LocalAITranslateRowResponse, MessageRole, QuestionOptions, RagOptions, RagSource,
+  RelatedQuestionOptions, MAX_RAG_TOP_K,
+};
+use af_ai_protocol::types::{ChatMessage, ModelCapabilities, PluginInfo};
 use af_plugin::core::parser::{EmptyResponseParser, ResponseParser};
 use af_plugin::core::plugin::Plugin;
-use af_plugin::error::{PluginError, RemoteError};
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
 use anyhow::anyhow;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::sync::Weak;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
 use tracing::{instrument, trace};
 
+/// The params of a `handle` request of `method`, with the trace id of the operation if any.
+pub(crate) fn handle_params(
+  method: &str,
+  mut params: JsonValue,
+  trace_id: Option<&str>,
+) -> JsonValue {
+  if let (Some(trace_id), JsonValue::Object(params)) = (trace_id, &mut params) {
+    params.insert(TRACE_ID_KEY.to_string(), json!(trace_id));
+  }
+  json!({ "method": method, "params": params })
+}
+
 pub struct AIPluginOperation {
   plugin: Weak<Plugin>,
+  trace_id: Option<String>,
+  model_name: Option<String>,
+  /// Methods the plugin reported in its `system_info`, `None` when it didn't report them.
+  supported_methods: Option<Arc<HashSet<String>>>,
+  slow_requests: Option<Arc<SlowRequestMonitor>>,
+  rate_limiter: Option<Arc<RateLimiter>>,
+  priority: Priority,
+  retrier: Option<Arc<Retrier>>,
+  /// Attempts sent again by `retrier`, across the requests of the operation.
+  retries: AtomicU32,
 }
 
 impl AIPluginOperation {
   pub fn new(plugin: Weak<Plugin>) -> Self {
-    AIPluginOperation { plugin }
+    AIPluginOperation {
+      plugin,
+      trace_id: None,
+      model_name: None,
+      supported_methods: None,
+      slow_requests: None,
+      rate_limiter: None,
+      priority: Priority::default(),
+      retrier: None,
+      retries: AtomicU32::new(0),
+    }
+  }
+
+  /// Sends `trace_id` with the requests, so the plugin's logs can be correlated with the
+  /// host's spans.
+  pub fn with_trace_id(mut self, trace_id: &str) -> Self {
+    self.trace_id = Some(trace_id.to_string());
+    self
+  }
+
+  /// Asks the plugin to answer the requests with `model_name` instead of the model of its
+  /// settings, see [ModelRoutingTable](crate::model_routing::ModelRoutingTable). `None` keeps the
+  /// model of the settings.
+  pub fn with_model_name(mut self, model_name: Option<String>) -> Self {
+    self.model_name = model_name;
+    self
+  }
+
+  /// Fails the requests of the methods missing from `supported_methods` with
+  /// [PluginError::UnsupportedMethod], without sending them. `None` sends every request.
+  pub fn with_supported_methods(mut self, supported_methods: Option<Arc<HashSet<String>>>) -> Self {
+    self.supported_methods = supported_methods;
+    self
+  }
+
+  /// Reports the requests slower than the thresholds of `monitor`.
+  pub fn with_slow_requests(mut self, monitor: Arc<SlowRequestMonitor>) -> Self {
+    self.slow_requests = Some(monitor);
+    self
+  }
+
+  /// Waits for a token of `limiter` before sending each request, and before starting each
+  /// stream.
+  pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+    self.rate_limiter = Some(limiter);
+    self
+  }
+
+  /// The priority the requests take their tokens of the rate limiter with,
+  /// [Priority::Interactive] by default.
+  pub fn with_priority(mut self, priority: Priority) -> Self {
+    self.priority = priority;
+    self
+  }
+
+  /// Sends the requests again as the policy of `retrier` says when they fail, with the same
+  /// params and trace id. Streams are never sent again.
+  pub(crate) fn with_retrier(mut self, retrier: Arc<Retrier>) -> Self {
+    self.retrier = Some(retrier);
+    self
+  }
+
+  /// Attempts sent again after a transient error so far, see
+  /// [OllamaAIPlugin::set_retry_policy](crate::ollama_plugin::OllamaAIPlugin::set_retry_policy).
+  pub fn retries(&self) -> u32 {
+    self.retries.load(Ordering::Relaxed)
+  }
+
+  async fn acquire_rate_limit(&self) -> Result<(), PluginError> {
+    match &self.rate_limiter {
+      Some(limiter) => limiter.acquire(self.priority).await,
+      None => Ok(()),
+    }
+  }
+
+  fn check_supported(&self, method: &str) -> Result<(), PluginError> {
+    match &self.supported_methods {
+      // `system_info` is how the plugin reports its methods, so it is always sent.
+      Some(methods) if method != method::SYSTEM_INFO && !methods.contains(method) => {
+        Err(PluginError::UnsupportedMethod {
+          method: method.to_string(),
+        })
+      },
+      _ => Ok(()),
+    }
+  }
+
+  /// The params of a request of `method`, failing when the plugin doesn't support it.
+  fn handle_params(&self, method: &str, params: JsonValue) -> Result<JsonValue, PluginError> {
+    self.check_supported(method)?;
+    Ok(self.request_params(method, params))
+  }
+
+  fn request_params(&self, method: &str, mut params: JsonValue) -> JsonValue {
+    if let (Some(model_name), JsonValue::Object(params)) = (&self.model_name, &mut params) {
+      params
+        .entry(MODEL_NAME_KEY)
+        .or_insert_with(|| json!(model_name));
+    }
+    handle_params(method, params, self.trace_id.as_deref())
   }
 
   fn get_plugin(&self) -> Result<std::sync::Arc<Plugin>, PluginError> {
@@ -33,15 +179,96 @@ impl AIPluginOperation {
     &self,
     method: &str,
     params: JsonValue,
+  ) -> Result<T::ValueType, PluginError> {
+    self.check_supported(method)?;
+    self.send_unchecked::<T>(method, params).await
+  }
+
+  async fn send_unchecked<T: ResponseParser>(
+    &self,
+    method: &str,
+    params: JsonValue,
   ) -> Result<T::ValueType, PluginError> {
     let plugin = self.get_plugin()?;
-    let request = json!({ "method": method, "params": params });
-    plugin.async_request::<T>("handle", &request).await
+    let request = self.request_params(method, params);
+    let result = match &self.retrier {
+      Some(retrier) => {
+        retrier
+          .run(method, &self.retries, || {
+            self.send_once::<T>(&plugin, &request)
+          })
+          .await
+      },
+      None => self.send_once::<T>(&plugin, &request).await,
+    };
+    result.map_err(|err| match err {
+      PluginError::RemoteError(err) if err.is_method_not_found() => {
+        PluginError::UnsupportedMethod {
+          method: method.to_string(),
+        }
+      },
+      err => err,
+    })
+  }
+
+  async fn send_once<T: ResponseParser>(
+    &self,
+    plugin: &Plugin,
+    request: &JsonValue,
+  ) -> Result<T::ValueType, PluginError> {
+    self.acquire_rate_limit().await?;
+    let start = Instant::now();
+    let result = plugin.async_request::<T>("handle", request).await;
+    if let Some(monitor) = &self.slow_requests {
+      monitor.finish(request, start);
+    }
+    result
+  }
+
+  /// Sends the `handle` request of `params` as a stream request.
+  async fn stream<P: ResponseParser + 'static>(
+    &self,
+    plugin: &Plugin,
+    params: JsonValue,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<P::ValueType, PluginError>>, PluginError> {
+    self.acquire_rate_limit().await?;
+    let start = Instant::now();
+    let stream = plugin.stream_request::<P>("handle", &params, options)?;
+    Ok(match &self.slow_requests {
+      Some(monitor) => monitor.watch_stream(stream, params, start),
+      None => stream,
+    })
+  }
+
+  /// Sends `params` to `method`, which this crate may not know, see
+  /// [OllamaAIPlugin::raw_request](crate::ollama_plugin::OllamaAIPlugin::raw_request). The
+  /// request is sent even when `method` is not one of the supported methods.
+  pub async fn raw_request<P: ResponseParser>(
+    &self,
+    method: &str,
+    params: JsonValue,
+  ) -> Result<P::ValueType, PluginError> {
+    self.send_unchecked::<P>(method, params).await
+  }
+
+  /// Streams the replies of `method`, which this crate may not know, see
+  /// [OllamaAIPlugin::raw_stream_request](crate::ollama_plugin::OllamaAIPlugin::raw_stream_request),
+  /// even when it is not one of the supported methods.
+  pub async fn raw_stream_request<P: ResponseParser + 'static>(
+    &self,
+    method: &str,
+    params: JsonValue,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<P::ValueType, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    let params = self.request_params(method, params);
+    self.stream::<P>(&plugin, params, options).await
   }
 
   pub async fn plugin_info(&self) -> Result<PluginInfo, PluginError> {
     let value = self
-      .send_request::<DataJsonParser>("system_info", json!({}))
+      .send_request::<DataJsonParser>(method::SYSTEM_INFO, json!({}))
       .await?;
     let info = serde_json::from_value::<PluginInfo>(value)
       .map_err(|err| PluginError::Internal(err.into()))?;
@@ -49,15 +276,78 @@ impl AIPluginOperation {
     Ok(info)
   }
 
-  pub async fn create_chat(&self, chat_id: &str) -> Result<(), PluginError> {
+  /// Checks that the plugin answers, see [method::PING]. Plugins without the method answer with
+  /// an error, which tells just as well that they are alive.
+  pub async fn ping(&self) -> Result<(), PluginError> {
+    let plugin = self.get_plugin()?;
+    let request = self.request_params(method::PING, json!({}));
+    match plugin
+      .async_request::<EmptyResponseParser>("handle", &request)
+      .await
+    {
+      Err(err) if err.remote_error().is_none() => Err(err),
+      _ => Ok(()),
+    }
+  }
+
+  /// Names of the models of the Ollama server.
+  pub async fn list_models(&self) -> Result<Vec<String>, PluginError> {
+    let value = self
+      .send_request::<DataJsonParser>(method::LIST_MODELS, json!({}))
+      .await?;
+    serde_json::from_value::<Vec<String>>(value).map_err(|err| PluginError::Internal(err.into()))
+  }
+
+  /// What `model` supports, see [method::MODEL_CAPABILITIES].
+  pub async fn model_capabilities(&self, model: &str) -> Result<ModelCapabilities, PluginError> {
     self
-      .send_request::<EmptyResponseParser>("create_chat", json!({ "chat_id": chat_id, "top_k": 2}))
+      .send_request::<ModelCapabilitiesParse>(method::MODEL_CAPABILITIES, json!({ "model": model }))
+      .await
+  }
+
+  pub async fn set_log_level(&self, level: LogLevel) -> Result<(), PluginError> {
+    self
+      .send_request::<EmptyResponseParser>(method::SET_LOG_LEVEL, json!({ "level": level }))
+      .await
+  }
+
+  /// Sends the init params in `settings` to the running plugin.
+  pub async fn update_settings(&self, settings: Value) -> Result<(), PluginError> {
+    self
+      .send_request::<EmptyResponseParser>(method::UPDATE_SETTINGS, settings)
+      .await
+  }
+
+  pub async fn create_chat(&self, chat_id: &str, rag: &RagOptions) -> Result<(), PluginError> {
+    let mut params = json!({ "chat_id": chat_id });
+    if let (JsonValue::Object(params), JsonValue::Object(rag)) = (&mut params, json!(rag)) {
+      params.extend(rag);
+    }
+    self
+      .send_request::<EmptyResponseParser>(method::CREATE_CHAT, params)
+      .await
+  }
+
+  /// Whether the plugin has `chat_id`. The reply is `{"data": true}` or `{"data": false}`.
+  pub async fn chat_exists(&self, chat_id: &str) -> Result<bool, PluginError> {
+    let data = self
+      .send_request::<DataJsonParser>(method::CHAT_EXISTS, json!({ "chat_id": chat_id }))
+      .await?;
+    data
+      .as_bool()
+      .ok_or_else(|| PluginError::Internal(anyhow!("invalid chat_exists reply: {}", data)))
+  }
+
+  /// The chats of the plugin, see [ChatListParse].
+  pub async fn list_chats(&self) -> Result<Vec<ChatSummary>, PluginError> {
+    self
+      .send_request::<ChatListParse>(method::LIST_CHATS, json!({}))
       .await
   }
 
   pub async fn close_chat(&self, chat_id: &str) -> Result<(), PluginError> {
     self
-      .send_request::<EmptyResponseParser>("close_chat", json!({ "chat_id": chat_id }))
+      .send_request::<EmptyResponseParser>(method::CLOSE_CHAT, json!({ "chat_id": chat_id }))
       .await
   }
 
@@ -68,8 +358,36 @@ impl AIPluginOperation {
     _rag_enabled: bool,
   ) -> Result<String, PluginError> {
     self
-      .send_request::<ChatResponseParser>(
-        "answer",
+      .send_message_with_options(chat_id, message, &QuestionOptions::default())
+      .await
+  }
+
+  /// Sends `message` with its sampling `options`, left out of the request when none is set.
+  #[instrument(level = "debug", skip(self), err)]
+  pub async fn send_message_with_options(
+    &self,
+    chat_id: &str,
+    message: &str,
+    options: &QuestionOptions,
+  ) -> Result<String, PluginError> {
+    let mut params = json!({ "chat_id": chat_id, "content": message });
+    if !options.is_empty() {
+      params["options"] = json!(options);
+    }
+    self
+      .send_request::<ChatResponseParser>(method::ANSWER, params)
+      .await
+  }
+
+  /// Answers `message` with the chunks the answer was retrieved from.
+  pub async fn answer_with_sources(
+    &self,
+    chat_id: &str,
+    message: &str,
+  ) -> Result<AnswerWithSources, PluginError> {
+    self
+      .send_request::<AnswerWithSourcesParse>(
+        method::ANSWER_WITH_SOURCES,
         json!({ "chat_id": chat_id, "content": message }),
       )
       .await
@@ -82,13 +400,16 @@ impl AIPluginOperation {
     message: &str,
     metadata: serde_json::Value,
   ) -> Result<ReceiverStream<Result<Bytes, PluginError>>, PluginError> {
+    self.check_supported(method::STREAM_ANSWER)?;
     let plugin = self.get_plugin()?;
     let params = json!({
         "chat_id": chat_id,
-        "method": "stream_answer",
+        "method": method::STREAM_ANSWER,
         "params": { "content": message, "metadata": metadata }
     });
-    plugin.stream_request::<ChatStreamResponseParser>("handle", &params)
+    self
+      .stream::<ChatStreamResponseParser>(&plugin, params, StreamOptions::default())
+      .await
   }
   #[instrument(level = "debug", skip(self), err)]
   pub async fn stream_message_v2(
@@ -97,9 +418,22 @@ impl AIPluginOperation {
     message: &str,
     format: Option<serde_json::Value>,
     metadata: serde_json::Value,
+    rag: Option<RagOptions>,
+    options: StreamOptions,
   ) -> Result<ReceiverStream<Result<serde_json::Value, PluginError>>, PluginError> {
-    let plugin = self.get_plugin()?;
+    let params = self.stream_message_v2_params(chat_id, message, format, metadata, rag)?;
+    self.send_stream_message(params, options).await
+  }
 
+  /// The params of the `handle` request [AIPluginOperation::stream_message_v2] sends.
+  pub fn stream_message_v2_params(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    rag: Option<RagOptions>,
+  ) -> Result<JsonValue, PluginError> {
     // Build the inner params as a map.
     let mut inner_params = serde_json::Map::new();
     inner_params.insert("chat_id".to_string(), json!(chat_id));
@@ -108,24 +442,210 @@ impl AIPluginOperation {
     if let Some(fmt) = format {
       inner_params.insert("format".to_string(), fmt);
     }
+    if let Some(rag) = rag {
+      inner_params.insert("rag".to_string(), json!(rag));
+    }
+    self.handle_params(method::STREAM_ANSWER_V2, Value::Object(inner_params))
+  }
 
-    let params = json!({
-        "method": "stream_answer_v2",
-        "params": serde_json::Value::Object(inner_params)
-    });
+  /// Streams the answer of `params`, built with [AIPluginOperation::stream_message_v2_params],
+  /// without changing them.
+  pub async fn send_stream_message(
+    &self,
+    params: JsonValue,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<serde_json::Value, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, options)
+      .await
+  }
+
+  /// Loads the chat model with a trivial generation. The plugin streams Ollama's load progress
+  /// as `{"completed": u64, "total": u64}` frames when it is available.
+  pub async fn warm_up(
+    &self,
+  ) -> Result<ReceiverStream<Result<serde_json::Value, PluginError>>, PluginError> {
+    self.check_supported(method::WARM_UP)?;
+    let plugin = self.get_plugin()?;
+    let params = json!({ "method": method::WARM_UP, "params": {} });
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, StreamOptions::default())
+      .await
+  }
+
+  /// Continues an answer of `chat_id` that was cut off. `received` is the end of the text
+  /// received so far; the plugin streams what follows it.
+  #[allow(clippy::too_many_arguments)]
+  pub async fn continue_answer(
+    &self,
+    chat_id: &str,
+    message: &str,
+    received: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    rag: Option<RagOptions>,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<serde_json::Value, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    let mut inner_params = serde_json::Map::new();
+    inner_params.insert("chat_id".to_string(), json!(chat_id));
+    inner_params.insert("data".to_string(), json!({ "content": message }));
+    inner_params.insert("received".to_string(), json!(received));
+    inner_params.insert("metadata".to_string(), metadata);
+    if let Some(fmt) = format {
+      inner_params.insert("format".to_string(), fmt);
+    }
+    if let Some(rag) = rag {
+      inner_params.insert("rag".to_string(), json!(rag));
+    }
 
-    plugin.stream_request::<JsonStringToJsonObject>("handle", &params)
+    let params = self.handle_params(method::CONTINUE_ANSWER, Value::Object(inner_params))?;
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, options)
+      .await
   }
 
   pub async fn get_related_questions(&self, chat_id: &str) -> Result<Vec<String>, PluginError> {
     self
       .send_request::<ChatRelatedQuestionsResponseParser>(
-        "related_question",
+        method::RELATED_QUESTION,
         json!({ "chat_id": chat_id }),
       )
       .await
   }
 
+  /// Streams the related questions of the latest answer of `chat_id`, one per frame. An
+  /// unsupported method is reported by the first frame of the stream.
+  pub async fn stream_related_questions(
+    &self,
+    chat_id: &str,
+    options: &RelatedQuestionOptions,
+    stream_options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<String, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    let params = self.handle_params(
+      method::RELATED_QUESTION_STREAM,
+      json!({ "chat_id": chat_id, "count": options.count }),
+    )?;
+    self
+      .stream::<RelatedQuestionStreamParser>(&plugin, params, stream_options)
+      .await
+  }
+
+  /// Suggests up to `count` questions about `text`, without needing a chat.
+  pub async fn suggest_questions(&self, text: &str, count: u8) -> Result<Vec<String>, PluginError> {
+    self
+      .send_request::<ChatRelatedQuestionsResponseParser>(
+        method::SUGGEST_QUESTIONS,
+        json!({ "content": text, "count": count }),
+      )
+      .await
+  }
+
+  /// Returns the last `limit` messages of a chat, oldest first.
+  pub async fn chat_history(
+    &self,
+    chat_id: &str,
+    limit: usize,
+  ) -> Result<Vec<ChatMessage>, PluginError> {
+    let value = self
+      .send_request::<DataJsonParser>(
+        method::GET_CHAT_HISTORY,
+        json!({ "chat_id": chat_id, "limit": limit }),
+      )
+      .await?;
+    serde_json::from_value::<Vec<ChatMessage>>(value)
+      .map_err(|err| PluginError::Internal(err.into()))
+  }
+
+  /// Forgets the turns of `chat_id` after the first `keep_turns`.
+  pub async fn truncate_chat(&self, chat_id: &str, keep_turns: usize) -> Result<(), PluginError> {
+    self
+      .send_request::<EmptyResponseParser>(
+        method::TRUNCATE_CHAT,
+        json!({ "chat_id": chat_id, "keep_turns": keep_turns }),
+      )
+      .await
+  }
+
+  /// Records a message of `role` in the history of `chat_id`, without generating an answer.
+  pub async fn append_message(
+    &self,
+    chat_id: &str,
+    role: MessageRole,
+    content: &str,
+  ) -> Result<(), PluginError> {
+    self
+      .send_request::<EmptyResponseParser>(
+        method::APPEND_MESSAGE,
+        json!({ "chat_id": chat_id, "role": role, "content": content }),
+      )
+      .await
+  }
+
+  /// Replaces the first `turns` turns of `chat_id` with `summary`.
+  pub async fn replace_history_prefix(
+    &self,
+    chat_id: &str,
+    turns: usize,
+    summary: &str,
+  ) -> Result<(), PluginError> {
+    self
+      .send_request::<EmptyResponseParser>(
+        method::REPLACE_HISTORY_PREFIX,
+        json!({ "chat_id": chat_id, "turns": turns, "summary": summary }),
+      )
+      .await
+  }
+
+  pub async fn chat_summary(
+    &self,
+    chat_id: &str,
+    length: SummaryLength,
+  ) -> Result<String, PluginError> {
+    self
+      .send_request::<ChatResponseParser>(
+        method::CHAT_SUMMARY,
+        json!({ "chat_id": chat_id, "length": length }),
+      )
+      .await
+  }
+
+  /// Summarizes the first `turns` turns of `chat_id`. Plugins that don't read `first_turns`
+  /// summarize the whole chat.
+  pub async fn chat_summary_of_first_turns(
+    &self,
+    chat_id: &str,
+    length: SummaryLength,
+    turns: usize,
+  ) -> Result<String, PluginError> {
+    self
+      .send_request::<ChatResponseParser>(
+        method::CHAT_SUMMARY,
+        json!({ "chat_id": chat_id, "length": length, "first_turns": turns }),
+      )
+      .await
+  }
+
+  /// Streams the summary of a chat as v2 frames. An unsupported method is reported by the
+  /// first frame of the stream.
+  pub async fn stream_chat_summary(
+    &self,
+    chat_id: &str,
+    length: SummaryLength,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<Value, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    let params = self.handle_params(
+      method::CHAT_SUMMARY,
+      json!({ "chat_id": chat_id, "length": length, "stream": true }),
+    )?;
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, options)
+      .await
+  }
+
   #[instrument(level = "debug", skip_all, err)]
   pub async fn embed_file(
     &self,
@@ -138,7 +658,27 @@ impl AIPluginOperation {
     let params = json!({ "metadata": metadata, "file_path": json!(file_path) });
     trace!("[AI Plugin] indexing file: {:?}", params);
     self
-      .send_request::<EmptyResponseParser>("embed_file", params)
+      .send_request::<EmptyResponseParser>(method::EMBED_FILE, params)
+      .await
+  }
+
+  /// Same as [AIPluginOperation::embed_file], indexing the chunks of the file that the plugin
+  /// can read and reporting the others. Requires [Capability::EmbedReport].
+  ///
+  /// [Capability::EmbedReport]: af_ai_protocol::capability::Capability::EmbedReport
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn embed_file_with_report(
+    &self,
+    chat_id: &str,
+    file_path: String,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+  ) -> Result<EmbedReport, PluginError> {
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.insert("chat_id".to_string(), json!(chat_id));
+    let params = json!({ "metadata": metadata, "file_path": file_path, "report": true });
+    trace!("[AI Plugin] indexing file with report: {:?}", params);
+    self
+      .send_request::<EmbedReportParse>(method::EMBED_FILE, params)
       .await
   }
 
@@ -157,12 +697,11 @@ impl AIPluginOperation {
       inner_params.insert("format".to_string(), fmt);
     }
 
-    let params = json!({
-        "method": "complete_text",
-        "params": serde_json::Value::Object(inner_params)
-    });
+    let params = self.handle_params(method::COMPLETE_TEXT, Value::Object(inner_params))?;
 
-    plugin.stream_request::<ChatStreamResponseParser>("handle", &params)
+    self
+      .stream::<ChatStreamResponseParser>(&plugin, params, StreamOptions::default())
+      .await
   }
   #[instrument(level = "debug", skip_all, err)]
   pub async fn complete_text_v2(
@@ -171,6 +710,7 @@ impl AIPluginOperation {
     complete_type: u8,
     format: Option<Value>,
     metadata: Option<Value>,
+    options: StreamOptions,
   ) -> Result<ReceiverStream<Result<Value, PluginError>>, PluginError> {
     let plugin = self.get_plugin()?;
 
@@ -185,18 +725,64 @@ impl AIPluginOperation {
       inner_params.insert("metadata".to_string(), metadata);
     }
 
-    let params = json!({
-        "method": "complete_text_v2",
-        "params": Value::Object(inner_params)
-    });
+    let params = self.handle_params(method::COMPLETE_TEXT_V2, Value::Object(inner_params))?;
+
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, options)
+      .await
+  }
 
-    plugin.stream_request::<JsonStringToJsonObject>("handle", &params)
+  /// Streams a rework of `previous_output` following `instruction`, in the frames of
+  /// `complete_text_v2`.
+  pub async fn complete_text_followup(
+    &self,
+    original_text: &str,
+    previous_output: &str,
+    instruction: &str,
+    metadata: Option<Value>,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<Result<Value, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    let mut inner_params = serde_json::Map::new();
+    inner_params.insert("original_text".to_string(), json!(original_text));
+    inner_params.insert("previous_output".to_string(), json!(previous_output));
+    inner_params.insert("instruction".to_string(), json!(instruction));
+    if let Some(metadata) = metadata {
+      inner_params.insert("metadata".to_string(), metadata);
+    }
+    let params = self.handle_params(method::COMPLETE_TEXT_FOLLOWUP, Value::Object(inner_params))?;
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, options)
+      .await
+  }
+
+  /// Sends a non-streaming `complete_text` request that generates at most `max_tokens` tokens
+  /// and fails with [PluginError::RequestTimeout] once `timeout` has elapsed.
+  pub async fn quick_complete(
+    &self,
+    text: &str,
+    max_tokens: u16,
+    timeout: Duration,
+  ) -> Result<String, PluginError> {
+    let plugin = self.get_plugin()?;
+    let params = self.handle_params(
+      method::COMPLETE_TEXT,
+      json!({
+        "text": text,
+        "completion_type": CompleteTextType::ContinueWriting as u8,
+        "stream": false,
+        "options": { "num_predict": max_tokens },
+      }),
+    )?;
+    plugin
+      .timed_request::<ChatResponseParser>("handle", &params, timeout)
+      .await
   }
 
   #[instrument(level = "debug", skip(self), err)]
   pub async fn summary_row(&self, row: HashMap<String, String>) -> Result<String, PluginError> {
     self
-      .send_request::<DatabaseSummaryResponseParser>("database_summary", json!(row))
+      .send_request::<DatabaseSummaryResponseParser>(method::DATABASE_SUMMARY, json!(row))
       .await
   }
 
@@ -206,154 +792,121 @@ impl AIPluginOperation {
     data: LocalAITranslateRowData,
   ) -> Result<LocalAITranslateRowResponse, PluginError> {
     self
-      .send_request::<DatabaseTranslateResponseParser>("database_translate", json!(data))
+      .send_request::<DatabaseTranslateResponseParser>(method::DATABASE_TRANSLATE, json!(data))
       .await
   }
-}
-
-#[derive(Clone, Debug, Serialize)]
-pub struct LocalAITranslateRowData {
-  pub cells: Vec<LocalAITranslateItem>,
-  pub language: String,
-  pub include_header: bool,
-}
-
-#[derive(Clone, Debug, Serialize)]
-pub struct LocalAITranslateItem {
-  pub title: String,
-  pub content: String,
-}
-
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
-pub struct LocalAITranslateRowResponse {
-  pub items: Vec<HashMap<String, String>>,
-}
-
-pub struct ChatResponseParser;
-impl ResponseParser for ChatResponseParser {
-  type ValueType = String;
 
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .get("data")
-      .and_then(|data| data.as_str())
-      .map(String::from)
-      .ok_or(RemoteError::ParseResponse(json))
+  /// Answers `question` over `rows`. The returned indices are those of `rows`.
+  #[instrument(level = "debug", skip(self, rows, schema), err)]
+  pub async fn query_database(
+    &self,
+    rows: &[HashMap<String, String>],
+    schema: &[ColumnDef],
+    question: &str,
+  ) -> Result<DatabaseQueryAnswer, PluginError> {
+    self
+      .send_request::<DatabaseQueryResponseParser>(
+        method::DATABASE_QUERY,
+        json!({ "rows": rows, "schema": schema, "question": question }),
+      )
+      .await
   }
-}
 
-pub struct DataJsonParser;
-impl ResponseParser for DataJsonParser {
-  type ValueType = Value;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .get("data")
-      .cloned()
-      .ok_or(RemoteError::ParseResponse(json))
+  /// Streams the translated cells of `data` as JSON frames, in the order the plugin finishes
+  /// them.
+  pub async fn translate_row_stream(
+    &self,
+    data: &LocalAITranslateRowData,
+  ) -> Result<ReceiverStream<Result<Value, PluginError>>, PluginError> {
+    let plugin = self.get_plugin()?;
+    let params = self.handle_params(method::DATABASE_TRANSLATE_STREAM, json!(data))?;
+    self
+      .stream::<JsonStringToJsonObject>(&plugin, params, StreamOptions::default())
+      .await
   }
 }
 
-pub struct ChatStreamResponseParser;
-impl ResponseParser for ChatStreamResponseParser {
-  type ValueType = Bytes;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .as_str()
-      .map(|message| Bytes::from(message.to_string()))
-      .ok_or(RemoteError::ParseResponse(json))
-  }
+/// A completion collected from its stream, see
+/// [OllamaAIPlugin::complete_text_v2_collect](crate::ollama_plugin::OllamaAIPlugin::complete_text_v2_collect).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompletionResult {
+  pub answer: String,
+  pub comment: Option<String>,
+  /// The words the answer changed, when asked for with [StreamOptions::compute_diff].
+  pub diff: Option<Vec<DiffSpan>>,
 }
 
-pub struct JsonStringToJsonObject;
-impl ResponseParser for JsonStringToJsonObject {
-  type ValueType = serde_json::Value;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .as_str()
-      .and_then(|s| serde_json::from_str(s).ok())
-      .ok_or(RemoteError::ParseResponse(json))
+impl CompletionResult {
+  pub async fn collect(
+    mut stream: ReceiverStream<Result<Value, PluginError>>,
+  ) -> Result<Self, PluginError> {
+    let mut result = Self::default();
+    while let Some(frame) = stream.next().await {
+      let frame = frame?;
+      if let Some(answer) = frame.get(STREAM_ANSWER_KEY).and_then(|v| v.as_str()) {
+        result.answer.push_str(answer);
+      }
+      if let Some(comment) = frame.get(STREAM_COMMENT_KEY).and_then(|v| v.as_str()) {
+        result
+          .comment
+          .get_or_insert_with(String::new)
+          .push_str(comment);
+      }
+      if let Some(diff) = frame.get(STREAM_DIFF_KEY) {
+        let diff =
+          serde_json::from_value(diff.clone()).map_err(|err| PluginError::Internal(err.into()))?;
+        result.diff = Some(diff);
+      }
+    }
+    Ok(result)
   }
 }
 
-pub struct ChatRelatedQuestionsResponseParser;
-impl ResponseParser for ChatRelatedQuestionsResponseParser {
-  type ValueType = Vec<String>;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .get("data")
-      .and_then(|data| data.as_array())
-      .map(|array| {
-        array
-          .iter()
-          .flat_map(|item| {
-            item
-              .get("content")
-              .map(|s| s.as_str().map(|s| s.to_string()))?
-          })
-          .collect()
-      })
-      .ok_or(RemoteError::ParseResponse(json))
-  }
+/// Per-chat settings kept by the host and applied to every question asked in the chat.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatSettings {
+  /// Language the answers must be written in, e.g. `French`. Takes precedence over
+  /// `auto_match_language`.
+  pub response_language: Option<String>,
+  /// Detect the language of each question and ask the model to answer in the same language.
+  pub auto_match_language: bool,
+  /// Retrieval of embedded chunks, which questions can override with
+  /// [OllamaAIPlugin::stream_question_with_rag](crate::ollama_plugin::OllamaAIPlugin::stream_question_with_rag).
+  #[serde(default)]
+  pub rag: RagOptions,
+  /// Once more turns than this were asked, the oldest ones are summarized in the background and
+  /// replaced with their summary, so long chats keep fitting in the context window. `0`, the
+  /// default, never summarizes.
+  #[serde(default)]
+  pub auto_summarize_after_turns: u32,
+  /// How relevant the embedded documents must be to answer from them, for the questions asked
+  /// with [StreamOptions::require_relevant_context].
+  #[serde(default)]
+  pub context_gate: ContextGate,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-#[repr(u8)]
-pub enum CompleteTextType {
-  ImproveWriting = 1,
-  SpellingAndGrammar = 2,
-  MakeShorter = 3,
-  MakeLonger = 4,
-  ContinueWriting = 5,
-  Explain = 6,
-  AskAI = 7,
-  Custom = 8,
+/// Best score a question must reach in the documents of its chat, see [ChatSettings::context_gate].
+pub const DEFAULT_MIN_CONTEXT_SCORE: f64 = 0.5;
+
+/// What to do with a question none of the embedded documents of its chat is relevant to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContextGate {
+  /// The best matching chunk must score at least this much.
+  pub min_score: f64,
+  /// Fail the question with [PluginError::NoRelevantContext] without calling the model, rather
+  /// than answering it without document context.
+  pub strict: bool,
 }
 
-impl From<u8> for CompleteTextType {
-  fn from(value: u8) -> Self {
-    match value {
-      1 => CompleteTextType::ImproveWriting,
-      2 => CompleteTextType::SpellingAndGrammar,
-      3 => CompleteTextType::MakeShorter,
-      4 => CompleteTextType::MakeLonger,
-      5 => CompleteTextType::ContinueWriting,
-      6 => CompleteTextType::Explain,
-      7 => CompleteTextType::AskAI,
-      8 => CompleteTextType::Custom,
-      _ => CompleteTextType::AskAI,
+impl Default for ContextGate {
+  fn default() -> Self {
+    Self {
+      min_score: DEFAULT_MIN_CONTEXT_SCORE,
+      strict: false,
     }
   }
 }
 
-pub struct DatabaseSummaryResponseParser;
-impl ResponseParser for DatabaseSummaryResponseParser {
-  type ValueType = String;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .get("data")
-      .and_then(|data| data.as_str())
-      .map(|s| s.to_string())
-      .ok_or(RemoteError::ParseResponse(json))
-  }
-}
-
-pub struct DatabaseTranslateResponseParser;
-impl ResponseParser for DatabaseTranslateResponseParser {
-  type ValueType = LocalAITranslateRowResponse;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    json
-      .get("data")
-      .and_then(|data| LocalAITranslateRowResponse::deserialize(data).ok())
-      .ok_or(RemoteError::ParseResponse(json))
-  }
-}
-
 // async fn collect_answer(
 //   mut stream: QuestionStream,
 //   stop_when_num_of_char: Option<usize>,
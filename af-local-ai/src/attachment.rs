@@ -0,0 +1,546 @@
+use crate::ai_ops::EmbedReport;
+use crate::citation::{FILE_NAME_KEY, SOURCE_ID_KEY};
+use crate::index_audit::{finish_audit, AuditContent, AuditOperation};
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::scheduler::Priority;
+use af_ai_protocol::capability::Capability;
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io;
+use tracing::{error, info, trace, warn};
+
+/// Metadata key marking a file or text as embedded for the current session of a chat only. Its
+/// vectors are deleted when the chat is closed, see
+/// [OllamaAIPlugin::close_chat](crate::ollama_plugin::OllamaAIPlugin::close_chat).
+pub const EPHEMERAL_KEY: &str = "ephemeral";
+
+const PENDING_CLEANUP_FILE_NAME: &str = "pending_cleanup.json";
+
+/// A file or text embedded into a chat, see
+/// [OllamaAIPlugin::list_chat_attachments](crate::ollama_plugin::OllamaAIPlugin::list_chat_attachments).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentRecord {
+  pub source_id: String,
+  /// The file name, or the path when the file has no name.
+  pub path_or_name: String,
+  pub embedded_at: SystemTime,
+  /// Number of chunks stored in the vector store, when the plugin reports it.
+  pub chunk_count: Option<usize>,
+  /// Whether the attachment is deleted when the chat is closed, see [EPHEMERAL_KEY].
+  #[serde(default)]
+  pub ephemeral: bool,
+}
+
+/// Whether `metadata` marks its content as ephemeral with [EPHEMERAL_KEY].
+pub fn is_ephemeral(metadata: &HashMap<String, Value>) -> bool {
+  metadata
+    .get(EPHEMERAL_KEY)
+    .and_then(Value::as_bool)
+    .unwrap_or(false)
+}
+
+/// Outcome of [OllamaAIPlugin::close_chat_with_report](crate::ollama_plugin::OllamaAIPlugin::close_chat_with_report).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CloseChatReport {
+  /// Ephemeral attachments whose vectors were deleted.
+  pub purged: usize,
+  /// Ephemeral attachments whose vectors could not be deleted. Their deletes are retried at the
+  /// next init of a plugin with the same persist directory.
+  pub pending: usize,
+}
+
+pub fn pending_cleanup_path(persist_directory: &Path) -> PathBuf {
+  persist_directory.join(PENDING_CLEANUP_FILE_NAME)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PendingCleanupFile {
+  /// Filters of the `delete_documents` requests that failed.
+  filters: Vec<HashMap<String, Value>>,
+}
+
+/// Returns the filters of the deletes that failed in `persist_directory`, oldest first.
+pub fn read_pending_cleanup(
+  persist_directory: &Path,
+) -> Result<Vec<HashMap<String, Value>>, PluginError> {
+  let path = pending_cleanup_path(persist_directory);
+  if !path.exists() {
+    return Ok(vec![]);
+  }
+  let content = std::fs::read(&path)?;
+  let file = serde_json::from_slice::<PendingCleanupFile>(&content)
+    .map_err(|err| PluginError::Internal(err.into()))?;
+  Ok(file.filters)
+}
+
+/// Replaces the pending deletes of `persist_directory` with `filters`. The file is removed once
+/// there are none left.
+pub fn write_pending_cleanup(
+  persist_directory: &Path,
+  filters: Vec<HashMap<String, Value>>,
+) -> Result<(), PluginError> {
+  let path = pending_cleanup_path(persist_directory);
+  if filters.is_empty() {
+    if path.exists() {
+      std::fs::remove_file(&path)?;
+    }
+    return Ok(());
+  }
+  let content = serde_json::to_vec_pretty(&PendingCleanupFile { filters })
+    .map_err(|err| PluginError::Internal(err.into()))?;
+  let tmp_path = path.with_extension("json.tmp");
+  std::fs::write(&tmp_path, content)?;
+  std::fs::rename(&tmp_path, path)?;
+  Ok(())
+}
+
+/// Adds `filters` to the pending deletes of `persist_directory`, skipping those already pending.
+pub fn add_pending_cleanup(
+  persist_directory: &Path,
+  filters: Vec<HashMap<String, Value>>,
+) -> Result<(), PluginError> {
+  let mut pending = read_pending_cleanup(persist_directory)?;
+  for filter in filters {
+    if !pending.contains(&filter) {
+      pending.push(filter);
+    }
+  }
+  write_pending_cleanup(persist_directory, pending)
+}
+
+impl OllamaAIPlugin {
+  /// Embeds the file at `file_path` into `chat_id`. With
+  /// [EPHEMERAL_KEY] set to `true` in `metadata`, the file is
+  /// only kept until the chat is closed, see [OllamaAIPlugin::close_chat].
+  pub async fn embed_file(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+  ) -> Result<(), PluginError> {
+    self
+      .embed_file_with_priority(chat_id, file_path, metadata, Priority::Background)
+      .await
+  }
+
+  /// Same as [OllamaAIPlugin::embed_file], dispatched in the lane of `priority`. Use
+  /// [Priority::Interactive] for files the user is about to ask about.
+  pub async fn embed_file_with_priority(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+    priority: Priority,
+  ) -> Result<(), PluginError> {
+    self
+      .index_file(chat_id, file_path, metadata, priority, false)
+      .await
+      .map(|_| ())
+  }
+
+  /// Same as [OllamaAIPlugin::embed_file], indexing the chunks of the file that the plugin can
+  /// read when others fail, e.g. the pages of a PDF that are scanned images. Only fails when the
+  /// file can't be indexed at all, such as when it is missing or the plugin is not running.
+  ///
+  /// Plugins without [Capability::EmbedReport] index a file whole or not at all, reported as
+  /// [EmbedReport::whole_file] or [EmbedReport::whole_file_failed].
+  pub async fn embed_file_partial(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+  ) -> Result<EmbedReport, PluginError> {
+    self
+      .index_file(chat_id, file_path, metadata, Priority::Background, true)
+      .await
+  }
+
+  pub(crate) async fn index_file(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+    priority: Priority,
+    partial: bool,
+  ) -> Result<EmbedReport, PluginError> {
+    self.check_store_writable().await?;
+    if !file_path.exists() {
+      return Err(PluginError::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        "file not found",
+      )));
+    }
+
+    let file_path_str = file_path
+      .to_str()
+      .ok_or(PluginError::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        "file path invalid",
+      )))?
+      .to_string();
+
+    // Lets citations point back at the file, see [OllamaAIPlugin::stream_question_with_sources].
+    let mut metadata = metadata.unwrap_or_default();
+    if let Some(file_name) = file_path.file_name().and_then(|name| name.to_str()) {
+      metadata
+        .entry(FILE_NAME_KEY.to_string())
+        .or_insert_with(|| json!(file_name));
+    }
+    metadata
+      .entry(SOURCE_ID_KEY.to_string())
+      .or_insert_with(|| json!(file_path_str));
+    let source_id = metadata
+      .get(SOURCE_ID_KEY)
+      .and_then(|v| v.as_str())
+      .unwrap_or(&file_path_str)
+      .to_string();
+    let path_or_name = file_path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .unwrap_or(&file_path_str)
+      .to_string();
+    let ephemeral = is_ephemeral(&metadata);
+
+    self.wait_until_plugin_ready().await?;
+    let _permit = self.scheduler.acquire(priority).await;
+    self.embedding_model_info().await?;
+    let _store = self.read_vector_store().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.operation(plugin).with_priority(priority);
+    let audit = self
+      .index_audit
+      .start(AuditOperation::EmbedFile, &metadata, || {
+        AuditContent::File(file_path.clone())
+      });
+    let result = match (partial, self.has_capability(Capability::EmbedReport)) {
+      (true, true) => {
+        operation
+          .embed_file_with_report(chat_id, file_path_str, Some(metadata))
+          .await
+      },
+      (true, false) => match operation
+        .embed_file(chat_id, file_path_str, Some(metadata))
+        .await
+      {
+        Ok(()) => Ok(EmbedReport::whole_file()),
+        Err(err) if err.remote_error().is_some() => {
+          Ok(EmbedReport::whole_file_failed(err.to_string()))
+        },
+        Err(err) => Err(err),
+      },
+      (false, _) => operation
+        .embed_file(chat_id, file_path_str, Some(metadata))
+        .await
+        .map(|_| EmbedReport::whole_file()),
+    };
+    finish_audit(audit, &result);
+    let report = result?;
+    if report.chunks_indexed > 0 {
+      self
+        .record_attachment(chat_id, &source_id, &path_or_name, ephemeral)
+        .await;
+    }
+    Ok(report)
+  }
+
+  /// Same as [OllamaAIPlugin::embed_file], but citations of the file carry `source_id` instead of
+  /// the file path.
+  pub async fn embed_file_with_source(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    source_id: &str,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+  ) -> Result<(), PluginError> {
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.insert(SOURCE_ID_KEY.to_string(), json!(source_id));
+    self.embed_file(chat_id, file_path, Some(metadata)).await
+  }
+
+  /// Returns the files and texts embedded into `chat_id`, oldest first.
+  pub async fn list_chat_attachments(&self, chat_id: &str) -> Vec<AttachmentRecord> {
+    self
+      .attachments
+      .read()
+      .await
+      .get(chat_id)
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  /// Deletes the vectors of the attachment `source_id` from the vector store, so answers in the
+  /// chat no longer draw on it.
+  pub async fn remove_chat_attachment(
+    &self,
+    chat_id: &str,
+    source_id: &str,
+  ) -> Result<(), PluginError> {
+    trace!(
+      "[AI Plugin] remove attachment {} from chat {}",
+      source_id,
+      chat_id
+    );
+    self.check_store_writable().await?;
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.embedding_operation(plugin);
+    let mut filter = HashMap::new();
+    filter.insert("chat_id".to_string(), json!(chat_id));
+    filter.insert(SOURCE_ID_KEY.to_string(), json!(source_id));
+    let audit = self
+      .index_audit
+      .start(AuditOperation::Delete, &filter, || AuditContent::None);
+    let result = operation.delete_documents(filter).await;
+    finish_audit(audit, &result);
+    result?;
+    self.forget_attachment(chat_id, source_id).await;
+    Ok(())
+  }
+
+  pub(crate) async fn forget_attachment(&self, chat_id: &str, source_id: &str) {
+    let mut attachments = self.attachments.write().await;
+    if let Some(records) = attachments.get_mut(chat_id) {
+      records.retain(|record| record.source_id != source_id);
+      if records.is_empty() {
+        attachments.remove(chat_id);
+      }
+    }
+  }
+
+  /// Deletes the vectors of the ephemeral `records` of `chat_id` and forgets them, without
+  /// waiting for the plugin. Deletes that fail are saved to be retried at the next init.
+  pub(crate) async fn purge_ephemeral_attachments(
+    &self,
+    chat_id: &str,
+    records: Vec<AttachmentRecord>,
+  ) -> CloseChatReport {
+    let mut report = CloseChatReport::default();
+    if records.is_empty() {
+      return report;
+    }
+    let plugin = self.get_ai_plugin().await;
+    let mut failed = vec![];
+    for record in records {
+      let mut filter = HashMap::new();
+      filter.insert("chat_id".to_string(), json!(chat_id));
+      filter.insert(SOURCE_ID_KEY.to_string(), json!(record.source_id));
+      let result = match plugin.as_ref() {
+        Ok(plugin) => {
+          let operation = self.embedding_operation(plugin.clone());
+          let audit = self
+            .index_audit
+            .start(AuditOperation::Delete, &filter, || AuditContent::None);
+          let result = operation.delete_documents(filter.clone()).await;
+          finish_audit(audit, &result);
+          result.map_err(|err| err.to_string())
+        },
+        Err(err) => Err(err.to_string()),
+      };
+      match result {
+        Ok(()) => report.purged += 1,
+        Err(err) => {
+          warn!(
+            "[AI Plugin] failed to purge ephemeral attachment {} of chat {}: {}",
+            record.source_id, chat_id, err
+          );
+          failed.push(filter);
+        },
+      }
+      self.forget_attachment(chat_id, &record.source_id).await;
+    }
+    report.pending = failed.len();
+    if !failed.is_empty() {
+      match self.persist_directory().await {
+        Ok(persist_directory) => {
+          if let Err(err) = add_pending_cleanup(&persist_directory, failed) {
+            error!("[AI Plugin] failed to save pending cleanup: {:?}", err);
+          }
+        },
+        Err(_) => warn!(
+          "[AI Plugin] RAG is not enabled, dropping {} pending deletes",
+          report.pending
+        ),
+      }
+    }
+    report
+  }
+
+  /// Purges the ephemeral attachments of every chat, for chats that were never closed.
+  pub(crate) async fn purge_all_ephemeral_attachments(&self) {
+    let chats = self
+      .attachments
+      .read()
+      .await
+      .iter()
+      .map(|(chat_id, records)| {
+        let ephemeral = records
+          .iter()
+          .filter(|record| record.ephemeral)
+          .cloned()
+          .collect::<Vec<_>>();
+        (chat_id.clone(), ephemeral)
+      })
+      .filter(|(_, ephemeral)| !ephemeral.is_empty())
+      .collect::<Vec<_>>();
+    for (chat_id, records) in chats {
+      let report = self.purge_ephemeral_attachments(&chat_id, records).await;
+      info!(
+        "[AI Plugin] purged ephemeral attachments of chat {}: {:?}",
+        chat_id, report
+      );
+    }
+  }
+
+  /// Retries the deletes of ephemeral attachments that failed before, see
+  /// [OllamaAIPlugin::close_chat_with_report]. Those failing again stay pending.
+  pub(crate) async fn retry_pending_cleanup(&self) {
+    let Ok(persist_directory) = self.persist_directory().await else {
+      return;
+    };
+    let filters = match read_pending_cleanup(&persist_directory) {
+      Ok(filters) if !filters.is_empty() => filters,
+      Ok(_) => return,
+      Err(err) => {
+        error!("[AI Plugin] failed to read pending cleanup: {:?}", err);
+        return;
+      },
+    };
+    let Ok(plugin) = self.get_ai_plugin().await else {
+      return;
+    };
+    let operation = self.embedding_operation(plugin);
+    let mut failed = vec![];
+    for filter in filters {
+      let audit = self
+        .index_audit
+        .start(AuditOperation::Delete, &filter, || AuditContent::None);
+      let result = operation.delete_documents(filter.clone()).await;
+      finish_audit(audit, &result);
+      if let Err(err) = result {
+        warn!("[AI Plugin] pending delete {:?} failed: {}", filter, err);
+        failed.push(filter);
+      }
+    }
+    info!(
+      "[AI Plugin] retried pending deletes, {} still pending",
+      failed.len()
+    );
+    if let Err(err) = write_pending_cleanup(&persist_directory, failed) {
+      error!("[AI Plugin] failed to save pending cleanup: {:?}", err);
+    }
+  }
+
+  /// Remembers that `source_id` was embedded into `chat_id`. Embedding the same source again
+  /// replaces its record.
+  pub(crate) async fn record_attachment(
+    &self,
+    chat_id: &str,
+    source_id: &str,
+    path_or_name: &str,
+    ephemeral: bool,
+  ) {
+    let record = AttachmentRecord {
+      source_id: source_id.to_string(),
+      path_or_name: path_or_name.to_string(),
+      embedded_at: SystemTime::now(),
+      chunk_count: None,
+      ephemeral,
+    };
+    let mut attachments = self.attachments.write().await;
+    let records = attachments.entry(chat_id.to_string()).or_default();
+    records.retain(|existing| existing.source_id != source_id);
+    records.push(record);
+  }
+
+  /// Indexes the text of an MCP resource into a chat, so questions in the chat can draw on it.
+  /// Citations of the resource carry its URI as `source_id`.
+  #[cfg(feature = "mcp")]
+  pub async fn embed_mcp_resource(
+    &self,
+    chat_id: &str,
+    client: &af_mcp::client::MCPClient,
+    uri: &str,
+  ) -> Result<(), PluginError> {
+    let content = client.read_resource(uri).await?;
+    let text = match (content.text, content.blob) {
+      (Some(text), _) => text,
+      (None, Some(blob)) => String::from_utf8(blob).map_err(|_| {
+        PluginError::Internal(anyhow::anyhow!("resource {} is not a text resource", uri))
+      })?,
+      (None, None) => {
+        return Err(PluginError::Internal(anyhow::anyhow!(
+          "resource {} is empty",
+          uri
+        )))
+      },
+    };
+
+    let file_name = uri.rsplit('/').next().unwrap_or(uri);
+    let mut metadata = HashMap::new();
+    metadata.insert("chat_id".to_string(), json!(chat_id));
+    metadata.insert("uri".to_string(), json!(uri));
+    metadata.insert(SOURCE_ID_KEY.to_string(), json!(uri));
+    metadata.insert(FILE_NAME_KEY.to_string(), json!(file_name));
+    self.embed_text(&text, metadata).await
+  }
+
+  /// Closes an existing chat session.
+  ///
+  /// # Arguments
+  ///
+  /// * `chat_id` - A string slice containing the unique identifier for the chat session to close.
+  /// * `purge_attachments` - Whether to also delete the files and texts embedded into the chat.
+  ///   Ephemeral ones are deleted either way, see
+  ///   [EPHEMERAL_KEY].
+  ///
+  /// # Returns
+  ///
+  /// A `Result<()>` indicating success or failure.
+  pub async fn close_chat(&self, chat_id: &str, purge_attachments: bool) -> anyhow::Result<()> {
+    self
+      .close_chat_with_report(chat_id, purge_attachments)
+      .await?;
+    Ok(())
+  }
+
+  /// Same as [OllamaAIPlugin::close_chat], and reports how many ephemeral attachments of the chat
+  /// were deleted.
+  ///
+  /// Deletes that fail, e.g. because the plugin is gone, are saved in the persist directory and
+  /// retried at the next successful init.
+  pub async fn close_chat_with_report(
+    &self,
+    chat_id: &str,
+    purge_attachments: bool,
+  ) -> anyhow::Result<CloseChatReport> {
+    trace!("[AI Plugin] close chat: {}", chat_id);
+    if purge_attachments {
+      self.check_store_writable().await?;
+    }
+    self.chat_settings.write().await.remove(chat_id);
+    self.rolling_summary.forget(chat_id);
+    self.chat_budget.forget(chat_id);
+    self.created_chats.lock().remove(chat_id);
+    let (ephemeral, persistent): (Vec<_>, Vec<_>) = self
+      .list_chat_attachments(chat_id)
+      .await
+      .into_iter()
+      .partition(|attachment| attachment.ephemeral);
+    let report = self.purge_ephemeral_attachments(chat_id, ephemeral).await;
+    if purge_attachments {
+      for attachment in persistent {
+        self
+          .remove_chat_attachment(chat_id, &attachment.source_id)
+          .await?;
+      }
+    }
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.operation(plugin);
+    operation.close_chat(chat_id).await?;
+    Ok(report)
+  }
+}
@@ -0,0 +1,51 @@
+use af_plugin::util::REDACTED;
+use serde_json::{json, Value};
+use std::fmt::{Debug, Formatter};
+
+/// Credentials for an Ollama server behind a reverse proxy that requires authentication.
+///
+/// The secrets are sent to the plugin in the `auth` init param and are masked in `Debug` output
+/// and logs.
+#[derive(Clone, Eq, PartialEq)]
+pub enum OllamaAuth {
+  /// `Authorization: Bearer <token>`.
+  Bearer(String),
+  /// `Authorization: Basic <base64(user:pass)>`.
+  Basic { user: String, pass: String },
+  /// A header of the proxy's choosing, such as an API key.
+  CustomHeader { name: String, value: String },
+}
+
+impl OllamaAuth {
+  /// The `auth` block of the plugin init params.
+  pub fn to_params(&self) -> Value {
+    match self {
+      OllamaAuth::Bearer(token) => json!({ "type": "bearer", "token": token }),
+      OllamaAuth::Basic { user, pass } => json!({ "type": "basic", "user": user, "pass": pass }),
+      OllamaAuth::CustomHeader { name, value } => {
+        json!({ "type": "custom_header", "name": name, "value": value })
+      },
+    }
+  }
+}
+
+impl Debug for OllamaAuth {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      OllamaAuth::Bearer(_) => f
+        .debug_tuple("Bearer")
+        .field(&format_args!("{}", REDACTED))
+        .finish(),
+      OllamaAuth::Basic { user, .. } => f
+        .debug_struct("Basic")
+        .field("user", user)
+        .field("pass", &format_args!("{}", REDACTED))
+        .finish(),
+      OllamaAuth::CustomHeader { name, .. } => f
+        .debug_struct("CustomHeader")
+        .field("name", name)
+        .field("value", &format_args!("{}", REDACTED))
+        .finish(),
+    }
+  }
+}
@@ -0,0 +1,447 @@
+//! A stand-in for the Ollama plugin used by the `fake-plugin-tests` integration tests.
+//!
+//! It speaks the same newline delimited JSON RPC protocol as the real plugin, but answers
+//! `handle` requests from a scenario file instead of running models. The scenario is read from
+//! the path given as the first argument, or from `fake_plugin_scenario.json` next to the
+//! executable. Given `--rpc-pipe <path>`, it talks over the Unix domain socket at `path` instead
+//! of stdio, and writes noise to stdout.
+//!
+//! ```json
+//! {
+//!   "request_log": "/tmp/requests.log",
+//!   "methods": {
+//!     "system_info": [{ "result": { "data": { "version": "fake" } } }],
+//!     "stream_answer_v2": [
+//!       { "stream": ["{\"1\":\"Hello\"}", "{\"1\":\" world\"}"], "delay_ms": 10 },
+//!       { "stream": ["{\"1\":\"Bye\"}"], "disconnect_after": 1 }
+//!     ],
+//!     "answer": [{ "error": { "code": 1, "message": "model not loaded" } }]
+//!   }
+//! }
+//! ```
+//!
+//! Each method replies with its responses in order and keeps repeating the last one. A response
+//! with `when` is only sent to requests whose params have the given fields, every time they
+//! match, e.g. `"when": { "filter": { "space": "a" } }`. Methods missing from the scenario are
+//! rejected with a method not found error.
+//!
+//! With `"vector_store": true`, `embed_text`, `embed_file`, `delete_documents` and
+//! `similarity_search` are answered from an in-memory store instead: a search returns the texts
+//! whose metadata has every field of the filter and that share a word with the query. Files are
+//! split into chunks on blank lines, and the chunks with malformed UTF-8 fail to embed. Each persist directory has its own
+//! store: the one of `initialize` at first, then the one given to `vs_switch`. These methods are
+//! answered after the `delay_ms` of their scenario reply, if any.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const SCENARIO_FILE_NAME: &str = "fake_plugin_scenario.json";
+
+#[derive(Debug, Default, Deserialize)]
+struct Scenario {
+  /// Every request line received is appended to this file.
+  #[serde(default)]
+  request_log: Option<PathBuf>,
+  /// Delay before acknowledging `initialize`, to simulate a plugin that is slow to start.
+  #[serde(default)]
+  init_delay_ms: u64,
+  /// Delay between acknowledging `shutdown` and exiting, to simulate a plugin that is slow to
+  /// exit.
+  #[serde(default)]
+  shutdown_delay_ms: u64,
+  #[serde(default)]
+  methods: HashMap<String, VecDeque<Reply>>,
+  /// Answers the vector store methods from memory, see the module documentation.
+  #[serde(default)]
+  vector_store: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Reply {
+  /// Sent as the result of the request.
+  #[serde(default)]
+  result: Option<Value>,
+  /// Sent as stream frames, followed by the end of the stream.
+  #[serde(default)]
+  stream: Option<Vec<Value>>,
+  /// Sent as the error of the request. With `stream`, sent after the frames instead of the end of
+  /// the stream.
+  #[serde(default)]
+  error: Option<Value>,
+  /// Delay before replying, and between stream frames.
+  #[serde(default)]
+  delay_ms: u64,
+  /// Exit the process after sending this many stream frames. Requests without a stream exit
+  /// before replying.
+  #[serde(default)]
+  disconnect_after: Option<usize>,
+  /// Sent as the `data` of the result instead of `result`: the value of this environment
+  /// variable of the fake plugin process, or null if it is not set.
+  #[serde(default)]
+  echo_env: Option<String>,
+  /// Sent as the single answer frame of a stream instead of `stream`: the value of this field of
+  /// the params of the request.
+  #[serde(default)]
+  echo_param: Option<String>,
+  /// Fields the params of the request must have for this reply to be sent.
+  #[serde(default)]
+  when: Option<serde_json::Map<String, Value>>,
+}
+
+type Output = Arc<Mutex<Box<dyn Write + Send>>>;
+
+type Document = (String, serde_json::Map<String, Value>);
+
+/// Texts embedded with `embed_text`, with their metadata, by persist directory.
+#[derive(Default)]
+struct VectorStore {
+  directory: String,
+  documents: HashMap<String, Vec<Document>>,
+}
+
+impl VectorStore {
+  fn is_store_method(method: &str) -> bool {
+    matches!(
+      method,
+      "embed_text" | "embed_file" | "delete_documents" | "similarity_search" | "vs_switch"
+    )
+  }
+
+  fn open(&mut self, init_params: &Value) {
+    self.directory = init_params["vectorstore_config"]["persist_directory"]
+      .as_str()
+      .unwrap_or_default()
+      .to_string();
+  }
+
+  /// The result of `method`, one of the vector store methods, or its error.
+  fn handle(&mut self, method: &str, params: &Value) -> Result<Value, Value> {
+    if method == "vs_switch" {
+      self.directory = params["persist_directory"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+      return Ok(json!({}));
+    }
+    let documents = self.documents.entry(self.directory.clone()).or_default();
+    let filter = params["filter"].as_object().cloned().unwrap_or_default();
+    let matches = |metadata: &serde_json::Map<String, Value>| {
+      filter
+        .iter()
+        .all(|(key, value)| metadata.get(key) == Some(value))
+    };
+    match method {
+      "embed_text" => {
+        let text = params["input"].as_str().unwrap_or_default().to_string();
+        let metadata = params["metadata"].as_object().cloned().unwrap_or_default();
+        documents.push((text, metadata));
+        Ok(json!({}))
+      },
+      "embed_file" => {
+        let path = params["file_path"].as_str().unwrap_or_default();
+        let content = std::fs::read(path)
+          .map_err(|err| json!({ "code": 1, "message": format!("{}: {}", path, err) }))?;
+        let content = String::from_utf8_lossy(&content);
+        let metadata = params["metadata"].as_object().cloned().unwrap_or_default();
+        let chunks = content
+          .split("\n\n")
+          .map(str::trim)
+          .filter(|chunk| !chunk.is_empty())
+          .collect::<Vec<_>>();
+        let failures = chunks
+          .iter()
+          .enumerate()
+          .filter(|(_, chunk)| chunk.contains('\u{FFFD}'))
+          .map(|(index, _)| json!({ "index": index, "reason": "malformed text" }))
+          .collect::<Vec<_>>();
+        if params["report"] != json!(true) && !failures.is_empty() {
+          return Err(json!({ "code": 1, "message": "malformed text" }));
+        }
+        let indexed = chunks
+          .iter()
+          .filter(|chunk| !chunk.contains('\u{FFFD}'))
+          .map(|chunk| (chunk.to_string(), metadata.clone()))
+          .collect::<Vec<_>>();
+        let report = json!({
+          "chunks_indexed": indexed.len(),
+          "chunks_failed": failures.len(),
+          "failures": failures,
+        });
+        documents.extend(indexed);
+        Ok(json!({ "data": report }))
+      },
+      "delete_documents" => {
+        documents.retain(|(_, metadata)| !matches(metadata));
+        Ok(json!({}))
+      },
+      "similarity_search" => {
+        let query = words(params["query"].as_str().unwrap_or_default());
+        let found = documents
+          .iter()
+          .filter(|(text, metadata)| {
+            matches(metadata) && words(text).iter().any(|word| query.contains(word))
+          })
+          .map(|(text, _)| json!(text))
+          .collect::<Vec<_>>();
+        Ok(json!({ "data": found }))
+      },
+      _ => Ok(json!({})),
+    }
+  }
+}
+
+fn words(text: &str) -> Vec<String> {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(str::to_lowercase)
+    .collect()
+}
+
+fn main() {
+  let scenario = read_scenario().unwrap_or_else(|err| {
+    eprintln!("fake plugin: {}", err);
+    std::process::exit(2);
+  });
+  let request_log = scenario.request_log.clone();
+  let init_delay = Duration::from_millis(scenario.init_delay_ms);
+  let shutdown_delay = Duration::from_millis(scenario.shutdown_delay_ms);
+  let methods = Arc::new(Mutex::new(scenario.methods));
+  let vector_store = scenario
+    .vector_store
+    .then(|| Arc::new(Mutex::new(VectorStore::default())));
+  let (input, output) = connect().unwrap_or_else(|err| {
+    eprintln!("fake plugin: {}", err);
+    std::process::exit(2);
+  });
+  let output: Output = Arc::new(Mutex::new(output));
+
+  for line in BufReader::new(input).lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => break,
+    };
+    if let Some(path) = request_log.as_ref() {
+      log_request(path, &line);
+    }
+    let request = match serde_json::from_str::<Value>(&line) {
+      Ok(request) => request,
+      Err(_) => continue,
+    };
+    let id = match request.get("id").and_then(Value::as_u64) {
+      Some(id) => id,
+      None => continue,
+    };
+
+    match request.get("method").and_then(Value::as_str) {
+      Some("initialize") => {
+        if let Some(store) = vector_store.as_ref() {
+          store.lock().unwrap().open(&request["params"]);
+        }
+        std::thread::sleep(init_delay);
+        write_line(&output, &json!({ "id": id, "result": {} }));
+      },
+      Some("shutdown") => {
+        write_line(&output, &json!({ "id": id, "result": {} }));
+        std::thread::sleep(shutdown_delay);
+        std::process::exit(0);
+      },
+      Some("handle") => {
+        let method = request["params"]["method"]
+          .as_str()
+          .unwrap_or_default()
+          .to_string();
+        let params = &request["params"]["params"];
+        if let Some(store) = vector_store
+          .clone()
+          .filter(|_| VectorStore::is_store_method(&method))
+        {
+          let delay = next_reply(&methods, &method, params).map_or(0, |reply| reply.delay_ms);
+          let params = params.clone();
+          let output = output.clone();
+          std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(delay));
+            let response = match store.lock().unwrap().handle(&method, &params) {
+              Ok(result) => json!({ "id": id, "result": result }),
+              Err(error) => json!({ "id": id, "error": error }),
+            };
+            write_line(&output, &response);
+          });
+          continue;
+        }
+        let reply = next_reply(&methods, &method, params).map(|reply| reply.echo(params));
+        let output = output.clone();
+        std::thread::spawn(move || send_reply(&output, id, &method, reply));
+      },
+      Some(other) => {
+        let error = json!({ "code": -32601, "message": format!("Method not found: {}", other) });
+        write_line(&output, &json!({ "id": id, "error": error }));
+      },
+      None => {},
+    }
+  }
+}
+
+/// The value of `--rpc-pipe`, and the other arguments.
+fn parse_args() -> (Option<PathBuf>, Vec<std::ffi::OsString>) {
+  let mut pipe = None;
+  let mut rest = Vec::new();
+  let mut args = std::env::args_os().skip(1);
+  while let Some(arg) = args.next() {
+    if arg == "--rpc-pipe" {
+      pipe = args.next().map(PathBuf::from);
+    } else {
+      rest.push(arg);
+    }
+  }
+  (pipe, rest)
+}
+
+type Channel = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+fn connect() -> Result<Channel, String> {
+  match parse_args().0 {
+    None => Ok((Box::new(std::io::stdin()), Box::new(std::io::stdout()))),
+    Some(path) => connect_pipe(&path),
+  }
+}
+
+#[cfg(unix)]
+fn connect_pipe(path: &std::path::Path) -> Result<Channel, String> {
+  let stream =
+    std::os::unix::net::UnixStream::connect(path).map_err(|err| format!("{:?}: {}", path, err))?;
+  let writer = stream.try_clone().map_err(|err| err.to_string())?;
+  // Not read by the host, which only listens to the pipe.
+  println!("{{\"injected\": \"not a protocol message\"}}");
+  Ok((Box::new(stream), Box::new(writer)))
+}
+
+#[cfg(not(unix))]
+fn connect_pipe(_path: &std::path::Path) -> Result<Channel, String> {
+  Err("--rpc-pipe is only supported on Unix".to_string())
+}
+
+fn read_scenario() -> Result<Scenario, String> {
+  let path = match parse_args().1.into_iter().next() {
+    Some(path) => PathBuf::from(path),
+    None => std::env::current_exe()
+      .map_err(|err| err.to_string())?
+      .with_file_name(SCENARIO_FILE_NAME),
+  };
+  if !path.exists() {
+    return Ok(Scenario::default());
+  }
+  let content = std::fs::read(&path).map_err(|err| format!("{:?}: {}", path, err))?;
+  serde_json::from_slice(&content).map_err(|err| format!("{:?}: {}", path, err))
+}
+
+fn next_reply(
+  methods: &Mutex<HashMap<String, VecDeque<Reply>>>,
+  method: &str,
+  params: &Value,
+) -> Option<Reply> {
+  let mut methods = methods.lock().unwrap();
+  let replies = methods.get_mut(method)?;
+  let matching = replies.iter().find(|reply| match reply.when.as_ref() {
+    Some(when) => when
+      .iter()
+      .all(|(key, value)| params.get(key) == Some(value)),
+    None => false,
+  });
+  if let Some(reply) = matching {
+    return Some(reply.clone());
+  }
+  let queued = replies
+    .iter()
+    .enumerate()
+    .filter(|(_, reply)| reply.when.is_none())
+    .map(|(index, _)| index)
+    .collect::<Vec<_>>();
+  match queued.as_slice() {
+    [] => None,
+    [index] => replies.get(*index).cloned(),
+    [index, ..] => replies.remove(*index),
+  }
+}
+
+impl Reply {
+  fn echo(mut self, params: &Value) -> Self {
+    if let Some(name) = self.echo_param.as_ref() {
+      let frame = json!({ "1": params[name.as_str()] });
+      self.stream = Some(vec![json!(frame.to_string())]);
+    }
+    self
+  }
+}
+
+fn send_reply(output: &Output, id: u64, method: &str, reply: Option<Reply>) {
+  let reply = match reply {
+    Some(reply) => reply,
+    None => {
+      let error = json!({ "code": -32601, "message": format!("Method not found: {}", method) });
+      write_line(output, &json!({ "id": id, "error": error }));
+      return;
+    },
+  };
+
+  let delay = Duration::from_millis(reply.delay_ms);
+  std::thread::sleep(delay);
+
+  if let Some(frames) = reply.stream {
+    for (index, frame) in frames.into_iter().enumerate() {
+      if reply.disconnect_after == Some(index) {
+        std::process::exit(1);
+      }
+      if index > 0 {
+        std::thread::sleep(delay);
+      }
+      let frame = json!({ "id": id, "result": { "stream": { "has_more": true, "data": frame } } });
+      write_line(output, &frame);
+    }
+    if reply.disconnect_after.is_some() {
+      std::process::exit(1);
+    }
+    if let Some(error) = reply.error {
+      write_line(output, &json!({ "id": id, "error": error }));
+      return;
+    }
+    let end = json!({ "id": id, "result": { "stream": { "has_more": false, "data": {} } } });
+    write_line(output, &end);
+    return;
+  }
+
+  if reply.disconnect_after.is_some() {
+    std::process::exit(1);
+  }
+  match reply.error {
+    Some(error) => write_line(output, &json!({ "id": id, "error": error })),
+    None => {
+      let result = match reply.echo_env {
+        Some(name) => json!({ "data": std::env::var(name).ok() }),
+        None => reply.result.unwrap_or_else(|| json!({})),
+      };
+      write_line(output, &json!({ "id": id, "result": result }));
+    },
+  }
+}
+
+fn write_line(output: &Output, value: &Value) {
+  let mut output = output.lock().unwrap();
+  let _ = writeln!(output, "{}", value);
+  let _ = output.flush();
+}
+
+fn log_request(path: &PathBuf, line: &str) {
+  if let Ok(mut file) = std::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+  {
+    let _ = writeln!(file, "{}", line);
+  }
+}
@@ -0,0 +1,170 @@
+use crate::ai_ops::{STREAM_ANSWER_KEY, STREAM_COMMENT_KEY};
+use crate::ollama_plugin::{OllamaAIPlugin, OllamaPluginConfig};
+use af_plugin::error::PluginError;
+use af_plugin::manager::PluginManager;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio_stream::StreamExt;
+use tracing::error;
+
+/// Time given to the runtime's tasks to finish in [BlockingLocalAI::shutdown].
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answer and comment of a completion, see [BlockingLocalAI::complete_text_collect].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompletedText {
+  pub answer: String,
+  pub comment: Option<String>,
+}
+
+/// Synchronous facade over [OllamaAIPlugin] for callers without a Tokio runtime, such as FFI
+/// threads. It owns a dedicated runtime that every call blocks on.
+///
+/// Methods return [PluginError::BlockingInAsyncContext] when called from within a Tokio
+/// runtime, where blocking on another runtime would panic.
+pub struct BlockingLocalAI {
+  runtime: Option<Runtime>,
+  plugin: Arc<OllamaAIPlugin>,
+}
+
+impl BlockingLocalAI {
+  /// Creates the facade with a runtime of `worker_threads` threads. The plugin is started with
+  /// [BlockingLocalAI::init_plugin].
+  pub fn new(
+    plugin_manager: Arc<PluginManager>,
+    worker_threads: usize,
+  ) -> Result<Self, PluginError> {
+    ensure_not_in_runtime()?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+      .worker_threads(worker_threads.max(1))
+      .thread_name("af-local-ai-blocking")
+      .enable_all()
+      .build()?;
+    Ok(Self {
+      runtime: Some(runtime),
+      plugin: Arc::new(OllamaAIPlugin::new(plugin_manager)),
+    })
+  }
+
+  /// The wrapped plugin, for the APIs without a blocking counterpart.
+  pub fn plugin(&self) -> &Arc<OllamaAIPlugin> {
+    &self.plugin
+  }
+
+  pub fn init_plugin(&self, config: OllamaPluginConfig) -> Result<(), PluginError> {
+    let plugin = self.plugin.clone();
+    self.block_on(async move { plugin.init_plugin(config).await }, None)
+  }
+
+  pub fn ask_question(
+    &self,
+    chat_id: &str,
+    message: &str,
+    timeout: Duration,
+  ) -> Result<String, PluginError> {
+    self.block_on(self.plugin.ask_question(chat_id, message), Some(timeout))
+  }
+
+  /// Runs a v2 completion and drains its stream.
+  pub fn complete_text_collect(
+    &self,
+    message: &str,
+    complete_type: u8,
+    format: Option<Value>,
+    metadata: Option<Value>,
+    timeout: Duration,
+  ) -> Result<CompletedText, PluginError> {
+    self.block_on(
+      async {
+        let mut stream = self
+          .plugin
+          .complete_text_v2(message, complete_type, format, metadata)
+          .await?;
+        let mut completed = CompletedText::default();
+        while let Some(frame) = stream.next().await {
+          let frame = frame?;
+          if let Some(answer) = frame.get(STREAM_ANSWER_KEY).and_then(|v| v.as_str()) {
+            completed.answer.push_str(answer);
+          }
+          if let Some(comment) = frame.get(STREAM_COMMENT_KEY).and_then(|v| v.as_str()) {
+            completed
+              .comment
+              .get_or_insert_with(String::new)
+              .push_str(comment);
+          }
+        }
+        Ok(completed)
+      },
+      Some(timeout),
+    )
+  }
+
+  pub fn generate_embedding(
+    &self,
+    text: &str,
+    timeout: Duration,
+  ) -> Result<Vec<Vec<f64>>, PluginError> {
+    self.block_on(self.plugin.generate_embedding(text), Some(timeout))
+  }
+
+  pub fn similarity_search(
+    &self,
+    query: &str,
+    filter: HashMap<String, Value>,
+    timeout: Duration,
+  ) -> Result<Vec<String>, PluginError> {
+    self.block_on(self.plugin.similarity_search(query, filter), Some(timeout))
+  }
+
+  /// Stops the plugin and joins the runtime's threads.
+  pub fn shutdown(mut self) -> Result<(), PluginError> {
+    ensure_not_in_runtime()?;
+    if let Some(runtime) = self.runtime.take() {
+      let plugin = self.plugin.clone();
+      if let Err(err) = runtime.block_on(plugin.destroy_plugin()) {
+        error!("[AI Plugin] Failed to destroy plugin: {:?}", err);
+      }
+      runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    }
+    Ok(())
+  }
+
+  fn block_on<T, F>(&self, future: F, timeout: Option<Duration>) -> Result<T, PluginError>
+  where
+    F: Future<Output = Result<T, PluginError>>,
+  {
+    ensure_not_in_runtime()?;
+    let runtime = self
+      .runtime
+      .as_ref()
+      .ok_or(PluginError::PluginNotConnected)?;
+    runtime.block_on(async {
+      match timeout {
+        Some(duration) => tokio::time::timeout(duration, future)
+          .await
+          .map_err(|_| PluginError::RequestTimeout(duration))?,
+        None => future.await,
+      }
+    })
+  }
+}
+
+impl Drop for BlockingLocalAI {
+  fn drop(&mut self) {
+    // Dropping a runtime blocks, which panics inside another runtime.
+    if let Some(runtime) = self.runtime.take() {
+      runtime.shutdown_background();
+    }
+  }
+}
+
+fn ensure_not_in_runtime() -> Result<(), PluginError> {
+  match tokio::runtime::Handle::try_current() {
+    Ok(_) => Err(PluginError::BlockingInAsyncContext),
+    Err(_) => Ok(()),
+  }
+}
@@ -0,0 +1,242 @@
+use crate::ai_ops::STREAM_ANSWER_KEY;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::rolling_summary::summarize_in_background;
+use af_plugin::core::plugin::Plugin;
+use af_plugin::error::PluginError;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Characters of English text per token, a rough average over the tokenizers of the chat models.
+pub const CHARS_PER_TOKEN: f64 = 4.0;
+
+/// Context window of the models missing from [default_context_window]: the `num_ctx` Ollama
+/// loads a model with unless told otherwise.
+pub const DEFAULT_CONTEXT_WINDOW: u32 = 2048;
+
+/// Context window of `model_name`, matched on its family, see
+/// [OllamaPluginConfig::with_context_window](crate::ollama_plugin::OllamaPluginConfig::with_context_window).
+pub fn default_context_window(model_name: &str) -> u32 {
+  let family = model_name.split(':').next().unwrap_or_default();
+  match family {
+    "llama3.1" | "llama3.2" | "llama3.3" | "phi3" | "phi3.5" | "qwen2.5" => 131_072,
+    "mistral" | "mistral-nemo" | "mixtral" => 32_768,
+    "gemma2" | "gemma" | "llama3" => 8_192,
+    "llama2" | "deepseek-r1" => 4_096,
+    _ => DEFAULT_CONTEXT_WINDOW,
+  }
+}
+
+/// How much of the context window of the chat model the turns of a chat fill, in estimated
+/// tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChatBudget {
+  pub used_estimate: u32,
+  pub capacity_estimate: u32,
+  /// `used_estimate` over `capacity_estimate`. Past 1.0, the model no longer sees the earliest
+  /// turns.
+  pub ratio: f64,
+}
+
+pub type BudgetCallback = Arc<dyn Fn(&str, ChatBudget) + Send + Sync>;
+
+struct BudgetThreshold {
+  ratio: f64,
+  callback: BudgetCallback,
+  /// Chats at or past `ratio`, notified once until they drop below it.
+  crossed: HashSet<String>,
+}
+
+struct BudgetState {
+  capacity: u32,
+  /// Characters of the question and answer of each turn of each chat, in order.
+  turns: HashMap<String, Vec<usize>>,
+  thresholds: Vec<BudgetThreshold>,
+}
+
+/// Estimates the share of the context window each chat uses from the characters of its turns.
+pub struct ChatBudgetTracker {
+  state: parking_lot::Mutex<BudgetState>,
+}
+
+impl Default for ChatBudgetTracker {
+  fn default() -> Self {
+    Self::new(DEFAULT_CONTEXT_WINDOW)
+  }
+}
+
+impl ChatBudgetTracker {
+  /// A tracker for a model with a context window of `capacity` tokens.
+  pub fn new(capacity: u32) -> Self {
+    Self {
+      state: parking_lot::Mutex::new(BudgetState {
+        capacity,
+        turns: HashMap::new(),
+        thresholds: Vec::new(),
+      }),
+    }
+  }
+
+  /// Changes the context window, e.g. after another chat model was configured.
+  pub fn set_capacity(&self, capacity: u32) {
+    self.state.lock().capacity = capacity;
+  }
+
+  /// The context window, in tokens.
+  pub fn capacity(&self) -> u32 {
+    self.state.lock().capacity
+  }
+
+  /// Calls `callback` when a chat reaches `ratio` of the context window. A chat is notified once,
+  /// and again only after its history was truncated or summarized below `ratio`.
+  pub fn on_budget_exceeded(&self, ratio: f64, callback: BudgetCallback) {
+    self.state.lock().thresholds.push(BudgetThreshold {
+      ratio,
+      callback,
+      crossed: HashSet::new(),
+    });
+  }
+
+  pub fn budget(&self, chat_id: &str) -> ChatBudget {
+    let state = self.state.lock();
+    budget_of(&state, chat_id)
+  }
+
+  /// Counts a turn of `chat_id` made of a `prompt_chars` question and a `response_chars` answer.
+  pub fn turn_done(&self, chat_id: &str, prompt_chars: usize, response_chars: usize) {
+    self.update(chat_id, |turns| turns.push(prompt_chars + response_chars));
+  }
+
+  /// Forgets the turns of `chat_id` after the first `keep_turns`.
+  pub fn truncated(&self, chat_id: &str, keep_turns: usize) {
+    self.update(chat_id, |turns| turns.truncate(keep_turns));
+  }
+
+  /// Replaces the first `turns` turns of `chat_id` by a summary of `summary_chars`, which belongs
+  /// to the turn that is now the first one.
+  pub fn summarized(&self, chat_id: &str, turns: usize, summary_chars: usize) {
+    self.update(chat_id, |history| {
+      history.drain(..turns.min(history.len()));
+      match history.first_mut() {
+        Some(first) => *first += summary_chars,
+        None => history.push(summary_chars),
+      }
+    });
+  }
+
+  pub fn forget(&self, chat_id: &str) {
+    let mut state = self.state.lock();
+    state.turns.remove(chat_id);
+    for threshold in &mut state.thresholds {
+      threshold.crossed.remove(chat_id);
+    }
+  }
+
+  /// Applies `change` to the turns of `chat_id`, then notifies the callbacks of the thresholds
+  /// the chat crossed. The callbacks run after the state is unlocked, so they may read it.
+  fn update(&self, chat_id: &str, change: impl FnOnce(&mut Vec<usize>)) {
+    let mut state = self.state.lock();
+    change(state.turns.entry(chat_id.to_string()).or_default());
+    let budget = budget_of(&state, chat_id);
+    let mut callbacks = Vec::new();
+    for threshold in &mut state.thresholds {
+      if budget.ratio < threshold.ratio {
+        threshold.crossed.remove(chat_id);
+      } else if threshold.crossed.insert(chat_id.to_string()) {
+        callbacks.push(threshold.callback.clone());
+      }
+    }
+    drop(state);
+    for callback in callbacks {
+      callback(chat_id, budget);
+    }
+  }
+}
+
+fn budget_of(state: &BudgetState, chat_id: &str) -> ChatBudget {
+  let chars = state
+    .turns
+    .get(chat_id)
+    .map(|turns| turns.iter().sum::<usize>())
+    .unwrap_or_default();
+  let used_estimate = (chars as f64 / CHARS_PER_TOKEN).ceil() as u32;
+  let ratio = if state.capacity == 0 {
+    0.0
+  } else {
+    used_estimate as f64 / state.capacity as f64
+  };
+  ChatBudget {
+    used_estimate,
+    capacity_estimate: state.capacity,
+    ratio,
+  }
+}
+
+/// Counts the turn of `chat_id` once its answer stream ends, sizing the answer with the
+/// characters of its answer frames. An answer dropped before the end counts what was received.
+pub(crate) fn budget_stream(
+  tracker: Arc<ChatBudgetTracker>,
+  chat_id: String,
+  prompt_chars: usize,
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut response_chars = 0;
+    while let Some(frame) = stream.next().await {
+      if let Ok(frame) = &frame {
+        if let Some(text) = frame.get(STREAM_ANSWER_KEY).and_then(Value::as_str) {
+          response_chars += text.chars().count();
+        }
+      }
+      if tx.send(frame).await.is_err() {
+        break;
+      }
+    }
+    tracker.turn_done(&chat_id, prompt_chars, response_chars);
+  });
+  ReceiverStream::new(rx)
+}
+
+impl OllamaAIPlugin {
+  /// Share of the context window of the chat model filled by the turns of `chat_id`, estimated
+  /// from their characters. Truncating or summarizing the history of the chat lowers it.
+  pub fn chat_budget(&self, chat_id: &str) -> ChatBudget {
+    self.chat_budget.budget(chat_id)
+  }
+
+  /// Calls `callback` with the chat id and its budget once a chat fills `ratio` of the context
+  /// window, e.g. to suggest starting a new chat at 0.8. Each chat is notified once per
+  /// threshold, until its history is truncated or summarized below it.
+  pub fn on_budget_exceeded(
+    &self,
+    ratio: f64,
+    callback: impl Fn(&str, ChatBudget) + Send + Sync + 'static,
+  ) {
+    self
+      .chat_budget
+      .on_budget_exceeded(ratio, Arc::new(callback));
+  }
+
+  /// Counts a turn of `chat_id` and, once the chat passes its
+  /// [ChatSettings::auto_summarize_after_turns], summarizes its oldest turns in the background.
+  pub(crate) async fn turn_asked(&self, chat_id: &str, plugin: Weak<Plugin>) {
+    let threshold = self
+      .get_chat_settings(chat_id)
+      .await
+      .auto_summarize_after_turns;
+    if let Some(turns) = self.rolling_summary.turn_asked(chat_id, threshold) {
+      summarize_in_background(
+        self.rolling_summary.clone(),
+        self.chat_budget.clone(),
+        chat_id.to_string(),
+        turns,
+        plugin,
+        self.scheduler.clone(),
+        self.rate_limiter.clone(),
+      );
+    }
+  }
+}
@@ -0,0 +1,353 @@
+use crate::ai_ops::{ChatSettings, MessageRole, RagOptions};
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::outbound_filter::RequestKind;
+use crate::scheduler::Priority;
+use crate::summary::ChatMessage;
+use af_ai_protocol::capability::Capability;
+use af_ai_protocol::method::TRUNCATE_CHAT;
+use af_ai_protocol::types::ChatSummary;
+use af_plugin::core::plugin::Plugin;
+use af_plugin::error::PluginError;
+use anyhow::anyhow;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Weak;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, trace, warn};
+
+/// Messages read to rebuild a chat whose plugin can't truncate it. Longer chats can't be rebuilt.
+const REPLAYED_HISTORY_LIMIT: usize = 10_000;
+
+/// Differences between the chats the host knows and the ones the plugin has, see
+/// [OllamaAIPlugin::sync_chats](crate::ollama_plugin::OllamaAIPlugin::sync_chats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatSyncReport {
+  /// Chats the host knows that the plugin doesn't have, e.g. after the plugin lost its data.
+  /// Create them again before asking in them.
+  pub missing_on_plugin: Vec<String>,
+  /// Chats the plugin has that the host doesn't know, e.g. left over from a crash. Close them to
+  /// free their sessions.
+  pub unknown_to_host: Vec<String>,
+}
+
+impl ChatSyncReport {
+  /// Compares `known_ids` with the chats of the plugin, keeping the order of each list.
+  pub fn new(known_ids: &[String], chats: &[ChatSummary]) -> Self {
+    let on_plugin = chats
+      .iter()
+      .map(|chat| chat.chat_id.as_str())
+      .collect::<HashSet<_>>();
+    let known = known_ids.iter().map(String::as_str).collect::<HashSet<_>>();
+    Self {
+      missing_on_plugin: known_ids
+        .iter()
+        .filter(|chat_id| !on_plugin.contains(chat_id.as_str()))
+        .cloned()
+        .collect(),
+      unknown_to_host: chats
+        .iter()
+        .filter(|chat| !known.contains(chat.chat_id.as_str()))
+        .map(|chat| chat.chat_id.clone())
+        .collect(),
+    }
+  }
+
+  pub fn is_in_sync(&self) -> bool {
+    self.missing_on_plugin.is_empty() && self.unknown_to_host.is_empty()
+  }
+}
+
+/// Chats created since the plugin process started. A new process starts with none.
+#[derive(Default)]
+pub(crate) struct CreatedChats {
+  plugin: Weak<Plugin>,
+  chat_ids: HashSet<String>,
+}
+
+impl CreatedChats {
+  pub(crate) fn insert(&mut self, plugin: &Weak<Plugin>, chat_id: &str) {
+    self.reset_if_restarted(plugin);
+    self.chat_ids.insert(chat_id.to_string());
+  }
+
+  pub(crate) fn contains(&mut self, plugin: &Weak<Plugin>, chat_id: &str) -> bool {
+    self.reset_if_restarted(plugin);
+    self.chat_ids.contains(chat_id)
+  }
+
+  pub(crate) fn remove(&mut self, chat_id: &str) {
+    self.chat_ids.remove(chat_id);
+  }
+
+  pub(crate) fn reset_if_restarted(&mut self, plugin: &Weak<Plugin>) {
+    if !self.plugin.ptr_eq(plugin) {
+      self.plugin = plugin.clone();
+      self.chat_ids.clear();
+    }
+  }
+}
+
+/// The question of each turn of `history`, oldest first. A turn starts with a `human` message.
+fn turn_questions(history: &[ChatMessage]) -> Vec<&str> {
+  history
+    .iter()
+    .filter(|message| message.role == "human")
+    .map(|message| message.content.as_str())
+    .collect()
+}
+
+impl OllamaAIPlugin {
+  /// Creates a new chat session. Creating a chat that already exists is a no-op.
+  ///
+  /// # Arguments
+  ///
+  /// * `chat_id` - A string slice containing the unique identifier for the chat session.
+  ///
+  /// # Returns
+  ///
+  /// A `Result<()>` indicating success or failure.
+  pub async fn create_chat(&self, chat_id: &str) -> Result<(), PluginError> {
+    self
+      .create_chat_with_rag(chat_id, RagOptions::default())
+      .await
+  }
+
+  /// Creates a new chat session whose questions are answered according to `settings`. The
+  /// settings are replaced when the chat already exists.
+  ///
+  /// Fails with [PluginError::InvalidRagOptions] when `settings.rag` asks for no chunks; a
+  /// `top_k` above [MAX_RAG_TOP_K](crate::ai_ops::MAX_RAG_TOP_K) is lowered to it.
+  pub async fn create_chat_with_settings(
+    &self,
+    chat_id: &str,
+    mut settings: ChatSettings,
+  ) -> Result<(), PluginError> {
+    settings.rag = settings.rag.validate()?;
+    self
+      .create_chat_with_rag(chat_id, settings.rag.clone())
+      .await?;
+    self.update_chat_settings(chat_id, settings).await;
+    Ok(())
+  }
+
+  pub(crate) async fn create_chat_with_rag(
+    &self,
+    chat_id: &str,
+    rag: RagOptions,
+  ) -> Result<(), PluginError> {
+    trace!("[AI Plugin] create chat: {}, {:?}", chat_id, rag);
+    self.wait_until_plugin_ready().await?;
+
+    let rag = if self.has_capability(Capability::RagOptions) {
+      rag
+    } else {
+      RagOptions::new(rag.top_k)
+    };
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.operation(plugin.clone());
+    match operation.create_chat(chat_id, &rag).await {
+      Err(PluginError::RemoteError(err)) if err.is_already_exists() => {
+        trace!("[AI Plugin] chat {} already exists", chat_id);
+      },
+      result => result?,
+    }
+    self.created_chats.lock().insert(&plugin, chat_id);
+    Ok(())
+  }
+
+  /// Whether the plugin has `chat_id`, which it forgets when it restarts.
+  ///
+  /// Plugins without a `chat_exists` method are asked nothing: the chats created with this
+  /// [OllamaAIPlugin] since the plugin process started are taken to exist.
+  pub async fn chat_exists(&self, chat_id: &str) -> Result<bool, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    match self.operation(plugin.clone()).chat_exists(chat_id).await {
+      Err(PluginError::UnsupportedMethod { .. }) => {
+        Ok(self.created_chats.lock().contains(&plugin, chat_id))
+      },
+      result => result,
+    }
+  }
+
+  /// The chats the plugin has, with their activity when the plugin reports it. Fails with
+  /// [PluginError::UnsupportedMethod] when the plugin can't list its chats.
+  pub async fn list_chats(&self) -> Result<Vec<ChatSummary>, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    self.operation(plugin).list_chats().await
+  }
+
+  /// Compares `known_ids`, the chats of the host, with the ones of the plugin, so the host can
+  /// create the missing chats again and close the ones it lost track of, e.g. after restoring a
+  /// backup. Fails like [OllamaAIPlugin::list_chats].
+  pub async fn sync_chats(&self, known_ids: &[String]) -> Result<ChatSyncReport, PluginError> {
+    let chats = self.list_chats().await?;
+    Ok(ChatSyncReport::new(known_ids, &chats))
+  }
+
+  /// Creates `chat_id` with `settings` unless the plugin already has it, see
+  /// [OllamaAIPlugin::chat_exists].
+  pub async fn ensure_chat(
+    &self,
+    chat_id: &str,
+    settings: ChatSettings,
+  ) -> Result<(), PluginError> {
+    if self.chat_exists(chat_id).await? {
+      return Ok(());
+    }
+    info!("[AI Plugin] creating missing chat: {}", chat_id);
+    self.create_chat_with_settings(chat_id, settings).await
+  }
+
+  /// Creates `chat_id` with its current settings if it is missing and
+  /// [OllamaPluginConfig::auto_create_chat] is set.
+  pub(crate) async fn auto_create_chat(&self, chat_id: &str) -> Result<(), PluginError> {
+    let enabled = self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .is_some_and(|config| config.auto_create_chat);
+    if !enabled {
+      return Ok(());
+    }
+    let settings = self.get_chat_settings(chat_id).await;
+    self.ensure_chat(chat_id, settings).await
+  }
+
+  /// Replaces the settings of a chat. They apply to the next question asked in the chat.
+  pub async fn update_chat_settings(&self, chat_id: &str, settings: ChatSettings) {
+    trace!(
+      "[AI Plugin] update chat settings: {}, {:?}",
+      chat_id,
+      settings
+    );
+    self
+      .chat_settings
+      .write()
+      .await
+      .insert(chat_id.to_string(), settings);
+  }
+
+  pub async fn get_chat_settings(&self, chat_id: &str) -> ChatSettings {
+    self
+      .chat_settings
+      .read()
+      .await
+      .get(chat_id)
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  /// Records a message of `role` in the history of `chat_id` without generating an answer, e.g.
+  /// a [MessageRole::Context] message telling the model that the page was renamed, or the turns
+  /// of a conversation imported from elsewhere. The following questions are answered with it.
+  ///
+  /// Fails with [PluginError::EmptyMessage] when `content` is blank, and with
+  /// [PluginError::UnsupportedMethod] when the plugin can't record messages.
+  pub async fn append_chat_message(
+    &self,
+    chat_id: &str,
+    role: MessageRole,
+    content: &str,
+  ) -> Result<(), PluginError> {
+    if content.trim().is_empty() {
+      return Err(PluginError::EmptyMessage);
+    }
+    let content = self.filter_outbound(content, RequestKind::Question)?;
+    trace!("[AI Plugin] append {:?} message to chat: {}", role, chat_id);
+    self.wait_until_plugin_ready().await?;
+    self.auto_create_chat(chat_id).await?;
+    self.related_questions.invalidate(chat_id);
+    let _permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    self
+      .operation(plugin)
+      .append_message(chat_id, role, &content)
+      .await
+  }
+
+  /// Forgets the turns of `chat_id` after the first `keep_first_n_turns`, a turn being a
+  /// question and its answer, so the following questions aren't answered with their context.
+  ///
+  /// Plugins without a `truncate_chat` method get the chat rebuilt from its history: it is
+  /// closed and created again with the same settings, and the retained questions are asked
+  /// again, which generates their answers anew. Fails with [PluginError::UnsupportedMethod]
+  /// when the plugin can't list the history either.
+  pub async fn truncate_chat_history(
+    &self,
+    chat_id: &str,
+    keep_first_n_turns: usize,
+  ) -> Result<(), PluginError> {
+    trace!(
+      "[AI Plugin] truncate chat: {}, keep turns: {}",
+      chat_id,
+      keep_first_n_turns
+    );
+    self.wait_until_plugin_ready().await?;
+    self.related_questions.invalidate(chat_id);
+    let _permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.operation(plugin);
+    self.rolling_summary.truncated(chat_id, keep_first_n_turns);
+    self.chat_budget.truncated(chat_id, keep_first_n_turns);
+    match operation.truncate_chat(chat_id, keep_first_n_turns).await {
+      Err(PluginError::UnsupportedMethod { method }) => {
+        warn!(
+          "[AI Plugin] plugin has no {} method, rebuilding chat {}",
+          method, chat_id
+        );
+      },
+      result => return result,
+    }
+
+    let history = match operation
+      .chat_history(chat_id, REPLAYED_HISTORY_LIMIT)
+      .await
+    {
+      Ok(history) if history.len() < REPLAYED_HISTORY_LIMIT => history,
+      Ok(_) => {
+        return Err(PluginError::Internal(anyhow!(
+          "chat {} is too long to be rebuilt",
+          chat_id
+        )))
+      },
+      Err(PluginError::UnsupportedMethod { .. }) => {
+        return Err(PluginError::UnsupportedMethod {
+          method: TRUNCATE_CHAT.to_string(),
+        })
+      },
+      Err(err) => return Err(err),
+    };
+    let questions = turn_questions(&history);
+    if questions.len() <= keep_first_n_turns {
+      return Ok(());
+    }
+
+    operation.close_chat(chat_id).await?;
+    let rag = self.get_chat_settings(chat_id).await.rag;
+    self.create_chat_with_rag(chat_id, rag).await?;
+    for question in &questions[..keep_first_n_turns] {
+      operation.send_message(chat_id, question, true).await?;
+    }
+    Ok(())
+  }
+
+  /// Asks the question of turn `turn_index` of `chat_id` again as `new_message`, after
+  /// forgetting that turn and the following ones, see [OllamaAIPlugin::truncate_chat_history].
+  /// Returns the stream of [OllamaAIPlugin::stream_question].
+  pub async fn edit_and_regenerate(
+    &self,
+    chat_id: &str,
+    turn_index: usize,
+    new_message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self.truncate_chat_history(chat_id, turn_index).await?;
+    self
+      .stream_question(chat_id, new_message, format, metadata)
+      .await
+  }
+}
@@ -0,0 +1,651 @@
+use crate::ai_ops::{
+  AIPluginOperation, ChunkFailure, CompleteTextType, EmbedReport, STREAM_ANSWER_KEY,
+  STREAM_METADATA_KEY,
+};
+use crate::chat_budget::CHARS_PER_TOKEN;
+use crate::diff::with_diff;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::post_process::post_processed;
+use crate::scheduler::Priority;
+use crate::semantic_search::sentences;
+use crate::trace::TracedStream;
+use crate::usage::{tracked_stream, UsageKind};
+use af_ai_protocol::capability::Capability;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ops::Range;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{trace, warn};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Share of the context window of the chat model the text of a completion may fill. The rest is
+/// left to the prompt and to the answer, which is often as long as the text, e.g. when improving
+/// writing.
+pub const COMPLETION_INPUT_SHARE: f64 = 0.5;
+
+/// Characters of the end of the previous chunk sent along with each chunk, see
+/// [TextChunk::preceding_text].
+pub const CHUNK_OVERLAP_CHARS: usize = 200;
+
+/// Key of the completion metadata holding [TextChunk::preceding_text].
+pub const PRECEDING_TEXT_KEY: &str = "preceding_text";
+
+const PARAGRAPH_SEPARATOR: &str = "\n\n";
+
+/// Tokens the text of a completion may have with a chat model of `context_window` tokens.
+pub fn completion_input_limit(context_window: u32) -> u32 {
+  (context_window as f64 * COMPLETION_INPUT_SHARE) as u32
+}
+
+/// Estimated tokens of `text`, see [CHARS_PER_TOKEN].
+pub fn estimate_tokens(text: &str) -> u32 {
+  (text.chars().count() as f64 / CHARS_PER_TOKEN).ceil() as u32
+}
+
+/// A piece of a text too long to be completed at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+  /// The text between the previous chunk and this one: a paragraph break, or nothing when a
+  /// paragraph was split. Concatenating the separators and texts gives back the original text.
+  pub separator: String,
+  pub text: String,
+  /// The end of the previous chunk, so the model knows what this chunk follows without
+  /// completing it twice.
+  pub preceding_text: Option<String>,
+}
+
+/// Splits `text` into chunks of at most `max_chars` characters with
+/// [ChunkStrategy::Paragraphs]. Chunks end at paragraph breaks, and paragraphs too long for a
+/// chunk are split between words, or between graphemes for words too long as well. Graphemes are
+/// never split, so a chunk only exceeds `max_chars` when it is a single grapheme longer than that.
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<TextChunk> {
+  let ranges = TextChunker::new(ChunkStrategy::Paragraphs { max_chars }).ranges(text);
+  let mut chunks: Vec<TextChunk> = vec![];
+  let mut previous_end = 0;
+  for range in ranges {
+    let chunk = TextChunk {
+      separator: text[previous_end..range.start].to_string(),
+      text: text[range.clone()].to_string(),
+      preceding_text: chunks.last().map(|previous| tail(&previous.text)),
+    };
+    chunks.push(chunk);
+    previous_end = range.end;
+  }
+  chunks
+}
+
+/// The chunks of [ChunkStrategy::Paragraphs]. Only the paragraph breaks between chunks are left
+/// out of them.
+fn paragraph_chunks(text: &str, max_chars: usize) -> Vec<Range<usize>> {
+  let max_chars = max_chars.max(1);
+  let mut units = vec![];
+  let mut start = 0;
+  for paragraph in text.split(PARAGRAPH_SEPARATOR) {
+    let range = start..start + paragraph.len();
+    start = range.end + PARAGRAPH_SEPARATOR.len();
+    if paragraph.chars().count() <= max_chars {
+      units.push(range);
+    } else {
+      units.extend(split_paragraph(text, range, max_chars));
+    }
+  }
+
+  let mut ranges = vec![];
+  let mut current: Option<Range<usize>> = None;
+  for unit in units {
+    match current.take() {
+      Some(chunk) if text[chunk.start..unit.end].chars().count() <= max_chars => {
+        current = Some(chunk.start..unit.end);
+      },
+      Some(chunk) => {
+        ranges.push(chunk);
+        current = Some(unit);
+      },
+      None => current = Some(unit),
+    }
+  }
+  ranges.extend(current);
+  ranges
+}
+
+/// Splits the paragraph `text[range]`, longer than `max_chars`, between words, and words longer
+/// than that between graphemes.
+fn split_paragraph(text: &str, range: Range<usize>, max_chars: usize) -> Vec<Range<usize>> {
+  let offset = range.start;
+  let mut units = vec![];
+  for (index, word) in text[range].split_word_bound_indices() {
+    let start = offset + index;
+    if word.chars().count() <= max_chars {
+      units.push(start..start + word.len());
+    } else {
+      units.extend(
+        word
+          .grapheme_indices(true)
+          .map(|(index, grapheme)| start + index..start + index + grapheme.len()),
+      );
+    }
+  }
+
+  let mut pieces = vec![];
+  let mut piece = offset..offset;
+  let mut piece_chars = 0;
+  for unit in units {
+    let chars = text[unit.clone()].chars().count();
+    if piece_chars + chars > max_chars && !piece.is_empty() {
+      pieces.push(piece);
+      piece = unit.start..unit.start;
+      piece_chars = 0;
+    }
+    piece.end = unit.end;
+    piece_chars += chars;
+  }
+  if !piece.is_empty() {
+    pieces.push(piece);
+  }
+  pieces
+}
+
+/// The last graphemes of `text`, up to [CHUNK_OVERLAP_CHARS] characters.
+fn tail(text: &str) -> String {
+  let mut chars = 0;
+  let mut start = text.len();
+  for (index, grapheme) in text.grapheme_indices(true).rev() {
+    chars += grapheme.chars().count();
+    if chars > CHUNK_OVERLAP_CHARS {
+      break;
+    }
+    start = index;
+  }
+  text[start..].to_string()
+}
+
+/// Key of the metadata of each chunk embedded by
+/// [OllamaAIPlugin::embed_document](crate::ollama_plugin::OllamaAIPlugin::embed_document) holding
+/// [Chunk::index].
+pub const CHUNK_INDEX_KEY: &str = "chunk_index";
+/// Key of the metadata holding [Chunk::byte_range], as `[start, end]`.
+pub const BYTE_RANGE_KEY: &str = "byte_range";
+
+/// How a [TextChunker] splits a document. Sizes are in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStrategy {
+  /// Windows of `size` characters, each starting `overlap` characters before the end of the
+  /// previous one.
+  FixedChars { size: usize, overlap: usize },
+  /// Runs of whole sentences of at most `max_chars` characters, each starting with the last
+  /// `overlap_sentences` sentences of the previous one.
+  SentenceWindow {
+    max_chars: usize,
+    overlap_sentences: usize,
+  },
+  /// Sections of Markdown: a chunk starts at each heading, and holds whole blocks, such as
+  /// paragraphs and lists, up to `max_chars` characters. Code fences are never split, so a
+  /// chunk holding one may be longer.
+  MarkdownAware { max_chars: usize },
+  /// Runs of whole paragraphs of at most `max_chars` characters, the chunks of [chunk_text].
+  /// Paragraphs longer than that are split between words.
+  Paragraphs { max_chars: usize },
+}
+
+/// A piece of a document, see [TextChunker].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+  /// `&document[byte_range]`.
+  pub text: String,
+  /// Position of the chunk in the document, from 0.
+  pub index: usize,
+  /// Where the chunk is in the document, so a citation of the chunk can point at its source.
+  /// Both ends are on grapheme boundaries.
+  pub byte_range: Range<usize>,
+}
+
+/// Outcome of
+/// [OllamaAIPlugin::embed_document](crate::ollama_plugin::OllamaAIPlugin::embed_document).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentEmbedReport {
+  /// Every chunk of the document, embedded or not.
+  pub chunks: Vec<Chunk>,
+  /// The chunks that were embedded and the ones that failed, by [Chunk::index].
+  pub report: EmbedReport,
+}
+
+/// Splits documents into [Chunk]s to embed, so every caller retrieves from chunks of the same
+/// kind. Graphemes are never split, nor are the sentences and blocks of the strategies keeping
+/// them whole unless they are longer than a chunk on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextChunker {
+  strategy: ChunkStrategy,
+}
+
+impl TextChunker {
+  pub fn new(strategy: ChunkStrategy) -> Self {
+    Self { strategy }
+  }
+
+  /// The chunks of `text`, in order. Whitespace between chunks is left out, and a text of
+  /// whitespace only has none.
+  pub fn chunk(&self, text: &str) -> Vec<Chunk> {
+    self
+      .ranges(text)
+      .into_iter()
+      .filter_map(|range| trimmed(text, range))
+      .enumerate()
+      .map(|(index, byte_range)| Chunk {
+        text: text[byte_range.clone()].to_string(),
+        index,
+        byte_range,
+      })
+      .collect()
+  }
+
+  /// The ranges of the chunks of `text`, before their whitespace is trimmed.
+  fn ranges(&self, text: &str) -> Vec<Range<usize>> {
+    match self.strategy {
+      ChunkStrategy::FixedChars { size, overlap } => {
+        fixed_chars(text, 0..text.len(), size, overlap)
+      },
+      ChunkStrategy::SentenceWindow {
+        max_chars,
+        overlap_sentences,
+      } => {
+        let units = fit_units(text, sentences(text), max_chars);
+        windows(text, &units, max_chars, overlap_sentences)
+      },
+      ChunkStrategy::MarkdownAware { max_chars } => markdown_chunks(text, max_chars),
+      ChunkStrategy::Paragraphs { max_chars } => paragraph_chunks(text, max_chars),
+    }
+  }
+}
+
+/// Windows of `size` characters of `text[range]`, ending on grapheme boundaries.
+fn fixed_chars(text: &str, range: Range<usize>, size: usize, overlap: usize) -> Vec<Range<usize>> {
+  let size = size.max(1);
+  let offset = range.start;
+  let graphemes = text[range.clone()]
+    .grapheme_indices(true)
+    .map(|(index, grapheme)| (offset + index, grapheme.chars().count()))
+    .collect::<Vec<_>>();
+  let boundary = |index: usize| graphemes.get(index).map_or(range.end, |(byte, _)| *byte);
+  let mut ranges = vec![];
+  let mut start = 0;
+  while start < graphemes.len() {
+    let mut end = start;
+    let mut chars = 0;
+    while end < graphemes.len() && (end == start || chars + graphemes[end].1 <= size) {
+      chars += graphemes[end].1;
+      end += 1;
+    }
+    ranges.push(boundary(start)..boundary(end));
+    if end == graphemes.len() {
+      break;
+    }
+    let mut next = end;
+    let mut overlapped = 0;
+    while next > start + 1 && overlapped + graphemes[next - 1].1 <= overlap {
+      next -= 1;
+      overlapped += graphemes[next].1;
+    }
+    start = next;
+  }
+  ranges
+}
+
+/// `units` with the ones longer than `max_chars` split into windows of `max_chars` characters.
+fn fit_units(text: &str, units: Vec<Range<usize>>, max_chars: usize) -> Vec<Range<usize>> {
+  units
+    .into_iter()
+    .flat_map(|unit| {
+      if text[unit.clone()].chars().count() <= max_chars {
+        vec![unit]
+      } else {
+        fixed_chars(text, unit, max_chars, 0)
+      }
+    })
+    .collect()
+}
+
+/// Runs of consecutive `units` spanning at most `max_chars` characters, each starting with the
+/// last `overlap` units of the previous run, unless no new unit fits after them.
+fn windows(
+  text: &str,
+  units: &[Range<usize>],
+  max_chars: usize,
+  overlap: usize,
+) -> Vec<Range<usize>> {
+  let fill = |start: usize| {
+    let mut end = start + 1;
+    while end < units.len() && text[units[start].start..units[end].end].chars().count() <= max_chars
+    {
+      end += 1;
+    }
+    end
+  };
+  let mut ranges = vec![];
+  let mut start = 0;
+  while start < units.len() {
+    let end = fill(start);
+    ranges.push(units[start].start..units[end - 1].end);
+    if end == units.len() {
+      break;
+    }
+    let overlapped = end.saturating_sub(overlap).max(start + 1);
+    start = if fill(overlapped) > end {
+      overlapped
+    } else {
+      end
+    };
+  }
+  ranges
+}
+
+/// A block of a Markdown document, see [markdown_blocks].
+struct Block {
+  range: Range<usize>,
+  heading: bool,
+  fence: bool,
+}
+
+/// The chunks of [ChunkStrategy::MarkdownAware].
+fn markdown_chunks(text: &str, max_chars: usize) -> Vec<Range<usize>> {
+  let mut ranges = vec![];
+  let mut current: Option<Range<usize>> = None;
+  for block in markdown_blocks(text) {
+    let fits =
+      |current: &Range<usize>| text[current.start..block.range.end].chars().count() <= max_chars;
+    match current.take() {
+      Some(chunk) if !block.heading && fits(&chunk) => {
+        current = Some(chunk.start..block.range.end);
+        continue;
+      },
+      Some(chunk) => ranges.push(chunk),
+      None => {},
+    }
+    if block.fence || text[block.range.clone()].chars().count() <= max_chars {
+      current = Some(block.range);
+      continue;
+    }
+    let units = fit_units(text, sentences_in(text, block.range), max_chars);
+    let mut pieces = windows(text, &units, max_chars, 0);
+    current = pieces.pop();
+    ranges.extend(pieces);
+  }
+  ranges.extend(current);
+  ranges
+}
+
+/// The blocks of a Markdown document: headings, code fences up to their closing fence or the end
+/// of the document, and runs of other lines up to a blank line, a heading or a fence.
+fn markdown_blocks(text: &str) -> Vec<Block> {
+  let mut blocks: Vec<Block> = vec![];
+  let mut fence: Option<(Range<usize>, &str)> = None;
+  let mut paragraph: Option<Range<usize>> = None;
+  let mut offset = 0;
+  for line in text.split_inclusive('\n') {
+    let start = offset;
+    offset += line.len();
+    let end = start + line.trim_end_matches(['\n', '\r']).len();
+    let content = line.trim_start_matches(' ');
+    let indented = line.len() - content.len() > 3;
+    if let Some((range, marker)) = fence.as_mut() {
+      range.end = end;
+      if !indented && content.trim_end().starts_with(*marker) && content.trim().len() >= 3 {
+        let range = range.clone();
+        fence = None;
+        blocks.push(Block {
+          range,
+          heading: false,
+          fence: true,
+        });
+      }
+      continue;
+    }
+    let fence_marker = ["```", "~~~"]
+      .into_iter()
+      .find(|marker| !indented && content.starts_with(marker));
+    let heading = !indented && is_heading(content);
+    if fence_marker.is_some() || heading || line.trim().is_empty() {
+      blocks.extend(paragraph.take().map(|range| Block {
+        range,
+        heading: false,
+        fence: false,
+      }));
+    }
+    if let Some(marker) = fence_marker {
+      fence = Some((start..end, marker));
+    } else if heading {
+      blocks.push(Block {
+        range: start..end,
+        heading: true,
+        fence: false,
+      });
+    } else if !line.trim().is_empty() {
+      paragraph = Some(paragraph.map_or(start..end, |range| range.start..end));
+    }
+  }
+  let unclosed = fence.map(|(range, _)| Block {
+    range,
+    heading: false,
+    fence: true,
+  });
+  let last = paragraph.map(|range| Block {
+    range,
+    heading: false,
+    fence: false,
+  });
+  blocks.extend(unclosed.into_iter().chain(last));
+  blocks
+}
+
+/// Whether `line` is an ATX heading, such as `## Install`.
+fn is_heading(line: &str) -> bool {
+  let level = line.chars().take_while(|c| *c == '#').count();
+  (1..=6).contains(&level)
+    && line[level..]
+      .chars()
+      .next()
+      .map_or(true, char::is_whitespace)
+}
+
+/// The sentences of `text[range]`, see [sentences].
+fn sentences_in(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+  sentences(&text[range.clone()])
+    .into_iter()
+    .map(|sentence| sentence.start + range.start..sentence.end + range.start)
+    .collect()
+}
+
+/// `range` without its leading and trailing whitespace, `None` when nothing is left.
+fn trimmed(text: &str, range: Range<usize>) -> Option<Range<usize>> {
+  let slice = &text[range.clone()];
+  let start = range.start + (slice.len() - slice.trim_start().len());
+  let end = range.end - (slice.len() - slice.trim_end().len());
+  (start < end).then_some(start..end)
+}
+
+/// A completion of one [TextChunk].
+pub(crate) struct ChunkCompletion {
+  pub separator: String,
+  pub message: String,
+  pub complete_type: u8,
+  pub metadata: Option<Value>,
+}
+
+/// Runs the completions of `chunks` one after the other, in a single stream. Each chunk starts
+/// with a `{"chunk": {"index": 0, "count": 3}}` metadata frame, followed by its separator as an
+/// answer frame, if any. The stream ends at the first error.
+pub(crate) fn chunked_completion(
+  operation: AIPluginOperation,
+  chunks: Vec<ChunkCompletion>,
+  format: Option<Value>,
+  options: StreamOptions,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(options.capacity);
+  tokio::spawn(async move {
+    let count = chunks.len();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+      let marker = json!({ STREAM_METADATA_KEY: { "chunk": { "index": index, "count": count } } });
+      if tx.send(Ok(marker)).await.is_err() {
+        return;
+      }
+      if !chunk.separator.is_empty()
+        && tx
+          .send(Ok(json!({ STREAM_ANSWER_KEY: chunk.separator })))
+          .await
+          .is_err()
+      {
+        return;
+      }
+      let stream = operation
+        .complete_text_v2(
+          &chunk.message,
+          chunk.complete_type,
+          format.clone(),
+          chunk.metadata,
+          options.clone(),
+        )
+        .await;
+      let mut stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+          let _ = tx.send(Err(err)).await;
+          return;
+        },
+      };
+      while let Some(frame) = stream.next().await {
+        let failed = frame.is_err();
+        if tx.send(frame).await.is_err() || failed {
+          return;
+        }
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// `metadata` with the text preceding a chunk added, when it is an object or missing.
+pub(crate) fn with_preceding_text(metadata: Option<Value>, preceding_text: &str) -> Option<Value> {
+  match metadata {
+    None => Some(json!({ PRECEDING_TEXT_KEY: preceding_text })),
+    Some(Value::Object(mut object)) => {
+      object.insert(PRECEDING_TEXT_KEY.to_string(), json!(preceding_text));
+      Some(Value::Object(object))
+    },
+    other => other,
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Completes `message`, which is longer than `limit` tokens, in chunks that fit, see
+  /// [chunk_text] and [chunked_completion].
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) async fn complete_text_in_chunks(
+    &self,
+    trace_id: String,
+    original: &str,
+    message: &str,
+    limit: u32,
+    complete_type: u8,
+    format: Option<Value>,
+    metadata: Option<Value>,
+    options: StreamOptions,
+  ) -> Result<TracedStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let max_chars = (limit as f64 * CHARS_PER_TOKEN) as usize;
+    let chunks = chunk_text(message, max_chars.saturating_sub(CHUNK_OVERLAP_CHARS));
+    let model_name = self.completion_model(complete_type);
+    trace!(
+      "[AI Plugin] complete text v2 in {} chunks of at most {} tokens",
+      chunks.len(),
+      limit
+    );
+    let completions = chunks
+      .into_iter()
+      .map(|chunk| {
+        let metadata = match &chunk.preceding_text {
+          Some(preceding_text) => with_preceding_text(metadata.clone(), preceding_text),
+          None => metadata.clone(),
+        };
+        let (message, complete_type) =
+          match self.host_prompt(complete_type, &chunk.text, metadata.as_ref()) {
+            Some(prompt) => (prompt, CompleteTextType::Custom as u8),
+            None => (chunk.text, complete_type),
+          };
+        ChunkCompletion {
+          separator: chunk.separator,
+          message,
+          complete_type,
+          metadata: metadata.filter(|_| self.has_capability(Capability::CompletionMetadata)),
+        }
+      })
+      .collect::<Vec<_>>();
+    let format = format.filter(|_| self.has_capability(Capability::ResponseFormat));
+    let permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_trace_id(&trace_id)
+      .with_model_name(model_name);
+    let compute_diff = options.compute_diff;
+    let post_process = options.post_process.clone();
+    let usage = self
+      .usage
+      .start(UsageKind::Completion, None, message.chars().count());
+    let stream = chunked_completion(operation, completions, format, options);
+    let mut stream = post_processed(tracked_stream(usage, Ok(stream))?, &post_process);
+    if compute_diff {
+      stream = with_diff(stream, original.to_string());
+    }
+    Ok(TracedStream {
+      trace_id,
+      stream: permit.hold_until_done(stream),
+    })
+  }
+
+  /// Splits `text` into chunks with `strategy` and embeds each with `metadata`, plus its
+  /// [CHUNK_INDEX_KEY] and [BYTE_RANGE_KEY] so a retrieved chunk can be traced back to its place
+  /// in the document. A chunk failing to embed doesn't stop the others, the report tells which
+  /// ones are missing, like [Self::embed_file_partial]. Only fails when no chunk was embedded.
+  pub async fn embed_document(
+    &self,
+    text: &str,
+    metadata: HashMap<String, Value>,
+    strategy: ChunkStrategy,
+  ) -> Result<DocumentEmbedReport, PluginError> {
+    let chunks = TextChunker::new(strategy).chunk(text);
+    let mut report = EmbedReport::default();
+    let mut last_error = None;
+    for chunk in &chunks {
+      let mut metadata = metadata.clone();
+      metadata.insert(CHUNK_INDEX_KEY.to_string(), json!(chunk.index));
+      metadata.insert(
+        BYTE_RANGE_KEY.to_string(),
+        json!([chunk.byte_range.start, chunk.byte_range.end]),
+      );
+      match self
+        .embed_text_with_priority(&chunk.text, metadata, Priority::Background)
+        .await
+      {
+        Ok(()) => report.chunks_indexed += 1,
+        Err(err) => {
+          warn!(
+            "[AI Plugin] failed to embed chunk {} of a document: {}",
+            chunk.index, err
+          );
+          report.chunks_failed += 1;
+          report.failures.push(ChunkFailure {
+            index: chunk.index,
+            reason: err.to_string(),
+          });
+          last_error = Some(err);
+        },
+      }
+    }
+    match last_error {
+      Some(err) if report.chunks_indexed == 0 => Err(err),
+      _ => Ok(DocumentEmbedReport { chunks, report }),
+    }
+  }
+}
@@ -0,0 +1,124 @@
+use crate::ai_ops::{STREAM_ANSWER_KEY, STREAM_METADATA_KEY};
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Metadata key identifying the file a chunk was embedded from.
+pub const SOURCE_ID_KEY: &str = "source_id";
+/// Metadata key holding the name of the file a chunk was embedded from.
+pub const FILE_NAME_KEY: &str = "file_name";
+
+/// A chunk of an embedded file that an answer draws on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+  pub source_id: String,
+  pub file_name: String,
+  pub chunk_text: String,
+  pub score: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourcedFrame {
+  /// A piece of the answer text.
+  Answer(String),
+  /// Citations reported by a metadata frame of the answer.
+  Citations(Vec<Citation>),
+  /// The last frame of the stream, with every citation of the answer.
+  Done { citations: Vec<Citation> },
+}
+
+/// Parses the sources of a metadata frame into citations. Sources without a `source_id`, i.e.
+/// chunks that were not embedded by `embed_file`, are skipped.
+pub fn parse_citations(metadata: &Value) -> Vec<Citation> {
+  let sources = match metadata {
+    Value::Array(sources) => sources.as_slice(),
+    Value::Object(map) => match map.get("sources") {
+      Some(Value::Array(sources)) => sources.as_slice(),
+      _ => std::slice::from_ref(metadata),
+    },
+    _ => &[],
+  };
+  sources.iter().filter_map(parse_citation).collect()
+}
+
+fn parse_citation(source: &Value) -> Option<Citation> {
+  let source = source.as_object()?;
+  let get = |key: &str| {
+    source
+      .get("metadata")
+      .and_then(|metadata| metadata.get(key))
+      .or_else(|| source.get(key))
+  };
+
+  let source_id = get(SOURCE_ID_KEY)?.as_str()?.to_string();
+  let file_name = get(FILE_NAME_KEY)
+    .and_then(|v| v.as_str())
+    .unwrap_or_default()
+    .to_string();
+  let chunk_text = ["chunk_text", "page_content", "content", "text"]
+    .iter()
+    .find_map(|key| source.get(*key).and_then(|v| v.as_str()))
+    .unwrap_or_default()
+    .to_string();
+  let score = ["score", "relevance_score"]
+    .iter()
+    .find_map(|key| get(key).and_then(|v| v.as_f64()))
+    .unwrap_or_default();
+
+  Some(Citation {
+    source_id,
+    file_name,
+    chunk_text,
+    score,
+  })
+}
+
+/// Turns a v2 answer stream into a stream of [SourcedFrame]s that ends with
+/// [SourcedFrame::Done].
+pub(crate) fn sourced_stream(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+) -> ReceiverStream<Result<SourcedFrame, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(100);
+  tokio::spawn(async move {
+    let mut all_citations: Vec<Citation> = vec![];
+    while let Some(frame) = stream.next().await {
+      let frames = match frame {
+        Ok(Value::Object(mut map)) => {
+          let mut frames = vec![];
+          if let Some(metadata) = map.remove(STREAM_METADATA_KEY) {
+            let citations = parse_citations(&metadata);
+            if !citations.is_empty() {
+              for citation in citations.iter() {
+                if !all_citations.contains(citation) {
+                  all_citations.push(citation.clone());
+                }
+              }
+              frames.push(Ok(SourcedFrame::Citations(citations)));
+            }
+          }
+          if let Some(Value::String(text)) = map.remove(STREAM_ANSWER_KEY) {
+            frames.push(Ok(SourcedFrame::Answer(text)));
+          }
+          frames
+        },
+        Ok(_) => vec![],
+        Err(err) => vec![Err(err)],
+      };
+
+      for frame in frames {
+        if tx.send(frame).await.is_err() {
+          return;
+        }
+      }
+    }
+
+    let _ = tx
+      .send(Ok(SourcedFrame::Done {
+        citations: all_citations,
+      }))
+      .await;
+  });
+  ReceiverStream::new(rx)
+}
@@ -0,0 +1,105 @@
+//! The API of the `appflowy-local-ai` crate, so applications can move to this crate one call at
+//! a time: import these names from `af_local_ai::compat` instead of `appflowy_local_ai`, then
+//! replace each deprecated call with the one its warning points at.
+
+use crate::ai_ops;
+use crate::ollama_plugin::OllamaAIPlugin;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+pub use crate::ollama_plugin::OllamaPluginConfig;
+pub use af_plugin::core::plugin::RunningState;
+pub use af_plugin::error::PluginError;
+
+/// Completions of `appflowy-local-ai`, sent with the same values.
+///
+/// Converted to and from [ai_ops::CompleteTextType], which also has prompts of its own to
+/// continue writing and to explain a text. Those have no match here:
+/// [ContinueWriting](ai_ops::CompleteTextType::ContinueWriting) becomes
+/// [CompleteTextType::MakeLonger], and [Explain](ai_ops::CompleteTextType::Explain) and
+/// [Custom](ai_ops::CompleteTextType::Custom) become [CompleteTextType::AskAI].
+#[derive(Clone, Debug, Copy, Eq, PartialEq)]
+pub enum CompleteTextType {
+  ImproveWriting = 1,
+  SpellingAndGrammar = 2,
+  MakeShorter = 3,
+  MakeLonger = 4,
+  AskAI = 7,
+}
+
+impl From<CompleteTextType> for ai_ops::CompleteTextType {
+  fn from(value: CompleteTextType) -> Self {
+    match value {
+      CompleteTextType::ImproveWriting => ai_ops::CompleteTextType::ImproveWriting,
+      CompleteTextType::SpellingAndGrammar => ai_ops::CompleteTextType::SpellingAndGrammar,
+      CompleteTextType::MakeShorter => ai_ops::CompleteTextType::MakeShorter,
+      CompleteTextType::MakeLonger => ai_ops::CompleteTextType::MakeLonger,
+      CompleteTextType::AskAI => ai_ops::CompleteTextType::AskAI,
+    }
+  }
+}
+
+impl From<ai_ops::CompleteTextType> for CompleteTextType {
+  fn from(value: ai_ops::CompleteTextType) -> Self {
+    match value {
+      ai_ops::CompleteTextType::ImproveWriting => CompleteTextType::ImproveWriting,
+      ai_ops::CompleteTextType::SpellingAndGrammar => CompleteTextType::SpellingAndGrammar,
+      ai_ops::CompleteTextType::MakeShorter => CompleteTextType::MakeShorter,
+      ai_ops::CompleteTextType::MakeLonger | ai_ops::CompleteTextType::ContinueWriting => {
+        CompleteTextType::MakeLonger
+      },
+      ai_ops::CompleteTextType::AskAI
+      | ai_ops::CompleteTextType::Explain
+      | ai_ops::CompleteTextType::Custom => CompleteTextType::AskAI,
+    }
+  }
+}
+
+/// The methods of the `appflowy-local-ai` plugin that were renamed or changed signature.
+#[async_trait]
+pub trait LocalAICompat {
+  /// Embeds the file at `file_path` into `chat_id`, or `file_content` when there is no path.
+  #[deprecated(note = "use OllamaAIPlugin::embed_file, or OllamaAIPlugin::embed_text for content")]
+  async fn index_file(
+    &self,
+    chat_id: &str,
+    file_path: Option<PathBuf>,
+    file_content: Option<String>,
+    metadata: Option<HashMap<String, Value>>,
+  ) -> Result<(), PluginError>;
+
+  #[deprecated(note = "use OllamaAIPlugin::destroy_plugin")]
+  async fn destroy_chat_plugin(&self) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl LocalAICompat for OllamaAIPlugin {
+  async fn index_file(
+    &self,
+    chat_id: &str,
+    file_path: Option<PathBuf>,
+    file_content: Option<String>,
+    metadata: Option<HashMap<String, Value>>,
+  ) -> Result<(), PluginError> {
+    match (file_path, file_content) {
+      (Some(file_path), _) => self.embed_file(chat_id, file_path, metadata).await,
+      (None, Some(content)) => {
+        let mut metadata = metadata.unwrap_or_default();
+        metadata
+          .entry("chat_id".to_string())
+          .or_insert_with(|| Value::from(chat_id));
+        self.embed_text(&content, metadata).await
+      },
+      (None, None) => Err(PluginError::Internal(anyhow!(
+        "file_path or file_content is required"
+      ))),
+    }
+  }
+
+  async fn destroy_chat_plugin(&self) -> anyhow::Result<()> {
+    self.destroy_plugin().await.map(|_| ())
+  }
+}
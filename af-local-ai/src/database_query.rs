@@ -0,0 +1,186 @@
+use crate::ai_ops::{CompleteTextType, STREAM_ANSWER_KEY};
+use crate::model_routing::RequestKind as ModelRequestKind;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::outbound_filter::RequestKind;
+use crate::summary::collect_answer;
+use crate::usage::{finish_usage, UsageKind};
+use af_ai_protocol::method::DATABASE_SUMMARY;
+pub use af_ai_protocol::types::{ColumnDef, DatabaseQueryAnswer, FieldType};
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::Write;
+use tracing::trace;
+
+/// Rows sent in one `database_query` request, see
+/// [OllamaPluginConfig::with_database_query_chunk_rows](crate::ollama_plugin::OllamaPluginConfig::with_database_query_chunk_rows).
+pub const DEFAULT_DATABASE_QUERY_CHUNK_ROWS: usize = 50;
+
+/// The answers of the chunks of a database too large to be queried at once.
+#[derive(Debug, Default)]
+pub(crate) struct ChunkedAnswer {
+  answers: Vec<String>,
+  matching_row_indices: Option<Vec<usize>>,
+}
+
+impl ChunkedAnswer {
+  /// Adds the answer of the `len` rows starting at row `offset`. Its indices are relative to
+  /// the chunk; the ones out of it are dropped.
+  pub(crate) fn add(&mut self, offset: usize, len: usize, answer: DatabaseQueryAnswer) {
+    self.answers.push(answer.answer);
+    if let Some(indices) = answer.matching_row_indices {
+      self
+        .matching_row_indices
+        .get_or_insert_with(Vec::new)
+        .extend(
+          indices
+            .into_iter()
+            .filter(|index| *index < len)
+            .map(|index| index + offset),
+        );
+    }
+  }
+
+  /// The matching rows of every chunk in the caller's order, or `None` if no chunk selected
+  /// rows.
+  pub(crate) fn matching_row_indices(&self) -> Option<Vec<usize>> {
+    self.matching_row_indices.clone().map(|mut indices| {
+      indices.sort_unstable();
+      indices.dedup();
+      indices
+    })
+  }
+
+  /// The answer of a database queried in a single chunk.
+  pub(crate) fn into_single_answer(self) -> DatabaseQueryAnswer {
+    DatabaseQueryAnswer {
+      matching_row_indices: self.matching_row_indices(),
+      answer: self.answers.into_iter().next().unwrap_or_default(),
+    }
+  }
+
+  /// Builds the prompt merging the answers of the chunks into one answer to `question`.
+  pub(crate) fn synthesis_prompt(&self, question: &str) -> String {
+    let mut prompt = String::new();
+    let _ = writeln!(
+      prompt,
+      "The rows of a database were split into parts and the question below was answered for \
+      each part. Combine the partial answers into a single answer to the question. Only use \
+      the information of the partial answers."
+    );
+    let _ = writeln!(prompt);
+    let _ = writeln!(prompt, "Question: {}", question.trim());
+    let _ = writeln!(prompt);
+    for (index, answer) in self.answers.iter().enumerate() {
+      let _ = writeln!(prompt, "Part {}: {}", index + 1, answer.trim());
+    }
+    prompt
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Answers `question` over the rows of a database, such as "which books did I rate above 7?".
+  ///
+  /// Databases with more rows than [OllamaPluginConfig::database_query_chunk_rows](crate::ollama_plugin::OllamaPluginConfig::database_query_chunk_rows) are queried
+  /// in chunks, whose answers are then merged into one by `complete_text_v2`. The matching row
+  /// indices are those of `rows`.
+  pub async fn query_database(
+    &self,
+    rows: Vec<HashMap<String, String>>,
+    schema: Vec<ColumnDef>,
+    question: &str,
+  ) -> Result<DatabaseQueryAnswer, PluginError> {
+    let rows = rows
+      .into_iter()
+      .map(|row| {
+        row
+          .into_iter()
+          .map(|(field, content)| {
+            let content = self.filter_outbound(&content, RequestKind::DatabaseRow)?;
+            Ok((field, content.into_owned()))
+          })
+          .collect::<Result<HashMap<_, _>, PluginError>>()
+      })
+      .collect::<Result<Vec<_>, PluginError>>()?;
+    let question = self.filter_outbound(question, RequestKind::DatabaseRow)?;
+    trace!(
+      "[AI Plugin] query database of {} rows: {}",
+      rows.len(),
+      question
+    );
+    self.wait_until_plugin_ready().await?;
+    let chunk_rows = self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .map(|config| config.database_query_chunk_rows)
+      .unwrap_or(DEFAULT_DATABASE_QUERY_CHUNK_ROWS)
+      .max(1);
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.operation(plugin);
+    // A database without rows is still queried once, for questions about its schema.
+    let chunk_count = rows.len().div_ceil(chunk_rows).max(1);
+    let mut chunked = ChunkedAnswer::default();
+    for index in 0..chunk_count {
+      let offset = index * chunk_rows;
+      let chunk = &rows[offset..rows.len().min(offset + chunk_rows)];
+      let answer = operation.query_database(chunk, &schema, &question).await?;
+      chunked.add(offset, chunk.len(), answer);
+    }
+    if chunk_count == 1 {
+      return Ok(chunked.into_single_answer());
+    }
+
+    let stream = operation
+      .complete_text_v2(
+        &chunked.synthesis_prompt(&question),
+        CompleteTextType::AskAI as u8,
+        None,
+        None,
+        StreamOptions::default(),
+      )
+      .await?;
+    Ok(DatabaseQueryAnswer {
+      answer: collect_answer(stream, STREAM_ANSWER_KEY).await?,
+      matching_row_indices: chunked.matching_row_indices(),
+    })
+  }
+
+  pub async fn summary_database_row(
+    &self,
+    row: HashMap<String, String>,
+  ) -> Result<String, PluginError> {
+    let row = row
+      .into_iter()
+      .map(|(field, content)| {
+        let content = self.filter_outbound(&content, RequestKind::DatabaseRow)?;
+        Ok((field, content.into_owned()))
+      })
+      .collect::<Result<HashMap<_, _>, PluginError>>()?;
+    trace!("[AI Plugin] summary database row: {:?}", row);
+    let params = json!(row);
+    self
+      .cached(
+        DATABASE_SUMMARY,
+        ModelRequestKind::DatabaseSummary,
+        &params,
+        || async {
+          self.wait_until_plugin_ready().await?;
+          let plugin = self.get_ai_plugin().await?;
+          let operation = self
+            .operation(plugin)
+            .with_model_name(self.routed_model(ModelRequestKind::DatabaseSummary));
+          let chars_in = row.values().map(|content| content.chars().count()).sum();
+          let usage = self.usage.start(UsageKind::RowSummary, None, chars_in);
+          let text = operation.summary_row(row).await;
+          finish_usage(usage, &text, operation.retries(), |text| {
+            text.chars().count()
+          });
+          text
+        },
+      )
+      .await
+  }
+}
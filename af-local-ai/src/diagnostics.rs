@@ -0,0 +1,117 @@
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::rate_limit::RateLimitState;
+use crate::retry::RetryStats;
+use crate::slow_request::SlowRequest;
+use af_plugin::core::plugin::RunningState;
+use af_plugin::core::process_limits::ProcessLimits;
+use af_plugin::core::resource_usage::ResourceUsage;
+use af_plugin::core::state_machine::StateTransition;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::wrappers::WatchStream;
+use tracing::trace;
+
+/// A snapshot of the plugin's state, attached to bug reports.
+#[derive(Debug, Clone)]
+pub struct PluginDiagnostics {
+  pub running_state: RunningState,
+  /// The latest running state transitions, oldest first.
+  pub running_state_history: Vec<StateTransition>,
+  /// Version reported by the plugin, once it has been asked for.
+  pub plugin_version: Option<String>,
+  /// Latest sample of the resource monitor, see
+  /// [OllamaAIPlugin::enable_resource_monitor](crate::ollama_plugin::OllamaAIPlugin::enable_resource_monitor).
+  pub resource_usage: Option<ResourceUsage>,
+  /// The latest requests slower than their threshold, oldest first, see
+  /// [OllamaAIPlugin::set_slow_request_threshold](crate::ollama_plugin::OllamaAIPlugin::set_slow_request_threshold).
+  pub slow_requests: Vec<SlowRequest>,
+  /// The bucket of the rate limit, `None` without one, see
+  /// [OllamaAIPlugin::set_rate_limit](crate::ollama_plugin::OllamaAIPlugin::set_rate_limit).
+  pub rate_limit: Option<RateLimitState>,
+  /// Requests sent again after a transient error, see
+  /// [OllamaAIPlugin::set_retry_policy](crate::ollama_plugin::OllamaAIPlugin::set_retry_policy).
+  pub retries: RetryStats,
+  /// The limits applied to the plugin process, `None` when it isn't running, see
+  /// [OllamaPluginConfig::with_process_limits](crate::ollama_plugin::OllamaPluginConfig::with_process_limits).
+  pub process_limits: Option<ProcessLimits>,
+}
+
+impl OllamaAIPlugin {
+  /// Samples the memory and CPU used by the plugin process every `interval`. Samples are published
+  /// to [OllamaAIPlugin::subscribe_resource_usage]; `None` is published while the plugin is not
+  /// running.
+  pub fn enable_resource_monitor(&self, interval: Duration) {
+    let plugin_manager = self.plugin_manager.clone();
+    let running_state = self.running_state.subscribe();
+    let resource_usage = Arc::downgrade(&self.resource_usage);
+    let handle = tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+      loop {
+        ticker.tick().await;
+        let resource_usage = match resource_usage.upgrade() {
+          Some(resource_usage) => resource_usage,
+          None => break,
+        };
+
+        let plugin_id = match &*running_state.borrow() {
+          RunningState::Running { plugin_id } => Some(*plugin_id),
+          _ => None,
+        };
+        let mut sample = None;
+        if let Some(plugin_id) = plugin_id {
+          if let Some(plugin) = plugin_manager
+            .get_plugin(plugin_id)
+            .await
+            .ok()
+            .and_then(|plugin| plugin.upgrade())
+          {
+            match plugin.resource_usage() {
+              Ok(usage) => sample = Some(usage),
+              Err(err) => trace!("[AI Plugin] failed to sample resource usage: {:?}", err),
+            }
+          }
+        }
+        resource_usage.send_replace(sample);
+      }
+    });
+
+    if let Some(previous) = self.resource_monitor.lock().replace(handle) {
+      previous.abort();
+    }
+  }
+
+  pub fn disable_resource_monitor(&self) {
+    if let Some(handle) = self.resource_monitor.lock().take() {
+      handle.abort();
+    }
+    self.resource_usage.send_replace(None);
+  }
+
+  pub fn subscribe_resource_usage(&self) -> WatchStream<Option<ResourceUsage>> {
+    WatchStream::new(self.resource_usage.subscribe())
+  }
+
+  pub async fn diagnostics(&self) -> PluginDiagnostics {
+    PluginDiagnostics {
+      running_state: self.get_plugin_running_state(),
+      running_state_history: self.running_state.history(),
+      plugin_version: self
+        .plugin_info
+        .read()
+        .await
+        .as_ref()
+        .map(|info| info.version.clone()),
+      resource_usage: *self.resource_usage.borrow(),
+      slow_requests: self.slow_requests.recent(),
+      rate_limit: self.rate_limiter.state(),
+      retries: self.retrier.stats(),
+      process_limits: self
+        .get_ai_plugin()
+        .await
+        .ok()
+        .and_then(|plugin| plugin.upgrade())
+        .map(|plugin| plugin.process_limits().clone()),
+    }
+  }
+}
@@ -0,0 +1,304 @@
+use crate::ai_ops::STREAM_ANSWER_KEY;
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::ops::Range;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Key of the frame sent after the answer when
+/// [StreamOptions::compute_diff](af_plugin::core::stream::StreamOptions::compute_diff) is set,
+/// holding the [DiffSpan]s of the answer.
+pub const STREAM_DIFF_KEY: &str = "diff";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+  Equal,
+  Insert,
+  Delete,
+}
+
+/// A run of words the answer kept, inserted or deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffSpan {
+  pub op: DiffOp,
+  /// The words, from the original text, or from the answer for [DiffOp::Insert].
+  pub text: String,
+  /// Byte range of the words in the original text. Insertions have an empty range at the
+  /// position they are inserted at.
+  pub range: Range<usize>,
+}
+
+/// Compares `original` and `revised` word by word. Whitespace, punctuation, and each CJK
+/// character or emoji count as words of their own.
+pub fn word_diff(original: &str, revised: &str) -> Vec<DiffSpan> {
+  let old = tokenize(original);
+  let new = tokenize(revised);
+  let old_words = old
+    .iter()
+    .map(|range| &original[range.clone()])
+    .collect::<Vec<_>>();
+  let new_words = new
+    .iter()
+    .map(|range| &revised[range.clone()])
+    .collect::<Vec<_>>();
+
+  let mut spans: Vec<DiffSpan> = vec![];
+  let mut position = 0;
+  for edit in cleanup(myers(&old_words, &new_words), &old_words, &new_words) {
+    let (op, text, range) = match edit {
+      Edit::Equal(index, _) => (DiffOp::Equal, old_words[index], old[index].clone()),
+      Edit::Delete(index) => (DiffOp::Delete, old_words[index], old[index].clone()),
+      Edit::Insert(index) => (DiffOp::Insert, new_words[index], position..position),
+    };
+    position = range.end;
+    match spans.last_mut() {
+      Some(last) if last.op == op => {
+        last.text.push_str(text);
+        last.range.end = range.end;
+      },
+      _ => spans.push(DiffSpan {
+        op,
+        text: text.to_string(),
+        range,
+      }),
+    }
+  }
+  spans
+}
+
+/// Forwards a completion stream, followed by a `{"diff": [...]}` frame comparing the answer to
+/// `original` once it is complete. Streams that fail don't get the frame.
+pub(crate) fn with_diff(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  original: String,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut answer = String::new();
+    while let Some(frame) = stream.next().await {
+      let failed = frame.is_err();
+      if let Some(text) = frame
+        .as_ref()
+        .ok()
+        .and_then(|frame| frame.get(STREAM_ANSWER_KEY))
+        .and_then(|text| text.as_str())
+      {
+        answer.push_str(text);
+      }
+      if tx.send(frame).await.is_err() || failed {
+        return;
+      }
+    }
+    let diff = word_diff(&original, &answer);
+    let _ = tx.send(Ok(json!({ STREAM_DIFF_KEY: diff }))).await;
+  });
+  ReceiverStream::new(rx)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+  Space,
+  Word,
+  /// CJK characters, emoji and punctuation, one token each.
+  Single,
+}
+
+/// Byte ranges of the words of `text`. Ranges always fall on char boundaries, and marks that
+/// modify the previous character, such as emoji variation selectors, stay with it.
+fn tokenize(text: &str) -> Vec<Range<usize>> {
+  let mut tokens: Vec<Range<usize>> = vec![];
+  let mut last_kind = None;
+  let mut joined = false;
+  for (index, c) in text.char_indices() {
+    let end = index + c.len_utf8();
+    if let Some(last) = tokens.last_mut() {
+      if joined || is_extender(c) {
+        last.end = end;
+        joined = c == '\u{200D}';
+        continue;
+      }
+    }
+    let kind = if c.is_whitespace() {
+      TokenKind::Space
+    } else if c.is_alphanumeric() && !is_cjk(c) {
+      TokenKind::Word
+    } else {
+      TokenKind::Single
+    };
+    match tokens.last_mut() {
+      Some(last) if last_kind == Some(kind) && kind != TokenKind::Single => last.end = end,
+      _ => tokens.push(index..end),
+    }
+    last_kind = Some(kind);
+  }
+  tokens
+}
+
+/// Characters that belong to the one before: combining marks, variation selectors, emoji skin
+/// tones and the zero width joiner, which also pulls in the character after it.
+fn is_extender(c: char) -> bool {
+  matches!(c,
+    '\u{0300}'..='\u{036F}'
+    | '\u{200D}'
+    | '\u{20D0}'..='\u{20FF}'
+    | '\u{FE00}'..='\u{FE0F}'
+    | '\u{1F3FB}'..='\u{1F3FF}'
+    | '\u{E0020}'..='\u{E007F}')
+}
+
+fn is_cjk(c: char) -> bool {
+  matches!(c,
+    '\u{3040}'..='\u{30FF}'
+    | '\u{3400}'..='\u{4DBF}'
+    | '\u{4E00}'..='\u{9FFF}'
+    | '\u{AC00}'..='\u{D7AF}'
+    | '\u{F900}'..='\u{FAFF}'
+    | '\u{20000}'..='\u{2FA1F}')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+  /// Index of the word in the original and in the revised text.
+  Equal(usize, usize),
+  Delete(usize),
+  /// Index of the word in the revised text.
+  Insert(usize),
+}
+
+/// The shortest edit script from `old` to `new`, following Myers' O(ND) algorithm.
+fn myers(old: &[&str], new: &[&str]) -> Vec<Edit> {
+  let n = old.len() as isize;
+  let m = new.len() as isize;
+  let max = n + m;
+  let offset = max + 1;
+  let mut v = vec![0isize; 2 * max as usize + 3];
+  // `trace[d]` holds the furthest reaching x of each diagonal before step `d`.
+  let mut trace = vec![];
+  'search: for d in 0..=max {
+    trace.push(v.clone());
+    for k in (-d..=d).step_by(2) {
+      let index = (k + offset) as usize;
+      let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+        v[index + 1]
+      } else {
+        v[index - 1] + 1
+      };
+      let mut y = x - k;
+      while x < n && y < m && old[x as usize] == new[y as usize] {
+        x += 1;
+        y += 1;
+      }
+      v[index] = x;
+      if x >= n && y >= m {
+        break 'search;
+      }
+    }
+  }
+
+  let mut edits = vec![];
+  let (mut x, mut y) = (n, m);
+  for (d, v) in trace.iter().enumerate().rev() {
+    let d = d as isize;
+    let k = x - y;
+    let index = (k + offset) as usize;
+    let prev_k = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+      k + 1
+    } else {
+      k - 1
+    };
+    let prev_x = v[(prev_k + offset) as usize];
+    let prev_y = prev_x - prev_k;
+    while x > prev_x && y > prev_y {
+      x -= 1;
+      y -= 1;
+      edits.push(Edit::Equal(x as usize, y as usize));
+    }
+    if d > 0 {
+      if x == prev_x {
+        edits.push(Edit::Insert(prev_y as usize));
+      } else {
+        edits.push(Edit::Delete(prev_x as usize));
+      }
+    }
+    x = prev_x;
+    y = prev_y;
+  }
+  edits.reverse();
+  edits
+}
+
+/// Makes the edits read like a person's: whitespace kept between two changes becomes part of
+/// them, so "everyday" replaced by "every day" is one change rather than two around a kept space,
+/// and each change deletes before it inserts.
+fn cleanup(edits: Vec<Edit>, old: &[&str], new: &[&str]) -> Vec<Edit> {
+  let is_change = |edit: Option<&Edit>| matches!(edit, Some(Edit::Delete(_) | Edit::Insert(_)));
+  let mut absorbed = vec![];
+  for (index, edit) in edits.iter().enumerate() {
+    match *edit {
+      Edit::Equal(x, y)
+        if old[x].trim().is_empty()
+          && is_change(absorbed.last())
+          && is_change(edits.get(index + 1)) =>
+      {
+        absorbed.push(Edit::Delete(x));
+        absorbed.push(Edit::Insert(y));
+      },
+      edit => absorbed.push(edit),
+    }
+  }
+
+  let mut cleaned = vec![];
+  let mut deleted = vec![];
+  let mut inserted = vec![];
+  for edit in absorbed
+    .into_iter()
+    .chain([Edit::Equal(old.len(), new.len())])
+  {
+    match edit {
+      Edit::Delete(x) => deleted.push(x),
+      Edit::Insert(y) => inserted.push(y),
+      Edit::Equal(x, y) => {
+        // The change may start or end with the words it was merged with.
+        let mut prefix = 0;
+        while prefix < deleted.len().min(inserted.len())
+          && old[deleted[prefix]] == new[inserted[prefix]]
+        {
+          prefix += 1;
+        }
+        let mut suffix = 0;
+        while suffix < (deleted.len() - prefix).min(inserted.len() - prefix)
+          && old[deleted[deleted.len() - 1 - suffix]] == new[inserted[inserted.len() - 1 - suffix]]
+        {
+          suffix += 1;
+        }
+        let (kept_before, kept_after) = (prefix, deleted.len() - suffix);
+        let (inserted_before, inserted_after) = (prefix, inserted.len() - suffix);
+        cleaned.extend((0..kept_before).map(|i| Edit::Equal(deleted[i], inserted[i])));
+        cleaned.extend(
+          deleted[kept_before..kept_after]
+            .iter()
+            .map(|x| Edit::Delete(*x)),
+        );
+        cleaned.extend(
+          inserted[inserted_before..inserted_after]
+            .iter()
+            .map(|y| Edit::Insert(*y)),
+        );
+        cleaned.extend((0..suffix).rev().map(|i| {
+          Edit::Equal(
+            deleted[deleted.len() - 1 - i],
+            inserted[inserted.len() - 1 - i],
+          )
+        }));
+        deleted.clear();
+        inserted.clear();
+        if x < old.len() {
+          cleaned.push(Edit::Equal(x, y));
+        }
+      },
+    }
+  }
+  cleaned
+}
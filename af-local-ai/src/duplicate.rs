@@ -0,0 +1,100 @@
+use crate::embedding_ops::{SearchOptions, SearchResult};
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Results searched by
+/// [OllamaAIPlugin::find_near_duplicates](crate::ollama_plugin::OllamaAIPlugin::find_near_duplicates),
+/// enough for a page split into many chunks to be found along with other pages.
+pub const DUPLICATE_SEARCH_TOP_K: usize = 50;
+
+/// Characters of the matching chunk kept in [DuplicateCandidate::snippet].
+pub const DUPLICATE_SNIPPET_CHARS: usize = 200;
+
+/// An embedded page nearly identical to the searched text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateCandidate {
+  /// Metadata the page was embedded with, e.g. its `object_id`.
+  pub metadata: HashMap<String, Value>,
+  /// Similarity of the best matching chunk of the page, from the threshold to 1.0.
+  pub score: f64,
+  /// The start of the best matching chunk.
+  pub snippet: String,
+}
+
+pub(crate) fn check_threshold(threshold: f64) -> Result<(), PluginError> {
+  if (0.0..=1.0).contains(&threshold) {
+    Ok(())
+  } else {
+    Err(PluginError::InvalidThreshold(threshold))
+  }
+}
+
+/// The results scoring at least `threshold`, best first. Chunks of the same `object_id` make a
+/// single candidate with the best score; results without an `object_id` each make one. Results
+/// without a score can't be judged and are left out.
+pub(crate) fn near_duplicates(
+  results: Vec<SearchResult>,
+  threshold: f64,
+) -> Vec<DuplicateCandidate> {
+  let mut candidates: Vec<DuplicateCandidate> = vec![];
+  let mut by_object: HashMap<String, usize> = HashMap::new();
+  for result in results {
+    let score = match result.score {
+      Some(score) if score >= threshold => score,
+      _ => continue,
+    };
+    let candidate = DuplicateCandidate {
+      snippet: result
+        .content
+        .chars()
+        .take(DUPLICATE_SNIPPET_CHARS)
+        .collect(),
+      metadata: result.metadata,
+      score,
+    };
+    let object_id = candidate
+      .metadata
+      .get("object_id")
+      .and_then(|id| id.as_str())
+      .map(String::from);
+    match object_id {
+      Some(object_id) => match by_object.get(&object_id) {
+        Some(&index) if candidates[index].score >= score => {},
+        Some(&index) => candidates[index] = candidate,
+        None => {
+          by_object.insert(object_id, candidates.len());
+          candidates.push(candidate);
+        },
+      },
+      None => candidates.push(candidate),
+    }
+  }
+  candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+  candidates
+}
+
+impl OllamaAIPlugin {
+  /// Finds the embedded pages nearly identical to `text`, e.g. to warn that a new page may
+  /// duplicate an existing one. Pages are the chunks matching `filter` that share an
+  /// `object_id`, scoring at least `threshold`, from 0.0 to 1.0, best first. An empty vector
+  /// store has none.
+  ///
+  /// `text` is embedded once, by the search, which looks at the best [DUPLICATE_SEARCH_TOP_K]
+  /// chunks.
+  pub async fn find_near_duplicates(
+    &self,
+    text: &str,
+    threshold: f64,
+    filter: HashMap<String, Value>,
+  ) -> Result<Vec<DuplicateCandidate>, PluginError> {
+    check_threshold(threshold)?;
+    let options = SearchOptions::new(DUPLICATE_SEARCH_TOP_K).with_min_score(threshold);
+    let page = self
+      .similarity_search_with_options(text, filter, options)
+      .await?;
+    Ok(near_duplicates(page.results, threshold))
+  }
+}
@@ -0,0 +1,216 @@
+use crate::citation::SOURCE_ID_KEY;
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{trace, warn};
+
+const INDEX_FILE_NAME: &str = "embedding_index.json";
+
+/// Metadata keys that identify what a text belongs to. They are part of the content hash, so the
+/// same text embedded for two objects is indexed for both.
+const IDENTITY_KEYS: [&str; 3] = ["object_id", "chat_id", SOURCE_ID_KEY];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IndexOutcome {
+  /// The text was sent to the plugin to be embedded.
+  Indexed,
+  /// The same text was already embedded for the same object.
+  Skipped,
+}
+
+/// Hashes `text` together with the identifying keys of `metadata`.
+pub fn content_hash(text: &str, metadata: &HashMap<String, Value>) -> String {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update(text.as_bytes());
+  for key in IDENTITY_KEYS {
+    if let Some(value) = metadata.get(key) {
+      hasher.update(b"\0");
+      hasher.update(key.as_bytes());
+      hasher.update(b"=");
+      hasher.update(value.to_string().as_bytes());
+    }
+  }
+  hasher.finalize().to_hex().to_string()
+}
+
+pub fn index_path(persist_directory: &Path) -> PathBuf {
+  persist_directory.join(INDEX_FILE_NAME)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct IndexFile {
+  /// Content hash to the `object_id` the text was embedded for, if any.
+  entries: HashMap<String, Option<String>>,
+}
+
+/// Records the content hashes of the texts already embedded, so unchanged content is not
+/// indexed again. The index is stored next to the vectors in the persist directory.
+#[derive(Default)]
+pub struct EmbeddingIndex {
+  path: Option<PathBuf>,
+  entries: parking_lot::Mutex<HashMap<String, Option<String>>>,
+  in_flight: parking_lot::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl EmbeddingIndex {
+  /// An index that is not persisted, used when RAG is disabled.
+  pub fn in_memory() -> Self {
+    Self::default()
+  }
+
+  /// Opens the index stored in `persist_directory`. An unreadable index is discarded, which only
+  /// costs indexing the content again.
+  pub fn open(persist_directory: &Path) -> Result<Self, PluginError> {
+    let path = index_path(persist_directory);
+    let entries = if path.exists() {
+      let content = std::fs::read(&path)?;
+      match serde_json::from_slice::<IndexFile>(&content) {
+        Ok(file) => file.entries,
+        Err(err) => {
+          warn!(
+            "[AI Plugin] discarding corrupted embedding index {:?}: {}",
+            path, err
+          );
+          HashMap::new()
+        },
+      }
+    } else {
+      HashMap::new()
+    };
+
+    Ok(Self {
+      path: Some(path),
+      entries: parking_lot::Mutex::new(entries),
+      in_flight: Default::default(),
+    })
+  }
+
+  pub fn contains(&self, hash: &str) -> bool {
+    self.entries.lock().contains_key(hash)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.lock().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.lock().is_empty()
+  }
+
+  pub fn insert(&self, hash: &str, object_id: Option<&str>) -> Result<(), PluginError> {
+    let mut entries = self.entries.lock();
+    entries.insert(hash.to_string(), object_id.map(String::from));
+    self.save(&entries)
+  }
+
+  /// Forgets every text indexed for `object_id`, returning how many were removed.
+  pub fn invalidate(&self, object_id: &str) -> Result<usize, PluginError> {
+    let mut entries = self.entries.lock();
+    let len = entries.len();
+    entries.retain(|_, id| id.as_deref() != Some(object_id));
+    let removed = len - entries.len();
+    if removed > 0 {
+      self.save(&entries)?;
+    }
+    Ok(removed)
+  }
+
+  /// Runs `index` unless `hash` is already indexed, and records `hash` once it succeeds.
+  /// Concurrent calls with the same hash run `index` only once.
+  pub async fn index_once<F, Fut>(
+    &self,
+    hash: &str,
+    object_id: Option<&str>,
+    index: F,
+  ) -> Result<IndexOutcome, PluginError>
+  where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(), PluginError>>,
+  {
+    if self.contains(hash) {
+      return Ok(IndexOutcome::Skipped);
+    }
+
+    let lock = self
+      .in_flight
+      .lock()
+      .entry(hash.to_string())
+      .or_default()
+      .clone();
+    let result = {
+      let _guard = lock.lock().await;
+      if self.contains(hash) {
+        Ok(IndexOutcome::Skipped)
+      } else {
+        match index().await {
+          Ok(()) => self.insert(hash, object_id).map(|_| IndexOutcome::Indexed),
+          Err(err) => Err(err),
+        }
+      }
+    };
+
+    // The map and this function hold the only references when no other call is waiting.
+    let mut in_flight = self.in_flight.lock();
+    if Arc::strong_count(&lock) == 2 {
+      in_flight.remove(hash);
+    }
+    trace!("[AI Plugin] embedding index {}: {:?}", hash, result);
+    result
+  }
+
+  fn save(&self, entries: &HashMap<String, Option<String>>) -> Result<(), PluginError> {
+    let path = match self.path.as_ref() {
+      Some(path) => path,
+      None => return Ok(()),
+    };
+    let file = IndexFile {
+      entries: entries.clone(),
+    };
+    let content = serde_json::to_vec(&file).map_err(|err| PluginError::Internal(err.into()))?;
+    // Write to a temporary file first so a crash never leaves a truncated index behind.
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Like [Self::embed_text], but skips texts that were already embedded with the same
+  /// `object_id`, `chat_id` and `source_id` metadata.
+  pub async fn embed_text_if_changed(
+    &self,
+    text: &str,
+    metadata: HashMap<String, Value>,
+  ) -> Result<IndexOutcome, PluginError> {
+    let index = self.embedding_index.read().await.clone();
+    let hash = content_hash(text, &metadata);
+    let object_id = metadata
+      .get("object_id")
+      .and_then(|v| v.as_str())
+      .map(String::from);
+    index
+      .index_once(&hash, object_id.as_deref(), || {
+        self.embed_text(text, metadata)
+      })
+      .await
+  }
+
+  /// Forgets the texts indexed for `object_id` by [Self::embed_text_if_changed], so they are
+  /// embedded again next time.
+  pub async fn invalidate(&self, object_id: &str) -> Result<(), PluginError> {
+    let index = self.embedding_index.read().await.clone();
+    let removed = index.invalidate(object_id)?;
+    trace!(
+      "[AI Plugin] invalidated {} indexed texts of {}",
+      removed,
+      object_id
+    );
+    Ok(())
+  }
+}
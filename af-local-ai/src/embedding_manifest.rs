@@ -0,0 +1,86 @@
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const MANIFEST_FILE_NAME: &str = "embedding_manifest.json";
+
+/// The embedding model that produced the vectors stored in a persist directory.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EmbeddingModelInfo {
+  pub model: String,
+  pub dimension: usize,
+}
+
+/// What to do when the configured embedding model differs from the one recorded in the
+/// persist directory.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchPolicy {
+  /// Fail initialization with [PluginError::EmbeddingModelChanged].
+  #[default]
+  Error,
+  /// Purge the persist directory so the content is indexed again with the new model.
+  Reindex,
+  /// Keep the existing vectors and continue.
+  Ignore,
+}
+
+pub fn manifest_path(persist_directory: &Path) -> PathBuf {
+  persist_directory.join(MANIFEST_FILE_NAME)
+}
+
+pub fn read_manifest(persist_directory: &Path) -> Result<Option<EmbeddingModelInfo>, PluginError> {
+  let path = manifest_path(persist_directory);
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content = std::fs::read(&path)?;
+  let info = serde_json::from_slice(&content).map_err(|err| PluginError::Internal(err.into()))?;
+  Ok(Some(info))
+}
+
+pub fn write_manifest(
+  persist_directory: &Path,
+  info: &EmbeddingModelInfo,
+) -> Result<(), PluginError> {
+  let content = serde_json::to_vec_pretty(info).map_err(|err| PluginError::Internal(err.into()))?;
+  std::fs::write(manifest_path(persist_directory), content)?;
+  Ok(())
+}
+
+/// Compares `configured_model` against the manifest stored in `persist_directory` and applies
+/// `policy` on mismatch. A directory without a manifest is accepted as is.
+pub fn check_manifest(
+  persist_directory: &Path,
+  configured_model: &str,
+  policy: MismatchPolicy,
+) -> Result<(), PluginError> {
+  let stored = match read_manifest(persist_directory)? {
+    Some(info) if info.model != configured_model => info.model,
+    _ => return Ok(()),
+  };
+
+  match policy {
+    MismatchPolicy::Error => Err(PluginError::EmbeddingModelChanged {
+      stored,
+      configured: configured_model.to_string(),
+    }),
+    MismatchPolicy::Reindex => {
+      info!(
+        "[AI Plugin] embedding model changed from {} to {}, purging {:?}",
+        stored, configured_model, persist_directory
+      );
+      std::fs::remove_dir_all(persist_directory)?;
+      std::fs::create_dir_all(persist_directory)?;
+      Ok(())
+    },
+    MismatchPolicy::Ignore => {
+      warn!(
+        "[AI Plugin] embedding model changed from {} to {}, keeping existing vectors",
+        stored, configured_model
+      );
+      Ok(())
+    },
+  }
+}
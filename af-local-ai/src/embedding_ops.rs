@@ -1,19 +1,133 @@
+use crate::ai_ops::handle_params;
+use crate::rate_limit::RateLimiter;
+use crate::retry::Retrier;
+use crate::scheduler::Priority;
+use crate::slow_request::SlowRequestMonitor;
+use af_ai_protocol::method;
+pub use af_ai_protocol::parser::{
+  EmbeddingResponseParse, MigrationProgressParser, SimilaritySearchPageParse,
+  SimilaritySearchResponseParse, StoredEmbeddingPageParse, VectorStoreExportParse,
+  VectorStoreStatsParse,
+};
+pub use af_ai_protocol::types::{
+  MigrationProgress, SearchOptions, SearchPage, SearchResult, StoredEmbedding, StoredEmbeddingPage,
+  VectorStoreCounts,
+};
 use af_plugin::core::parser::{EmptyResponseParser, ResponseParser};
 use af_plugin::core::plugin::Plugin;
-use af_plugin::error::{PluginError, RemoteError};
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
 use anyhow::anyhow;
-use serde_json::Value as JsonValue;
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::sync::Weak;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Vectors asked for in each `vs_get` request, so large vectors are read a page at a time.
+pub const STORED_EMBEDDING_PAGE_SIZE: usize = 100;
 
 pub struct EmbeddingPluginOperation {
   plugin: Weak<Plugin>,
+  trace_id: Option<String>,
+  slow_requests: Option<Arc<SlowRequestMonitor>>,
+  rate_limiter: Option<Arc<RateLimiter>>,
+  priority: Priority,
+  retrier: Option<Arc<Retrier>>,
+  retries: AtomicU32,
 }
 
 impl EmbeddingPluginOperation {
   pub fn new(plugin: Weak<Plugin>) -> Self {
-    EmbeddingPluginOperation { plugin }
+    EmbeddingPluginOperation {
+      plugin,
+      trace_id: None,
+      slow_requests: None,
+      rate_limiter: None,
+      priority: Priority::default(),
+      retrier: None,
+      retries: AtomicU32::new(0),
+    }
+  }
+
+  /// See [AIPluginOperation::with_trace_id](crate::ai_ops::AIPluginOperation::with_trace_id).
+  pub fn with_trace_id(mut self, trace_id: &str) -> Self {
+    self.trace_id = Some(trace_id.to_string());
+    self
+  }
+
+  /// See [AIPluginOperation::with_slow_requests](crate::ai_ops::AIPluginOperation::with_slow_requests).
+  pub fn with_slow_requests(mut self, monitor: Arc<SlowRequestMonitor>) -> Self {
+    self.slow_requests = Some(monitor);
+    self
+  }
+
+  /// See [AIPluginOperation::with_rate_limiter](crate::ai_ops::AIPluginOperation::with_rate_limiter).
+  pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+    self.rate_limiter = Some(limiter);
+    self
+  }
+
+  /// See [AIPluginOperation::with_priority](crate::ai_ops::AIPluginOperation::with_priority).
+  pub fn with_priority(mut self, priority: Priority) -> Self {
+    self.priority = priority;
+    self
+  }
+
+  /// See [AIPluginOperation::with_retrier](crate::ai_ops::AIPluginOperation::with_retrier).
+  pub(crate) fn with_retrier(mut self, retrier: Arc<Retrier>) -> Self {
+    self.retrier = Some(retrier);
+    self
+  }
+
+  /// See [AIPluginOperation::retries](crate::ai_ops::AIPluginOperation::retries).
+  pub fn retries(&self) -> u32 {
+    self.retries.load(Ordering::Relaxed)
+  }
+
+  async fn acquire_rate_limit(&self) -> Result<(), PluginError> {
+    match &self.rate_limiter {
+      Some(limiter) => limiter.acquire(self.priority).await,
+      None => Ok(()),
+    }
+  }
+
+  fn handle_params(&self, method: &str, params: Value) -> Value {
+    handle_params(method, params, self.trace_id.as_deref())
+  }
+
+  async fn request<P: ResponseParser>(
+    &self,
+    plugin: &Plugin,
+    params: &Value,
+  ) -> Result<P::ValueType, PluginError> {
+    match &self.retrier {
+      Some(retrier) => {
+        let method = params.get("method").and_then(Value::as_str).unwrap_or("");
+        retrier
+          .run(method, &self.retries, || {
+            self.send_once::<P>(plugin, params)
+          })
+          .await
+      },
+      None => self.send_once::<P>(plugin, params).await,
+    }
+  }
+
+  async fn send_once<P: ResponseParser>(
+    &self,
+    plugin: &Plugin,
+    params: &Value,
+  ) -> Result<P::ValueType, PluginError> {
+    self.acquire_rate_limit().await?;
+    let start = Instant::now();
+    let result = plugin.async_request::<P>("handle", params).await;
+    if let Some(monitor) = &self.slow_requests {
+      monitor.finish(params, start);
+    }
+    result
   }
 
   pub async fn gen_embeddings(&self, message: &str) -> Result<Vec<Vec<f64>>, PluginError> {
@@ -21,9 +135,9 @@ impl EmbeddingPluginOperation {
       .plugin
       .upgrade()
       .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
-    let params = json!({"method": "gen_embeddings", "params": {"input": message }});
-    plugin
-      .async_request::<EmbeddingResponseParse>("handle", &params)
+    let params = self.handle_params(method::GEN_EMBEDDINGS, json!({"input": message }));
+    self
+      .request::<EmbeddingResponseParse>(&plugin, &params)
       .await
   }
 
@@ -37,13 +151,151 @@ impl EmbeddingPluginOperation {
       .upgrade()
       .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
     let metadata = json!(metadata);
-    let params =
-      json!({"method": "embed_text", "params": {"input": message, "metadata": metadata }});
-    plugin
-      .async_request::<EmptyResponseParser>("handle", &params)
+    let params = self.handle_params(
+      method::EMBED_TEXT,
+      json!({"input": message, "metadata": metadata }),
+    );
+    self.request::<EmptyResponseParser>(&plugin, &params).await
+  }
+
+  /// Deletes the vectors whose metadata matches every key of `filter`.
+  pub async fn delete_documents(&self, filter: HashMap<String, Value>) -> Result<(), PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = json!({"method": method::DELETE_DOCUMENTS, "params": {"filter": filter }});
+    self.request::<EmptyResponseParser>(&plugin, &params).await
+  }
+
+  /// Flushes the vector store to the persist directory so it can be copied, and returns the
+  /// number of vectors it holds.
+  pub async fn export_vector_store(&self) -> Result<u64, PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = json!({"method": method::VS_EXPORT, "params": {}});
+    self
+      .request::<VectorStoreExportParse>(&plugin, &params)
       .await
   }
 
+  /// Reopens the vector store after its persist directory was replaced.
+  pub async fn reload_vector_store(&self) -> Result<(), PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = json!({"method": method::VS_RELOAD, "params": {}});
+    self.request::<EmptyResponseParser>(&plugin, &params).await
+  }
+
+  /// Closes the vector store and opens the one stored in `persist_directory`.
+  pub async fn switch_vector_store(&self, persist_directory: &Path) -> Result<(), PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = self.handle_params(
+      method::VS_SWITCH,
+      json!({ "persist_directory": persist_directory }),
+    );
+    self.request::<EmptyResponseParser>(&plugin, &params).await
+  }
+
+  /// Rewrites the vector store from the `from` store format into the `to` one, streaming its
+  /// progress.
+  pub async fn migrate_vector_store(
+    &self,
+    from: u32,
+    to: u32,
+  ) -> Result<ReceiverStream<Result<MigrationProgress, PluginError>>, PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = self.handle_params(method::VS_MIGRATE, json!({ "from": from, "to": to }));
+    self.acquire_rate_limit().await?;
+    let start = Instant::now();
+    let stream = plugin.stream_request::<MigrationProgressParser>(
+      "handle",
+      &params,
+      StreamOptions::default(),
+    )?;
+    Ok(match &self.slow_requests {
+      Some(monitor) => monitor.watch_stream(stream, params, start),
+      None => stream,
+    })
+  }
+
+  /// Counts the documents and chunks of the vector store.
+  pub async fn vector_store_stats(&self) -> Result<VectorStoreCounts, PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = self.handle_params(method::VS_STATS, json!({}));
+    self
+      .request::<VectorStoreStatsParse>(&plugin, &params)
+      .await
+  }
+
+  /// Reclaims the space of the deleted chunks of the vector store.
+  pub async fn compact_vector_store(&self) -> Result<(), PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = self.handle_params(method::VS_COMPACT, json!({}));
+    self.request::<EmptyResponseParser>(&plugin, &params).await
+  }
+
+  /// Reads a page of at most `limit` vectors whose metadata matches every key of `filter`,
+  /// starting at `cursor`, the `next_cursor` of the previous page.
+  pub async fn get_embeddings_page(
+    &self,
+    filter: &HashMap<String, Value>,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<StoredEmbeddingPage, PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = self.handle_params(
+      method::VS_GET,
+      json!({"filter": filter, "cursor": cursor, "limit": limit }),
+    );
+    self
+      .request::<StoredEmbeddingPageParse>(&plugin, &params)
+      .await
+  }
+
+  /// Reads up to `limit` vectors whose metadata matches every key of `filter`, a page of
+  /// [STORED_EMBEDDING_PAGE_SIZE] at a time.
+  pub async fn get_embeddings(
+    &self,
+    filter: HashMap<String, Value>,
+    limit: usize,
+  ) -> Result<Vec<StoredEmbedding>, PluginError> {
+    let mut embeddings = Vec::new();
+    let mut cursor = None;
+    while embeddings.len() < limit {
+      let page_size = (limit - embeddings.len()).min(STORED_EMBEDDING_PAGE_SIZE);
+      let page = self
+        .get_embeddings_page(&filter, cursor.as_deref(), page_size)
+        .await?;
+      embeddings.extend(page.embeddings);
+      cursor = match page.next_cursor {
+        Some(next) if cursor.as_ref() != Some(&next) => Some(next),
+        _ => break,
+      };
+    }
+    embeddings.truncate(limit);
+    Ok(embeddings)
+  }
+
   pub async fn similarity_search(
     &self,
     query: &str,
@@ -53,66 +305,72 @@ impl EmbeddingPluginOperation {
       .plugin
       .upgrade()
       .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
-    let params =
-      json!({"method": "similarity_search", "params": {"query": query, "filter": filter }});
-    plugin
-      .async_request::<SimilaritySearchResponseParse>("handle", &params)
+    let params = self.handle_params(
+      method::SIMILARITY_SEARCH,
+      json!({"query": query, "filter": filter }),
+    );
+    self
+      .request::<SimilaritySearchResponseParse>(&plugin, &params)
       .await
   }
-}
 
-pub struct SimilaritySearchResponseParse;
-impl ResponseParser for SimilaritySearchResponseParse {
-  type ValueType = Vec<String>;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    if json.is_object() {
-      if let Some(data) = json.get("data") {
-        if let Some(array) = data.as_array() {
-          let mut result = Vec::new();
-          for item in array {
-            if let Some(value) = item.as_str() {
-              result.push(value.to_string());
-            } else {
-              return Err(RemoteError::ParseResponse(json));
-            }
-          }
-          return Ok(result);
-        }
-      }
-    }
-    Err(RemoteError::ParseResponse(json))
+  /// Searches one page of results. `options` is sent to the plugin, and also enforced on the
+  /// reply for plugins that ignore it.
+  pub async fn similarity_search_with_options(
+    &self,
+    query: &str,
+    filter: HashMap<String, Value>,
+    options: &SearchOptions,
+  ) -> Result<SearchPage, PluginError> {
+    let plugin = self
+      .plugin
+      .upgrade()
+      .ok_or(PluginError::Internal(anyhow!("Plugin is dropped")))?;
+    let params = self.handle_params(
+      method::SIMILARITY_SEARCH,
+      json!({
+        "query": query,
+        "filter": filter,
+        "top_k": options.top_k,
+        "min_score": options.min_score,
+        "offset": options.offset,
+      }),
+    );
+    let page = self
+      .request::<SimilaritySearchPageParse>(&plugin, &params)
+      .await?;
+    Ok(paginate(page, options))
   }
 }
 
-pub struct EmbeddingResponseParse;
-impl ResponseParser for EmbeddingResponseParse {
-  type ValueType = Vec<Vec<f64>>;
-
-  fn parse_json(json: JsonValue) -> Result<Self::ValueType, RemoteError> {
-    if json.is_object() {
-      if let Some(embeddings) = json.get("data") {
-        if let Some(array) = embeddings.as_array() {
-          let mut result = Vec::new();
-          for item in array {
-            if let Some(inner_array) = item.as_array() {
-              let mut inner_result = Vec::new();
-              for num in inner_array {
-                if let Some(value) = num.as_f64() {
-                  inner_result.push(value);
-                } else {
-                  return Err(RemoteError::ParseResponse(json));
-                }
-              }
-              result.push(inner_result);
-            } else {
-              return Err(RemoteError::ParseResponse(json));
-            }
-          }
-          return Ok(result);
-        }
-      }
-    }
-    Err(RemoteError::ParseResponse(json))
+/// Sorts `page` by descending score and drops the results below `min_score`. Plugins that page
+/// results report a `total`; the replies of the others hold every result, which are paged here.
+/// Results without a score are kept, after the scored ones.
+fn paginate(mut page: SearchPage, options: &SearchOptions) -> SearchPage {
+  if let Some(min_score) = options.min_score {
+    page.results.retain(|result| match result.score {
+      Some(score) => score >= min_score,
+      None => true,
+    });
+  }
+  page.results.sort_by(|a, b| {
+    let score = |result: &SearchResult| result.score.unwrap_or(f64::NEG_INFINITY);
+    score(b).total_cmp(&score(a))
+  });
+
+  if page.total.is_some() {
+    page.results.truncate(options.top_k);
+    return page;
+  }
+  let total = page.results.len();
+  let results = page
+    .results
+    .into_iter()
+    .skip(options.offset)
+    .take(options.top_k)
+    .collect();
+  SearchPage {
+    results,
+    total: Some(total),
   }
 }
@@ -1,9 +1,11 @@
 use crate::embedding_ops::EmbeddingPluginOperation;
+use crate::ollama_plugin::{OllamaAIPlugin, OllamaPluginConfig};
 use std::collections::HashMap;
 
 use af_plugin::core::plugin::{
   Plugin, PluginConfig, RunningState, RunningStateReceiver, RunningStateSender,
 };
+use af_plugin::core::state_machine::StateMachine;
 use af_plugin::error::PluginError;
 use af_plugin::manager::PluginManager;
 use anyhow::anyhow;
@@ -25,23 +27,55 @@ pub struct EmbeddingPlugin {
   #[allow(dead_code)]
   // keep at least one receiver that make sure the sender can receive value
   running_state_rx: RunningStateReceiver,
+  /// Config of the chat plugin whose process is shared, see [EmbeddingPlugin::attached].
+  chat_config: Option<Arc<RwLock<Option<OllamaPluginConfig>>>>,
 }
 
 impl EmbeddingPlugin {
   pub fn new(plugin_manager: Arc<PluginManager>) -> Self {
-    let (running_state, rx) = tokio::sync::watch::channel(RunningState::ReadyToConnect);
+    let running_state = StateMachine::new(RunningState::ReadyToConnect);
+    let rx = running_state.subscribe();
     Self {
       plugin_manager,
       plugin_config: Default::default(),
       running_state: Arc::new(running_state),
       running_state_rx: rx,
+      chat_config: None,
     }
   }
 
+  /// Creates an embedding plugin sending its requests to the process of `ollama_plugin`
+  /// instead of starting its own. The chat plugin must be initialized with
+  /// [OllamaPluginConfig::with_embedded_embedding], and restarts of the chat plugin are
+  /// followed.
+  ///
+  /// Attached plugins are not initialized with [EmbeddingPlugin::init_embedding_plugin].
+  pub fn attached(ollama_plugin: &OllamaAIPlugin) -> Self {
+    let running_state = ollama_plugin.running_state.clone();
+    let running_state_rx = running_state.subscribe();
+    Self {
+      plugin_manager: ollama_plugin.plugin_manager.clone(),
+      plugin_config: Default::default(),
+      running_state,
+      running_state_rx,
+      chat_config: Some(ollama_plugin.plugin_config.clone()),
+    }
+  }
+
+  /// Whether the plugin shares the process of a chat plugin, see [EmbeddingPlugin::attached].
+  pub fn is_attached(&self) -> bool {
+    self.chat_config.is_some()
+  }
+
   pub async fn init_embedding_plugin(
     &self,
     config: EmbeddingPluginConfig,
   ) -> Result<(), PluginError> {
+    if self.is_attached() {
+      return Err(PluginError::EmbeddingModeMismatch(
+        "an attached embedding plugin runs in its chat plugin, which initializes it".to_string(),
+      ));
+    }
     if let Some(existing_config) = self.plugin_config.read().await.as_ref() {
       trace!(
         "[Embedding Plugin] existing config: {:?}, new config:{:?}",
@@ -54,6 +88,8 @@ impl EmbeddingPlugin {
       name: "embedding".to_string(),
       exec_path: config.executable_path.clone(),
       exec_command: "".to_string(),
+      crash_journal_dir: None,
+      ..Default::default()
     };
     let plugin_id = self
       .plugin_manager
@@ -110,7 +146,28 @@ impl EmbeddingPlugin {
     Ok(result)
   }
 
-  async fn get_embedding_plugin(&self) -> Result<Weak<Plugin>> {
+  async fn get_embedding_plugin(&self) -> Result<Weak<Plugin>, PluginError> {
+    if let Some(chat_config) = self.chat_config.as_ref() {
+      let embedded = chat_config
+        .read()
+        .await
+        .as_ref()
+        .map(|config| config.embedded_embedding);
+      match embedded {
+        Some(true) => {},
+        Some(false) => {
+          return Err(PluginError::EmbeddingModeMismatch(
+            "the chat plugin was not configured with `with_embedded_embedding`".to_string(),
+          ))
+        },
+        None => {
+          return Err(PluginError::Internal(anyhow!(
+            "Chat plugin is not initialized yet"
+          )))
+        },
+      }
+    }
+
     let plugin_id = self
       .running_state
       .borrow()
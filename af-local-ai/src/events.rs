@@ -0,0 +1,251 @@
+use crate::ai_ops::{STREAM_ANSWER_KEY, STREAM_COMMENT_KEY};
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+/// Identifies a completion or chat answer started with [CompletionEvents].
+pub type CompletionId = u64;
+
+/// A frame of a completion or answer stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamFrame {
+  /// A piece of the answer.
+  Answer(String),
+  /// A piece of the comment of a completion.
+  Comment(String),
+  /// Any other frame, such as metadata, as sent by the plugin.
+  Other(Value),
+}
+
+impl From<Value> for StreamFrame {
+  fn from(frame: Value) -> Self {
+    let text_of = |key: &str| match frame.as_object() {
+      Some(object) if object.len() == 1 => object.get(key).and_then(Value::as_str),
+      _ => None,
+    };
+    if let Some(answer) = text_of(STREAM_ANSWER_KEY) {
+      return Self::Answer(answer.to_string());
+    }
+    if let Some(comment) = text_of(STREAM_COMMENT_KEY) {
+      return Self::Comment(comment.to_string());
+    }
+    Self::Other(frame)
+  }
+}
+
+/// Receives the frames of the completions started with [CompletionEvents]. Each completion gets
+/// its frames in order, then exactly one call to either `on_done` or `on_error`, after which it
+/// gets no more calls.
+pub trait CompletionSink: Send + Sync {
+  fn on_chunk(&self, id: CompletionId, frame: StreamFrame);
+  fn on_error(&self, id: CompletionId, error: PluginError);
+  /// Called once the stream ended, or was cancelled with [CompletionEvents::cancel].
+  fn on_done(&self, id: CompletionId);
+}
+
+/// A completion, see [OllamaAIPlugin::complete_text_v2_with_options].
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+  pub text: String,
+  pub completion_type: u8,
+  pub format: Option<Value>,
+  pub metadata: Option<Value>,
+  pub options: StreamOptions,
+}
+
+impl CompletionRequest {
+  pub fn new(text: &str, completion_type: u8) -> Self {
+    Self {
+      text: text.to_string(),
+      completion_type,
+      format: None,
+      metadata: None,
+      options: StreamOptions::default(),
+    }
+  }
+
+  pub fn with_format(mut self, format: Value) -> Self {
+    self.format = Some(format);
+    self
+  }
+
+  pub fn with_metadata(mut self, metadata: Value) -> Self {
+    self.metadata = Some(metadata);
+    self
+  }
+
+  pub fn with_options(mut self, options: StreamOptions) -> Self {
+    self.options = options;
+    self
+  }
+}
+
+/// A question asked in a chat, see [OllamaAIPlugin::stream_question_with_options].
+#[derive(Debug, Clone)]
+pub struct ChatStreamRequest {
+  pub chat_id: String,
+  pub message: String,
+  pub format: Option<Value>,
+  pub metadata: Value,
+  pub options: StreamOptions,
+}
+
+impl ChatStreamRequest {
+  pub fn new(chat_id: &str, message: &str) -> Self {
+    Self {
+      chat_id: chat_id.to_string(),
+      message: message.to_string(),
+      format: None,
+      metadata: Value::Object(Default::default()),
+      options: StreamOptions::default(),
+    }
+  }
+
+  pub fn with_format(mut self, format: Value) -> Self {
+    self.format = Some(format);
+    self
+  }
+
+  pub fn with_metadata(mut self, metadata: Value) -> Self {
+    self.metadata = metadata;
+    self
+  }
+
+  pub fn with_options(mut self, options: StreamOptions) -> Self {
+    self.options = options;
+    self
+  }
+}
+
+/// Callback based counterpart of the streaming APIs of [OllamaAIPlugin], for callers that can't
+/// hold a stream, such as FFI bridges. Completions are started with an id, their frames are
+/// delivered to a [CompletionSink], and they can be cancelled by id.
+pub struct CompletionEvents {
+  plugin: Arc<OllamaAIPlugin>,
+  runtime: Handle,
+  next_id: AtomicU64,
+  /// Cancellation tokens of the completions that haven't ended yet.
+  running: Arc<parking_lot::Mutex<HashMap<CompletionId, CancellationToken>>>,
+}
+
+impl CompletionEvents {
+  /// Runs the completions of `plugin` as tasks of `runtime`.
+  pub fn new(plugin: Arc<OllamaAIPlugin>, runtime: Handle) -> Self {
+    Self {
+      plugin,
+      runtime,
+      next_id: AtomicU64::new(1),
+      running: Default::default(),
+    }
+  }
+
+  pub fn start_completion(
+    &self,
+    request: CompletionRequest,
+    sink: Arc<dyn CompletionSink>,
+  ) -> CompletionId {
+    let plugin = self.plugin.clone();
+    self.start(sink, async move {
+      plugin
+        .complete_text_v2_with_options(
+          &request.text,
+          request.completion_type,
+          request.format,
+          request.metadata,
+          request.options,
+        )
+        .await
+    })
+  }
+
+  pub fn start_chat_stream(
+    &self,
+    request: ChatStreamRequest,
+    sink: Arc<dyn CompletionSink>,
+  ) -> CompletionId {
+    let plugin = self.plugin.clone();
+    self.start(sink, async move {
+      plugin
+        .stream_question_with_options(
+          &request.chat_id,
+          &request.message,
+          request.format,
+          request.metadata,
+          request.options,
+        )
+        .await
+    })
+  }
+
+  /// Stops the completion `id`, whose sink then gets `on_done`. Returns false when it already
+  /// ended.
+  pub fn cancel(&self, id: CompletionId) -> bool {
+    match self.running.lock().remove(&id) {
+      Some(token) => {
+        token.cancel();
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// Number of completions that haven't ended yet.
+  pub fn running(&self) -> usize {
+    self.running.lock().len()
+  }
+
+  fn start<F>(&self, sink: Arc<dyn CompletionSink>, stream: F) -> CompletionId
+  where
+    F: std::future::Future<Output = Result<ReceiverStream<Result<Value, PluginError>>, PluginError>>
+      + Send
+      + 'static,
+  {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let token = CancellationToken::new();
+    self.running.lock().insert(id, token.clone());
+    let running = self.running.clone();
+    // All the calls to the sink come from this task, so they can't overlap or come after the
+    // last one.
+    self.runtime.spawn(async move {
+      let result = tokio::select! {
+        biased;
+        _ = token.cancelled() => Ok(()),
+        result = forward(id, &*sink, &token, stream) => result,
+      };
+      running.lock().remove(&id);
+      match result {
+        Ok(()) => sink.on_done(id),
+        Err(err) => sink.on_error(id, err),
+      }
+    });
+    id
+  }
+}
+
+async fn forward<F>(
+  id: CompletionId,
+  sink: &dyn CompletionSink,
+  token: &CancellationToken,
+  stream: F,
+) -> Result<(), PluginError>
+where
+  F: std::future::Future<Output = Result<ReceiverStream<Result<Value, PluginError>>, PluginError>>,
+{
+  let mut stream = stream.await?;
+  while let Some(frame) = stream.next().await {
+    // Frames already received don't yield to the select, e.g. when `on_chunk` cancels.
+    if token.is_cancelled() {
+      break;
+    }
+    sink.on_chunk(id, StreamFrame::from(frame?));
+  }
+  Ok(())
+}
@@ -0,0 +1,324 @@
+use crate::ai_ops::CompleteTextType;
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+use tracing::warn;
+
+/// Type of a field read from a text by
+/// [OllamaAIPlugin::extract_fields](crate::ollama_plugin::OllamaAIPlugin::extract_fields). Unlike
+/// the [FieldType](crate::database_query::FieldType) of database questions, it carries the
+/// options of select fields.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldType {
+  Text,
+  Number,
+  /// Extracted as `YYYY-MM-DD`.
+  Date,
+  /// Extracted as one of `options`, in its own spelling.
+  SingleSelect {
+    options: Vec<String>,
+  },
+  Checkbox,
+}
+
+/// A field to read from a text.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldSpec {
+  pub name: String,
+  pub field_type: FieldType,
+  /// Tells the model what the field holds when its name is not enough.
+  pub description: Option<String>,
+}
+
+impl FieldSpec {
+  pub fn new(name: &str, field_type: FieldType) -> Self {
+    Self {
+      name: name.to_string(),
+      field_type,
+      description: None,
+    }
+  }
+
+  pub fn with_description(mut self, description: &str) -> Self {
+    self.description = Some(description.to_string());
+    self
+  }
+}
+
+/// JSON schema of the object the model replies with, sent as the `format` of the completion.
+/// Every field is nullable, so the model can leave out what the text doesn't say.
+pub fn extraction_schema(fields: &[FieldSpec]) -> Value {
+  let properties = fields
+    .iter()
+    .map(|field| {
+      let mut property = match &field.field_type {
+        FieldType::Text => json!({ "type": ["string", "null"] }),
+        FieldType::Number => json!({ "type": ["number", "null"] }),
+        FieldType::Date => json!({ "type": ["string", "null"], "format": "date" }),
+        FieldType::SingleSelect { options } => {
+          let mut options = options
+            .iter()
+            .map(|option| json!(option))
+            .collect::<Vec<_>>();
+          options.push(Value::Null);
+          json!({ "enum": options })
+        },
+        FieldType::Checkbox => json!({ "type": ["boolean", "null"] }),
+      };
+      if let Some(description) = &field.description {
+        property["description"] = json!(description);
+      }
+      (field.name.clone(), property)
+    })
+    .collect::<Map<_, _>>();
+  json!({
+    "type": "object",
+    "properties": properties,
+    "required": fields.iter().map(|field| &field.name).collect::<Vec<_>>(),
+  })
+}
+
+/// Builds the prompt asking the model to read `fields` from `text`, sent as a custom completion.
+pub fn extraction_prompt(text: &str, fields: &[FieldSpec]) -> String {
+  let mut prompt = String::new();
+  let _ = writeln!(
+    prompt,
+    "Extract the fields below from the text. Reply with a JSON object with one key per field, \
+    using null for the fields the text doesn't give."
+  );
+  let _ = writeln!(prompt, "\nFields:");
+  for field in fields {
+    let kind = match &field.field_type {
+      FieldType::Text => "text".to_string(),
+      FieldType::Number => "number".to_string(),
+      FieldType::Date => "date, as YYYY-MM-DD".to_string(),
+      FieldType::SingleSelect { options } => format!("one of: {}", options.join(", ")),
+      FieldType::Checkbox => "true or false".to_string(),
+    };
+    let _ = match &field.description {
+      Some(description) => writeln!(prompt, "- {} ({}): {}", field.name, kind, description),
+      None => writeln!(prompt, "- {} ({})", field.name, kind),
+    };
+  }
+  let _ = write!(prompt, "\nText:\n{}", text);
+  prompt
+}
+
+/// Reads the JSON object of a model reply, which may be wrapped in prose or a code block.
+pub fn parse_extraction_reply(reply: &str) -> Option<Map<String, Value>> {
+  let start = reply.find('{')?;
+  let end = reply.rfind('}')?;
+  if end < start {
+    return None;
+  }
+  match serde_json::from_str(&reply[start..=end]) {
+    Ok(Value::Object(object)) => Some(object),
+    _ => None,
+  }
+}
+
+/// Values of `fields` in `reply`, converted to their types. Fields that are missing or can't be
+/// converted are null.
+pub fn coerce_fields(reply: &Map<String, Value>, fields: &[FieldSpec]) -> HashMap<String, Value> {
+  fields
+    .iter()
+    .map(|field| {
+      let value = reply
+        .get(&field.name)
+        .or_else(|| {
+          reply
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&field.name))
+            .map(|(_, value)| value)
+        })
+        .and_then(|value| coerce_value(value, &field.field_type))
+        .unwrap_or(Value::Null);
+      (field.name.clone(), value)
+    })
+    .collect()
+}
+
+/// Converts `value` to `field_type`, e.g. numbers written as strings.
+pub fn coerce_value(value: &Value, field_type: &FieldType) -> Option<Value> {
+  if value.is_null() {
+    return None;
+  }
+  match field_type {
+    FieldType::Text => match value {
+      Value::String(text) => Some(text.trim())
+        .filter(|text| !text.is_empty())
+        .map(|text| Value::String(text.to_string())),
+      Value::Number(_) | Value::Bool(_) => Some(Value::String(value.to_string())),
+      _ => None,
+    },
+    FieldType::Number => match value {
+      Value::Number(_) => Some(value.clone()),
+      Value::String(text) => parse_number(text),
+      _ => None,
+    },
+    FieldType::Date => value.as_str().and_then(parse_date).map(Value::String),
+    FieldType::SingleSelect { options } => {
+      let text = match value {
+        Value::String(text) => text.trim().to_string(),
+        Value::Number(_) | Value::Bool(_) => value.to_string(),
+        _ => return None,
+      };
+      options
+        .iter()
+        .find(|option| option.trim().to_lowercase() == text.to_lowercase())
+        .map(|option| Value::String(option.clone()))
+    },
+    FieldType::Checkbox => match value {
+      Value::Bool(_) => Some(value.clone()),
+      Value::Number(number) => match number.as_f64() {
+        Some(number) if number == 0.0 => Some(Value::Bool(false)),
+        Some(number) if number == 1.0 => Some(Value::Bool(true)),
+        _ => None,
+      },
+      Value::String(text) => match text.trim().to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" | "checked" | "x" => Some(Value::Bool(true)),
+        "false" | "no" | "n" | "0" | "unchecked" | "" => Some(Value::Bool(false)),
+        _ => None,
+      },
+      _ => None,
+    },
+  }
+}
+
+/// Reads the first number of `text`, ignoring currency signs, units and thousands separators,
+/// e.g. `4.5/5` or `$1,200`.
+fn parse_number(text: &str) -> Option<Value> {
+  let text = text.replace([',', '_'], "");
+  let start = text.find(|c: char| c.is_ascii_digit())?;
+  let negative = text[..start].ends_with('-');
+  let digits = text[start..]
+    .char_indices()
+    .take_while(|(index, c)| c.is_ascii_digit() || (*c == '.' && *index > 0))
+    .map(|(_, c)| c)
+    .collect::<String>();
+  let digits = digits.trim_end_matches('.');
+  let number = if negative {
+    format!("-{}", digits)
+  } else {
+    digits.to_string()
+  };
+  if let Ok(integer) = number.parse::<i64>() {
+    return Some(json!(integer));
+  }
+  number.parse::<f64>().ok().map(|number| json!(number))
+}
+
+const MONTHS: [&str; 12] = [
+  "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Reads a date written as `2024-03-05` (optionally with a time), `2024/03/05`, `03/05/2024`,
+/// `March 5, 2024` or `5 March 2024`, returning it as `YYYY-MM-DD`. Numeric dates with the year
+/// last are taken as month first, unless the first number can't be a month.
+pub fn parse_date(text: &str) -> Option<String> {
+  let text = text.trim();
+  let text = text
+    .split(['T', ' '])
+    .next()
+    .filter(|date| {
+      date.len() >= 8
+        && date
+          .chars()
+          .all(|c| c.is_ascii_digit() || matches!(c, '-' | '/' | '.'))
+    })
+    .unwrap_or(text);
+
+  let tokens = text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|token| !token.is_empty())
+    .collect::<Vec<_>>();
+  if tokens.len() != 3 {
+    return None;
+  }
+  let month_of = |token: &str| {
+    let prefix = token.to_lowercase().chars().take(3).collect::<String>();
+    MONTHS
+      .iter()
+      .position(|month| *month == prefix)
+      .map(|index| index as u32 + 1)
+  };
+  let number = |token: &str| {
+    let digits = token.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    digits.parse::<u32>().ok()
+  };
+
+  let (year, month, day) = match (month_of(tokens[0]), month_of(tokens[1])) {
+    // March 5, 2024
+    (Some(month), _) if number(tokens[0]).is_none() => {
+      (number(tokens[2])?, month, number(tokens[1])?)
+    },
+    // 5 March 2024
+    (_, Some(month)) if number(tokens[1]).is_none() => {
+      (number(tokens[2])?, month, number(tokens[0])?)
+    },
+    _ => {
+      let (first, second, third) = (number(tokens[0])?, number(tokens[1])?, number(tokens[2])?);
+      if tokens[0].len() == 4 {
+        (first, second, third)
+      } else if first > 12 {
+        (third, second, first)
+      } else {
+        (third, first, second)
+      }
+    },
+  };
+  let days = match month {
+    1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+    4 | 6 | 9 | 11 => 30,
+    2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+    2 => 28,
+    _ => return None,
+  };
+  if !(1000..=9999).contains(&year) || day == 0 || day > days {
+    return None;
+  }
+  Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+impl OllamaAIPlugin {
+  /// Reads the values of `fields` from `text`, e.g. to turn a text into a database row.
+  ///
+  /// The model is asked for a JSON object following [extraction_schema], whose values are then
+  /// converted to the field types: numbers written as strings, dates in any common format and
+  /// select options in any case. Fields the model leaves out or whose value can't be converted
+  /// are null. Replies without a JSON object fail with [PluginError::InvalidResponse].
+  pub async fn extract_fields(
+    &self,
+    text: &str,
+    fields: Vec<FieldSpec>,
+    metadata: Option<Value>,
+  ) -> Result<HashMap<String, Value>, PluginError> {
+    if fields.is_empty() {
+      return Ok(HashMap::new());
+    }
+    let result = self
+      .complete_text_v2_collect(
+        &extraction_prompt(text, &fields),
+        CompleteTextType::Custom as u8,
+        Some(extraction_schema(&fields)),
+        metadata,
+        StreamOptions::default(),
+      )
+      .await?;
+    match parse_extraction_reply(&result.answer) {
+      Some(reply) => Ok(coerce_fields(&reply, &fields)),
+      None => {
+        warn!(
+          "[AI Plugin] no JSON object in extraction reply: {}",
+          result.answer
+        );
+        Err(PluginError::InvalidResponse)
+      },
+    }
+  }
+}
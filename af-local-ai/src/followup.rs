@@ -0,0 +1,146 @@
+use crate::ai_ops::CompleteTextType;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::outbound_filter::RequestKind;
+use crate::scheduler::Priority;
+use crate::summary::prepend;
+use crate::trace::start_trace;
+use af_ai_protocol::capability::Capability;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::{PluginError, RemoteError};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt::Write;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{instrument, trace};
+
+/// Characters of a follow-up prompt, beyond which the previous output is shortened.
+pub const MAX_FOLLOWUP_PROMPT_CHARS: usize = 12_000;
+/// Characters of the previous output kept however long the original text is.
+const MIN_PREVIOUS_OUTPUT_CHARS: usize = 2_000;
+const TRUNCATION_MARKER: &str = "\n[...]\n";
+
+/// Shortens `previous_output` so a follow-up of `original_text` stays within
+/// [MAX_FOLLOWUP_PROMPT_CHARS]. Its head and tail are kept, as they usually carry the structure
+/// the instruction refers to.
+pub fn fit_previous_output<'a>(
+  original_text: &str,
+  previous_output: &'a str,
+  instruction: &str,
+) -> Cow<'a, str> {
+  let used = original_text.chars().count() + instruction.chars().count();
+  let budget = MAX_FOLLOWUP_PROMPT_CHARS
+    .saturating_sub(used)
+    .max(MIN_PREVIOUS_OUTPUT_CHARS);
+  truncate_middle(previous_output, budget)
+}
+
+/// Removes the middle of `text` so it is at most `max_chars` characters long.
+pub fn truncate_middle(text: &str, max_chars: usize) -> Cow<'_, str> {
+  let len = text.chars().count();
+  if len <= max_chars {
+    return Cow::Borrowed(text);
+  }
+  let kept = max_chars.saturating_sub(TRUNCATION_MARKER.len());
+  let head = kept / 2;
+  let tail = kept - head;
+  let head_end = text
+    .char_indices()
+    .nth(head)
+    .map(|(index, _)| index)
+    .unwrap_or(text.len());
+  let tail_start = text
+    .char_indices()
+    .nth(len - tail)
+    .map(|(index, _)| index)
+    .unwrap_or(text.len());
+  Cow::Owned(format!(
+    "{}{}{}",
+    &text[..head_end],
+    TRUNCATION_MARKER,
+    &text[tail_start..]
+  ))
+}
+
+/// Builds the prompt of a follow-up for plugins without a `complete_text_followup` method, sent
+/// as a custom completion.
+pub fn followup_prompt(original_text: &str, previous_output: &str, instruction: &str) -> String {
+  let mut prompt = String::new();
+  let _ = writeln!(
+    prompt,
+    "You rewrote the original text below. Rewrite your previous output following the new \
+    instruction. Reply with the rewritten text only."
+  );
+  for (section, text) in [
+    ("ORIGINAL TEXT", original_text),
+    ("PREVIOUS OUTPUT", previous_output),
+    ("NEW INSTRUCTION", instruction),
+  ] {
+    let _ = writeln!(prompt);
+    let _ = writeln!(prompt, "### {}", section);
+    let _ = writeln!(prompt, "{}", text.trim());
+  }
+  prompt
+}
+
+impl OllamaAIPlugin {
+  /// Reworks `previous_output`, a completion of `original_text`, following `instruction`, e.g.
+  /// "make it more formal". The stream has the frames of [OllamaAIPlugin::complete_text_v2].
+  ///
+  /// Plugins without a `complete_text_followup` method get the three texts in a custom
+  /// completion instead. A long `previous_output` is shortened from the middle, see
+  /// [fit_previous_output].
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn complete_text_followup(
+    &self,
+    previous_output: &str,
+    instruction: &str,
+    original_text: &str,
+    metadata: Option<serde_json::Value>,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    let trace_id = start_trace();
+    let original_text = self.filter_outbound(original_text, RequestKind::Completion)?;
+    let previous_output = self.filter_outbound(previous_output, RequestKind::Completion)?;
+    let instruction = self.filter_outbound(instruction, RequestKind::Completion)?;
+    let previous_output = fit_previous_output(&original_text, &previous_output, &instruction);
+    trace!(
+      "[AI Plugin] complete text followup: {}, metadata: {:?}",
+      instruction,
+      metadata
+    );
+    self.wait_until_plugin_ready().await?;
+    let permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.operation(plugin).with_trace_id(&trace_id);
+    let mut stream = operation
+      .complete_text_followup(
+        &original_text,
+        &previous_output,
+        &instruction,
+        metadata.clone(),
+        StreamOptions::default(),
+      )
+      .await?;
+    let stream = match stream.next().await {
+      Some(Err(err))
+        if err
+          .remote_error()
+          .is_some_and(RemoteError::is_method_not_found) =>
+      {
+        let metadata = metadata.filter(|_| self.has_capability(Capability::CompletionMetadata));
+        operation
+          .complete_text_v2(
+            &followup_prompt(&original_text, &previous_output, &instruction),
+            CompleteTextType::Custom as u8,
+            None,
+            metadata,
+            StreamOptions::default(),
+          )
+          .await?
+      },
+      Some(first) => prepend(first, stream),
+      None => stream,
+    };
+    Ok(permit.hold_until_done(stream))
+  }
+}
@@ -0,0 +1,326 @@
+use crate::citation::SOURCE_ID_KEY;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::usage::{millis_since_epoch, TimeRange};
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+/// Size past which the audit log is rotated, see [OllamaPluginConfig::with_index_audit_log](crate::ollama_plugin::OllamaPluginConfig::with_index_audit_log).
+pub const DEFAULT_INDEX_AUDIT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+  EmbedText,
+  EmbedFile,
+  Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+  Success,
+  Failed,
+}
+
+/// A line of the index audit log. The indexed content is never written, only its hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+  /// Milliseconds since the Unix epoch.
+  pub timestamp: u64,
+  pub operation: AuditOperation,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub object_id: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_id: Option<String>,
+  /// Bytes of the indexed text or file, zero for deletions.
+  pub content_length: u64,
+  /// Hex encoded SHA-256 of the indexed text or file, `None` for deletions and for files that
+  /// could no longer be read.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub content_sha256: Option<String>,
+  pub status: AuditStatus,
+}
+
+/// What an audited operation sent to the plugin, hashed by the writer.
+pub(crate) enum AuditContent {
+  Text(String),
+  File(PathBuf),
+  None,
+}
+
+enum AuditMessage {
+  Record(AuditRecord, AuditContent),
+  Read(
+    TimeRange,
+    tokio::sync::oneshot::Sender<Result<Vec<AuditRecord>, PluginError>>,
+  ),
+}
+
+struct AuditWriter {
+  path: PathBuf,
+  max_bytes: u64,
+  tx: Sender<AuditMessage>,
+}
+
+/// Appends the operations that add or remove content from the vector store to a JSON lines file,
+/// once enabled with [OllamaPluginConfig::with_index_audit_log](crate::ollama_plugin::OllamaPluginConfig::with_index_audit_log).
+///
+/// Records are written by a dedicated thread. Unlike usage records, they are never dropped: the
+/// queue is unbounded, so auditing never blocks an operation.
+#[derive(Default)]
+pub(crate) struct IndexAudit {
+  writer: parking_lot::RwLock<Option<AuditWriter>>,
+}
+
+impl IndexAudit {
+  /// Writes the next records to `path`, or stops auditing when `None`. The records queued for a
+  /// previous file are still written to it.
+  pub(crate) fn configure(&self, path: Option<PathBuf>, max_bytes: u64) -> Result<(), PluginError> {
+    let mut writer = self.writer.write();
+    let path = match path {
+      Some(path) => path,
+      None => {
+        writer.take();
+        return Ok(());
+      },
+    };
+    if let Some(current) = writer.as_ref() {
+      if current.path == path && current.max_bytes == max_bytes {
+        return Ok(());
+      }
+    }
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let (tx, rx) = mpsc::channel();
+    let thread_path = path.clone();
+    thread::Builder::new()
+      .name("index audit writer".to_string())
+      .spawn(move || run(thread_path, max_bytes, rx))?;
+    info!("[AI Plugin] auditing indexed content to {:?}", path);
+    *writer = Some(AuditWriter {
+      path,
+      max_bytes,
+      tx,
+    });
+    Ok(())
+  }
+
+  /// Starts auditing an operation on the documents described by `metadata`, or returns `None`
+  /// when auditing is off. `content` is only called when the operation is audited.
+  pub(crate) fn start(
+    &self,
+    operation: AuditOperation,
+    metadata: &HashMap<String, Value>,
+    content: impl FnOnce() -> AuditContent,
+  ) -> Option<PendingAudit> {
+    let tx = self.writer.read().as_ref()?.tx.clone();
+    let get = |key: &str| metadata.get(key).and_then(Value::as_str).map(String::from);
+    Some(PendingAudit {
+      tx,
+      operation,
+      object_id: get("object_id"),
+      source_id: get(SOURCE_ID_KEY),
+      content: content(),
+      started_at: SystemTime::now(),
+    })
+  }
+
+  /// Reads back the records of `range`, including every one queued before this call.
+  pub(crate) async fn read(&self, range: TimeRange) -> Result<Vec<AuditRecord>, PluginError> {
+    let tx = self
+      .writer
+      .read()
+      .as_ref()
+      .map(|writer| writer.tx.clone())
+      .ok_or_else(|| PluginError::Internal(anyhow::anyhow!("index audit log is not enabled")))?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    tx.send(AuditMessage::Read(range, reply_tx))
+      .map_err(|_| writer_stopped())?;
+    reply_rx.await.map_err(|_| writer_stopped())?
+  }
+}
+
+/// An audited operation in progress, recorded by [finish_audit].
+pub(crate) struct PendingAudit {
+  tx: Sender<AuditMessage>,
+  operation: AuditOperation,
+  object_id: Option<String>,
+  source_id: Option<String>,
+  content: AuditContent,
+  started_at: SystemTime,
+}
+
+/// Records the operation of `audit` as done with `result`.
+pub(crate) fn finish_audit<T>(audit: Option<PendingAudit>, result: &Result<T, PluginError>) {
+  if let Some(audit) = audit {
+    let record = AuditRecord {
+      timestamp: millis_since_epoch(audit.started_at),
+      operation: audit.operation,
+      object_id: audit.object_id,
+      source_id: audit.source_id,
+      content_length: 0,
+      content_sha256: None,
+      status: match result {
+        Ok(_) => AuditStatus::Success,
+        Err(_) => AuditStatus::Failed,
+      },
+    };
+    if audit
+      .tx
+      .send(AuditMessage::Record(record, audit.content))
+      .is_err()
+    {
+      error!("[AI Plugin] index audit writer stopped, dropping record");
+    }
+  }
+}
+
+fn run(path: PathBuf, max_bytes: u64, rx: Receiver<AuditMessage>) {
+  while let Ok(message) = rx.recv() {
+    match message {
+      AuditMessage::Record(mut record, content) => {
+        let (length, sha256) = hash_content(&content);
+        record.content_length = length;
+        record.content_sha256 = sha256;
+        if let Err(err) = append(&path, max_bytes, &record) {
+          error!("[AI Plugin] failed to write index audit record: {}", err);
+        }
+      },
+      AuditMessage::Read(range, reply) => {
+        let _ = reply.send(read_records(&path, range).map_err(PluginError::from));
+      },
+    }
+  }
+}
+
+fn hash_content(content: &AuditContent) -> (u64, Option<String>) {
+  match content {
+    AuditContent::Text(text) => (
+      text.len() as u64,
+      Some(format!("{:x}", Sha256::digest(text.as_bytes()))),
+    ),
+    AuditContent::File(path) => {
+      let mut hasher = Sha256::new();
+      match File::open(path).and_then(|mut file| std::io::copy(&mut file, &mut hasher)) {
+        Ok(length) => (length, Some(format!("{:x}", hasher.finalize()))),
+        Err(err) => {
+          warn!(
+            "[AI Plugin] failed to hash audited file {:?}: {}",
+            path, err
+          );
+          (0, None)
+        },
+      }
+    },
+    AuditContent::None => (0, None),
+  }
+}
+
+/// Appends `record` to `path`, first moving `path` to the next rotated file when the record
+/// would take it past `max_bytes`. Rotated files are kept.
+fn append(path: &Path, max_bytes: u64, record: &AuditRecord) -> std::io::Result<()> {
+  let mut line = serde_json::to_vec(record)?;
+  line.push(b'\n');
+  let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+  if size > 0 && size + line.len() as u64 > max_bytes {
+    let next = rotated_files(path)?
+      .last()
+      .map_or(1, |(index, _)| index + 1);
+    std::fs::rename(path, rotated_path(path, next))?;
+  }
+  let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+  file.write_all(&line)?;
+  file.sync_data()
+}
+
+fn read_records(path: &Path, range: TimeRange) -> std::io::Result<Vec<AuditRecord>> {
+  let start = millis_since_epoch(range.start);
+  // Rounded up, so the records of the last millisecond of the range are included.
+  let end = millis_since_epoch(range.end + Duration::from_millis(1));
+  let mut files = rotated_files(path)?
+    .into_iter()
+    .map(|(_, path)| path)
+    .collect::<Vec<_>>();
+  files.push(path.to_path_buf());
+
+  let mut records = vec![];
+  for file in files {
+    let file = match File::open(&file) {
+      Ok(file) => file,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+      Err(err) => return Err(err),
+    };
+    for line in BufReader::new(file).lines() {
+      let line = line?;
+      match serde_json::from_str::<AuditRecord>(&line) {
+        Ok(record) if record.timestamp >= start && record.timestamp < end => records.push(record),
+        Ok(_) => {},
+        Err(err) => warn!(
+          "[AI Plugin] skipping unreadable index audit record: {}",
+          err
+        ),
+      }
+    }
+  }
+  Ok(records)
+}
+
+/// The rotated files of `path`, `<name>.1`, `<name>.2` and so on, oldest first.
+fn rotated_files(path: &Path) -> std::io::Result<Vec<(u32, PathBuf)>> {
+  let (dir, name) = match (
+    path.parent(),
+    path.file_name().and_then(|name| name.to_str()),
+  ) {
+    (Some(dir), Some(name)) => (dir, name),
+    _ => return Ok(vec![]),
+  };
+  let dir = if dir.as_os_str().is_empty() {
+    Path::new(".")
+  } else {
+    dir
+  };
+  let prefix = format!("{}.", name);
+  let mut files = vec![];
+  for entry in std::fs::read_dir(dir)? {
+    let entry = entry?;
+    let index = entry
+      .file_name()
+      .to_str()
+      .and_then(|file_name| file_name.strip_prefix(&prefix))
+      .and_then(|index| index.parse::<u32>().ok());
+    if let Some(index) = index {
+      files.push((index, entry.path()));
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+  let mut rotated = path.as_os_str().to_os_string();
+  rotated.push(format!(".{}", index));
+  PathBuf::from(rotated)
+}
+
+fn writer_stopped() -> PluginError {
+  PluginError::Internal(anyhow::anyhow!("index audit writer stopped"))
+}
+
+impl OllamaAIPlugin {
+  /// Reads back the records of the index audit log within `range`, oldest first, rotated files
+  /// included. Requires [OllamaPluginConfig::with_index_audit_log](crate::ollama_plugin::OllamaPluginConfig::with_index_audit_log).
+  pub async fn read_index_audit(&self, range: TimeRange) -> Result<Vec<AuditRecord>, PluginError> {
+    self.index_audit.read(range).await
+  }
+}
@@ -0,0 +1,136 @@
+use af_plugin::error::PluginError;
+use std::sync::Arc;
+use tokio::sync::{watch, OnceCell};
+use tokio_stream::wrappers::WatchStream;
+
+/// Progress of an initialization of the plugin, see [InitHandle].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitPhase {
+  /// No initialization was started since the plugin was last destroyed.
+  NotStarted,
+  /// The plugin process is being started.
+  Spawning,
+  /// The plugin is loading its models.
+  Initializing,
+  /// The plugin is started, and its `system_info` is being checked.
+  WaitingReady,
+  Succeeded,
+  /// The message of the error the initialization failed with.
+  Failed(String),
+}
+
+impl InitPhase {
+  pub fn is_in_progress(&self) -> bool {
+    matches!(
+      self,
+      InitPhase::Spawning | InitPhase::Initializing | InitPhase::WaitingReady
+    )
+  }
+}
+
+type InitOutcome = Result<(), String>;
+
+/// One initialization of the plugin, shared by the caller running it and the callers waiting for
+/// it.
+#[derive(Clone)]
+pub(crate) struct InitAttempt {
+  phase: Arc<watch::Sender<InitPhase>>,
+  outcome: Arc<OnceCell<InitOutcome>>,
+}
+
+impl InitAttempt {
+  pub(crate) fn start() -> Self {
+    Self::with_phase(InitPhase::Spawning)
+  }
+
+  pub(crate) fn not_started() -> Self {
+    Self::with_phase(InitPhase::NotStarted)
+  }
+
+  fn with_phase(phase: InitPhase) -> Self {
+    Self {
+      phase: Arc::new(watch::channel(phase).0),
+      outcome: Default::default(),
+    }
+  }
+
+  pub(crate) fn is_finished(&self) -> bool {
+    self.outcome.initialized()
+  }
+
+  pub(crate) fn set_phase(&self, phase: InitPhase) {
+    if !self.is_finished() {
+      self.phase.send_replace(phase);
+    }
+  }
+
+  /// Records the outcome for every waiter. Only the first outcome is kept.
+  pub(crate) fn finish(&self, result: &Result<(), PluginError>) {
+    let outcome = result.as_ref().map(|_| ()).map_err(|err| err.to_string());
+    if self.outcome.set(outcome.clone()).is_ok() {
+      self.phase.send_replace(match outcome {
+        Ok(()) => InitPhase::Succeeded,
+        Err(message) => InitPhase::Failed(message),
+      });
+    }
+  }
+
+  /// Fails the attempt when the returned guard is dropped before [InitAttempt::finish], e.g.
+  /// because the future running the initialization was dropped.
+  pub(crate) fn fail_on_drop(&self) -> impl Drop {
+    struct FailOnDrop(InitAttempt);
+    impl Drop for FailOnDrop {
+      fn drop(&mut self) {
+        self.0.finish(&Err(PluginError::Internal(anyhow::anyhow!(
+          "initialization was cancelled"
+        ))));
+      }
+    }
+    FailOnDrop(self.clone())
+  }
+
+  pub(crate) fn handle(&self) -> InitHandle {
+    InitHandle {
+      phase: self.phase.subscribe(),
+      outcome: self.outcome.clone(),
+    }
+  }
+}
+
+/// Follows an initialization of the plugin, see
+/// [OllamaAIPlugin::init_handle](crate::ollama_plugin::OllamaAIPlugin::init_handle).
+#[derive(Clone)]
+pub struct InitHandle {
+  phase: watch::Receiver<InitPhase>,
+  outcome: Arc<OnceCell<InitOutcome>>,
+}
+
+impl InitHandle {
+  pub fn phase(&self) -> InitPhase {
+    self.phase.borrow().clone()
+  }
+
+  /// The current phase, then every change until the initialization finishes.
+  pub fn phases(&self) -> WatchStream<InitPhase> {
+    WatchStream::new(self.phase.clone())
+  }
+
+  /// Waits for the initialization to finish. Failures are reported as
+  /// [PluginError::InitFailed] with the message of the error, and
+  /// [PluginError::PluginNotConnected] when no initialization was started.
+  pub async fn wait(&self) -> Result<(), PluginError> {
+    let mut phase = self.phase.clone();
+    if phase
+      .wait_for(|phase| !phase.is_in_progress())
+      .await
+      .is_err()
+    {
+      return Err(PluginError::PluginNotConnected);
+    }
+    match self.outcome.get() {
+      Some(Ok(())) => Ok(()),
+      Some(Err(message)) => Err(PluginError::InitFailed(message.clone())),
+      None => Err(PluginError::PluginNotConnected),
+    }
+  }
+}
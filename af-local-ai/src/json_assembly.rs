@@ -0,0 +1,277 @@
+use crate::ai_ops::STREAM_ANSWER_KEY;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Key of the frames holding the fields of the answer completed so far, when
+/// [StreamOptions::assemble_json](af_plugin::core::stream::StreamOptions::assemble_json) is set.
+pub const STREAM_PARTIAL_JSON_KEY: &str = "partial_json";
+/// Key of the last frame of an assembled answer, holding the whole object.
+pub const STREAM_FINAL_JSON_KEY: &str = "final_json";
+
+/// A frame of an answer streamed with
+/// [StreamOptions::assemble_json](af_plugin::core::stream::StreamOptions::assemble_json).
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonFrame {
+  /// The object with the top-level fields completed so far, sent each time a field completes.
+  PartialJson(Value),
+  /// The whole object, validated against the format schema.
+  FinalJson(Value),
+}
+
+impl JsonFrame {
+  /// Reads a frame of the stream, `None` for the frames that aren't part of the JSON answer,
+  /// such as metadata.
+  pub fn from_frame(frame: &Value) -> Option<Self> {
+    if let Some(value) = frame.get(STREAM_PARTIAL_JSON_KEY) {
+      return Some(JsonFrame::PartialJson(value.clone()));
+    }
+    frame
+      .get(STREAM_FINAL_JSON_KEY)
+      .map(|value| JsonFrame::FinalJson(value.clone()))
+  }
+}
+
+/// Assembles a JSON object from the fragments of an answer. Text before the object, such as the
+/// opening of a code block, and text after it are ignored.
+///
+/// A top-level field is complete once the `,` or `}` following its value arrives. The text up to
+/// there, closed with a `}`, is then a whole object holding only the complete fields.
+#[derive(Debug, Default)]
+pub struct JsonAssembler {
+  text: String,
+  scanned: usize,
+  /// Byte offset of the `{` opening the object.
+  start: Option<usize>,
+  /// Byte offset just past the `}` closing the object.
+  end: Option<usize>,
+  depth: usize,
+  in_string: bool,
+  escaped: bool,
+  /// Whether a field has started since the last complete one.
+  field_started: bool,
+  /// Byte offset of the `,` or `}` after the last complete field.
+  fields_end: Option<usize>,
+  fields: usize,
+}
+
+impl JsonAssembler {
+  /// Appends `fragment` to the answer, returning the object with the fields completed so far
+  /// when `fragment` completes at least one field.
+  pub fn push(&mut self, fragment: &str) -> Option<Value> {
+    self.text.push_str(fragment);
+    let fields = self.fields;
+    self.scan();
+    if self.fields == fields {
+      return None;
+    }
+    let (start, fields_end) = (self.start?, self.fields_end?);
+    let partial = format!("{}}}", &self.text[start..fields_end]);
+    serde_json::from_str(&partial).ok()
+  }
+
+  /// The text of the answer so far.
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Parses the whole object and validates it against `schema`.
+  pub fn finish(self, schema: &Value) -> Result<Value, PluginError> {
+    let object = match (self.start, self.end) {
+      (Some(start), Some(end)) => {
+        serde_json::from_str::<Value>(&self.text[start..end]).map_err(|err| err.to_string())
+      },
+      (Some(_), None) => Err("the JSON object is not closed".to_string()),
+      (None, _) => Err("the answer has no JSON object".to_string()),
+    };
+    match object.and_then(|object| validate_schema(&object, schema).map(|_| object)) {
+      Ok(object) => Ok(object),
+      Err(reason) => Err(PluginError::SchemaValidationFailed {
+        reason,
+        raw: self.text,
+      }),
+    }
+  }
+
+  fn scan(&mut self) {
+    for index in self.scanned..self.text.len() {
+      let byte = self.text.as_bytes()[index];
+      if self.end.is_some() {
+        break;
+      }
+      if self.start.is_none() {
+        if byte == b'{' {
+          self.start = Some(index);
+          self.depth = 1;
+        }
+        continue;
+      }
+      if self.in_string {
+        match byte {
+          _ if self.escaped => self.escaped = false,
+          b'\\' => self.escaped = true,
+          b'"' => self.in_string = false,
+          _ => {},
+        }
+        continue;
+      }
+      match byte {
+        b'"' => {
+          self.in_string = true;
+          self.field_started |= self.depth == 1;
+        },
+        b'{' | b'[' => self.depth += 1,
+        b'}' | b']' => {
+          self.depth = self.depth.saturating_sub(1);
+          if self.depth == 0 {
+            self.end = Some(index + 1);
+            self.complete_field(index);
+          }
+        },
+        b',' if self.depth == 1 => self.complete_field(index),
+        _ if byte.is_ascii_whitespace() => {},
+        _ => self.field_started |= self.depth == 1,
+      }
+    }
+    self.scanned = self.text.len();
+  }
+
+  fn complete_field(&mut self, index: usize) {
+    if self.field_started {
+      self.field_started = false;
+      self.fields += 1;
+      self.fields_end = Some(index);
+    }
+  }
+}
+
+/// Checks `value` against the keywords of JSON schema that format schemas use: `type`, `enum`,
+/// `const`, `properties`, `required`, `additionalProperties` and `items`. Other keywords are
+/// ignored. The error names the path of the first value that doesn't match.
+pub fn validate_schema(value: &Value, schema: &Value) -> Result<(), String> {
+  validate_at("$", value, schema)
+}
+
+fn validate_at(path: &str, value: &Value, schema: &Value) -> Result<(), String> {
+  let schema = match schema {
+    Value::Object(schema) => schema,
+    // `true` and a missing schema accept anything, `false` nothing.
+    Value::Bool(false) => return Err(format!("{}: no value is allowed", path)),
+    _ => return Ok(()),
+  };
+  if let Some(expected) = schema.get("type") {
+    let types = match expected {
+      Value::Array(types) => types.iter().filter_map(|t| t.as_str()).collect(),
+      Value::String(expected) => vec![expected.as_str()],
+      _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|expected| has_type(value, expected)) {
+      return Err(format!(
+        "{}: expected {}, found {}",
+        path,
+        types.join(" or "),
+        type_name(value)
+      ));
+    }
+  }
+  if let Some(Value::Array(options)) = schema.get("enum") {
+    if !options.contains(value) {
+      return Err(format!(
+        "{}: {} is not one of {}",
+        path,
+        value,
+        json!(options)
+      ));
+    }
+  }
+  if let Some(expected) = schema.get("const") {
+    if expected != value {
+      return Err(format!("{}: expected {}, found {}", path, expected, value));
+    }
+  }
+  if let Value::Object(object) = value {
+    if let Some(Value::Array(required)) = schema.get("required") {
+      for name in required.iter().filter_map(|name| name.as_str()) {
+        if !object.contains_key(name) {
+          return Err(format!("{}: missing required field {:?}", path, name));
+        }
+      }
+    }
+    let properties = schema.get("properties").and_then(|p| p.as_object());
+    for (name, field) in object {
+      let path = format!("{}.{}", path, name);
+      match properties.and_then(|properties| properties.get(name)) {
+        Some(property) => validate_at(&path, field, property)?,
+        None => match schema.get("additionalProperties") {
+          Some(Value::Bool(false)) => return Err(format!("{}: field is not allowed", path)),
+          Some(additional) => validate_at(&path, field, additional)?,
+          None => {},
+        },
+      }
+    }
+  }
+  if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+    for (index, item) in items.iter().enumerate() {
+      validate_at(&format!("{}[{}]", path, index), item, item_schema)?;
+    }
+  }
+  Ok(())
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+  match expected {
+    "integer" => value.is_i64() || value.is_u64(),
+    "number" => value.is_number(),
+    expected => type_name(value) == expected,
+  }
+}
+
+fn type_name(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "null",
+    Value::Bool(_) => "boolean",
+    Value::Number(_) => "number",
+    Value::String(_) => "string",
+    Value::Array(_) => "array",
+    Value::Object(_) => "object",
+  }
+}
+
+/// Turns the answer text of `stream` into [JsonFrame]s: a `{"partial_json": ...}` frame each time
+/// a top-level field completes, then a `{"final_json": ...}` frame, or a
+/// [PluginError::SchemaValidationFailed] error when the answer doesn't follow `schema`. Frames
+/// without answer text, such as metadata, are forwarded as they are.
+pub(crate) fn assembled_json_stream(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  schema: Value,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut assembler = JsonAssembler::default();
+    while let Some(frame) = stream.next().await {
+      let frame = match frame {
+        Ok(frame) => frame,
+        Err(err) => {
+          let _ = tx.send(Err(err)).await;
+          return;
+        },
+      };
+      let frame = match frame.get(STREAM_ANSWER_KEY).and_then(|text| text.as_str()) {
+        Some(text) => match assembler.push(text) {
+          Some(partial) => json!({ STREAM_PARTIAL_JSON_KEY: partial }),
+          None => continue,
+        },
+        None => frame,
+      };
+      if tx.send(Ok(frame)).await.is_err() {
+        return;
+      }
+    }
+    let frame = assembler
+      .finish(&schema)
+      .map(|object| json!({ STREAM_FINAL_JSON_KEY: object }));
+    let _ = tx.send(frame).await;
+  });
+  ReceiverStream::new(rx)
+}
@@ -0,0 +1,84 @@
+use crate::ai_ops::{AIPluginOperation, STREAM_METADATA_KEY};
+use af_plugin::core::plugin::Plugin;
+use af_plugin::core::stream_error::StreamErrorKind;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+/// Time a stream may go without frames before the plugin is pinged, see
+/// [OllamaPluginConfig::with_keep_alive_interval](crate::ollama_plugin::OllamaPluginConfig::with_keep_alive_interval).
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Pings in a row that may fail before the plugin is taken for dead.
+const MAX_MISSED_PINGS: u32 = 2;
+
+/// Forwards an answer stream, pinging the plugin each time the stream goes `interval` without a
+/// frame, e.g. while the model reasons or evaluates a long prompt. An answered ping sends a
+/// `{"keep_alive": true}` metadata frame. When [MAX_MISSED_PINGS] pings in a row fail or go
+/// unanswered for `interval`, the stream ends with a [StreamErrorKind::PluginDied] error.
+///
+/// Any frame of the stream, including the keep-alive frames of the plugin, restarts the wait.
+pub(crate) fn keep_alive_stream(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  interval: Duration,
+  plugin: Weak<Plugin>,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let operation = AIPluginOperation::new(plugin);
+    let mut missed = 0;
+    loop {
+      let frame = match timeout(interval, stream.next()).await {
+        Ok(frame) => frame,
+        Err(_) => {
+          let ping = timeout(interval, operation.ping());
+          tokio::select! {
+            frame = stream.next() => frame,
+            pong = ping => {
+              let err = match pong {
+                Ok(Ok(())) => {
+                  missed = 0;
+                  let frame = json!({ STREAM_METADATA_KEY: { "keep_alive": true } });
+                  if tx.send(Ok(frame)).await.is_err() {
+                    return;
+                  }
+                  continue;
+                },
+                Ok(Err(err)) => err,
+                Err(_) => PluginError::RequestTimeout(interval),
+              };
+              missed += 1;
+              warn!(
+                "[AI Plugin] stream silent for {:?}, ping {} failed: {}",
+                interval, missed, err
+              );
+              if missed >= MAX_MISSED_PINGS {
+                let err = PluginError::Stream {
+                  kind: StreamErrorKind::PluginDied,
+                  source: Box::new(err),
+                };
+                let _ = tx.send(Err(err)).await;
+                return;
+              }
+              continue;
+            },
+          }
+        },
+      };
+      match frame {
+        Some(frame) => {
+          missed = 0;
+          if tx.send(frame).await.is_err() {
+            return;
+          }
+        },
+        None => return,
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
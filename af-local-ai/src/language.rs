@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// Texts shorter than this many characters are not detected, the result is too unreliable.
+pub const MIN_DETECTION_LENGTH: usize = 20;
+
+/// A detected language.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LanguageTag {
+  /// ISO 639-3 code, e.g. `fra`.
+  pub code: String,
+  /// English name of the language, e.g. `French`. This is what gets sent to the plugin.
+  pub name: String,
+}
+
+/// Detects the language of `text`.
+///
+/// Returns `None` for short texts, when the detection is not reliable, or when the
+/// `language-detection` feature is disabled.
+#[cfg(feature = "language-detection")]
+pub fn detect_language(text: &str) -> Option<LanguageTag> {
+  if text.trim().chars().count() < MIN_DETECTION_LENGTH {
+    return None;
+  }
+  let info = whatlang::detect(text)?;
+  if !info.is_reliable() {
+    return None;
+  }
+  Some(LanguageTag {
+    code: info.lang().code().to_string(),
+    name: info.lang().eng_name().to_string(),
+  })
+}
+
+#[cfg(not(feature = "language-detection"))]
+pub fn detect_language(_text: &str) -> Option<LanguageTag> {
+  None
+}
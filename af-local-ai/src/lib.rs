@@ -1,5 +1,63 @@
 pub mod ai_ops;
+pub mod attachment;
+pub mod auth;
+pub mod blocking;
+pub mod chat_budget;
+pub mod chat_list;
+pub mod chunking;
+pub mod citation;
+#[cfg(feature = "compat")]
+pub mod compat;
+pub mod database_query;
+pub mod diagnostics;
+pub mod diff;
+pub mod duplicate;
+pub mod embedding_index;
+pub mod embedding_manifest;
 pub mod embedding_ops;
 pub mod embedding_plugin;
+pub mod events;
+pub mod extraction;
+pub mod followup;
+pub mod index_audit;
+pub mod init;
+pub mod json_assembly;
+pub mod keep_alive;
+pub mod language;
+pub mod local_ai;
+#[cfg(feature = "test-support")]
+pub mod mock;
+pub mod model_capabilities;
+pub mod model_routing;
 pub mod ollama_plugin;
+pub mod outbound_filter;
+pub mod pausable;
 pub mod plugin_request;
+mod plugin_slot;
+pub mod plugin_version;
+pub mod post_process;
+pub mod prepared_request;
+pub mod profile;
+pub mod prompt_template;
+pub mod rate_limit;
+mod related_question;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod response_cache;
+pub mod resume;
+pub mod retry;
+mod rolling_summary;
+pub mod scheduler;
+pub mod search;
+pub mod semantic_search;
+pub mod similarity;
+pub mod slow_request;
+pub mod store_meta;
+pub mod summary;
+pub mod trace;
+pub mod translate;
+pub mod usage;
+pub mod vector_store;
+pub mod warm_up;
+#[cfg(feature = "http")]
+pub mod web_page;
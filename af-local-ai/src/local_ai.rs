@@ -0,0 +1,156 @@
+use crate::ai_ops::{LocalAITranslateRowData, LocalAITranslateRowResponse};
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Answer and completion streams, whose frames are the JSON objects described by
+/// [STREAM_ANSWER_KEY](crate::ai_ops::STREAM_ANSWER_KEY) and its siblings.
+pub type LocalAIStream = ReceiverStream<Result<Value, PluginError>>;
+
+/// The chat, completion, embedding and database operations of a local AI, so applications can
+/// depend on `Arc<dyn LocalAIChat>` and substitute the `MockLocalAI` of the `test-support`
+/// feature in their tests. Implemented by [OllamaAIPlugin], whose methods of the same names
+/// document each operation.
+#[async_trait]
+pub trait LocalAIChat: Send + Sync {
+  async fn create_chat(&self, chat_id: &str) -> Result<(), PluginError>;
+
+  async fn close_chat(&self, chat_id: &str, purge_attachments: bool) -> Result<(), PluginError>;
+
+  async fn ask_question(&self, chat_id: &str, message: &str) -> Result<String, PluginError>;
+
+  async fn stream_question(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<Value>,
+    metadata: Value,
+  ) -> Result<LocalAIStream, PluginError>;
+
+  async fn get_related_question(&self, chat_id: &str) -> Result<Vec<String>, PluginError>;
+
+  async fn complete_text_v2(
+    &self,
+    message: &str,
+    complete_type: u8,
+    format: Option<Value>,
+    metadata: Option<Value>,
+  ) -> Result<LocalAIStream, PluginError>;
+
+  async fn generate_embedding(&self, text: &str) -> Result<Vec<Vec<f64>>, PluginError>;
+
+  async fn embed_text(
+    &self,
+    text: &str,
+    metadata: HashMap<String, Value>,
+  ) -> Result<(), PluginError>;
+
+  async fn embed_file(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    metadata: Option<HashMap<String, Value>>,
+  ) -> Result<(), PluginError>;
+
+  async fn similarity_search(
+    &self,
+    query: &str,
+    filter: HashMap<String, Value>,
+  ) -> Result<Vec<String>, PluginError>;
+
+  async fn summary_database_row(&self, row: HashMap<String, String>)
+    -> Result<String, PluginError>;
+
+  async fn translate_database_row(
+    &self,
+    row: LocalAITranslateRowData,
+  ) -> Result<LocalAITranslateRowResponse, PluginError>;
+}
+
+#[async_trait]
+impl LocalAIChat for OllamaAIPlugin {
+  async fn create_chat(&self, chat_id: &str) -> Result<(), PluginError> {
+    OllamaAIPlugin::create_chat(self, chat_id).await
+  }
+
+  async fn close_chat(&self, chat_id: &str, purge_attachments: bool) -> Result<(), PluginError> {
+    OllamaAIPlugin::close_chat(self, chat_id, purge_attachments)
+      .await
+      .map_err(|err| err.downcast().unwrap_or_else(PluginError::Internal))
+  }
+
+  async fn ask_question(&self, chat_id: &str, message: &str) -> Result<String, PluginError> {
+    OllamaAIPlugin::ask_question(self, chat_id, message).await
+  }
+
+  async fn stream_question(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<Value>,
+    metadata: Value,
+  ) -> Result<LocalAIStream, PluginError> {
+    OllamaAIPlugin::stream_question(self, chat_id, message, format, metadata).await
+  }
+
+  async fn get_related_question(&self, chat_id: &str) -> Result<Vec<String>, PluginError> {
+    OllamaAIPlugin::get_related_question(self, chat_id).await
+  }
+
+  async fn complete_text_v2(
+    &self,
+    message: &str,
+    complete_type: u8,
+    format: Option<Value>,
+    metadata: Option<Value>,
+  ) -> Result<LocalAIStream, PluginError> {
+    OllamaAIPlugin::complete_text_v2(self, message, complete_type, format, metadata).await
+  }
+
+  async fn generate_embedding(&self, text: &str) -> Result<Vec<Vec<f64>>, PluginError> {
+    OllamaAIPlugin::generate_embedding(self, text).await
+  }
+
+  async fn embed_text(
+    &self,
+    text: &str,
+    metadata: HashMap<String, Value>,
+  ) -> Result<(), PluginError> {
+    OllamaAIPlugin::embed_text(self, text, metadata).await
+  }
+
+  async fn embed_file(
+    &self,
+    chat_id: &str,
+    file_path: PathBuf,
+    metadata: Option<HashMap<String, Value>>,
+  ) -> Result<(), PluginError> {
+    OllamaAIPlugin::embed_file(self, chat_id, file_path, metadata).await
+  }
+
+  async fn similarity_search(
+    &self,
+    query: &str,
+    filter: HashMap<String, Value>,
+  ) -> Result<Vec<String>, PluginError> {
+    OllamaAIPlugin::similarity_search(self, query, filter).await
+  }
+
+  async fn summary_database_row(
+    &self,
+    row: HashMap<String, String>,
+  ) -> Result<String, PluginError> {
+    OllamaAIPlugin::summary_database_row(self, row).await
+  }
+
+  async fn translate_database_row(
+    &self,
+    row: LocalAITranslateRowData,
+  ) -> Result<LocalAITranslateRowResponse, PluginError> {
+    OllamaAIPlugin::translate_database_row(self, row).await
+  }
+}
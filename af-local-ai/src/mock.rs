@@ -0,0 +1,249 @@
+use crate::ai_ops::{LocalAITranslateRowData, LocalAITranslateRowResponse, STREAM_ANSWER_KEY};
+use crate::local_ai::{LocalAIChat, LocalAIStream};
+use af_plugin::error::PluginError;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::PathBuf;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// A [LocalAIChat] that serves canned responses without starting a plugin, for the tests of
+/// applications built on this crate. Answers and completions are served in the order they were
+/// added, each streamed as one frame per chunk.
+///
+/// Questions must be asked in chats created with [LocalAIChat::create_chat], like with the
+/// plugin. Calls are recorded, see [MockLocalAI::questions].
+#[derive(Default)]
+pub struct MockLocalAI {
+  state: Mutex<MockState>,
+}
+
+/// A question asked to a [MockLocalAI].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockQuestion {
+  pub chat_id: String,
+  pub message: String,
+}
+
+#[derive(Default)]
+struct MockState {
+  answers: VecDeque<Vec<String>>,
+  completions: VecDeque<Vec<String>>,
+  related_questions: Vec<String>,
+  embedding: Vec<f64>,
+  search_results: Vec<String>,
+  row_summary: String,
+  row_translation: LocalAITranslateRowResponse,
+  next_error: Option<PluginError>,
+  chats: BTreeSet<String>,
+  questions: Vec<MockQuestion>,
+  embedded_texts: Vec<String>,
+  embedded_files: Vec<PathBuf>,
+}
+
+impl MockLocalAI {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds the answer of the next question, streamed as `chunks`.
+  pub fn with_answer(mut self, chunks: &[&str]) -> Self {
+    self.state.get_mut().answers.push_back(to_strings(chunks));
+    self
+  }
+
+  /// Adds the answer of the next completion, streamed as `chunks`.
+  pub fn with_completion(mut self, chunks: &[&str]) -> Self {
+    self
+      .state
+      .get_mut()
+      .completions
+      .push_back(to_strings(chunks));
+    self
+  }
+
+  pub fn with_related_questions(mut self, questions: &[&str]) -> Self {
+    self.state.get_mut().related_questions = to_strings(questions);
+    self
+  }
+
+  /// The embedding returned for every text.
+  pub fn with_embedding(mut self, embedding: Vec<f64>) -> Self {
+    self.state.get_mut().embedding = embedding;
+    self
+  }
+
+  /// The results of every similarity search.
+  pub fn with_search_results(mut self, results: &[&str]) -> Self {
+    self.state.get_mut().search_results = to_strings(results);
+    self
+  }
+
+  pub fn with_row_summary(mut self, summary: &str) -> Self {
+    self.state.get_mut().row_summary = summary.to_string();
+    self
+  }
+
+  pub fn with_row_translation(mut self, translation: LocalAITranslateRowResponse) -> Self {
+    self.state.get_mut().row_translation = translation;
+    self
+  }
+
+  /// Makes the next call, whichever it is, fail with `err`.
+  pub fn fail_next(&self, err: PluginError) {
+    self.state.lock().next_error = Some(err);
+  }
+
+  /// The chats created and not closed yet, sorted.
+  pub fn chats(&self) -> Vec<String> {
+    self.state.lock().chats.iter().cloned().collect()
+  }
+
+  /// The questions asked, streamed or not, oldest first.
+  pub fn questions(&self) -> Vec<MockQuestion> {
+    self.state.lock().questions.clone()
+  }
+
+  pub fn embedded_texts(&self) -> Vec<String> {
+    self.state.lock().embedded_texts.clone()
+  }
+
+  pub fn embedded_files(&self) -> Vec<PathBuf> {
+    self.state.lock().embedded_files.clone()
+  }
+
+  /// The state, unless the call must fail.
+  fn begin(&self) -> Result<parking_lot::MutexGuard<'_, MockState>, PluginError> {
+    let mut state = self.state.lock();
+    match state.next_error.take() {
+      Some(err) => Err(err),
+      None => Ok(state),
+    }
+  }
+
+  /// Records the question and returns its answer.
+  fn answer(&self, chat_id: &str, message: &str) -> Result<Vec<String>, PluginError> {
+    let mut state = self.begin()?;
+    if !state.chats.contains(chat_id) {
+      return Err(PluginError::Internal(anyhow!("chat {} not found", chat_id)));
+    }
+    state.questions.push(MockQuestion {
+      chat_id: chat_id.to_string(),
+      message: message.to_string(),
+    });
+    state
+      .answers
+      .pop_front()
+      .ok_or_else(|| PluginError::Internal(anyhow!("no answer left for {:?}", message)))
+  }
+}
+
+#[async_trait]
+impl LocalAIChat for MockLocalAI {
+  async fn create_chat(&self, chat_id: &str) -> Result<(), PluginError> {
+    self.begin()?.chats.insert(chat_id.to_string());
+    Ok(())
+  }
+
+  async fn close_chat(&self, chat_id: &str, _purge_attachments: bool) -> Result<(), PluginError> {
+    self.begin()?.chats.remove(chat_id);
+    Ok(())
+  }
+
+  async fn ask_question(&self, chat_id: &str, message: &str) -> Result<String, PluginError> {
+    Ok(self.answer(chat_id, message)?.concat())
+  }
+
+  async fn stream_question(
+    &self,
+    chat_id: &str,
+    message: &str,
+    _format: Option<Value>,
+    _metadata: Value,
+  ) -> Result<LocalAIStream, PluginError> {
+    Ok(answer_stream(self.answer(chat_id, message)?))
+  }
+
+  async fn get_related_question(&self, chat_id: &str) -> Result<Vec<String>, PluginError> {
+    let state = self.begin()?;
+    if !state.chats.contains(chat_id) {
+      return Err(PluginError::Internal(anyhow!("chat {} not found", chat_id)));
+    }
+    Ok(state.related_questions.clone())
+  }
+
+  async fn complete_text_v2(
+    &self,
+    message: &str,
+    _complete_type: u8,
+    _format: Option<Value>,
+    _metadata: Option<Value>,
+  ) -> Result<LocalAIStream, PluginError> {
+    let chunks = self
+      .begin()?
+      .completions
+      .pop_front()
+      .ok_or_else(|| PluginError::Internal(anyhow!("no completion left for {:?}", message)))?;
+    Ok(answer_stream(chunks))
+  }
+
+  async fn generate_embedding(&self, _text: &str) -> Result<Vec<Vec<f64>>, PluginError> {
+    Ok(vec![self.begin()?.embedding.clone()])
+  }
+
+  async fn embed_text(
+    &self,
+    text: &str,
+    _metadata: HashMap<String, Value>,
+  ) -> Result<(), PluginError> {
+    self.begin()?.embedded_texts.push(text.to_string());
+    Ok(())
+  }
+
+  async fn embed_file(
+    &self,
+    _chat_id: &str,
+    file_path: PathBuf,
+    _metadata: Option<HashMap<String, Value>>,
+  ) -> Result<(), PluginError> {
+    self.begin()?.embedded_files.push(file_path);
+    Ok(())
+  }
+
+  async fn similarity_search(
+    &self,
+    _query: &str,
+    _filter: HashMap<String, Value>,
+  ) -> Result<Vec<String>, PluginError> {
+    Ok(self.begin()?.search_results.clone())
+  }
+
+  async fn summary_database_row(
+    &self,
+    _row: HashMap<String, String>,
+  ) -> Result<String, PluginError> {
+    Ok(self.begin()?.row_summary.clone())
+  }
+
+  async fn translate_database_row(
+    &self,
+    _row: LocalAITranslateRowData,
+  ) -> Result<LocalAITranslateRowResponse, PluginError> {
+    Ok(self.begin()?.row_translation.clone())
+  }
+}
+
+/// A stream of one answer frame per chunk, all ready to be read.
+fn answer_stream(chunks: Vec<String>) -> LocalAIStream {
+  let (tx, rx) = tokio::sync::mpsc::channel(chunks.len().max(1));
+  for chunk in chunks {
+    let _ = tx.try_send(Ok(json!({ STREAM_ANSWER_KEY: chunk })));
+  }
+  ReceiverStream::new(rx)
+}
+
+fn to_strings(texts: &[&str]) -> Vec<String> {
+  texts.iter().map(|text| text.to_string()).collect()
+}
@@ -0,0 +1,191 @@
+use crate::ai_ops::{AIPluginOperation, CompleteTextType};
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::trace::start_trace;
+use af_ai_protocol::capability::Capability;
+pub use af_ai_protocol::types::ModelCapabilities;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio_stream::StreamExt;
+use tracing::{debug, instrument};
+
+/// Capabilities of the common model families, for plugins without `model_capabilities`. A
+/// model is matched to the longest family its name starts with, see [model_family].
+const FAMILIES: &[(&str, ModelCapabilities)] = &[
+  ("codellama", caps(false, false, false, 16_384)),
+  ("deepseek-r1", caps(false, false, false, 131_072)),
+  ("gemma2", caps(true, false, false, 8_192)),
+  ("gemma3", caps(true, false, true, 131_072)),
+  ("llama2", caps(false, false, false, 4_096)),
+  ("llama3", caps(true, false, false, 8_192)),
+  ("llama3.1", caps(true, true, false, 131_072)),
+  ("llama3.2", caps(true, true, false, 131_072)),
+  ("llama3.2-vision", caps(true, false, true, 131_072)),
+  ("llama3.3", caps(true, true, false, 131_072)),
+  ("llava", caps(false, false, true, 4_096)),
+  ("mistral", caps(true, true, false, 32_768)),
+  ("mistral-nemo", caps(true, true, false, 131_072)),
+  ("mixtral", caps(true, true, false, 32_768)),
+  ("phi3", caps(true, false, false, 4_096)),
+  ("phi4", caps(true, false, false, 16_384)),
+  ("qwen2.5", caps(true, true, false, 32_768)),
+  ("qwen3", caps(true, true, false, 40_960)),
+  ("tinyllama", caps(false, false, false, 2_048)),
+];
+
+const fn caps(json_format: bool, tools: bool, vision: bool, max_context: u32) -> ModelCapabilities {
+  ModelCapabilities {
+    supports_json_format: json_format,
+    supports_tools: tools,
+    supports_vision: vision,
+    max_context: Some(max_context),
+  }
+}
+
+/// The family of an Ollama model name, without its namespace nor its tag, e.g. `llama3.2` for
+/// `library/llama3.2:3b`.
+pub fn model_family(model: &str) -> String {
+  let name = model.rsplit('/').next().unwrap_or(model);
+  let name = name.split(':').next().unwrap_or(name);
+  name.to_lowercase()
+}
+
+/// Capabilities of the family of `model` in the table of common families, `None` for a model of
+/// another family.
+pub fn heuristic_capabilities(model: &str) -> Option<ModelCapabilities> {
+  let family = model_family(model);
+  FAMILIES
+    .iter()
+    .filter(|(name, _)| family.starts_with(name))
+    .max_by_key(|(name, _)| name.len())
+    .map(|(_, capabilities)| *capabilities)
+}
+
+/// What a model of an unknown family is assumed to support until the live probe says
+/// otherwise: a JSON format, and nothing else.
+pub(crate) fn unknown_capabilities() -> ModelCapabilities {
+  ModelCapabilities {
+    supports_json_format: true,
+    ..Default::default()
+  }
+}
+
+/// Format of the live probe, the smallest schema a model following formats can answer.
+pub(crate) fn probe_format() -> Value {
+  json!({
+    "type": "object",
+    "properties": { "ok": { "type": "boolean" } },
+    "required": ["ok"],
+  })
+}
+
+/// Capabilities of the models probed with
+/// [OllamaAIPlugin::probe_model_capabilities](crate::ollama_plugin::OllamaAIPlugin::probe_model_capabilities),
+/// kept until the plugin is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct ModelCapabilityCache {
+  models: parking_lot::RwLock<HashMap<String, ModelCapabilities>>,
+}
+
+impl ModelCapabilityCache {
+  pub(crate) fn get(&self, model: &str) -> Option<ModelCapabilities> {
+    self.models.read().get(model).copied()
+  }
+
+  pub(crate) fn insert(&self, model: &str, capabilities: ModelCapabilities) {
+    self.models.write().insert(model.to_string(), capabilities);
+  }
+}
+
+/// Whether the model of `operation` answers a tiny completion constrained to [probe_format].
+/// Only the first frame is awaited. Errors of the plugin rather than of the model fail the probe.
+async fn probe_json_format(operation: &AIPluginOperation) -> Result<bool, PluginError> {
+  let stream = operation
+    .complete_text_v2(
+      "Reply with ok.",
+      CompleteTextType::Custom as u8,
+      Some(probe_format()),
+      None,
+      StreamOptions::default(),
+    )
+    .await;
+  let first = match stream {
+    Ok(mut stream) => stream.next().await.transpose(),
+    Err(err) => Err(err),
+  };
+  match first {
+    Ok(_) => Ok(true),
+    Err(err) => match err.remote_error() {
+      Some(remote) => {
+        debug!("[AI Plugin] model failed the JSON format probe: {}", remote);
+        Ok(false)
+      },
+      None => Err(err),
+    },
+  }
+}
+
+impl OllamaAIPlugin {
+  /// What `model` supports, asked to the plugin with its `model_capabilities` method. Plugins
+  /// without the method get the capabilities of the family of the model in a table of common
+  /// families, see [heuristic_capabilities], then a tiny request constrained to a JSON format
+  /// that marks [ModelCapabilities::supports_json_format] false when it fails. Models of other
+  /// families are assumed to support a JSON format and nothing else.
+  ///
+  /// Capabilities are kept per model name until the plugin is dropped. [OllamaAIPlugin::stream_question]
+  /// and [OllamaAIPlugin::complete_text_v2] then fail with [PluginError::UnsupportedByModel]
+  /// instead of sending a format to a model known not to follow one.
+  #[instrument(level = "debug", skip(self), err)]
+  pub async fn probe_model_capabilities(
+    &self,
+    model: &str,
+  ) -> Result<ModelCapabilities, PluginError> {
+    if let Some(capabilities) = self.model_capabilities.get(model) {
+      return Ok(capabilities);
+    }
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_trace_id(&start_trace())
+      .with_model_name(Some(model.to_string()));
+    let capabilities = match operation.model_capabilities(model).await {
+      Ok(capabilities) => capabilities,
+      Err(PluginError::UnsupportedMethod { .. }) => {
+        let mut capabilities = heuristic_capabilities(model).unwrap_or_else(unknown_capabilities);
+        if capabilities.supports_json_format && self.has_capability(Capability::ResponseFormat) {
+          capabilities.supports_json_format = probe_json_format(&operation).await?;
+        }
+        capabilities
+      },
+      Err(err) => return Err(err),
+    };
+    self.model_capabilities.insert(model, capabilities);
+    Ok(capabilities)
+  }
+
+  /// The capabilities of `model` found by [OllamaAIPlugin::probe_model_capabilities], `None`
+  /// when it wasn't probed.
+  pub fn model_capabilities(&self, model: &str) -> Option<ModelCapabilities> {
+    self.model_capabilities.get(model)
+  }
+
+  /// Fails when `format` is given and `model` was probed not to follow one, so the request isn't
+  /// sent. Models that weren't probed are sent the format.
+  pub(crate) fn check_format_supported(
+    &self,
+    model: &str,
+    format: Option<&Value>,
+  ) -> Result<(), PluginError> {
+    match (format, self.model_capabilities.get(model)) {
+      (Some(_), Some(capabilities)) if !capabilities.supports_json_format => {
+        Err(PluginError::UnsupportedByModel {
+          model: model.to_string(),
+          feature: "a JSON format".to_string(),
+        })
+      },
+      _ => Ok(()),
+    }
+  }
+}
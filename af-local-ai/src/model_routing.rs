@@ -0,0 +1,164 @@
+use crate::ai_ops::CompleteTextType;
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Kind of a request whose chat model can be chosen with a [ModelRoutingTable]. Unlike the
+/// [RequestKind](crate::outbound_filter::RequestKind) of outbound filters, it tells apart the
+/// operations on the same kind of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+  /// Questions asked in a chat, streamed or not.
+  Chat,
+  /// Texts completed with `completion_type`. Inline autocompletes are
+  /// [CompleteTextType::ContinueWriting] completions.
+  Completion {
+    completion_type: CompleteTextType,
+  },
+  DatabaseSummary,
+  DatabaseTranslate,
+  /// Questions suggested after an answer, or about a text.
+  RelatedQuestions,
+  /// Summaries of a chat, such as the one a chat title is made from.
+  ChatTitle,
+}
+
+/// Chat model used by each [RequestKind], see
+/// [OllamaAIPlugin::set_model_routing](crate::ollama_plugin::OllamaAIPlugin::set_model_routing).
+/// Kinds without a model use the `chat_model_name` of the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelRoutingTable {
+  models: HashMap<RequestKind, String>,
+}
+
+impl ModelRoutingTable {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sends the requests of `kind` to `model`.
+  pub fn with_route(mut self, kind: RequestKind, model: &str) -> Self {
+    self.models.insert(kind, model.to_string());
+    self
+  }
+
+  /// Sends the completions of every type to `model`.
+  pub fn with_completions(mut self, model: &str) -> Self {
+    for completion_type in [
+      CompleteTextType::ImproveWriting,
+      CompleteTextType::SpellingAndGrammar,
+      CompleteTextType::MakeShorter,
+      CompleteTextType::MakeLonger,
+      CompleteTextType::ContinueWriting,
+      CompleteTextType::Explain,
+      CompleteTextType::AskAI,
+      CompleteTextType::Custom,
+    ] {
+      self.models.insert(
+        RequestKind::Completion { completion_type },
+        model.to_string(),
+      );
+    }
+    self
+  }
+
+  /// The model of `kind`, if it has one.
+  pub fn model(&self, kind: RequestKind) -> Option<&str> {
+    self.models.get(&kind).map(String::as_str)
+  }
+
+  /// Models used by the table, sorted and without duplicates.
+  pub fn models(&self) -> Vec<&str> {
+    let mut models = self.models.values().map(String::as_str).collect::<Vec<_>>();
+    models.sort_unstable();
+    models.dedup();
+    models
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.models.is_empty()
+  }
+}
+
+/// Whether `model` is one of `available`. Names without a tag are those of the `latest` tag, as
+/// in Ollama.
+pub fn has_model(available: &[String], model: &str) -> bool {
+  let with_tag = |name: &str| {
+    if name.contains(':') {
+      name.to_string()
+    } else {
+      format!("{}:latest", name)
+    }
+  };
+  let model = with_tag(model);
+  available.iter().any(|name| with_tag(name) == model)
+}
+
+impl OllamaAIPlugin {
+  /// Names of the models of the Ollama server. Fails with [PluginError::UnsupportedMethod] when
+  /// the plugin can't list them.
+  pub async fn list_models(&self) -> Result<Vec<String>, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    self.operation(plugin).list_models().await
+  }
+
+  /// Sends the requests of each kind of `table` to its model, and the other requests to the
+  /// `chat_model_name` of the config.
+  ///
+  /// Fails with [PluginError::ModelNotFound] when the Ollama server doesn't have a model of the
+  /// table. The table is still set, with a warning, when the models can't be listed, e.g.
+  /// because the plugin is not running or is too old.
+  pub async fn set_model_routing(&self, table: ModelRoutingTable) -> Result<(), PluginError> {
+    if !table.is_empty() {
+      match self.list_models().await {
+        Ok(available) => {
+          if let Some(missing) = table
+            .models()
+            .into_iter()
+            .find(|model| !has_model(&available, model))
+          {
+            return Err(PluginError::ModelNotFound(missing.to_string()));
+          }
+        },
+        Err(err) => warn!(
+          "[AI Plugin] can't check the models of the routing table: {}",
+          err
+        ),
+      }
+    }
+    *self.model_routing.write() = table;
+    Ok(())
+  }
+
+  pub fn model_routing(&self) -> ModelRoutingTable {
+    self.model_routing.read().clone()
+  }
+
+  /// The model the requests of `kind` are sent to: the one of the routing table, or the
+  /// `chat_model_name` of the config. Empty before the plugin is initialized.
+  pub async fn resolve_model(&self, kind: RequestKind) -> String {
+    if let Some(model) = self.routed_model(kind) {
+      return model;
+    }
+    self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .map(|config| config.chat_model_name.clone())
+      .unwrap_or_default()
+  }
+
+  /// The model of `kind` in the routing table, sent as a per-request override.
+  pub(crate) fn routed_model(&self, kind: RequestKind) -> Option<String> {
+    self.model_routing.read().model(kind).map(String::from)
+  }
+
+  pub(crate) fn completion_model(&self, complete_type: u8) -> Option<String> {
+    self.routed_model(RequestKind::Completion {
+      completion_type: CompleteTextType::from(complete_type),
+    })
+  }
+}
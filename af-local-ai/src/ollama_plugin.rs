@@ -1,54 +1,218 @@
-use crate::ai_ops::{AIPluginOperation, LocalAITranslateRowData, LocalAITranslateRowResponse};
+use crate::ai_ops::{
+  AIPluginOperation, AnswerWithSources, ChatSettings, CompleteTextType, CompletionResult,
+  QuestionOptions, RagOptions, RagSource, STREAM_METADATA_KEY,
+};
+use crate::chat_list::CreatedChats;
+use af_ai_protocol::capability::{Capability, DEFAULT_PROTOCOL_VERSION};
+pub use af_ai_protocol::types::PluginInfo;
+use af_plugin::core::journal::{read_crash_report, CrashReport};
+use af_plugin::core::parser::ResponseParser;
+use af_plugin::core::path::check_executable;
 use af_plugin::core::plugin::{
   Plugin, PluginConfig, PluginId, RunningState, RunningStateReceiver, RunningStateSender,
 };
+use af_plugin::core::process_limits::ProcessLimits;
+use af_plugin::core::resource_usage::ResourceUsage;
+use af_plugin::core::state_machine::StateMachine;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::core::transport::TransportKind;
 use af_plugin::error::PluginError;
-use af_plugin::manager::PluginManager;
+use af_plugin::manager::{PluginManager, TeardownReport};
+use af_plugin::util::{redact_secrets, RedactedEnv};
 use anyhow::{anyhow, Result};
+use semver::Version;
 
-use crate::embedding_ops::EmbeddingPluginOperation;
+use crate::attachment::{is_ephemeral, AttachmentRecord};
+use crate::auth::OllamaAuth;
+use crate::chat_budget::{budget_stream, default_context_window, ChatBudgetTracker};
+use crate::chunking::{completion_input_limit, estimate_tokens};
+use crate::citation::{sourced_stream, SourcedFrame, FILE_NAME_KEY, SOURCE_ID_KEY};
+use crate::database_query::DEFAULT_DATABASE_QUERY_CHUNK_ROWS;
+use crate::diff::with_diff;
+use crate::embedding_index::EmbeddingIndex;
+use crate::embedding_manifest::{
+  check_manifest, read_manifest, write_manifest, EmbeddingModelInfo, MismatchPolicy,
+};
+use crate::embedding_ops::{EmbeddingPluginOperation, SearchOptions, SearchResult};
+use crate::index_audit::{
+  finish_audit, AuditContent, AuditOperation, IndexAudit, DEFAULT_INDEX_AUDIT_MAX_BYTES,
+};
+use crate::init::{InitAttempt, InitHandle, InitPhase};
+use crate::json_assembly::assembled_json_stream;
+use crate::keep_alive::{keep_alive_stream, DEFAULT_KEEP_ALIVE_INTERVAL};
+use crate::language::detect_language;
+use crate::model_capabilities::ModelCapabilityCache;
+use crate::model_routing::{ModelRoutingTable, RequestKind as ModelRequestKind};
+use crate::outbound_filter::{OutboundFilter, RequestKind};
+use crate::pausable::{pausable_stream, PausableStream};
+use crate::plugin_slot::{clear_when_stopped, PluginSlot};
+use crate::plugin_version::{
+  parse_plugin_version, DEFAULT_MINIMUM_PLUGIN_VERSION, PLUGIN_DOWNLOAD_HINT,
+};
+use crate::post_process::{post_process_text, post_processed, PostProcessor};
+use crate::prepared_request::{PreparedRequest, QuestionRequestOptions};
+use crate::prompt_template::PromptTemplates;
+use crate::rate_limit::RateLimiter;
+use crate::related_question::{prefetch_after_answer, RelatedQuestionPrefetch};
+use crate::response_cache::ResponseCache;
+use crate::resume::{resumable_stream, AnswerRequest};
+use crate::retry::Retrier;
+use crate::rolling_summary::RollingSummary;
+use crate::scheduler::{Priority, RequestScheduler};
+use crate::slow_request::SlowRequestMonitor;
+use crate::store_meta::StoreMigration;
+use crate::summary::prepend;
+use crate::trace::{continue_trace, start_trace, TracedStream};
+use crate::usage::{finish_usage, tracked_stream, UsageKind, UsageRecorder};
+use crate::warm_up::{warming_up_hint, DEFAULT_WARMING_UP_THRESHOLD};
+use af_ai_protocol::method::ANSWER;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
 use std::path::PathBuf;
+use std::str::FromStr;
 
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Weak};
 use std::time::Duration;
-use tokio::io;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 use tokio_stream::wrappers::{ReceiverStream, WatchStream};
 use tokio_stream::StreamExt;
-use tracing::{error, info, instrument, trace};
+use tracing::{error, info, instrument, trace, warn};
 
-#[derive(Debug, Clone, serde::Deserialize)]
-pub struct PluginInfo {
-  pub version: String,
-}
+/// Number of requests included in a crash report.
+const CRASH_REPORT_REQUESTS: usize = 20;
+
+/// Text embedded once to find out the dimension of the configured embedding model.
+const EMBEDDING_PROBE_TEXT: &str = "AppFlowy";
+
+/// How long [OllamaAIPlugin::switch_vector_store] waits for the indexing and searches in flight.
+pub const VECTOR_STORE_SWITCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long [OllamaAIPlugin::destroy_plugin] waits for the plugin process to exit before killing
+/// it, see [OllamaPluginConfig::with_teardown_grace_period].
+pub const DEFAULT_TEARDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
 
 pub struct OllamaAIPlugin {
-  plugin_manager: Arc<PluginManager>,
-  plugin_config: RwLock<Option<OllamaPluginConfig>>,
-  running_state: RunningStateSender,
+  pub(crate) plugin_manager: Arc<PluginManager>,
+  /// Shared with the embedding plugins attached to this one, see [EmbeddingPlugin::attached](crate::embedding_plugin::EmbeddingPlugin::attached).
+  pub(crate) plugin_config: Arc<RwLock<Option<OllamaPluginConfig>>>,
+  pub(crate) running_state: RunningStateSender,
   #[allow(dead_code)]
   // keep at least one receiver that make sure the sender can receive value
-  running_state_rx: RunningStateReceiver,
-  init_lock: tokio::sync::Mutex<()>,
-  plugin_id: tokio::sync::Mutex<Option<PluginId>>,
-  plugin_info: tokio::sync::RwLock<Option<PluginInfo>>,
+  pub(crate) running_state_rx: RunningStateReceiver,
+  pub(crate) init_lock: tokio::sync::Mutex<()>,
+  /// Latest initialization, replaced while `init_lock` is taken, see
+  /// [OllamaAIPlugin::init_handle].
+  pub(crate) init_attempt: parking_lot::Mutex<InitAttempt>,
+  pub(crate) plugin_id: tokio::sync::Mutex<Option<PluginId>>,
+  pub(crate) plugin_info: tokio::sync::RwLock<Option<PluginInfo>>,
+  /// Methods reported in `plugin_info`, see [OllamaAIPlugin::supports].
+  pub(crate) supported_methods: parking_lot::RwLock<Option<Arc<HashSet<String>>>>,
+  /// Protocol reported by the running plugin, see [OllamaAIPlugin::negotiated_protocol].
+  pub(crate) protocol_version: AtomicU32,
+  /// Plugin resolved from `plugin_id`, so looking it up doesn't contend on `plugin_id`.
+  pub(crate) plugin_slot: Arc<PluginSlot>,
+  pub(crate) embedding_model_info: RwLock<Option<EmbeddingModelInfo>>,
+  pub(crate) embedding_index: RwLock<Arc<EmbeddingIndex>>,
+  /// Held for reading while embedding or searching and for writing while the vector store is
+  /// exported, imported or switched, so the persist directory is not modified while it is copied.
+  pub(crate) vector_store_lock: RwLock<()>,
+  /// Set while [OllamaAIPlugin::switch_vector_store] holds `vector_store_lock`, so the
+  /// initialization it may run doesn't wait for the lock to compact the vector store.
+  pub(crate) vector_store_switching: AtomicBool,
+  /// Found at init when the vector store has another store format than the plugin, see
+  /// [OllamaAIPlugin::migrate_vector_store].
+  pub(crate) store_migration: Arc<parking_lot::Mutex<Option<StoreMigration>>>,
+  /// Set while [OllamaAIPlugin::migrate_vector_store] streams the migration.
+  pub(crate) store_migrating: Arc<AtomicBool>,
+  /// Set up from [OllamaPluginConfig::index_audit_log] at init.
+  pub(crate) index_audit: IndexAudit,
+  pub(crate) scheduler: RequestScheduler,
+  pub(crate) chat_settings: RwLock<HashMap<String, ChatSettings>>,
+  /// Answers [OllamaAIPlugin::chat_exists] for plugins without a `chat_exists` method.
+  pub(crate) created_chats: parking_lot::Mutex<CreatedChats>,
+  /// Files and texts embedded into each chat, keyed by chat id.
+  pub(crate) attachments: RwLock<HashMap<String, Vec<AttachmentRecord>>>,
+  pub(crate) related_questions: Arc<RelatedQuestionPrefetch>,
+  pub(crate) rolling_summary: Arc<RollingSummary>,
+  /// Context window filled by each chat, see [OllamaAIPlugin::chat_budget].
+  pub(crate) chat_budget: Arc<ChatBudgetTracker>,
+  pub(crate) outbound_filter: parking_lot::RwLock<Option<Arc<dyn OutboundFilter>>>,
+  /// Set by [OllamaAIPlugin::enable_response_cache].
+  pub(crate) response_cache: parking_lot::RwLock<Option<Arc<ResponseCache>>>,
+  pub(crate) log_level: Arc<parking_lot::Mutex<LogLevelState>>,
+  pub(crate) resource_usage: Arc<tokio::sync::watch::Sender<Option<ResourceUsage>>>,
+  pub(crate) resource_monitor: parking_lot::Mutex<Option<JoinHandle<()>>>,
+  pub(crate) usage: UsageRecorder,
+  pub(crate) prompt_templates: parking_lot::RwLock<PromptTemplates>,
+  /// Set by [OllamaAIPlugin::set_model_routing].
+  pub(crate) model_routing: parking_lot::RwLock<ModelRoutingTable>,
+  /// Filled by [OllamaAIPlugin::probe_model_capabilities].
+  pub(crate) model_capabilities: ModelCapabilityCache,
+  pub(crate) slow_requests: Arc<SlowRequestMonitor>,
+  /// Set by [OllamaAIPlugin::set_rate_limit].
+  pub(crate) rate_limiter: Arc<RateLimiter>,
+  /// Set by [OllamaAIPlugin::set_retry_policy].
+  pub(crate) retrier: Arc<Retrier>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct LogLevelState {
+  /// Level asked for with [OllamaAIPlugin::set_plugin_log_level].
+  requested: Option<LogLevel>,
+  /// Level the running plugin process uses.
+  applied: Option<LogLevel>,
+  /// Whether a task is waiting for the plugin to run to apply `requested`.
+  waiting: bool,
 }
 
 impl OllamaAIPlugin {
   pub fn new(plugin_manager: Arc<PluginManager>) -> Self {
-    let (running_state, rx) = tokio::sync::watch::channel(RunningState::ReadyToConnect);
+    let running_state = StateMachine::new(RunningState::ReadyToConnect);
+    let rx = running_state.subscribe();
     Self {
       plugin_manager,
       plugin_config: Default::default(),
       running_state: Arc::new(running_state),
       running_state_rx: rx,
       init_lock: tokio::sync::Mutex::new(()),
+      init_attempt: parking_lot::Mutex::new(InitAttempt::not_started()),
       plugin_id: Default::default(),
       plugin_info: Default::default(),
+      supported_methods: Default::default(),
+      plugin_slot: Default::default(),
+      embedding_model_info: Default::default(),
+      embedding_index: Default::default(),
+      vector_store_lock: Default::default(),
+      vector_store_switching: Default::default(),
+      store_migration: Default::default(),
+      store_migrating: Default::default(),
+      index_audit: Default::default(),
+      scheduler: RequestScheduler::new(),
+      chat_settings: Default::default(),
+      created_chats: Default::default(),
+      attachments: Default::default(),
+      related_questions: Default::default(),
+      rolling_summary: Default::default(),
+      chat_budget: Default::default(),
+      outbound_filter: Default::default(),
+      response_cache: Default::default(),
+      protocol_version: AtomicU32::new(DEFAULT_PROTOCOL_VERSION),
+      log_level: Default::default(),
+      resource_usage: Arc::new(tokio::sync::watch::channel(None).0),
+      resource_monitor: Default::default(),
+      usage: Default::default(),
+      prompt_templates: Default::default(),
+      model_routing: Default::default(),
+      model_capabilities: Default::default(),
+      slow_requests: Default::default(),
+      rate_limiter: Default::default(),
+      retrier: Default::default(),
     }
   }
 
@@ -60,7 +224,7 @@ impl OllamaAIPlugin {
         let plugin = self.get_ai_plugin().await?;
         let operation = AIPluginOperation::new(plugin);
         let info = operation.plugin_info().await?;
-        self.plugin_info.write().await.replace(info.clone());
+        self.set_plugin_info(Some(info.clone())).await;
 
         Ok(info)
       },
@@ -68,40 +232,144 @@ impl OllamaAIPlugin {
     }
   }
 
-  /// Creates a new chat session.
-  ///
-  /// # Arguments
-  ///
-  /// * `chat_id` - A string slice containing the unique identifier for the chat session.
-  ///
-  /// # Returns
+  /// Version the plugin reported in its `system_info` when it started, `None` before it started
+  /// or when the version is not semver.
+  pub async fn plugin_version(&self) -> Option<Version> {
+    let plugin_info = self.plugin_info.read().await;
+    parse_plugin_version(&plugin_info.as_ref()?.version)
+  }
+
+  /// Protocol version of the running plugin, read from `system_info` when the plugin starts.
+  /// Optional request fields the plugin doesn't know are not sent, see [Capability].
+  pub fn negotiated_protocol(&self) -> u32 {
+    self.protocol_version.load(Ordering::SeqCst)
+  }
+
+  pub(crate) fn has_capability(&self, capability: Capability) -> bool {
+    capability.is_supported_by(self.negotiated_protocol())
+  }
+
+  /// Whether the running plugin handles `method`, see [af_ai_protocol::method]. `None` when the
+  /// plugin is not running, or predates reporting its methods in its `system_info`.
+  pub fn supports(&self, method: &str) -> Option<bool> {
+    self
+      .supported_methods
+      .read()
+      .as_ref()
+      .map(|methods| methods.contains(method))
+  }
+
+  /// An operation on `plugin` that fails the methods the plugin doesn't support without sending
+  /// them.
+  pub(crate) fn operation(&self, plugin: Weak<Plugin>) -> AIPluginOperation {
+    AIPluginOperation::new(plugin)
+      .with_supported_methods(self.supported_methods.read().clone())
+      .with_slow_requests(self.slow_requests.clone())
+      .with_rate_limiter(self.rate_limiter.clone())
+      .with_retrier(self.retrier.clone())
+  }
+
+  pub(crate) fn embedding_operation(&self, plugin: Weak<Plugin>) -> EmbeddingPluginOperation {
+    EmbeddingPluginOperation::new(plugin)
+      .with_slow_requests(self.slow_requests.clone())
+      .with_rate_limiter(self.rate_limiter.clone())
+      .with_retrier(self.retrier.clone())
+  }
+
+  /// Caches the `system_info` of the running plugin, or clears it with `None`.
+  pub(crate) async fn set_plugin_info(&self, plugin_info: Option<PluginInfo>) {
+    *self.supported_methods.write() = plugin_info
+      .as_ref()
+      .and_then(|info| info.supported_methods.as_ref())
+      .map(|methods| Arc::new(methods.iter().cloned().collect()));
+    *self.plugin_info.write().await = plugin_info;
+  }
+
+  /// Sends `params` to the plugin method `method` and parses the reply with `P`, e.g. to try a
+  /// method of a newer plugin before this crate wraps it. The params are sent in the same
+  /// `{"method": ..., "params": ...}` envelope as the wrapped methods, with a trace id. Fails
+  /// with [PluginError::UnsupportedMethod] when the plugin doesn't know `method`.
   ///
-  /// A `Result<()>` indicating success or failure.
-  pub async fn create_chat(&self, chat_id: &str) -> Result<(), PluginError> {
-    trace!("[AI Plugin] create chat: {}", chat_id);
+  /// Raw methods aren't stable: their params and replies may change with any plugin release.
+  pub async fn raw_request<P: ResponseParser>(
+    &self,
+    method: &str,
+    params: Value,
+  ) -> Result<P::ValueType, PluginError> {
+    let trace_id = start_trace();
+    trace!("[AI Plugin] raw request: {}, {:?}", method, params);
     self.wait_until_plugin_ready().await?;
-
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    operation.create_chat(chat_id).await?;
-    Ok(())
+    AIPluginOperation::new(plugin)
+      .with_trace_id(&trace_id)
+      .with_rate_limiter(self.rate_limiter.clone())
+      .raw_request::<P>(method, params)
+      .await
   }
 
-  /// Closes an existing chat session.
-  ///
-  /// # Arguments
+  /// Same as [OllamaAIPlugin::raw_request] for a method that streams its reply, each frame
+  /// parsed with `P`.
   ///
-  /// * `chat_id` - A string slice containing the unique identifier for the chat session to close.
-  ///
-  /// # Returns
-  ///
-  /// A `Result<()>` indicating success or failure.
-  pub async fn close_chat(&self, chat_id: &str) -> Result<()> {
-    trace!("[AI Plugin] close chat: {}", chat_id);
+  /// Raw methods aren't stable: their params and replies may change with any plugin release.
+  pub async fn raw_stream_request<P: ResponseParser + 'static>(
+    &self,
+    method: &str,
+    params: Value,
+  ) -> Result<ReceiverStream<Result<P::ValueType, PluginError>>, PluginError> {
+    let trace_id = start_trace();
+    trace!("[AI Plugin] raw stream request: {}, {:?}", method, params);
+    self.wait_until_plugin_ready().await?;
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    operation.close_chat(chat_id).await?;
-    Ok(())
+    AIPluginOperation::new(plugin)
+      .with_trace_id(&trace_id)
+      .with_rate_limiter(self.rate_limiter.clone())
+      .raw_stream_request::<P>(method, params, StreamOptions::default())
+      .await
+  }
+
+  /// Changes the log level of the plugin process without restarting it.
+  ///
+  /// Setting the level the plugin already uses is a no-op. When the plugin isn't running yet,
+  /// the level is applied as soon as it is.
+  pub async fn set_plugin_log_level(&self, level: LogLevel) -> Result<(), PluginError> {
+    trace!("[AI Plugin] set plugin log level: {}", level);
+    self.log_level.lock().requested = Some(level);
+    let running_state = self.running_state.borrow().clone();
+    match running_state {
+      RunningState::Running { plugin_id } => {
+        apply_log_level(&self.plugin_manager, &self.log_level, plugin_id).await
+      },
+      _ => {
+        self.apply_log_level_when_running();
+        Ok(())
+      },
+    }
+  }
+
+  pub(crate) fn apply_log_level_when_running(&self) {
+    {
+      let mut state = self.log_level.lock();
+      if state.waiting || state.requested.is_none() || state.requested == state.applied {
+        return;
+      }
+      state.waiting = true;
+    }
+
+    let mut rx = self.subscribe_running_state();
+    let plugin_manager = self.plugin_manager.clone();
+    let log_level = self.log_level.clone();
+    tokio::spawn(async move {
+      while let Some(state) = rx.next().await {
+        if let RunningState::Running { plugin_id } = state {
+          log_level.lock().waiting = false;
+          if let Err(err) = apply_log_level(&plugin_manager, &log_level, plugin_id).await {
+            error!("[AI Plugin] failed to set plugin log level: {:?}", err);
+          }
+          return;
+        }
+      }
+      log_level.lock().waiting = false;
+    });
   }
 
   pub fn subscribe_running_state(&self) -> WatchStream<RunningState> {
@@ -114,6 +382,15 @@ impl OllamaAIPlugin {
 
   /// Asks a question and returns a stream of responses.
   ///
+  /// The stream starts with a `{"warming_up": true}` metadata frame when the first frame takes
+  /// longer than [OllamaPluginConfig::warming_up_threshold], usually while the model is loading.
+  ///
+  /// Questions asked with [StreamOptions::require_relevant_context] are first searched in the
+  /// documents embedded into the chat. When none scores the
+  /// [ContextGate](crate::ai_ops::ContextGate) of the chat, the question is answered without
+  /// retrieval after a `{"no_document_context": true}` metadata frame, or fails with
+  /// [PluginError::NoRelevantContext] if the gate is strict.
+  ///
   /// # Arguments
   ///
   /// * `chat_id` - A string slice containing the unique identifier for the chat session.
@@ -129,52 +406,453 @@ impl OllamaAIPlugin {
     format: Option<serde_json::Value>,
     metadata: serde_json::Value,
   ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .stream_question_with_options(chat_id, message, format, metadata, StreamOptions::default())
+      .await
+  }
+
+  /// Same as [OllamaAIPlugin::stream_question], with control over how the answer stream behaves
+  /// when the consumer falls behind, and whether an interrupted answer is continued, see
+  /// [StreamOptions::resume_on_error].
+  pub async fn stream_question_with_options(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .stream_question_traced(chat_id, message, format, metadata, options)
+      .await
+      .map(TracedStream::into_inner)
+  }
+
+  /// Same as [OllamaAIPlugin::stream_question], returning a stream that can be paused, for
+  /// example while the window showing the answer is in the background. Up to
+  /// `max_buffered_bytes` of answer text, usually
+  /// [DEFAULT_PAUSE_BUFFER_BYTES](crate::pausable::DEFAULT_PAUSE_BUFFER_BYTES), is buffered
+  /// frame by frame while paused, see [PausableStream].
+  pub async fn stream_question_pausable(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    max_buffered_bytes: usize,
+  ) -> Result<PausableStream, PluginError> {
+    let stream = self
+      .stream_question(chat_id, message, format, metadata)
+      .await?;
+    Ok(pausable_stream(stream, max_buffered_bytes))
+  }
+
+  /// Same as [OllamaAIPlugin::stream_question_with_options], retrieving embedded chunks for
+  /// this question according to `rag`. Fields `rag` leaves unset are taken from the
+  /// [ChatSettings] of the chat, see [RagOptions::merged].
+  ///
+  /// Plugins older than [Capability::RagOptions] answer with the options the chat was created
+  /// with.
+  pub async fn stream_question_with_rag(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    options: StreamOptions,
+    rag: RagOptions,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .stream_question_inner(chat_id, message, format, metadata, options, Some(rag))
+      .await
+      .map(TracedStream::into_inner)
+  }
+
+  /// Same as [OllamaAIPlugin::stream_question_with_options], returning the trace id the
+  /// question was sent with, see [TracedStream].
+  pub async fn stream_question_traced(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    options: StreamOptions,
+  ) -> Result<TracedStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .stream_question_inner(chat_id, message, format, metadata, options, None)
+      .await
+  }
+
+  pub(crate) async fn stream_question_inner(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+    options: StreamOptions,
+    rag: Option<RagOptions>,
+  ) -> Result<TracedStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    let options = QuestionRequestOptions {
+      format,
+      metadata,
+      stream: options,
+      rag,
+    };
+    let prepared = self
+      .build_question_request(chat_id, message, options)
+      .await?;
+    self.send_prepared_traced(prepared).await
+  }
+
+  /// Composes the request [OllamaAIPlugin::stream_question] sends for `message` without sending
+  /// it, e.g. to preview it: the chat settings are merged, the model is routed and the outbound
+  /// filter is applied. [OllamaAIPlugin::send_prepared] then sends [PreparedRequest::params] as
+  /// they are.
+  ///
+  /// The one exception is [StreamOptions::require_relevant_context]: the chat is searched when
+  /// the request is sent, and retrieval is turned off in the params sent when nothing in it is
+  /// relevant.
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn build_question_request(
+    &self,
+    chat_id: &str,
+    message: &str,
+    options: QuestionRequestOptions,
+  ) -> Result<PreparedRequest, PluginError> {
+    let trace_id = start_trace();
+    let QuestionRequestOptions {
+      format,
+      metadata,
+      stream: options,
+      rag,
+    } = options;
+    let json_schema = match (options.assemble_json, &format) {
+      (false, _) => None,
+      (true, Some(schema @ Value::Object(_))) => Some(schema.clone()),
+      (true, _) => {
+        return Err(PluginError::InvalidStreamOptions(
+          "assemble_json needs a format schema".to_string(),
+        ))
+      },
+    };
+    let rag = self.question_rag_options(chat_id, rag).await?;
+    let filtered = self.filter_outbound(message, RequestKind::Question)?;
+    let redacted = matches!(filtered, Cow::Owned(_));
+    let message = filtered.into_owned();
+    self.wait_until_plugin_ready().await?;
+    let format = format.filter(|_| self.has_capability(Capability::ResponseFormat));
+    let metadata = self
+      .apply_response_language(chat_id, &message, metadata)
+      .await;
+    let model_name = self.routed_model(ModelRequestKind::Chat);
+    let model = match model_name.clone() {
+      Some(model_name) => model_name,
+      None => self
+        .plugin_config
+        .read()
+        .await
+        .as_ref()
+        .map(|config| config.chat_model_name.clone())
+        .unwrap_or_default(),
+    };
+    self.check_format_supported(&model, format.as_ref())?;
+    let plugin = self.get_ai_plugin().await?;
+    let params = self
+      .operation(plugin)
+      .with_trace_id(&trace_id)
+      .with_model_name(model_name.clone())
+      .stream_message_v2_params(
+        chat_id,
+        &message,
+        format.clone(),
+        metadata.clone(),
+        rag.clone(),
+      )?;
+    Ok(PreparedRequest {
+      trace_id,
+      chat_id: chat_id.to_string(),
+      message,
+      format,
+      metadata,
+      rag,
+      options,
+      json_schema,
+      model_name,
+      model,
+      redacted,
+      params,
+    })
+  }
+
+  /// Asks the question of `prepared`, built with [OllamaAIPlugin::build_question_request],
+  /// streaming the answer like [OllamaAIPlugin::stream_question].
+  pub async fn send_prepared(
+    &self,
+    prepared: PreparedRequest,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .send_prepared_traced(prepared)
+      .await
+      .map(TracedStream::into_inner)
+  }
+
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub(crate) async fn send_prepared_traced(
+    &self,
+    prepared: PreparedRequest,
+  ) -> Result<TracedStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    continue_trace(&prepared.trace_id);
+    let PreparedRequest {
+      trace_id,
+      chat_id,
+      message,
+      format,
+      metadata,
+      rag,
+      options,
+      json_schema,
+      model_name,
+      mut params,
+      ..
+    } = prepared;
+    let (chat_id, message) = (chat_id.as_str(), message.as_str());
     trace!("[AI Plugin] ask question: {}", message);
     self.wait_until_plugin_ready().await?;
+    self.auto_create_chat(chat_id).await?;
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
+    let operation = self
+      .operation(plugin.clone())
+      .with_trace_id(&trace_id)
+      .with_model_name(model_name.clone());
+    let (rag, without_context) = if options.require_relevant_context {
+      let (gated, without_context) = self.gate_context(chat_id, message, rag.clone()).await?;
+      if gated != rag {
+        params = operation.stream_message_v2_params(
+          chat_id,
+          message,
+          format.clone(),
+          metadata.clone(),
+          gated.clone(),
+        )?;
+      }
+      (gated, without_context)
+    } else {
+      (rag, false)
+    };
+    self.related_questions.invalidate(chat_id);
+    let permit = self.scheduler.acquire(Priority::Interactive).await;
+    let request = options.resume_on_error.then(|| AnswerRequest {
+      trace_id: trace_id.clone(),
+      chat_id: chat_id.to_string(),
+      message: message.to_string(),
+      format,
+      metadata,
+      rag,
+      options: options.clone(),
+      model_name,
+    });
+    let usage = self
+      .usage
+      .start(UsageKind::Question, Some(chat_id), message.chars().count());
     let stream = operation
-      .stream_message_v2(chat_id, message, format, metadata)
+      .send_stream_message(params, options)
+      .await
+      .map(|stream| match request {
+        Some(request) => {
+          resumable_stream(stream, request, plugin.clone(), self.rate_limiter.clone())
+        },
+        None => stream,
+      });
+    let stream = tracked_stream(usage, stream)?;
+    let stream = budget_stream(
+      self.chat_budget.clone(),
+      chat_id.to_string(),
+      message.chars().count(),
+      stream,
+    );
+    self.turn_asked(chat_id, plugin.clone()).await;
+    let threshold = self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .map(|config| config.warming_up_threshold)
+      .unwrap_or(DEFAULT_WARMING_UP_THRESHOLD);
+    let stream = warming_up_hint(stream, threshold);
+    let stream = self.with_keep_alive(stream, plugin.clone()).await;
+    let stream = if without_context {
+      prepend(
+        Ok(json!({ STREAM_METADATA_KEY: { "no_document_context": true } })),
+        stream,
+      )
+    } else {
+      stream
+    };
+    let stream = permit.hold_until_done(stream);
+    let stream = if self.related_questions.is_enabled() {
+      prefetch_after_answer(
+        stream,
+        chat_id.to_string(),
+        self.related_questions.clone(),
+        plugin,
+        self.scheduler.clone(),
+        self.rate_limiter.clone(),
+        self.routed_model(ModelRequestKind::RelatedQuestions),
+      )
+    } else {
+      stream
+    };
+    // Assembled last, so the wrappers above still see the answer text.
+    let stream = match json_schema {
+      Some(schema) => assembled_json_stream(stream, schema),
+      None => stream,
+    };
+    Ok(TracedStream { trace_id, stream })
+  }
+
+  /// Pings the plugin while `stream` is silent, when [OllamaPluginConfig::keep_alive_interval]
+  /// is set.
+  pub(crate) async fn with_keep_alive(
+    &self,
+    stream: ReceiverStream<Result<Value, PluginError>>,
+    plugin: Weak<Plugin>,
+  ) -> ReceiverStream<Result<Value, PluginError>> {
+    let interval = self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .and_then(|config| config.keep_alive_interval);
+    match interval {
+      Some(interval) => keep_alive_stream(stream, interval, plugin),
+      None => stream,
+    }
+  }
+
+  /// Asks a question in a chat with embedded files. Metadata frames of the answer are parsed
+  /// into [Citation](crate::citation::Citation)s, and the stream ends with [SourcedFrame::Done] listing all of them.
+  pub async fn stream_question_with_sources(
+    &self,
+    chat_id: &str,
+    message: &str,
+    format: Option<serde_json::Value>,
+    metadata: serde_json::Value,
+  ) -> Result<ReceiverStream<Result<SourcedFrame, PluginError>>, PluginError> {
+    let stream = self
+      .stream_question(chat_id, message, format, metadata)
       .await?;
-    Ok(stream)
+    Ok(sourced_stream(stream))
   }
 
-  pub async fn get_related_question(&self, chat_id: &str) -> Result<Vec<String>, PluginError> {
-    self.wait_until_plugin_ready().await?;
+  /// Checks that a chunk embedded into `chat_id` is relevant to `message`, per the
+  /// [ContextGate](crate::ai_ops::ContextGate) of the chat. Returns the retrieval options to
+  /// answer with, and whether the answer goes without document context.
+  pub(crate) async fn gate_context(
+    &self,
+    chat_id: &str,
+    message: &str,
+    rag: Option<RagOptions>,
+  ) -> Result<(Option<RagOptions>, bool), PluginError> {
+    if !self.has_capability(Capability::SearchScores) {
+      warn!(
+        "[AI Plugin] plugin protocol {} doesn't score search results, answering without checking the context",
+        self.negotiated_protocol()
+      );
+      return Ok((rag, false));
+    }
+    let settings = self.get_chat_settings(chat_id).await;
+    let filter = HashMap::from([("chat_id".to_string(), json!(chat_id))]);
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    let values = operation.get_related_questions(chat_id).await?;
-    Ok(values)
+    let page = self
+      .embedding_operation(plugin)
+      .similarity_search_with_options(message, filter, &SearchOptions::new(1))
+      .await?;
+    let top_score = match page.results.first() {
+      // A result without a score can't be judged.
+      Some(SearchResult { score: None, .. }) => return Ok((rag, false)),
+      Some(result) => result.score,
+      None => None,
+    };
+    let gate = settings.context_gate;
+    if top_score.is_some_and(|score| score >= gate.min_score) {
+      return Ok((rag, false));
+    }
+    info!(
+      "[AI Plugin] no relevant context in chat {}, best score: {:?}",
+      chat_id, top_score
+    );
+    if gate.strict {
+      return Err(PluginError::NoRelevantContext { top_score });
+    }
+    if !self.has_capability(Capability::SkipRetrieval) {
+      warn!(
+        "[AI Plugin] plugin protocol {} can't skip retrieval, answering with the chunks found",
+        self.negotiated_protocol()
+      );
+      return Ok((rag, true));
+    }
+    let rag = RagOptions {
+      skip_retrieval: true,
+      ..rag.unwrap_or(settings.rag)
+    };
+    Ok((Some(rag), true))
   }
 
-  pub async fn embed_file(
+  /// The retrieval options sent with a question: those of the question merged over those of the
+  /// chat, or none when they are the defaults the chat was created with.
+  pub(crate) async fn question_rag_options(
     &self,
     chat_id: &str,
-    file_path: PathBuf,
-    metadata: Option<HashMap<String, serde_json::Value>>,
-  ) -> Result<(), PluginError> {
-    if !file_path.exists() {
-      return Err(PluginError::Io(io::Error::new(
-        io::ErrorKind::NotFound,
-        "file not found",
-      )));
+    rag: Option<RagOptions>,
+  ) -> Result<Option<RagOptions>, PluginError> {
+    let chat = self.get_chat_settings(chat_id).await.rag;
+    let overridden = rag.is_some();
+    let rag = match rag {
+      Some(rag) => rag.merged(&chat),
+      None => chat,
+    }
+    .validate()?;
+    if rag == RagOptions::default() {
+      return Ok(None);
+    }
+    // The chat was created with its own options, only those of the question are lost.
+    if !self.has_capability(Capability::RagOptions) {
+      if !overridden {
+        return Ok(None);
+      }
+      warn!(
+        "[AI Plugin] plugin protocol {} doesn't support per question RAG options, ignoring {:?}",
+        self.negotiated_protocol(),
+        rag
+      );
+      return Ok(None);
     }
+    Ok(Some(rag))
+  }
 
-    let file_path_str = file_path
-      .to_str()
-      .ok_or(PluginError::Io(io::Error::new(
-        io::ErrorKind::NotFound,
-        "file path invalid",
-      )))?
-      .to_string();
+  /// Adds `response_language` to the request metadata, either pinned by the chat settings or
+  /// detected from the question.
+  pub(crate) async fn apply_response_language(
+    &self,
+    chat_id: &str,
+    message: &str,
+    metadata: Value,
+  ) -> Value {
+    let settings = self.get_chat_settings(chat_id).await;
+    let language = match settings.response_language {
+      Some(language) => Some(language),
+      None if settings.auto_match_language => detect_language(message).map(|tag| tag.name),
+      None => None,
+    };
 
-    self.wait_until_plugin_ready().await?;
-    let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    operation
-      .embed_file(chat_id, file_path_str, metadata)
-      .await?;
-    Ok(())
+    match (language, metadata) {
+      (Some(language), Value::Object(mut map)) => {
+        map.insert("response_language".to_string(), json!(language));
+        Value::Object(map)
+      },
+      (Some(language), Value::Null) => json!({ "response_language": language }),
+      (_, metadata) => metadata,
+    }
   }
 
   /// Generates a complete answer for a given message.
@@ -188,24 +866,236 @@ impl OllamaAIPlugin {
   ///
   /// A `Result<String>` containing the generated answer.
   pub async fn ask_question(&self, chat_id: &str, message: &str) -> Result<String, PluginError> {
+    self
+      .ask_question_with_options(chat_id, message, QuestionOptions::default())
+      .await
+  }
+
+  /// Same as [OllamaAIPlugin::ask_question], sampling the answer with `options`. The answers of
+  /// deterministic options are cached when [OllamaAIPlugin::enable_response_cache] was called.
+  ///
+  /// A cached answer is keyed by the chat, the question and the options only, so it is returned
+  /// even when the history or the documents of the chat changed since, and the question isn't
+  /// recorded again in the history of the chat.
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn ask_question_with_options(
+    &self,
+    chat_id: &str,
+    message: &str,
+    options: QuestionOptions,
+  ) -> Result<String, PluginError> {
+    let message = self.filter_outbound(message, RequestKind::Question)?;
+    if !options.is_deterministic() {
+      return self.ask_question_inner(chat_id, &message, &options).await;
+    }
+    let params = json!({ "chat_id": chat_id, "content": message, "options": options });
+    self
+      .cached(ANSWER, ModelRequestKind::Chat, &params, || {
+        self.ask_question_inner(chat_id, &message, &options)
+      })
+      .await
+  }
+
+  /// Same as [OllamaAIPlugin::ask_question_with_options], never answered from the response
+  /// cache.
+  #[cfg(feature = "replay")]
+  pub(crate) async fn ask_question_uncached(
+    &self,
+    chat_id: &str,
+    message: &str,
+    options: &QuestionOptions,
+  ) -> Result<String, PluginError> {
+    let message = self.filter_outbound(message, RequestKind::Question)?;
+    self.ask_question_inner(chat_id, &message, options).await
+  }
+
+  /// Same as [OllamaAIPlugin::ask_question], with the chunks of the chat the answer was retrieved
+  /// from, e.g. to cite them without streaming the answer.
+  ///
+  /// Plugins without `answer_with_sources` are asked with `answer` while the chat is searched
+  /// for the question with its [RagOptions]: the results are then the sources, flagged
+  /// [RagSource::approximate]. A search that fails leaves the answer without sources.
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn ask_question_with_sources(
+    &self,
+    chat_id: &str,
+    message: &str,
+  ) -> Result<AnswerWithSources, PluginError> {
+    let trace_id = start_trace();
+    let message = self.filter_outbound(message, RequestKind::Question)?;
+    self.wait_until_plugin_ready().await?;
+    self.auto_create_chat(chat_id).await?;
+    self.related_questions.invalidate(chat_id);
+    let _permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin.clone())
+      .with_trace_id(&trace_id)
+      .with_model_name(self.routed_model(ModelRequestKind::Chat));
+    let usage = self
+      .usage
+      .start(UsageKind::Question, Some(chat_id), message.chars().count());
+    let result = match operation.answer_with_sources(chat_id, &message).await {
+      Err(PluginError::UnsupportedMethod { .. }) => {
+        let options = QuestionOptions::default();
+        let (answer, sources) = tokio::join!(
+          operation.send_message_with_options(chat_id, &message, &options),
+          self.approximate_sources(chat_id, &message, plugin.clone())
+        );
+        answer.map(|answer| AnswerWithSources { answer, sources })
+      },
+      result => result,
+    };
+    finish_usage(usage, &result, operation.retries(), |result| {
+      result.answer.chars().count()
+    });
+    if let Ok(result) = &result {
+      self.chat_budget.turn_done(
+        chat_id,
+        message.chars().count(),
+        result.answer.chars().count(),
+      );
+      self.turn_asked(chat_id, plugin).await;
+    }
+    result
+  }
+
+  /// The chunks of `chat_id` most similar to `message`, as retrieved with the [RagOptions] of
+  /// the chat. None when the chat skips retrieval or the plugin can't filter a search by chat.
+  pub(crate) async fn approximate_sources(
+    &self,
+    chat_id: &str,
+    message: &str,
+    plugin: Weak<Plugin>,
+  ) -> Vec<RagSource> {
+    let rag = self.get_chat_settings(chat_id).await.rag;
+    if rag.skip_retrieval || !self.has_capability(Capability::SearchFilter) {
+      return vec![];
+    }
+    let filter = HashMap::from([("chat_id".to_string(), json!(chat_id))]);
+    let options = SearchOptions {
+      min_score: rag.score_threshold,
+      ..SearchOptions::new(rag.top_k as usize)
+    };
+    let page = match self.read_vector_store().await {
+      Ok(_store) => {
+        self
+          .embedding_operation(plugin)
+          .similarity_search_with_options(message, filter, &options)
+          .await
+      },
+      Err(err) => Err(err),
+    };
+    match page {
+      Ok(page) => page
+        .results
+        .into_iter()
+        .map(|result| RagSource {
+          metadata: result.metadata,
+          chunk_text: result.content,
+          score: result.score,
+          approximate: true,
+        })
+        .collect(),
+      Err(err) => {
+        warn!(
+          "[AI Plugin] failed to search the sources of chat {}: {}",
+          chat_id, err
+        );
+        vec![]
+      },
+    }
+  }
+
+  /// Same as [OllamaAIPlugin::ask_question], with the answer rewritten by `post_process`, like
+  /// the completions of [StreamOptions::post_process].
+  pub async fn ask_question_post_processed(
+    &self,
+    chat_id: &str,
+    message: &str,
+    post_process: &[PostProcessor],
+  ) -> Result<String, PluginError> {
+    let answer = self.ask_question(chat_id, message).await?;
+    Ok(post_process_text(&answer, post_process))
+  }
+
+  pub(crate) async fn ask_question_inner(
+    &self,
+    chat_id: &str,
+    message: &str,
+    options: &QuestionOptions,
+  ) -> Result<String, PluginError> {
+    let trace_id = start_trace();
     self.wait_until_plugin_ready().await?;
+    self.auto_create_chat(chat_id).await?;
+    self.related_questions.invalidate(chat_id);
+    let _permit = self.scheduler.acquire(Priority::Interactive).await;
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    let answer = operation.send_message(chat_id, message, true).await?;
-    Ok(answer)
+    let operation = self
+      .operation(plugin.clone())
+      .with_trace_id(&trace_id)
+      .with_model_name(self.routed_model(ModelRequestKind::Chat));
+    let usage = self
+      .usage
+      .start(UsageKind::Question, Some(chat_id), message.chars().count());
+    let answer = operation
+      .send_message_with_options(chat_id, message, options)
+      .await;
+    finish_usage(usage, &answer, operation.retries(), |answer| {
+      answer.chars().count()
+    });
+    if let Ok(answer) = &answer {
+      self
+        .chat_budget
+        .turn_done(chat_id, message.chars().count(), answer.chars().count());
+      self.turn_asked(chat_id, plugin).await;
+    }
+    answer
   }
 
+  /// Stops the plugin and returns once its process is gone, killing it when it doesn't exit
+  /// within the [OllamaPluginConfig::teardown_grace_period]. The lock file of the persist
+  /// directory is removed then.
   #[instrument(skip_all, err)]
-  pub async fn destroy_plugin(&self) -> Result<()> {
+  pub async fn destroy_plugin(&self) -> Result<TeardownReport> {
+    // Ephemeral attachments don't outlive the plugin, even when their chat was never closed.
+    self.purge_all_ephemeral_attachments().await;
     let plugin_id = self.plugin_id.lock().await.take();
-    if let Some(plugin_id) = plugin_id {
-      info!("[AI Plugin]: destroy plugin: {:?}", plugin_id);
-
-      if let Err(err) = self.plugin_manager.remove_plugin(plugin_id).await {
-        error!("remove plugin failed: {:?}", err);
+    self.plugin_slot.clear();
+    self.supported_methods.write().take();
+    {
+      let mut attempt = self.init_attempt.lock();
+      if attempt.is_finished() {
+        *attempt = InitAttempt::not_started();
       }
     }
-    Ok(())
+    let (grace, locked_directory) = self.plugin_config.read().await.as_ref().map_or(
+      (DEFAULT_TEARDOWN_GRACE_PERIOD, None),
+      |config| {
+        (
+          config.teardown_grace_period,
+          config.writable_directory().cloned(),
+        )
+      },
+    );
+    let report = match plugin_id {
+      Some(plugin_id) => {
+        info!("[AI Plugin]: destroy plugin: {:?}", plugin_id);
+        match self.plugin_manager.teardown_plugin(plugin_id, grace).await {
+          Ok(report) => report,
+          Err(err) => {
+            error!("remove plugin failed: {:?}", err);
+            TeardownReport::default()
+          },
+        }
+      },
+      None => TeardownReport::default(),
+    };
+    // Only once the plugin is gone, as it keeps the vector store open until then.
+    if let Some(directory) = locked_directory {
+      self.plugin_manager.unlock_persist_directory(&directory);
+    }
+    Ok(report)
   }
 
   pub async fn complete_text_v2(
@@ -215,6 +1105,106 @@ impl OllamaAIPlugin {
     format: Option<serde_json::Value>,
     metadata: Option<serde_json::Value>,
   ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .complete_text_v2_with_options(
+        message,
+        complete_type,
+        format,
+        metadata,
+        StreamOptions::default(),
+      )
+      .await
+  }
+
+  /// Same as [OllamaAIPlugin::complete_text_v2], with control over how the completion stream
+  /// behaves when the consumer falls behind.
+  ///
+  /// Texts estimated to take more than [completion_input_limit] of the context window fail with
+  /// [PluginError::InputTooLong], unless [StreamOptions::auto_chunk] is set. They are then
+  /// completed chunk after chunk, see [chunked_completion].
+  pub async fn complete_text_v2_with_options(
+    &self,
+    message: &str,
+    complete_type: u8,
+    format: Option<serde_json::Value>,
+    metadata: Option<serde_json::Value>,
+    options: StreamOptions,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    self
+      .complete_text_v2_traced(message, complete_type, format, metadata, options)
+      .await
+      .map(TracedStream::into_inner)
+  }
+
+  /// Same as [OllamaAIPlugin::complete_text_v2_with_options], collecting the whole completion.
+  /// With [StreamOptions::compute_diff], [CompletionResult::diff] holds the words the answer
+  /// changed in `message`, such as the fixes of [CompleteTextType::SpellingAndGrammar].
+  pub async fn complete_text_v2_collect(
+    &self,
+    message: &str,
+    complete_type: u8,
+    format: Option<serde_json::Value>,
+    metadata: Option<serde_json::Value>,
+    options: StreamOptions,
+  ) -> Result<CompletionResult, PluginError> {
+    let stream = self
+      .complete_text_v2_with_options(message, complete_type, format, metadata, options)
+      .await?;
+    CompletionResult::collect(stream).await
+  }
+
+  /// Same as [OllamaAIPlugin::complete_text_v2_with_options], returning the trace id the
+  /// completion was sent with, see [TracedStream].
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn complete_text_v2_traced(
+    &self,
+    message: &str,
+    complete_type: u8,
+    format: Option<serde_json::Value>,
+    metadata: Option<serde_json::Value>,
+    options: StreamOptions,
+  ) -> Result<TracedStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    let trace_id = start_trace();
+    let model = self
+      .resolve_model(ModelRequestKind::Completion {
+        completion_type: CompleteTextType::from(complete_type),
+      })
+      .await;
+    self.check_format_supported(&model, format.as_ref())?;
+    let original = message;
+    let message = self.filter_outbound(message, RequestKind::Completion)?;
+    let limit = completion_input_limit(self.chat_budget.capacity());
+    let estimated_tokens = estimate_tokens(&message);
+    if estimated_tokens > limit {
+      if !options.auto_chunk {
+        return Err(PluginError::InputTooLong {
+          estimated_tokens,
+          limit,
+        });
+      }
+      return self
+        .complete_text_in_chunks(
+          trace_id,
+          original,
+          &message,
+          limit,
+          complete_type,
+          format,
+          metadata,
+          options,
+        )
+        .await;
+    }
+    self.wait_until_plugin_ready().await?;
+    let model_name = self.completion_model(complete_type);
+    let (message, complete_type) =
+      match self.host_prompt(complete_type, &message, metadata.as_ref()) {
+        Some(prompt) => (Cow::Owned(prompt), CompleteTextType::Custom as u8),
+        None => (message, complete_type),
+      };
+    let message = message.as_ref();
+    let format = format.filter(|_| self.has_capability(Capability::ResponseFormat));
+    let metadata = metadata.filter(|_| self.has_capability(Capability::CompletionMetadata));
     trace!(
       "[AI Plugin] complete text v2: {}, completion_type: {:?}, format: {:?}, metadata: {:?}",
       message,
@@ -222,141 +1212,470 @@ impl OllamaAIPlugin {
       format,
       metadata
     );
-    self.wait_until_plugin_ready().await?;
+    let permit = self.scheduler.acquire(Priority::Interactive).await;
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
+    let operation = self
+      .operation(plugin.clone())
+      .with_trace_id(&trace_id)
+      .with_model_name(model_name);
+    let compute_diff = options.compute_diff;
+    let post_process = options.post_process.clone();
+    let usage = self
+      .usage
+      .start(UsageKind::Completion, None, message.chars().count());
     let stream = operation
-      .complete_text_v2(message, complete_type, format, metadata)
-      .await?;
-    Ok(stream)
+      .complete_text_v2(message, complete_type, format, metadata, options)
+      .await;
+    let stream = self
+      .with_keep_alive(tracked_stream(usage, stream)?, plugin)
+      .await;
+    let mut stream = post_processed(stream, &post_process);
+    if compute_diff {
+      stream = with_diff(stream, original.to_string());
+    }
+    Ok(TracedStream {
+      trace_id,
+      stream: permit.hold_until_done(stream),
+    })
   }
 
-  pub async fn summary_database_row(
+  /// Generates a short completion for inline autocomplete.
+  ///
+  /// Unlike the other operations this does not wait for the plugin to become ready: it returns
+  /// [PluginError::NotReady] right away unless the plugin is running, and never blocks longer
+  /// than `timeout`.
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn quick_complete(
     &self,
-    row: HashMap<String, String>,
+    text: &str,
+    max_tokens: u16,
+    timeout: Duration,
   ) -> Result<String, PluginError> {
-    trace!("[AI Plugin] summary database row: {:?}", row);
-    self.wait_until_plugin_ready().await?;
+    if !self.running_state.borrow().is_running() {
+      return Err(PluginError::NotReady);
+    }
+
+    let trace_id = start_trace();
+    let text = self.filter_outbound(text, RequestKind::Completion)?;
+    let _permit = self.scheduler.acquire(Priority::Interactive).await;
     let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    let text = operation.summary_row(row).await?;
-    Ok(text)
+    let operation = self
+      .operation(plugin)
+      .with_trace_id(&trace_id)
+      .with_model_name(self.completion_model(CompleteTextType::ContinueWriting as u8));
+    operation.quick_complete(&text, max_tokens, timeout).await
   }
 
-  pub async fn translate_database_row(
+  /// Points the plugin at another Ollama server. The url and its credentials are replaced
+  /// together, then the plugin is restarted with them.
+  pub async fn update_server_url(
     &self,
-    row: LocalAITranslateRowData,
-  ) -> Result<LocalAITranslateRowResponse, PluginError> {
-    trace!("[AI Plugin] summary database row: {:?}", row);
-    self.wait_until_plugin_ready().await?;
-    let plugin = self.get_ai_plugin().await?;
-    let operation = AIPluginOperation::new(plugin);
-    let resp = operation.translate_row(row).await?;
-    Ok(resp)
+    server_url: String,
+    auth: Option<OllamaAuth>,
+  ) -> Result<(), PluginError> {
+    let mut config = self
+      .plugin_config
+      .read()
+      .await
+      .clone()
+      .ok_or_else(|| PluginError::Internal(anyhow!("chat plugin not initialized")))?;
+    config.server_url = server_url;
+    config.auth = auth;
+    self.init_plugin(config).await
   }
 
+  /// Starts the plugin with `config`, replacing the running one if any.
+  ///
+  /// When an initialization is already in progress, waits for it and returns its outcome instead
+  /// of starting another one. Its failures are reported as [PluginError::InitFailed].
   pub async fn init_plugin(&self, config: OllamaPluginConfig) -> Result<(), PluginError> {
-    // Try to acquire the initialization lock without waiting.
-    match self.init_lock.try_lock() {
-      Ok(_guard) => {
-        // We have the lock and can proceed with initialization.
-        trace!("[AI Plugin] Creating chat plugin with config: {:?}", config);
-        let plugin_config = PluginConfig {
-          name: "af_ollama_plugin".to_string(),
-          exec_path: config.executable_path.clone(),
-          exec_command: config.executable_command.clone(),
-        };
-
-        if let Err(err) = self.destroy_plugin().await {
-          error!("[AI Plugin] Failed to destroy plugin: {:?}", err);
-        }
+    // The attempt is replaced with the lock taken, so callers that lose the race always wait for
+    // the attempt of the winner.
+    let started = {
+      let mut attempt = self.init_attempt.lock();
+      match self.init_lock.try_lock() {
+        Ok(guard) => {
+          *attempt = InitAttempt::start();
+          Ok((guard, attempt.clone()))
+        },
+        Err(_) => Err(attempt.handle()),
+      }
+    };
+    match started {
+      Ok((_guard, attempt)) => {
+        let _fail_on_drop = attempt.fail_on_drop();
+        let result = self.run_init(config, &attempt).await;
+        attempt.finish(&result);
+        result
+      },
+      Err(handle) => {
+        trace!("[AI Plugin] Initialization already in progress, waiting for it");
+        handle.wait().await
+      },
+    }
+  }
+
+  /// Follows the latest initialization of the plugin, e.g. to show its progress or to wait for
+  /// an initialization started by another caller. Reports [InitPhase::NotStarted] once the plugin
+  /// is destroyed.
+  pub fn init_handle(&self) -> InitHandle {
+    self.init_attempt.lock().handle()
+  }
+
+  pub(crate) async fn run_init(
+    &self,
+    config: OllamaPluginConfig,
+    attempt: &InitAttempt,
+  ) -> Result<(), PluginError> {
+    trace!("[AI Plugin] Creating chat plugin with config: {:?}", config);
+    check_executable(&config.executable_path, &config.executable_command).into_result()?;
+    let plugin_config = PluginConfig {
+      name: "af_ollama_plugin".to_string(),
+      exec_path: config.executable_path.clone(),
+      exec_command: config.executable_command.clone(),
+      crash_journal_dir: config.crash_journal_dir.clone(),
+      env: config.env.clone(),
+      inherit_env: config.inherit_env,
+      working_dir: config.working_dir.clone(),
+      transport: config.transport,
+      process_limits: config.process_limits.clone(),
+      ..Default::default()
+    };
+
+    if let Err(err) = self.destroy_plugin().await {
+      error!("[AI Plugin] Failed to destroy plugin: {:?}", err);
+    }
+
+    if config.kill_orphaned_instances {
+      self.reap_orphans(&config).await?;
+    }
+    prepare_store_directories(&config, &self.plugin_manager)?;
+    self.embedding_model_info.write().await.take();
+    self.store_migration.lock().take();
+    self.set_plugin_info(None).await;
+    self
+      .protocol_version
+      .store(DEFAULT_PROTOCOL_VERSION, Ordering::SeqCst);
+    let embedding_index = match config.writable_directory() {
+      Some(directory) => EmbeddingIndex::open(directory)?,
+      None => EmbeddingIndex::in_memory(),
+    };
+    *self.embedding_index.write().await = Arc::new(embedding_index);
+    self
+      .index_audit
+      .configure(config.index_audit_log.clone(), config.index_audit_max_bytes)?;
+    self
+      .chat_budget
+      .set_capacity(config.effective_context_window());
+
+    self.log_level.lock().applied = Some(config.log_level);
+    let plugin_id = self
+      .plugin_manager
+      .create_plugin(plugin_config, self.running_state.clone())
+      .await?;
+    self.apply_log_level_when_running();
+    *self.plugin_id.lock().await = Some(plugin_id);
+
+    // Set up plugin parameters.
+    let mut params = json!({});
+    params["verbose"] = json!(config.verbose);
+    params["server_url"] = json!(config.server_url);
+    if let Some(auth) = config.auth.as_ref() {
+      params["auth"] = auth.to_params();
+    }
+    params["model_name"] = json!(config.chat_model_name);
+    params["log_level"] = json!(config.log_level);
+
+    if config.persist_directory.is_some() || config.embedded_embedding {
+      params["vectorstore_config"] = json!({
+        "model_name": config.embedding_model_name,
+        "persist_directory": config.persist_directory,
+        "read_only": config.read_only,
+      });
+      if let (true, Some(overlay_directory)) = (config.read_only, &config.overlay_directory) {
+        params["overlay_vectorstore_config"] = json!({
+          "model_name": config.embedding_model_name,
+          "persist_directory": overlay_directory,
+          "read_only": false,
+        });
+      }
+    }
 
-        let plugin_id = self
-          .plugin_manager
-          .create_plugin(plugin_config, self.running_state.clone())
-          .await?;
-        *self.plugin_id.lock().await = Some(plugin_id);
-
-        // Set up plugin parameters.
-        let mut params = json!({});
-        params["verbose"] = json!(config.verbose);
-        params["server_url"] = json!(config.server_url);
-        params["model_name"] = json!(config.chat_model_name);
-
-        if let Some(persist_directory) = config.persist_directory.clone() {
-          params["vectorstore_config"] = json!({
-            "model_name": config.embedding_model_name,
-            "persist_directory": persist_directory,
-          });
+    info!(
+      "[AI Plugin] Setting up chat plugin: {:?}, params: {:?}",
+      plugin_id,
+      redact_secrets(&params)
+    );
+    attempt.set_phase(InitPhase::Initializing);
+    let plugin = self.plugin_manager.init_plugin(plugin_id, params).await?;
+    info!("[AI Plugin] {} setup success", plugin);
+    self.plugin_slot.set(plugin_id, Arc::downgrade(&plugin));
+    clear_when_stopped(
+      &self.plugin_slot,
+      plugin_id,
+      plugin.subscribe_running_state(),
+    );
+    attempt.set_phase(InitPhase::WaitingReady);
+    let min_protocol_version = config.min_protocol_version;
+    let minimum_plugin_version =
+      (!config.allow_outdated).then(|| config.minimum_plugin_version.clone());
+    self.plugin_config.write().await.replace(config);
+
+    let mut rx = plugin.subscribe_running_state();
+    let weak_plugin = Arc::downgrade(&plugin);
+    let timeout_duration = Duration::from_secs(30);
+    let plugin_info = timeout(timeout_duration, async {
+      while let Some(state) = rx.next().await {
+        if state.is_running() {
+          let operation = AIPluginOperation::new(weak_plugin);
+          return operation.plugin_info().await.ok();
         }
+      }
+      None
+    })
+    .await
+    .ok()
+    .flatten();
 
-        info!(
-          "[AI Plugin] Setting up chat plugin: {:?}, params: {:?}",
-          plugin_id, params
+    if let (Some(plugin_info), Some(minimum)) = (&plugin_info, &minimum_plugin_version) {
+      self.check_plugin_version(plugin_info, minimum).await?;
+    }
+    self
+      .negotiate_protocol(plugin_info, min_protocol_version)
+      .await?;
+    self.check_store_format().await?;
+    self.finish_interrupted_compaction().await;
+    self.retry_pending_cleanup().await;
+    Ok(())
+  }
+
+  /// Kills the plugin processes a crashed run left behind, failing when another running
+  /// application uses the persist directory.
+  pub(crate) async fn reap_orphans(&self, config: &OllamaPluginConfig) -> Result<(), PluginError> {
+    let executable =
+      check_executable(&config.executable_path, &config.executable_command).into_result()?;
+    let report = match self
+      .plugin_manager
+      .reap_orphans(
+        &executable,
+        config.writable_directory().map(PathBuf::as_path),
+      )
+      .await
+    {
+      Ok(report) => report,
+      Err(PluginError::Unsupported(what)) => {
+        warn!("[AI Plugin] {} is not supported on this platform", what);
+        return Ok(());
+      },
+      Err(err) => return Err(err),
+    };
+    if !report.killed.is_empty() {
+      info!("[AI Plugin] killed orphaned plugins: {:?}", report.killed);
+    }
+    match report.locked_by {
+      Some(pid) => Err(PluginError::PersistDirectoryLocked(pid)),
+      None => Ok(()),
+    }
+  }
+
+  /// Stops plugins older than `minimum`. Versions that are not semver, e.g. of development
+  /// builds, are let through.
+  pub(crate) async fn check_plugin_version(
+    &self,
+    plugin_info: &PluginInfo,
+    minimum: &Version,
+  ) -> Result<(), PluginError> {
+    let found = match parse_plugin_version(&plugin_info.version) {
+      Some(found) => found,
+      None => {
+        warn!(
+          "[AI Plugin] cannot parse plugin version {:?}, skip the minimum version check",
+          plugin_info.version
         );
-        let plugin = self.plugin_manager.init_plugin(plugin_id, params).await?;
-        info!("[AI Plugin] {} setup success", plugin);
-        self.plugin_config.write().await.replace(config);
-
-        let mut rx = plugin.subscribe_running_state();
-        let weak_plugin = Arc::downgrade(&plugin);
-        let timeout_duration = Duration::from_secs(30);
-        let _ = timeout(timeout_duration, async {
-          while let Some(state) = rx.next().await {
-            if state.is_running() {
-              let operation = AIPluginOperation::new(weak_plugin);
-              if let Ok(info) = operation.plugin_info().await {
-                info!("[AI Plugin] using plugin version: {}", info.version);
-              }
-              break;
-            }
-          }
-        })
-        .await;
+        return Ok(());
+      },
+    };
+    if found.cmp_precedence(minimum).is_ge() {
+      return Ok(());
+    }
+    if let Err(err) = self.destroy_plugin().await {
+      error!("[AI Plugin] Failed to destroy plugin: {:?}", err);
+    }
+    Err(PluginError::PluginOutdated {
+      found: plugin_info.version.clone(),
+      minimum: minimum.to_string(),
+      download_hint: PLUGIN_DOWNLOAD_HINT.to_string(),
+    })
+  }
 
-        Ok(())
+  /// Records the protocol of the plugin from its `system_info`, and stops plugins older than
+  /// `min_protocol_version`.
+  pub(crate) async fn negotiate_protocol(
+    &self,
+    plugin_info: Option<PluginInfo>,
+    min_protocol_version: u32,
+  ) -> Result<(), PluginError> {
+    let found = match plugin_info {
+      Some(plugin_info) => {
+        info!(
+          "[AI Plugin] using plugin version: {}, protocol: {}",
+          plugin_info.version, plugin_info.protocol_version
+        );
+        let found = plugin_info.protocol_version;
+        self.set_plugin_info(Some(plugin_info)).await;
+        found
       },
-      Err(_) => {
-        // Lock is already held – an initialization is in progress.
-        trace!("[AI Plugin] Initialization already in progress, returning immediately");
-        Ok(())
+      None => {
+        warn!(
+          "[AI Plugin] plugin did not report its protocol, assuming {}",
+          DEFAULT_PROTOCOL_VERSION
+        );
+        DEFAULT_PROTOCOL_VERSION
       },
+    };
+    self.protocol_version.store(found, Ordering::SeqCst);
+
+    if found < min_protocol_version {
+      if let Err(err) = self.destroy_plugin().await {
+        error!("[AI Plugin] Failed to destroy plugin: {:?}", err);
+      }
+      return Err(PluginError::IncompatiblePlugin {
+        required: min_protocol_version,
+        found,
+      });
     }
+    Ok(())
   }
 
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
   pub async fn generate_embedding(&self, text: &str) -> Result<Vec<Vec<f64>>, PluginError> {
+    let trace_id = start_trace();
     trace!("[AI Plugin] generate embedding for text: {}", text);
     self.wait_until_plugin_ready().await?;
     let plugin = self.get_ai_plugin().await?;
-    let operation = EmbeddingPluginOperation::new(plugin);
+    let operation = self.embedding_operation(plugin).with_trace_id(&trace_id);
     let embeddings = operation.gen_embeddings(text).await?;
     Ok(embeddings)
   }
 
+  /// Returns the configured embedding model and the dimension of its vectors.
+  ///
+  /// The dimension is found by embedding a probe string once; the result is cached and recorded
+  /// in the persist directory manifest if there is none yet.
+  pub async fn embedding_model_info(&self) -> Result<EmbeddingModelInfo, PluginError> {
+    if let Some(info) = self.embedding_model_info.read().await.clone() {
+      return Ok(info);
+    }
+
+    let config = self
+      .plugin_config
+      .read()
+      .await
+      .clone()
+      .ok_or_else(|| PluginError::Internal(anyhow!("chat plugin not initialized")))?;
+    let embeddings = self.generate_embedding(EMBEDDING_PROBE_TEXT).await?;
+    let dimension = embeddings
+      .first()
+      .map(Vec::len)
+      .ok_or_else(|| PluginError::Internal(anyhow!("embedding model returned no vector")))?;
+    let info = EmbeddingModelInfo {
+      model: config.embedding_model_name.clone(),
+      dimension,
+    };
+
+    if let Some(directory) = config.writable_directory() {
+      if read_manifest(directory)?.is_none() {
+        write_manifest(directory, &info)?;
+      }
+    }
+    self
+      .embedding_model_info
+      .write()
+      .await
+      .replace(info.clone());
+    Ok(info)
+  }
+
+  /// Embeds `text` with `metadata`. Texts with a `chat_id` and a `source_id` are listed as
+  /// attachments of the chat. With [EPHEMERAL_KEY](crate::attachment::EPHEMERAL_KEY) set to
+  /// `true`, the text is only kept until the chat is closed, see [OllamaAIPlugin::close_chat];
+  /// such texts need a `chat_id`, and get a generated `source_id` when they have none.
   pub async fn embed_text(
     &self,
     text: &str,
     metadata: HashMap<String, Value>,
   ) -> Result<(), PluginError> {
-    trace!("[AI Plugin] generate embedding for text: {}", text);
-    self.wait_until_plugin_ready().await?;
-    let plugin = self.get_ai_plugin().await?;
-    let operation = EmbeddingPluginOperation::new(plugin);
-    operation.embed_text(text, metadata).await?;
-    Ok(())
+    self
+      .embed_text_with_priority(text, metadata, Priority::Background)
+      .await
   }
 
-  pub async fn similarity_search(
+  /// Same as [OllamaAIPlugin::embed_text], dispatched in the lane of `priority`.
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn embed_text_with_priority(
     &self,
-    query: &str,
-    filter: HashMap<String, Value>,
-  ) -> Result<Vec<String>, PluginError> {
-    trace!("[Embedding Plugin] similarity search for query: {}", query);
+    text: &str,
+    metadata: HashMap<String, Value>,
+    priority: Priority,
+  ) -> Result<(), PluginError> {
+    let trace_id = start_trace();
+    self.check_store_writable().await?;
+    let text = self.filter_outbound(text, RequestKind::Embedding)?;
+    let mut metadata = metadata;
+    let ephemeral = is_ephemeral(&metadata);
+    if ephemeral {
+      if !metadata.contains_key("chat_id") {
+        return Err(PluginError::Internal(anyhow!(
+          "ephemeral texts must be embedded into a chat"
+        )));
+      }
+      metadata
+        .entry(SOURCE_ID_KEY.to_string())
+        .or_insert_with(|| json!(uuid::Uuid::new_v4().to_string()));
+    }
+    trace!("[AI Plugin] generate embedding for text: {}", text);
     self.wait_until_plugin_ready().await?;
+    let _permit = self.scheduler.acquire(priority).await;
+    self.embedding_model_info().await?;
+    let _store = self.read_vector_store().await?;
     let plugin = self.get_ai_plugin().await?;
-    let operation = EmbeddingPluginOperation::new(plugin);
-    let result = operation.similarity_search(query, filter).await?;
-    Ok(result)
+    let operation = self
+      .embedding_operation(plugin)
+      .with_trace_id(&trace_id)
+      .with_priority(priority);
+    // Texts embedded into a chat with a source are listed as attachments of the chat.
+    let attachment = match (
+      metadata.get("chat_id").and_then(|v| v.as_str()),
+      metadata.get(SOURCE_ID_KEY).and_then(|v| v.as_str()),
+    ) {
+      (Some(chat_id), Some(source_id)) => {
+        let name = metadata
+          .get(FILE_NAME_KEY)
+          .and_then(|v| v.as_str())
+          .unwrap_or(source_id);
+        Some((chat_id.to_string(), source_id.to_string(), name.to_string()))
+      },
+      _ => None,
+    };
+    let chat_id = metadata.get("chat_id").and_then(|v| v.as_str());
+    let usage = self
+      .usage
+      .start(UsageKind::Embedding, chat_id, text.chars().count());
+    let audit = self
+      .index_audit
+      .start(AuditOperation::EmbedText, &metadata, || {
+        AuditContent::Text(text.to_string())
+      });
+    let result = operation.embed_text(&text, metadata).await;
+    finish_usage(usage, &result, operation.retries(), |_| 0);
+    finish_audit(audit, &result);
+    result?;
+    if let Some((chat_id, source_id, name)) = attachment {
+      self
+        .record_attachment(&chat_id, &source_id, &name, ephemeral)
+        .await;
+    }
+    Ok(())
   }
 
   /// Waits for the plugin to be ready.
@@ -369,7 +1688,7 @@ impl OllamaAIPlugin {
   /// # Returns
   ///
   /// A `Result<()>` indicating success or failure.
-  async fn wait_until_plugin_ready(&self) -> Result<()> {
+  pub(crate) async fn wait_until_plugin_ready(&self) -> Result<()> {
     let is_loading = self.running_state.borrow().is_loading();
     if !is_loading {
       return Ok(());
@@ -391,35 +1710,270 @@ impl OllamaAIPlugin {
     }
   }
 
+  /// Returns the requests that were sent right before the plugin last terminated unexpectedly.
+  ///
+  /// Requires the crash journal to be enabled with [OllamaPluginConfig::with_crash_journal].
+  pub async fn last_crash_report(&self) -> Option<CrashReport> {
+    let dir = self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()?
+      .crash_journal_dir
+      .clone()?;
+    match read_crash_report(&dir, CRASH_REPORT_REQUESTS) {
+      Ok(report) => report,
+      Err(err) => {
+        error!("[AI Plugin] failed to read crash journal: {:?}", err);
+        None
+      },
+    }
+  }
+
   /// Retrieves the chat plugin.
   ///
   /// # Returns
   ///
   /// A `Result<Weak<Plugin>>` containing a weak reference to the plugin.
   pub async fn get_ai_plugin(&self) -> Result<Weak<Plugin>, PluginError> {
-    let plugin_id = self
-      .plugin_id
-      .lock()
-      .await
+    if let Some(plugin) = self.plugin_slot.get() {
+      return Ok(plugin);
+    }
+
+    // The plugin was replaced, stopped or not initialized yet: resolve it again. `plugin_id`
+    // stays locked until the slot is set, so a concurrent destroy clears it after.
+    let plugin_id = self.plugin_id.lock().await;
+    let id = plugin_id
       .as_ref()
       .cloned()
       .ok_or_else(|| PluginError::Internal(anyhow!("chat plugin not initialized")))?;
-
-    let plugin = self.plugin_manager.get_plugin(plugin_id).await?;
+    let plugin = self.plugin_manager.get_plugin(id).await?;
+    self.plugin_slot.set(id, plugin.clone());
     Ok(plugin)
   }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+async fn apply_log_level(
+  plugin_manager: &PluginManager,
+  log_level: &parking_lot::Mutex<LogLevelState>,
+  plugin_id: PluginId,
+) -> Result<(), PluginError> {
+  // Claimed before sending so a concurrent call doesn't send the same level again.
+  let (level, previous) = {
+    let mut state = log_level.lock();
+    match state.requested {
+      Some(level) if state.applied != Some(level) => (level, state.applied.replace(level)),
+      _ => return Ok(()),
+    }
+  };
+
+  let result = match plugin_manager.get_plugin(plugin_id).await {
+    Ok(plugin) => AIPluginOperation::new(plugin).set_log_level(level).await,
+    Err(err) => Err(err),
+  };
+  if result.is_err() {
+    let mut state = log_level.lock();
+    if state.applied == Some(level) {
+      state.applied = previous;
+    }
+  }
+  result
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+  Error,
+  Warn,
+  #[default]
+  Info,
+  Debug,
+  Trace,
+}
+
+impl LogLevel {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LogLevel::Error => "error",
+      LogLevel::Warn => "warn",
+      LogLevel::Info => "info",
+      LogLevel::Debug => "debug",
+      LogLevel::Trace => "trace",
+    }
+  }
+}
+
+impl Display for LogLevel {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for LogLevel {
+  type Err = PluginError;
+
+  /// Accepts the level names the config used to take as plain strings, in any case.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_lowercase().as_str() {
+      "error" => Ok(LogLevel::Error),
+      "warn" | "warning" => Ok(LogLevel::Warn),
+      "info" => Ok(LogLevel::Info),
+      "debug" => Ok(LogLevel::Debug),
+      "trace" => Ok(LogLevel::Trace),
+      _ => Err(PluginError::InvalidLogLevel(s.to_string())),
+    }
+  }
+}
+
+impl TryFrom<&str> for LogLevel {
+  type Error = PluginError;
+
+  fn try_from(s: &str) -> Result<Self, PluginError> {
+    s.parse()
+  }
+}
+
+impl TryFrom<String> for LogLevel {
+  type Error = PluginError;
+
+  fn try_from(s: String) -> Result<Self, PluginError> {
+    s.parse()
+  }
+}
+
+/// Checks the embedding model of the stores of `config` and locks the writable one. A read-only
+/// store is never purged, so an embedding model mismatch is an error there even with
+/// [MismatchPolicy::Reindex].
+pub(crate) fn prepare_store_directories(
+  config: &OllamaPluginConfig,
+  plugin_manager: &PluginManager,
+) -> Result<(), PluginError> {
+  if let Some(persist_directory) = config.persist_directory.as_ref() {
+    let on_mismatch = match config.on_mismatch {
+      MismatchPolicy::Reindex if config.read_only => MismatchPolicy::Error,
+      on_mismatch => on_mismatch,
+    };
+    check_manifest(persist_directory, &config.embedding_model_name, on_mismatch)?;
+  }
+  if let (true, Some(overlay_directory)) = (config.read_only, &config.overlay_directory) {
+    if !overlay_directory.exists() {
+      std::fs::create_dir_all(overlay_directory)?;
+    }
+    check_manifest(
+      overlay_directory,
+      &config.embedding_model_name,
+      config.on_mismatch,
+    )?;
+  }
+  if let Some(directory) = config.writable_directory() {
+    plugin_manager.lock_persist_directory(directory)?;
+  }
+  Ok(())
+}
+
+#[derive(Eq, PartialEq, Clone)]
 pub struct OllamaPluginConfig {
   pub executable_path: PathBuf,
   pub executable_command: String,
   pub chat_model_name: String,
   pub embedding_model_name: String,
   pub server_url: String,
+  /// Credentials of `server_url`, if it requires authentication.
+  pub auth: Option<OllamaAuth>,
   pub persist_directory: Option<PathBuf>,
   pub verbose: bool,
-  pub log_level: String,
+  pub log_level: LogLevel,
+  /// Applied at init when `persist_directory` was indexed with another embedding model.
+  pub on_mismatch: MismatchPolicy,
+  pub crash_journal_dir: Option<PathBuf>,
+  /// Time the first frame of an answer may take before a `{"warming_up": true}` metadata frame
+  /// is sent, see [OllamaAIPlugin::stream_question].
+  pub warming_up_threshold: Duration,
+  /// Time an answer or completion stream may go without frames before the plugin is pinged, see
+  /// [OllamaPluginConfig::with_keep_alive_interval]. `None` never pings.
+  pub keep_alive_interval: Option<Duration>,
+  /// Oldest plugin protocol accepted at init, see [OllamaAIPlugin::negotiated_protocol].
+  pub min_protocol_version: u32,
+  /// Kill the plugin processes left behind by previous runs at init, see
+  /// [PluginManager::reap_orphans].
+  pub kill_orphaned_instances: bool,
+  /// Rows sent in one `database_query` request, see [OllamaAIPlugin::query_database].
+  pub database_query_chunk_rows: usize,
+  /// Whether the plugin process also serves the embedding plugins attached to it, see
+  /// [OllamaPluginConfig::with_embedded_embedding].
+  pub embedded_embedding: bool,
+  /// Variables set in the environment of the plugin process, see [PluginConfig::env].
+  pub env: HashMap<String, String>,
+  /// Whether the plugin process starts from the environment of the host.
+  pub inherit_env: bool,
+  /// Directory the plugin process runs in, the one of the host if not set.
+  pub working_dir: Option<PathBuf>,
+  /// Channel of the RPC messages, see [OllamaPluginConfig::with_transport].
+  pub transport: TransportKind,
+  /// JSON lines file the embedded and deleted documents are recorded to, see
+  /// [OllamaPluginConfig::with_index_audit_log].
+  pub index_audit_log: Option<PathBuf>,
+  /// Size past which the index audit log is rotated.
+  pub index_audit_max_bytes: u64,
+  /// `num_ctx` of the chat model, see [OllamaPluginConfig::with_context_window].
+  pub context_window: Option<u32>,
+  /// Create the chat of a question when the plugin doesn't have it, see
+  /// [OllamaPluginConfig::with_auto_create_chat].
+  pub auto_create_chat: bool,
+  /// Oldest plugin version accepted at init, see
+  /// [OllamaPluginConfig::with_minimum_plugin_version].
+  pub minimum_plugin_version: Version,
+  /// Skip the `minimum_plugin_version` check.
+  pub allow_outdated: bool,
+  /// Limits applied to the plugin process, see [OllamaPluginConfig::with_process_limits].
+  pub process_limits: ProcessLimits,
+  /// Whether `persist_directory` is only searched, never written, see
+  /// [OllamaPluginConfig::with_read_only].
+  pub read_only: bool,
+  /// Writable store searched together with a read-only `persist_directory`, see
+  /// [OllamaPluginConfig::with_overlay_directory].
+  pub overlay_directory: Option<PathBuf>,
+  /// Time the plugin process may take to exit before it is killed, see
+  /// [OllamaAIPlugin::destroy_plugin].
+  pub teardown_grace_period: Duration,
+}
+
+impl Debug for OllamaPluginConfig {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("OllamaPluginConfig")
+      .field("executable_path", &self.executable_path)
+      .field("executable_command", &self.executable_command)
+      .field("chat_model_name", &self.chat_model_name)
+      .field("embedding_model_name", &self.embedding_model_name)
+      .field("server_url", &self.server_url)
+      .field("auth", &self.auth)
+      .field("persist_directory", &self.persist_directory)
+      .field("verbose", &self.verbose)
+      .field("log_level", &self.log_level)
+      .field("on_mismatch", &self.on_mismatch)
+      .field("crash_journal_dir", &self.crash_journal_dir)
+      .field("warming_up_threshold", &self.warming_up_threshold)
+      .field("keep_alive_interval", &self.keep_alive_interval)
+      .field("min_protocol_version", &self.min_protocol_version)
+      .field("kill_orphaned_instances", &self.kill_orphaned_instances)
+      .field("database_query_chunk_rows", &self.database_query_chunk_rows)
+      .field("embedded_embedding", &self.embedded_embedding)
+      .field("env", &RedactedEnv(&self.env))
+      .field("inherit_env", &self.inherit_env)
+      .field("working_dir", &self.working_dir)
+      .field("transport", &self.transport)
+      .field("index_audit_log", &self.index_audit_log)
+      .field("index_audit_max_bytes", &self.index_audit_max_bytes)
+      .field("context_window", &self.context_window)
+      .field("auto_create_chat", &self.auto_create_chat)
+      .field("minimum_plugin_version", &self.minimum_plugin_version)
+      .field("allow_outdated", &self.allow_outdated)
+      .field("process_limits", &self.process_limits)
+      .field("read_only", &self.read_only)
+      .field("overlay_directory", &self.overlay_directory)
+      .field("teardown_grace_period", &self.teardown_grace_period)
+      .finish()
+  }
 }
 
 impl OllamaPluginConfig {
@@ -437,8 +1991,31 @@ impl OllamaPluginConfig {
       embedding_model_name,
       persist_directory: None,
       server_url: server_url.unwrap_or("http://localhost:11434".to_string()),
+      auth: None,
       verbose: false,
-      log_level: "info".to_string(),
+      log_level: LogLevel::default(),
+      on_mismatch: MismatchPolicy::default(),
+      crash_journal_dir: None,
+      warming_up_threshold: DEFAULT_WARMING_UP_THRESHOLD,
+      keep_alive_interval: Some(DEFAULT_KEEP_ALIVE_INTERVAL),
+      min_protocol_version: DEFAULT_PROTOCOL_VERSION,
+      kill_orphaned_instances: false,
+      database_query_chunk_rows: DEFAULT_DATABASE_QUERY_CHUNK_ROWS,
+      embedded_embedding: false,
+      env: HashMap::new(),
+      inherit_env: true,
+      working_dir: None,
+      transport: TransportKind::default(),
+      index_audit_log: None,
+      index_audit_max_bytes: DEFAULT_INDEX_AUDIT_MAX_BYTES,
+      context_window: None,
+      auto_create_chat: false,
+      minimum_plugin_version: DEFAULT_MINIMUM_PLUGIN_VERSION,
+      allow_outdated: false,
+      process_limits: ProcessLimits::default(),
+      read_only: false,
+      overlay_directory: None,
+      teardown_grace_period: DEFAULT_TEARDOWN_GRACE_PERIOD,
     })
   }
   pub fn with_verbose(mut self, verbose: bool) -> Self {
@@ -446,11 +2023,201 @@ impl OllamaPluginConfig {
     self
   }
 
-  pub fn set_log_level(&mut self, log_level: String) {
-    self.log_level = log_level;
+  pub fn with_auth(mut self, auth: OllamaAuth) -> Self {
+    self.auth = Some(auth);
+    self
+  }
+
+  pub fn with_warming_up_threshold(mut self, threshold: Duration) -> Self {
+    self.warming_up_threshold = threshold;
+    self
+  }
+
+  /// Pings the plugin each time an answer or completion stream goes `interval` without frames,
+  /// sending a `{"keep_alive": true}` metadata frame while it answers, and ending the stream with
+  /// [StreamErrorKind::PluginDied] when it doesn't, see
+  /// [keep_alive_stream](crate::keep_alive::keep_alive_stream). `None` turns the pings off.
+  pub fn with_keep_alive_interval(mut self, interval: Option<Duration>) -> Self {
+    self.keep_alive_interval = interval;
+    self
+  }
+
+  /// Runs the embedding model `model_name` in the chat plugin process, so the embedding plugins
+  /// created with [EmbeddingPlugin::attached](crate::embedding_plugin::EmbeddingPlugin::attached)
+  /// don't need a process of their own. Saves the memory of a second interpreter on low-RAM
+  /// machines.
+  pub fn with_embedded_embedding(mut self, model_name: String) -> Self {
+    self.embedding_model_name = model_name;
+    self.embedded_embedding = true;
+    self
+  }
+
+  /// Sets `name` in the environment of the plugin process, e.g. `HTTP_PROXY`. Values that look
+  /// like secrets are redacted from the logs.
+  pub fn with_env(mut self, name: &str, value: &str) -> Self {
+    self.env.insert(name.to_string(), value.to_string());
+    self
+  }
+
+  /// Starts the plugin process with only the variables set by [OllamaPluginConfig::with_env]
+  /// when `inherit` is false.
+  pub fn with_inherit_env(mut self, inherit: bool) -> Self {
+    self.inherit_env = inherit;
+    self
+  }
+
+  /// Runs the plugin process in `dir`, which must exist when the plugin is initialized.
+  pub fn with_working_dir(mut self, dir: PathBuf) -> Self {
+    self.working_dir = Some(dir);
+    self
+  }
+
+  /// Talks to the plugin over a named pipe, or a Unix domain socket, instead of its stdio, see
+  /// [TransportKind::NamedPipe]. The plugin must support the `--rpc-pipe` argument.
+  pub fn with_transport(mut self, transport: TransportKind) -> Self {
+    self.transport = transport;
+    self
+  }
+
+  /// Records every text and file embedded, and every deletion from the vector store, to the
+  /// JSON lines file at `path`: the time, the operation, the `object_id` and `source_id` of the
+  /// document, the length and SHA-256 of the content and whether the plugin succeeded. The
+  /// content itself is never written. See [OllamaAIPlugin::read_index_audit].
+  pub fn with_index_audit_log(mut self, path: PathBuf) -> Self {
+    self.index_audit_log = Some(path);
+    self
+  }
+
+  /// Moves the index audit log to `<path>.1`, `<path>.2` and so on once it grows past
+  /// `max_bytes`. Rotated files are kept.
+  pub fn with_index_audit_max_bytes(mut self, max_bytes: u64) -> Self {
+    self.index_audit_max_bytes = max_bytes;
+    self
+  }
+
+  /// Tokens the chat model sees of a chat, used to estimate [OllamaAIPlugin::chat_budget]. When
+  /// not set, it is looked up from the family of the chat model with
+  /// [default_context_window].
+  pub fn with_context_window(mut self, tokens: u32) -> Self {
+    self.context_window = Some(tokens);
+    self
+  }
+
+  /// [OllamaPluginConfig::context_window], or the default one of the chat model.
+  pub fn effective_context_window(&self) -> u32 {
+    self
+      .context_window
+      .unwrap_or_else(|| default_context_window(&self.chat_model_name))
+  }
+
+  /// Databases with more rows are queried in chunks by [OllamaAIPlugin::query_database].
+  pub fn with_database_query_chunk_rows(mut self, rows: usize) -> Self {
+    self.database_query_chunk_rows = rows;
+    self
+  }
+
+  /// Fails [OllamaAIPlugin::init_plugin] with [PluginError::IncompatiblePlugin] when the plugin
+  /// speaks an older protocol.
+  pub fn with_min_protocol_version(mut self, version: u32) -> Self {
+    self.min_protocol_version = version;
+    self
+  }
+
+  /// Creates the chat of [OllamaAIPlugin::stream_question] and [OllamaAIPlugin::ask_question]
+  /// with its [ChatSettings] first when the plugin doesn't have it, e.g. because it restarted
+  /// since the chat was created, see [OllamaAIPlugin::ensure_chat].
+  pub fn with_auto_create_chat(mut self, auto_create: bool) -> Self {
+    self.auto_create_chat = auto_create;
+    self
+  }
+
+  /// Fails [OllamaAIPlugin::init_plugin] with [PluginError::PluginOutdated] when the plugin
+  /// reports an older version. Pre-releases are older than their release, and build metadata is
+  /// ignored.
+  pub fn with_minimum_plugin_version(mut self, version: Version) -> Self {
+    self.minimum_plugin_version = version;
+    self
+  }
+
+  /// Starts plugins older than [OllamaPluginConfig::with_minimum_plugin_version] anyway.
+  pub fn allow_outdated(mut self, allow: bool) -> Self {
+    self.allow_outdated = allow;
+    self
+  }
+
+  /// Runs the plugin process with `limits`, e.g. a positive nice value so indexing doesn't slow
+  /// down the rest of the machine. The limits that can't be applied are skipped with a warning,
+  /// [PluginDiagnostics::process_limits](crate::diagnostics::PluginDiagnostics::process_limits) holds the ones that were.
+  pub fn with_process_limits(mut self, limits: ProcessLimits) -> Self {
+    self.process_limits = limits;
+    self
+  }
+
+  /// Opens `persist_directory` read-only, e.g. a pre-built store of the company handbook mounted
+  /// on every machine. It is searched and used by chats, while indexing fails with
+  /// [PluginError::ReadOnlyStore] unless an [OllamaPluginConfig::with_overlay_directory] is set.
+  /// Set it before [OllamaPluginConfig::set_rag_enabled], which then doesn't create the
+  /// directory.
+  pub fn with_read_only(mut self, read_only: bool) -> Self {
+    self.read_only = read_only;
+    self
+  }
+
+  /// Indexes into the store in `dir`, created if needed, when `persist_directory` is
+  /// [read-only](OllamaPluginConfig::with_read_only). The plugin searches both stores together.
+  pub fn with_overlay_directory(mut self, dir: PathBuf) -> Self {
+    self.overlay_directory = Some(dir);
+    self
+  }
+
+  /// The store directory indexing writes to: the overlay of a read-only `persist_directory`, or
+  /// `persist_directory` itself.
+  pub fn writable_directory(&self) -> Option<&PathBuf> {
+    if self.read_only {
+      self.overlay_directory.as_ref()
+    } else {
+      self.persist_directory.as_ref()
+    }
+  }
+
+  pub fn with_kill_orphaned_instances(mut self, kill: bool) -> Self {
+    self.kill_orphaned_instances = kill;
+    self
+  }
+
+  /// Time the plugin process may take to exit once asked to shut down, before it is killed.
+  /// Defaults to [DEFAULT_TEARDOWN_GRACE_PERIOD].
+  pub fn with_teardown_grace_period(mut self, grace: Duration) -> Self {
+    self.teardown_grace_period = grace;
+    self
+  }
+
+  /// Journals every request sent to the plugin in `dir`, so [OllamaAIPlugin::last_crash_report]
+  /// can tell what was in flight when the plugin died.
+  pub fn with_crash_journal(mut self, dir: PathBuf) -> Self {
+    self.crash_journal_dir = Some(dir);
+    self
+  }
+
+  /// Log level the plugin starts with, a [LogLevel] or its name such as `"debug"`. An unknown
+  /// name keeps the current level. Use [OllamaAIPlugin::set_plugin_log_level] to change it
+  /// while the plugin is running.
+  pub fn set_log_level<L>(&mut self, log_level: L)
+  where
+    L: TryInto<LogLevel>,
+    L::Error: Display,
+  {
+    match log_level.try_into() {
+      Ok(log_level) => self.log_level = log_level,
+      Err(err) => warn!("[AI Plugin] keeping log level {}: {}", self.log_level, err),
+    }
+  }
+
+  pub fn set_on_mismatch(&mut self, on_mismatch: MismatchPolicy) {
+    self.on_mismatch = on_mismatch;
   }
   pub fn set_rag_enabled(&mut self, persist_directory: &PathBuf) -> Result<()> {
-    if !persist_directory.exists() {
+    if !self.read_only && !persist_directory.exists() {
       std::fs::create_dir_all(persist_directory)?;
     }
 
@@ -0,0 +1,208 @@
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use anyhow::Result;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// The kind of request a text is sent with, see [OutboundFilter::filter].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+  /// A question asked in a chat, or a text to suggest questions about.
+  Question,
+  /// A text to complete or rewrite.
+  Completion,
+  /// A text to embed, or a similarity search query.
+  Embedding,
+  /// The cells of a database row to summarize or translate.
+  DatabaseRow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+  Allow,
+  /// Send this text instead.
+  Redact(String),
+  /// Don't send the request, failing it with [PluginError::BlockedByPolicy].
+  Block(String),
+}
+
+/// Inspects every text before it is sent to the plugin, and thus to the Ollama server, which may
+/// run on another machine. Set with
+/// [OllamaAIPlugin::set_outbound_filter](crate::ollama_plugin::OllamaAIPlugin::set_outbound_filter).
+///
+/// Files embedded with `embed_file` are read by the plugin and are not filtered.
+pub trait OutboundFilter: Send + Sync {
+  fn filter(&self, text: &str, kind: RequestKind) -> FilterDecision;
+}
+
+impl<F> OutboundFilter for F
+where
+  F: Fn(&str, RequestKind) -> FilterDecision + Send + Sync,
+{
+  fn filter(&self, text: &str, kind: RequestKind) -> FilterDecision {
+    self(text, kind)
+  }
+}
+
+/// Runs `text` through `filter`, if any.
+pub(crate) fn apply_filter<'a>(
+  filter: Option<&dyn OutboundFilter>,
+  text: &'a str,
+  kind: RequestKind,
+) -> Result<Cow<'a, str>, PluginError> {
+  let filter = match filter {
+    Some(filter) => filter,
+    None => return Ok(Cow::Borrowed(text)),
+  };
+  match filter.filter(text, kind) {
+    FilterDecision::Allow => Ok(Cow::Borrowed(text)),
+    FilterDecision::Redact(text) => Ok(Cow::Owned(text)),
+    FilterDecision::Block(reason) => Err(PluginError::BlockedByPolicy(reason)),
+  }
+}
+
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+const API_KEY_PATTERN: &str = r"\b(?:(?:sk|pk|rk)-[A-Za-z0-9_-]{16,}|AKIA[0-9A-Z]{16}|gh[pousr]_[A-Za-z0-9]{36,}|xox[abprs]-[A-Za-z0-9-]{10,})\b";
+const CARD_NUMBER_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+
+struct PatternRule {
+  name: String,
+  regex: Regex,
+  replacement: String,
+  /// Rejects matches that only look like the pattern, such as digits that are not a card number.
+  validate: Option<fn(&str) -> bool>,
+}
+
+/// An [OutboundFilter] that replaces the matches of regular expressions, or blocks texts that
+/// contain any with [PatternFilter::block_on_match].
+#[derive(Default)]
+pub struct PatternFilter {
+  rules: Vec<PatternRule>,
+  block: bool,
+}
+
+impl PatternFilter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Redacts emails, common API key formats and credit card numbers.
+  pub fn pii() -> Self {
+    let mut filter = Self::new()
+      .with_pattern("email", EMAIL_PATTERN, "[EMAIL]")
+      .and_then(|filter| filter.with_pattern("api key", API_KEY_PATTERN, "[API_KEY]"))
+      .and_then(|filter| filter.with_pattern("card number", CARD_NUMBER_PATTERN, "[CARD_NUMBER]"))
+      .expect("built-in patterns are valid");
+    if let Some(rule) = filter.rules.last_mut() {
+      rule.validate = Some(is_card_number);
+    }
+    filter
+  }
+
+  /// Replaces the matches of `pattern` with `replacement`. `name` is reported when the text is
+  /// blocked.
+  pub fn with_pattern(
+    mut self,
+    name: &str,
+    pattern: &str,
+    replacement: &str,
+  ) -> Result<Self, regex::Error> {
+    self.rules.push(PatternRule {
+      name: name.to_string(),
+      regex: Regex::new(pattern)?,
+      replacement: replacement.to_string(),
+      validate: None,
+    });
+    Ok(self)
+  }
+
+  /// Blocks texts that contain a match instead of redacting them.
+  pub fn block_on_match(mut self) -> Self {
+    self.block = true;
+    self
+  }
+}
+
+impl OutboundFilter for PatternFilter {
+  fn filter(&self, text: &str, _kind: RequestKind) -> FilterDecision {
+    let mut redacted = text.to_string();
+    let mut changed = false;
+    for rule in &self.rules {
+      let mut matched = false;
+      let replaced = rule.regex.replace_all(&redacted, |captures: &Captures| {
+        let found = &captures[0];
+        let valid = match rule.validate {
+          Some(validate) => validate(found),
+          None => true,
+        };
+        if valid {
+          matched = true;
+          rule.replacement.clone()
+        } else {
+          found.to_string()
+        }
+      });
+      if !matched {
+        continue;
+      }
+      if self.block {
+        return FilterDecision::Block(format!("text contains {}", rule.name));
+      }
+      redacted = replaced.into_owned();
+      changed = true;
+    }
+
+    if changed {
+      FilterDecision::Redact(redacted)
+    } else {
+      FilterDecision::Allow
+    }
+  }
+}
+
+/// Whether the last digit of `text` is the Luhn check digit of the others, as in card numbers.
+fn is_card_number(text: &str) -> bool {
+  let mut digits = text
+    .chars()
+    .filter_map(|c| c.to_digit(10))
+    .collect::<Vec<_>>();
+  if !(13..=19).contains(&digits.len()) {
+    return false;
+  }
+  let check_digit = digits.pop().unwrap_or_default();
+  let sum: u32 = digits
+    .iter()
+    .rev()
+    .enumerate()
+    .map(|(index, digit)| match index % 2 {
+      0 if *digit * 2 > 9 => *digit * 2 - 9,
+      0 => *digit * 2,
+      _ => *digit,
+    })
+    .sum();
+  (10 - sum % 10) % 10 == check_digit
+}
+
+impl OllamaAIPlugin {
+  /// Runs the questions, completions, embedded texts and database rows sent to the plugin
+  /// through `filter`, which can redact them or block the request with
+  /// [PluginError::BlockedByPolicy]. See [PatternFilter]
+  /// for a filter of common personal data.
+  pub fn set_outbound_filter(&self, filter: Arc<dyn OutboundFilter>) {
+    *self.outbound_filter.write() = Some(filter);
+  }
+
+  pub fn clear_outbound_filter(&self) {
+    self.outbound_filter.write().take();
+  }
+
+  pub(crate) fn filter_outbound<'a>(
+    &self,
+    text: &'a str,
+    kind: RequestKind,
+  ) -> Result<Cow<'a, str>, PluginError> {
+    let filter = self.outbound_filter.read().clone();
+    apply_filter(filter.as_deref(), text, kind)
+  }
+}
@@ -0,0 +1,172 @@
+use crate::ai_ops::STREAM_ANSWER_KEY;
+use af_plugin::error::PluginError;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// Answer text held by a paused [PausableStream] before its frames are merged, see
+/// [OllamaAIPlugin::stream_question_pausable](crate::ollama_plugin::OllamaAIPlugin::stream_question_pausable).
+pub const DEFAULT_PAUSE_BUFFER_BYTES: usize = 2 * 1024 * 1024;
+
+/// An answer stream that can stop yielding frames while the UI is hidden, and catch up without
+/// losing any once it is shown again.
+///
+/// While paused, the answer keeps being read from the plugin into a buffer, so the plugin is
+/// never blocked by a consumer that stopped polling. Past the buffer's size, answer frames are
+/// merged into one. Dropping the stream cancels the question.
+pub struct PausableStream {
+  stream: ReceiverStream<Result<Value, PluginError>>,
+  paused: watch::Sender<bool>,
+  waker: Mutex<Option<Waker>>,
+  task: AbortHandle,
+}
+
+impl PausableStream {
+  pub fn pause(&self) {
+    self.paused.send_replace(true);
+  }
+
+  /// Yields the frames buffered while paused, in order, then the live ones.
+  pub fn resume(&self) {
+    self.paused.send_replace(false);
+    if let Some(waker) = self.waker.lock().take() {
+      waker.wake();
+    }
+  }
+
+  pub fn is_paused(&self) -> bool {
+    *self.paused.borrow()
+  }
+}
+
+impl Stream for PausableStream {
+  type Item = Result<Value, PluginError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    if self.is_paused() {
+      *self.waker.lock() = Some(cx.waker().clone());
+      return Poll::Pending;
+    }
+    Pin::new(&mut self.stream).poll_next(cx)
+  }
+}
+
+impl Drop for PausableStream {
+  fn drop(&mut self) {
+    // Drops the answer stream, which cancels the question.
+    self.task.abort();
+  }
+}
+
+/// Wraps an answer stream into a [PausableStream] buffering up to `max_buffered_bytes` of
+/// answer text while paused.
+pub(crate) fn pausable_stream(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  max_buffered_bytes: usize,
+) -> PausableStream {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  let (paused_tx, mut paused) = watch::channel(false);
+  let task = tokio::spawn(async move {
+    let mut buffer = PauseBuffer::new(max_buffered_bytes);
+    let mut ended = false;
+    loop {
+      if !*paused.borrow_and_update() {
+        while let Some(frame) = buffer.pop() {
+          if tx.send(frame).await.is_err() {
+            return;
+          }
+        }
+        if ended {
+          return;
+        }
+      }
+      if ended {
+        // Waits to be resumed to flush the end of the answer.
+        if paused.changed().await.is_err() {
+          return;
+        }
+        continue;
+      }
+
+      tokio::select! {
+        frame = stream.next() => match frame {
+          Some(frame) if *paused.borrow() => buffer.push(frame),
+          Some(frame) => {
+            if tx.send(frame).await.is_err() {
+              return;
+            }
+          },
+          None => ended = true,
+        },
+        changed = paused.changed() => {
+          if changed.is_err() {
+            return;
+          }
+        },
+      }
+    }
+  });
+  PausableStream {
+    stream: ReceiverStream::new(rx),
+    paused: paused_tx,
+    waker: Mutex::new(None),
+    task: task.abort_handle(),
+  }
+}
+
+/// Frames read while paused.
+struct PauseBuffer {
+  frames: VecDeque<Result<Value, PluginError>>,
+  text_bytes: usize,
+  max_text_bytes: usize,
+}
+
+impl PauseBuffer {
+  fn new(max_text_bytes: usize) -> Self {
+    Self {
+      frames: VecDeque::new(),
+      text_bytes: 0,
+      max_text_bytes,
+    }
+  }
+
+  fn push(&mut self, frame: Result<Value, PluginError>) {
+    if let Some(text) = answer_only(&frame) {
+      let text = text.to_string();
+      self.text_bytes += text.len();
+      if self.text_bytes > self.max_text_bytes {
+        if let Some(Ok(Value::Object(last))) = self.frames.back_mut() {
+          if last.len() == 1 {
+            if let Some(Value::String(last)) = last.get_mut(STREAM_ANSWER_KEY) {
+              last.push_str(&text);
+              return;
+            }
+          }
+        }
+      }
+    }
+    self.frames.push_back(frame);
+  }
+
+  fn pop(&mut self) -> Option<Result<Value, PluginError>> {
+    let frame = self.frames.pop_front()?;
+    if let Some(text) = answer_only(&frame) {
+      self.text_bytes = self.text_bytes.saturating_sub(text.len());
+    }
+    Some(frame)
+  }
+}
+
+/// The text of a frame that only holds answer text.
+fn answer_only(frame: &Result<Value, PluginError>) -> Option<&str> {
+  match frame {
+    Ok(Value::Object(frame)) if frame.len() == 1 => frame.get(STREAM_ANSWER_KEY)?.as_str(),
+    _ => None,
+  }
+}
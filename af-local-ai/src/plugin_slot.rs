@@ -0,0 +1,75 @@
+use af_plugin::core::plugin::{Plugin, PluginId, RunningState};
+use arc_swap::ArcSwapOption;
+use std::sync::{Arc, Weak};
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::StreamExt;
+
+/// The plugin returned by
+/// [OllamaAIPlugin::get_ai_plugin](crate::ollama_plugin::OllamaAIPlugin::get_ai_plugin), so
+/// looking it up is a lock-free load instead of a search of the plugin manager.
+///
+/// Set once the plugin is initialized, and cleared when it is destroyed or stops. A plugin
+/// replaced without either is only dropped by the plugin manager, which [PluginSlot::get] notices.
+#[derive(Debug, Default)]
+pub(crate) struct PluginSlot {
+  current: ArcSwapOption<ResolvedPlugin>,
+}
+
+#[derive(Debug)]
+struct ResolvedPlugin {
+  id: PluginId,
+  plugin: Weak<Plugin>,
+}
+
+impl PluginSlot {
+  /// The plugin in the slot, `None` when it is empty or the plugin was dropped since.
+  pub(crate) fn get(&self) -> Option<Weak<Plugin>> {
+    let current = self.current.load();
+    let resolved = current.as_ref()?;
+    if resolved.plugin.strong_count() == 0 {
+      return None;
+    }
+    Some(resolved.plugin.clone())
+  }
+
+  pub(crate) fn set(&self, id: PluginId, plugin: Weak<Plugin>) {
+    self
+      .current
+      .store(Some(Arc::new(ResolvedPlugin { id, plugin })));
+  }
+
+  pub(crate) fn clear(&self) {
+    self.current.store(None);
+  }
+
+  /// Empties the slot if it holds the plugin `id`, leaving a plugin set since in place.
+  fn clear_plugin(&self, id: PluginId) {
+    self.current.rcu(|current| match current {
+      Some(resolved) if resolved.id == id => None,
+      current => current.clone(),
+    });
+  }
+}
+
+/// Empties `slot` once the plugin `id` stops, so the next lookup resolves the plugin again.
+pub(crate) fn clear_when_stopped(
+  slot: &Arc<PluginSlot>,
+  id: PluginId,
+  mut running_state: WatchStream<RunningState>,
+) {
+  let slot = Arc::downgrade(slot);
+  tokio::spawn(async move {
+    while let Some(state) = running_state.next().await {
+      if let RunningState::Stopped { plugin_id } | RunningState::UnexpectedStop { plugin_id, .. } =
+        state
+      {
+        if plugin_id == id {
+          if let Some(slot) = slot.upgrade() {
+            slot.clear_plugin(id);
+          }
+          return;
+        }
+      }
+    }
+  });
+}
@@ -0,0 +1,33 @@
+use semver::Version;
+
+/// Oldest plugin accepted by default, see
+/// [OllamaPluginConfig::with_minimum_plugin_version](crate::ollama_plugin::OllamaPluginConfig::with_minimum_plugin_version).
+pub const DEFAULT_MINIMUM_PLUGIN_VERSION: Version = Version::new(0, 1, 0);
+
+/// Where an outdated plugin is replaced, sent in
+/// [PluginError::PluginOutdated](af_plugin::error::PluginError::PluginOutdated).
+pub const PLUGIN_DOWNLOAD_HINT: &str =
+  "Please update the AI plugin: https://github.com/AppFlowy-IO/AppFlowy-LAI/releases/latest";
+
+/// Parses the version reported by the `system_info` of a plugin, e.g. `v0.2.1`,
+/// `0.2.1-beta.1` or `0.2.1+build.7`. A missing patch or minor is taken as zero. Returns `None`
+/// for versions that aren't semver, such as the names of development builds.
+pub fn parse_plugin_version(version: &str) -> Option<Version> {
+  let version = version.trim();
+  let version = version
+    .strip_prefix('v')
+    .or_else(|| version.strip_prefix('V'))
+    .unwrap_or(version);
+  if let Ok(parsed) = Version::parse(version) {
+    return Some(parsed);
+  }
+  // Pads `1` and `1.2`, keeping the pre-release and build metadata in place.
+  let end = version.find(['-', '+']).unwrap_or(version.len());
+  let (core, rest) = version.split_at(end);
+  let padding = match core.split('.').count() {
+    1 => ".0.0",
+    2 => ".0",
+    _ => return None,
+  };
+  Version::parse(&format!("{}{}{}", core, padding, rest)).ok()
+}
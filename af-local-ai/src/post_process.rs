@@ -0,0 +1,501 @@
+use crate::ai_ops::STREAM_ANSWER_KEY;
+pub use af_plugin::core::stream::PostProcessor;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::mem::take;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Text a [PostProcessPipeline] lets through.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Processed {
+  /// Empty while the processors hold the text back.
+  pub text: String,
+  /// The answer is complete, e.g. it reached [PostProcessor::EnforceMaxChars], and the rest of
+  /// the stream is to be dropped.
+  pub done: bool,
+}
+
+impl Processed {
+  fn text(text: String) -> Self {
+    Self { text, done: false }
+  }
+}
+
+trait TextProcessor: Send {
+  /// Processes the next piece of the answer.
+  fn push(&mut self, text: &str) -> Processed;
+
+  /// The text held back, once the answer is complete.
+  fn finish(&mut self) -> String;
+}
+
+/// Runs the answer of a completion through [PostProcessor]s as it streams, each processor
+/// rewriting the output of the previous one.
+pub struct PostProcessPipeline {
+  processors: Vec<Box<dyn TextProcessor>>,
+  done: bool,
+}
+
+impl PostProcessPipeline {
+  pub fn new(processors: &[PostProcessor]) -> Self {
+    let processors = processors
+      .iter()
+      .map(|processor| -> Box<dyn TextProcessor> {
+        match processor {
+          PostProcessor::StripCodeFences => Box::<StripCodeFences>::default(),
+          PostProcessor::EnforceMaxChars(max) => Box::new(EnforceMaxChars::new(*max)),
+          PostProcessor::EnsureSingleParagraph => Box::<EnsureSingleParagraph>::default(),
+          PostProcessor::TrimWhitespace => Box::<TrimWhitespace>::default(),
+        }
+      })
+      .collect();
+    Self {
+      processors,
+      done: false,
+    }
+  }
+
+  /// Processes the next piece of the answer. Once [Processed::done], the text pushed is ignored.
+  pub fn push(&mut self, text: &str) -> Processed {
+    if self.done {
+      return Processed {
+        text: String::new(),
+        done: true,
+      };
+    }
+    let mut text = text.to_string();
+    for index in 0..self.processors.len() {
+      let processed = self.processors[index].push(&text);
+      text = processed.text;
+      if processed.done {
+        self.done = true;
+        return Processed {
+          text: self.flush(index + 1, text),
+          done: true,
+        };
+      }
+    }
+    Processed::text(text)
+  }
+
+  /// The text the processors held back, once the answer is complete.
+  pub fn finish(&mut self) -> String {
+    if self.done {
+      return String::new();
+    }
+    self.done = true;
+    self.flush(0, String::new())
+  }
+
+  /// Feeds `text` to the processors from `from` on, taking the text each one holds.
+  fn flush(&mut self, from: usize, mut text: String) -> String {
+    for processor in &mut self.processors[from..] {
+      text = processor.push(&text).text;
+      text.push_str(&processor.finish());
+    }
+    text
+  }
+}
+
+/// Runs the whole `text`, e.g. the answer of [OllamaAIPlugin::ask_question], through
+/// `processors`.
+///
+/// [OllamaAIPlugin::ask_question]: crate::ollama_plugin::OllamaAIPlugin::ask_question
+pub fn post_process_text(text: &str, processors: &[PostProcessor]) -> String {
+  let mut pipeline = PostProcessPipeline::new(processors);
+  let mut processed = pipeline.push(text).text;
+  processed.push_str(&pipeline.finish());
+  processed
+}
+
+/// Forwards a completion stream with its answer frames rewritten by `processors`. Frames without
+/// an answer are forwarded as they are, and the stream ends early when a processor is done.
+pub(crate) fn post_processed(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  processors: &[PostProcessor],
+) -> ReceiverStream<Result<Value, PluginError>> {
+  if processors.is_empty() {
+    return stream;
+  }
+  let mut pipeline = PostProcessPipeline::new(processors);
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    while let Some(frame) = stream.next().await {
+      let mut frame = match frame {
+        Ok(frame) => frame,
+        Err(err) => {
+          let _ = tx.send(Err(err)).await;
+          return;
+        },
+      };
+      let Some(text) = frame.get(STREAM_ANSWER_KEY).and_then(Value::as_str) else {
+        if tx.send(Ok(frame)).await.is_err() {
+          return;
+        }
+        continue;
+      };
+      let processed = pipeline.push(text);
+      let answer_only = frame.as_object().is_some_and(|frame| frame.len() == 1);
+      if !(processed.text.is_empty() && answer_only) {
+        frame[STREAM_ANSWER_KEY] = json!(processed.text);
+        if tx.send(Ok(frame)).await.is_err() {
+          return;
+        }
+      }
+      if processed.done {
+        return;
+      }
+    }
+    let rest = pipeline.finish();
+    if !rest.is_empty() {
+      let _ = tx.send(Ok(json!({ STREAM_ANSWER_KEY: rest }))).await;
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// Holds the whitespace at the end of what it has seen until more text follows.
+#[derive(Default)]
+struct TrimWhitespace {
+  started: bool,
+  spaces: String,
+}
+
+impl TextProcessor for TrimWhitespace {
+  fn push(&mut self, text: &str) -> Processed {
+    let text = if self.started {
+      text
+    } else {
+      text.trim_start()
+    };
+    let content = text.trim_end();
+    if content.is_empty() {
+      self.spaces.push_str(text);
+      return Processed::default();
+    }
+    self.started = true;
+    let mut processed = take(&mut self.spaces);
+    processed.push_str(content);
+    self.spaces = text[content.len()..].to_string();
+    Processed::text(processed)
+  }
+
+  fn finish(&mut self) -> String {
+    self.spaces.clear();
+    String::new()
+  }
+}
+
+/// Replaces each run of whitespace with a line break by a single space.
+#[derive(Default)]
+struct EnsureSingleParagraph {
+  started: bool,
+  spaces: String,
+}
+
+impl TextProcessor for EnsureSingleParagraph {
+  fn push(&mut self, text: &str) -> Processed {
+    let mut processed = String::new();
+    for c in text.chars() {
+      if c.is_whitespace() {
+        self.spaces.push(c);
+        continue;
+      }
+      let spaces = take(&mut self.spaces);
+      if !spaces.contains(is_line_break) {
+        processed.push_str(&spaces);
+      } else if self.started {
+        processed.push(' ');
+      }
+      self.started = true;
+      processed.push(c);
+    }
+    Processed::text(processed)
+  }
+
+  fn finish(&mut self) -> String {
+    let spaces = take(&mut self.spaces);
+    if spaces.contains(is_line_break) {
+      String::new()
+    } else {
+      spaces
+    }
+  }
+}
+
+fn is_line_break(c: char) -> bool {
+  matches!(c, '\n' | '\r' | '\u{2028}' | '\u{2029}')
+}
+
+/// A code fence: at least three backticks or tildes.
+#[derive(Debug, Clone, Copy)]
+struct Fence {
+  marker: char,
+  len: usize,
+}
+
+impl Fence {
+  /// The fence `line` opens, if any. Backtick fences can't have backticks in their info string.
+  fn opened_by(line: &str) -> Option<Self> {
+    let line = line.trim();
+    let marker = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.chars().take_while(|c| *c == marker).count();
+    let info = &line[len * marker.len_utf8()..];
+    (len >= 3 && !(marker == '`' && info.contains('`'))).then_some(Self { marker, len })
+  }
+
+  fn is_closed_by(&self, line: &str) -> bool {
+    let line = line.trim();
+    line.chars().count() >= self.len && self.may_be_closed_by(line)
+  }
+
+  /// Whether `line`, so far, could become a closing fence.
+  fn may_be_closed_by(&self, line: &str) -> bool {
+    line.trim().chars().all(|c| c == self.marker)
+  }
+}
+
+/// Drops the fence opening the answer, then holds back the lines that could be its closing fence
+/// until more text follows. The closing fence ending the answer is dropped with the line break
+/// before it.
+enum StripCodeFences {
+  /// Reading the first line, which may open a fence.
+  Opening(String),
+  /// The answer doesn't start with a fence.
+  Plain,
+  Fenced {
+    fence: Fence,
+    /// The current line, starting with its line break, while it could be a closing fence.
+    line: String,
+    /// Lines of whitespace and closing fences, not sent yet.
+    held: String,
+    /// The current line is sent as it comes.
+    plain_line: bool,
+  },
+}
+
+impl Default for StripCodeFences {
+  fn default() -> Self {
+    StripCodeFences::Opening(String::new())
+  }
+}
+
+impl StripCodeFences {
+  fn push_fenced(&mut self, text: &str, processed: &mut String) {
+    let StripCodeFences::Fenced {
+      fence,
+      line,
+      held,
+      plain_line,
+    } = self
+    else {
+      return;
+    };
+    for c in text.chars() {
+      if *plain_line {
+        if c == '\n' {
+          *plain_line = false;
+          line.push(c);
+        } else {
+          processed.push(c);
+        }
+        continue;
+      }
+      if c == '\n' {
+        if fence.is_closed_by(line) || line.trim().is_empty() {
+          held.push_str(line);
+        } else {
+          processed.push_str(&take(held));
+          processed.push_str(line);
+        }
+        line.clear();
+        line.push(c);
+        continue;
+      }
+      line.push(c);
+      if !fence.may_be_closed_by(line) {
+        processed.push_str(&take(held));
+        processed.push_str(&take(line));
+        *plain_line = true;
+      }
+    }
+  }
+}
+
+impl TextProcessor for StripCodeFences {
+  fn push(&mut self, text: &str) -> Processed {
+    let mut processed = String::new();
+    match self {
+      StripCodeFences::Opening(opening) => {
+        opening.push_str(text);
+        let first_line = opening.trim_start();
+        let decided = !first_line.is_empty()
+          && (first_line.contains('\n')
+            || !first_line.starts_with(['`', '~'])
+            || (first_line.len() >= 3 && Fence::opened_by(first_line).is_none()));
+        if !decided {
+          return Processed::default();
+        }
+        let opening = take(opening);
+        let first_line = opening.trim_start();
+        match first_line
+          .split_once('\n')
+          .and_then(|(line, rest)| Some((Fence::opened_by(line)?, rest)))
+        {
+          Some((fence, rest)) => {
+            let rest = rest.to_string();
+            *self = StripCodeFences::Fenced {
+              fence,
+              line: String::new(),
+              held: String::new(),
+              plain_line: false,
+            };
+            self.push_fenced(&rest, &mut processed);
+          },
+          None => {
+            *self = StripCodeFences::Plain;
+            processed = opening;
+          },
+        }
+      },
+      StripCodeFences::Plain => processed.push_str(text),
+      StripCodeFences::Fenced { .. } => self.push_fenced(text, &mut processed),
+    }
+    Processed::text(processed)
+  }
+
+  fn finish(&mut self) -> String {
+    match take(self) {
+      StripCodeFences::Opening(opening) => opening,
+      StripCodeFences::Fenced {
+        fence, line, held, ..
+      } => {
+        let rest = held + &line;
+        let closed = rest.lines().any(|line| fence.is_closed_by(line))
+          && rest
+            .lines()
+            .all(|line| line.trim().is_empty() || fence.is_closed_by(line));
+        if closed {
+          String::new()
+        } else {
+          rest
+        }
+      },
+      StripCodeFences::Plain => String::new(),
+    }
+  }
+}
+
+/// Lets whole sentences through while they fit in `max` characters, holding back the sentence
+/// being written and the whitespace after the last one.
+struct EnforceMaxChars {
+  max: usize,
+  sent: usize,
+  /// Whitespace after the last sentence sent.
+  gap: String,
+  pending: String,
+  done: bool,
+}
+
+impl EnforceMaxChars {
+  fn new(max: usize) -> Self {
+    Self {
+      max,
+      sent: 0,
+      gap: String::new(),
+      pending: String::new(),
+      done: false,
+    }
+  }
+
+  /// Adds `sentence` to `processed` if it fits, otherwise the start of the first sentence, cut
+  /// at a word or grapheme boundary, and returns false.
+  fn accept(&mut self, sentence: &str, processed: &mut String) -> bool {
+    let content = sentence.trim_end();
+    if content.is_empty() {
+      self.gap.push_str(sentence);
+      return true;
+    }
+    let chars = self.gap.chars().count() + content.chars().count();
+    if self.sent + chars > self.max {
+      if self.sent == 0 {
+        processed.push_str(cut(content, self.max));
+      }
+      self.done = true;
+      return false;
+    }
+    processed.push_str(&take(&mut self.gap));
+    processed.push_str(content);
+    self.sent += chars;
+    self.gap = sentence[content.len()..].to_string();
+    true
+  }
+}
+
+impl TextProcessor for EnforceMaxChars {
+  fn push(&mut self, text: &str) -> Processed {
+    if self.done {
+      return Processed::default();
+    }
+    self.pending.push_str(text);
+    let pending = take(&mut self.pending);
+    let sentences = pending.split_sentence_bounds().collect::<Vec<_>>();
+    let mut processed = String::new();
+    let Some((last, complete)) = sentences.split_last() else {
+      return Processed::default();
+    };
+    for sentence in complete {
+      if !self.accept(sentence, &mut processed) {
+        return Processed {
+          text: processed,
+          done: true,
+        };
+      }
+    }
+    // The sentence being written only gets longer.
+    let chars = self.gap.chars().count() + last.trim_end().chars().count();
+    if self.sent + chars > self.max {
+      self.accept(last, &mut processed);
+      return Processed {
+        text: processed,
+        done: true,
+      };
+    }
+    self.pending = last.to_string();
+    Processed::text(processed)
+  }
+
+  fn finish(&mut self) -> String {
+    let mut processed = String::new();
+    if !self.done {
+      let pending = take(&mut self.pending);
+      self.accept(&pending, &mut processed);
+      self.done = true;
+    }
+    processed
+  }
+}
+
+/// The start of `text` of at most `max` characters, ending at the last whitespace if it has any,
+/// and never inside a grapheme.
+fn cut(text: &str, max: usize) -> &str {
+  let mut chars = 0;
+  let mut end = 0;
+  for (index, grapheme) in text.grapheme_indices(true) {
+    chars += grapheme.chars().count();
+    if chars > max {
+      break;
+    }
+    end = index + grapheme.len();
+  }
+  let cut = &text[..end];
+  if end == text.len() {
+    return cut;
+  }
+  match cut.trim_end().rfind(char::is_whitespace) {
+    Some(space) => cut[..space].trim_end(),
+    None => cut,
+  }
+}
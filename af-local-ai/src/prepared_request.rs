@@ -0,0 +1,115 @@
+use crate::ai_ops::RagOptions;
+use crate::chunking::estimate_tokens;
+use af_plugin::core::stream::StreamOptions;
+use serde_json::{json, Value};
+
+/// What a question is asked with, besides its chat and message, see
+/// [OllamaAIPlugin::build_question_request](crate::ollama_plugin::OllamaAIPlugin::build_question_request).
+#[derive(Debug, Clone)]
+pub struct QuestionRequestOptions {
+  /// JSON schema the answer follows, see [OllamaAIPlugin::stream_question](crate::ollama_plugin::OllamaAIPlugin::stream_question).
+  pub format: Option<Value>,
+  pub metadata: Value,
+  pub stream: StreamOptions,
+  /// Retrieval options of this question, merged with the ones of the chat.
+  pub rag: Option<RagOptions>,
+}
+
+impl Default for QuestionRequestOptions {
+  fn default() -> Self {
+    Self {
+      format: None,
+      metadata: json!({}),
+      stream: StreamOptions::default(),
+      rag: None,
+    }
+  }
+}
+
+impl QuestionRequestOptions {
+  pub fn with_format(mut self, format: Value) -> Self {
+    self.format = Some(format);
+    self
+  }
+
+  pub fn with_metadata(mut self, metadata: Value) -> Self {
+    self.metadata = metadata;
+    self
+  }
+
+  pub fn with_stream_options(mut self, options: StreamOptions) -> Self {
+    self.stream = options;
+    self
+  }
+
+  pub fn with_rag(mut self, rag: RagOptions) -> Self {
+    self.rag = Some(rag);
+    self
+  }
+}
+
+/// A question composed but not sent, built with
+/// [OllamaAIPlugin::build_question_request](crate::ollama_plugin::OllamaAIPlugin::build_question_request)
+/// and sent as it is with
+/// [OllamaAIPlugin::send_prepared](crate::ollama_plugin::OllamaAIPlugin::send_prepared).
+#[derive(Debug, Clone)]
+pub struct PreparedRequest {
+  pub(crate) trace_id: String,
+  pub(crate) chat_id: String,
+  /// The message after the outbound filter.
+  pub(crate) message: String,
+  pub(crate) format: Option<Value>,
+  pub(crate) metadata: Value,
+  pub(crate) rag: Option<RagOptions>,
+  pub(crate) options: StreamOptions,
+  /// Schema the answer is assembled with, see [StreamOptions::assemble_json].
+  pub(crate) json_schema: Option<Value>,
+  /// Model of the model routing table, `None` for the model of the plugin settings.
+  pub(crate) model_name: Option<String>,
+  pub(crate) model: String,
+  pub(crate) redacted: bool,
+  pub(crate) params: Value,
+}
+
+impl PreparedRequest {
+  /// The params of the `handle` request, as the plugin receives them.
+  pub fn params(&self) -> &Value {
+    &self.params
+  }
+
+  /// The trace id the question is sent with, see [TracedStream](crate::trace::TracedStream).
+  pub fn trace_id(&self) -> &str {
+    &self.trace_id
+  }
+
+  pub fn chat_id(&self) -> &str {
+    &self.chat_id
+  }
+
+  /// The message sent, after the outbound filter.
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+
+  /// Whether the outbound filter replaced the message, see
+  /// [OllamaAIPlugin::set_outbound_filter](crate::ollama_plugin::OllamaAIPlugin::set_outbound_filter).
+  pub fn redacted(&self) -> bool {
+    self.redacted
+  }
+
+  /// The model the question is answered with, from the model routing table or the config.
+  pub fn model(&self) -> &str {
+    &self.model
+  }
+
+  /// Retrieval options of the question, merged with the ones of the chat. `None` when the
+  /// plugin retrieves with the options the chat was created with.
+  pub fn rag(&self) -> Option<&RagOptions> {
+    self.rag.as_ref()
+  }
+
+  /// Estimated tokens of the message, see [estimate_tokens](crate::chunking::estimate_tokens).
+  pub fn estimated_tokens(&self) -> u32 {
+    estimate_tokens(&self.message)
+  }
+}
@@ -0,0 +1,344 @@
+use crate::embedding_manifest::MismatchPolicy;
+use crate::ollama_plugin::{LogLevel, OllamaAIPlugin, OllamaPluginConfig};
+use af_plugin::core::process_limits::ProcessLimits;
+use af_plugin::core::transport::TransportKind;
+use af_plugin::error::PluginError;
+use af_plugin::util::is_secret_env;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const PROFILE_EXTENSION: &str = "json";
+
+/// Named [OllamaPluginConfig]s saved as JSON files in a directory, one file per profile. Apply one
+/// with [OllamaAIPlugin::apply_profile](crate::ollama_plugin::OllamaAIPlugin::apply_profile).
+///
+/// Credentials are not saved, see [ConfigProfileStore::save_profile].
+#[derive(Debug, Clone)]
+pub struct ConfigProfileStore {
+  dir: PathBuf,
+}
+
+/// A saved profile, as listed by [ConfigProfileStore::list_profiles].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProfileSummary {
+  pub name: String,
+  pub chat_model_name: String,
+  pub embedding_model_name: String,
+  pub server_url: String,
+  /// Whether the profile has a persist directory.
+  pub rag_enabled: bool,
+}
+
+impl ConfigProfileStore {
+  pub fn new(dir: PathBuf) -> Self {
+    Self { dir }
+  }
+
+  /// Saves `config` as the profile `name`, replacing the profile of that name if any.
+  ///
+  /// `config.auth` and the secret variables of `config.env` are left out so secrets are not
+  /// written to disk in clear. Fields written by newer versions into an existing profile are
+  /// kept.
+  pub fn save_profile(&self, name: &str, config: &OllamaPluginConfig) -> Result<(), PluginError> {
+    let path = self.profile_path(name)?;
+    if config.auth.is_some() {
+      warn!(
+        "[AI Plugin] credentials of {} are not saved in profile {}",
+        config.server_url, name
+      );
+    }
+    let mut profile = StoredProfile::from(config);
+    if let Ok(existing) = read_profile(&path) {
+      profile.unknown = existing.unknown;
+    }
+    std::fs::create_dir_all(&self.dir)?;
+    let content =
+      serde_json::to_vec_pretty(&profile).map_err(|err| PluginError::Internal(err.into()))?;
+    std::fs::write(path, content)?;
+    Ok(())
+  }
+
+  /// The config saved as the profile `name`, without credentials.
+  pub fn load_profile(&self, name: &str) -> Result<OllamaPluginConfig, PluginError> {
+    let path = self.profile_path(name)?;
+    if !path.exists() {
+      return Err(PluginError::ProfileNotFound(name.to_string()));
+    }
+    Ok(read_profile(&path)?.into())
+  }
+
+  /// The saved profiles, sorted by name. Files that can't be read are skipped.
+  pub fn list_profiles(&self) -> Result<Vec<ProfileSummary>, PluginError> {
+    if !self.dir.exists() {
+      return Ok(vec![]);
+    }
+    let mut profiles = vec![];
+    for entry in std::fs::read_dir(&self.dir)? {
+      let path = entry?.path();
+      if path.extension().and_then(|ext| ext.to_str()) != Some(PROFILE_EXTENSION) {
+        continue;
+      }
+      let name = match path.file_stem().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string(),
+        None => continue,
+      };
+      match read_profile(&path) {
+        Ok(profile) => profiles.push(ProfileSummary {
+          name,
+          chat_model_name: profile.chat_model_name,
+          embedding_model_name: profile.embedding_model_name,
+          server_url: profile.server_url,
+          rag_enabled: profile.persist_directory.is_some(),
+        }),
+        Err(err) => warn!("[AI Plugin] skip unreadable profile {:?}: {}", path, err),
+      }
+    }
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(profiles)
+  }
+
+  pub fn delete_profile(&self, name: &str) -> Result<(), PluginError> {
+    let path = self.profile_path(name)?;
+    if !path.exists() {
+      return Err(PluginError::ProfileNotFound(name.to_string()));
+    }
+    std::fs::remove_file(path)?;
+    Ok(())
+  }
+
+  /// Names become file names, so they are limited to letters, digits, spaces, `-` and `_`.
+  fn profile_path(&self, name: &str) -> Result<PathBuf, PluginError> {
+    let valid = !name.trim().is_empty()
+      && name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+    if !valid {
+      return Err(PluginError::InvalidProfileName(name.to_string()));
+    }
+    Ok(self.dir.join(format!("{}.{}", name, PROFILE_EXTENSION)))
+  }
+}
+
+/// How a running plugin moves from one config to another, see
+/// [OllamaAIPlugin::apply_profile](crate::ollama_plugin::OllamaAIPlugin::apply_profile).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConfigChange {
+  Unchanged,
+  /// Only the chat model differs, which the plugin can switch without restarting.
+  ModelOnly,
+  /// The plugin is initialized again with the new config.
+  Restart,
+}
+
+impl ConfigChange {
+  pub fn between(current: &OllamaPluginConfig, next: &OllamaPluginConfig) -> Self {
+    if current == next {
+      return ConfigChange::Unchanged;
+    }
+    let mut switched = current.clone();
+    switched.chat_model_name = next.chat_model_name.clone();
+    if &switched == next {
+      ConfigChange::ModelOnly
+    } else {
+      ConfigChange::Restart
+    }
+  }
+}
+
+/// The file format of a profile. Every field has a default and unknown fields are kept, so
+/// profiles can be read by older and newer versions alike.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct StoredProfile {
+  executable_path: PathBuf,
+  executable_command: String,
+  chat_model_name: String,
+  embedding_model_name: String,
+  server_url: String,
+  persist_directory: Option<PathBuf>,
+  verbose: bool,
+  log_level: LogLevel,
+  on_mismatch: MismatchPolicy,
+  crash_journal_dir: Option<PathBuf>,
+  warming_up_threshold_ms: u64,
+  keep_alive_interval_ms: Option<u64>,
+  min_protocol_version: u32,
+  kill_orphaned_instances: bool,
+  database_query_chunk_rows: usize,
+  embedded_embedding: bool,
+  env: HashMap<String, String>,
+  inherit_env: bool,
+  working_dir: Option<PathBuf>,
+  transport: TransportKind,
+  index_audit_log: Option<PathBuf>,
+  index_audit_max_bytes: u64,
+  context_window: Option<u32>,
+  auto_create_chat: bool,
+  minimum_plugin_version: Version,
+  allow_outdated: bool,
+  process_limits: ProcessLimits,
+  read_only: bool,
+  overlay_directory: Option<PathBuf>,
+  teardown_grace_period_ms: u64,
+  #[serde(flatten)]
+  unknown: Map<String, Value>,
+}
+
+impl Default for StoredProfile {
+  fn default() -> Self {
+    let config = OllamaPluginConfig::new(
+      PathBuf::new(),
+      String::new(),
+      String::new(),
+      String::new(),
+      None,
+    )
+    .expect("default config is valid");
+    StoredProfile::from(&config)
+  }
+}
+
+impl From<&OllamaPluginConfig> for StoredProfile {
+  fn from(config: &OllamaPluginConfig) -> Self {
+    Self {
+      executable_path: config.executable_path.clone(),
+      executable_command: config.executable_command.clone(),
+      chat_model_name: config.chat_model_name.clone(),
+      embedding_model_name: config.embedding_model_name.clone(),
+      server_url: config.server_url.clone(),
+      persist_directory: config.persist_directory.clone(),
+      verbose: config.verbose,
+      log_level: config.log_level,
+      on_mismatch: config.on_mismatch,
+      crash_journal_dir: config.crash_journal_dir.clone(),
+      warming_up_threshold_ms: config.warming_up_threshold.as_millis() as u64,
+      keep_alive_interval_ms: config
+        .keep_alive_interval
+        .map(|interval| interval.as_millis() as u64),
+      min_protocol_version: config.min_protocol_version,
+      kill_orphaned_instances: config.kill_orphaned_instances,
+      database_query_chunk_rows: config.database_query_chunk_rows,
+      embedded_embedding: config.embedded_embedding,
+      env: config
+        .env
+        .iter()
+        .filter(|(name, value)| !is_secret_env(name, value))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect(),
+      inherit_env: config.inherit_env,
+      working_dir: config.working_dir.clone(),
+      transport: config.transport,
+      index_audit_log: config.index_audit_log.clone(),
+      index_audit_max_bytes: config.index_audit_max_bytes,
+      context_window: config.context_window,
+      auto_create_chat: config.auto_create_chat,
+      minimum_plugin_version: config.minimum_plugin_version.clone(),
+      allow_outdated: config.allow_outdated,
+      process_limits: config.process_limits.clone(),
+      read_only: config.read_only,
+      overlay_directory: config.overlay_directory.clone(),
+      teardown_grace_period_ms: config.teardown_grace_period.as_millis() as u64,
+      unknown: Map::new(),
+    }
+  }
+}
+
+impl From<StoredProfile> for OllamaPluginConfig {
+  fn from(profile: StoredProfile) -> Self {
+    OllamaPluginConfig {
+      executable_path: profile.executable_path,
+      executable_command: profile.executable_command,
+      chat_model_name: profile.chat_model_name,
+      embedding_model_name: profile.embedding_model_name,
+      server_url: profile.server_url,
+      auth: None,
+      persist_directory: profile.persist_directory,
+      verbose: profile.verbose,
+      log_level: profile.log_level,
+      on_mismatch: profile.on_mismatch,
+      crash_journal_dir: profile.crash_journal_dir,
+      warming_up_threshold: Duration::from_millis(profile.warming_up_threshold_ms),
+      keep_alive_interval: profile.keep_alive_interval_ms.map(Duration::from_millis),
+      min_protocol_version: profile.min_protocol_version,
+      kill_orphaned_instances: profile.kill_orphaned_instances,
+      database_query_chunk_rows: profile.database_query_chunk_rows,
+      embedded_embedding: profile.embedded_embedding,
+      env: profile.env,
+      inherit_env: profile.inherit_env,
+      working_dir: profile.working_dir,
+      transport: profile.transport,
+      index_audit_log: profile.index_audit_log,
+      index_audit_max_bytes: profile.index_audit_max_bytes,
+      context_window: profile.context_window,
+      auto_create_chat: profile.auto_create_chat,
+      minimum_plugin_version: profile.minimum_plugin_version,
+      allow_outdated: profile.allow_outdated,
+      process_limits: profile.process_limits,
+      read_only: profile.read_only,
+      overlay_directory: profile.overlay_directory,
+      teardown_grace_period: Duration::from_millis(profile.teardown_grace_period_ms),
+    }
+  }
+}
+
+fn read_profile(path: &Path) -> Result<StoredProfile, PluginError> {
+  let content = std::fs::read(path)?;
+  serde_json::from_slice(&content).map_err(|err| PluginError::Internal(err.into()))
+}
+
+impl OllamaAIPlugin {
+  /// Switches to the config saved as the profile `name` in `profiles`.
+  ///
+  /// When only the chat model differs from the running config, the plugin switches models
+  /// without restarting; plugins that can't are restarted. Profiles don't hold credentials, so
+  /// the current ones are kept when the profile uses the same server.
+  pub async fn apply_profile(
+    &self,
+    profiles: &ConfigProfileStore,
+    name: &str,
+  ) -> Result<ConfigChange, PluginError> {
+    let mut config = profiles.load_profile(name)?;
+    let current = self.plugin_config.read().await.clone();
+    let change = match current {
+      Some(current) => {
+        if current.server_url == config.server_url {
+          config.auth = current.auth.clone();
+        }
+        ConfigChange::between(&current, &config)
+      },
+      None => ConfigChange::Restart,
+    };
+    info!("[AI Plugin] apply profile {}: {:?}", name, change);
+
+    if self.get_plugin_running_state().is_running() {
+      match change {
+        ConfigChange::Unchanged => return Ok(change),
+        ConfigChange::ModelOnly => {
+          let plugin = self.get_ai_plugin().await?;
+          let settings = json!({ "model_name": config.chat_model_name });
+          match self.operation(plugin).update_settings(settings).await {
+            Ok(()) => {
+              self
+                .chat_budget
+                .set_capacity(config.effective_context_window());
+              self.plugin_config.write().await.replace(config);
+              return Ok(change);
+            },
+            Err(PluginError::UnsupportedMethod { .. }) => {
+              info!("[AI Plugin] plugin can't switch models, restarting it");
+            },
+            Err(err) => return Err(err),
+          }
+        },
+        ConfigChange::Restart => {},
+      }
+    }
+    self.init_plugin(config).await?;
+    Ok(ConfigChange::Restart)
+  }
+}
@@ -0,0 +1,194 @@
+use crate::ai_ops::CompleteTextType;
+use crate::language::detect_language;
+use crate::ollama_plugin::OllamaAIPlugin;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use tracing::trace;
+
+/// Language asked for when the metadata has none and the text's can't be detected.
+const DEFAULT_LANGUAGE: &str = "the same language as the text";
+
+/// Tone asked for when the metadata has none.
+const DEFAULT_TONE: &str = "neutral";
+
+/// Prompts of the completion types, expanded by the host for plugins too old to have their own,
+/// see [CompleteTextType::prompt_capability]. The prompt is then sent as a
+/// [CompleteTextType::Custom] completion.
+///
+/// Templates may use the `{text}`, `{language}` and `{tone}` placeholders. The language and tone
+/// are read from the `response_language` and `tone` fields of the completion metadata; without
+/// them, the language of the text is detected and the tone is neutral.
+///
+/// Templates set by the caller are expanded by the host whatever the plugin supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplates {
+  templates: HashMap<CompleteTextType, String>,
+  overridden: HashSet<CompleteTextType>,
+}
+
+impl Default for PromptTemplates {
+  fn default() -> Self {
+    let templates = [
+      (
+        CompleteTextType::ImproveWriting,
+        "Improve the writing of the text below. Keep its meaning, write in {language} with a \
+        {tone} tone, and only answer with the improved text.\n\n{text}",
+      ),
+      (
+        CompleteTextType::SpellingAndGrammar,
+        "Fix the spelling and grammar mistakes of the text below, written in {language}. Only \
+        answer with the corrected text.\n\n{text}",
+      ),
+      (
+        CompleteTextType::MakeShorter,
+        "Make the text below shorter while keeping its meaning. Write in {language} with a \
+        {tone} tone, and only answer with the shortened text.\n\n{text}",
+      ),
+      (
+        CompleteTextType::MakeLonger,
+        "Make the text below longer by adding details and examples. Write in {language} with a \
+        {tone} tone, and only answer with the lengthened text.\n\n{text}",
+      ),
+      (
+        CompleteTextType::ContinueWriting,
+        "Continue writing the text below in {language} with a {tone} tone. Only answer with \
+        the continuation, without repeating the text.\n\n{text}",
+      ),
+      (
+        CompleteTextType::Explain,
+        "Explain the text below in {language} with a {tone} tone, so that someone unfamiliar \
+        with its subject understands it.\n\n{text}",
+      ),
+    ];
+    Self {
+      templates: templates
+        .into_iter()
+        .map(|(completion_type, template)| (completion_type, template.to_string()))
+        .collect(),
+      overridden: HashSet::new(),
+    }
+  }
+}
+
+impl PromptTemplates {
+  /// The template of `completion_type`. [CompleteTextType::AskAI] and
+  /// [CompleteTextType::Custom] send the text as is and have none.
+  pub fn get(&self, completion_type: CompleteTextType) -> Option<&str> {
+    self.templates.get(&completion_type).map(String::as_str)
+  }
+
+  /// Replaces the template of `completion_type`.
+  pub fn set(&mut self, completion_type: CompleteTextType, template: String) {
+    self.templates.insert(completion_type, template);
+    self.overridden.insert(completion_type);
+  }
+
+  /// Restores the built-in template of `completion_type`.
+  pub fn reset(&mut self, completion_type: CompleteTextType) {
+    let mut defaults = PromptTemplates::default();
+    match defaults.templates.remove(&completion_type) {
+      Some(template) => self.templates.insert(completion_type, template),
+      None => self.templates.remove(&completion_type),
+    };
+    self.overridden.remove(&completion_type);
+  }
+
+  /// Whether the template of `completion_type` was set by the caller.
+  pub fn is_overridden(&self, completion_type: CompleteTextType) -> bool {
+    self.overridden.contains(&completion_type)
+  }
+
+  /// Expands the template of `completion_type` for `text`, or returns `None` if it has no
+  /// template.
+  pub fn render(
+    &self,
+    completion_type: CompleteTextType,
+    text: &str,
+    metadata: Option<&Value>,
+  ) -> Option<String> {
+    let template = self.get(completion_type)?;
+    let field = |name: &str| {
+      metadata
+        .and_then(|metadata| metadata.get(name))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+    };
+    let language = field("response_language")
+      .or_else(|| detect_language(text).map(|tag| tag.name))
+      .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+    let tone = field("tone").unwrap_or_else(|| DEFAULT_TONE.to_string());
+    Some(expand(
+      template,
+      &[("text", text), ("language", &language), ("tone", &tone)],
+    ))
+  }
+}
+
+/// Replaces the `{name}` placeholders of `template`. Substituted values are not expanded again,
+/// so a text containing `{tone}` is sent as written.
+fn expand(template: &str, values: &[(&str, &str)]) -> String {
+  let mut output = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    output.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let value = values.iter().find_map(|(name, value)| {
+      rest
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_prefix(name))
+        .filter(|rest| rest.starts_with('}'))
+        .map(|_| (name.len() + 2, *value))
+    });
+    match value {
+      Some((len, value)) => {
+        output.push_str(value);
+        rest = &rest[len..];
+      },
+      None => {
+        output.push('{');
+        rest = &rest[1..];
+      },
+    }
+  }
+  output.push_str(rest);
+  output
+}
+
+impl OllamaAIPlugin {
+  /// Replaces the prompt of `completion_type` expanded by the host, which is then used even with
+  /// plugins that have their own, see [PromptTemplates].
+  pub fn set_prompt_template(&self, completion_type: CompleteTextType, template: String) {
+    self.prompt_templates.write().set(completion_type, template);
+  }
+
+  /// Restores the built-in prompt of `completion_type`, see [PromptTemplates::reset].
+  pub fn reset_prompt_template(&self, completion_type: CompleteTextType) {
+    self.prompt_templates.write().reset(completion_type);
+  }
+
+  /// The prompt the host expands for a completion, when the plugin is too old to have its own
+  /// prompt for the type or the caller set one.
+  pub(crate) fn host_prompt(
+    &self,
+    complete_type: u8,
+    text: &str,
+    metadata: Option<&Value>,
+  ) -> Option<String> {
+    let completion_type = CompleteTextType::from(complete_type);
+    let templates = self.prompt_templates.read();
+    let plugin_prompt = match completion_type.prompt_capability() {
+      Some(capability) => self.has_capability(capability),
+      None => true,
+    };
+    if plugin_prompt && !templates.is_overridden(completion_type) {
+      return None;
+    }
+    let prompt = templates.render(completion_type, text, metadata)?;
+    trace!(
+      "[AI Plugin] expanded prompt of {:?} for plugin protocol {}",
+      completion_type,
+      self.negotiated_protocol()
+    );
+    Some(prompt)
+  }
+}
@@ -0,0 +1,229 @@
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::scheduler::Priority;
+use af_plugin::error::PluginError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Limits the requests sent to the Ollama server, so one client's bulk indexing doesn't starve
+/// the others sharing the server, see
+/// [OllamaAIPlugin::set_rate_limit](crate::ollama_plugin::OllamaAIPlugin::set_rate_limit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+  pub requests_per_minute: u32,
+  /// Requests that may be sent at once after a quiet period.
+  pub burst: u32,
+  /// Fail the requests beyond the limit with [PluginError::RateLimited] instead of waiting.
+  #[serde(default)]
+  pub fail_fast: bool,
+}
+
+impl RateLimit {
+  pub fn new(requests_per_minute: u32, burst: u32) -> Self {
+    Self {
+      requests_per_minute,
+      burst,
+      fail_fast: false,
+    }
+  }
+
+  pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+    self.fail_fast = fail_fast;
+    self
+  }
+
+  /// Tokens of the bucket only interactive requests may take, a quarter of the burst, so chat
+  /// stays responsive while background requests use up the rest.
+  pub fn interactive_reserve(&self) -> u32 {
+    self.burst / 4
+  }
+
+  pub fn validate(&self) -> Result<(), PluginError> {
+    if self.requests_per_minute == 0 {
+      return Err(PluginError::InvalidRateLimit(
+        "requests_per_minute must be at least 1".to_string(),
+      ));
+    }
+    if self.burst == 0 {
+      return Err(PluginError::InvalidRateLimit(
+        "burst must be at least 1".to_string(),
+      ));
+    }
+    Ok(())
+  }
+}
+
+/// A token bucket holding up to [RateLimit::burst] tokens, refilled at
+/// [RateLimit::requests_per_minute]. Each request takes one token, a stream when it starts.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+  limit: RateLimit,
+  tokens: f64,
+  updated_at: Instant,
+}
+
+impl TokenBucket {
+  /// A full bucket.
+  pub fn new(limit: RateLimit, now: Instant) -> Self {
+    Self {
+      limit,
+      tokens: limit.burst as f64,
+      updated_at: now,
+    }
+  }
+
+  pub fn limit(&self) -> RateLimit {
+    self.limit
+  }
+
+  /// Tokens in the bucket at `now`, fractions included.
+  pub fn tokens(&mut self, now: Instant) -> f64 {
+    self.refill(now);
+    self.tokens
+  }
+
+  /// Takes a token for a request of `priority`, or returns how long until one is available to
+  /// it. Background requests leave the [RateLimit::interactive_reserve] in the bucket.
+  pub fn try_take(&mut self, priority: Priority, now: Instant) -> Result<(), Duration> {
+    self.refill(now);
+    let needed = match priority {
+      Priority::Interactive => 1.0,
+      Priority::Background => self.limit.interactive_reserve() as f64 + 1.0,
+    };
+    if self.tokens >= needed {
+      self.tokens -= 1.0;
+      return Ok(());
+    }
+    let per_second = self.limit.requests_per_minute as f64 / 60.0;
+    Err(Duration::from_secs_f64((needed - self.tokens) / per_second))
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+    let refilled = elapsed * self.limit.requests_per_minute as f64 / 60.0;
+    self.tokens = (self.tokens + refilled).min(self.limit.burst as f64);
+    self.updated_at = now;
+  }
+}
+
+/// The bucket of a [RateLimiter], reported in
+/// [PluginDiagnostics](crate::diagnostics::PluginDiagnostics).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitState {
+  pub limit: RateLimit,
+  /// Tokens in the bucket, fractions included.
+  pub tokens: f64,
+  /// Requests waiting for a token, of each priority.
+  pub waiting_interactive: usize,
+  pub waiting_background: usize,
+}
+
+/// Applies a [RateLimit] to the requests sent to the plugin. Requests of the same priority wait
+/// their turn in the order they came, and interactive requests don't wait behind background
+/// ones. Without a limit, requests are sent right away.
+#[derive(Default)]
+pub struct RateLimiter {
+  bucket: parking_lot::Mutex<Option<TokenBucket>>,
+  interactive: WaitQueue,
+  background: WaitQueue,
+}
+
+#[derive(Default)]
+struct WaitQueue {
+  turn: tokio::sync::Mutex<()>,
+  waiting: AtomicUsize,
+}
+
+impl RateLimiter {
+  /// Replaces the limit with `limit`, starting with a full bucket, or removes it with `None`.
+  pub fn set_limit(&self, limit: Option<RateLimit>) -> Result<(), PluginError> {
+    if let Some(limit) = limit.as_ref() {
+      limit.validate()?;
+    }
+    *self.bucket.lock() = limit.map(|limit| TokenBucket::new(limit, Instant::now()));
+    Ok(())
+  }
+
+  pub fn state(&self) -> Option<RateLimitState> {
+    let mut bucket = self.bucket.lock();
+    let bucket = bucket.as_mut()?;
+    Some(RateLimitState {
+      limit: bucket.limit(),
+      tokens: bucket.tokens(Instant::now()),
+      waiting_interactive: self.interactive.waiting(),
+      waiting_background: self.background.waiting(),
+    })
+  }
+
+  /// Waits until a request of `priority` may be sent, or fails with [PluginError::RateLimited]
+  /// when the limit is [RateLimit::fail_fast].
+  pub async fn acquire(&self, priority: Priority) -> Result<(), PluginError> {
+    if self.bucket.lock().is_none() {
+      return Ok(());
+    }
+    let queue = match priority {
+      Priority::Interactive => &self.interactive,
+      Priority::Background => &self.background,
+    };
+    let _waiting = queue.enter();
+    let _turn = queue.turn.lock().await;
+    self.take_when_available(priority).await
+  }
+
+  async fn take_when_available(&self, priority: Priority) -> Result<(), PluginError> {
+    loop {
+      let wait = {
+        let mut bucket = self.bucket.lock();
+        let bucket = match bucket.as_mut() {
+          Some(bucket) => bucket,
+          None => return Ok(()),
+        };
+        match bucket.try_take(priority, Instant::now()) {
+          Ok(()) => return Ok(()),
+          Err(retry_after) if bucket.limit().fail_fast => {
+            return Err(PluginError::RateLimited { retry_after })
+          },
+          Err(wait) => wait,
+        }
+      };
+      tokio::time::sleep(wait).await;
+    }
+  }
+}
+
+impl WaitQueue {
+  fn waiting(&self) -> usize {
+    self.waiting.load(Ordering::SeqCst)
+  }
+
+  /// Counts a request as waiting until the returned guard is dropped, even when the request is.
+  fn enter(&self) -> Waiting<'_> {
+    self.waiting.fetch_add(1, Ordering::SeqCst);
+    Waiting(&self.waiting)
+  }
+}
+
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Limits the requests sent to the plugin to `limit`, across every operation. A stream counts
+  /// as one request when it starts. Requests beyond the limit wait for a token, or fail with
+  /// [PluginError::RateLimited] when the limit is [RateLimit::fail_fast]. A share of the bucket
+  /// is kept for [Priority::Interactive] requests, see [RateLimit::interactive_reserve].
+  pub fn set_rate_limit(&self, limit: RateLimit) -> Result<(), PluginError> {
+    self.rate_limiter.set_limit(Some(limit))
+  }
+
+  /// Removes the limit set with [OllamaAIPlugin::set_rate_limit].
+  pub fn clear_rate_limit(&self) {
+    let _ = self.rate_limiter.set_limit(None);
+  }
+}
@@ -0,0 +1,348 @@
+use crate::ai_ops::{AIPluginOperation, RelatedQuestionOptions, STREAM_ANSWER_KEY};
+use crate::model_routing::RequestKind as ModelRequestKind;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::outbound_filter::RequestKind;
+use crate::rate_limit::RateLimiter;
+use crate::scheduler::{Priority, RequestScheduler};
+use crate::summary::prepend;
+use af_plugin::core::plugin::Plugin;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::{PluginError, RemoteError};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::sync::OnceCell;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{error, trace};
+
+/// Related questions fetched for the answer hashed `answer_hash`.
+#[derive(Clone)]
+struct Prefetched {
+  answer_hash: String,
+  questions: Vec<String>,
+}
+
+/// Related questions of the latest answer of a chat.
+struct PrefetchEntry {
+  answer_hash: String,
+  questions: Arc<OnceCell<Prefetched>>,
+}
+
+#[derive(Default)]
+struct PrefetchState {
+  entries: HashMap<String, PrefetchEntry>,
+  /// Chats with a prefetch request in flight.
+  in_flight: HashSet<String>,
+}
+
+/// Caches the related questions fetched in the background once an answer is streamed, see
+/// [OllamaAIPlugin::enable_related_question_prefetch](crate::ollama_plugin::OllamaAIPlugin::enable_related_question_prefetch).
+#[derive(Default)]
+pub(crate) struct RelatedQuestionPrefetch {
+  enabled: AtomicBool,
+  state: parking_lot::Mutex<PrefetchState>,
+}
+
+fn answer_hash(answer: &str) -> String {
+  blake3::hash(answer.as_bytes()).to_hex().to_string()
+}
+
+impl RelatedQuestionPrefetch {
+  pub(crate) fn set_enabled(&self, enabled: bool) {
+    self.enabled.store(enabled, Ordering::SeqCst);
+    if !enabled {
+      self.state.lock().entries.clear();
+    }
+  }
+
+  pub(crate) fn is_enabled(&self) -> bool {
+    self.enabled.load(Ordering::SeqCst)
+  }
+
+  /// Drops the related questions of `chat_id`, called when a new question is asked.
+  pub(crate) fn invalidate(&self, chat_id: &str) {
+    self.state.lock().entries.remove(chat_id);
+  }
+
+  /// The related questions of the latest answer of `chat_id`: the prefetched ones, waiting for a
+  /// prefetch in flight, or the ones of `fetch`. Questions prefetched for an earlier answer are
+  /// never returned.
+  pub(crate) async fn get_or_fetch<F, Fut>(
+    &self,
+    chat_id: &str,
+    fetch: F,
+  ) -> Result<Vec<String>, PluginError>
+  where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Vec<String>, PluginError>>,
+  {
+    let (answer_hash, questions) = match self.latest(chat_id) {
+      Some(latest) => latest,
+      None => return fetch().await,
+    };
+    let prefetched = questions
+      .get_or_try_init(|| async {
+        let questions = fetch().await?;
+        Ok::<_, PluginError>(Prefetched {
+          answer_hash: answer_hash.clone(),
+          questions,
+        })
+      })
+      .await?;
+    if prefetched.answer_hash == answer_hash {
+      return Ok(prefetched.questions.clone());
+    }
+    trace!(
+      "[AI Plugin] related questions of {} were prefetched for another answer",
+      chat_id
+    );
+    fetch().await
+  }
+
+  /// The prefetched related questions of the latest answer of `chat_id`, `None` until the
+  /// prefetch request succeeds.
+  pub(crate) fn prefetched(&self, chat_id: &str) -> Option<Vec<String>> {
+    let (answer_hash, questions) = self.latest(chat_id)?;
+    let prefetched = questions.get()?;
+    (prefetched.answer_hash == answer_hash).then(|| prefetched.questions.clone())
+  }
+
+  fn latest(&self, chat_id: &str) -> Option<(String, Arc<OnceCell<Prefetched>>)> {
+    let state = self.state.lock();
+    let entry = state.entries.get(chat_id)?;
+    Some((entry.answer_hash.clone(), entry.questions.clone()))
+  }
+
+  /// Records the latest answer of `chat_id`. Returns whether to start prefetching its related
+  /// questions: a prefetch already in flight prefetches them once it is done instead.
+  fn answer_finished(&self, chat_id: &str, answer: &str) -> bool {
+    let mut state = self.state.lock();
+    state.entries.insert(
+      chat_id.to_string(),
+      PrefetchEntry {
+        answer_hash: answer_hash(answer),
+        questions: Arc::new(OnceCell::new()),
+      },
+    );
+    state.in_flight.insert(chat_id.to_string())
+  }
+
+  /// Ends the prefetch of `chat_id` for the answer hashed `answer_hash`. Returns `false` when a
+  /// newer answer finished meanwhile and still needs its questions, so the prefetch goes on.
+  fn prefetch_done(&self, chat_id: &str, answer_hash: &str) -> bool {
+    let mut state = self.state.lock();
+    let newer_answer = state
+      .entries
+      .get(chat_id)
+      .is_some_and(|entry| entry.answer_hash != answer_hash && entry.questions.get().is_none());
+    if !newer_answer {
+      state.in_flight.remove(chat_id);
+    }
+    !newer_answer
+  }
+}
+
+/// Forwards an answer stream and, once it completes without error, fetches the related questions
+/// of the answer in the background.
+pub(crate) fn prefetch_after_answer(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  chat_id: String,
+  prefetch: Arc<RelatedQuestionPrefetch>,
+  plugin: Weak<Plugin>,
+  scheduler: RequestScheduler,
+  rate_limiter: Arc<RateLimiter>,
+  model_name: Option<String>,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut answer = String::new();
+    while let Some(frame) = stream.next().await {
+      let is_err = frame.is_err();
+      if let Some(text) = frame
+        .as_ref()
+        .ok()
+        .and_then(|frame| frame.get(STREAM_ANSWER_KEY))
+        .and_then(|text| text.as_str())
+      {
+        answer.push_str(text);
+      }
+      // An answer that failed or was dropped by its consumer is not prefetched.
+      if tx.send(frame).await.is_err() || is_err {
+        return;
+      }
+    }
+    let start = prefetch.is_enabled() && prefetch.answer_finished(&chat_id, &answer);
+    // The answer is recorded before the stream ends, so a `get_related_question` call made right
+    // after waits for this prefetch instead of sending its own request.
+    drop(tx);
+    if !start {
+      return;
+    }
+    let operation = AIPluginOperation::new(plugin)
+      .with_model_name(model_name)
+      .with_rate_limiter(rate_limiter);
+    loop {
+      let (answer_hash, questions) = match prefetch.latest(&chat_id) {
+        Some(latest) => latest,
+        None => {
+          prefetch.prefetch_done(&chat_id, "");
+          return;
+        },
+      };
+      trace!("[AI Plugin] prefetch related questions of {}", chat_id);
+      let permit = scheduler.acquire(Priority::Interactive).await;
+      let result = questions
+        .get_or_try_init(|| async {
+          let questions = operation.get_related_questions(&chat_id).await?;
+          Ok::<_, PluginError>(Prefetched {
+            answer_hash: answer_hash.clone(),
+            questions,
+          })
+        })
+        .await;
+      drop(permit);
+      if let Err(err) = result {
+        error!(
+          "[AI Plugin] failed to prefetch related questions of {}: {:?}",
+          chat_id, err
+        );
+      }
+      if prefetch.prefetch_done(&chat_id, &answer_hash) {
+        return;
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// Forwards the questions of `stream` until `count` were sent, dropping empty ones and those
+/// repeating an earlier question, ignoring case.
+pub(crate) fn distinct_questions(
+  mut stream: ReceiverStream<Result<String, PluginError>>,
+  count: usize,
+) -> ReceiverStream<Result<String, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut seen = HashSet::new();
+    while seen.len() < count {
+      let question = match stream.next().await {
+        Some(Ok(question)) => question.trim().to_string(),
+        Some(Err(err)) => {
+          let _ = tx.send(Err(err)).await;
+          return;
+        },
+        None => return,
+      };
+      if question.is_empty() || !seen.insert(question.to_lowercase()) {
+        continue;
+      }
+      if tx.send(Ok(question)).await.is_err() {
+        return;
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// A stream sending each of `questions` as a frame, for plugins that can't stream them.
+pub(crate) fn questions_stream(
+  questions: Vec<String>,
+) -> ReceiverStream<Result<String, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(questions.len().max(1));
+  for question in questions {
+    let _ = tx.try_send(Ok(question));
+  }
+  ReceiverStream::new(rx)
+}
+
+impl OllamaAIPlugin {
+  /// When enabled, the related questions of each answer streamed by
+  /// [OllamaAIPlugin::stream_question] are fetched in the background as soon as the answer ends,
+  /// so [OllamaAIPlugin::get_related_question] returns them without waiting. Disabled by default.
+  pub fn enable_related_question_prefetch(&self, enabled: bool) {
+    self.related_questions.set_enabled(enabled);
+  }
+
+  /// Returns the related questions of the latest answer of `chat_id`, from the prefetched ones
+  /// if available.
+  pub async fn get_related_question(&self, chat_id: &str) -> Result<Vec<String>, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_model_name(self.routed_model(ModelRequestKind::RelatedQuestions));
+    self
+      .related_questions
+      .get_or_fetch(chat_id, || operation.get_related_questions(chat_id))
+      .await
+  }
+
+  /// Streams the related questions of the latest answer of `chat_id`, each as soon as the plugin
+  /// has generated it, until `options.count` were sent. Empty questions and those repeating an
+  /// earlier one, ignoring case, are dropped.
+  ///
+  /// Prefetched questions are sent at once. Plugins without a `related_question_stream` method
+  /// get the questions of [OllamaAIPlugin::get_related_question], sent one per frame.
+  pub async fn get_related_questions_stream(
+    &self,
+    chat_id: &str,
+    options: RelatedQuestionOptions,
+  ) -> Result<ReceiverStream<Result<String, PluginError>>, PluginError> {
+    let prefetched = self.related_questions.prefetched(chat_id);
+    if let Some(questions) = prefetched {
+      return Ok(distinct_questions(
+        questions_stream(questions),
+        options.count,
+      ));
+    }
+
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_model_name(self.routed_model(ModelRequestKind::RelatedQuestions));
+    let stream = match operation
+      .stream_related_questions(chat_id, &options, StreamOptions::default())
+      .await
+    {
+      Ok(mut stream) => match stream.next().await {
+        Some(Err(err))
+          if err
+            .remote_error()
+            .is_some_and(RemoteError::is_method_not_found) =>
+        {
+          None
+        },
+        Some(first) => Some(prepend(first, stream)),
+        None => Some(stream),
+      },
+      Err(PluginError::UnsupportedMethod { .. }) => None,
+      Err(err) => return Err(err),
+    };
+    let stream = match stream {
+      Some(stream) => stream,
+      None => questions_stream(self.get_related_question(chat_id).await?),
+    };
+    Ok(distinct_questions(stream, options.count))
+  }
+
+  /// Suggests follow-up questions for a piece of text that is not part of a chat.
+  ///
+  /// Returns [PluginError::UnsupportedMethod] if the plugin is too old to suggest questions.
+  pub async fn suggest_questions(&self, text: &str, count: u8) -> Result<Vec<String>, PluginError> {
+    if text.trim().is_empty() {
+      return Ok(vec![]);
+    }
+
+    let text = self.filter_outbound(text, RequestKind::Question)?;
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_model_name(self.routed_model(ModelRequestKind::RelatedQuestions));
+    operation.suggest_questions(&text, count).await
+  }
+}
@@ -0,0 +1,217 @@
+use crate::ai_ops::ChatSettings;
+use crate::attachment::EPHEMERAL_KEY;
+use crate::citation::{FILE_NAME_KEY, SOURCE_ID_KEY};
+use crate::embedding_index::content_hash;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::similarity::cosine_similarity;
+use af_ai_protocol::types::QuestionOptions;
+use af_plugin::error::PluginError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/// A recorded conversation, the input of a [ChatReplay]. Read from JSON, e.g. a corpus of real
+/// conversations kept next to the regression tests of the prompts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatExport {
+  pub chat_id: String,
+  /// The settings the chat was created with.
+  #[serde(default)]
+  pub settings: ChatSettings,
+  #[serde(default)]
+  pub attachments: Vec<ExportedAttachment>,
+  /// The turns of the chat, oldest first.
+  pub turns: Vec<ExportedTurn>,
+}
+
+/// A text that was embedded into a recorded chat.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExportedAttachment {
+  pub source_id: String,
+  #[serde(default)]
+  pub name: Option<String>,
+  /// [content_hash] of the text alone, without metadata.
+  pub content_hash: String,
+  /// The text, when it was exported with the chat. Attachments without it, or whose text no
+  /// longer matches `content_hash`, are not embedded again, see
+  /// [ReplayResult::skipped_attachments].
+  #[serde(default)]
+  pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExportedTurn {
+  pub question: String,
+  pub answer: String,
+}
+
+/// How the questions of a [ChatReplay] are sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayOptions {
+  pub seed: Option<u64>,
+  /// Sample with a zero temperature, so the same prompt gets the same answer.
+  pub temperature_zero: bool,
+}
+
+impl Default for ReplayOptions {
+  fn default() -> Self {
+    Self {
+      seed: None,
+      temperature_zero: true,
+    }
+  }
+}
+
+impl ReplayOptions {
+  fn question_options(&self) -> QuestionOptions {
+    QuestionOptions {
+      temperature: self.temperature_zero.then_some(0.0),
+      seed: self.seed,
+    }
+  }
+}
+
+/// The outcome of [ChatReplay::replay], serializable as a CI artifact.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayResult {
+  /// The chat the turns were asked in, closed once they were all answered.
+  pub chat_id: String,
+  pub turns: Vec<ReplayTurn>,
+  /// Source ids of the attachments that were not embedded again.
+  pub skipped_attachments: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayTurn {
+  pub question: String,
+  pub original_answer: String,
+  pub new_answer: String,
+  /// Cosine similarity of the embeddings of both answers, `None` when the plugin can't embed.
+  pub similarity: Option<f64>,
+}
+
+/// Asks the questions of a recorded chat again, e.g. to find the answers a change of the system
+/// prompt or of the retrieval settings made worse.
+#[derive(Debug, Clone)]
+pub struct ChatReplay {
+  export: ChatExport,
+}
+
+impl ChatReplay {
+  pub fn from_export(export: ChatExport) -> Self {
+    Self { export }
+  }
+
+  /// Replays the chat in a new chat created with the recorded settings: the attachments are
+  /// embedded again as ephemeral texts, then the questions are asked one after the other,
+  /// bypassing the response cache. The chat is closed at the end, which deletes the attachments.
+  ///
+  /// Fails with the error of the first question that fails.
+  pub async fn replay(
+    &self,
+    plugin: &OllamaAIPlugin,
+    options: ReplayOptions,
+  ) -> Result<ReplayResult, PluginError> {
+    let chat_id = format!("replay-{}-{}", self.export.chat_id, uuid::Uuid::new_v4());
+    info!(
+      "[AI Plugin] replaying {} turns of {} in {}",
+      self.export.turns.len(),
+      self.export.chat_id,
+      chat_id
+    );
+    plugin
+      .create_chat_with_settings(&chat_id, self.export.settings.clone())
+      .await?;
+    let result = self.replay_in(plugin, &chat_id, options).await;
+    if let Err(err) = plugin.close_chat(&chat_id, false).await {
+      warn!(
+        "[AI Plugin] failed to close replayed chat {}: {}",
+        chat_id, err
+      );
+    }
+    result
+  }
+
+  async fn replay_in(
+    &self,
+    plugin: &OllamaAIPlugin,
+    chat_id: &str,
+    options: ReplayOptions,
+  ) -> Result<ReplayResult, PluginError> {
+    let mut skipped_attachments = vec![];
+    for attachment in &self.export.attachments {
+      let content = match attachment.content.as_deref() {
+        Some(content) if content_hash(content, &HashMap::new()) == attachment.content_hash => {
+          content
+        },
+        Some(_) => {
+          warn!(
+            "[AI Plugin] content of attachment {} doesn't match its hash",
+            attachment.source_id
+          );
+          skipped_attachments.push(attachment.source_id.clone());
+          continue;
+        },
+        None => {
+          skipped_attachments.push(attachment.source_id.clone());
+          continue;
+        },
+      };
+      let mut metadata = HashMap::from([
+        ("chat_id".to_string(), json!(chat_id)),
+        (SOURCE_ID_KEY.to_string(), json!(attachment.source_id)),
+        (EPHEMERAL_KEY.to_string(), json!(true)),
+      ]);
+      if let Some(name) = &attachment.name {
+        metadata.insert(FILE_NAME_KEY.to_string(), json!(name));
+      }
+      plugin.embed_text(content, metadata).await?;
+    }
+
+    let question_options = options.question_options();
+    let mut can_embed = true;
+    let mut turns = vec![];
+    for turn in &self.export.turns {
+      let new_answer = plugin
+        .ask_question_uncached(chat_id, &turn.question, &question_options)
+        .await?;
+      let mut similarity = None;
+      if can_embed {
+        match answer_similarity(plugin, &turn.answer, &new_answer).await {
+          Ok(value) => similarity = value,
+          Err(err) => {
+            warn!("[AI Plugin] can't embed replayed answers: {}", err);
+            can_embed = false;
+          },
+        }
+      }
+      turns.push(ReplayTurn {
+        question: turn.question.clone(),
+        original_answer: turn.answer.clone(),
+        new_answer,
+        similarity,
+      });
+    }
+    Ok(ReplayResult {
+      chat_id: chat_id.to_string(),
+      turns,
+      skipped_attachments,
+    })
+  }
+}
+
+/// Cosine similarity of the embeddings of `original` and `new`, `None` for embeddings that
+/// can't be compared, such as empty ones.
+async fn answer_similarity(
+  plugin: &OllamaAIPlugin,
+  original: &str,
+  new: &str,
+) -> Result<Option<f64>, PluginError> {
+  let original = plugin.generate_embedding(original).await?;
+  let new = plugin.generate_embedding(new).await?;
+  Ok(match (original.first(), new.first()) {
+    (Some(original), Some(new)) => cosine_similarity(original, new).ok(),
+    _ => None,
+  })
+}
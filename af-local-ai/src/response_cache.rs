@@ -0,0 +1,339 @@
+use crate::model_routing::RequestKind as ModelRequestKind;
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{trace, warn};
+
+const ENTRY_EXTENSION: &str = "json";
+
+/// Where and how long the responses of deterministic operations are kept, see
+/// [OllamaAIPlugin::enable_response_cache](crate::ollama_plugin::OllamaAIPlugin::enable_response_cache).
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+  pub dir: PathBuf,
+  /// Entries kept; the least recently used ones are evicted beyond it.
+  pub max_entries: usize,
+  /// Entries older than this are fetched again.
+  pub ttl: Duration,
+}
+
+/// Lookups of the response cache since it was enabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+  pub hits: u64,
+  pub misses: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+  /// Milliseconds since the Unix epoch.
+  created_at: u64,
+  value: Value,
+}
+
+#[derive(Default)]
+struct LruIndex {
+  /// Key of each entry to the tick it was last used at.
+  last_used: HashMap<String, u64>,
+  tick: u64,
+}
+
+impl LruIndex {
+  fn touch(&mut self, key: &str) {
+    self.tick += 1;
+    self.last_used.insert(key.to_string(), self.tick);
+  }
+
+  /// Removes and returns the least recently used keys beyond `max_entries`.
+  fn evict(&mut self, max_entries: usize) -> Vec<String> {
+    let mut evicted = vec![];
+    while self.last_used.len() > max_entries {
+      let oldest = self
+        .last_used
+        .iter()
+        .min_by_key(|(_, tick)| **tick)
+        .map(|(key, _)| key.clone());
+      match oldest {
+        Some(key) => {
+          self.last_used.remove(&key);
+          evicted.push(key);
+        },
+        None => break,
+      }
+    }
+    evicted
+  }
+}
+
+/// Responses of deterministic operations stored on disk, one file per entry, keyed by a hash of
+/// the method, its params and the model.
+pub(crate) struct ResponseCache {
+  config: CacheConfig,
+  lru: parking_lot::Mutex<LruIndex>,
+  in_flight: parking_lot::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+impl ResponseCache {
+  /// Opens the cache in `config.dir`, keeping its entries in the order they were last written.
+  pub(crate) fn open(config: CacheConfig) -> Result<Self, PluginError> {
+    std::fs::create_dir_all(&config.dir)?;
+    let mut entries = vec![];
+    for entry in std::fs::read_dir(&config.dir)? {
+      let path = entry?.path();
+      if path.extension().and_then(|ext| ext.to_str()) != Some(ENTRY_EXTENSION) {
+        continue;
+      }
+      let key = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(key) => key.to_string(),
+        None => continue,
+      };
+      let modified = std::fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or(UNIX_EPOCH);
+      entries.push((modified, key));
+    }
+    entries.sort();
+
+    let cache = Self {
+      config,
+      lru: Default::default(),
+      in_flight: Default::default(),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+    };
+    {
+      let mut lru = cache.lru.lock();
+      for (_, key) in entries {
+        lru.touch(&key);
+      }
+    }
+    cache.evict();
+    Ok(cache)
+  }
+
+  pub(crate) fn stats(&self) -> CacheStats {
+    CacheStats {
+      hits: self.hits.load(Ordering::SeqCst),
+      misses: self.misses.load(Ordering::SeqCst),
+    }
+  }
+
+  /// Returns the cached response of `key`, or runs `fetch` and caches its response. Concurrent
+  /// calls with the same key run `fetch` only once.
+  pub(crate) async fn get_or_fetch<T, F, Fut>(&self, key: &str, fetch: F) -> Result<T, PluginError>
+  where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, PluginError>>,
+  {
+    let lock = self
+      .in_flight
+      .lock()
+      .entry(key.to_string())
+      .or_default()
+      .clone();
+    let result = {
+      let _guard = lock.lock().await;
+      match self.get(key) {
+        Some(value) => {
+          self.hits.fetch_add(1, Ordering::SeqCst);
+          Ok(value)
+        },
+        None => {
+          self.misses.fetch_add(1, Ordering::SeqCst);
+          let result = fetch().await;
+          if let Ok(value) = result.as_ref() {
+            self.insert(key, value);
+          }
+          result
+        },
+      }
+    };
+
+    // The map and this function hold the only references when no other call is waiting.
+    let mut in_flight = self.in_flight.lock();
+    if Arc::strong_count(&lock) == 2 {
+      in_flight.remove(key);
+    }
+    result
+  }
+
+  /// The response of `key`, unless it is missing, expired or unreadable.
+  fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+    let path = self.entry_path(key);
+    let content = std::fs::read(&path).ok()?;
+    let entry = match serde_json::from_slice::<CacheEntry>(&content) {
+      Ok(entry) => entry,
+      Err(err) => {
+        warn!(
+          "[AI Plugin] discarding corrupted cache entry {:?}: {}",
+          path, err
+        );
+        self.remove(key);
+        return None;
+      },
+    };
+    let age = now_millis().saturating_sub(entry.created_at);
+    if age > self.config.ttl.as_millis() as u64 {
+      trace!("[AI Plugin] cache entry {} expired", key);
+      self.remove(key);
+      return None;
+    }
+    let value = serde_json::from_value(entry.value).ok()?;
+    self.lru.lock().touch(key);
+    Some(value)
+  }
+
+  /// Stores the response of `key`. Failures are only logged: the response is fetched again
+  /// next time.
+  fn insert<T: Serialize>(&self, key: &str, value: &T) {
+    let entry = match serde_json::to_value(value) {
+      Ok(value) => CacheEntry {
+        created_at: now_millis(),
+        value,
+      },
+      Err(err) => {
+        warn!(
+          "[AI Plugin] failed to serialize cache entry {}: {}",
+          key, err
+        );
+        return;
+      },
+    };
+    let path = self.entry_path(key);
+    // Write to a temporary file first so a crash never leaves a truncated entry behind.
+    let tmp_path = path.with_extension("tmp");
+    let written = serde_json::to_vec(&entry)
+      .map_err(std::io::Error::from)
+      .and_then(|content| std::fs::write(&tmp_path, content))
+      .and_then(|_| std::fs::rename(&tmp_path, &path));
+    if let Err(err) = written {
+      warn!(
+        "[AI Plugin] failed to write cache entry {:?}: {}",
+        path, err
+      );
+      return;
+    }
+    self.lru.lock().touch(key);
+    self.evict();
+  }
+
+  fn evict(&self) {
+    let evicted = self.lru.lock().evict(self.config.max_entries);
+    for key in evicted {
+      trace!("[AI Plugin] evict cache entry {}", key);
+      let _ = std::fs::remove_file(self.entry_path(&key));
+    }
+  }
+
+  fn remove(&self, key: &str) {
+    self.lru.lock().last_used.remove(key);
+    let _ = std::fs::remove_file(self.entry_path(key));
+  }
+
+  fn entry_path(&self, key: &str) -> PathBuf {
+    self.config.dir.join(key).with_extension(ENTRY_EXTENSION)
+  }
+}
+
+/// Hashes `method`, `params` and `model` into a cache key. Object keys are hashed in sorted
+/// order, so params built from a `HashMap` get the same key whatever their iteration order.
+pub(crate) fn cache_key(method: &str, params: &Value, model: &str) -> String {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update(method.as_bytes());
+  hasher.update(b"\0");
+  hasher.update(model.as_bytes());
+  hasher.update(b"\0");
+  hash_value(&mut hasher, params);
+  hasher.finalize().to_hex().to_string()
+}
+
+fn hash_value(hasher: &mut blake3::Hasher, value: &Value) {
+  match value {
+    Value::Object(map) => {
+      let mut keys = map.keys().collect::<Vec<_>>();
+      keys.sort();
+      hasher.update(b"{");
+      for key in keys {
+        hasher.update(Value::String(key.clone()).to_string().as_bytes());
+        hasher.update(b":");
+        hash_value(hasher, &map[key]);
+        hasher.update(b",");
+      }
+      hasher.update(b"}");
+    },
+    Value::Array(values) => {
+      hasher.update(b"[");
+      for value in values {
+        hash_value(hasher, value);
+        hasher.update(b",");
+      }
+      hasher.update(b"]");
+    },
+    value => {
+      hasher.update(value.to_string().as_bytes());
+    },
+  }
+}
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+impl OllamaAIPlugin {
+  /// Caches the responses of deterministic operations in `config.dir`: row summaries, row
+  /// translations and the questions asked with a seed or a zero temperature, see
+  /// [OllamaAIPlugin::ask_question_with_options]. Cached responses are returned without a call
+  /// to the plugin.
+  pub fn enable_response_cache(&self, config: CacheConfig) -> Result<(), PluginError> {
+    let cache = ResponseCache::open(config)?;
+    *self.response_cache.write() = Some(Arc::new(cache));
+    Ok(())
+  }
+
+  /// Hits and misses of the response cache, zero when it isn't enabled.
+  pub fn cache_stats(&self) -> CacheStats {
+    match self.response_cache.read().as_ref() {
+      Some(cache) => cache.stats(),
+      None => CacheStats::default(),
+    }
+  }
+
+  /// Returns the cached response of `method` with `params` for the configured chat model, or
+  /// runs `fetch` and caches its response. Without a response cache, only runs `fetch`.
+  pub(crate) async fn cached<T, F, Fut>(
+    &self,
+    method: &str,
+    kind: ModelRequestKind,
+    params: &Value,
+    fetch: F,
+  ) -> Result<T, PluginError>
+  where
+    T: Serialize + serde::de::DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<T, PluginError>>,
+  {
+    let cache = self.response_cache.read().clone();
+    match cache {
+      None => fetch().await,
+      Some(cache) => {
+        let model = self.resolve_model(kind).await;
+        let key = cache_key(method, params, &model);
+        cache.get_or_fetch(&key, fetch).await
+      },
+    }
+  }
+}
@@ -0,0 +1,200 @@
+use crate::ai_ops::{AIPluginOperation, RagOptions, STREAM_ANSWER_KEY};
+use crate::rate_limit::RateLimiter;
+use af_plugin::core::plugin::Plugin;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::{error_code, PluginError};
+use serde_json::Value;
+use std::sync::{Arc, Weak};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+/// Number of times an answer is continued after a transient error.
+pub const MAX_RESUME_ATTEMPTS: usize = 2;
+/// Characters at the end of the answer sent with a continuation request.
+const RESUME_TAIL_CHARS: usize = 200;
+/// Shortest repeated text removed at the seam. Shorter overlaps are more likely a coincidence
+/// than the plugin repeating the end of the answer.
+const MIN_SEAM_OVERLAP: usize = 8;
+
+/// The question an answer stream was started with, needed to continue it.
+pub(crate) struct AnswerRequest {
+  pub trace_id: String,
+  pub chat_id: String,
+  pub message: String,
+  pub format: Option<Value>,
+  pub metadata: Value,
+  pub rag: Option<RagOptions>,
+  pub options: StreamOptions,
+  /// Model the question was routed to, see [ModelRoutingTable](crate::model_routing::ModelRoutingTable).
+  pub model_name: Option<String>,
+}
+
+/// Forwards an answer stream, continuing it with `continue_answer` requests when it fails with a
+/// transient error, at most [MAX_RESUME_ATTEMPTS] times. Other errors end the stream.
+pub(crate) fn resumable_stream(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  request: AnswerRequest,
+  plugin: Weak<Plugin>,
+  rate_limiter: Arc<RateLimiter>,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut answer = String::new();
+    let mut attempts = 0;
+    // Start of a continuation, held back until it is long enough to tell whether it repeats
+    // the end of `answer`.
+    let mut seam: Option<Seam> = None;
+
+    while let Some(frame) = stream.next().await {
+      let mut frame = match frame {
+        Ok(frame) => frame,
+        Err(err) if attempts < MAX_RESUME_ATTEMPTS && is_transient(&err, &plugin) => {
+          attempts += 1;
+          if let Some(seam) = seam.take() {
+            if !flush_seam(seam, &mut answer, &tx).await {
+              return;
+            }
+          }
+          warn!(
+            "[AI Plugin] answer of {} interrupted: {}, continuing ({}/{})",
+            request.chat_id, err, attempts, MAX_RESUME_ATTEMPTS
+          );
+          let received = tail(&answer);
+          let operation = AIPluginOperation::new(plugin.clone())
+            .with_trace_id(&request.trace_id)
+            .with_model_name(request.model_name.clone())
+            .with_rate_limiter(rate_limiter.clone());
+          match operation
+            .continue_answer(
+              &request.chat_id,
+              &request.message,
+              received,
+              request.format.clone(),
+              request.metadata.clone(),
+              request.rag.clone(),
+              request.options.clone(),
+            )
+            .await
+          {
+            Ok(next) => {
+              stream = next;
+              seam = Some(Seam::new(received.len()));
+              continue;
+            },
+            Err(err) => {
+              let _ = tx.send(Err(err)).await;
+              return;
+            },
+          }
+        },
+        Err(err) => {
+          let _ = tx.send(Err(err)).await;
+          return;
+        },
+      };
+
+      if let Some(mut pending) = seam.take() {
+        if let Some(text) = answer_text(&frame) {
+          pending.text.push_str(text);
+          if pending.text.len() < pending.tail_len {
+            seam = Some(pending);
+            continue;
+          }
+          let text = pending.trimmed(&answer).to_string();
+          if text.is_empty() && frame.as_object().is_some_and(|frame| frame.len() == 1) {
+            continue;
+          }
+          frame[STREAM_ANSWER_KEY] = Value::String(text);
+        } else {
+          seam = Some(pending);
+        }
+      }
+
+      if let Some(text) = answer_text(&frame) {
+        answer.push_str(text);
+      }
+      if tx.send(Ok(frame)).await.is_err() {
+        return;
+      }
+    }
+
+    // The continuation ended before it was as long as the text it was sent.
+    if let Some(seam) = seam {
+      flush_seam(seam, &mut answer, &tx).await;
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// Sends the text held back in `seam`. Returns `false` if the consumer is gone.
+async fn flush_seam(
+  seam: Seam,
+  answer: &mut String,
+  tx: &Sender<Result<Value, PluginError>>,
+) -> bool {
+  let text = seam.trimmed(answer).to_string();
+  if text.is_empty() {
+    return true;
+  }
+  answer.push_str(&text);
+  let mut frame = serde_json::Map::new();
+  frame.insert(STREAM_ANSWER_KEY.to_string(), Value::String(text));
+  tx.send(Ok(Value::Object(frame))).await.is_ok()
+}
+
+struct Seam {
+  /// Length of the text sent with the continuation request.
+  tail_len: usize,
+  text: String,
+}
+
+impl Seam {
+  fn new(tail_len: usize) -> Self {
+    Self {
+      tail_len,
+      text: String::new(),
+    }
+  }
+
+  /// The continuation without the end of `answer` it repeats, if any.
+  fn trimmed(&self, answer: &str) -> &str {
+    let max = self.tail_len.min(self.text.len());
+    (MIN_SEAM_OVERLAP..=max)
+      .rev()
+      .find(|len| self.text.is_char_boundary(*len) && answer.ends_with(&self.text[..*len]))
+      .map(|len| &self.text[len..])
+      .unwrap_or(&self.text)
+  }
+}
+
+/// A plugin error the answer can be continued after: the connection to the plugin broke while
+/// its process is still alive, or the plugin reported a retryable error.
+fn is_transient(err: &PluginError, plugin: &Weak<Plugin>) -> bool {
+  match err.root_cause() {
+    PluginError::PeerDisconnect => plugin
+      .upgrade()
+      .map(|plugin| !plugin.has_exited())
+      .unwrap_or(false),
+    PluginError::RemoteError(err) => {
+      err.is_retryable() || err.code() == Some(error_code::STREAM_INTERRUPTED)
+    },
+    _ => false,
+  }
+}
+
+fn answer_text(frame: &Value) -> Option<&str> {
+  frame.get(STREAM_ANSWER_KEY).and_then(|text| text.as_str())
+}
+
+/// The last [RESUME_TAIL_CHARS] characters of `answer`.
+fn tail(answer: &str) -> &str {
+  let start = answer
+    .char_indices()
+    .rev()
+    .nth(RESUME_TAIL_CHARS - 1)
+    .map(|(index, _)| index)
+    .unwrap_or(0);
+  &answer[start..]
+}
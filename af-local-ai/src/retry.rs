@@ -0,0 +1,252 @@
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_ai_protocol::method;
+use af_plugin::error::{PluginError, RemoteError};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Whether a failed request may succeed when sent again, see [RetryPolicy::retry_on].
+pub type RetryClassifier = Arc<dyn Fn(&PluginError) -> bool + Send + Sync>;
+
+/// Sends the non-streaming requests again when they fail with a transient error, see
+/// [OllamaAIPlugin::set_retry_policy](crate::ollama_plugin::OllamaAIPlugin::set_retry_policy).
+/// Streams are resumed instead, see [resume](crate::resume).
+#[derive(Clone)]
+pub struct RetryPolicy {
+  /// Attempts of a request, the first one included. Zero sends it once, like one.
+  pub max_attempts: u32,
+  /// Wait before the second attempt, doubled before each next one.
+  pub backoff: Duration,
+  /// Time the attempts and the waits between them may take in total, `None` for no limit. An
+  /// attempt still running at the deadline fails with [PluginError::RequestTimeout].
+  pub deadline: Option<Duration>,
+  pub retry_on: RetryClassifier,
+  /// Methods sent again after a local timeout, see [RetryPolicy::may_resend]. Defaults to
+  /// [IDEMPOTENT_METHODS].
+  pub idempotent_methods: Vec<String>,
+}
+
+impl RetryPolicy {
+  pub fn new(max_attempts: u32, backoff: Duration) -> Self {
+    Self {
+      max_attempts,
+      backoff,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_deadline(mut self, deadline: Option<Duration>) -> Self {
+    self.deadline = deadline;
+    self
+  }
+
+  pub fn with_retry_on(mut self, retry_on: RetryClassifier) -> Self {
+    self.retry_on = retry_on;
+    self
+  }
+
+  /// Marks `method` as safe to send again after a local timeout, such as a method sent with
+  /// [OllamaAIPlugin::raw_request](crate::ollama_plugin::OllamaAIPlugin::raw_request).
+  pub fn with_idempotent_method(mut self, method: &str) -> Self {
+    self.idempotent_methods.push(method.to_string());
+    self
+  }
+
+  /// Whether a request of `method` that failed with `err` is sent again. A request that timed
+  /// out locally may still be running in the plugin, so it is only sent again when `method`
+  /// changes nothing, or the plugin would append the same message twice, embed the same file
+  /// twice or truncate a chat twice. An error the plugin replied with ends the request, so
+  /// it is retried for every method the policy retries.
+  pub fn may_resend(&self, method: &str, err: &PluginError) -> bool {
+    if !(self.retry_on)(err) {
+      return false;
+    }
+    !matches!(err, PluginError::RequestTimeout(_))
+      || self
+        .idempotent_methods
+        .iter()
+        .any(|idempotent| idempotent == method)
+  }
+
+  /// Wait before attempt `attempt`, counted from 1 for the first one.
+  fn backoff_before(&self, attempt: u32) -> Duration {
+    self
+      .backoff
+      .saturating_mul(1u32 << attempt.saturating_sub(2).min(16))
+  }
+}
+
+impl Default for RetryPolicy {
+  /// Three attempts, half a second apart then a second, within 30 seconds, retrying the errors
+  /// of [is_transient_error].
+  fn default() -> Self {
+    Self {
+      max_attempts: 3,
+      backoff: Duration::from_millis(500),
+      deadline: Some(Duration::from_secs(30)),
+      retry_on: Arc::new(is_transient_error),
+      idempotent_methods: IDEMPOTENT_METHODS.iter().map(|m| m.to_string()).collect(),
+    }
+  }
+}
+
+impl fmt::Debug for RetryPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RetryPolicy")
+      .field("max_attempts", &self.max_attempts)
+      .field("backoff", &self.backoff)
+      .field("deadline", &self.deadline)
+      .field("idempotent_methods", &self.idempotent_methods)
+      .finish_non_exhaustive()
+  }
+}
+
+/// The methods that only read the state of the plugin, so sending one twice is harmless.
+pub const IDEMPOTENT_METHODS: &[&str] = &[
+  method::SYSTEM_INFO,
+  method::PING,
+  method::LIST_MODELS,
+  method::MODEL_CAPABILITIES,
+  method::CHAT_EXISTS,
+  method::LIST_CHATS,
+  method::GET_CHAT_HISTORY,
+  method::DATABASE_SUMMARY,
+  method::DATABASE_TRANSLATE,
+  method::GEN_EMBEDDINGS,
+  method::SIMILARITY_SEARCH,
+  method::VS_STATS,
+  method::VS_GET,
+];
+
+/// The default [RetryClassifier]: retries an overloaded model and the requests that timed out,
+/// never the ones the plugin rejected nor the ones the outbound filter blocked. Requests that
+/// timed out locally are further limited to the idempotent methods, see
+/// [RetryPolicy::may_resend].
+pub fn is_transient_error(err: &PluginError) -> bool {
+  match err {
+    PluginError::RequestTimeout(_) => true,
+    PluginError::RemoteError(RemoteError::InvalidParams { .. }) => false,
+    PluginError::RemoteError(err) => err.is_retryable() || is_timeout(err),
+    PluginError::BlockedByPolicy(_) => false,
+    _ => false,
+  }
+}
+
+fn is_timeout(err: &RemoteError) -> bool {
+  match err {
+    RemoteError::Internal { message, .. } | RemoteError::Custom { message, .. } => {
+      let message = message.to_lowercase();
+      message.contains("timeout") || message.contains("timed out")
+    },
+    _ => false,
+  }
+}
+
+/// Retries counted since the plugin was created, see
+/// [PluginDiagnostics::retries](crate::diagnostics::PluginDiagnostics::retries).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+  /// Attempts sent again after a transient error.
+  pub retried_attempts: u64,
+  /// Requests that still failed with a transient error when the attempts or the deadline ran
+  /// out.
+  pub exhausted: u64,
+}
+
+/// Holds the [RetryPolicy] of the plugin and counts the retries of every operation.
+#[derive(Debug, Default)]
+pub(crate) struct Retrier {
+  policy: parking_lot::RwLock<Option<RetryPolicy>>,
+  retried_attempts: AtomicU64,
+  exhausted: AtomicU64,
+}
+
+impl Retrier {
+  pub(crate) fn set_policy(&self, policy: Option<RetryPolicy>) {
+    *self.policy.write() = policy;
+  }
+
+  pub(crate) fn stats(&self) -> RetryStats {
+    RetryStats {
+      retried_attempts: self.retried_attempts.load(Ordering::Relaxed),
+      exhausted: self.exhausted.load(Ordering::Relaxed),
+    }
+  }
+
+  /// Runs `attempt` until it succeeds, fails with an error the policy doesn't retry, or the
+  /// attempts or the deadline run out, adding the retries to `retries`.
+  pub(crate) async fn run<T, F, Fut>(
+    &self,
+    method: &str,
+    retries: &AtomicU32,
+    mut attempt: F,
+  ) -> Result<T, PluginError>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, PluginError>>,
+  {
+    let policy = self.policy.read().clone();
+    let policy = match policy {
+      Some(policy) => policy,
+      None => return attempt().await,
+    };
+    let deadline = policy.deadline.map(|deadline| Instant::now() + deadline);
+    let mut number = 1;
+    loop {
+      let result = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, attempt())
+          .await
+          .unwrap_or_else(|_| {
+            Err(PluginError::RequestTimeout(
+              policy.deadline.unwrap_or_default(),
+            ))
+          }),
+        None => attempt().await,
+      };
+      let err = match result {
+        Ok(value) => return Ok(value),
+        Err(err) if !policy.may_resend(method, &err) => return Err(err),
+        Err(err) => err,
+      };
+      let backoff = policy.backoff_before(number + 1);
+      let out_of_time = deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline);
+      if number >= policy.max_attempts || out_of_time {
+        self.exhausted.fetch_add(1, Ordering::Relaxed);
+        warn!(
+          "[AI Plugin] {} failed after {} attempts: {}",
+          method, number, err
+        );
+        return Err(err);
+      }
+      warn!(
+        "[AI Plugin] {} attempt {} failed, retrying in {:?}: {}",
+        method, number, backoff, err
+      );
+      tokio::time::sleep(backoff).await;
+      number += 1;
+      self.retried_attempts.fetch_add(1, Ordering::Relaxed);
+      retries.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Sends the requests that fail with an error of [RetryPolicy::retry_on] again, up to
+  /// [RetryPolicy::max_attempts] times within [RetryPolicy::deadline], with the same params and
+  /// trace id. Only requests with a single reply are sent again: interrupted streams are resumed
+  /// instead, see [resume](crate::resume). Retries are counted in
+  /// [OllamaAIPlugin::diagnostics] and in the usage of the operation.
+  pub fn set_retry_policy(&self, policy: RetryPolicy) {
+    self.retrier.set_policy(Some(policy));
+  }
+
+  /// Removes the policy set with [OllamaAIPlugin::set_retry_policy], so failed requests fail
+  /// right away.
+  pub fn clear_retry_policy(&self) {
+    self.retrier.set_policy(None);
+  }
+}
@@ -0,0 +1,151 @@
+use crate::ai_ops::{AIPluginOperation, CompleteTextType, STREAM_ANSWER_KEY};
+use crate::chat_budget::ChatBudgetTracker;
+use crate::rate_limit::RateLimiter;
+use crate::scheduler::{Priority, RequestScheduler};
+use crate::summary::{collect_answer, summary_prompt, ChatMessage, SummaryLength};
+use af_plugin::core::plugin::Plugin;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use tracing::{error, trace};
+
+/// Messages read to find the oldest turns of a chat when the plugin can't summarize them itself.
+const ROLLING_HISTORY_LIMIT: usize = 10_000;
+
+#[derive(Default)]
+struct RollingState {
+  /// Turns asked in each chat since its oldest turns were last summarized.
+  turns: HashMap<String, u32>,
+  /// Chats whose oldest turns are being summarized.
+  in_flight: HashSet<String>,
+}
+
+/// Counts the turns of each chat and summarizes the oldest ones once a chat passes its
+/// [ChatSettings::auto_summarize_after_turns](crate::ai_ops::ChatSettings::auto_summarize_after_turns).
+#[derive(Default)]
+pub(crate) struct RollingSummary {
+  state: parking_lot::Mutex<RollingState>,
+}
+
+impl RollingSummary {
+  /// Counts a turn asked in `chat_id`. Returns the number of oldest turns to summarize when the
+  /// chat has more than `threshold` turns and none of its turns are being summarized.
+  pub(crate) fn turn_asked(&self, chat_id: &str, threshold: u32) -> Option<u32> {
+    let mut state = self.state.lock();
+    let turns = state.turns.entry(chat_id.to_string()).or_default();
+    *turns += 1;
+    let turns = *turns;
+    if threshold == 0 || turns <= threshold || !state.in_flight.insert(chat_id.to_string()) {
+      return None;
+    }
+    // Half of the turns are kept as they are, so the latest answers keep their details.
+    Some(turns - (threshold / 2).max(1))
+  }
+
+  /// Lowers the turn count of `chat_id` after its history was truncated to `keep_turns`.
+  pub(crate) fn truncated(&self, chat_id: &str, keep_turns: usize) {
+    if let Some(turns) = self.state.lock().turns.get_mut(chat_id) {
+      *turns = (*turns).min(keep_turns as u32);
+    }
+  }
+
+  pub(crate) fn forget(&self, chat_id: &str) {
+    self.state.lock().turns.remove(chat_id);
+  }
+
+  fn summary_done(&self, chat_id: &str, summarized_turns: u32) {
+    let mut state = self.state.lock();
+    state.in_flight.remove(chat_id);
+    if let Some(turns) = state.turns.get_mut(chat_id) {
+      *turns = turns.saturating_sub(summarized_turns);
+    }
+  }
+}
+
+/// Summarizes the first `turns` turns of `chat_id` in the background and replaces them with the
+/// summary, in the chat and in its `budget`. Failures are only logged: the chat keeps its full
+/// history.
+pub(crate) fn summarize_in_background(
+  rolling: Arc<RollingSummary>,
+  budget: Arc<ChatBudgetTracker>,
+  chat_id: String,
+  turns: u32,
+  plugin: Weak<Plugin>,
+  scheduler: RequestScheduler,
+  rate_limiter: Arc<RateLimiter>,
+) {
+  tokio::spawn(async move {
+    trace!(
+      "[AI Plugin] summarize the first {} turns of {}",
+      turns,
+      chat_id
+    );
+    let _permit = scheduler.acquire(Priority::Background).await;
+    let operation = AIPluginOperation::new(plugin)
+      .with_rate_limiter(rate_limiter)
+      .with_priority(Priority::Background);
+    let result = match summarize_turns(&operation, &chat_id, turns as usize).await {
+      Ok(summary) => operation
+        .replace_history_prefix(&chat_id, turns as usize, &summary)
+        .await
+        .map(|_| summary.chars().count()),
+      Err(err) => Err(err),
+    };
+    match result {
+      Ok(summary_chars) => {
+        budget.summarized(&chat_id, turns as usize, summary_chars);
+        rolling.summary_done(&chat_id, turns);
+      },
+      Err(err) => {
+        error!(
+          "[AI Plugin] failed to summarize the oldest turns of {}: {:?}",
+          chat_id, err
+        );
+        rolling.summary_done(&chat_id, 0);
+      },
+    }
+  });
+}
+
+/// Summarizes the first `turns` turns of `chat_id` with `chat_summary`, or with a completion of
+/// their history for plugins without it.
+async fn summarize_turns(
+  operation: &AIPluginOperation,
+  chat_id: &str,
+  turns: usize,
+) -> Result<String, PluginError> {
+  match operation
+    .chat_summary_of_first_turns(chat_id, SummaryLength::Bullets, turns)
+    .await
+  {
+    Err(PluginError::UnsupportedMethod { .. }) => {},
+    result => return result,
+  }
+  let history = operation
+    .chat_history(chat_id, ROLLING_HISTORY_LIMIT)
+    .await?;
+  let stream = operation
+    .complete_text_v2(
+      &summary_prompt(first_turns(&history, turns), SummaryLength::Bullets),
+      CompleteTextType::AskAI as u8,
+      None,
+      None,
+      StreamOptions::default(),
+    )
+    .await?;
+  collect_answer(stream, STREAM_ANSWER_KEY).await
+}
+
+/// The messages of the first `turns` turns of `history`. A turn starts with a `human` message;
+/// the messages before the first one, such as an earlier summary, belong to the first turn.
+fn first_turns(history: &[ChatMessage], turns: usize) -> &[ChatMessage] {
+  let end = history
+    .iter()
+    .enumerate()
+    .filter(|(_, message)| message.role == "human")
+    .nth(turns)
+    .map(|(index, _)| index)
+    .unwrap_or(history.len());
+  &history[..end]
+}
@@ -0,0 +1,126 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Number of background requests the plugin works on at once. Kept low so an interactive
+/// request never queues behind more than a few background ones inside the plugin.
+pub const MAX_BACKGROUND_IN_FLIGHT: usize = 1;
+
+/// Which lane a request is dispatched in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+  /// Requests a user is waiting on, such as chat and completion. Dispatched right away.
+  #[default]
+  Interactive,
+  /// Requests such as indexing that can wait. Dispatched only while no interactive request is
+  /// in flight.
+  Background,
+}
+
+#[derive(Debug, Default)]
+struct LaneState {
+  interactive: usize,
+  background: usize,
+}
+
+#[derive(Default)]
+struct Lanes {
+  state: Mutex<LaneState>,
+  changed: Notify,
+}
+
+/// Orders the requests sent to the plugin so background work never delays interactive requests.
+#[derive(Clone, Default)]
+pub struct RequestScheduler {
+  lanes: Arc<Lanes>,
+}
+
+impl RequestScheduler {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Waits until a request of `priority` may be dispatched. The request counts as in flight until
+  /// the returned permit is dropped.
+  pub async fn acquire(&self, priority: Priority) -> LanePermit {
+    loop {
+      // Created before checking the state so a release in between is not missed.
+      let changed = self.lanes.changed.notified();
+      {
+        let mut state = self.lanes.state.lock();
+        match priority {
+          Priority::Interactive => {
+            state.interactive += 1;
+            break;
+          },
+          Priority::Background
+            if state.interactive == 0 && state.background < MAX_BACKGROUND_IN_FLIGHT =>
+          {
+            state.background += 1;
+            break;
+          },
+          Priority::Background => {},
+        }
+      }
+      changed.await;
+    }
+
+    LanePermit {
+      lanes: self.lanes.clone(),
+      priority,
+    }
+  }
+
+  /// Number of interactive and background requests in flight.
+  pub fn in_flight(&self) -> (usize, usize) {
+    let state = self.lanes.state.lock();
+    (state.interactive, state.background)
+  }
+}
+
+/// Marks a request as in flight in its lane.
+pub struct LanePermit {
+  lanes: Arc<Lanes>,
+  priority: Priority,
+}
+
+impl LanePermit {
+  pub fn priority(&self) -> Priority {
+    self.priority
+  }
+
+  /// Keeps the permit until `stream` ends or its consumer drops it, so a streamed answer counts
+  /// as in flight while it is being generated.
+  pub fn hold_until_done<T: Send + 'static>(
+    self,
+    mut stream: ReceiverStream<T>,
+  ) -> ReceiverStream<T> {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+      let _permit = self;
+      while let Some(item) = stream.next().await {
+        if tx.send(item).await.is_err() {
+          break;
+        }
+      }
+    });
+    ReceiverStream::new(rx)
+  }
+}
+
+impl Drop for LanePermit {
+  fn drop(&mut self) {
+    {
+      let mut state = self.lanes.state.lock();
+      match self.priority {
+        Priority::Interactive => state.interactive -= 1,
+        Priority::Background => state.background -= 1,
+      }
+    }
+    self.lanes.changed.notify_waiters();
+  }
+}
@@ -0,0 +1,253 @@
+use crate::embedding_index::content_hash;
+use crate::embedding_ops::{
+  EmbeddingPluginOperation, SearchOptions, SearchPage, SearchResult, StoredEmbedding,
+};
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::outbound_filter::RequestKind;
+use crate::rate_limit::RateLimiter;
+use crate::scheduler::Priority;
+use crate::slow_request::SlowRequestMonitor;
+use crate::trace::start_trace;
+use crate::usage::{finish_usage, UsageKind};
+use af_ai_protocol::capability::Capability;
+use af_plugin::core::plugin::Plugin;
+use af_plugin::error::PluginError;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{instrument, trace, warn};
+
+/// Filters searched at the same time by
+/// [OllamaAIPlugin::similarity_search_stream](crate::ollama_plugin::OllamaAIPlugin::similarity_search_stream).
+pub const MAX_PARALLEL_SEARCHES: usize = 4;
+
+/// A result of
+/// [OllamaAIPlugin::similarity_search_stream](crate::ollama_plugin::OllamaAIPlugin::similarity_search_stream).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilteredSearchResult {
+  /// Index of the filter that found the result.
+  pub filter_index: usize,
+  pub result: SearchResult,
+}
+
+/// Stops a search started with
+/// [OllamaAIPlugin::similarity_search_stream](crate::ollama_plugin::OllamaAIPlugin::similarity_search_stream).
+/// Dropping the handle lets the search run to the end.
+#[derive(Debug)]
+pub struct SearchHandle {
+  task: AbortHandle,
+}
+
+impl SearchHandle {
+  /// Aborts the searches still running and ends the stream. Results already sent stay in it.
+  pub fn cancel(&self) {
+    self.task.abort();
+  }
+}
+
+/// Searches `query` once for each of `filters`, at most [MAX_PARALLEL_SEARCHES] at a time, and
+/// sends the results of each search as soon as it is done. Results already sent by another filter
+/// are dropped.
+pub(crate) fn fan_out_search(
+  plugin: Weak<Plugin>,
+  slow_requests: Arc<SlowRequestMonitor>,
+  rate_limiter: Arc<RateLimiter>,
+  trace_id: String,
+  query: String,
+  filters: Vec<HashMap<String, Value>>,
+  options: SearchOptions,
+) -> (
+  SearchHandle,
+  ReceiverStream<Result<FilteredSearchResult, PluginError>>,
+) {
+  let (tx, rx) = tokio::sync::mpsc::channel(8);
+  let task = tokio::spawn(async move {
+    let mut pending = filters.into_iter().enumerate();
+    let mut searches = JoinSet::new();
+    let mut seen = HashSet::new();
+    loop {
+      while searches.len() < MAX_PARALLEL_SEARCHES {
+        let (index, filter) = match pending.next() {
+          Some(next) => next,
+          None => break,
+        };
+        let operation = EmbeddingPluginOperation::new(plugin.clone())
+          .with_trace_id(&trace_id)
+          .with_slow_requests(slow_requests.clone())
+          .with_rate_limiter(rate_limiter.clone());
+        let query = query.clone();
+        let options = options.clone();
+        searches.spawn(async move {
+          let page = operation
+            .similarity_search_with_options(&query, filter, &options)
+            .await;
+          (index, page)
+        });
+      }
+
+      let (filter_index, page) = match searches.join_next().await {
+        Some(Ok(done)) => done,
+        Some(Err(err)) => {
+          warn!("[AI Plugin] similarity search task failed: {}", err);
+          continue;
+        },
+        None => break,
+      };
+      let page = match page {
+        Ok(page) => page,
+        Err(err) => {
+          if tx.send(Err(err)).await.is_err() {
+            return;
+          }
+          continue;
+        },
+      };
+      trace!(
+        "[AI Plugin] filter {} found {} results",
+        filter_index,
+        page.results.len()
+      );
+      for result in page.results {
+        if !seen.insert(content_hash(&result.content, &HashMap::new())) {
+          continue;
+        }
+        let result = FilteredSearchResult {
+          filter_index,
+          result,
+        };
+        if tx.send(Ok(result)).await.is_err() {
+          return;
+        }
+      }
+    }
+  });
+  let handle = SearchHandle {
+    task: task.abort_handle(),
+  };
+  (handle, ReceiverStream::new(rx))
+}
+
+impl OllamaAIPlugin {
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn similarity_search(
+    &self,
+    query: &str,
+    filter: HashMap<String, Value>,
+  ) -> Result<Vec<String>, PluginError> {
+    let trace_id = start_trace();
+    let query = self.filter_outbound(query, RequestKind::Embedding)?;
+    trace!("[Embedding Plugin] similarity search for query: {}", query);
+    self.wait_until_plugin_ready().await?;
+    let filter = if self.has_capability(Capability::SearchFilter) {
+      filter
+    } else {
+      HashMap::new()
+    };
+    let _store = self.read_vector_store().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.embedding_operation(plugin).with_trace_id(&trace_id);
+    let usage = self
+      .usage
+      .start(UsageKind::Search, None, query.chars().count());
+    let result = operation.similarity_search(&query, filter).await;
+    finish_usage(usage, &result, operation.retries(), |results| {
+      results.iter().map(|result| result.chars().count()).sum()
+    });
+    result
+  }
+
+  /// Reads back up to `limit` stored vectors whose metadata matches every key of `filter`, e.g.
+  /// to sync them instead of embedding their content again.
+  pub async fn get_embeddings(
+    &self,
+    filter: HashMap<String, Value>,
+    limit: usize,
+  ) -> Result<Vec<StoredEmbedding>, PluginError> {
+    trace!(
+      "[Embedding Plugin] get embeddings matching {:?}, limit: {}",
+      filter,
+      limit
+    );
+    self.wait_until_plugin_ready().await?;
+    let _permit = self.scheduler.acquire(Priority::Background).await;
+    let _store = self.read_vector_store().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .embedding_operation(plugin)
+      .with_priority(Priority::Background);
+    operation.get_embeddings(filter, limit).await
+  }
+
+  /// Searches one page of results by descending score, see [SearchOptions]. An `offset` past
+  /// the last result returns an empty page.
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn similarity_search_with_options(
+    &self,
+    query: &str,
+    filter: HashMap<String, Value>,
+    options: SearchOptions,
+  ) -> Result<SearchPage, PluginError> {
+    let trace_id = start_trace();
+    let query = self.filter_outbound(query, RequestKind::Embedding)?;
+    trace!(
+      "[Embedding Plugin] similarity search for query: {}, options: {:?}",
+      query,
+      options
+    );
+    self.wait_until_plugin_ready().await?;
+    let filter = if self.has_capability(Capability::SearchFilter) {
+      filter
+    } else {
+      HashMap::new()
+    };
+    let _store = self.read_vector_store().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self.embedding_operation(plugin).with_trace_id(&trace_id);
+    operation
+      .similarity_search_with_options(&query, filter, &options)
+      .await
+  }
+
+  /// Searches `query` once for each of `filters`, such as one filter per space, sending the
+  /// results of each filter as soon as its search is done. A result found by several filters is
+  /// only sent once. Stop the remaining searches with [SearchHandle::cancel].
+  #[instrument(level = "debug", skip_all, fields(trace_id = tracing::field::Empty))]
+  pub async fn similarity_search_stream(
+    &self,
+    query: &str,
+    filters: Vec<HashMap<String, Value>>,
+    options: SearchOptions,
+  ) -> Result<
+    (
+      SearchHandle,
+      ReceiverStream<Result<FilteredSearchResult, PluginError>>,
+    ),
+    PluginError,
+  > {
+    let trace_id = start_trace();
+    let query = self.filter_outbound(query, RequestKind::Embedding)?;
+    trace!(
+      "[Embedding Plugin] similarity search for query: {} across {} filters",
+      query,
+      filters.len()
+    );
+    self.wait_until_plugin_ready().await?;
+    let filters = if self.has_capability(Capability::SearchFilter) {
+      filters
+    } else {
+      filters.into_iter().map(|_| HashMap::new()).collect()
+    };
+    let plugin = self.get_ai_plugin().await?;
+    Ok(fan_out_search(
+      plugin,
+      self.slow_requests.clone(),
+      self.rate_limiter.clone(),
+      trace_id,
+      query.into_owned(),
+      filters,
+      options,
+    ))
+  }
+}
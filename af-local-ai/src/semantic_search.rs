@@ -0,0 +1,335 @@
+use crate::citation::{FILE_NAME_KEY, SOURCE_ID_KEY};
+use crate::embedding_ops::{SearchOptions, SearchResult};
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Characters of [SearchHit::snippet], ellipses excluded. Longer sentences are cut around their
+/// first highlight.
+pub const SNIPPET_MAX_CHARS: usize = 240;
+
+/// Characters kept before the first highlight of a sentence cut to [SNIPPET_MAX_CHARS].
+const SNIPPET_LEAD_CHARS: usize = 60;
+
+const ELLIPSIS: &str = "…";
+
+/// Query words too common to say anything about a sentence.
+const STOP_WORDS: &[&str] = &[
+  "a", "an", "and", "are", "do", "does", "for", "how", "in", "is", "it", "of", "on", "or", "the",
+  "to", "was", "what", "with",
+];
+
+/// A result of
+/// [OllamaAIPlugin::semantic_search](crate::ollama_plugin::OllamaAIPlugin::semantic_search),
+/// ready to display.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+  /// The `object_id` the chunks were embedded with, or their `source_id`. `None` for chunks
+  /// embedded without either, which are never merged.
+  pub object_id: Option<String>,
+  /// The `title` the chunks were embedded with, or their `file_name`.
+  pub title: Option<String>,
+  /// The sentence of the object that best matches the query, see [best_snippet].
+  pub snippet: String,
+  /// Byte ranges of the words of `snippet` matching the query, in order. Each starts and ends on
+  /// a char boundary, so `&snippet[range]` never panics.
+  pub highlight_ranges: Vec<Range<usize>>,
+  /// Best similarity of the chunks of the object, `None` when the plugin sends no scores.
+  pub score: Option<f64>,
+}
+
+/// The sentence of a text that best matches a query, see [best_snippet].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+  pub text: String,
+  /// See [SearchHit::highlight_ranges].
+  pub highlight_ranges: Vec<Range<usize>>,
+  /// How well `text` matches the query: each query word adds 1.0 when found as is, 0.75 when
+  /// found with another ending, such as a plural, and 0.5 when found with a typo.
+  pub score: f64,
+}
+
+/// The sentence of `content` that best matches `query`, the first one on a tie, with its
+/// matching words highlighted. Sentences longer than [SNIPPET_MAX_CHARS] are cut around their
+/// first highlight. Matching is lexical: words are compared case-insensitively, and common words
+/// of the query are ignored.
+pub fn best_snippet(content: &str, query: &str) -> Snippet {
+  let terms = query_terms(query);
+  let mut best: Option<(Range<usize>, f64, Vec<Range<usize>>)> = None;
+  for sentence in sentences(content) {
+    let (score, highlights) = score_sentence(&content[sentence.clone()], sentence.start, &terms);
+    if best.as_ref().map_or(true, |(_, best, _)| score > *best) {
+      best = Some((sentence, score, highlights));
+    }
+  }
+  match best {
+    Some((sentence, score, highlights)) => {
+      let (text, highlight_ranges) = cut(content, sentence, &highlights);
+      Snippet {
+        text,
+        highlight_ranges,
+        score,
+      }
+    },
+    None => Snippet {
+      text: String::new(),
+      highlight_ranges: vec![],
+      score: 0.0,
+    },
+  }
+}
+
+/// The hits of `results`, sorted by descending score: the chunks of the same object make one
+/// hit, with the best score of its chunks and the snippet that best matches `query`.
+pub(crate) fn search_hits(results: Vec<SearchResult>, query: &str) -> Vec<SearchHit> {
+  let mut hits: Vec<(SearchHit, f64)> = vec![];
+  let mut by_object: HashMap<String, usize> = HashMap::new();
+  for result in results {
+    let text = |key: &str| {
+      result
+        .metadata
+        .get(key)
+        .and_then(|value| value.as_str())
+        .map(String::from)
+    };
+    let object_id = text("object_id").or_else(|| text(SOURCE_ID_KEY));
+    let title = text("title").or_else(|| text(FILE_NAME_KEY));
+    let snippet = best_snippet(&result.content, query);
+    let existing = object_id.as_ref().and_then(|id| by_object.get(id)).copied();
+    match existing {
+      Some(index) => {
+        let (hit, lexical_score) = &mut hits[index];
+        if result.score > hit.score {
+          hit.score = result.score;
+        }
+        if hit.title.is_none() {
+          hit.title = title;
+        }
+        if snippet.score > *lexical_score {
+          hit.snippet = snippet.text;
+          hit.highlight_ranges = snippet.highlight_ranges;
+          *lexical_score = snippet.score;
+        }
+      },
+      None => {
+        if let Some(id) = &object_id {
+          by_object.insert(id.clone(), hits.len());
+        }
+        hits.push((
+          SearchHit {
+            object_id,
+            title,
+            snippet: snippet.text,
+            highlight_ranges: snippet.highlight_ranges,
+            score: result.score,
+          },
+          snippet.score,
+        ));
+      },
+    }
+  }
+  let mut hits = hits.into_iter().map(|(hit, _)| hit).collect::<Vec<_>>();
+  // Stable, so hits scoring the same keep the order of the plugin.
+  hits.sort_by(|a, b| {
+    b.score
+      .unwrap_or(f64::MIN)
+      .total_cmp(&a.score.unwrap_or(f64::MIN))
+  });
+  hits
+}
+
+/// The lowercase words of `query` worth looking for, each once.
+fn query_terms(query: &str) -> Vec<String> {
+  let mut terms: Vec<String> = vec![];
+  for (_, word) in words(query) {
+    // A single letter is too short to match, but a single ideogram is a word.
+    let short = word.chars().count() < 2 && word.is_ascii();
+    if !short && !STOP_WORDS.contains(&word.as_str()) && !terms.contains(&word) {
+      terms.push(word);
+    }
+  }
+  terms
+}
+
+/// The byte ranges of the alphanumeric runs of `text`, with their lowercase text.
+fn words(text: &str) -> Vec<(Range<usize>, String)> {
+  let mut words = vec![];
+  let mut start = None;
+  for (index, c) in text.char_indices() {
+    match (c.is_alphanumeric(), start) {
+      (true, None) => start = Some(index),
+      (false, Some(begin)) => {
+        words.push((begin..index, text[begin..index].to_lowercase()));
+        start = None;
+      },
+      _ => {},
+    }
+  }
+  if let Some(begin) = start {
+    words.push((begin..text.len(), text[begin..].to_lowercase()));
+  }
+  words
+}
+
+/// The byte ranges of the sentences of `text`, without their surrounding whitespace. A sentence
+/// ends with a line break, or with a terminal punctuation mark followed by whitespace or the end
+/// of the text, so numbers such as 3.5 don't split a sentence.
+pub(crate) fn sentences(text: &str) -> Vec<Range<usize>> {
+  let mut sentences = vec![];
+  let mut start = 0;
+  let mut chars = text.char_indices().peekable();
+  while let Some((index, c)) = chars.next() {
+    let end = index + c.len_utf8();
+    let ends_sentence = match c {
+      '\n' => true,
+      '。' | '！' | '？' => true,
+      '.' | '!' | '?' => chars.peek().map_or(true, |(_, next)| next.is_whitespace()),
+      _ => false,
+    };
+    if ends_sentence {
+      push_trimmed(text, start..end, &mut sentences);
+      start = end;
+    }
+  }
+  push_trimmed(text, start..text.len(), &mut sentences);
+  sentences
+}
+
+fn push_trimmed(text: &str, range: Range<usize>, sentences: &mut Vec<Range<usize>>) {
+  let slice = &text[range.clone()];
+  let start = range.start + (slice.len() - slice.trim_start().len());
+  let end = range.end - (slice.len() - slice.trim_end().len());
+  if start < end {
+    sentences.push(start..end);
+  }
+}
+
+/// The score of `sentence` for `terms`, see [Snippet::score], and the byte ranges of its
+/// matching words, offset by `offset`.
+fn score_sentence(sentence: &str, offset: usize, terms: &[String]) -> (f64, Vec<Range<usize>>) {
+  let words = words(sentence);
+  let mut score = 0.0;
+  let mut highlights = vec![];
+  for (range, word) in &words {
+    if terms.iter().any(|term| term_weight(term, word) > 0.0) {
+      highlights.push(range.start + offset..range.end + offset);
+    }
+  }
+  for term in terms {
+    score += words
+      .iter()
+      .map(|(_, word)| term_weight(term, word))
+      .fold(0.0, f64::max);
+  }
+  (score, highlights)
+}
+
+/// How well `word` matches the query word `term`, both lowercase.
+fn term_weight(term: &str, word: &str) -> f64 {
+  if term == word {
+    return 1.0;
+  }
+  let (shorter, longer) = if term.len() < word.len() {
+    (term, word)
+  } else {
+    (word, term)
+  };
+  if shorter.chars().count() >= 3 && longer.starts_with(shorter) {
+    return 0.75;
+  }
+  if shorter.chars().count() >= 5 && within_one_edit(shorter, longer) {
+    return 0.5;
+  }
+  0.0
+}
+
+/// Whether one char inserted, removed or replaced turns `a` into `b`.
+fn within_one_edit(a: &str, b: &str) -> bool {
+  let a = a.chars().collect::<Vec<_>>();
+  let b = b.chars().collect::<Vec<_>>();
+  let (shorter, longer) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+  if longer.len() - shorter.len() > 1 {
+    return false;
+  }
+  let prefix = shorter
+    .iter()
+    .zip(&longer)
+    .take_while(|(a, b)| a == b)
+    .count();
+  if prefix == shorter.len() {
+    return true;
+  }
+  if shorter.len() == longer.len() {
+    shorter[prefix + 1..] == longer[prefix + 1..]
+  } else {
+    shorter[prefix..] == longer[prefix + 1..]
+  }
+}
+
+/// The text of `sentence` in `content`, cut to [SNIPPET_MAX_CHARS] around the first of
+/// `highlights`, with the highlights it keeps made relative to it.
+fn cut(
+  content: &str,
+  sentence: Range<usize>,
+  highlights: &[Range<usize>],
+) -> (String, Vec<Range<usize>>) {
+  let text = &content[sentence.clone()];
+  if text.chars().count() <= SNIPPET_MAX_CHARS {
+    let highlights = highlights
+      .iter()
+      .map(|range| range.start - sentence.start..range.end - sentence.start)
+      .collect();
+    return (text.to_string(), highlights);
+  }
+  let first = highlights
+    .first()
+    .map_or(0, |range| range.start - sentence.start);
+  let lead = text[..first].chars().count().min(SNIPPET_LEAD_CHARS);
+  let start = match lead {
+    0 => first,
+    lead => text[..first]
+      .char_indices()
+      .rev()
+      .nth(lead - 1)
+      .map_or(first, |(index, _)| index),
+  };
+  let end = text[start..]
+    .char_indices()
+    .nth(SNIPPET_MAX_CHARS)
+    .map_or(text.len(), |(index, _)| start + index);
+
+  let prefix = if start > 0 { ELLIPSIS } else { "" };
+  let suffix = if end < text.len() { ELLIPSIS } else { "" };
+  let shift = |position: usize| position - sentence.start - start + prefix.len();
+  let highlights = highlights
+    .iter()
+    .filter(|range| range.start - sentence.start >= start && range.end - sentence.start <= end)
+    .map(|range| shift(range.start)..shift(range.end))
+    .collect();
+  (
+    format!("{}{}{}", prefix, &text[start..end], suffix),
+    highlights,
+  )
+}
+
+impl OllamaAIPlugin {
+  /// Searches the whole vector store for `query` and returns display-ready hits, e.g. for a
+  /// global search box: the chunks of the same object are merged into one hit, keeping their
+  /// best score and the sentence that best matches the query, with its matching words
+  /// highlighted, see [best_snippet]. An empty query finds
+  /// nothing, without a search.
+  pub async fn semantic_search(
+    &self,
+    query: &str,
+    options: SearchOptions,
+  ) -> Result<Vec<SearchHit>, PluginError> {
+    if query.trim().is_empty() {
+      return Ok(vec![]);
+    }
+    let page = self
+      .similarity_search_with_options(query, HashMap::new(), options)
+      .await?;
+    Ok(search_hits(page.results, query))
+  }
+}
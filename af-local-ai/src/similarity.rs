@@ -0,0 +1,59 @@
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SimilarityError {
+  #[error("Vectors have different lengths: {left} and {right}")]
+  LengthMismatch { left: usize, right: usize },
+  /// One of the vectors is empty or all zeros, so it has no direction to compare.
+  #[error("Vector has no magnitude")]
+  ZeroVector,
+}
+
+/// Cosine of the angle between `a` and `b`, from -1.0 for opposite vectors to 1.0 for vectors
+/// pointing the same way.
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> Result<f64, SimilarityError> {
+  if a.len() != b.len() {
+    return Err(SimilarityError::LengthMismatch {
+      left: a.len(),
+      right: b.len(),
+    });
+  }
+  let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+  for (x, y) in a.iter().zip(b) {
+    dot += x * y;
+    norm_a += x * x;
+    norm_b += y * y;
+  }
+  if norm_a == 0.0 || norm_b == 0.0 {
+    return Err(SimilarityError::ZeroVector);
+  }
+  // Rounding can push the cosine of near parallel vectors slightly past 1.0.
+  Ok((dot / (norm_a.sqrt() * norm_b.sqrt())).clamp(-1.0, 1.0))
+}
+
+/// Joins the vectors returned by
+/// [generate_embedding](crate::ollama_plugin::OllamaAIPlugin::generate_embedding) into one.
+pub fn flatten_embeddings(embeddings: Vec<Vec<f64>>) -> Vec<f64> {
+  embeddings.into_iter().flatten().collect()
+}
+
+/// The `top_k` candidates most similar to `query_embedding`, most similar first. Candidates whose
+/// embedding can't be compared to the query, because of its length or because it is all zeros,
+/// are left out.
+pub fn rerank<'a, T>(
+  query_embedding: &[f64],
+  candidates: &'a [(T, Vec<f64>)],
+  top_k: usize,
+) -> Vec<(&'a T, f64)> {
+  let mut scored = candidates
+    .iter()
+    .filter_map(|(candidate, embedding)| {
+      let score = cosine_similarity(query_embedding, embedding).ok()?;
+      Some((candidate, score))
+    })
+    .collect::<Vec<_>>();
+  // Stable, so candidates with the same score keep their order.
+  scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+  scored.truncate(top_k);
+  scored
+}
@@ -0,0 +1,244 @@
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::usage::chat_hash;
+use af_ai_protocol::method;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+/// Slow requests kept for [PluginDiagnostics](crate::diagnostics::PluginDiagnostics), the oldest
+/// dropped first.
+pub const SLOW_REQUEST_HISTORY: usize = 32;
+
+/// Kind of a plugin method, each with its own slow request threshold, see
+/// [OllamaAIPlugin::set_slow_request_threshold](crate::ollama_plugin::OllamaAIPlugin::set_slow_request_threshold).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+  /// Questions asked in a chat, related questions and the other chat methods.
+  Chat,
+  Completion,
+  EmbedFile,
+  /// Texts embedded, embeddings generated and vector store maintenance.
+  Embedding,
+  Search,
+  /// Summaries, translations and queries of database rows.
+  Database,
+  /// Methods of none of the other kinds, such as `system_info`.
+  Other,
+}
+
+const KINDS: usize = 7;
+
+impl OperationKind {
+  /// The kind of `method`, one of the names of [af_ai_protocol::method].
+  pub fn of_method(method: &str) -> Self {
+    match method {
+      method::COMPLETE_TEXT | method::COMPLETE_TEXT_V2 | method::COMPLETE_TEXT_FOLLOWUP => {
+        OperationKind::Completion
+      },
+      method::EMBED_FILE => OperationKind::EmbedFile,
+      method::SIMILARITY_SEARCH => OperationKind::Search,
+      method::DATABASE_SUMMARY
+      | method::DATABASE_TRANSLATE
+      | method::DATABASE_TRANSLATE_STREAM
+      | method::DATABASE_QUERY => OperationKind::Database,
+      method::EMBED_TEXT
+      | method::GEN_EMBEDDINGS
+      | method::DELETE_DOCUMENTS
+      | method::VS_EXPORT
+      | method::VS_RELOAD
+      | method::VS_STATS
+      | method::VS_COMPACT
+      | method::VS_GET
+      | method::VS_SWITCH
+      | method::VS_MIGRATE => OperationKind::Embedding,
+      method::CREATE_CHAT
+      | method::CLOSE_CHAT
+      | method::CHAT_EXISTS
+      | method::ANSWER
+      | method::STREAM_ANSWER
+      | method::STREAM_ANSWER_V2
+      | method::CONTINUE_ANSWER
+      | method::RELATED_QUESTION
+      | method::RELATED_QUESTION_STREAM
+      | method::SUGGEST_QUESTIONS
+      | method::GET_CHAT_HISTORY
+      | method::CHAT_SUMMARY
+      | method::TRUNCATE_CHAT
+      | method::REPLACE_HISTORY_PREFIX
+      | method::APPEND_MESSAGE => OperationKind::Chat,
+      _ => OperationKind::Other,
+    }
+  }
+
+  /// Requests taking longer than this are reported, unless the threshold was changed.
+  pub fn default_threshold(&self) -> Duration {
+    match self {
+      OperationKind::Completion | OperationKind::Search => Duration::from_secs(3),
+      OperationKind::EmbedFile => Duration::from_secs(30),
+      OperationKind::Chat
+      | OperationKind::Embedding
+      | OperationKind::Database
+      | OperationKind::Other => Duration::from_secs(10),
+    }
+  }
+
+  fn index(&self) -> usize {
+    *self as usize
+  }
+
+  const ALL: [OperationKind; KINDS] = [
+    OperationKind::Chat,
+    OperationKind::Completion,
+    OperationKind::EmbedFile,
+    OperationKind::Embedding,
+    OperationKind::Search,
+    OperationKind::Database,
+    OperationKind::Other,
+  ];
+}
+
+/// A request that took longer than the threshold of its kind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowRequest {
+  pub method: String,
+  pub kind: OperationKind,
+  /// From the request being sent to its reply, or to the end of its stream.
+  pub duration: Duration,
+  /// Identifies the chat of the request, if it has one, without its id.
+  pub chat_hash: Option<String>,
+  /// Size of the JSON params of the request, in bytes.
+  pub payload_bytes: usize,
+  pub finished_at: SystemTime,
+}
+
+pub type SlowRequestCallback = Arc<dyn Fn(&SlowRequest) + Send + Sync>;
+
+/// Reports the requests slower than the threshold of their [OperationKind]. Requests are only
+/// timed; the thresholds are atomics, and the locks are only taken for the slow ones.
+pub struct SlowRequestMonitor {
+  /// Threshold of each kind, in milliseconds, indexed by [OperationKind::index].
+  thresholds_ms: [AtomicU64; KINDS],
+  callback: parking_lot::RwLock<Option<SlowRequestCallback>>,
+  history: parking_lot::Mutex<VecDeque<SlowRequest>>,
+}
+
+impl Default for SlowRequestMonitor {
+  fn default() -> Self {
+    Self {
+      thresholds_ms: OperationKind::ALL
+        .map(|kind| AtomicU64::new(kind.default_threshold().as_millis() as u64)),
+      callback: Default::default(),
+      history: Default::default(),
+    }
+  }
+}
+
+impl SlowRequestMonitor {
+  pub fn set_threshold(&self, kind: OperationKind, threshold: Duration) {
+    self.thresholds_ms[kind.index()].store(threshold.as_millis() as u64, Ordering::Relaxed);
+  }
+
+  pub fn threshold(&self, kind: OperationKind) -> Duration {
+    Duration::from_millis(self.thresholds_ms[kind.index()].load(Ordering::Relaxed))
+  }
+
+  /// Calls `callback` with each slow request, replacing the previous callback.
+  pub fn on_slow_request(&self, callback: SlowRequestCallback) {
+    *self.callback.write() = Some(callback);
+  }
+
+  /// The latest slow requests, oldest first.
+  pub fn recent(&self) -> Vec<SlowRequest> {
+    self.history.lock().iter().cloned().collect()
+  }
+
+  /// Reports the `handle` request of `params`, sent at `start`, if it was slow.
+  pub fn finish(&self, params: &Value, start: Instant) {
+    let duration = start.elapsed();
+    let method = params["method"].as_str().unwrap_or_default();
+    let kind = OperationKind::of_method(method);
+    if duration <= self.threshold(kind) {
+      return;
+    }
+
+    let chat_id = [
+      &params["chat_id"],
+      &params["params"]["chat_id"],
+      &params["params"]["metadata"]["chat_id"],
+    ]
+    .into_iter()
+    .find_map(Value::as_str);
+    let request = SlowRequest {
+      method: method.to_string(),
+      kind,
+      duration,
+      chat_hash: chat_id.map(chat_hash),
+      payload_bytes: params.to_string().len(),
+      finished_at: SystemTime::now(),
+    };
+    warn!(
+      method = %request.method,
+      duration_ms = request.duration.as_millis() as u64,
+      chat_hash = request.chat_hash.as_deref().unwrap_or_default(),
+      payload_bytes = request.payload_bytes,
+      "[AI Plugin] slow request"
+    );
+    {
+      let mut history = self.history.lock();
+      if history.len() == SLOW_REQUEST_HISTORY {
+        history.pop_front();
+      }
+      history.push_back(request.clone());
+    }
+    let callback = self.callback.read().clone();
+    if let Some(callback) = callback {
+      callback(&request);
+    }
+  }
+
+  /// Forwards `stream` and reports its request, of `params` sent at `start`, once the stream ends
+  /// or is dropped, if it was slow.
+  pub fn watch_stream<T: Send + 'static>(
+    self: &Arc<Self>,
+    mut stream: ReceiverStream<T>,
+    params: Value,
+    start: Instant,
+  ) -> ReceiverStream<T> {
+    let monitor = self.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+      while let Some(frame) = stream.next().await {
+        if tx.send(frame).await.is_err() {
+          break;
+        }
+      }
+      monitor.finish(&params, start);
+    });
+    ReceiverStream::new(rx)
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Reports the requests of `kind` taking longer than `threshold`, from being sent to their
+  /// reply or the end of their stream, instead of the [OperationKind::default_threshold]. Slow
+  /// requests are logged as warnings, kept in [OllamaAIPlugin::diagnostics] and passed to the
+  /// callback of [OllamaAIPlugin::on_slow_request].
+  pub fn set_slow_request_threshold(&self, kind: OperationKind, threshold: Duration) {
+    self.slow_requests.set_threshold(kind, threshold);
+  }
+
+  pub fn slow_request_threshold(&self, kind: OperationKind) -> Duration {
+    self.slow_requests.threshold(kind)
+  }
+
+  /// Calls `callback` with each slow request, e.g. to tell the user that an operation takes
+  /// longer than usual. Replaces the previous callback.
+  pub fn on_slow_request(&self, callback: impl Fn(&SlowRequest) + Send + Sync + 'static) {
+    self.slow_requests.on_slow_request(Arc::new(callback));
+  }
+}
@@ -0,0 +1,223 @@
+use crate::embedding_ops::MigrationProgress;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::summary::prepend;
+use af_ai_protocol::method::VS_MIGRATE;
+use af_ai_protocol::types::PluginInfo;
+use af_plugin::error::PluginError;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+const STORE_META_FILE_NAME: &str = "store_meta.json";
+
+/// How the vector store of a persist directory was written, so a plugin that changed its store
+/// format finds the stores it can't open as is.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StoreMeta {
+  /// See [PluginInfo::store_format_version](af_ai_protocol::types::PluginInfo::store_format_version).
+  pub store_format_version: u32,
+  pub embedding_model: String,
+  /// Version of the plugin that created the store, or last migrated it.
+  pub created_by: String,
+}
+
+/// A vector store written in another store format than the one of the running plugin, see
+/// [OllamaAIPlugin::migrate_vector_store](crate::ollama_plugin::OllamaAIPlugin::migrate_vector_store).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StoreMigration {
+  pub from: u32,
+  pub to: u32,
+}
+
+pub fn store_meta_path(persist_directory: &Path) -> PathBuf {
+  persist_directory.join(STORE_META_FILE_NAME)
+}
+
+pub fn read_store_meta(persist_directory: &Path) -> Result<Option<StoreMeta>, PluginError> {
+  let path = store_meta_path(persist_directory);
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content = std::fs::read(&path)?;
+  let meta = serde_json::from_slice(&content).map_err(|err| PluginError::Internal(err.into()))?;
+  Ok(Some(meta))
+}
+
+pub fn write_store_meta(persist_directory: &Path, meta: &StoreMeta) -> Result<(), PluginError> {
+  let content = serde_json::to_vec_pretty(meta).map_err(|err| PluginError::Internal(err.into()))?;
+  std::fs::write(store_meta_path(persist_directory), content)?;
+  Ok(())
+}
+
+/// Compares the store format recorded in `persist_directory` against the one of `current`, the
+/// meta the running plugin would write. A directory without a meta gets `current`, its store
+/// being taken as written in the current format.
+pub fn check_store_meta(
+  persist_directory: &Path,
+  current: &StoreMeta,
+) -> Result<Option<StoreMigration>, PluginError> {
+  let stored = match read_store_meta(persist_directory)? {
+    Some(stored) => stored,
+    None => {
+      info!(
+        "[AI Plugin] recording store format {} in {:?}",
+        current.store_format_version, persist_directory
+      );
+      write_store_meta(persist_directory, current)?;
+      return Ok(None);
+    },
+  };
+  if stored.store_format_version == current.store_format_version {
+    return Ok(None);
+  }
+  warn!(
+    "[AI Plugin] vector store was written in format {} by plugin {}, plugin {} uses format {}",
+    stored.store_format_version,
+    stored.created_by,
+    current.created_by,
+    current.store_format_version
+  );
+  Ok(Some(StoreMigration {
+    from: stored.store_format_version,
+    to: current.store_format_version,
+  }))
+}
+
+impl OllamaAIPlugin {
+  /// The migration the vector store needs before it can be used, found at init.
+  pub fn pending_store_migration(&self) -> Option<StoreMigration> {
+    *self.store_migration.lock()
+  }
+
+  /// Records the store format of the plugin in its persist directory, or finds the migration the
+  /// vector store needs when it was written in another one. Plugins that don't report their
+  /// store format are trusted to open any store.
+  pub(crate) async fn check_store_format(&self) -> Result<(), PluginError> {
+    let (store_format_version, created_by) = match self.plugin_info.read().await.as_ref() {
+      Some(PluginInfo {
+        store_format_version: Some(version),
+        version: created_by,
+        ..
+      }) => (*version, created_by.clone()),
+      _ => return Ok(()),
+    };
+    // A read-only store is left as it is, only the store indexing writes to is migrated.
+    let (persist_directory, embedding_model) = match self.plugin_config.read().await.as_ref() {
+      Some(config) => match config.writable_directory() {
+        Some(directory) => (directory.clone(), config.embedding_model_name.clone()),
+        None => return Ok(()),
+      },
+      None => return Ok(()),
+    };
+    let current = StoreMeta {
+      store_format_version,
+      embedding_model,
+      created_by,
+    };
+    *self.store_migration.lock() = check_store_meta(&persist_directory, &current)?;
+    Ok(())
+  }
+
+  /// Migrates the vector store to the store format of the plugin, streaming its progress, when
+  /// [OllamaAIPlugin::pending_store_migration] found it written in another one. The stream ends
+  /// at once when there is nothing to migrate. Embedding and searching fail with
+  /// [PluginError::StoreNeedsMigration] until the migration ends without error.
+  ///
+  /// Fails with [PluginError::StoreNeedsReindex] when the plugin can't migrate the store, whose
+  /// content must then be indexed again into an empty persist directory.
+  pub async fn migrate_vector_store(
+    &self,
+  ) -> Result<ReceiverStream<Result<MigrationProgress, PluginError>>, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let migration = match *self.store_migration.lock() {
+      Some(migration) => migration,
+      None => return Ok(ReceiverStream::new(tokio::sync::mpsc::channel(1).1)),
+    };
+    if self.store_migrating.swap(true, Ordering::SeqCst) {
+      return Err(PluginError::Internal(anyhow!(
+        "Vector store is already being migrated"
+      )));
+    }
+    let result = self.start_store_migration(migration).await;
+    if result.is_err() {
+      self.store_migrating.store(false, Ordering::SeqCst);
+    }
+    result
+  }
+
+  pub(crate) async fn start_store_migration(
+    &self,
+    migration: StoreMigration,
+  ) -> Result<ReceiverStream<Result<MigrationProgress, PluginError>>, PluginError> {
+    let StoreMigration { from, to } = migration;
+    let persist_directory = self.persist_directory().await?;
+    let embedding_model = self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .map(|config| config.embedding_model_name.clone())
+      .unwrap_or_default();
+    let created_by = self.plugin_info().await?.version;
+    if self.supports(VS_MIGRATE) == Some(false) {
+      return Err(PluginError::StoreNeedsReindex { from, to });
+    }
+
+    info!(
+      "[AI Plugin] migrating vector store from format {} to {}",
+      from, to
+    );
+    let plugin = self.get_ai_plugin().await?;
+    let mut stream = self
+      .embedding_operation(plugin)
+      .migrate_vector_store(from, to)
+      .await?;
+    // The plugin refuses to migrate, or doesn't know how to, before reporting any progress.
+    let stream = match stream.next().await {
+      Some(Err(err)) if err.remote_error().is_some() => {
+        warn!("[AI Plugin] plugin can't migrate the vector store: {}", err);
+        return Err(PluginError::StoreNeedsReindex { from, to });
+      },
+      Some(first) => prepend(first, stream),
+      None => stream,
+    };
+
+    let meta = StoreMeta {
+      store_format_version: to,
+      embedding_model,
+      created_by,
+    };
+    let store_migration = self.store_migration.clone();
+    let store_migrating = self.store_migrating.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+      let mut stream = stream;
+      let mut failed = false;
+      while let Some(frame) = stream.next().await {
+        failed |= frame.is_err();
+        // The migration goes on when the caller stops listening.
+        let _ = tx.send(frame).await;
+      }
+      if !failed {
+        match write_store_meta(&persist_directory, &meta) {
+          Ok(()) => {
+            info!("[AI Plugin] migrated vector store to format {}", to);
+            let mut pending = store_migration.lock();
+            if *pending == Some(migration) {
+              pending.take();
+            }
+          },
+          Err(err) => {
+            let _ = tx.send(Err(err)).await;
+          },
+        }
+      }
+      store_migrating.store(false, Ordering::SeqCst);
+    });
+    Ok(ReceiverStream::new(rx))
+  }
+}
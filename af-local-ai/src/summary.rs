@@ -0,0 +1,198 @@
+use crate::ai_ops::{AIPluginOperation, CompleteTextType, STREAM_ANSWER_KEY};
+use crate::model_routing::RequestKind as ModelRequestKind;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::scheduler::Priority;
+pub use af_ai_protocol::types::ChatMessage;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::{PluginError, RemoteError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::trace;
+
+/// Number of recent messages used when the summary is built from the chat history.
+pub const SUMMARY_HISTORY_LIMIT: usize = 100;
+
+/// How long a chat summary should be.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryLength {
+  /// One or two sentences.
+  #[default]
+  Brief,
+  /// Up to seven bullet points.
+  Bullets,
+  /// Several paragraphs; best consumed with
+  /// [OllamaAIPlugin::summarize_chat_stream](crate::ollama_plugin::OllamaAIPlugin::summarize_chat_stream).
+  Detailed,
+}
+
+impl SummaryLength {
+  fn instruction(&self) -> &'static str {
+    match self {
+      SummaryLength::Brief => "Summarize the conversation below in one or two sentences.",
+      SummaryLength::Bullets => {
+        "Summarize the conversation below as a list of at most 7 bullet points, one per line, \
+        each starting with \"- \"."
+      },
+      SummaryLength::Detailed => {
+        "Write a detailed summary of the conversation below. Cover every topic that was \
+        discussed, the questions that were asked and the answers that were given."
+      },
+    }
+  }
+}
+
+/// Builds the prompt used to summarize `history` when the plugin has no `chat_summary` method.
+pub fn summary_prompt(history: &[ChatMessage], length: SummaryLength) -> String {
+  let mut prompt = String::new();
+  let _ = writeln!(prompt, "{}", length.instruction());
+  let _ = writeln!(prompt);
+  for message in history {
+    let _ = writeln!(prompt, "{}: {}", message.role, message.content.trim());
+  }
+  prompt
+}
+
+/// Returns a stream yielding `first` and then the rest of `stream`.
+pub(crate) fn prepend<T: Send + 'static>(
+  first: T,
+  mut stream: ReceiverStream<T>,
+) -> ReceiverStream<T> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    if tx.send(first).await.is_err() {
+      return;
+    }
+    while let Some(item) = stream.next().await {
+      if tx.send(item).await.is_err() {
+        break;
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// Joins the answer text of a v2 stream.
+pub(crate) async fn collect_answer(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  answer_key: &str,
+) -> Result<String, PluginError> {
+  let mut answer = String::new();
+  while let Some(frame) = stream.next().await {
+    if let Some(text) = frame?.get(answer_key).and_then(|v| v.as_str()) {
+      answer.push_str(text);
+    }
+  }
+  Ok(answer)
+}
+
+/// Returns the recent messages of `chat_id`, or `None` when the plugin can't list them.
+async fn summary_history(
+  operation: &AIPluginOperation,
+  chat_id: &str,
+) -> Result<Option<Vec<ChatMessage>>, PluginError> {
+  match operation.chat_history(chat_id, SUMMARY_HISTORY_LIMIT).await {
+    Ok(history) if history.is_empty() => Err(PluginError::EmptyChat {
+      chat_id: chat_id.to_string(),
+    }),
+    Ok(history) => Ok(Some(history)),
+    Err(PluginError::UnsupportedMethod { .. }) => Ok(None),
+    Err(err) => Err(err),
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Summarizes a chat. Plugins without a `chat_summary` method get the chat history
+  /// summarized by `complete_text_v2` instead.
+  ///
+  /// Returns [PluginError::EmptyChat] without calling the model when the chat has no messages.
+  pub async fn summarize_chat(
+    &self,
+    chat_id: &str,
+    length: SummaryLength,
+  ) -> Result<String, PluginError> {
+    trace!(
+      "[AI Plugin] summarize chat: {}, length: {:?}",
+      chat_id,
+      length
+    );
+    self.wait_until_plugin_ready().await?;
+    let _permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_model_name(self.routed_model(ModelRequestKind::ChatTitle));
+    let history = summary_history(&operation, chat_id).await?;
+    match (operation.chat_summary(chat_id, length).await, history) {
+      (Err(PluginError::UnsupportedMethod { .. }), Some(history)) => {
+        let stream = operation
+          .complete_text_v2(
+            &summary_prompt(&history, length),
+            CompleteTextType::AskAI as u8,
+            None,
+            None,
+            StreamOptions::default(),
+          )
+          .await?;
+        collect_answer(stream, STREAM_ANSWER_KEY).await
+      },
+      (result, _) => result,
+    }
+  }
+
+  /// Same as [OllamaAIPlugin::summarize_chat], but streams the summary as v2 frames. Meant for
+  /// [SummaryLength::Detailed], which takes a while to generate.
+  pub async fn summarize_chat_stream(
+    &self,
+    chat_id: &str,
+    length: SummaryLength,
+  ) -> Result<ReceiverStream<anyhow::Result<Value, PluginError>>, PluginError> {
+    trace!(
+      "[AI Plugin] stream chat summary: {}, length: {:?}",
+      chat_id,
+      length
+    );
+    self.wait_until_plugin_ready().await?;
+    let permit = self.scheduler.acquire(Priority::Interactive).await;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_model_name(self.routed_model(ModelRequestKind::ChatTitle));
+    let history = summary_history(&operation, chat_id).await?;
+    let mut stream = operation
+      .stream_chat_summary(chat_id, length, StreamOptions::default())
+      .await?;
+    let stream = match stream.next().await {
+      Some(Err(err))
+        if err
+          .remote_error()
+          .is_some_and(RemoteError::is_method_not_found) =>
+      {
+        match history {
+          Some(history) => {
+            operation
+              .complete_text_v2(
+                &summary_prompt(&history, length),
+                CompleteTextType::AskAI as u8,
+                None,
+                None,
+                StreamOptions::default(),
+              )
+              .await?
+          },
+          None => {
+            return Err(PluginError::UnsupportedMethod {
+              method: "chat_summary".to_string(),
+            })
+          },
+        }
+      },
+      Some(first) => prepend(first, stream),
+      None => stream,
+    };
+    Ok(permit.hold_until_done(stream))
+  }
+}
@@ -0,0 +1,32 @@
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::Span;
+
+/// A stream with the trace id of the operation that started it. The id is sent to the plugin
+/// with the request and recorded by the host's spans, so both sides' logs can be correlated.
+pub struct TracedStream<T> {
+  pub trace_id: String,
+  pub stream: ReceiverStream<T>,
+}
+
+impl<T> TracedStream<T> {
+  pub fn into_inner(self) -> ReceiverStream<T> {
+    self.stream
+  }
+}
+
+/// A new id for one operation, such as a question or a completion.
+pub fn new_trace_id() -> String {
+  uuid::Uuid::new_v4().to_string()
+}
+
+/// Records `trace_id`, started by another operation, as the `trace_id` field of the current span.
+pub(crate) fn continue_trace(trace_id: &str) {
+  Span::current().record("trace_id", trace_id);
+}
+
+/// A new trace id, recorded as the `trace_id` field of the current span.
+pub(crate) fn start_trace() -> String {
+  let trace_id = new_trace_id();
+  Span::current().record("trace_id", trace_id.as_str());
+  trace_id
+}
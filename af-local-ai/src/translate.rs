@@ -0,0 +1,449 @@
+use crate::ai_ops::{
+  AIPluginOperation, CompleteTextType, LocalAITranslateRowData, STREAM_ANSWER_KEY,
+};
+use crate::model_routing::RequestKind as ModelRequestKind;
+use crate::ollama_plugin::OllamaAIPlugin;
+use crate::outbound_filter::RequestKind;
+use crate::prepared_request::QuestionRequestOptions;
+use crate::scheduler::Priority;
+use crate::summary::{collect_answer, prepend};
+use crate::usage::{finish_usage, UsageKind};
+use af_ai_protocol::method::DATABASE_TRANSLATE;
+use af_ai_protocol::types::LocalAITranslateRowResponse;
+pub use af_ai_protocol::types::TranslatedCell;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::{PluginError, RemoteError};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{trace, warn};
+
+/// Segments of an answer translated at once by
+/// [OllamaAIPlugin::stream_question_translated](crate::ollama_plugin::OllamaAIPlugin::stream_question_translated).
+pub const MAX_SEGMENT_TRANSLATIONS_IN_FLIGHT: usize = 2;
+
+/// Characters past which a segment without the end of a sentence is cut at its last whitespace.
+const SEGMENT_MAX_CHARS: usize = 400;
+
+/// A frame of [OllamaAIPlugin::translate_database_row_stream](crate::ollama_plugin::OllamaAIPlugin::translate_database_row_stream).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranslateRowFrame {
+  /// A translated cell, in the order of the cells of the row.
+  Cell(TranslatedCell),
+  /// The last frame, with every cell as
+  /// [OllamaAIPlugin::translate_database_row](crate::ollama_plugin::OllamaAIPlugin::translate_database_row)
+  /// returns them.
+  Done(LocalAITranslateRowResponse),
+}
+
+/// Turns the frames of `database_translate_stream` into [TranslateRowFrame]s. Cells the plugin
+/// finishes ahead of an earlier one are held back until that one arrives.
+pub(crate) fn translated_cells(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+) -> ReceiverStream<Result<TranslateRowFrame, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(8);
+  tokio::spawn(async move {
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    let mut items = vec![];
+    while let Some(frame) = stream.next().await {
+      let cell = match frame.map(serde_json::from_value::<TranslatedCell>) {
+        Ok(Ok(cell)) => cell,
+        Ok(Err(err)) => {
+          trace!("[AI Plugin] skip translate frame: {}", err);
+          continue;
+        },
+        Err(err) => {
+          let _ = tx.send(Err(err)).await;
+          return;
+        },
+      };
+      pending.insert(cell.index, cell);
+      while let Some(cell) = pending.remove(&next_index) {
+        next_index += 1;
+        items.push(HashMap::from([(cell.title.clone(), cell.content.clone())]));
+        if tx.send(Ok(TranslateRowFrame::Cell(cell))).await.is_err() {
+          return;
+        }
+      }
+    }
+
+    // The plugin skipped a cell; the ones after it are sent in order.
+    for (_, cell) in pending {
+      items.push(HashMap::from([(cell.title.clone(), cell.content.clone())]));
+      if tx.send(Ok(TranslateRowFrame::Cell(cell))).await.is_err() {
+        return;
+      }
+    }
+    let _ = tx
+      .send(Ok(TranslateRowFrame::Done(LocalAITranslateRowResponse {
+        items,
+      })))
+      .await;
+  });
+  ReceiverStream::new(rx)
+}
+
+/// A segment of an answer of
+/// [OllamaAIPlugin::stream_question_translated](crate::ollama_plugin::OllamaAIPlugin::stream_question_translated),
+/// usually a sentence. The `original` of the frames, joined, are the answer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranslatedFrame {
+  pub original: String,
+  /// `original` in the target language, with the same surrounding whitespace. `original` itself
+  /// when it has nothing to translate, or when `translation_failed`.
+  pub translated: String,
+  /// The translation request failed or came back empty.
+  pub translation_failed: bool,
+}
+
+impl TranslatedFrame {
+  fn untranslated(original: String, translation_failed: bool) -> Self {
+    Self {
+      translated: original.clone(),
+      original,
+      translation_failed,
+    }
+  }
+}
+
+/// Builds the prompt translating a segment of an answer to `target_language`, sent as a custom
+/// completion.
+pub fn translation_prompt(text: &str, target_language: &str) -> String {
+  let mut prompt = String::new();
+  let _ = writeln!(
+    prompt,
+    "Translate the text below to {}. Keep its formatting, such as markdown, and leave code, \
+    names and links as they are. Reply with the translation only.",
+    target_language
+  );
+  let _ = write!(prompt, "\nText:\n{}", text);
+  prompt
+}
+
+/// Removes the complete segments at the start of `buffer`, each ending with a sentence or a
+/// line. The whitespace after a segment starts the next one, as more of it may be on its way.
+pub(crate) fn take_segments(buffer: &mut String) -> Vec<String> {
+  let mut segments = vec![];
+  while let Some(end) = segment_end(buffer) {
+    let rest = buffer.split_off(end);
+    segments.push(std::mem::replace(buffer, rest));
+  }
+  segments
+}
+
+/// The end of the first complete segment of `text`. Segments hold at least one letter, so list
+/// markers such as `1.` stay with their item.
+fn segment_end(text: &str) -> Option<usize> {
+  let mut has_letter = false;
+  let mut chars = text.char_indices().peekable();
+  while let Some((index, c)) = chars.next() {
+    has_letter |= c.is_alphabetic();
+    let end = match c {
+      '\n' => Some(index),
+      '。' | '！' | '？' => Some(index + c.len_utf8()),
+      '.' | '!' | '?' => chars
+        .peek()
+        .filter(|(_, next)| next.is_whitespace())
+        .map(|_| index + 1),
+      _ => None,
+    };
+    if let (Some(end), true) = (end, has_letter) {
+      return Some(end);
+    }
+  }
+  if text.chars().count() <= SEGMENT_MAX_CHARS {
+    return None;
+  }
+  text
+    .trim_end()
+    .rfind(char::is_whitespace)
+    .filter(|index| text[..*index].chars().any(char::is_alphabetic))
+}
+
+/// Translates the answer of `stream` segment by segment, see [take_segments], while it streams.
+/// Up to [MAX_SEGMENT_TRANSLATIONS_IN_FLIGHT] segments are translated at once, and their frames
+/// are sent in the order of the answer. An error of `stream` is sent after the frames of the
+/// answer received before it.
+pub(crate) fn translated_answer(
+  stream: ReceiverStream<Result<Value, PluginError>>,
+  operation: AIPluginOperation,
+  target_language: String,
+) -> ReceiverStream<Result<TranslatedFrame, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(8);
+  tokio::spawn(async move {
+    let mut translator = SegmentTranslator {
+      operation: Arc::new(operation),
+      target_language: Arc::from(target_language),
+      in_flight: VecDeque::new(),
+    };
+    translator.run(stream, &tx).await;
+    // Left when the consumer dropped the stream.
+    for (_, handle) in translator.in_flight {
+      handle.abort();
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+struct SegmentTranslator {
+  operation: Arc<AIPluginOperation>,
+  target_language: Arc<str>,
+  /// The segments being translated, in the order of the answer.
+  in_flight: VecDeque<(String, JoinHandle<TranslatedFrame>)>,
+}
+
+impl SegmentTranslator {
+  async fn run(
+    &mut self,
+    mut stream: ReceiverStream<Result<Value, PluginError>>,
+    tx: &Sender<Result<TranslatedFrame, PluginError>>,
+  ) {
+    let mut buffer = String::new();
+    let mut error = None;
+    while let Some(frame) = stream.next().await {
+      let frame = match frame {
+        Ok(frame) => frame,
+        Err(err) => {
+          error = Some(err);
+          break;
+        },
+      };
+      if let Some(text) = frame.get(STREAM_ANSWER_KEY).and_then(Value::as_str) {
+        buffer.push_str(text);
+      }
+      for segment in take_segments(&mut buffer) {
+        if !self.translate(segment, tx).await {
+          return;
+        }
+      }
+      // Sends the translations already done without waiting for the next frames.
+      while self
+        .in_flight
+        .front()
+        .is_some_and(|(_, handle)| handle.is_finished())
+      {
+        if !self.send_next(tx).await {
+          return;
+        }
+      }
+    }
+    if !buffer.is_empty() && !self.translate(buffer, tx).await {
+      return;
+    }
+    while !self.in_flight.is_empty() {
+      if !self.send_next(tx).await {
+        return;
+      }
+    }
+    if let Some(err) = error {
+      let _ = tx.send(Err(err)).await;
+    }
+  }
+
+  /// Starts translating `segment` once fewer than [MAX_SEGMENT_TRANSLATIONS_IN_FLIGHT] are, and
+  /// returns whether the consumer is still there.
+  async fn translate(
+    &mut self,
+    segment: String,
+    tx: &Sender<Result<TranslatedFrame, PluginError>>,
+  ) -> bool {
+    if self.in_flight.len() >= MAX_SEGMENT_TRANSLATIONS_IN_FLIGHT && !self.send_next(tx).await {
+      return false;
+    }
+    let operation = self.operation.clone();
+    let target_language = self.target_language.clone();
+    let original = segment.clone();
+    let handle =
+      tokio::spawn(async move { translate_segment(&operation, segment, &target_language).await });
+    self.in_flight.push_back((original, handle));
+    true
+  }
+
+  /// Waits for the oldest translation in flight and sends it, returning whether the consumer is
+  /// still there.
+  async fn send_next(&mut self, tx: &Sender<Result<TranslatedFrame, PluginError>>) -> bool {
+    let (original, handle) = match self.in_flight.pop_front() {
+      Some(next) => next,
+      None => return true,
+    };
+    let frame = match handle.await {
+      Ok(frame) => frame,
+      Err(err) => {
+        warn!("[AI Plugin] segment translation panicked: {}", err);
+        TranslatedFrame::untranslated(original, true)
+      },
+    };
+    tx.send(Ok(frame)).await.is_ok()
+  }
+}
+
+async fn translate_segment(
+  operation: &AIPluginOperation,
+  segment: String,
+  target_language: &str,
+) -> TranslatedFrame {
+  let text = segment.trim();
+  if !text.chars().any(char::is_alphabetic) {
+    return TranslatedFrame::untranslated(segment, false);
+  }
+  let answer = match operation
+    .complete_text_v2(
+      &translation_prompt(text, target_language),
+      CompleteTextType::Custom as u8,
+      None,
+      None,
+      StreamOptions::default(),
+    )
+    .await
+  {
+    Ok(stream) => collect_answer(stream, STREAM_ANSWER_KEY).await,
+    Err(err) => Err(err),
+  };
+  let translation = match answer {
+    Ok(answer) if !answer.trim().is_empty() => answer,
+    Ok(_) => {
+      warn!("[AI Plugin] empty translation of segment: {:?}", text);
+      return TranslatedFrame::untranslated(segment, true);
+    },
+    Err(err) => {
+      warn!("[AI Plugin] failed to translate segment: {}", err);
+      return TranslatedFrame::untranslated(segment, true);
+    },
+  };
+  let leading = &segment[..segment.len() - segment.trim_start().len()];
+  let trailing = &segment[segment.trim_end().len()..];
+  TranslatedFrame {
+    translated: format!("{}{}{}", leading, translation.trim(), trailing),
+    original: segment,
+    translation_failed: false,
+  }
+}
+
+impl OllamaAIPlugin {
+  pub async fn translate_database_row(
+    &self,
+    row: LocalAITranslateRowData,
+  ) -> Result<LocalAITranslateRowResponse, PluginError> {
+    let row = self.filter_translate_row(row)?;
+    trace!("[AI Plugin] summary database row: {:?}", row);
+    let params = json!(row);
+    self
+      .cached(
+        DATABASE_TRANSLATE,
+        ModelRequestKind::DatabaseTranslate,
+        &params,
+        || async {
+          self.wait_until_plugin_ready().await?;
+          let plugin = self.get_ai_plugin().await?;
+          let operation = self
+            .operation(plugin)
+            .with_model_name(self.routed_model(ModelRequestKind::DatabaseTranslate));
+          let chars_in = row
+            .cells
+            .iter()
+            .map(|cell| cell.content.chars().count())
+            .sum();
+          let usage = self.usage.start(UsageKind::RowTranslation, None, chars_in);
+          let resp = operation.translate_row(row).await;
+          finish_usage(usage, &resp, operation.retries(), |resp| {
+            resp
+              .items
+              .iter()
+              .flat_map(|item| item.values())
+              .map(|content| content.chars().count())
+              .sum()
+          });
+          resp
+        },
+      )
+      .await
+  }
+
+  /// Same as [OllamaAIPlugin::translate_database_row], sending each cell as soon as it is
+  /// translated, followed by [TranslateRowFrame::Done]. Plugins without a
+  /// `database_translate_stream` method send the whole row at once, so the stream only has the
+  /// [TranslateRowFrame::Done] frame.
+  pub async fn translate_database_row_stream(
+    &self,
+    row: LocalAITranslateRowData,
+  ) -> Result<ReceiverStream<Result<TranslateRowFrame, PluginError>>, PluginError> {
+    let row = self.filter_translate_row(row)?;
+    trace!("[AI Plugin] stream database row translation: {:?}", row);
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_model_name(self.routed_model(ModelRequestKind::DatabaseTranslate));
+    let mut stream = operation.translate_row_stream(&row).await?;
+    let stream = match stream.next().await {
+      Some(Err(err))
+        if err
+          .remote_error()
+          .is_some_and(RemoteError::is_method_not_found) =>
+      {
+        let resp = operation.translate_row(row).await?;
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let _ = tx.send(Ok(TranslateRowFrame::Done(resp))).await;
+        return Ok(ReceiverStream::new(rx));
+      },
+      Some(first) => prepend(first, stream),
+      None => stream,
+    };
+    Ok(translated_cells(stream))
+  }
+
+  pub(crate) fn filter_translate_row(
+    &self,
+    mut row: LocalAITranslateRowData,
+  ) -> Result<LocalAITranslateRowData, PluginError> {
+    for cell in row.cells.iter_mut() {
+      cell.title = self
+        .filter_outbound(&cell.title, RequestKind::DatabaseRow)?
+        .into_owned();
+      cell.content = self
+        .filter_outbound(&cell.content, RequestKind::DatabaseRow)?
+        .into_owned();
+    }
+    Ok(row)
+  }
+
+  /// Asks `message` like [OllamaAIPlugin::stream_question], translating the answer to
+  /// `target_language` while it streams, e.g. to display an answer written in English in the
+  /// language of the user.
+  ///
+  /// The answer is cut into segments, usually sentences, each translated with a custom
+  /// completion, so a smaller model can be routed to them with the
+  /// [Custom](CompleteTextType::Custom) completions of the model routing table. Frames follow the
+  /// order of the answer. A segment that fails to translate is sent as it is, with
+  /// [TranslatedFrame::translation_failed] set, and the stream goes on.
+  pub async fn stream_question_translated(
+    &self,
+    chat_id: &str,
+    message: &str,
+    target_language: &str,
+    options: QuestionRequestOptions,
+  ) -> Result<ReceiverStream<Result<TranslatedFrame, PluginError>>, PluginError> {
+    let prepared = self
+      .build_question_request(chat_id, message, options)
+      .await?;
+    let trace_id = prepared.trace_id().to_string();
+    let stream = self.send_prepared(prepared).await?;
+    let plugin = self.get_ai_plugin().await?;
+    let operation = self
+      .operation(plugin)
+      .with_trace_id(&trace_id)
+      .with_model_name(self.completion_model(CompleteTextType::Custom as u8));
+    let permit = self.scheduler.acquire(Priority::Interactive).await;
+    Ok(permit.hold_until_done(translated_answer(
+      stream,
+      operation,
+      target_language.to_string(),
+    )))
+  }
+}
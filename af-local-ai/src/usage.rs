@@ -0,0 +1,467 @@
+use crate::ai_ops::STREAM_ANSWER_KEY;
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use serde_json::Value;
+#[cfg(feature = "usage-tracking")]
+use std::path::PathBuf;
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::trace;
+
+#[cfg(feature = "usage-tracking")]
+pub use store::{DayUsage, UsageSummary, USAGE_RETENTION};
+
+/// The operations counted by usage tracking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UsageKind {
+  Question,
+  Completion,
+  Embedding,
+  Search,
+  RowSummary,
+  RowTranslation,
+}
+
+impl UsageKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      UsageKind::Question => "question",
+      UsageKind::Completion => "completion",
+      UsageKind::Embedding => "embedding",
+      UsageKind::Search => "search",
+      UsageKind::RowSummary => "row_summary",
+      UsageKind::RowTranslation => "row_translation",
+    }
+  }
+}
+
+/// The operations started from `start` included to `end` excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+  pub start: SystemTime,
+  pub end: SystemTime,
+}
+
+impl TimeRange {
+  pub fn new(start: SystemTime, end: SystemTime) -> Self {
+    Self { start, end }
+  }
+
+  /// The last `days` days, up to now.
+  pub fn last_days(days: u32) -> Self {
+    let end = SystemTime::now();
+    let start = end
+      .checked_sub(Duration::from_secs(days as u64 * 24 * 60 * 60))
+      .unwrap_or(SystemTime::UNIX_EPOCH);
+    Self { start, end }
+  }
+}
+
+/// One operation. Only the lengths of what was sent and received are kept, never the text.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "usage-tracking"), allow(dead_code))]
+pub(crate) struct UsageRecord {
+  kind: UsageKind,
+  chat_hash: Option<String>,
+  /// Milliseconds since the Unix epoch.
+  started_at: u64,
+  duration_ms: u64,
+  chars_in: usize,
+  chars_out: usize,
+  success: bool,
+  /// Attempts sent again after a transient error, see
+  /// [OllamaAIPlugin::set_retry_policy](crate::ollama_plugin::OllamaAIPlugin::set_retry_policy).
+  retries: u32,
+}
+
+#[cfg_attr(not(feature = "usage-tracking"), allow(dead_code))]
+pub(crate) enum UsageMessage {
+  Record(UsageRecord),
+  #[cfg(feature = "usage-tracking")]
+  Summary(
+    TimeRange,
+    tokio::sync::oneshot::Sender<Result<UsageSummary, PluginError>>,
+  ),
+}
+
+/// Queues the operations of the plugin to the writer of the usage database, once enabled with
+/// [OllamaAIPlugin::enable_usage_tracking](crate::ollama_plugin::OllamaAIPlugin::enable_usage_tracking).
+#[derive(Default)]
+pub(crate) struct UsageRecorder {
+  tx: parking_lot::RwLock<Option<SyncSender<UsageMessage>>>,
+}
+
+impl UsageRecorder {
+  /// Starts timing an operation sending `chars_in` characters to the plugin, or returns `None`
+  /// when usage is not tracked.
+  pub(crate) fn start(
+    &self,
+    kind: UsageKind,
+    chat_id: Option<&str>,
+    chars_in: usize,
+  ) -> Option<PendingUsage> {
+    let tx = self.tx.read().clone()?;
+    Some(PendingUsage {
+      tx,
+      kind,
+      chat_hash: chat_id.map(chat_hash),
+      started_at: SystemTime::now(),
+      start: Instant::now(),
+      chars_in,
+    })
+  }
+}
+
+/// An operation in progress, recorded by [PendingUsage::finish].
+pub(crate) struct PendingUsage {
+  tx: SyncSender<UsageMessage>,
+  kind: UsageKind,
+  chat_hash: Option<String>,
+  started_at: SystemTime,
+  start: Instant,
+  chars_in: usize,
+}
+
+impl PendingUsage {
+  pub(crate) fn finish(self, chars_out: usize, success: bool, retries: u32) {
+    let record = UsageRecord {
+      kind: self.kind,
+      chat_hash: self.chat_hash,
+      started_at: millis_since_epoch(self.started_at),
+      duration_ms: self.start.elapsed().as_millis() as u64,
+      chars_in: self.chars_in,
+      chars_out,
+      success,
+      retries,
+    };
+    if let Err(TrySendError::Full(_)) = self.tx.try_send(UsageMessage::Record(record)) {
+      trace!("[AI Plugin] usage database is busy, dropping record");
+    }
+  }
+}
+
+/// Records the operation of `usage` as done with `result` after `retries` retried attempts,
+/// sizing a successful result with `chars_out`.
+pub(crate) fn finish_usage<T>(
+  usage: Option<PendingUsage>,
+  result: &Result<T, PluginError>,
+  retries: u32,
+  chars_out: impl FnOnce(&T) -> usize,
+) {
+  if let Some(usage) = usage {
+    match result {
+      Ok(value) => usage.finish(chars_out(value), true, retries),
+      Err(_) => usage.finish(0, false, retries),
+    }
+  }
+}
+
+/// Records the operation of `usage` once its answer stream ends, counting the characters of
+/// its answer frames. An answer that reports an error, or whose consumer drops it before the
+/// end, is recorded as failed.
+pub(crate) fn tracked_stream(
+  usage: Option<PendingUsage>,
+  result: Result<ReceiverStream<Result<Value, PluginError>>, PluginError>,
+) -> Result<ReceiverStream<Result<Value, PluginError>>, PluginError> {
+  let usage = match usage {
+    Some(usage) => usage,
+    None => return result,
+  };
+  let mut stream = match result {
+    Ok(stream) => stream,
+    Err(err) => {
+      usage.finish(0, false, 0);
+      return Err(err);
+    },
+  };
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let mut chars_out = 0;
+    let mut success = true;
+    while let Some(frame) = stream.next().await {
+      match &frame {
+        Ok(frame) => {
+          if let Some(text) = frame.get(STREAM_ANSWER_KEY).and_then(Value::as_str) {
+            chars_out += text.chars().count();
+          }
+        },
+        Err(_) => success = false,
+      }
+      if tx.send(frame).await.is_err() {
+        success = false;
+        break;
+      }
+    }
+    usage.finish(chars_out, success, 0);
+  });
+  Ok(ReceiverStream::new(rx))
+}
+
+/// Identifies the chat of an operation without storing its id.
+pub(crate) fn chat_hash(chat_id: &str) -> String {
+  let mut hash = blake3::hash(chat_id.as_bytes()).to_hex().to_string();
+  hash.truncate(16);
+  hash
+}
+
+pub(crate) fn millis_since_epoch(time: SystemTime) -> u64 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as u64
+}
+
+#[cfg(feature = "usage-tracking")]
+mod store {
+  use super::{millis_since_epoch, TimeRange, UsageKind, UsageMessage, UsageRecord, UsageRecorder};
+  use af_plugin::error::PluginError;
+  use rusqlite::{params, Connection};
+  use std::collections::BTreeMap;
+  use std::path::PathBuf;
+  use std::sync::mpsc::{self, Receiver};
+  use std::thread;
+  use std::time::{Duration, SystemTime};
+  use tracing::{error, info};
+
+  /// Records are dropped instead of blocking an operation once this many are queued.
+  const CHANNEL_CAPACITY: usize = 256;
+
+  /// Records older than this are deleted when usage tracking is enabled.
+  pub const USAGE_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+  const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+  /// What the plugin was used for over a [TimeRange].
+  #[derive(Debug, Clone, Default, PartialEq, Eq)]
+  pub struct UsageSummary {
+    /// Number of operations of each kind, failed ones included.
+    pub per_kind_counts: BTreeMap<UsageKind, u64>,
+    pub failures: u64,
+    /// Attempts sent again after a transient error, across the operations.
+    pub retries: u64,
+    pub total_duration: Duration,
+    /// The day with the most operations, the earliest one on a tie.
+    pub busiest_day: Option<DayUsage>,
+  }
+
+  #[derive(Debug, Clone, PartialEq, Eq)]
+  pub struct DayUsage {
+    /// The UTC date, as `YYYY-MM-DD`.
+    pub date: String,
+    pub operations: u64,
+  }
+
+  impl UsageRecorder {
+    /// Opens the database at `db_path`, creating it if needed, deletes the records older than
+    /// [USAGE_RETENTION] and records the next operations to it.
+    pub(crate) fn enable(&self, db_path: PathBuf) -> Result<(), PluginError> {
+      if let Some(parent) = db_path.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      let conn = Connection::open(&db_path).map_err(sql_error)?;
+      conn
+        .execute_batch(
+          "CREATE TABLE IF NOT EXISTS usage (
+            kind TEXT NOT NULL,
+            chat_hash TEXT,
+            started_at INTEGER NOT NULL,
+            duration_ms INTEGER NOT NULL,
+            chars_in INTEGER NOT NULL,
+            chars_out INTEGER NOT NULL,
+            success INTEGER NOT NULL,
+            retries INTEGER NOT NULL DEFAULT 0
+          );
+          CREATE INDEX IF NOT EXISTS usage_started_at ON usage (started_at);",
+        )
+        .map_err(sql_error)?;
+      add_retries_column(&conn).map_err(sql_error)?;
+      let cutoff = SystemTime::now()
+        .checked_sub(USAGE_RETENTION)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+      let deleted = conn
+        .execute(
+          "DELETE FROM usage WHERE started_at < ?1",
+          params![millis_since_epoch(cutoff) as i64],
+        )
+        .map_err(sql_error)?;
+      if deleted > 0 {
+        info!("[AI Plugin] deleted {} expired usage records", deleted);
+      }
+
+      let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+      thread::Builder::new()
+        .name("usage writer".to_string())
+        .spawn(move || run(conn, rx))?;
+      *self.tx.write() = Some(tx);
+      Ok(())
+    }
+
+    /// Summarizes the operations of `range`, including every one recorded before this call.
+    pub(crate) async fn summary(&self, range: TimeRange) -> Result<UsageSummary, PluginError> {
+      let tx = self
+        .tx
+        .read()
+        .clone()
+        .ok_or_else(|| PluginError::Internal(anyhow::anyhow!("usage tracking is not enabled")))?;
+      let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+      // Waits behind the queued records rather than dropping the query.
+      tokio::task::spawn_blocking(move || tx.send(UsageMessage::Summary(range, reply_tx)))
+        .await
+        .map_err(|err| PluginError::Internal(err.into()))?
+        .map_err(|_| writer_stopped())?;
+      reply_rx.await.map_err(|_| writer_stopped())?
+    }
+  }
+
+  fn run(conn: Connection, rx: Receiver<UsageMessage>) {
+    while let Ok(message) = rx.recv() {
+      match message {
+        UsageMessage::Record(record) => {
+          if let Err(err) = insert(&conn, &record) {
+            error!("[AI Plugin] failed to record usage: {}", err);
+          }
+        },
+        UsageMessage::Summary(range, reply) => {
+          let _ = reply.send(summarize(&conn, range).map_err(sql_error));
+        },
+      }
+    }
+  }
+
+  /// Adds the `retries` column to the databases created before retries were recorded.
+  fn add_retries_column(conn: &Connection) -> rusqlite::Result<()> {
+    let mut statement = conn.prepare("SELECT name FROM pragma_table_info('usage')")?;
+    let columns = statement
+      .query_map([], |row| row.get::<_, String>(0))?
+      .collect::<rusqlite::Result<Vec<_>>>()?;
+    if !columns.iter().any(|column| column == "retries") {
+      conn.execute(
+        "ALTER TABLE usage ADD COLUMN retries INTEGER NOT NULL DEFAULT 0",
+        [],
+      )?;
+    }
+    Ok(())
+  }
+
+  fn insert(conn: &Connection, record: &UsageRecord) -> rusqlite::Result<()> {
+    conn.execute(
+      "INSERT INTO usage (kind, chat_hash, started_at, duration_ms, chars_in, chars_out, success,
+       retries) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+      params![
+        record.kind.as_str(),
+        record.chat_hash,
+        record.started_at as i64,
+        record.duration_ms as i64,
+        record.chars_in as i64,
+        record.chars_out as i64,
+        record.success,
+        record.retries,
+      ],
+    )?;
+    Ok(())
+  }
+
+  fn summarize(conn: &Connection, range: TimeRange) -> rusqlite::Result<UsageSummary> {
+    let start = millis_since_epoch(range.start) as i64;
+    // Rounded up, so the operations started in the last millisecond of the range are included.
+    let end = millis_since_epoch(range.end + Duration::from_millis(1)) as i64;
+    let mut summary = UsageSummary::default();
+
+    let mut statement = conn.prepare(
+      "SELECT kind, COUNT(*), SUM(duration_ms), SUM(NOT success), SUM(retries) FROM usage
+       WHERE started_at >= ?1 AND started_at < ?2 GROUP BY kind",
+    )?;
+    let mut rows = statement.query(params![start, end])?;
+    let mut total_ms = 0;
+    while let Some(row) = rows.next()? {
+      let kind: String = row.get(0)?;
+      let count: i64 = row.get(1)?;
+      let duration_ms: i64 = row.get(2)?;
+      let failures: i64 = row.get(3)?;
+      let retries: i64 = row.get(4)?;
+      // Kinds written by a newer version are left out.
+      if let Some(kind) = parse_kind(&kind) {
+        summary.per_kind_counts.insert(kind, count as u64);
+        summary.failures += failures as u64;
+        summary.retries += retries as u64;
+        total_ms += duration_ms as u64;
+      }
+    }
+    summary.total_duration = Duration::from_millis(total_ms);
+
+    let busiest = conn.query_row(
+      "SELECT started_at / ?3 AS day, COUNT(*) AS operations FROM usage
+       WHERE started_at >= ?1 AND started_at < ?2
+       GROUP BY day ORDER BY operations DESC, day ASC LIMIT 1",
+      params![start, end, MILLIS_PER_DAY as i64],
+      |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+    );
+    summary.busiest_day = match busiest {
+      Ok((day, operations)) => Some(DayUsage {
+        date: format_date(day),
+        operations: operations as u64,
+      }),
+      Err(rusqlite::Error::QueryReturnedNoRows) => None,
+      Err(err) => return Err(err),
+    };
+    Ok(summary)
+  }
+
+  fn parse_kind(kind: &str) -> Option<UsageKind> {
+    match kind {
+      "question" => Some(UsageKind::Question),
+      "completion" => Some(UsageKind::Completion),
+      "embedding" => Some(UsageKind::Embedding),
+      "search" => Some(UsageKind::Search),
+      "row_summary" => Some(UsageKind::RowSummary),
+      "row_translation" => Some(UsageKind::RowTranslation),
+      _ => None,
+    }
+  }
+
+  /// Formats a number of days since the Unix epoch as a `YYYY-MM-DD` date.
+  fn format_date(days: i64) -> String {
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+  }
+
+  fn sql_error(err: rusqlite::Error) -> PluginError {
+    PluginError::Internal(err.into())
+  }
+
+  fn writer_stopped() -> PluginError {
+    PluginError::Internal(anyhow::anyhow!("usage writer stopped"))
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Records each question, completion, embedding, search and database row operation to the
+  /// SQLite database at `db_path`, for [OllamaAIPlugin::usage_summary]. Records older than
+  /// [USAGE_RETENTION] are deleted when it is opened.
+  ///
+  /// Only the kind, duration and outcome of each operation are kept, with the lengths of the
+  /// texts and a hash of the chat id, never the texts themselves. Records are written in the
+  /// background and dropped if the database falls behind.
+  #[cfg(feature = "usage-tracking")]
+  pub fn enable_usage_tracking(&self, db_path: PathBuf) -> Result<(), PluginError> {
+    self.usage.enable(db_path)
+  }
+
+  /// Summarizes the operations recorded since [OllamaAIPlugin::enable_usage_tracking] was
+  /// called with the same database, within `range`.
+  #[cfg(feature = "usage-tracking")]
+  pub async fn usage_summary(&self, range: TimeRange) -> Result<UsageSummary, PluginError> {
+    self.usage.summary(range).await
+  }
+}
@@ -0,0 +1,691 @@
+use crate::embedding_index::EmbeddingIndex;
+use crate::embedding_manifest::EmbeddingModelInfo;
+use crate::ollama_plugin::{
+  prepare_store_directories, OllamaAIPlugin, OllamaPluginConfig, VECTOR_STORE_SWITCH_TIMEOUT,
+};
+use crate::scheduler::Priority;
+use crate::store_meta::StoreMigration;
+use af_ai_protocol::method::VS_SWITCH;
+use af_plugin::core::orphan::LOCK_FILE_NAME;
+use af_plugin::error::{PluginError, RemoteError};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+/// File of the persist directory present while the vector store is compacted. Found at init, it
+/// means the plugin died mid-compaction, which is then run again.
+pub const COMPACTION_MARKER_NAME: &str = "compaction_in_progress";
+
+/// Name of the first entry of a snapshot, holding its [StoreSnapshotInfo].
+const SNAPSHOT_MANIFEST_NAME: &str = "snapshot_manifest.json";
+/// Directory of the snapshot holding the files of the persist directory.
+const STORE_DIR: &str = "store";
+/// Largest snapshot manifest that is read, anything bigger is not a snapshot.
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024;
+
+/// Describes the vector store saved by
+/// [OllamaAIPlugin::export_vector_store](crate::ollama_plugin::OllamaAIPlugin::export_vector_store).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreSnapshotInfo {
+  /// Embedding model that produced the vectors.
+  pub model: String,
+  pub dimension: usize,
+  pub item_count: u64,
+  /// Unix timestamp in seconds.
+  pub created_at: u64,
+}
+
+impl StoreSnapshotInfo {
+  pub fn new(model_info: EmbeddingModelInfo, item_count: u64) -> Self {
+    let created_at = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or_default();
+    Self {
+      model: model_info.model,
+      dimension: model_info.dimension,
+      item_count,
+      created_at,
+    }
+  }
+
+  /// Checks that the vectors of the snapshot can be searched with the `current` embedding model.
+  pub fn check_compatible(&self, current: &EmbeddingModelInfo) -> Result<(), PluginError> {
+    if self.model != current.model {
+      return Err(PluginError::EmbeddingModelChanged {
+        stored: self.model.clone(),
+        configured: current.model.clone(),
+      });
+    }
+    if self.dimension != current.dimension {
+      return Err(PluginError::EmbeddingDimensionChanged {
+        stored: self.dimension,
+        configured: current.dimension,
+      });
+    }
+    Ok(())
+  }
+}
+
+/// Size of the vector store, see
+/// [OllamaAIPlugin::vector_store_stats](crate::ollama_plugin::OllamaAIPlugin::vector_store_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorStoreStats {
+  pub total_documents: u64,
+  pub total_chunks: u64,
+  /// Bytes of the files of the persist directory, `0` without one.
+  pub disk_bytes: u64,
+  /// Chunks deleted but still taking space on disk, until the vector store is compacted.
+  pub deleted_chunks: u64,
+}
+
+/// Outcome of
+/// [OllamaAIPlugin::compact_vector_store](crate::ollama_plugin::OllamaAIPlugin::compact_vector_store).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+  /// How much smaller the persist directory got.
+  pub reclaimed_bytes: u64,
+  pub duration: Duration,
+}
+
+/// How [OllamaAIPlugin::import_vector_store](crate::ollama_plugin::OllamaAIPlugin::import_vector_store)
+/// treats a snapshot made with another embedding model.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ImportPolicy {
+  /// Reject the snapshot with [PluginError::EmbeddingModelChanged] or
+  /// [PluginError::EmbeddingDimensionChanged].
+  #[default]
+  Validate,
+  /// Import the snapshot anyway. Its manifest is kept, so the next init applies the configured
+  /// [MismatchPolicy](crate::embedding_manifest::MismatchPolicy).
+  Force,
+}
+
+/// Writes `info` followed by the files of `persist_directory` to `dest`, as a zstd compressed tar
+/// archive. `dest` is only replaced once the archive is complete.
+pub(crate) fn write_snapshot(
+  persist_directory: &Path,
+  info: &StoreSnapshotInfo,
+  dest: &Path,
+) -> Result<(), PluginError> {
+  let partial = with_suffix(dest, ".partial");
+  match write_archive(persist_directory, info, &partial) {
+    Ok(()) => {
+      std::fs::rename(&partial, dest)?;
+      Ok(())
+    },
+    Err(err) => {
+      let _ = std::fs::remove_file(&partial);
+      Err(err)
+    },
+  }
+}
+
+/// Reads the [StoreSnapshotInfo] of the snapshot at `src`.
+pub(crate) fn read_snapshot_info(src: &Path) -> Result<StoreSnapshotInfo, PluginError> {
+  let mut archive = open_archive(src)?;
+  let mut entries = archive.entries().map_err(archive_error)?;
+  read_manifest_entry(&mut entries)
+}
+
+/// Replaces the content of `persist_directory` with the files of the snapshot at `src`. The
+/// snapshot is extracted next to the directory first, so a broken snapshot leaves it untouched,
+/// and the old directory is only deleted once the new one is in place. The lock file of the
+/// directory is carried over.
+pub(crate) fn restore_snapshot(src: &Path, persist_directory: &Path) -> Result<(), PluginError> {
+  let staging = with_suffix(persist_directory, ".importing");
+  let previous = with_suffix(persist_directory, ".previous");
+  for leftover in [&staging, &previous] {
+    if leftover.exists() {
+      std::fs::remove_dir_all(leftover)?;
+    }
+  }
+  std::fs::create_dir_all(&staging)?;
+  let lock = persist_directory.join(LOCK_FILE_NAME);
+  let extracted = extract_archive(src, &staging).and_then(|_| {
+    if lock.exists() {
+      std::fs::copy(&lock, staging.join(LOCK_FILE_NAME))?;
+    }
+    Ok(())
+  });
+  if let Err(err) = extracted {
+    let _ = std::fs::remove_dir_all(&staging);
+    return Err(err);
+  }
+
+  if persist_directory.exists() {
+    std::fs::rename(persist_directory, &previous)?;
+  }
+  if let Err(err) = std::fs::rename(&staging, persist_directory) {
+    if previous.exists() {
+      let _ = std::fs::rename(&previous, persist_directory);
+    }
+    let _ = std::fs::remove_dir_all(&staging);
+    return Err(err.into());
+  }
+  if previous.exists() {
+    if let Err(err) = std::fs::remove_dir_all(&previous) {
+      warn!(
+        "[AI Plugin] failed to delete the replaced vector store {:?}: {}",
+        previous, err
+      );
+    }
+  }
+  Ok(())
+}
+
+/// Total size of the files under `dir`. Symlinks are not followed.
+pub(crate) fn directory_size(dir: &Path) -> Result<u64, PluginError> {
+  let mut size = 0;
+  for entry in std::fs::read_dir(dir)? {
+    let entry = entry?;
+    let file_type = entry.file_type()?;
+    if file_type.is_dir() {
+      size += directory_size(&entry.path())?;
+    } else if file_type.is_file() {
+      size += entry.metadata()?.len();
+    }
+  }
+  Ok(size)
+}
+
+/// Marks `persist_directory` as being compacted, until [end_compaction].
+pub(crate) fn begin_compaction(persist_directory: &Path) -> Result<(), PluginError> {
+  let started_at = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_secs())
+    .unwrap_or_default();
+  let mut marker = File::create(persist_directory.join(COMPACTION_MARKER_NAME))?;
+  marker.write_all(started_at.to_string().as_bytes())?;
+  marker.sync_all()?;
+  Ok(())
+}
+
+pub(crate) fn end_compaction(persist_directory: &Path) -> Result<(), PluginError> {
+  match std::fs::remove_file(persist_directory.join(COMPACTION_MARKER_NAME)) {
+    Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+    _ => Ok(()),
+  }
+}
+
+/// Whether a compaction of `persist_directory` started and never ended.
+pub(crate) fn compaction_interrupted(persist_directory: &Path) -> bool {
+  persist_directory.join(COMPACTION_MARKER_NAME).exists()
+}
+
+fn write_archive(
+  persist_directory: &Path,
+  info: &StoreSnapshotInfo,
+  dest: &Path,
+) -> Result<(), PluginError> {
+  let encoder = zstd::Encoder::new(
+    BufWriter::new(File::create(dest)?),
+    zstd::DEFAULT_COMPRESSION_LEVEL,
+  )?;
+  let mut archive = tar::Builder::new(encoder);
+  let manifest =
+    serde_json::to_vec_pretty(info).map_err(|err| PluginError::Internal(err.into()))?;
+  let mut header = entry_header(
+    tar::EntryType::Regular,
+    manifest.len() as u64,
+    info.created_at,
+  );
+  archive.append_data(&mut header, SNAPSHOT_MANIFEST_NAME, manifest.as_slice())?;
+  let mut header = entry_header(tar::EntryType::Directory, 0, info.created_at);
+  archive.append_data(&mut header, STORE_DIR, io::empty())?;
+  append_dir(
+    &mut archive,
+    persist_directory,
+    Path::new(STORE_DIR),
+    info.created_at,
+  )?;
+  archive
+    .into_inner()?
+    .finish()?
+    .into_inner()
+    .map_err(|err| err.into_error())?
+    .sync_all()?;
+  Ok(())
+}
+
+fn append_dir(
+  archive: &mut tar::Builder<impl Write>,
+  dir: &Path,
+  name: &Path,
+  mtime: u64,
+) -> Result<(), PluginError> {
+  let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+  entries.sort_by_key(|entry| entry.file_name());
+  for entry in entries {
+    // The lock belongs to the exporting application.
+    if name == Path::new(STORE_DIR) && entry.file_name() == LOCK_FILE_NAME {
+      continue;
+    }
+    let entry_name = name.join(entry.file_name());
+    // Symlinks are skipped, the vector store doesn't create any.
+    let file_type = entry.file_type()?;
+    if file_type.is_dir() {
+      let mut header = entry_header(tar::EntryType::Directory, 0, mtime);
+      archive.append_data(&mut header, &entry_name, io::empty())?;
+      append_dir(archive, &entry.path(), &entry_name, mtime)?;
+    } else if file_type.is_file() {
+      let file = File::open(entry.path())?;
+      let size = file.metadata()?.len();
+      let mut header = entry_header(tar::EntryType::Regular, size, mtime);
+      let file = ExactSize {
+        inner: file.take(size),
+        remaining: size,
+      };
+      archive
+        .append_data(&mut header, &entry_name, file)
+        .map_err(|err| match err.kind() {
+          io::ErrorKind::UnexpectedEof => invalid_snapshot(format!(
+            "{} changed while it was exported",
+            entry_name.display()
+          )),
+          _ => err.into(),
+        })?;
+    }
+  }
+  Ok(())
+}
+
+/// Reads exactly `remaining` bytes of `inner`, failing if it ends early. An entry shorter than
+/// its header would corrupt the archive.
+struct ExactSize<R> {
+  inner: R,
+  remaining: u64,
+}
+
+impl<R: Read> Read for ExactSize<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.inner.read(buf)?;
+    if read == 0 && self.remaining > 0 && !buf.is_empty() {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    self.remaining -= read as u64;
+    Ok(read)
+  }
+}
+
+fn entry_header(kind: tar::EntryType, size: u64, mtime: u64) -> tar::Header {
+  let mut header = tar::Header::new_gnu();
+  header.set_entry_type(kind);
+  header.set_size(size);
+  header.set_mtime(mtime);
+  header.set_mode(if kind.is_dir() { 0o755 } else { 0o644 });
+  header
+}
+
+fn open_archive(src: &Path) -> Result<tar::Archive<impl Read>, PluginError> {
+  let decoder =
+    zstd::Decoder::new(File::open(src)?).map_err(|err| invalid_snapshot(err.to_string()))?;
+  Ok(tar::Archive::new(decoder))
+}
+
+fn extract_archive(src: &Path, dest: &Path) -> Result<(), PluginError> {
+  let mut archive = open_archive(src)?;
+  let mut entries = archive.entries().map_err(archive_error)?;
+  read_manifest_entry(&mut entries)?;
+  for entry in entries {
+    let mut entry = entry.map_err(archive_error)?;
+    let name = entry.path().map_err(archive_error)?.into_owned();
+    let path = match name.strip_prefix(STORE_DIR) {
+      Ok(path) => safe_relative_path(path)?,
+      Err(_) => {
+        return Err(invalid_snapshot(format!(
+          "unexpected entry {}",
+          name.display()
+        )))
+      },
+    };
+    let kind = entry.header().entry_type();
+    if kind.is_dir() {
+      std::fs::create_dir_all(dest.join(path))?;
+    } else if kind.is_file() {
+      let path = dest.join(path);
+      if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      let mut file = BufWriter::new(File::create(&path)?);
+      io::copy(&mut entry, &mut file).map_err(archive_error)?;
+      file.flush()?;
+    }
+  }
+  Ok(())
+}
+
+fn read_manifest_entry<R: Read>(
+  entries: &mut tar::Entries<'_, R>,
+) -> Result<StoreSnapshotInfo, PluginError> {
+  let entry = entries
+    .next()
+    .ok_or_else(|| invalid_snapshot("empty archive"))?;
+  let entry = entry.map_err(archive_error)?;
+  let is_manifest = entry
+    .path()
+    .is_ok_and(|path| path == Path::new(SNAPSHOT_MANIFEST_NAME));
+  if !is_manifest || entry.size() > MAX_MANIFEST_SIZE {
+    return Err(invalid_snapshot("missing snapshot manifest"));
+  }
+  serde_json::from_reader(entry).map_err(|err| invalid_snapshot(err.to_string()))
+}
+
+/// Rejects entries that would be extracted outside the persist directory.
+fn safe_relative_path(path: &Path) -> Result<PathBuf, PluginError> {
+  if path
+    .components()
+    .all(|component| matches!(component, Component::Normal(_)))
+  {
+    Ok(path.to_path_buf())
+  } else {
+    Err(invalid_snapshot(format!("unsafe path {:?}", path)))
+  }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+  name.push(suffix);
+  path.with_file_name(name)
+}
+
+/// Errors of a malformed archive are reported as an invalid snapshot.
+fn archive_error(err: io::Error) -> PluginError {
+  match err.kind() {
+    io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof => {
+      invalid_snapshot(err.to_string())
+    },
+    _ => err.into(),
+  }
+}
+
+fn invalid_snapshot(reason: impl Into<String>) -> PluginError {
+  PluginError::Io(io::Error::new(
+    io::ErrorKind::InvalidData,
+    format!("invalid vector store snapshot: {}", reason.into()),
+  ))
+}
+
+impl OllamaAIPlugin {
+  /// Saves the vector store to `dest` as a single archive that [Self::import_vector_store] can
+  /// restore, e.g. on another device. Embedding waits until the export is done.
+  ///
+  /// Requires RAG to be enabled, see [OllamaPluginConfig::set_rag_enabled].
+  pub async fn export_vector_store(&self, dest: &Path) -> Result<StoreSnapshotInfo, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let persist_directory = self.persist_directory().await?;
+    let model_info = self.embedding_model_info().await?;
+    let _store = self.vector_store_lock.write().await;
+    let plugin = self.get_ai_plugin().await?;
+    let item_count = self
+      .embedding_operation(plugin)
+      .export_vector_store()
+      .await?;
+    let snapshot = StoreSnapshotInfo::new(model_info, item_count);
+    let info = snapshot.clone();
+    let dest = dest.to_path_buf();
+    tokio::task::spawn_blocking(move || write_snapshot(&persist_directory, &info, &dest))
+      .await
+      .map_err(|err| PluginError::Internal(err.into()))??;
+    info!(
+      "[AI Plugin] exported {} vectors of {}",
+      snapshot.item_count, snapshot.model
+    );
+    Ok(snapshot)
+  }
+
+  /// Replaces the vector store with the snapshot at `src`, made by [Self::export_vector_store].
+  ///
+  /// With [ImportPolicy::Validate], a snapshot of another embedding model or dimension is
+  /// rejected and the vector store is left untouched.
+  pub async fn import_vector_store(
+    &self,
+    src: &Path,
+    policy: ImportPolicy,
+  ) -> Result<StoreSnapshotInfo, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let persist_directory = self.persist_directory().await?;
+    let path = src.to_path_buf();
+    let snapshot = tokio::task::spawn_blocking(move || read_snapshot_info(&path))
+      .await
+      .map_err(|err| PluginError::Internal(err.into()))??;
+    if policy == ImportPolicy::Validate {
+      snapshot.check_compatible(&self.embedding_model_info().await?)?;
+    }
+
+    let _store = self.vector_store_lock.write().await;
+    let src = src.to_path_buf();
+    let directory = persist_directory.clone();
+    tokio::task::spawn_blocking(move || restore_snapshot(&src, &directory))
+      .await
+      .map_err(|err| PluginError::Internal(err.into()))??;
+    // The restored directory has its own manifest and index.
+    self.embedding_model_info.write().await.take();
+    *self.embedding_index.write().await = Arc::new(EmbeddingIndex::open(&persist_directory)?);
+    let plugin = self.get_ai_plugin().await?;
+    self
+      .embedding_operation(plugin)
+      .reload_vector_store()
+      .await?;
+    info!(
+      "[AI Plugin] imported {} vectors of {}",
+      snapshot.item_count, snapshot.model
+    );
+    Ok(snapshot)
+  }
+
+  /// Moves the vector store to `persist_directory`, creating the directory if needed, e.g. when
+  /// the user picks another storage location. Chats keep being answered during the switch.
+  ///
+  /// Indexing and searches started during the switch wait for it and then use the new directory.
+  /// The ones in flight are given [VECTOR_STORE_SWITCH_TIMEOUT] to finish, after which the switch
+  /// fails with [PluginError::RequestTimeout] and the vector store is left untouched. A plugin
+  /// that can't switch its vector store is initialized again with the new directory instead.
+  pub async fn switch_vector_store(&self, persist_directory: PathBuf) -> Result<(), PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let mut config = self
+      .plugin_config
+      .read()
+      .await
+      .clone()
+      .ok_or_else(|| PluginError::Internal(anyhow!("Plugin is not initialized")))?;
+    let store = timeout(VECTOR_STORE_SWITCH_TIMEOUT, self.vector_store_lock.write())
+      .await
+      .map_err(|_| PluginError::RequestTimeout(VECTOR_STORE_SWITCH_TIMEOUT))?;
+    self.vector_store_switching.store(true, Ordering::SeqCst);
+    let result = self
+      .switch_locked_vector_store(&mut config, persist_directory)
+      .await;
+    self.vector_store_switching.store(false, Ordering::SeqCst);
+    drop(store);
+    result?;
+    self.finish_interrupted_compaction().await;
+    self.retry_pending_cleanup().await;
+    Ok(())
+  }
+
+  pub(crate) async fn switch_locked_vector_store(
+    &self,
+    config: &mut OllamaPluginConfig,
+    persist_directory: PathBuf,
+  ) -> Result<(), PluginError> {
+    config.set_rag_enabled(&persist_directory)?;
+    if self.supports(VS_SWITCH) != Some(false) {
+      prepare_store_directories(config, &self.plugin_manager)?;
+      let plugin = self.get_ai_plugin().await?;
+      match self
+        .embedding_operation(plugin)
+        .switch_vector_store(&persist_directory)
+        .await
+      {
+        Ok(()) => {
+          self.embedding_model_info.write().await.take();
+          let embedding_index = match config.writable_directory() {
+            Some(directory) => EmbeddingIndex::open(directory)?,
+            None => EmbeddingIndex::in_memory(),
+          };
+          *self.embedding_index.write().await = Arc::new(embedding_index);
+          let previous = self.plugin_config.write().await.replace(config.clone());
+          if let Some(previous) = previous
+            .as_ref()
+            .and_then(OllamaPluginConfig::writable_directory)
+            .filter(|directory| Some(*directory) != config.writable_directory())
+          {
+            self.plugin_manager.unlock_persist_directory(previous);
+          }
+          self.check_store_format().await?;
+          info!(
+            "[AI Plugin] switched vector store to {:?}",
+            persist_directory
+          );
+          return Ok(());
+        },
+        Err(err)
+          if !err
+            .remote_error()
+            .is_some_and(RemoteError::is_method_not_found) =>
+        {
+          return Err(err)
+        },
+        Err(_) => {},
+      }
+    }
+    info!(
+      "[AI Plugin] plugin can't switch its vector store, initializing it with {:?}",
+      persist_directory
+    );
+    self.init_plugin(config.clone()).await
+  }
+
+  /// Counts the documents and chunks of the vector store, and measures its persist directory.
+  /// `disk_bytes` is `0` when RAG is not enabled.
+  pub async fn vector_store_stats(&self) -> Result<VectorStoreStats, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    let counts = self
+      .embedding_operation(plugin)
+      .vector_store_stats()
+      .await?;
+    let disk_bytes = match self.persist_directory().await {
+      Ok(persist_directory) => {
+        tokio::task::spawn_blocking(move || directory_size(&persist_directory))
+          .await
+          .map_err(|err| PluginError::Internal(err.into()))??
+      },
+      Err(_) => 0,
+    };
+    Ok(VectorStoreStats {
+      total_documents: counts.total_documents,
+      total_chunks: counts.total_chunks,
+      disk_bytes,
+      deleted_chunks: counts.deleted_chunks,
+    })
+  }
+
+  /// Reclaims the disk space of the deleted chunks of the vector store.
+  ///
+  /// The compaction runs in the background lane, see [Priority::Background], and embedding waits
+  /// until it is done, while questions and completions are answered as usual. If the plugin dies
+  /// mid-compaction, the next [OllamaAIPlugin::init_plugin] runs it again.
+  ///
+  /// Requires RAG to be enabled, see [OllamaPluginConfig::set_rag_enabled].
+  pub async fn compact_vector_store(&self) -> Result<CompactionReport, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let persist_directory = self.persist_directory().await?;
+    let _permit = self.scheduler.acquire(Priority::Background).await;
+    let _store = self.vector_store_lock.write().await;
+    let started = std::time::Instant::now();
+    let directory = persist_directory.clone();
+    let before = tokio::task::spawn_blocking(move || {
+      begin_compaction(&directory)?;
+      directory_size(&directory)
+    })
+    .await
+    .map_err(|err| PluginError::Internal(err.into()))??;
+    let plugin = self.get_ai_plugin().await?;
+    let result = self
+      .embedding_operation(plugin)
+      .with_priority(Priority::Background)
+      .compact_vector_store()
+      .await;
+    if let Err(err) = result {
+      // A plugin that replied didn't die mid-compaction, there is nothing to run again.
+      if err.remote_error().is_some() {
+        end_compaction(&persist_directory)?;
+      }
+      return Err(err);
+    }
+    let after = tokio::task::spawn_blocking(move || {
+      end_compaction(&persist_directory)?;
+      directory_size(&persist_directory)
+    })
+    .await
+    .map_err(|err| PluginError::Internal(err.into()))??;
+    let report = CompactionReport {
+      reclaimed_bytes: before.saturating_sub(after),
+      duration: started.elapsed(),
+    };
+    info!("[AI Plugin] compacted vector store: {:?}", report);
+    Ok(report)
+  }
+
+  /// Runs the compaction the plugin died in the middle of, if any, see
+  /// [OllamaAIPlugin::compact_vector_store].
+  pub(crate) async fn finish_interrupted_compaction(&self) {
+    if self.vector_store_switching.load(Ordering::SeqCst) {
+      // Run by the switch once it releases the vector store.
+      return;
+    }
+    match self.persist_directory().await {
+      Ok(persist_directory) if compaction_interrupted(&persist_directory) => {},
+      _ => return,
+    }
+    warn!("[AI Plugin] vector store compaction was interrupted, running it again");
+    if let Err(err) = self.compact_vector_store().await {
+      error!(
+        "[AI Plugin] failed to finish vector store compaction: {:?}",
+        err
+      );
+    }
+  }
+
+  /// Takes `vector_store_lock` to embed or search, failing with
+  /// [PluginError::StoreNeedsMigration] until the vector store is migrated, see
+  /// [OllamaAIPlugin::migrate_vector_store].
+  pub(crate) async fn read_vector_store(
+    &self,
+  ) -> Result<tokio::sync::RwLockReadGuard<'_, ()>, PluginError> {
+    if let Some(StoreMigration { from, to }) = *self.store_migration.lock() {
+      return Err(PluginError::StoreNeedsMigration { from, to });
+    }
+    Ok(self.vector_store_lock.read().await)
+  }
+
+  pub(crate) async fn persist_directory(&self) -> Result<PathBuf, PluginError> {
+    self
+      .plugin_config
+      .read()
+      .await
+      .as_ref()
+      .and_then(|config| config.writable_directory().cloned())
+      .ok_or_else(|| PluginError::Internal(anyhow!("RAG is not enabled")))
+  }
+
+  /// Fails with [PluginError::ReadOnlyStore] when indexing has no store to write to.
+  pub(crate) async fn check_store_writable(&self) -> Result<(), PluginError> {
+    match self.plugin_config.read().await.as_ref() {
+      Some(config) if config.read_only && config.overlay_directory.is_none() => {
+        Err(PluginError::ReadOnlyStore)
+      },
+      _ => Ok(()),
+    }
+  }
+}
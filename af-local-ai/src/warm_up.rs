@@ -0,0 +1,142 @@
+use crate::ai_ops::STREAM_METADATA_KEY;
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::trace;
+
+/// Interval of [WarmUpProgress::StillLoading] while the plugin reports no progress.
+pub const WARM_UP_HEARTBEAT: Duration = Duration::from_secs(2);
+/// Time the first frame of an answer may take before the stream reports that the model is
+/// loading, see [OllamaPluginConfig::with_warming_up_threshold](crate::ollama_plugin::OllamaPluginConfig::with_warming_up_threshold).
+pub const DEFAULT_WARMING_UP_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Progress of [OllamaAIPlugin::warm_up_model](crate::ollama_plugin::OllamaAIPlugin::warm_up_model).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarmUpProgress {
+  Started,
+  /// Bytes of the model loaded so far, as reported by Ollama.
+  Loading {
+    completed: u64,
+    total: u64,
+  },
+  /// Sent every [WARM_UP_HEARTBEAT] while no progress is reported.
+  StillLoading {
+    elapsed: Duration,
+  },
+  /// The model is loaded and answers without delay.
+  Done,
+  Failed(String),
+}
+
+/// Turns the frames of the `warm_up` request into [WarmUpProgress], ending with
+/// [WarmUpProgress::Done] or [WarmUpProgress::Failed].
+pub(crate) fn warm_up_progress(
+  stream: Result<ReceiverStream<Result<Value, PluginError>>, PluginError>,
+) -> ReceiverStream<WarmUpProgress> {
+  let (tx, rx) = tokio::sync::mpsc::channel(8);
+  tokio::spawn(async move {
+    if tx.send(WarmUpProgress::Started).await.is_err() {
+      return;
+    }
+    let mut stream = match stream {
+      Ok(stream) => stream,
+      Err(err) => {
+        let _ = tx.send(WarmUpProgress::Failed(err.to_string())).await;
+        return;
+      },
+    };
+
+    let started = Instant::now();
+    let mut next_heartbeat = started + WARM_UP_HEARTBEAT;
+    loop {
+      let progress = match tokio::time::timeout_at(next_heartbeat, stream.next()).await {
+        Err(_) => {
+          next_heartbeat += WARM_UP_HEARTBEAT;
+          WarmUpProgress::StillLoading {
+            elapsed: started.elapsed(),
+          }
+        },
+        Ok(None) => WarmUpProgress::Done,
+        Ok(Some(Ok(frame))) => match load_progress(&frame) {
+          Some(progress) => {
+            next_heartbeat = Instant::now() + WARM_UP_HEARTBEAT;
+            progress
+          },
+          None => continue,
+        },
+        Ok(Some(Err(err))) => WarmUpProgress::Failed(err.to_string()),
+      };
+      let finished = matches!(progress, WarmUpProgress::Done | WarmUpProgress::Failed(_));
+      if tx.send(progress).await.is_err() || finished {
+        return;
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+/// Forwards an answer stream, preceded by a `{"warming_up": true}` metadata frame when its first
+/// frame takes longer than `threshold`, which usually means Ollama is loading the model.
+pub(crate) fn warming_up_hint(
+  mut stream: ReceiverStream<Result<Value, PluginError>>,
+  threshold: Duration,
+) -> ReceiverStream<Result<Value, PluginError>> {
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  tokio::spawn(async move {
+    let first = match tokio::time::timeout(threshold, stream.next()).await {
+      Ok(first) => first,
+      Err(_) => {
+        trace!(
+          "[AI Plugin] no answer after {:?}, model is warming up",
+          threshold
+        );
+        let hint = json!({ STREAM_METADATA_KEY: { "warming_up": true } });
+        if tx.send(Ok(hint)).await.is_err() {
+          return;
+        }
+        stream.next().await
+      },
+    };
+    let first = match first {
+      Some(first) => first,
+      None => return,
+    };
+    if tx.send(first).await.is_err() {
+      return;
+    }
+    while let Some(frame) = stream.next().await {
+      if tx.send(frame).await.is_err() {
+        break;
+      }
+    }
+  });
+  ReceiverStream::new(rx)
+}
+
+fn load_progress(frame: &Value) -> Option<WarmUpProgress> {
+  Some(WarmUpProgress::Loading {
+    completed: frame.get("completed")?.as_u64()?,
+    total: frame.get("total")?.as_u64()?,
+  })
+}
+
+impl OllamaAIPlugin {
+  /// Loads the chat model ahead of the first question, which otherwise waits for Ollama to page
+  /// the model into memory. The stream reports the load progress and ends with
+  /// [WarmUpProgress::Done] or [WarmUpProgress::Failed].
+  pub async fn warm_up_model(&self) -> ReceiverStream<WarmUpProgress> {
+    warm_up_progress(self.start_warm_up().await)
+  }
+
+  pub(crate) async fn start_warm_up(
+    &self,
+  ) -> Result<ReceiverStream<Result<Value, PluginError>>, PluginError> {
+    self.wait_until_plugin_ready().await?;
+    let plugin = self.get_ai_plugin().await?;
+    self.operation(plugin).warm_up().await
+  }
+}
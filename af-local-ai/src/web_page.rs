@@ -0,0 +1,339 @@
+use crate::citation::{FILE_NAME_KEY, SOURCE_ID_KEY};
+use crate::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::{header, StatusCode, Url};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// Metadata key holding the URL a text was fetched from, see
+/// [OllamaAIPlugin::embed_url](crate::ollama_plugin::OllamaAIPlugin::embed_url).
+pub const SOURCE_URL_KEY: &str = "source_url";
+
+/// Web pages larger than this are not fetched.
+pub const MAX_WEB_PAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Time budget of a fetch, from the connection to the end of the body.
+pub const WEB_PAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAX_REDIRECTS: usize = 10;
+
+/// Readable content of a fetched web page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebPage {
+  /// The URL the page was served from, after redirects.
+  pub url: String,
+  pub title: Option<String>,
+  pub text: String,
+}
+
+/// Fetches the HTML page at `url` and extracts its text with [html_to_text].
+///
+/// Only `http` and `https` URLs answered with status 200 and an HTML content type are read. Other
+/// content types fail with [PluginError::UnsupportedContentType], other statuses with
+/// [PluginError::HttpStatus], and redirect loops, timeouts and pages larger than
+/// [MAX_WEB_PAGE_BYTES] with [PluginError::FetchFailed].
+pub async fn fetch_web_page(url: &str) -> Result<WebPage, PluginError> {
+  let fetch_failed = |reason: String| PluginError::FetchFailed {
+    url: url.to_string(),
+    reason,
+  };
+  let parsed = Url::parse(url).map_err(|err| fetch_failed(format!("invalid URL: {}", err)))?;
+  if !is_supported_scheme(&parsed) {
+    return Err(fetch_failed(format!(
+      "unsupported scheme: {}",
+      parsed.scheme()
+    )));
+  }
+
+  let client = reqwest::Client::builder()
+    .timeout(WEB_PAGE_TIMEOUT)
+    .redirect(Policy::custom(check_redirect))
+    .build()
+    .map_err(|err| fetch_failed(describe(&err)))?;
+  let mut response = client
+    .get(parsed)
+    .header(header::ACCEPT, "text/html,application/xhtml+xml")
+    .send()
+    .await
+    .map_err(|err| fetch_failed(describe(&err)))?;
+
+  if response.status() != StatusCode::OK {
+    return Err(PluginError::HttpStatus {
+      url: url.to_string(),
+      status: response.status().as_u16(),
+    });
+  }
+  let content_type = response
+    .headers()
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default()
+    .to_string();
+  if !is_html(&content_type) {
+    return Err(PluginError::UnsupportedContentType(
+      if content_type.is_empty() {
+        "unknown".to_string()
+      } else {
+        content_type
+      },
+    ));
+  }
+  let too_large = || fetch_failed(format!("page is larger than {} bytes", MAX_WEB_PAGE_BYTES));
+  if response
+    .content_length()
+    .is_some_and(|length| length > MAX_WEB_PAGE_BYTES as u64)
+  {
+    return Err(too_large());
+  }
+
+  let final_url = response.url().to_string();
+  let mut body = Vec::new();
+  while let Some(chunk) = response
+    .chunk()
+    .await
+    .map_err(|err| fetch_failed(describe(&err)))?
+  {
+    if body.len() + chunk.len() > MAX_WEB_PAGE_BYTES {
+      return Err(too_large());
+    }
+    body.extend_from_slice(&chunk);
+  }
+
+  let html = String::from_utf8_lossy(&body);
+  Ok(WebPage {
+    url: final_url,
+    title: html_title(&html),
+    text: html_to_text(&html),
+  })
+}
+
+fn is_supported_scheme(url: &Url) -> bool {
+  matches!(url.scheme(), "http" | "https")
+}
+
+fn is_html(content_type: &str) -> bool {
+  let media_type = content_type
+    .split(';')
+    .next()
+    .unwrap_or_default()
+    .trim()
+    .to_ascii_lowercase();
+  matches!(media_type.as_str(), "text/html" | "application/xhtml+xml")
+}
+
+fn check_redirect(attempt: Attempt) -> reqwest::redirect::Action {
+  if attempt.previous().iter().any(|url| url == attempt.url()) {
+    attempt.error("redirect loop")
+  } else if attempt.previous().len() > MAX_REDIRECTS {
+    attempt.error(format!("more than {} redirects", MAX_REDIRECTS))
+  } else if !is_supported_scheme(attempt.url()) {
+    let message = format!("redirect to unsupported scheme: {}", attempt.url().scheme());
+    attempt.error(message)
+  } else {
+    attempt.follow()
+  }
+}
+
+/// The message of a reqwest error, with the messages of its causes.
+fn describe(err: &reqwest::Error) -> String {
+  if err.is_timeout() {
+    return format!("timed out after {:?}", WEB_PAGE_TIMEOUT);
+  }
+  let mut message = err.to_string();
+  let mut source = err.source();
+  while let Some(cause) = source {
+    message.push_str(": ");
+    message.push_str(&cause.to_string());
+    source = cause.source();
+  }
+  message
+}
+
+/// Elements whose content is not part of the readable text of a page.
+const SKIPPED_ELEMENTS: [&str; 13] = [
+  "head", "script", "style", "noscript", "template", "svg", "iframe", "nav", "header", "footer",
+  "aside", "form", "button",
+];
+
+/// Elements that start a new line of text.
+const BLOCK_ELEMENTS: [&str; 26] = [
+  "p",
+  "div",
+  "br",
+  "li",
+  "ul",
+  "ol",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "tr",
+  "td",
+  "th",
+  "table",
+  "section",
+  "article",
+  "main",
+  "blockquote",
+  "pre",
+  "hr",
+  "dt",
+  "dd",
+  "figcaption",
+  "body",
+];
+
+/// The content of the `<title>` element of `html`.
+pub fn html_title(html: &str) -> Option<String> {
+  let lower = html.to_ascii_lowercase();
+  let start = lower.find("<title")?;
+  let start = start + lower[start..].find('>')? + 1;
+  let end = start + lower[start..].find("</title")?;
+  Some(collapse_whitespace(&decode_entities(&html[start..end]))).filter(|title| !title.is_empty())
+}
+
+/// Extracts the readable text of `html`: drops the tags, comments and the elements that are not
+/// content, such as scripts and navigation, and keeps one line per block.
+pub fn html_to_text(html: &str) -> String {
+  // Lowercasing ASCII only keeps the byte offsets of `html`.
+  let lower = html.to_ascii_lowercase();
+  let mut text = String::new();
+  let mut position = 0;
+  while let Some(offset) = lower[position..].find('<') {
+    let tag_start = position + offset;
+    push_text(&mut text, &html[position..tag_start]);
+    if lower[tag_start..].starts_with("<!--") {
+      position = lower[tag_start..]
+        .find("-->")
+        .map_or(html.len(), |end| tag_start + end + 3);
+      continue;
+    }
+    let Some(tag_end) = lower[tag_start..].find('>').map(|end| tag_start + end + 1) else {
+      position = html.len();
+      break;
+    };
+    let tag = &lower[tag_start + 1..tag_end - 1];
+    let closing = tag.starts_with('/');
+    let name = tag
+      .trim_start_matches('/')
+      .split(|c: char| c.is_whitespace() || c == '/')
+      .next()
+      .unwrap_or_default();
+    position = tag_end;
+    if !closing && !tag.ends_with('/') && SKIPPED_ELEMENTS.contains(&name) {
+      position = lower[tag_end..]
+        .find(&format!("</{}", name))
+        .and_then(|end| {
+          let close = tag_end + end;
+          lower[close..].find('>').map(|end| close + end + 1)
+        })
+        .unwrap_or(html.len());
+    }
+    if BLOCK_ELEMENTS.contains(&name) {
+      text.push('\n');
+    }
+  }
+  push_text(&mut text, &html[position..]);
+
+  text
+    .lines()
+    .map(collapse_whitespace)
+    .filter(|line| !line.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Appends a text node, whose line breaks are only whitespace; lines come from block elements.
+fn push_text(text: &mut String, node: &str) {
+  text.push_str(&decode_entities(node).replace(['\r', '\n'], " "));
+}
+
+fn collapse_whitespace(text: &str) -> String {
+  text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Decodes the character references of `text`, e.g. `&amp;` or `&#39;`. Unknown references are
+/// kept as they are.
+pub fn decode_entities(text: &str) -> String {
+  let mut decoded = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    decoded.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let reference = rest[1..]
+      .find(';')
+      .filter(|end| *end <= 10)
+      .map(|end| &rest[1..=end]);
+    match reference.and_then(decode_reference) {
+      Some(c) => {
+        decoded.push(c);
+        rest = &rest[reference.map_or(0, str::len) + 2..];
+      },
+      None => {
+        decoded.push('&');
+        rest = &rest[1..];
+      },
+    }
+  }
+  decoded.push_str(rest);
+  decoded
+}
+
+fn decode_reference(reference: &str) -> Option<char> {
+  if let Some(number) = reference.strip_prefix('#') {
+    let code = match number.strip_prefix(['x', 'X']) {
+      Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+      None => number.parse().ok()?,
+    };
+    return char::from_u32(code);
+  }
+  match reference {
+    "amp" => Some('&'),
+    "lt" => Some('<'),
+    "gt" => Some('>'),
+    "quot" => Some('"'),
+    "apos" => Some('\''),
+    "nbsp" => Some(' '),
+    "ndash" => Some('–'),
+    "mdash" => Some('—'),
+    "hellip" => Some('…'),
+    "copy" => Some('©'),
+    _ => None,
+  }
+}
+
+impl OllamaAIPlugin {
+  /// Fetches the web page at `url` and embeds its text into `chat_id`, see [fetch_web_page] for
+  /// the pages that can be read.
+  ///
+  /// The page is listed as an attachment of the chat with the URL as its source id, and its
+  /// chunks carry the URL under [SOURCE_URL_KEY]. Embedding the same URL again replaces the
+  /// attachment record.
+  pub async fn embed_url(
+    &self,
+    chat_id: &str,
+    url: &str,
+    metadata: Option<HashMap<String, Value>>,
+  ) -> Result<(), PluginError> {
+    let page = fetch_web_page(url).await?;
+    if page.text.is_empty() {
+      return Err(PluginError::FetchFailed {
+        url: url.to_string(),
+        reason: "page has no readable text".to_string(),
+      });
+    }
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.insert("chat_id".to_string(), json!(chat_id));
+    metadata.insert(SOURCE_URL_KEY.to_string(), json!(page.url));
+    metadata.insert(SOURCE_ID_KEY.to_string(), json!(url));
+    metadata
+      .entry(FILE_NAME_KEY.to_string())
+      .or_insert_with(|| json!(page.title.as_deref().unwrap_or(url)));
+    self.embed_text(&page.text, metadata).await
+  }
+}
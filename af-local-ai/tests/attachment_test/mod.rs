@@ -0,0 +1,285 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::{ChunkFailure, EmbedReport};
+use af_local_ai::attachment::{read_pending_cleanup, CloseChatReport, EPHEMERAL_KEY};
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[tokio::test]
+async fn fake_chat_attachments_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  let dir = tempfile::tempdir().unwrap();
+  let bananas = dir.path().join("bananas.txt");
+  let apples = dir.path().join("apples.txt");
+  std::fs::write(&bananas, "Bananas are yellow.").unwrap();
+  std::fs::write(&apples, "Apples are red.").unwrap();
+
+  harness
+    .ollama_plugin
+    .embed_file("chat", bananas.clone(), None)
+    .await
+    .unwrap();
+  harness
+    .ollama_plugin
+    .embed_file_with_source("chat", apples, "apples", None)
+    .await
+    .unwrap();
+  let attachments = harness.ollama_plugin.list_chat_attachments("chat").await;
+  let names = attachments
+    .iter()
+    .map(|attachment| attachment.path_or_name.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(names, vec!["bananas.txt", "apples.txt"]);
+  assert_eq!(attachments[1].source_id, "apples");
+
+  harness
+    .ollama_plugin
+    .remove_chat_attachment("chat", "apples")
+    .await
+    .unwrap();
+  let attachments = harness.ollama_plugin.list_chat_attachments("chat").await;
+  assert_eq!(attachments.len(), 1);
+  assert_eq!(attachments[0].source_id, bananas.to_str().unwrap());
+
+  harness
+    .ollama_plugin
+    .close_chat("chat", true)
+    .await
+    .unwrap();
+  assert!(harness
+    .ollama_plugin
+    .list_chat_attachments("chat")
+    .await
+    .is_empty());
+
+  let deletes = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "delete_documents")
+    .map(|request| request["params"]["filter"].clone())
+    .collect::<Vec<_>>();
+  assert_eq!(
+    deletes,
+    vec![
+      json!({ "chat_id": "chat", "source_id": "apples" }),
+      json!({ "chat_id": "chat", "source_id": bananas.to_str().unwrap() }),
+    ]
+  );
+}
+
+#[tokio::test]
+async fn fake_ephemeral_attachments_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new().with_vector_store()).await;
+  let mut persistent = HashMap::new();
+  persistent.insert("chat_id".to_string(), json!("chat"));
+  persistent.insert("source_id".to_string(), json!("guide"));
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are yellow", persistent)
+    .await
+    .unwrap();
+  let mut ephemeral = HashMap::new();
+  ephemeral.insert("chat_id".to_string(), json!("chat"));
+  ephemeral.insert(EPHEMERAL_KEY.to_string(), json!(true));
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are green", ephemeral)
+    .await
+    .unwrap();
+  let attachments = harness.ollama_plugin.list_chat_attachments("chat").await;
+  assert_eq!(
+    attachments
+      .iter()
+      .map(|attachment| attachment.ephemeral)
+      .collect::<Vec<_>>(),
+    vec![false, true]
+  );
+
+  let report = harness
+    .ollama_plugin
+    .close_chat_with_report("chat", false)
+    .await
+    .unwrap();
+  assert_eq!(
+    report,
+    CloseChatReport {
+      purged: 1,
+      pending: 0
+    }
+  );
+  let attachments = harness.ollama_plugin.list_chat_attachments("chat").await;
+  assert_eq!(attachments.len(), 1);
+  assert_eq!(attachments[0].source_id, "guide");
+  let found = harness
+    .ollama_plugin
+    .similarity_search("bananas", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(found, vec!["Bananas are yellow".to_string()]);
+
+  // Texts embedded without a chat can't be purged with one.
+  let mut metadata = HashMap::new();
+  metadata.insert(EPHEMERAL_KEY.to_string(), json!(true));
+  assert!(harness
+    .ollama_plugin
+    .embed_text("Bananas are brown", metadata)
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn fake_pending_ephemeral_cleanup_test() {
+  let delete_failed = json!({ "error": { "code": 1, "message": "vector store closed" } });
+  let scenario = FakeScenario::new().with_replies("delete_documents", vec![delete_failed]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let persist_dir = tempfile::tempdir().unwrap();
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&persist_dir.path().to_path_buf())
+    .unwrap();
+  harness
+    .ollama_plugin
+    .init_plugin(config.clone())
+    .await
+    .unwrap();
+
+  let mut metadata = HashMap::new();
+  metadata.insert("chat_id".to_string(), json!("chat"));
+  metadata.insert("source_id".to_string(), json!("notes"));
+  metadata.insert(EPHEMERAL_KEY.to_string(), json!(true));
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are green", metadata)
+    .await
+    .unwrap();
+  let report = harness
+    .ollama_plugin
+    .close_chat_with_report("chat", false)
+    .await
+    .unwrap();
+  assert_eq!(
+    report,
+    CloseChatReport {
+      purged: 0,
+      pending: 1
+    }
+  );
+  assert!(harness
+    .ollama_plugin
+    .list_chat_attachments("chat")
+    .await
+    .is_empty());
+  let filter = json!({ "chat_id": "chat", "source_id": "notes" });
+  let pending = read_pending_cleanup(persist_dir.path()).unwrap();
+  assert_eq!(pending.len(), 1);
+  assert_eq!(json!(pending[0]), filter);
+
+  // The delete is retried once a plugin that can delete is initialized.
+  harness.restart_with(FakeScenario::new()).await;
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  assert!(read_pending_cleanup(persist_dir.path()).unwrap().is_empty());
+  let deletes = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "delete_documents")
+    .map(|request| request["params"]["filter"].clone())
+    .collect::<Vec<_>>();
+  assert_eq!(deletes, vec![filter.clone(), filter]);
+}
+
+/// A file of three chunks, the second one of malformed text.
+fn write_mixed_file(dir: &tempfile::TempDir) -> PathBuf {
+  let path = dir.path().join("scan.txt");
+  let mut content = b"Bananas are yellow.\n\n".to_vec();
+  content.extend_from_slice(&[0xFF, 0xFE, b'\n', b'\n']);
+  content.extend_from_slice(b"Apples are red.");
+  std::fs::write(&path, content).unwrap();
+  path
+}
+
+#[tokio::test]
+async fn fake_embed_file_partial_test() {
+  let system_info = json!({ "result": { "data": { "version": "fake", "protocol_version": 6 } } });
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies("system_info", vec![system_info]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let dir = tempfile::tempdir().unwrap();
+  let path = write_mixed_file(&dir);
+
+  // Without a report, one failed chunk fails the whole file.
+  assert!(harness
+    .ollama_plugin
+    .embed_file("chat", path.clone(), None)
+    .await
+    .is_err());
+
+  let report = harness
+    .ollama_plugin
+    .embed_file_partial("chat", path, None)
+    .await
+    .unwrap();
+  assert_eq!(
+    report,
+    EmbedReport {
+      chunks_indexed: 2,
+      chunks_failed: 1,
+      failures: vec![ChunkFailure {
+        index: 1,
+        reason: "malformed text".to_string(),
+      }],
+    }
+  );
+  let found = harness
+    .ollama_plugin
+    .similarity_search("bananas and apples", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(found, vec!["Bananas are yellow.", "Apples are red."]);
+  let attachments = harness.ollama_plugin.list_chat_attachments("chat").await;
+  assert_eq!(attachments.len(), 1);
+
+  let missing = harness
+    .ollama_plugin
+    .embed_file_partial("chat", dir.path().join("missing.txt"), None)
+    .await;
+  assert!(matches!(missing, Err(PluginError::Io(_))));
+}
+
+#[tokio::test]
+async fn fake_embed_file_partial_fallback_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new().with_vector_store()).await;
+  let dir = tempfile::tempdir().unwrap();
+  let path = write_mixed_file(&dir);
+
+  // The plugin indexes files whole or not at all.
+  let report = harness
+    .ollama_plugin
+    .embed_file_partial("chat", path, None)
+    .await
+    .unwrap();
+  assert_eq!(report.chunks_indexed, 0);
+  assert_eq!(report.chunks_failed, 1);
+  assert!(report.failures[0].reason.contains("malformed text"));
+  assert!(harness
+    .ollama_plugin
+    .list_chat_attachments("chat")
+    .await
+    .is_empty());
+
+  let path = dir.path().join("notes.txt");
+  std::fs::write(&path, "Bananas are yellow.\n\nApples are red.").unwrap();
+  let report = harness
+    .ollama_plugin
+    .embed_file_partial("chat", path, None)
+    .await
+    .unwrap();
+  assert_eq!(report, EmbedReport::whole_file());
+  let request = harness
+    .handled_requests()
+    .into_iter()
+    .rfind(|request| request["method"] == "embed_file")
+    .unwrap();
+  assert!(request["params"].get("report").is_none());
+}
@@ -0,0 +1,39 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::auth::OllamaAuth;
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_auth_init_params_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  assert!(harness.initialize_params()[0].get("auth").is_none());
+
+  let config = harness
+    .config()
+    .with_auth(OllamaAuth::Bearer("first-token".to_string()));
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  let params = harness.initialize_params();
+  assert_eq!(
+    params[1]["auth"],
+    json!({ "type": "bearer", "token": "first-token" })
+  );
+
+  // The url and its credentials are switched together.
+  harness
+    .ollama_plugin
+    .update_server_url(
+      "https://ollama.example.com".to_string(),
+      Some(OllamaAuth::Basic {
+        user: "appflowy".to_string(),
+        pass: "second-pass".to_string(),
+      }),
+    )
+    .await
+    .unwrap();
+  let params = harness.initialize_params();
+  assert_eq!(params.len(), 3);
+  assert_eq!(params[2]["server_url"], "https://ollama.example.com");
+  assert_eq!(
+    params[2]["auth"],
+    json!({ "type": "basic", "user": "appflowy", "pass": "second-pass" })
+  );
+}
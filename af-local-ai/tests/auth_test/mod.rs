@@ -0,0 +1,69 @@
+use af_local_ai::auth::OllamaAuth;
+use af_local_ai::ollama_plugin::OllamaPluginConfig;
+use af_plugin::util::redact_secrets;
+use serde_json::json;
+use std::path::PathBuf;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn auth_is_redacted_in_debug_output_test() {
+  let auths = [
+    OllamaAuth::Bearer("secret-token".to_string()),
+    OllamaAuth::Basic {
+      user: "appflowy".to_string(),
+      pass: "secret-token".to_string(),
+    },
+    OllamaAuth::CustomHeader {
+      name: "X-Api-Key".to_string(),
+      value: "secret-token".to_string(),
+    },
+  ];
+  for auth in auths {
+    let config = OllamaPluginConfig::new(
+      PathBuf::from("ollama_plugin"),
+      String::new(),
+      "chat-model".to_string(),
+      "embedding-model".to_string(),
+      Some("https://ollama.example.com".to_string()),
+    )
+    .unwrap()
+    .with_auth(auth.clone());
+
+    let output = format!("{:?}", config);
+    assert!(!output.contains("secret-token"), "{}", output);
+    assert!(output.contains("***"), "{}", output);
+
+    // The params sent to the plugin keep the secret, the logged ones don't.
+    let params = json!({ "auth": auth.to_params() });
+    assert!(params.to_string().contains("secret-token"));
+    let logged = format!("{:?}", redact_secrets(&params));
+    assert!(!logged.contains("secret-token"), "{}", logged);
+    assert!(logged.contains("***"), "{}", logged);
+  }
+}
+
+#[test]
+fn auth_params_test() {
+  assert_eq!(
+    OllamaAuth::Bearer("token".to_string()).to_params(),
+    json!({ "type": "bearer", "token": "token" })
+  );
+  assert_eq!(
+    OllamaAuth::Basic {
+      user: "user".to_string(),
+      pass: "pass".to_string(),
+    }
+    .to_params(),
+    json!({ "type": "basic", "user": "user", "pass": "pass" })
+  );
+  assert_eq!(
+    OllamaAuth::CustomHeader {
+      name: "X-Api-Key".to_string(),
+      value: "key".to_string(),
+    }
+    .to_params(),
+    json!({ "type": "custom_header", "name": "X-Api-Key", "value": "key" })
+  );
+}
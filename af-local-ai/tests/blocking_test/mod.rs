@@ -0,0 +1,107 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::CompleteTextType;
+use af_local_ai::blocking::{BlockingLocalAI, CompletedText};
+use af_plugin::error::PluginError;
+use af_plugin::manager::PluginManager;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn fake_blocking_facade_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "answer",
+      vec![
+        json!({ "result": { "data": "Banana is a fruit" } }),
+        json!({ "result": { "data": "Too late" }, "delay_ms": 2000 }),
+      ],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![json!({ "stream": [
+        json!({ "1": "He and I" }).to_string(),
+        json!({ "1": " went", "4": "Fixed the subject." }).to_string(),
+      ] })],
+    )
+    .with_replies(
+      "similarity_search",
+      vec![json!({ "result": { "data": ["Bananas grow in the tropics."] } })],
+    );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let config = harness.config();
+  let plugin_manager = harness.plugin_manager.clone();
+
+  // A plain thread, without any Tokio runtime.
+  std::thread::spawn(move || {
+    let timeout = Duration::from_secs(5);
+    let local_ai = BlockingLocalAI::new(plugin_manager, 2).unwrap();
+    local_ai.init_plugin(config).unwrap();
+
+    let answer = local_ai
+      .ask_question("chat", "what is banana?", timeout)
+      .unwrap();
+    assert_eq!(answer, "Banana is a fruit");
+
+    let completed = local_ai
+      .complete_text_collect(
+        "Me and him went",
+        CompleteTextType::SpellingAndGrammar as u8,
+        None,
+        None,
+        timeout,
+      )
+      .unwrap();
+    assert_eq!(
+      completed,
+      CompletedText {
+        answer: "He and I went".to_string(),
+        comment: Some("Fixed the subject.".to_string()),
+      }
+    );
+
+    let embeddings = local_ai.generate_embedding("banana", timeout).unwrap();
+    assert_eq!(embeddings, vec![vec![0.1, 0.2, 0.3]]);
+
+    let chunks = local_ai
+      .similarity_search("banana", HashMap::new(), timeout)
+      .unwrap();
+    assert_eq!(chunks, vec!["Bananas grow in the tropics."]);
+
+    let err = local_ai
+      .ask_question("chat", "what is apple?", Duration::from_millis(100))
+      .unwrap_err();
+    assert!(matches!(err, PluginError::RequestTimeout(_)), "{:?}", err);
+
+    local_ai.shutdown().unwrap();
+  })
+  .join()
+  .unwrap();
+
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap().to_string())
+    .collect::<Vec<_>>();
+  for method in [
+    "answer",
+    "complete_text_v2",
+    "gen_embeddings",
+    "similarity_search",
+  ] {
+    assert!(methods.iter().any(|m| m == method), "{:?}", methods);
+  }
+}
+
+#[tokio::test]
+async fn blocking_facade_in_async_context_test() {
+  let err = BlockingLocalAI::new(Arc::new(PluginManager::new()), 1)
+    .err()
+    .unwrap();
+  assert!(
+    matches!(err, PluginError::BlockingInAsyncContext),
+    "{:?}",
+    err
+  );
+}
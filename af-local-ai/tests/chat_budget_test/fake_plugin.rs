@@ -0,0 +1,43 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn fake_chat_budget_test() {
+  let scenario = FakeScenario::new()
+    .with_replies("truncate_chat", vec![json!({ "result": {} })])
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["Pears", " are green."])],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let crossed = Arc::new(AtomicUsize::new(0));
+  let calls = crossed.clone();
+  harness
+    .ollama_plugin
+    .on_budget_exceeded(0.001, move |chat_id, _| {
+      assert_eq!(chat_id, "fruits");
+      calls.fetch_add(1, Ordering::SeqCst);
+    });
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question("fruits", "What color are pears?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Pears are green.");
+  // 21 characters asked and 16 answered.
+  let budget = harness.ollama_plugin.chat_budget("fruits");
+  assert_eq!(budget.used_estimate, 10);
+  assert_eq!(budget.capacity_estimate, 2048);
+  assert_eq!(crossed.load(Ordering::SeqCst), 1);
+
+  harness
+    .ollama_plugin
+    .truncate_chat_history("fruits", 0)
+    .await
+    .unwrap();
+  assert_eq!(harness.ollama_plugin.chat_budget("fruits").used_estimate, 0);
+}
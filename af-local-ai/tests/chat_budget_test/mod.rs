@@ -0,0 +1,108 @@
+use af_local_ai::chat_budget::{default_context_window, ChatBudget, ChatBudgetTracker};
+use std::sync::Arc;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+type Fired = Arc<parking_lot::Mutex<Vec<(String, ChatBudget)>>>;
+
+/// A tracker of 100 tokens, 400 characters, recording the budgets passed to a callback at 0.8.
+fn tracker() -> (ChatBudgetTracker, Fired) {
+  let tracker = ChatBudgetTracker::new(100);
+  let fired = Arc::new(parking_lot::Mutex::new(Vec::new()));
+  let calls = fired.clone();
+  tracker.on_budget_exceeded(
+    0.8,
+    Arc::new(move |chat_id: &str, budget| calls.lock().push((chat_id.to_string(), budget))),
+  );
+  (tracker, fired)
+}
+
+#[test]
+fn chat_budget_estimate_test() {
+  let tracker = ChatBudgetTracker::new(100);
+  assert_eq!(
+    tracker.budget("c1"),
+    ChatBudget {
+      used_estimate: 0,
+      capacity_estimate: 100,
+      ratio: 0.0,
+    }
+  );
+
+  tracker.turn_done("c1", 40, 80);
+  tracker.turn_done("c1", 1, 0);
+  let budget = tracker.budget("c1");
+  // 121 characters round up to 31 tokens.
+  assert_eq!(budget.used_estimate, 31);
+  assert_eq!(budget.ratio, 0.31);
+  assert_eq!(tracker.budget("c2").used_estimate, 0);
+
+  tracker.set_capacity(200);
+  assert_eq!(tracker.budget("c1").ratio, 0.155);
+}
+
+#[test]
+fn chat_budget_fires_once_per_crossing_test() {
+  let (tracker, fired) = tracker();
+
+  // 300 characters: 75 tokens.
+  for _ in 0..3 {
+    tracker.turn_done("c1", 20, 80);
+  }
+  assert!(fired.lock().is_empty());
+
+  // 340 characters: 85 tokens, past 0.8.
+  tracker.turn_done("c1", 10, 30);
+  assert_eq!(fired.lock().len(), 1);
+  let (chat_id, budget) = fired.lock()[0].clone();
+  assert_eq!(chat_id, "c1");
+  assert_eq!(budget.used_estimate, 85);
+
+  // Still past the threshold, and past the whole window.
+  tracker.turn_done("c1", 20, 80);
+  tracker.turn_done("c1", 20, 80);
+  assert_eq!(fired.lock().len(), 1);
+  assert!(tracker.budget("c1").ratio > 1.0);
+
+  // Other chats have their own crossing.
+  tracker.turn_done("c2", 100, 300);
+  assert_eq!(fired.lock().len(), 2);
+  assert_eq!(fired.lock()[1].0, "c2");
+}
+
+#[test]
+fn chat_budget_history_changes_rearm_test() {
+  let (tracker, fired) = tracker();
+  for _ in 0..4 {
+    tracker.turn_done("c1", 20, 80);
+  }
+  assert_eq!(fired.lock().len(), 1);
+
+  // Keeping two turns drops to 50 tokens, so the next crossing fires again.
+  tracker.truncated("c1", 2);
+  assert_eq!(tracker.budget("c1").used_estimate, 50);
+  tracker.turn_done("c1", 20, 80);
+  assert!(tracker.budget("c1").ratio < 0.8);
+  assert_eq!(fired.lock().len(), 1);
+  tracker.turn_done("c1", 20, 80);
+  assert_eq!(fired.lock().len(), 2);
+
+  // The three first turns summarized in 20 characters: 20 + 100 characters left.
+  tracker.summarized("c1", 3, 20);
+  assert_eq!(tracker.budget("c1").used_estimate, 30);
+  tracker.turn_done("c1", 20, 80);
+  assert_eq!(fired.lock().len(), 2);
+  tracker.turn_done("c1", 20, 80);
+  assert_eq!(fired.lock().len(), 3);
+
+  tracker.forget("c1");
+  assert_eq!(tracker.budget("c1").used_estimate, 0);
+}
+
+#[test]
+fn default_context_window_test() {
+  assert_eq!(default_context_window("llama3.1:8b"), 131_072);
+  assert_eq!(default_context_window("llama3"), 8_192);
+  assert_eq!(default_context_window("unknown-model:latest"), 2_048);
+}
@@ -0,0 +1,360 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::ai_ops::{ChatSettings, ChatSummary, MessageRole, RagOptions};
+use af_local_ai::chat_list::ChatSyncReport;
+use af_plugin::error::PluginError;
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_edit_and_regenerate_test() {
+  let methods = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+      .filter(|method| method != "system_info" && method != "set_log_level")
+      .collect::<Vec<_>>()
+  };
+  let scenario = FakeScenario::new()
+    .with_replies("truncate_chat", vec![json!({ "result": {} })])
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["Pears are green."])],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let stream = harness
+    .ollama_plugin
+    .edit_and_regenerate("fruits", 1, "What color are pears?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Pears are green.");
+  let requests = harness.handled_requests();
+  let truncate = requests
+    .iter()
+    .position(|request| request["method"] == "truncate_chat")
+    .unwrap();
+  let question = requests
+    .iter()
+    .position(|request| request["method"] == "stream_answer_v2")
+    .unwrap();
+  assert!(truncate < question);
+  assert_eq!(
+    requests[truncate]["params"],
+    json!({ "chat_id": "fruits", "keep_turns": 1 })
+  );
+  assert_eq!(
+    requests[question]["params"]["data"]["content"],
+    "What color are pears?"
+  );
+
+  // Without `truncate_chat`, the chat is rebuilt from its history.
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "get_chat_history",
+      vec![json!({ "result": { "data": [
+        { "role": "human", "content": "What color are bananas?" },
+        { "role": "ai", "content": "Yellow." },
+        { "role": "human", "content": "What color are apples?" },
+        { "role": "ai", "content": "Red." },
+        { "role": "human", "content": "And pears?" },
+        { "role": "ai", "content": "Green." },
+      ] } })],
+    )
+    .with_replies("answer", vec![json!({ "result": { "data": "Yellow." } })])
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["Cherries are red."])],
+    );
+  harness.restart_with(scenario).await;
+  let before = methods(&harness).len();
+  let stream = harness
+    .ollama_plugin
+    .edit_and_regenerate("fruits", 1, "What color are cherries?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Cherries are red.");
+  assert_eq!(
+    methods(&harness)[before..],
+    [
+      "truncate_chat",
+      "get_chat_history",
+      "close_chat",
+      "create_chat",
+      "answer",
+      "stream_answer_v2",
+    ]
+  );
+  let replayed = harness
+    .handled_requests()
+    .into_iter()
+    .rev()
+    .find(|request| request["method"] == "answer")
+    .unwrap();
+  assert_eq!(replayed["params"]["content"], "What color are bananas?");
+
+  // Nor without a history.
+  harness.restart_with(FakeScenario::new()).await;
+  let err = harness
+    .ollama_plugin
+    .truncate_chat_history("fruits", 1)
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(&err, PluginError::UnsupportedMethod { method } if method == "truncate_chat"),
+    "{:?}",
+    err
+  );
+}
+
+#[tokio::test]
+async fn fake_create_chat_twice_test() {
+  let methods = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+      .filter(|method| method != "system_info" && method != "set_log_level")
+      .collect::<Vec<_>>()
+  };
+  let scenario = FakeScenario::new().with_replies(
+    "create_chat",
+    vec![
+      json!({ "result": {} }),
+      json!({ "error": { "code": -32603, "message": "Chat fruits already exists" } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  harness.ollama_plugin.create_chat("fruits").await.unwrap();
+  harness.ollama_plugin.create_chat("fruits").await.unwrap();
+  assert_eq!(methods(&harness), ["create_chat", "create_chat"]);
+}
+
+#[tokio::test]
+async fn fake_chat_exists_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "chat_exists",
+    vec![
+      json!({ "result": { "data": false } }),
+      json!({ "result": { "data": true }, "when": { "chat_id": "fruits" } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  assert!(harness.ollama_plugin.chat_exists("fruits").await.unwrap());
+  assert!(!harness
+    .ollama_plugin
+    .chat_exists("vegetables")
+    .await
+    .unwrap());
+
+  // Plugins without the method only have the chats created since they started.
+  harness.restart_with(FakeScenario::new()).await;
+  assert!(!harness.ollama_plugin.chat_exists("fruits").await.unwrap());
+  harness.ollama_plugin.create_chat("fruits").await.unwrap();
+  assert!(harness.ollama_plugin.chat_exists("fruits").await.unwrap());
+  harness
+    .ollama_plugin
+    .close_chat("fruits", false)
+    .await
+    .unwrap();
+  assert!(!harness.ollama_plugin.chat_exists("fruits").await.unwrap());
+
+  harness.ollama_plugin.create_chat("fruits").await.unwrap();
+  harness.start().await;
+  assert!(!harness.ollama_plugin.chat_exists("fruits").await.unwrap());
+}
+
+#[tokio::test]
+async fn fake_auto_create_chat_test() {
+  let methods = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+      .filter(|method| method != "system_info" && method != "set_log_level")
+      .collect::<Vec<_>>()
+  };
+  let scenario = FakeScenario::new()
+    .with_replies("chat_exists", vec![json!({ "result": { "data": false } })])
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Yellow."])])
+    .with_replies("answer", vec![json!({ "result": { "data": "Red." } })]);
+  let harness = TestPluginHarness::unstarted(scenario);
+  harness
+    .ollama_plugin
+    .init_plugin(harness.config().with_auto_create_chat(true))
+    .await
+    .unwrap();
+  harness
+    .ollama_plugin
+    .update_chat_settings(
+      "fruits",
+      ChatSettings {
+        rag: RagOptions::new(3),
+        ..Default::default()
+      },
+    )
+    .await;
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question("fruits", "What color are bananas?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Yellow.");
+  let answer = harness
+    .ollama_plugin
+    .ask_question("fruits", "What color are cherries?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "Red.");
+  assert_eq!(
+    methods(&harness),
+    [
+      "chat_exists",
+      "create_chat",
+      "stream_answer_v2",
+      "chat_exists",
+      "create_chat",
+      "answer",
+    ]
+  );
+  let created = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "create_chat")
+    .unwrap();
+  assert_eq!(created["params"]["chat_id"], "fruits");
+  assert_eq!(created["params"]["top_k"], 3);
+}
+
+#[tokio::test]
+async fn fake_append_chat_message_test() {
+  let scenario = FakeScenario::new()
+    .with_replies("append_message", vec![json!({ "result": {} })])
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["Roadmap", " it is"])],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  harness
+    .ollama_plugin
+    .append_chat_message(
+      "chat",
+      MessageRole::Context,
+      "The user renamed the page to Roadmap",
+    )
+    .await
+    .unwrap();
+  harness
+    .ollama_plugin
+    .append_chat_message("chat", MessageRole::Assistant, "Imported answer")
+    .await
+    .unwrap();
+  let empty = harness
+    .ollama_plugin
+    .append_chat_message("chat", MessageRole::User, "  \n")
+    .await;
+  assert!(matches!(empty, Err(PluginError::EmptyMessage)));
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what is the page called?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Roadmap it is");
+
+  let requests = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] != "system_info")
+    .collect::<Vec<_>>();
+  let methods = requests
+    .iter()
+    .map(|request| request["method"].as_str().unwrap())
+    .collect::<Vec<_>>();
+  assert_eq!(
+    methods,
+    vec!["append_message", "append_message", "stream_answer_v2"]
+  );
+  assert_eq!(requests[0]["params"]["chat_id"], "chat");
+  assert_eq!(requests[0]["params"]["role"], "context");
+  assert_eq!(
+    requests[0]["params"]["content"],
+    "The user renamed the page to Roadmap"
+  );
+  assert_eq!(requests[1]["params"]["role"], "assistant");
+}
+
+#[tokio::test]
+async fn fake_append_chat_message_unsupported_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  let result = harness
+    .ollama_plugin
+    .append_chat_message("chat", MessageRole::System, "Answer in French")
+    .await;
+  assert!(
+    matches!(result, Err(PluginError::UnsupportedMethod { ref method }) if method == "append_message")
+  );
+}
+
+#[tokio::test]
+async fn fake_list_chats_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "list_chats",
+    vec![
+      json!({ "result": { "data": [
+        {
+          "chat_id": "fruits",
+          "created_at": "2026-10-01T08:00:00Z",
+          "last_activity": "2026-10-02T09:30:00Z",
+          "message_count": 4,
+        },
+        { "chat_id": "ghost" },
+      ] } }),
+      // A plugin that only knows the ids of its chats.
+      json!({ "result": { "data": ["fruits", "ghost"] } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let chats = harness.ollama_plugin.list_chats().await.unwrap();
+  assert_eq!(
+    chats,
+    vec![
+      ChatSummary {
+        chat_id: "fruits".to_string(),
+        created_at: Some("2026-10-01T08:00:00Z".to_string()),
+        last_activity: Some("2026-10-02T09:30:00Z".to_string()),
+        message_count: Some(4),
+      },
+      ChatSummary {
+        chat_id: "ghost".to_string(),
+        ..Default::default()
+      },
+    ]
+  );
+
+  let known = vec!["fruits".to_string(), "vegetables".to_string()];
+  let report = harness.ollama_plugin.sync_chats(&known).await.unwrap();
+  assert_eq!(
+    report,
+    ChatSyncReport {
+      missing_on_plugin: vec!["vegetables".to_string()],
+      unknown_to_host: vec!["ghost".to_string()],
+    }
+  );
+  assert!(!report.is_in_sync());
+}
+
+#[tokio::test]
+async fn fake_list_chats_unsupported_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  let err = harness.ollama_plugin.list_chats().await.unwrap_err();
+  assert!(matches!(err, PluginError::UnsupportedMethod { .. }));
+  let err = harness
+    .ollama_plugin
+    .sync_chats(&["fruits".to_string()])
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::UnsupportedMethod { .. }));
+}
@@ -1,14 +1,25 @@
 use crate::util::{collect_completion_stream, collect_json_stream, get_asset_path, LocalAITest};
 
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
 use af_local_ai::ai_ops::{CompleteTextType, LocalAITranslateItem, LocalAITranslateRowData};
+use af_local_ai::citation::{parse_citations, Citation, SourcedFrame};
+use af_local_ai::ollama_plugin::OllamaAIPlugin;
+use af_local_ai::summary::SummaryLength;
+use af_plugin::error::PluginError;
+use af_plugin::manager::PluginManager;
 
 use serde_json::json;
 
 use tokio_stream::StreamExt;
 
 #[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
 async fn load_chat_model_test() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
@@ -27,46 +38,47 @@ async fn load_chat_model_test() {
 }
 
 #[tokio::test]
-async fn ci_chat_stream_test() {
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_complete_text_followup_test() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
 
-  let chat_plugin = test
+  let original = "AppFlowy is an open source workspace. It combines notes, wikis, databases and \
+    task boards. Teams use it to plan projects and share knowledge. It can run AI models on your \
+    own machine, so your data never leaves it.";
+  let resp = test
     .ollama_plugin
-    .get_ai_plugin()
+    .complete_text_v2(original, CompleteTextType::MakeLonger as u8, None, None)
     .await
-    .unwrap()
-    .upgrade()
     .unwrap();
-  let mut state_rx = chat_plugin.subscribe_running_state();
-  tokio::spawn(async move {
-    while let Some(state) = state_rx.next().await {
-      eprintln!("chat state: {:?}", state);
-    }
-  });
+  let (first, _) = collect_completion_stream(resp).await;
+  eprintln!("first pass: {:?}", first);
 
-  let chat_id = uuid::Uuid::new_v4().to_string();
   let resp = test
-    .stream_chat_message(&chat_id, "what is banana?", None)
-    .await;
-  let answer = collect_json_stream(resp).await;
-  println!("stream response: {:?}", answer);
-
-  let expected = r#"banana is a fruit that belongs to the genus _______, which also includes other fruits such as apple and pear. It has several varieties with different shapes, colors, and flavors depending on where it grows. Bananas are typically green or yellow in color and have smooth skin that peels off easily when ripe. They are sweet and juicy, often eaten raw or roasted, and can also be used for cooking and baking. In some cultures, banana is considered a symbol of good luck, fertility, and prosperity. Bananas originated in Southeast Asia, where they were cultivated by early humans thousands of years ago. They are now grown around the world as a major crop, with significant production in many countries including the United States, Brazil, India, and China#"#;
-  let score = test.calculate_similarity(&answer, expected).await;
-  assert!(score > 0.7, "score: {}", score);
-
-  let questions = test
     .ollama_plugin
-    .get_related_question(&chat_id)
+    .complete_text_followup(&first, "Make it one sentence", original, None)
     .await
     .unwrap();
-  assert_eq!(questions.len(), 3);
-  println!("related questions: {:?}", questions)
+  let (followup, _) = collect_completion_stream(resp).await;
+  eprintln!("followup: {:?}", followup);
+  assert!(!followup.is_empty());
+  assert!(
+    followup.len() * 2 < first.len(),
+    "first: {}, followup: {}",
+    first.len(),
+    followup.len()
+  );
 }
 
 #[tokio::test]
-async fn ci_completion_text_v2_test() {
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_completion_text_v2_unicode_test() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
 
@@ -87,8 +99,8 @@ async fn ci_completion_text_v2_test() {
   let resp = test
     .ollama_plugin
     .complete_text_v2(
-      "Me and him was going to the store, but we didn’t had enough money",
-      CompleteTextType::SpellingAndGrammar as u8,
+      "He starts work everyday at 8 a.m. 然后他开始工作了一整天， 没有♨️",
+      CompleteTextType::ImproveWriting as u8,
       None,
       Some(json!({
         "object_id": "123",
@@ -100,54 +112,206 @@ async fn ci_completion_text_v2_test() {
   let (answer, comment) = collect_completion_stream(resp).await;
   eprintln!("answer: {:?}", answer);
   eprintln!("comment: {:?}", comment);
+}
 
-  let expected = r#"He and I were going to the store, but we didn’t have enough money"#;
-  let score = test.calculate_similarity(&answer, expected).await;
-  assert!(score > 0.7, "score: {}", score);
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_chat_with_multiple_files_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+  let chat_id = uuid::Uuid::new_v4().to_string();
 
-  let expected = r#"The subject "Me and him" was corrected to "He and I" because "I" is the correct subject pronoun when referring to oneself in the subject position. "Was" was changed to "were" to agree with the plural subject. "Didn’t had" was corrected to "didn’t have" as "didn't" requires the base form of"#;
-  let score = test.calculate_similarity(&comment, expected).await;
-  assert!(score > 0.7, "score: {}", score);
+  let dir = tempfile::tempdir().unwrap();
+  let orchard = dir.path().join("orchard.txt");
+  std::fs::write(
+    &orchard,
+    "The Willow Creek orchard grows twelve varieties of apples. Its harvest festival is held \
+    every October, and the orchard's oldest tree was planted in 1887 by Margaret Hale.",
+  )
+  .unwrap();
+  let rocket = dir.path().join("rocket.txt");
+  std::fs::write(
+    &rocket,
+    "The Aurora rocket uses a liquid methane engine. Its first test flight reached an altitude \
+    of 12 kilometers before landing on the recovery barge.",
+  )
+  .unwrap();
+
+  for (source_id, path) in [("orchard", orchard), ("rocket", rocket)] {
+    test
+      .ollama_plugin
+      .embed_file_with_source(&chat_id, path, source_id, None)
+      .await
+      .unwrap();
+  }
+
+  let mut stream = test
+    .ollama_plugin
+    .stream_question_with_sources(
+      &chat_id,
+      "Who planted the oldest tree of the Willow Creek orchard?",
+      None,
+      json!({}),
+    )
+    .await
+    .unwrap();
+
+  let mut answer = String::new();
+  let mut citations = vec![];
+  while let Some(frame) = stream.next().await {
+    match frame.unwrap() {
+      SourcedFrame::Answer(text) => answer.push_str(&text),
+      SourcedFrame::Citations(_) => {},
+      SourcedFrame::Done { citations: all } => citations = all,
+    }
+  }
+  eprintln!("answer: {}, citations: {:?}", answer, citations);
+
+  assert!(!citations.is_empty());
+  citations.sort_by(|a, b| b.score.total_cmp(&a.score));
+  assert_eq!(citations[0].source_id, "orchard");
+  assert_eq!(citations[0].file_name, "orchard.txt");
 }
 
 #[tokio::test]
-async fn ci_completion_text_v2_unicode_test() {
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_remove_chat_attachment_test() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
+  let chat_id = uuid::Uuid::new_v4().to_string();
 
-  let chat_plugin = test
+  let dir = tempfile::tempdir().unwrap();
+  let orchard = dir.path().join("orchard.txt");
+  std::fs::write(
+    &orchard,
+    "The Willow Creek orchard grows twelve varieties of apples.",
+  )
+  .unwrap();
+  let rocket = dir.path().join("rocket.txt");
+  std::fs::write(&rocket, "The Aurora rocket uses a liquid methane engine.").unwrap();
+  for (source_id, path) in [("orchard", orchard), ("rocket", rocket)] {
+    test
+      .ollama_plugin
+      .embed_file_with_source(&chat_id, path, source_id, None)
+      .await
+      .unwrap();
+  }
+
+  let attachments = test.ollama_plugin.list_chat_attachments(&chat_id).await;
+  let source_ids = attachments
+    .iter()
+    .map(|attachment| attachment.source_id.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(source_ids, vec!["orchard", "rocket"]);
+
+  test
     .ollama_plugin
-    .get_ai_plugin()
+    .remove_chat_attachment(&chat_id, "rocket")
     .await
-    .unwrap()
-    .upgrade()
     .unwrap();
-  let mut state_rx = chat_plugin.subscribe_running_state();
-  tokio::spawn(async move {
-    while let Some(state) = state_rx.next().await {
-      eprintln!("chat state: {:?}", state);
-    }
-  });
+  assert_eq!(
+    test
+      .ollama_plugin
+      .list_chat_attachments(&chat_id)
+      .await
+      .len(),
+    1
+  );
 
-  let resp = test
+  let mut filter = HashMap::new();
+  filter.insert("chat_id".to_string(), json!(chat_id));
+  let chunks = test
     .ollama_plugin
-    .complete_text_v2(
-      "He starts work everyday at 8 a.m. 然后他开始工作了一整天， 没有♨️",
-      CompleteTextType::ImproveWriting as u8,
-      None,
-      Some(json!({
-        "object_id": "123",
-      })),
-    )
+    .similarity_search("Which engine does the Aurora rocket use?", filter)
     .await
     .unwrap();
+  assert!(
+    chunks.iter().all(|chunk| !chunk.contains("Aurora")),
+    "{:?}",
+    chunks
+  );
+}
 
-  let (answer, comment) = collect_completion_stream(resp).await;
-  eprintln!("answer: {:?}", answer);
-  eprintln!("comment: {:?}", comment);
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_summarize_chat_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+  let chat_id = uuid::Uuid::new_v4().to_string();
+  for message in [
+    "How often should I water a cactus?",
+    "What is the capital of Japan?",
+    "How do I make sourdough bread?",
+  ] {
+    test.send_chat_message(&chat_id, message).await;
+  }
+
+  let summary = test
+    .ollama_plugin
+    .summarize_chat(&chat_id, SummaryLength::Bullets)
+    .await
+    .unwrap();
+  println!("summary: {}", summary);
+  assert!(summary.trim().lines().count() > 1, "{}", summary);
+
+  let expected = "Watering a cactus, the capital of Japan is Tokyo, making sourdough bread";
+  let score = test.calculate_similarity(&summary, expected).await;
+  assert!(score > 0.6, "score: {}", score);
+
+  let empty_chat = uuid::Uuid::new_v4().to_string();
+  let err = test
+    .ollama_plugin
+    .summarize_chat(&empty_chat, SummaryLength::Brief)
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::EmptyChat { .. }), "{:?}", err);
+}
+
+#[test]
+fn parse_citations_test() {
+  let metadata = json!([
+    {
+      "page_content": "The orchard's oldest tree was planted in 1887.",
+      "score": 0.82,
+      "metadata": { "source_id": "orchard", "file_name": "orchard.txt", "chat_id": "1" }
+    },
+    {
+      "page_content": "A chunk that was not embedded by embed_file.",
+      "metadata": { "chat_id": "1" }
+    }
+  ]);
+  let citations = parse_citations(&metadata);
+  assert_eq!(
+    citations,
+    vec![Citation {
+      source_id: "orchard".to_string(),
+      file_name: "orchard.txt".to_string(),
+      chunk_text: "The orchard's oldest tree was planted in 1887.".to_string(),
+      score: 0.82,
+    }]
+  );
+
+  let metadata = json!({ "sources": [{ "source_id": "rocket", "content": "Aurora" }] });
+  let citations = parse_citations(&metadata);
+  assert_eq!(citations.len(), 1);
+  assert_eq!(citations[0].source_id, "rocket");
+  assert_eq!(citations[0].chunk_text, "Aurora");
 }
 
 #[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
 async fn ci_chat_with_pdf() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
@@ -179,6 +343,87 @@ async fn ci_chat_with_pdf() {
 }
 
 #[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_ask_question_with_sources_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+  let chat_id = uuid::Uuid::new_v4().to_string();
+  let pdf = get_asset_path("AppFlowy_Values.pdf");
+  test
+    .ollama_plugin
+    .embed_file(&chat_id, pdf, None)
+    .await
+    .unwrap();
+
+  let result = test
+    .ollama_plugin
+    .ask_question_with_sources(&chat_id, "what is AppFlowy Values?")
+    .await
+    .unwrap();
+  println!("answer with sources: {:?}", result);
+  let expected = "Mission Driven, Collaboration, Honesty, Aim High and Iterate, Transparency";
+  let score = test.calculate_similarity(&result.answer, expected).await;
+  assert!(score > 0.6, "score: {}", score);
+  assert!(result
+    .sources
+    .iter()
+    .any(|source| source.chunk_text.contains("Mission")));
+}
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_chat_with_pdf_top_k_test() {
+  use af_local_ai::ai_ops::{ChatSettings, RagOptions};
+
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+  let expected = "Mission Driven, Collaboration, Honesty, Aim High and Iterate, Transparency";
+
+  let mut scores = vec![];
+  for top_k in [2, 8] {
+    let chat_id = uuid::Uuid::new_v4().to_string();
+    test
+      .ollama_plugin
+      .create_chat_with_settings(
+        &chat_id,
+        ChatSettings {
+          rag: RagOptions::new(top_k),
+          ..Default::default()
+        },
+      )
+      .await
+      .unwrap();
+    let pdf = get_asset_path("AppFlowy_Values.pdf");
+    test
+      .ollama_plugin
+      .embed_file(&chat_id, pdf, None)
+      .await
+      .unwrap();
+
+    let resp = test
+      .ollama_plugin
+      .stream_question(&chat_id, "List all the AppFlowy values.", None, json!({}))
+      .await
+      .unwrap();
+    let answer = collect_json_stream(resp).await;
+    println!("top_k {} answer: {}", top_k, answer);
+    scores.push(test.calculate_similarity(&answer, expected).await);
+  }
+  // More chunks give the model more of the values to list.
+  assert!(scores[1] >= scores[0] - 0.05, "scores: {:?}", scores);
+}
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
 async fn ci_database_row_test() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
@@ -249,6 +494,117 @@ async fn ci_database_row_test() {
 }
 
 #[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_quick_complete_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+
+  let completion = test
+    .ollama_plugin
+    .quick_complete("The quick brown fox jumps over", 8, Duration::from_secs(30))
+    .await
+    .unwrap();
+  eprintln!("quick completion: {:?}", completion);
+  assert!(!completion.is_empty());
+}
+
+#[tokio::test]
+async fn quick_complete_before_init_test() {
+  let plugin = OllamaAIPlugin::new(Arc::new(PluginManager::new()));
+  let result = plugin
+    .quick_complete("hello", 8, Duration::from_millis(300))
+    .await;
+  assert!(matches!(result, Err(PluginError::NotReady)));
+}
+
+#[cfg(feature = "language-detection")]
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_chat_auto_match_language_test() {
+  use af_local_ai::ai_ops::ChatSettings;
+
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+
+  let chat_id = uuid::Uuid::new_v4().to_string();
+  test
+    .ollama_plugin
+    .create_chat_with_settings(
+      &chat_id,
+      ChatSettings {
+        response_language: None,
+        auto_match_language: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+  let resp = test
+    .stream_chat_message(
+      &chat_id,
+      "Qu'est-ce qu'une banane ? Réponds en une phrase.",
+      None,
+    )
+    .await;
+  let answer = collect_json_stream(resp).await;
+  eprintln!("answer: {:?}", answer);
+
+  let french = test
+    .calculate_similarity(
+      &answer,
+      "La banane est un fruit tropical allongé, jaune et sucré.",
+    )
+    .await;
+  let english = test
+    .calculate_similarity(
+      &answer,
+      "The banana is an elongated, yellow and sweet tropical fruit.",
+    )
+    .await;
+  assert!(french > english, "french: {}, english: {}", french, english);
+}
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_suggest_questions_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+
+  let text = "Bananas are elongated, edible fruits produced by large herbaceous flowering plants. \
+    They are rich in potassium, usually eaten ripe when the peel turns yellow, and are one of \
+    the most widely grown fruits in tropical regions.";
+  let questions = test.ollama_plugin.suggest_questions(text, 3).await.unwrap();
+  eprintln!("suggested questions: {:?}", questions);
+  assert!(!questions.is_empty());
+
+  let score = test
+    .calculate_similarity(&questions.join(" "), "questions about bananas")
+    .await;
+  assert!(score > 0.5, "score: {}", score);
+}
+
+#[tokio::test]
+async fn suggest_questions_empty_text_test() {
+  let plugin = OllamaAIPlugin::new(Arc::new(PluginManager::new()));
+  let questions = plugin.suggest_questions("  \n ", 3).await.unwrap();
+  assert!(questions.is_empty());
+}
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
 async fn destroy_plugin_test() {
   let test = LocalAITest::new().unwrap();
 
@@ -0,0 +1,188 @@
+use crate::harness::{requests_of, FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::{CompleteTextType, STREAM_METADATA_KEY};
+use af_local_ai::chunking::{ChunkStrategy, BYTE_RANGE_KEY, CHUNK_INDEX_KEY};
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_complete_text_auto_chunk_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "system_info",
+      vec![json!({ "result": { "data": { "version": "fake", "protocol_version": 2 } } })],
+    )
+    .with_replies("complete_text_v2", vec![json!({ "echo_param": "text" })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  // About 2500 tokens, over the 1024 a completion may fill with the default context window.
+  let text = (0..10)
+    .map(|index| format!("Paragraph {}: {}", index, "word ".repeat(198).trim_end()))
+    .collect::<Vec<_>>()
+    .join("\n\n");
+
+  let err = harness
+    .ollama_plugin
+    .complete_text_v2(&text, CompleteTextType::ImproveWriting as u8, None, None)
+    .await
+    .err()
+    .unwrap();
+  assert!(
+    matches!(
+      err,
+      PluginError::InputTooLong {
+        estimated_tokens: 2505..,
+        limit: 1024
+      }
+    ),
+    "{:?}",
+    err
+  );
+  assert!(harness
+    .handled_requests()
+    .iter()
+    .all(|request| request["method"] != "complete_text_v2"));
+
+  let mut stream = harness
+    .ollama_plugin
+    .complete_text_v2_with_options(
+      &text,
+      CompleteTextType::ImproveWriting as u8,
+      None,
+      Some(json!({ "object_id": "doc" })),
+      StreamOptions::default().with_auto_chunk(true),
+    )
+    .await
+    .unwrap();
+  let mut stitched = String::new();
+  let mut markers = vec![];
+  while let Some(frame) = stream.next().await {
+    let frame = frame.unwrap();
+    if let Some(chunk) = frame.get(STREAM_METADATA_KEY).and_then(|m| m.get("chunk")) {
+      markers.push((
+        chunk["index"].as_u64().unwrap(),
+        chunk["count"].as_u64().unwrap(),
+      ));
+    }
+    if let Some(answer) = frame.get("1").and_then(|answer| answer.as_str()) {
+      stitched.push_str(answer);
+    }
+  }
+  // Every paragraph comes back, in order.
+  assert_eq!(stitched, text);
+  assert_eq!(markers, vec![(0, 4), (1, 4), (2, 4), (3, 4)]);
+
+  let requests = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "complete_text_v2")
+    .collect::<Vec<_>>();
+  assert_eq!(requests.len(), 4);
+  for (index, request) in requests.iter().enumerate() {
+    let params = &request["params"];
+    assert!(params["text"].as_str().unwrap().chars().count() <= 4096);
+    assert_eq!(params["metadata"]["object_id"], "doc");
+    let preceding = params["metadata"]["preceding_text"].as_str();
+    if index == 0 {
+      assert!(preceding.is_none(), "{}", params);
+    } else {
+      let previous = requests[index - 1]["params"]["text"].as_str().unwrap();
+      assert!(previous.ends_with(preceding.unwrap()));
+    }
+  }
+}
+
+#[tokio::test]
+async fn fake_embed_document_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new().with_vector_store()).await;
+  let document = "# Fruits\n\nApples are red.\n\n## Citrus\n\nLemons are sour.\n\n## Tropical\n\nMangoes are sweet.";
+  let mut metadata = HashMap::new();
+  metadata.insert("object_id".to_string(), json!("fruits"));
+  let embedded = harness
+    .ollama_plugin
+    .embed_document(
+      document,
+      metadata,
+      ChunkStrategy::MarkdownAware { max_chars: 40 },
+    )
+    .await
+    .unwrap();
+  assert!(embedded.report.is_complete());
+  assert_eq!(embedded.report.chunks_indexed, 3);
+  let chunks = embedded.chunks;
+  assert_eq!(
+    chunks
+      .iter()
+      .map(|chunk| chunk.text.as_str())
+      .collect::<Vec<_>>(),
+    vec![
+      "# Fruits\n\nApples are red.",
+      "## Citrus\n\nLemons are sour.",
+      "## Tropical\n\nMangoes are sweet."
+    ]
+  );
+
+  // The last chunk is retrieved like the others.
+  let found = harness
+    .ollama_plugin
+    .similarity_search("mangoes", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(found, vec![chunks[2].text.clone()]);
+
+  let embedded = requests_of(&harness, "embed_text");
+  assert_eq!(embedded.len(), chunks.len());
+  for (request, chunk) in embedded.iter().zip(&chunks) {
+    let metadata = &request["params"]["metadata"];
+    assert_eq!(metadata["object_id"], "fruits");
+    assert_eq!(metadata[CHUNK_INDEX_KEY], json!(chunk.index));
+    assert_eq!(
+      metadata[BYTE_RANGE_KEY],
+      json!([chunk.byte_range.start, chunk.byte_range.end])
+    );
+  }
+}
+
+#[tokio::test]
+async fn fake_embed_document_partial_failure_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "embed_text",
+    vec![
+      json!({ "result": {} }),
+      json!({ "error": { "code": 1, "message": "model not loaded" } }),
+      json!({ "result": {} }),
+      json!({ "error": { "code": 1, "message": "model not loaded" } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let document = "Apples are red. Lemons are sour. Mangoes are sweet.";
+  let strategy = ChunkStrategy::SentenceWindow {
+    max_chars: 20,
+    overlap_sentences: 0,
+  };
+
+  // The chunks after the failing one are embedded too, and the failure is reported.
+  let embedded = harness
+    .ollama_plugin
+    .embed_document(document, HashMap::new(), strategy)
+    .await
+    .unwrap();
+  assert_eq!(embedded.chunks.len(), 3);
+  assert_eq!(embedded.report.chunks_indexed, 2);
+  assert_eq!(embedded.report.chunks_failed, 1);
+  assert_eq!(embedded.report.failures.len(), 1);
+  assert_eq!(embedded.report.failures[0].index, 1);
+  assert!(embedded.report.failures[0]
+    .reason
+    .contains("model not loaded"));
+  assert_eq!(requests_of(&harness, "embed_text").len(), 3);
+
+  // A document that can't be embedded at all is an error.
+  let err = harness
+    .ollama_plugin
+    .embed_document(document, HashMap::new(), strategy)
+    .await
+    .unwrap_err();
+  assert!(err.to_string().contains("model not loaded"), "{}", err);
+}
@@ -0,0 +1,222 @@
+use af_local_ai::chunking::{
+  chunk_text, completion_input_limit, estimate_tokens, Chunk, ChunkStrategy, TextChunk,
+  TextChunker, CHUNK_OVERLAP_CHARS,
+};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+fn rejoin(chunks: &[TextChunk]) -> String {
+  chunks
+    .iter()
+    .flat_map(|chunk| [chunk.separator.as_str(), chunk.text.as_str()])
+    .collect()
+}
+
+#[test]
+fn estimate_tokens_test() {
+  assert_eq!(estimate_tokens(""), 0);
+  assert_eq!(estimate_tokens("abcd"), 1);
+  assert_eq!(estimate_tokens("abcde"), 2);
+  // Characters, not bytes.
+  assert_eq!(estimate_tokens("éééé"), 1);
+  assert_eq!(completion_input_limit(2048), 1024);
+}
+
+#[test]
+fn short_text_is_one_chunk_test() {
+  let chunks = chunk_text("One.\n\nTwo.", 100);
+  assert_eq!(
+    chunks,
+    vec![TextChunk {
+      separator: String::new(),
+      text: "One.\n\nTwo.".to_string(),
+      preceding_text: None,
+    }]
+  );
+}
+
+#[test]
+fn chunks_end_at_paragraph_breaks_test() {
+  let text = "aaaa aaaa\n\nbbbb bbbb\n\ncccc\n\ndddd dddd dddd";
+  let chunks = chunk_text(text, 16);
+  let texts = chunks
+    .iter()
+    .map(|chunk| chunk.text.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(
+    texts,
+    vec!["aaaa aaaa", "bbbb bbbb\n\ncccc", "dddd dddd dddd"]
+  );
+  assert_eq!(chunks[0].separator, "");
+  assert_eq!(chunks[1].separator, "\n\n");
+  assert_eq!(chunks[1].preceding_text.as_deref(), Some("aaaa aaaa"));
+  assert_eq!(rejoin(&chunks), text);
+}
+
+#[test]
+fn long_paragraph_is_split_between_words_test() {
+  let text = "one two three four five six seven";
+  let chunks = chunk_text(text, 10);
+  assert!(chunks.len() > 1);
+  for chunk in &chunks {
+    assert!(chunk.text.chars().count() <= 10, "{:?}", chunk);
+    assert_eq!(chunk.separator, "");
+  }
+  assert_eq!(rejoin(&chunks), text);
+}
+
+#[test]
+fn graphemes_are_never_split_test() {
+  // A family emoji is one grapheme of five chars, and an accented e written with a combining
+  // accent is one of two.
+  let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+  let accented = "e\u{0301}";
+  let text = format!("{}{}{}", family.repeat(3), accented.repeat(4), family);
+  let chunks = chunk_text(&text, 6);
+  for chunk in &chunks {
+    assert!(
+      chunk.text == family || chunk.text.chars().count() <= 6,
+      "{:?}",
+      chunk
+    );
+    let graphemes = chunk.text.replace(family, "").replace(accented, "");
+    assert!(graphemes.is_empty(), "split grapheme in {:?}", chunk.text);
+  }
+  assert_eq!(rejoin(&chunks), text);
+}
+
+#[test]
+fn preceding_text_is_the_end_of_the_previous_chunk_test() {
+  let first = "x".repeat(CHUNK_OVERLAP_CHARS + 50);
+  let text = format!("{}\n\nsecond", first);
+  let chunks = chunk_text(&text, CHUNK_OVERLAP_CHARS + 50);
+  assert_eq!(chunks.len(), 2);
+  assert_eq!(
+    chunks[1].preceding_text.as_deref(),
+    Some("x".repeat(CHUNK_OVERLAP_CHARS).as_str())
+  );
+}
+
+const FIXTURE: &str = include_str!("../asset/chunking_fixture.md");
+
+/// The texts of the chunks of the fixture, checking each is the text at its byte range.
+fn chunk_fixture(strategy: ChunkStrategy) -> Vec<String> {
+  let chunks = TextChunker::new(strategy).chunk(FIXTURE);
+  for (index, chunk) in chunks.iter().enumerate() {
+    assert_eq!(chunk.index, index);
+    assert_eq!(chunk.text, &FIXTURE[chunk.byte_range.clone()]);
+  }
+  chunks.into_iter().map(|chunk| chunk.text).collect()
+}
+
+#[test]
+fn fixed_chars_chunks_test() {
+  let chunks = chunk_fixture(ChunkStrategy::FixedChars {
+    size: 60,
+    overlap: 10,
+  });
+  assert_eq!(
+    chunks,
+    vec![
+      "# A rather long heading about syncing documents between devi",
+      "tween devices without losing edits\n\nAppFlowy keeps your note",
+      "your notes on your device. Edits sync when you are online.",
+      "e online. 离线时也可以编辑。\n\n## Emoji 🎉👩\u{200d}👩\u{200d}👧\n\nReactions like 👍🏽 and",
+      "ke 👍🏽 and 👩\u{200d}👩\u{200d}👧 are single graphemes. They must never be cut",
+      "ver be cut in half.\n\n```rust\nfn main() {\n    println!(\"hello",
+      "ln!(\"hello\");\n}\n```\n\n### 中文段落\n\n这是第一句。这是第二句！这是第三句？",
+    ]
+  );
+}
+
+#[test]
+fn sentence_window_chunks_test() {
+  let chunks = chunk_fixture(ChunkStrategy::SentenceWindow {
+    max_chars: 80,
+    overlap_sentences: 1,
+  });
+  assert_eq!(
+    chunks,
+    vec![
+      // The heading is longer than a chunk, so it is split.
+      "# A rather long heading about syncing documents between devices without losing e",
+      "dits\n\nAppFlowy keeps your notes on your device. Edits sync when you are online.",
+      "Edits sync when you are online. 离线时也可以编辑。\n\n## Emoji 🎉👩\u{200d}👩\u{200d}👧",
+      "## Emoji 🎉👩\u{200d}👩\u{200d}👧\n\nReactions like 👍🏽 and 👩\u{200d}👩\u{200d}👧 are single graphemes.",
+      // No sentence fits after the overlapped one, so this chunk starts without it.
+      "They must never be cut in half.\n\n```rust\nfn main() {\n    println!(\"hello\");\n}",
+      "}\n```\n\n### 中文段落\n\n这是第一句。这是第二句！这是第三句？",
+    ]
+  );
+}
+
+#[test]
+fn markdown_aware_chunks_test() {
+  let chunks = chunk_fixture(ChunkStrategy::MarkdownAware { max_chars: 120 });
+  assert_eq!(
+    chunks,
+    vec![
+      "# A rather long heading about syncing documents between devices without losing edits",
+      "AppFlowy keeps your notes on your device. Edits sync when you are online. 离线时也可以编辑。",
+      "## Emoji 🎉👩\u{200d}👩\u{200d}👧\n\nReactions like 👍🏽 and 👩\u{200d}👩\u{200d}👧 are single graphemes. They must never be cut in half.",
+      "```rust\nfn main() {\n    println!(\"hello\");\n}\n```",
+      "### 中文段落\n\n这是第一句。这是第二句！这是第三句？",
+    ]
+  );
+
+  // A code fence longer than a chunk is kept whole, and a long paragraph is split by sentences.
+  let text = "```\nlet a = 1;\nlet b = 2;\n```\nFirst sentence here. Second sentence here.";
+  let chunks = TextChunker::new(ChunkStrategy::MarkdownAware { max_chars: 24 }).chunk(text);
+  assert_eq!(
+    chunks,
+    vec![
+      Chunk {
+        text: "```\nlet a = 1;\nlet b = 2;\n```".to_string(),
+        index: 0,
+        byte_range: 0..29,
+      },
+      Chunk {
+        text: "First sentence here.".to_string(),
+        index: 1,
+        byte_range: 30..50,
+      },
+      Chunk {
+        text: "Second sentence here.".to_string(),
+        index: 2,
+        byte_range: 51..72,
+      },
+    ]
+  );
+}
+
+#[test]
+fn paragraphs_chunks_test() {
+  // The chunks of chunk_text, with their whitespace trimmed.
+  let expected = chunk_text(FIXTURE, 120)
+    .into_iter()
+    .map(|chunk| chunk.text.trim().to_string())
+    .collect::<Vec<_>>();
+  let chunks = chunk_fixture(ChunkStrategy::Paragraphs { max_chars: 120 });
+  assert_eq!(chunks, expected);
+  assert_eq!(chunks.len(), 4);
+}
+
+#[test]
+fn whitespace_has_no_chunks_test() {
+  for strategy in [
+    ChunkStrategy::FixedChars {
+      size: 4,
+      overlap: 1,
+    },
+    ChunkStrategy::SentenceWindow {
+      max_chars: 4,
+      overlap_sentences: 1,
+    },
+    ChunkStrategy::MarkdownAware { max_chars: 4 },
+    ChunkStrategy::Paragraphs { max_chars: 4 },
+  ] {
+    assert!(TextChunker::new(strategy).chunk(" \n\n ").is_empty());
+    assert!(TextChunker::new(strategy).chunk("").is_empty());
+  }
+}
@@ -0,0 +1,72 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops;
+use af_local_ai::compat::{CompleteTextType, LocalAICompat, PluginError};
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn compat_index_file_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new().with_vector_store()).await;
+  let plugin = &harness.ollama_plugin;
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("apples.md");
+  std::fs::write(&path, "Apples are red").unwrap();
+
+  plugin
+    .index_file("chat", Some(path), None, None)
+    .await
+    .unwrap();
+  plugin
+    .index_file(
+      "chat",
+      None,
+      Some("Bananas are yellow".to_string()),
+      Some(HashMap::new()),
+    )
+    .await
+    .unwrap();
+  let result = plugin.index_file("chat", None, None, None).await;
+  assert!(matches!(result, Err(PluginError::Internal(_))));
+
+  let found = plugin
+    .similarity_search("apples or bananas", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(found, vec!["Apples are red", "Bananas are yellow"]);
+}
+
+#[tokio::test]
+async fn compat_destroy_chat_plugin_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  harness.ollama_plugin.destroy_chat_plugin().await.unwrap();
+  assert!(!harness
+    .ollama_plugin
+    .get_plugin_running_state()
+    .is_running());
+}
+
+#[test]
+fn compat_complete_text_type_test() {
+  for legacy in [
+    CompleteTextType::ImproveWriting,
+    CompleteTextType::SpellingAndGrammar,
+    CompleteTextType::MakeShorter,
+    CompleteTextType::MakeLonger,
+    CompleteTextType::AskAI,
+  ] {
+    let converted = ai_ops::CompleteTextType::from(legacy);
+    assert_eq!(converted as u8, legacy as u8);
+    assert_eq!(CompleteTextType::from(converted), legacy);
+  }
+  assert_eq!(
+    CompleteTextType::from(ai_ops::CompleteTextType::ContinueWriting),
+    CompleteTextType::MakeLonger
+  );
+  assert_eq!(
+    CompleteTextType::from(ai_ops::CompleteTextType::Explain),
+    CompleteTextType::AskAI
+  );
+  assert_eq!(
+    CompleteTextType::from(ai_ops::CompleteTextType::Custom),
+    CompleteTextType::AskAI
+  );
+}
@@ -0,0 +1,123 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::database_query::{ColumnDef, DatabaseQueryAnswer, FieldType};
+use serde_json::json;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn fake_query_database_test() {
+  let rows = [
+    ("Dune", "9"),
+    ("Emma", "5"),
+    ("Ulysses", "8"),
+    ("Beloved", "10"),
+    ("Rebecca", "3"),
+  ]
+  .iter()
+  .map(|(title, rating)| {
+    HashMap::from([
+      ("Title".to_string(), title.to_string()),
+      ("Rating".to_string(), rating.to_string()),
+    ])
+  })
+  .collect::<Vec<_>>();
+  let schema = vec![
+    ColumnDef {
+      name: "Title".to_string(),
+      field_type: FieldType::Text,
+    },
+    ColumnDef {
+      name: "Rating".to_string(),
+      field_type: FieldType::Number,
+    },
+  ];
+  let question = "Which books did I rate above 7?";
+  let reply = |answer: &str, indices: serde_json::Value| json!({ "result": { "data": { "answer": answer, "matching_row_indices": indices } } });
+
+  // Two rows per chunk, with indices relative to each chunk.
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "database_query",
+      vec![
+        reply("Dune.", json!([0])),
+        reply("Ulysses and Beloved.", json!([0, 1])),
+        // Out of the chunk of one row.
+        reply("None.", json!([3])),
+      ],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![answer_stream(&["Dune, Ulysses", " and Beloved."])],
+    );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let config = harness.config().with_database_query_chunk_rows(2);
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  let answer = harness
+    .ollama_plugin
+    .query_database(rows.clone(), schema.clone(), question)
+    .await
+    .unwrap();
+  assert_eq!(
+    answer,
+    DatabaseQueryAnswer {
+      answer: "Dune, Ulysses and Beloved.".to_string(),
+      matching_row_indices: Some(vec![0, 2, 3]),
+    }
+  );
+  let requests = harness.handled_requests();
+  let chunks = requests
+    .iter()
+    .filter(|request| request["method"] == "database_query")
+    .map(|request| {
+      assert_eq!(request["params"]["question"], question);
+      assert_eq!(request["params"]["schema"][1]["field_type"], "number");
+      request["params"]["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["Title"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>()
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    chunks,
+    [
+      vec!["Dune", "Emma"],
+      vec!["Ulysses", "Beloved"],
+      vec!["Rebecca"]
+    ]
+  );
+  let prompt = requests
+    .iter()
+    .find(|request| request["method"] == "complete_text_v2")
+    .unwrap()["params"]["text"]
+    .as_str()
+    .unwrap()
+    .to_string();
+  assert!(prompt.contains(question), "{}", prompt);
+  assert!(
+    prompt.contains("Part 2: Ulysses and Beloved."),
+    "{}",
+    prompt
+  );
+
+  // Rows fitting in one chunk are answered by the plugin alone.
+  let scenario = FakeScenario::new().with_replies(
+    "database_query",
+    vec![reply("Dune, Ulysses and Beloved.", json!([3, 0, 2]))],
+  );
+  harness.restart_with(scenario).await;
+  let answer = harness
+    .ollama_plugin
+    .query_database(rows, schema, question)
+    .await
+    .unwrap();
+  assert_eq!(answer.answer, "Dune, Ulysses and Beloved.");
+  assert_eq!(answer.matching_row_indices, Some(vec![0, 2, 3]));
+  // Only the completion of the chunked query was sent.
+  let completions = harness
+    .handled_requests()
+    .iter()
+    .filter(|request| request["method"] == "complete_text_v2")
+    .count();
+  assert_eq!(completions, 1);
+}
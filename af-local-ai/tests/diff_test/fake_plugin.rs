@@ -0,0 +1,75 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::CompleteTextType;
+use af_plugin::core::stream::StreamOptions;
+use serde_json::json;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_completion_diff_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "complete_text_v2",
+    vec![json!({ "stream": [
+      json!({ "1": "He starts work " }).to_string(),
+      json!({ "1": "every day at 8 a.m." }).to_string(),
+      json!({ "4": "\"everyday\" is an adjective." }).to_string(),
+    ] })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let original = "He starts work everyday at 8 a.m.";
+  let options = StreamOptions::default().with_compute_diff(true);
+
+  let frames = harness
+    .ollama_plugin
+    .complete_text_v2_with_options(
+      original,
+      CompleteTextType::SpellingAndGrammar as u8,
+      None,
+      None,
+      options.clone(),
+    )
+    .await
+    .unwrap()
+    .map(Result::unwrap)
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(frames.len(), 4);
+  let diff = &frames[3]["diff"];
+  assert_eq!(
+    diff[1],
+    json!({ "op": "delete", "text": "everyday", "range": { "start": 15, "end": 23 } })
+  );
+  assert_eq!(diff[2]["op"], "insert");
+  assert_eq!(diff[2]["text"], "every day");
+
+  let result = harness
+    .ollama_plugin
+    .complete_text_v2_collect(
+      original,
+      CompleteTextType::SpellingAndGrammar as u8,
+      None,
+      None,
+      options,
+    )
+    .await
+    .unwrap();
+  assert_eq!(result.answer, "He starts work every day at 8 a.m.");
+  assert_eq!(
+    result.comment.as_deref(),
+    Some("\"everyday\" is an adjective.")
+  );
+  assert_eq!(result.diff.unwrap().len(), 4);
+
+  // Without the option, the stream has no diff frame.
+  let result = harness
+    .ollama_plugin
+    .complete_text_v2_collect(
+      original,
+      CompleteTextType::SpellingAndGrammar as u8,
+      None,
+      None,
+      StreamOptions::default(),
+    )
+    .await
+    .unwrap();
+  assert_eq!(result.diff, None);
+}
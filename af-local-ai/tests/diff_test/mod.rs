@@ -0,0 +1,103 @@
+use af_local_ai::diff::{word_diff, DiffOp, DiffSpan};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+/// Checks that the spans cover `original` in order, on char boundaries, and that applying them
+/// gives `revised`.
+fn assert_valid(original: &str, revised: &str, spans: &[DiffSpan]) {
+  let mut position = 0;
+  let mut applied = String::new();
+  for span in spans {
+    assert!(original.is_char_boundary(span.range.start), "{:?}", span);
+    assert!(original.is_char_boundary(span.range.end), "{:?}", span);
+    assert_eq!(span.range.start, position, "{:?}", span);
+    match span.op {
+      DiffOp::Insert => {
+        assert!(span.range.is_empty());
+        applied.push_str(&span.text);
+      },
+      DiffOp::Equal => {
+        assert_eq!(&original[span.range.clone()], span.text);
+        applied.push_str(&span.text);
+      },
+      DiffOp::Delete => assert_eq!(&original[span.range.clone()], span.text),
+    }
+    position = span.range.end;
+  }
+  assert_eq!(position, original.len());
+  assert_eq!(applied, revised);
+}
+
+fn changes(spans: &[DiffSpan]) -> Vec<(DiffOp, &str)> {
+  spans
+    .iter()
+    .filter(|span| span.op != DiffOp::Equal)
+    .map(|span| (span.op, span.text.as_str()))
+    .collect()
+}
+
+#[test]
+fn word_diff_test() {
+  let original = "He starts work everyday at 8 a.m.";
+  let revised = "He starts work every day at 8 a.m.";
+  let spans = word_diff(original, revised);
+  assert_valid(original, revised, &spans);
+  assert_eq!(
+    changes(&spans),
+    vec![(DiffOp::Delete, "everyday"), (DiffOp::Insert, "every day")]
+  );
+  let deleted = spans.iter().find(|span| span.op == DiffOp::Delete).unwrap();
+  assert_eq!(deleted.range, 15..23);
+
+  assert_eq!(
+    word_diff(original, original),
+    vec![DiffSpan {
+      op: DiffOp::Equal,
+      text: original.to_string(),
+      range: 0..original.len(),
+    }]
+  );
+}
+
+#[test]
+fn mixed_language_word_diff_test() {
+  let original = "He starts work everyday at 8 a.m. 然后他开始工作了一整天， 没有♨️";
+  let revised = "He starts work every day at 8 a.m. 然后他工作了一整天，没有休息♨️";
+  let spans = word_diff(original, revised);
+  assert_valid(original, revised, &spans);
+  assert_eq!(
+    changes(&spans),
+    vec![
+      (DiffOp::Delete, "everyday"),
+      (DiffOp::Insert, "every day"),
+      (DiffOp::Delete, "开始"),
+      (DiffOp::Delete, " "),
+      (DiffOp::Insert, "休息"),
+    ]
+  );
+  // The emoji and its variation selector are a single word.
+  let last = spans.last().unwrap();
+  assert_eq!(last.op, DiffOp::Equal);
+  assert!(last.text.ends_with("♨️"));
+}
+
+#[test]
+fn emoji_word_diff_test() {
+  let original = "Great job 👍🏽 team";
+  let revised = "Great job 👍🏿 team 👨‍👩‍👧";
+  let spans = word_diff(original, revised);
+  assert_valid(original, revised, &spans);
+  assert_eq!(
+    changes(&spans),
+    vec![
+      (DiffOp::Delete, "👍🏽"),
+      (DiffOp::Insert, "👍🏿"),
+      (DiffOp::Insert, " 👨‍👩‍👧"),
+    ]
+  );
+
+  let spans = word_diff("", "안녕하세요");
+  assert_valid("", "안녕하세요", &spans);
+  assert_eq!(changes(&spans), vec![(DiffOp::Insert, "안녕하세요")]);
+}
@@ -0,0 +1,77 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn fake_find_near_duplicates_test() {
+  let page = "Bananas are yellow and grow in bunches.";
+  // The fake plugin scores the same text embedded under two ids, and an unrelated one.
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![
+      json!({ "result": { "data": [] } }),
+      json!({ "result": { "data": [
+        { "content": page, "score": 0.99, "metadata": { "object_id": "page-1" } },
+        { "content": page, "score": 0.98, "metadata": { "object_id": "page-2" } },
+        { "content": "Grow in bunches.", "score": 0.96, "metadata": { "object_id": "page-1" } },
+        { "content": "Cars need fuel.", "score": 0.21, "metadata": { "object_id": "page-3" } },
+      ] } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  // Nothing is embedded yet.
+  let candidates = harness
+    .ollama_plugin
+    .find_near_duplicates(page, 0.95, HashMap::new())
+    .await
+    .unwrap();
+  assert!(candidates.is_empty());
+
+  for (object_id, text) in [
+    ("page-1", page),
+    ("page-2", page),
+    ("page-3", "Cars need fuel."),
+  ] {
+    harness
+      .ollama_plugin
+      .embed_text(
+        text,
+        HashMap::from([("object_id".to_string(), json!(object_id))]),
+      )
+      .await
+      .unwrap();
+  }
+  let candidates = harness
+    .ollama_plugin
+    .find_near_duplicates(page, 0.95, HashMap::new())
+    .await
+    .unwrap();
+  let found = candidates
+    .iter()
+    .map(|candidate| (candidate.metadata["object_id"].clone(), candidate.score))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    found,
+    vec![(json!("page-1"), 0.99), (json!("page-2"), 0.98)]
+  );
+  assert_eq!(candidates[0].snippet, page);
+  let params = harness
+    .handled_requests()
+    .into_iter()
+    .rev()
+    .find(|request| request["method"] == "similarity_search")
+    .unwrap()["params"]
+    .clone();
+  assert_eq!(params["min_score"], 0.95);
+
+  for threshold in [-0.1, 1.5, f64::NAN] {
+    let err = harness
+      .ollama_plugin
+      .find_near_duplicates(page, threshold, HashMap::new())
+      .await
+      .unwrap_err();
+    assert!(matches!(err, PluginError::InvalidThreshold(_)), "{:?}", err);
+  }
+}
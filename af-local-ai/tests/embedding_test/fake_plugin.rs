@@ -0,0 +1,73 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::embedding_plugin::{EmbeddingPlugin, EmbeddingPluginConfig};
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn fake_attached_embedding_plugin_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![json!({ "result": { "data": ["Bananas are yellow"] } })],
+  );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let embedding_plugin = EmbeddingPlugin::attached(&harness.ollama_plugin);
+  assert!(embedding_plugin.is_attached());
+  let config = harness
+    .config()
+    .with_embedded_embedding("nomic-embed-text".to_string());
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let embeddings = embedding_plugin
+    .generate_embedding("Bananas are yellow")
+    .await
+    .unwrap();
+  assert_eq!(embeddings, vec![vec![0.1, 0.2, 0.3]]);
+  let results = embedding_plugin
+    .similarity_search("bananas", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(results, vec!["Bananas are yellow".to_string()]);
+
+  // Both were answered by the only plugin process, which loaded the embedding model.
+  let initialize_params = harness.initialize_params();
+  assert_eq!(initialize_params.len(), 1);
+  assert_eq!(
+    initialize_params[0]["vectorstore_config"]["model_name"],
+    "nomic-embed-text"
+  );
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+    .collect::<Vec<_>>();
+  assert!(methods.contains(&"gen_embeddings".to_string()));
+  assert!(methods.contains(&"similarity_search".to_string()));
+
+  // An attached plugin has no process of its own to initialize.
+  let err = embedding_plugin
+    .init_embedding_plugin(
+      EmbeddingPluginConfig::new(harness.config().executable_path, "model".to_string(), None)
+        .unwrap(),
+    )
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::EmbeddingModeMismatch(_)),
+    "{:?}",
+    err
+  );
+
+  // Nor can it share a chat plugin that doesn't run the embedding model.
+  harness.start().await;
+  let err = embedding_plugin
+    .generate_embedding("Bananas are yellow")
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::EmbeddingModeMismatch(_)),
+    "{:?}",
+    err
+  );
+  assert!(!EmbeddingPlugin::new(harness.plugin_manager.clone()).is_attached());
+}
@@ -1,8 +1,24 @@
 use crate::util::LocalAITest;
+use af_local_ai::embedding_index::{content_hash, EmbeddingIndex, IndexOutcome};
+use af_local_ai::embedding_manifest::{
+  check_manifest, read_manifest, write_manifest, EmbeddingModelInfo, MismatchPolicy,
+};
+use af_local_ai::embedding_ops::SearchOptions;
+use af_local_ai::vector_store::ImportPolicy;
+use af_plugin::error::PluginError;
 use serde_json::json;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
 
 #[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
 async fn ci_generate_embedding_test() {
   let test = LocalAITest::new().unwrap();
   test.init_chat_plugin().await;
@@ -19,3 +35,263 @@ async fn ci_generate_embedding_test() {
     .unwrap();
   eprintln!("embedding response: {:?}", resp);
 }
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_similarity_search_pagination_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+  let id = uuid::Uuid::new_v4().to_string();
+  let mut metadata = HashMap::new();
+  metadata.insert("id".to_string(), json!(id));
+  for text in [
+    "Bananas are yellow fruits that grow in the tropics",
+    "Banana bread is baked with ripe bananas",
+    "Apples grow on trees in cold climates",
+    "The Aurora rocket uses a methane engine",
+    "AppFlowy is an AI collaborative workspace",
+  ] {
+    test
+      .ollama_plugin
+      .embed_text(text, metadata.clone())
+      .await
+      .unwrap();
+  }
+
+  let search = |offset: usize| {
+    test.ollama_plugin.similarity_search_with_options(
+      "bananas",
+      metadata.clone(),
+      SearchOptions::new(2).with_offset(offset),
+    )
+  };
+  let first = search(0).await.unwrap();
+  let second = search(2).await.unwrap();
+  assert_eq!(first.results.len(), 2);
+  assert_eq!(second.results.len(), 2);
+  for result in &first.results {
+    assert!(!second.results.contains(result), "{:?}", result);
+  }
+  let scores = first
+    .results
+    .iter()
+    .chain(second.results.iter())
+    .filter_map(|result| result.score)
+    .collect::<Vec<_>>();
+  assert!(
+    scores.windows(2).all(|pair| pair[0] >= pair[1]),
+    "{:?}",
+    scores
+  );
+  assert!(search(10).await.unwrap().results.is_empty());
+}
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_vector_store_snapshot_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+  let id = uuid::Uuid::new_v4().to_string();
+  let mut metadata = HashMap::new();
+  metadata.insert("id".to_string(), json!(id));
+  test
+    .ollama_plugin
+    .embed_text(
+      "AppFlowy is an AI collaborative workspace",
+      metadata.clone(),
+    )
+    .await
+    .unwrap();
+
+  let dir = tempfile::tempdir().unwrap();
+  let snapshot_path = dir.path().join("vectors.tar.zst");
+  let snapshot = test
+    .ollama_plugin
+    .export_vector_store(&snapshot_path)
+    .await
+    .unwrap();
+  assert!(snapshot.item_count >= 1);
+
+  // A fresh plugin with an empty vector store, as on another device.
+  let other = LocalAITest::new().unwrap();
+  other.init_chat_plugin().await;
+  let imported = other
+    .ollama_plugin
+    .import_vector_store(&snapshot_path, ImportPolicy::Validate)
+    .await
+    .unwrap();
+  assert_eq!(imported, snapshot);
+  let resp = other
+    .ollama_plugin
+    .similarity_search("AppFlowy", metadata)
+    .await
+    .unwrap();
+  assert!(!resp.is_empty());
+}
+
+#[test]
+fn embedding_manifest_mismatch_test() {
+  let persist_dir = tempfile::tempdir().unwrap();
+  let stored = EmbeddingModelInfo {
+    model: "nomic-embed-text".to_string(),
+    dimension: 768,
+  };
+  write_manifest(persist_dir.path(), &stored).unwrap();
+  std::fs::write(persist_dir.path().join("chroma.sqlite3"), b"vectors").unwrap();
+
+  let err = check_manifest(
+    persist_dir.path(),
+    "mxbai-embed-large",
+    MismatchPolicy::Error,
+  )
+  .unwrap_err();
+  match err {
+    PluginError::EmbeddingModelChanged { stored, configured } => {
+      assert_eq!(stored, "nomic-embed-text");
+      assert_eq!(configured, "mxbai-embed-large");
+    },
+    err => panic!("unexpected error: {:?}", err),
+  }
+
+  check_manifest(
+    persist_dir.path(),
+    "mxbai-embed-large",
+    MismatchPolicy::Ignore,
+  )
+  .unwrap();
+  assert_eq!(read_manifest(persist_dir.path()).unwrap(), Some(stored));
+
+  check_manifest(
+    persist_dir.path(),
+    "mxbai-embed-large",
+    MismatchPolicy::Reindex,
+  )
+  .unwrap();
+  assert!(read_manifest(persist_dir.path()).unwrap().is_none());
+  assert_eq!(std::fs::read_dir(persist_dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_embed_text_if_changed_test() {
+  let test = LocalAITest::new().unwrap();
+  test.init_chat_plugin().await;
+
+  let mut metadata = HashMap::new();
+  metadata.insert(
+    "object_id".to_string(),
+    json!(uuid::Uuid::new_v4().to_string()),
+  );
+  let text = "AppFlowy is an AI collaborative workspace";
+
+  let outcome = test
+    .ollama_plugin
+    .embed_text_if_changed(text, metadata.clone())
+    .await
+    .unwrap();
+  assert_eq!(outcome, IndexOutcome::Indexed);
+  let outcome = test
+    .ollama_plugin
+    .embed_text_if_changed(text, metadata.clone())
+    .await
+    .unwrap();
+  assert_eq!(outcome, IndexOutcome::Skipped);
+  let outcome = test
+    .ollama_plugin
+    .embed_text_if_changed("AppFlowy is an AI collaborative workspace!", metadata)
+    .await
+    .unwrap();
+  assert_eq!(outcome, IndexOutcome::Indexed);
+}
+
+#[tokio::test]
+async fn embedding_index_dedup_test() {
+  let persist_dir = tempfile::tempdir().unwrap();
+  let index = EmbeddingIndex::open(persist_dir.path()).unwrap();
+  let mut metadata = HashMap::new();
+  metadata.insert("object_id".to_string(), json!("doc-1"));
+  let calls = AtomicUsize::new(0);
+  let embed = || async {
+    calls.fetch_add(1, Ordering::SeqCst);
+    Ok::<_, PluginError>(())
+  };
+
+  let hash = content_hash("hello world", &metadata);
+  let outcome = index.index_once(&hash, Some("doc-1"), embed).await.unwrap();
+  assert_eq!(outcome, IndexOutcome::Indexed);
+  let outcome = index.index_once(&hash, Some("doc-1"), embed).await.unwrap();
+  assert_eq!(outcome, IndexOutcome::Skipped);
+
+  let changed = content_hash("hello world!", &metadata);
+  let outcome = index
+    .index_once(&changed, Some("doc-1"), embed)
+    .await
+    .unwrap();
+  assert_eq!(outcome, IndexOutcome::Indexed);
+  assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+  // The same text belonging to another object is indexed again.
+  metadata.insert("object_id".to_string(), json!("doc-2"));
+  assert_ne!(content_hash("hello world", &metadata), hash);
+
+  // The index survives a restart.
+  drop(index);
+  let index = EmbeddingIndex::open(persist_dir.path()).unwrap();
+  assert_eq!(index.len(), 2);
+  let outcome = index.index_once(&hash, Some("doc-1"), embed).await.unwrap();
+  assert_eq!(outcome, IndexOutcome::Skipped);
+
+  assert_eq!(index.invalidate("doc-1").unwrap(), 2);
+  let outcome = index.index_once(&hash, Some("doc-1"), embed).await.unwrap();
+  assert_eq!(outcome, IndexOutcome::Indexed);
+  assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn embedding_index_concurrent_dedup_test() {
+  let index = EmbeddingIndex::in_memory();
+  let hash = content_hash("hello world", &HashMap::new());
+  let calls = AtomicUsize::new(0);
+  let embed = || async {
+    calls.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    Ok::<_, PluginError>(())
+  };
+
+  let (first, second) = tokio::join!(
+    index.index_once(&hash, None, embed),
+    index.index_once(&hash, None, embed)
+  );
+  let mut outcomes = vec![first.unwrap(), second.unwrap()];
+  outcomes.sort_by_key(|outcome| *outcome == IndexOutcome::Skipped);
+  assert_eq!(outcomes, vec![IndexOutcome::Indexed, IndexOutcome::Skipped]);
+  assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn embedding_index_failed_embed_is_retried_test() {
+  let index = EmbeddingIndex::in_memory();
+  let hash = content_hash("hello world", &HashMap::new());
+  let result = index
+    .index_once(&hash, None, || async {
+      Err(PluginError::Internal(anyhow::anyhow!("embed failed")))
+    })
+    .await;
+  assert!(result.is_err());
+  assert!(!index.contains(&hash));
+
+  let outcome = index
+    .index_once(&hash, None, || async { Ok(()) })
+    .await
+    .unwrap();
+  assert_eq!(outcome, IndexOutcome::Indexed);
+}
@@ -0,0 +1,146 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::CompleteTextType;
+use af_local_ai::events::{
+  ChatStreamRequest, CompletionEvents, CompletionId, CompletionRequest, CompletionSink, StreamFrame,
+};
+use af_local_ai::ollama_plugin::OllamaAIPlugin;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+enum SinkEvent {
+  Chunk(CompletionId, StreamFrame),
+  Error(CompletionId, String),
+  Done(CompletionId),
+}
+
+/// Records the calls of [CompletionEvents], and wakes `wait_until_ended` on `on_done` and
+/// `on_error`.
+#[derive(Default)]
+struct RecordingSink {
+  events: parking_lot::Mutex<Vec<SinkEvent>>,
+  ended: tokio::sync::Notify,
+}
+
+impl RecordingSink {
+  fn events(&self) -> Vec<SinkEvent> {
+    self.events.lock().clone()
+  }
+
+  async fn wait_until_ended(&self) {
+    tokio::time::timeout(Duration::from_secs(5), self.ended.notified())
+      .await
+      .unwrap();
+  }
+}
+
+impl CompletionSink for RecordingSink {
+  fn on_chunk(&self, id: CompletionId, frame: StreamFrame) {
+    self.events.lock().push(SinkEvent::Chunk(id, frame));
+  }
+
+  fn on_error(&self, id: CompletionId, error: PluginError) {
+    self
+      .events
+      .lock()
+      .push(SinkEvent::Error(id, error.to_string()));
+    self.ended.notify_one();
+  }
+
+  fn on_done(&self, id: CompletionId) {
+    self.events.lock().push(SinkEvent::Done(id));
+    self.ended.notify_one();
+  }
+}
+
+async fn start_events(harness: &TestPluginHarness) -> CompletionEvents {
+  let plugin = Arc::new(OllamaAIPlugin::new(harness.plugin_manager.clone()));
+  plugin.init_plugin(harness.config()).await.unwrap();
+  CompletionEvents::new(plugin, tokio::runtime::Handle::current())
+}
+
+#[tokio::test]
+async fn fake_completion_events_test() {
+  let frames = vec![
+    json!({ "1": "He and I went" }).to_string(),
+    json!({ "4": "Fixed the subject." }).to_string(),
+  ];
+  let scenario = FakeScenario::new()
+    .with_replies("complete_text_v2", vec![json!({ "stream": frames })])
+    .with_replies(
+      "stream_answer_v2",
+      vec![json!({ "error": { "code": 1, "message": "model not loaded" } })],
+    );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let events = start_events(&harness).await;
+
+  let sink = Arc::new(RecordingSink::default());
+  let request = CompletionRequest::new(
+    "Me and him went",
+    CompleteTextType::SpellingAndGrammar as u8,
+  );
+  let id = events.start_completion(request, sink.clone());
+  sink.wait_until_ended().await;
+  assert_eq!(
+    sink.events(),
+    vec![
+      SinkEvent::Chunk(id, StreamFrame::Answer("He and I went".to_string())),
+      SinkEvent::Chunk(id, StreamFrame::Comment("Fixed the subject.".to_string())),
+      SinkEvent::Done(id),
+    ]
+  );
+  assert_eq!(events.running(), 0);
+  assert!(!events.cancel(id));
+
+  let sink = Arc::new(RecordingSink::default());
+  let failed = events.start_chat_stream(ChatStreamRequest::new("chat", "hello"), sink.clone());
+  assert_ne!(failed, id);
+  sink.wait_until_ended().await;
+  let recorded = sink.events();
+  assert_eq!(recorded.len(), 1, "{:?}", recorded);
+  assert!(
+    matches!(&recorded[0], SinkEvent::Error(error_id, message) if *error_id == failed && message.contains("model not loaded")),
+    "{:?}",
+    recorded
+  );
+  assert_eq!(events.running(), 0);
+}
+
+#[tokio::test]
+async fn fake_chat_stream_events_cancel_test() {
+  let words = ["one", " two", " three", " four", " five", " six"];
+  let mut slow_stream = answer_stream(&words);
+  slow_stream["delay_ms"] = json!(50);
+  let scenario = FakeScenario::new().with_replies("stream_answer_v2", vec![slow_stream]);
+  let harness = TestPluginHarness::unstarted(scenario);
+  let events = start_events(&harness).await;
+
+  let sink = Arc::new(RecordingSink::default());
+  let id = events.start_chat_stream(ChatStreamRequest::new("chat", "count"), sink.clone());
+  assert_eq!(events.running(), 1);
+  while sink.events().is_empty() {
+    tokio::time::sleep(Duration::from_millis(5)).await;
+  }
+  assert!(events.cancel(id));
+  sink.wait_until_ended().await;
+  assert_eq!(events.running(), 0);
+
+  // Nothing arrives after `on_done`, although the plugin had more to send.
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  let recorded = sink.events();
+  assert_eq!(recorded.last(), Some(&SinkEvent::Done(id)));
+  assert_eq!(
+    recorded
+      .iter()
+      .filter(|event| matches!(event, SinkEvent::Done(_)))
+      .count(),
+    1
+  );
+  assert!(recorded.len() < words.len(), "{:?}", recorded);
+  assert_eq!(
+    recorded[0],
+    SinkEvent::Chunk(id, StreamFrame::Answer("one".to_string()))
+  );
+}
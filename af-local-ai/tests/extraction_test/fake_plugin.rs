@@ -0,0 +1,74 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::extraction::{FieldSpec, FieldType as ExtractFieldType};
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn fake_extract_fields_test() {
+  let reply = answer_stream(&[
+    "```json\n{\"title\": \"The Left Hand of Darkness\", \"rating\": \"4.5/5\", ",
+    "\"finished\": \"March 5, 2024\", \"status\": \"FINISHED\", \"lent\": \"no\", ",
+    "\"pages\": \"a lot\"}\n```",
+  ]);
+  let scenario = FakeScenario::new().with_replies("complete_text_v2", vec![reply]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let fields = vec![
+    FieldSpec::new("title", ExtractFieldType::Text),
+    FieldSpec::new("rating", ExtractFieldType::Number).with_description("Out of 5"),
+    FieldSpec::new("finished", ExtractFieldType::Date),
+    FieldSpec::new(
+      "status",
+      ExtractFieldType::SingleSelect {
+        options: vec!["Reading".to_string(), "Finished".to_string()],
+      },
+    ),
+    FieldSpec::new("lent", ExtractFieldType::Checkbox),
+    FieldSpec::new("pages", ExtractFieldType::Number),
+    FieldSpec::new("author", ExtractFieldType::Text),
+  ];
+  let text =
+    "I finished The Left Hand of Darkness on March 5th 2024 and would rate it 4.5 out of 5.";
+  let values = harness
+    .ollama_plugin
+    .extract_fields(text, fields, None)
+    .await
+    .unwrap();
+  assert_eq!(values["title"], json!("The Left Hand of Darkness"));
+  assert_eq!(values["rating"], json!(4.5));
+  assert_eq!(values["finished"], json!("2024-03-05"));
+  assert_eq!(values["status"], json!("Finished"));
+  assert_eq!(values["lent"], json!(false));
+  // Values that can't be converted and missing fields are null.
+  assert_eq!(values["pages"], Value::Null);
+  assert_eq!(values["author"], Value::Null);
+
+  let request = harness
+    .handled_requests()
+    .into_iter()
+    .rev()
+    .find(|request| request["method"] == "complete_text_v2")
+    .unwrap();
+  let params = &request["params"];
+  assert_eq!(params["completion_type"], 8);
+  assert!(
+    params["text"].as_str().unwrap().ends_with(text),
+    "{}",
+    params
+  );
+  assert_eq!(
+    params["format"]["properties"]["rating"]["type"],
+    json!(["number", "null"])
+  );
+
+  // Replies without a JSON object fail.
+  let scenario =
+    FakeScenario::new().with_replies("complete_text_v2", vec![answer_stream(&["I can't tell."])]);
+  harness.restart_with(scenario).await;
+  let fields = vec![FieldSpec::new("title", ExtractFieldType::Text)];
+  let err = harness
+    .ollama_plugin
+    .extract_fields(text, fields, None)
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::InvalidResponse), "{:?}", err);
+}
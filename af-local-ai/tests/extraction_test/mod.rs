@@ -0,0 +1,91 @@
+use af_local_ai::extraction::{
+  coerce_value, extraction_schema, parse_date, parse_extraction_reply, FieldSpec, FieldType,
+};
+use serde_json::json;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn parse_date_test() {
+  let date = |text: &str| parse_date(text);
+  assert_eq!(date("2024-03-05").as_deref(), Some("2024-03-05"));
+  assert_eq!(date("2024-03-05T10:30:00Z").as_deref(), Some("2024-03-05"));
+  assert_eq!(date("2024/3/5").as_deref(), Some("2024-03-05"));
+  assert_eq!(date("03/05/2024").as_deref(), Some("2024-03-05"));
+  // The first number can't be a month, so it is the day.
+  assert_eq!(date("25.12.2023").as_deref(), Some("2023-12-25"));
+  assert_eq!(date("March 5, 2024").as_deref(), Some("2024-03-05"));
+  assert_eq!(date("5th Mar 2024").as_deref(), Some("2024-03-05"));
+  assert_eq!(date("29 February 2024").as_deref(), Some("2024-02-29"));
+  assert_eq!(date("29 February 2023"), None);
+  assert_eq!(date("2024-13-01"), None);
+  assert_eq!(date("last spring"), None);
+  assert_eq!(date(""), None);
+}
+
+#[test]
+fn coerce_value_test() {
+  let number = FieldType::Number;
+  assert_eq!(coerce_value(&json!(4.5), &number), Some(json!(4.5)));
+  assert_eq!(coerce_value(&json!("4.5/5"), &number), Some(json!(4.5)));
+  assert_eq!(coerce_value(&json!("$1,200"), &number), Some(json!(1200)));
+  assert_eq!(coerce_value(&json!("-3 degrees"), &number), Some(json!(-3)));
+  assert_eq!(coerce_value(&json!("many"), &number), None);
+
+  let status = FieldType::SingleSelect {
+    options: vec!["To Read".to_string(), "Finished".to_string()],
+  };
+  assert_eq!(
+    coerce_value(&json!(" finished "), &status),
+    Some(json!("Finished"))
+  );
+  assert_eq!(coerce_value(&json!("Abandoned"), &status), None);
+
+  let checkbox = FieldType::Checkbox;
+  assert_eq!(coerce_value(&json!("Yes"), &checkbox), Some(json!(true)));
+  assert_eq!(coerce_value(&json!(0), &checkbox), Some(json!(false)));
+  assert_eq!(coerce_value(&json!("maybe"), &checkbox), None);
+
+  assert_eq!(
+    coerce_value(&json!(42), &FieldType::Text),
+    Some(json!("42"))
+  );
+  assert_eq!(coerce_value(&json!("  "), &FieldType::Text), None);
+  assert_eq!(coerce_value(&json!(null), &FieldType::Text), None);
+}
+
+#[test]
+fn extraction_schema_test() {
+  let fields = vec![
+    FieldSpec::new("title", FieldType::Text).with_description("Title of the book"),
+    FieldSpec::new(
+      "status",
+      FieldType::SingleSelect {
+        options: vec!["To Read".to_string()],
+      },
+    ),
+  ];
+  assert_eq!(
+    extraction_schema(&fields),
+    json!({
+      "type": "object",
+      "properties": {
+        "title": { "type": ["string", "null"], "description": "Title of the book" },
+        "status": { "enum": ["To Read", null] },
+      },
+      "required": ["title", "status"],
+    })
+  );
+}
+
+#[test]
+fn parse_extraction_reply_test() {
+  let reply = "Here you go:\n```json\n{\"title\": \"Dune\"}\n```";
+  assert_eq!(
+    parse_extraction_reply(reply).unwrap().get("title"),
+    Some(&json!("Dune"))
+  );
+  assert!(parse_extraction_reply("no idea").is_none());
+  assert!(parse_extraction_reply("} {").is_none());
+}
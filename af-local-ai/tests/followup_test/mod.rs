@@ -0,0 +1,59 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_completion_stream;
+use af_local_ai::ai_ops::CompleteTextType;
+use af_local_ai::followup::MAX_FOLLOWUP_PROMPT_CHARS;
+
+#[tokio::test]
+async fn fake_complete_text_followup_test() {
+  // Without `complete_text_followup`, the follow-up is sent as a custom completion.
+  let scenario =
+    FakeScenario::new().with_replies("complete_text_v2", vec![answer_stream(&["Short."])]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let previous_output = format!("HEAD {} TAIL", "long ".repeat(5_000));
+  let stream = harness
+    .ollama_plugin
+    .complete_text_followup(&previous_output, "Make it one sentence", "Some text", None)
+    .await
+    .unwrap();
+  assert_eq!(collect_completion_stream(stream).await.0, "Short.");
+  let params = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "complete_text_v2")
+    .unwrap()["params"]
+    .clone();
+  assert_eq!(params["completion_type"], CompleteTextType::Custom as u8);
+  let prompt = params["text"].as_str().unwrap();
+  for section in [
+    "### ORIGINAL TEXT\nSome text",
+    "### NEW INSTRUCTION\nMake it one sentence",
+    "### PREVIOUS OUTPUT\nHEAD",
+    "[...]",
+    "TAIL",
+  ] {
+    assert!(prompt.contains(section), "{}", section);
+  }
+  assert!(prompt.len() <= MAX_FOLLOWUP_PROMPT_CHARS + 500);
+
+  let scenario = FakeScenario::new().with_replies(
+    "complete_text_followup",
+    vec![answer_stream(&["One", " sentence."])],
+  );
+  harness.restart_with(scenario).await;
+  let stream = harness
+    .ollama_plugin
+    .complete_text_followup("A first draft.", "Make it one sentence", "Some text", None)
+    .await
+    .unwrap();
+  assert_eq!(collect_completion_stream(stream).await.0, "One sentence.");
+  let params = harness
+    .handled_requests()
+    .into_iter()
+    .rev()
+    .find(|request| request["method"] == "complete_text_followup")
+    .unwrap()["params"]
+    .clone();
+  assert_eq!(params["previous_output"], "A first draft.");
+  assert_eq!(params["instruction"], "Make it one sentence");
+  assert_eq!(params["original_text"], "Some text");
+}
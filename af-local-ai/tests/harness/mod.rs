@@ -0,0 +1,201 @@
+use crate::util::setup_log;
+use af_local_ai::ollama_plugin::{LogLevel, OllamaAIPlugin, OllamaPluginConfig};
+use af_plugin::manager::PluginManager;
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// Replies of a fake plugin, see `src/bin/fake_plugin.rs` for the format.
+pub struct FakeScenario {
+  methods: Map<String, Value>,
+  init_delay_ms: u64,
+  shutdown_delay_ms: u64,
+  vector_store: bool,
+}
+
+impl Default for FakeScenario {
+  fn default() -> Self {
+    let mut methods = Map::new();
+    methods.insert(
+      "system_info".to_string(),
+      json!([{ "result": { "data": { "version": "fake" } } }]),
+    );
+    for method in [
+      "set_log_level",
+      "create_chat",
+      "close_chat",
+      "embed_text",
+      "embed_file",
+      "delete_documents",
+    ] {
+      methods.insert(method.to_string(), json!([{ "result": {} }]));
+    }
+    methods.insert(
+      "gen_embeddings".to_string(),
+      json!([{ "result": { "data": [[0.1, 0.2, 0.3]] } }]),
+    );
+    Self {
+      methods,
+      init_delay_ms: 0,
+      shutdown_delay_ms: 0,
+      vector_store: false,
+    }
+  }
+}
+
+impl FakeScenario {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Replaces the replies of `method`. The replies are sent in order and the last one repeats.
+  pub fn with_replies(mut self, method: &str, replies: Vec<Value>) -> Self {
+    self
+      .methods
+      .insert(method.to_string(), Value::Array(replies));
+    self
+  }
+
+  pub fn with_init_delay_ms(mut self, delay_ms: u64) -> Self {
+    self.init_delay_ms = delay_ms;
+    self
+  }
+
+  /// Makes the plugin wait `delay_ms` between acknowledging `shutdown` and exiting.
+  pub fn with_shutdown_delay_ms(mut self, delay_ms: u64) -> Self {
+    self.shutdown_delay_ms = delay_ms;
+    self
+  }
+
+  /// Answers `embed_text`, `delete_documents` and `similarity_search` from an in-memory vector
+  /// store, whose searches return the texts sharing a word with the query.
+  pub fn with_vector_store(mut self) -> Self {
+    self.vector_store = true;
+    self
+  }
+}
+
+/// A reply streaming each of `texts` as an answer frame of a v2 stream.
+pub fn answer_stream(texts: &[&str]) -> Value {
+  let frames = texts
+    .iter()
+    .map(|text| json!(json!({ "1": text }).to_string()))
+    .collect::<Vec<_>>();
+  json!({ "stream": frames })
+}
+
+/// Runs [OllamaAIPlugin] against the scripted fake plugin, so tests don't need Ollama or models.
+pub struct TestPluginHarness {
+  pub plugin_manager: Arc<PluginManager>,
+  pub ollama_plugin: OllamaAIPlugin,
+  exec_path: PathBuf,
+  request_log: PathBuf,
+  // Removed on drop, so it must outlive the plugin process.
+  _dir: TempDir,
+}
+
+impl TestPluginHarness {
+  pub async fn new(scenario: FakeScenario) -> Self {
+    let harness = Self::unstarted(scenario);
+    harness.start().await;
+    harness
+  }
+
+  /// Prepares the fake plugin without starting it, for tests that start it themselves.
+  pub fn unstarted(scenario: FakeScenario) -> Self {
+    setup_log();
+    let dir = tempfile::tempdir().unwrap();
+    let request_log = dir.path().join("requests.log");
+
+    // The fake plugin reads its scenario from next to its executable, so every harness runs its
+    // own link to the binary.
+    let fake_plugin = PathBuf::from(env!("CARGO_BIN_EXE_fake_plugin"));
+    let exec_path = dir.path().join(fake_plugin.file_name().unwrap());
+    if std::fs::hard_link(&fake_plugin, &exec_path).is_err() {
+      std::fs::copy(&fake_plugin, &exec_path).unwrap();
+    }
+    let plugin_manager = Arc::new(PluginManager::new());
+    let ollama_plugin = OllamaAIPlugin::new(plugin_manager.clone());
+    let harness = Self {
+      plugin_manager,
+      ollama_plugin,
+      exec_path,
+      request_log,
+      _dir: dir,
+    };
+    harness.write_scenario(scenario);
+    harness
+  }
+
+  fn write_scenario(&self, scenario: FakeScenario) {
+    let scenario = json!({
+      "request_log": self.request_log,
+      "init_delay_ms": scenario.init_delay_ms,
+      "shutdown_delay_ms": scenario.shutdown_delay_ms,
+      "methods": scenario.methods,
+      "vector_store": scenario.vector_store,
+    });
+    std::fs::write(
+      self.exec_path.with_file_name("fake_plugin_scenario.json"),
+      serde_json::to_vec_pretty(&scenario).unwrap(),
+    )
+    .unwrap();
+  }
+
+  pub fn config(&self) -> OllamaPluginConfig {
+    let mut config = OllamaPluginConfig::new(
+      self.exec_path.clone(),
+      String::new(),
+      "fake-chat-model".to_string(),
+      "fake-embedding-model".to_string(),
+      None,
+    )
+    .unwrap();
+    config.set_log_level(LogLevel::Debug);
+    // Scenarios that are silent on purpose would be pinged, see `with_keep_alive_interval`.
+    config.with_keep_alive_interval(None)
+  }
+
+  /// Starts the fake plugin, replacing the running one if any.
+  pub async fn start(&self) {
+    self.ollama_plugin.init_plugin(self.config()).await.unwrap();
+  }
+
+  /// Restarts the fake plugin with a new scenario, e.g. after it disconnected.
+  pub async fn restart_with(&self, scenario: FakeScenario) {
+    self.write_scenario(scenario);
+    self.start().await;
+  }
+
+  /// The params of each `initialize` request received by the fake plugin.
+  pub fn initialize_params(&self) -> Vec<Value> {
+    std::fs::read_to_string(&self.request_log)
+      .unwrap_or_default()
+      .lines()
+      .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+      .filter(|request| request["method"] == "initialize")
+      .map(|request| request["params"].clone())
+      .collect()
+  }
+
+  /// The `handle` requests received by the fake plugin, in the order they arrived.
+  pub fn handled_requests(&self) -> Vec<Value> {
+    std::fs::read_to_string(&self.request_log)
+      .unwrap_or_default()
+      .lines()
+      .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+      .filter(|request| request["method"] == "handle")
+      .map(|request| request["params"].clone())
+      .collect()
+  }
+}
+
+/// The requests of `method` the fake plugin handled, in order.
+pub fn requests_of(harness: &TestPluginHarness, method: &str) -> Vec<Value> {
+  harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == method)
+    .collect()
+}
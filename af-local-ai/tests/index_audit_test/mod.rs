@@ -0,0 +1,93 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::index_audit::{AuditOperation, AuditStatus};
+use af_local_ai::usage::TimeRange;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+#[tokio::test]
+async fn fake_index_audit_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "embed_text",
+    vec![
+      json!({ "result": {} }),
+      json!({ "error": { "code": 1, "message": "model not loaded" } }),
+    ],
+  );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let audit_dir = tempfile::tempdir().unwrap();
+  let audit_log = audit_dir.path().join("index_audit.jsonl");
+  let config = harness
+    .config()
+    .with_index_audit_log(audit_log.clone())
+    .with_index_audit_max_bytes(200);
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let start = SystemTime::now();
+  let metadata = |object_id: &str| {
+    HashMap::from([
+      ("chat_id".to_string(), json!("chat")),
+      ("object_id".to_string(), json!(object_id)),
+      ("source_id".to_string(), json!("bananas")),
+    ])
+  };
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are yellow", metadata("doc-1"))
+    .await
+    .unwrap();
+  assert!(harness
+    .ollama_plugin
+    .embed_text("Bananas are green", metadata("doc-2"))
+    .await
+    .is_err());
+  harness
+    .ollama_plugin
+    .remove_chat_attachment("chat", "bananas")
+    .await
+    .unwrap();
+
+  let records = harness
+    .ollama_plugin
+    .read_index_audit(TimeRange::new(start, SystemTime::now()))
+    .await
+    .unwrap();
+  assert_eq!(records.len(), 3);
+  assert_eq!(records[0].operation, AuditOperation::EmbedText);
+  assert_eq!(records[0].object_id.as_deref(), Some("doc-1"));
+  assert_eq!(records[0].source_id.as_deref(), Some("bananas"));
+  assert_eq!(records[0].content_length, "Bananas are yellow".len() as u64);
+  assert_eq!(
+    records[0].content_sha256.as_deref(),
+    Some("994f84321f1b5f829b997605a47ebcbfd9140652a2f83cb0e516898dd214e74d")
+  );
+  assert_eq!(records[0].status, AuditStatus::Success);
+  assert_eq!(records[1].object_id.as_deref(), Some("doc-2"));
+  assert_eq!(records[1].status, AuditStatus::Failed);
+  assert_eq!(records[2].operation, AuditOperation::Delete);
+  assert_eq!(records[2].source_id.as_deref(), Some("bananas"));
+  assert_eq!(records[2].content_length, 0);
+  assert_eq!(records[2].content_sha256, None);
+  assert_eq!(records[2].status, AuditStatus::Success);
+
+  // The log holds hashes only, and was rotated past 200 bytes without losing records.
+  let mut files = std::fs::read_dir(audit_dir.path())
+    .unwrap()
+    .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+    .collect::<Vec<_>>();
+  files.sort();
+  assert!(files.len() > 1, "{:?}", files);
+  for file in files {
+    let content = std::fs::read_to_string(audit_dir.path().join(file)).unwrap();
+    assert!(!content.contains("Bananas are"));
+  }
+  let before = harness
+    .ollama_plugin
+    .read_index_audit(TimeRange::new(
+      SystemTime::UNIX_EPOCH,
+      start - Duration::from_secs(60),
+    ))
+    .await
+    .unwrap();
+  assert!(before.is_empty());
+}
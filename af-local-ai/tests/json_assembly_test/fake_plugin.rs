@@ -0,0 +1,73 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::json_assembly::JsonFrame;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_stream_question_assemble_json_test() {
+  let schema = json!({
+    "type": "object",
+    "properties": { "fruit": { "type": "string" }, "count": { "type": "integer" } },
+    "required": ["fruit", "count"],
+  });
+  let scenario = FakeScenario::new().with_replies(
+    "stream_answer_v2",
+    vec![
+      answer_stream(&["{\"fruit\": \"ban", "ana\", \"count\"", ": 3}"]),
+      answer_stream(&["{\"fruit\": \"banana\", \"count\": \"three\"}"]),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let options = StreamOptions::default().with_assemble_json(true);
+
+  // Without a schema there is nothing to assemble the answer into.
+  let err = harness
+    .ollama_plugin
+    .stream_question_with_options("chat", "how many?", None, json!({}), options.clone())
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::InvalidStreamOptions(_)));
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question_with_options(
+      "chat",
+      "how many bananas?",
+      Some(schema.clone()),
+      json!({}),
+      options.clone(),
+    )
+    .await
+    .unwrap();
+  let frames = stream
+    .filter_map(|frame| JsonFrame::from_frame(&frame.unwrap()))
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(
+    frames,
+    vec![
+      JsonFrame::PartialJson(json!({ "fruit": "banana" })),
+      JsonFrame::PartialJson(json!({ "fruit": "banana", "count": 3 })),
+      JsonFrame::FinalJson(json!({ "fruit": "banana", "count": 3 })),
+    ]
+  );
+
+  let mut stream = harness
+    .ollama_plugin
+    .stream_question_with_options("chat", "how many pears?", Some(schema), json!({}), options)
+    .await
+    .unwrap();
+  let mut last = None;
+  while let Some(frame) = stream.next().await {
+    last = Some(frame);
+  }
+  match last.unwrap() {
+    Err(PluginError::SchemaValidationFailed { reason, raw }) => {
+      assert_eq!(reason, "$.count: expected integer, found string");
+      assert_eq!(raw, "{\"fruit\": \"banana\", \"count\": \"three\"}");
+    },
+    frame => panic!("unexpected frame: {:?}", frame),
+  }
+}
@@ -0,0 +1,193 @@
+use af_local_ai::json_assembly::{validate_schema, JsonAssembler, JsonFrame};
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+/// Feeds `fragments` one at a time, returning the objects emitted after each of them.
+fn feed(assembler: &mut JsonAssembler, fragments: &[&str]) -> Vec<Option<Value>> {
+  fragments
+    .iter()
+    .map(|fragment| assembler.push(fragment))
+    .collect()
+}
+
+fn table_schema() -> Value {
+  json!({
+    "type": "object",
+    "properties": {
+      "title": { "type": "string" },
+      "rows": {
+        "type": "array",
+        "items": {
+          "type": "object",
+          "properties": { "name": { "type": "string" }, "count": { "type": "integer" } },
+          "required": ["name", "count"],
+        },
+      },
+      "done": { "type": "boolean" },
+    },
+    "required": ["title", "rows"],
+    "additionalProperties": false,
+  })
+}
+
+#[test]
+fn json_assembler_field_split_across_frames_test() {
+  let mut assembler = JsonAssembler::default();
+  let emitted = feed(
+    &mut assembler,
+    &[
+      "{\"ti",
+      "tle\": \"Fru",
+      "its\"",
+      ", \"cou",
+      "nt\": 1",
+      "2",
+      "}",
+    ],
+  );
+  assert_eq!(
+    emitted,
+    vec![
+      None,
+      None,
+      None,
+      Some(json!({ "title": "Fruits" })),
+      None,
+      None,
+      Some(json!({ "title": "Fruits", "count": 12 })),
+    ]
+  );
+  assert_eq!(
+    assembler.finish(&json!({})).unwrap(),
+    json!({ "title": "Fruits", "count": 12 })
+  );
+}
+
+#[test]
+fn json_assembler_escaped_quotes_test() {
+  let mut assembler = JsonAssembler::default();
+  // Quotes, commas and braces inside a string don't complete the field.
+  let emitted = feed(
+    &mut assembler,
+    &[
+      r#"{"quote": "She said \"hi, "#,
+      r#"{there}\"""#,
+      // An escape split across frames.
+      r#", "path": "C:\"#,
+      r#"\", "#,
+      r#""n": 1}"#,
+    ],
+  );
+  assert_eq!(
+    emitted,
+    vec![
+      None,
+      None,
+      Some(json!({ "quote": "She said \"hi, {there}\"" })),
+      Some(json!({ "quote": "She said \"hi, {there}\"", "path": "C:\\" })),
+      Some(json!({ "quote": "She said \"hi, {there}\"", "path": "C:\\", "n": 1 })),
+    ]
+  );
+}
+
+#[test]
+fn json_assembler_nested_objects_test() {
+  let mut assembler = JsonAssembler::default();
+  let emitted = feed(
+    &mut assembler,
+    &[
+      "```json\n{\"title\": \"Stock\",",
+      " \"rows\": [{\"name\": \"apple\", \"count\": 3},",
+      " {\"name\": \"pear\", \"count\": 5}]",
+      ", \"done\"",
+      ": true}\n```",
+    ],
+  );
+  assert_eq!(
+    emitted,
+    vec![
+      Some(json!({ "title": "Stock" })),
+      None,
+      None,
+      Some(json!({
+        "title": "Stock",
+        "rows": [{ "name": "apple", "count": 3 }, { "name": "pear", "count": 5 }],
+      })),
+      Some(json!({
+        "title": "Stock",
+        "rows": [{ "name": "apple", "count": 3 }, { "name": "pear", "count": 5 }],
+        "done": true,
+      })),
+    ]
+  );
+  let object = assembler.finish(&table_schema()).unwrap();
+  assert_eq!(object["done"], json!(true));
+}
+
+#[test]
+fn json_assembler_final_validation_test() {
+  let mut assembler = JsonAssembler::default();
+  assembler.push(r#"{"title": "Stock", "rows": [{"name": "apple", "count": "3"}]}"#);
+  let err = assembler.finish(&table_schema()).unwrap_err();
+  match err {
+    PluginError::SchemaValidationFailed { reason, raw } => {
+      assert_eq!(reason, "$.rows[0].count: expected integer, found string");
+      assert!(raw.starts_with(r#"{"title": "Stock""#));
+    },
+    err => panic!("unexpected error: {:?}", err),
+  }
+
+  // An object that never closes is reported with the text received.
+  let mut assembler = JsonAssembler::default();
+  assembler.push(r#"{"title": "Sto"#);
+  let err = assembler.finish(&table_schema()).unwrap_err();
+  assert!(matches!(
+    err,
+    PluginError::SchemaValidationFailed { raw, .. } if raw == r#"{"title": "Sto"#
+  ));
+
+  let assembler = JsonAssembler::default();
+  assert!(matches!(
+    assembler.finish(&table_schema()),
+    Err(PluginError::SchemaValidationFailed { .. })
+  ));
+}
+
+#[test]
+fn validate_schema_test() {
+  let schema = table_schema();
+  assert!(validate_schema(&json!({ "title": "a", "rows": [] }), &schema).is_ok());
+  assert_eq!(
+    validate_schema(&json!({ "rows": [] }), &schema).unwrap_err(),
+    "$: missing required field \"title\""
+  );
+  assert_eq!(
+    validate_schema(&json!({ "title": "a", "rows": [], "extra": 1 }), &schema).unwrap_err(),
+    "$.extra: field is not allowed"
+  );
+  let schema = json!({ "enum": ["low", "high", null] });
+  assert!(validate_schema(&json!(null), &schema).is_ok());
+  assert!(validate_schema(&json!("medium"), &schema).is_err());
+  let schema = json!({ "type": ["number", "null"] });
+  assert!(validate_schema(&json!(1.5), &schema).is_ok());
+  assert!(validate_schema(&json!("1.5"), &schema).is_err());
+}
+
+#[test]
+fn json_frame_test() {
+  assert_eq!(
+    JsonFrame::from_frame(&json!({ "partial_json": { "a": 1 } })),
+    Some(JsonFrame::PartialJson(json!({ "a": 1 })))
+  );
+  assert_eq!(
+    JsonFrame::from_frame(&json!({ "final_json": { "a": 1 } })),
+    Some(JsonFrame::FinalJson(json!({ "a": 1 })))
+  );
+  assert_eq!(
+    JsonFrame::from_frame(&json!({ "0": { "keep_alive": true } })),
+    None
+  );
+}
@@ -0,0 +1,104 @@
+#[cfg(unix)]
+use crate::util::write_fake_plugin;
+use af_local_ai::ollama_plugin::{LogLevel, OllamaPluginConfig};
+use af_plugin::error::PluginError;
+use std::path::PathBuf;
+
+#[test]
+fn parse_log_level_test() {
+  assert_eq!("debug".parse::<LogLevel>().unwrap(), LogLevel::Debug);
+  assert_eq!(" WARN ".parse::<LogLevel>().unwrap(), LogLevel::Warn);
+  assert_eq!("warning".parse::<LogLevel>().unwrap(), LogLevel::Warn);
+  assert_eq!(LogLevel::Trace.to_string(), "trace");
+  assert!(matches!(
+    "verbose".parse::<LogLevel>(),
+    Err(PluginError::InvalidLogLevel(_))
+  ));
+}
+
+#[test]
+fn config_log_level_from_string_test() {
+  let mut config = OllamaPluginConfig::new(
+    PathBuf::from("plugin"),
+    String::new(),
+    "chat".to_string(),
+    "embedding".to_string(),
+    None,
+  )
+  .unwrap();
+  // Callers still passing the level as a string keep compiling.
+  config.set_log_level("debug".to_string());
+  assert_eq!(config.log_level, LogLevel::Debug);
+  config.set_log_level("Trace");
+  assert_eq!(config.log_level, LogLevel::Trace);
+  config.set_log_level("verbose");
+  assert_eq!(config.log_level, LogLevel::Trace);
+  config.set_log_level(LogLevel::Error);
+  assert_eq!(config.log_level, LogLevel::Error);
+}
+
+#[cfg(unix)]
+fn log_level_requests(log: &std::path::Path) -> Vec<serde_json::Value> {
+  std::fs::read_to_string(log)
+    .unwrap_or_default()
+    .lines()
+    .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+    .filter(|request| request["params"]["method"] == "set_log_level")
+    .collect()
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn set_log_level_before_plugin_is_ready_test() {
+  use af_local_ai::ollama_plugin::{OllamaAIPlugin, OllamaPluginConfig};
+  use af_plugin::manager::PluginManager;
+  use serde_json::json;
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  let dir = tempfile::tempdir().unwrap();
+  let log = dir.path().join("requests.log");
+  let script = write_fake_plugin(dir.path(), &log);
+
+  let plugin_manager = Arc::new(PluginManager::new());
+  let plugin = OllamaAIPlugin::new(plugin_manager.clone());
+  // Queued: the plugin hasn't been started yet.
+  plugin.set_plugin_log_level(LogLevel::Debug).await.unwrap();
+  assert!(log_level_requests(&log).is_empty());
+
+  let config = OllamaPluginConfig::new(
+    script,
+    String::new(),
+    "chat-model".to_string(),
+    "embedding-model".to_string(),
+    None,
+  )
+  .unwrap();
+  plugin.init_plugin(config).await.unwrap();
+
+  let mut requests = vec![];
+  for _ in 0..50 {
+    requests = log_level_requests(&log);
+    if !requests.is_empty() {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+  assert_eq!(requests.len(), 1);
+  assert_eq!(requests[0]["method"], "handle");
+  assert_eq!(
+    requests[0]["params"],
+    json!({ "method": "set_log_level", "params": { "level": "debug" } })
+  );
+
+  // Setting the level the plugin already uses sends nothing.
+  plugin.set_plugin_log_level(LogLevel::Debug).await.unwrap();
+  assert_eq!(log_level_requests(&log).len(), 1);
+
+  plugin.set_plugin_log_level(LogLevel::Trace).await.unwrap();
+  let requests = log_level_requests(&log);
+  assert_eq!(requests.len(), 2);
+  assert_eq!(requests[1]["params"]["params"]["level"], "trace");
+
+  plugin_manager.shutdown_all(Duration::from_secs(1)).await;
+}
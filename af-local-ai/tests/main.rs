@@ -1,3 +1,79 @@
+#[cfg(feature = "fake-plugin-tests")]
+pub mod attachment_test;
+pub mod auth_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod blocking_test;
+pub mod chat_budget_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod chat_list_test;
 pub mod chat_test;
+pub mod chunking_test;
+// Written against the deprecated API of appflowy-local-ai on purpose.
+#[cfg(all(feature = "compat", feature = "fake-plugin-tests"))]
+#[allow(deprecated)]
+pub mod compat_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod database_query_test;
+pub mod diff_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod duplicate_test;
 pub mod embedding_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod events_test;
+pub mod extraction_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod followup_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod harness;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod index_audit_test;
+pub mod json_assembly_test;
+pub mod log_level_test;
+#[cfg(feature = "test-support")]
+pub mod mock_test;
+pub mod model_capabilities_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod model_routing_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod ollama_plugin_test;
+pub mod outbound_filter_test;
+pub mod plugin_version_test;
+pub mod post_process_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod prepared_request_test;
+pub mod profile_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod prompt_template_test;
+pub mod rate_limit_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod related_question_test;
+#[cfg(all(feature = "fake-plugin-tests", feature = "replay"))]
+pub mod replay_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod response_cache_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod retry_test;
+pub mod scheduler_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod search_test;
+pub mod semantic_search_test;
+pub mod similarity_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod slow_request_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod store_meta_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod stream_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod summary_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod translate_test;
+#[cfg(all(feature = "fake-plugin-tests", feature = "usage-tracking"))]
+pub mod usage_test;
 pub mod util;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod vector_store_test;
+#[cfg(feature = "fake-plugin-tests")]
+pub mod warm_up_test;
+#[cfg(feature = "http")]
+pub mod web_page_test;
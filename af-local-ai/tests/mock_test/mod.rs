@@ -0,0 +1,85 @@
+use crate::util::collect_json_stream;
+use af_local_ai::local_ai::LocalAIChat;
+use af_local_ai::mock::{MockLocalAI, MockQuestion};
+use af_plugin::error::PluginError;
+use anyhow::anyhow;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A chat flow as an application would write it, knowing only the trait.
+struct ChatService {
+  ai: Arc<dyn LocalAIChat>,
+}
+
+impl ChatService {
+  async fn ask(&self, chat_id: &str, question: &str) -> Result<(String, Vec<String>), PluginError> {
+    let stream = self
+      .ai
+      .stream_question(chat_id, question, None, json!({}))
+      .await?;
+    let answer = collect_json_stream(stream).await;
+    let related = self.ai.get_related_question(chat_id).await?;
+    Ok((answer, related))
+  }
+}
+
+#[tokio::test]
+async fn mock_chat_flow_test() {
+  let mock = Arc::new(
+    MockLocalAI::new()
+      .with_answer(&["Bananas ", "are ", "yellow."])
+      .with_answer(&["They grow in the tropics."])
+      .with_related_questions(&["Are bananas berries?"])
+      .with_search_results(&["Bananas are rich in potassium."]),
+  );
+  let service = ChatService { ai: mock.clone() };
+
+  // Questions need a chat, like with the plugin.
+  assert!(service.ask("fruits", "What color?").await.is_err());
+
+  service.ai.create_chat("fruits").await.unwrap();
+  service
+    .ai
+    .embed_text("Bananas are rich in potassium.", HashMap::new())
+    .await
+    .unwrap();
+  let (answer, related) = service.ask("fruits", "What color?").await.unwrap();
+  assert_eq!(answer, "Bananas are yellow.");
+  assert_eq!(related, vec!["Are bananas berries?".to_string()]);
+  let answer = service
+    .ai
+    .ask_question("fruits", "Where do they grow?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "They grow in the tropics.");
+  let results = service
+    .ai
+    .similarity_search("potassium", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(results, vec!["Bananas are rich in potassium.".to_string()]);
+
+  mock.fail_next(PluginError::Internal(anyhow!("plugin crashed")));
+  assert!(service.ai.ask_question("fruits", "Again?").await.is_err());
+
+  service.ai.close_chat("fruits", false).await.unwrap();
+  assert!(mock.chats().is_empty());
+  assert_eq!(
+    mock.embedded_texts(),
+    vec!["Bananas are rich in potassium."]
+  );
+  assert_eq!(
+    mock.questions(),
+    vec![
+      MockQuestion {
+        chat_id: "fruits".to_string(),
+        message: "What color?".to_string(),
+      },
+      MockQuestion {
+        chat_id: "fruits".to_string(),
+        message: "Where do they grow?".to_string(),
+      },
+    ]
+  );
+}
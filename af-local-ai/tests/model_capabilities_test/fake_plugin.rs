@@ -0,0 +1,125 @@
+use crate::harness::{answer_stream, requests_of, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::ai_ops::CompleteTextType;
+use af_plugin::error::PluginError;
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_model_capabilities_probe_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "model_capabilities",
+      vec![json!({ "result": { "data": {
+        "supports_json_format": false,
+        "supports_tools": true,
+        "max_context": 4096,
+      } } })],
+    )
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Banana"])])
+    .with_replies("complete_text_v2", vec![answer_stream(&["Done"])]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let plugin = &harness.ollama_plugin;
+  assert_eq!(plugin.model_capabilities("fake-chat-model"), None);
+
+  let capabilities = plugin
+    .probe_model_capabilities("fake-chat-model")
+    .await
+    .unwrap();
+  assert!(!capabilities.supports_json_format);
+  assert!(capabilities.supports_tools);
+  assert_eq!(capabilities.max_context, Some(4096));
+  // Probed once per model, then cached.
+  assert_eq!(
+    plugin
+      .probe_model_capabilities("fake-chat-model")
+      .await
+      .unwrap(),
+    capabilities
+  );
+  let probes = requests_of(&harness, "model_capabilities");
+  assert_eq!(probes.len(), 1);
+  assert_eq!(probes[0]["params"]["model"], "fake-chat-model");
+  assert_eq!(
+    plugin.model_capabilities("fake-chat-model"),
+    Some(capabilities)
+  );
+
+  // A format the model can't follow is rejected without a request.
+  let schema = json!({ "type": "object", "properties": { "name": { "type": "string" } } });
+  let err = plugin
+    .stream_question("fruits", "Name a fruit", Some(schema.clone()), json!({}))
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::UnsupportedByModel { ref model, .. } if model == "fake-chat-model"),
+    "{:?}",
+    err
+  );
+  let err = plugin
+    .complete_text_v2(
+      "banana",
+      CompleteTextType::ImproveWriting as u8,
+      Some(schema),
+      None,
+    )
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::UnsupportedByModel { .. }),
+    "{:?}",
+    err
+  );
+  assert!(requests_of(&harness, "stream_answer_v2").is_empty());
+  assert!(requests_of(&harness, "complete_text_v2").is_empty());
+
+  // Requests without a format are still sent.
+  let stream = plugin
+    .stream_question("fruits", "Name a fruit", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Banana");
+}
+
+#[tokio::test]
+async fn fake_model_capabilities_fallback_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "complete_text_v2",
+    vec![
+      json!({
+        "when": { "model_name": "llama3.1:8b" },
+        "error": { "code": 1, "message": "format is not supported" },
+      }),
+      answer_stream(&["{\"ok\": true}"]),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let plugin = &harness.ollama_plugin;
+
+  // The plugin can't tell, so the table says llama3.1 has tools, and the failed live probe
+  // says it can't follow a format.
+  let llama = plugin
+    .probe_model_capabilities("llama3.1:8b")
+    .await
+    .unwrap();
+  assert!(!llama.supports_json_format);
+  assert!(llama.supports_tools);
+  assert_eq!(llama.max_context, Some(131_072));
+  let probes = requests_of(&harness, "complete_text_v2");
+  assert_eq!(probes.len(), 1);
+  assert_eq!(probes[0]["params"]["model_name"], "llama3.1:8b");
+  assert_eq!(probes[0]["params"]["format"]["required"], json!(["ok"]));
+
+  // A model of an unknown family that passes the probe only follows formats.
+  let unknown = plugin
+    .probe_model_capabilities("fake-chat-model")
+    .await
+    .unwrap();
+  assert!(unknown.supports_json_format);
+  assert!(!unknown.supports_tools);
+  assert_eq!(unknown.max_context, None);
+
+  // The table already says tinyllama can't follow a format, so it isn't probed.
+  let tiny = plugin.probe_model_capabilities("tinyllama").await.unwrap();
+  assert!(!tiny.supports_json_format);
+  assert_eq!(requests_of(&harness, "complete_text_v2").len(), 2);
+}
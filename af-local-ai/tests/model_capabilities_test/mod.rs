@@ -0,0 +1,40 @@
+use af_local_ai::model_capabilities::{heuristic_capabilities, model_family};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn model_family_test() {
+  assert_eq!(model_family("llama3.1"), "llama3.1");
+  assert_eq!(model_family("llama3.2:3b"), "llama3.2");
+  assert_eq!(model_family("library/Qwen2.5:7b-instruct"), "qwen2.5");
+  assert_eq!(model_family("hf.co/bartowski/gemma2:latest"), "gemma2");
+}
+
+#[test]
+fn heuristic_capabilities_test() {
+  let llama = heuristic_capabilities("llama3.1:8b").unwrap();
+  assert!(llama.supports_json_format);
+  assert!(llama.supports_tools);
+  assert!(!llama.supports_vision);
+  assert_eq!(llama.max_context, Some(131_072));
+
+  // The longest family wins: llama3.2-vision over llama3.2 and llama3.
+  let vision = heuristic_capabilities("llama3.2-vision:11b").unwrap();
+  assert!(vision.supports_vision);
+  assert!(!vision.supports_tools);
+
+  // Tags of a family share its capabilities.
+  assert_eq!(
+    heuristic_capabilities("mistral:7b-instruct-q4_0"),
+    heuristic_capabilities("mistral")
+  );
+  assert!(heuristic_capabilities("mistral-nemo").unwrap().max_context > Some(32_768));
+
+  assert!(
+    !heuristic_capabilities("tinyllama")
+      .unwrap()
+      .supports_json_format
+  );
+  assert_eq!(heuristic_capabilities("fake-chat-model"), None);
+}
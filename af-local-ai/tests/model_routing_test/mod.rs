@@ -0,0 +1,146 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::CompleteTextType;
+use af_local_ai::model_routing::{ModelRoutingTable, RequestKind as ModelRequestKind};
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn fake_model_routing_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "list_models",
+      vec![json!({ "result": { "data": ["fake-chat-model:latest", "tiny:latest", "writer:7b"] } })],
+    )
+    .with_replies("answer", vec![json!({ "result": { "data": "Yellow" } })])
+    .with_replies(
+      "database_summary",
+      vec![json!({ "result": { "data": "A yellow fruit" } })],
+    )
+    .with_replies(
+      "related_question",
+      vec![json!({ "result": { "data": [{ "content": "Where do bananas grow?" }] } })],
+    )
+    .with_replies("complete_text_v2", vec![answer_stream(&["Done"])]);
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let err = harness
+    .ollama_plugin
+    .set_model_routing(ModelRoutingTable::new().with_route(ModelRequestKind::ChatTitle, "huge"))
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::ModelNotFound(ref model) if model == "huge"),
+    "{:?}",
+    err
+  );
+  let table = ModelRoutingTable::new()
+    .with_route(ModelRequestKind::DatabaseSummary, "tiny")
+    .with_route(ModelRequestKind::RelatedQuestions, "tiny")
+    .with_route(
+      ModelRequestKind::Completion {
+        completion_type: CompleteTextType::ImproveWriting,
+      },
+      "writer:7b",
+    );
+  harness
+    .ollama_plugin
+    .set_model_routing(table.clone())
+    .await
+    .unwrap();
+  assert_eq!(harness.ollama_plugin.model_routing(), table);
+  assert_eq!(
+    harness
+      .ollama_plugin
+      .resolve_model(ModelRequestKind::DatabaseSummary)
+      .await,
+    "tiny"
+  );
+  assert_eq!(
+    harness
+      .ollama_plugin
+      .resolve_model(ModelRequestKind::Chat)
+      .await,
+    "fake-chat-model"
+  );
+
+  harness
+    .ollama_plugin
+    .ask_question("chat", "What color is a banana?")
+    .await
+    .unwrap();
+  harness
+    .ollama_plugin
+    .summary_database_row(HashMap::from([("name".to_string(), "banana".to_string())]))
+    .await
+    .unwrap();
+  harness
+    .ollama_plugin
+    .get_related_question("chat")
+    .await
+    .unwrap();
+  for completion_type in [
+    CompleteTextType::ImproveWriting,
+    CompleteTextType::SpellingAndGrammar,
+  ] {
+    harness
+      .ollama_plugin
+      .complete_text_v2_collect(
+        "a banana is yelow",
+        completion_type as u8,
+        None,
+        None,
+        StreamOptions::default(),
+      )
+      .await
+      .unwrap();
+  }
+
+  let model_names = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] != "list_models")
+    .map(|request| {
+      (
+        request["method"].as_str().unwrap().to_string(),
+        request["params"]["model_name"].clone(),
+      )
+    })
+    .collect::<Vec<_>>();
+  let model_name = |method: &str| {
+    model_names
+      .iter()
+      .filter(|(name, _)| name == method)
+      .map(|(_, model)| model.clone())
+      .collect::<Vec<_>>()
+  };
+  // Kinds without a route use the model of the plugin settings.
+  assert_eq!(model_name("answer"), vec![Value::Null]);
+  assert_eq!(model_name("database_summary"), vec![json!("tiny")]);
+  assert_eq!(model_name("related_question"), vec![json!("tiny")]);
+  assert_eq!(
+    model_name("complete_text_v2"),
+    vec![json!("writer:7b"), Value::Null]
+  );
+}
+
+#[tokio::test]
+async fn fake_model_routing_without_list_models_test() {
+  // The plugin can't list its models, so the table is set without being checked.
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  let table = ModelRoutingTable::new().with_route(ModelRequestKind::Chat, "anything");
+  harness
+    .ollama_plugin
+    .set_model_routing(table.clone())
+    .await
+    .unwrap();
+  assert_eq!(harness.ollama_plugin.model_routing(), table);
+  assert_eq!(
+    harness
+      .ollama_plugin
+      .resolve_model(ModelRequestKind::Chat)
+      .await,
+    "anything"
+  );
+}
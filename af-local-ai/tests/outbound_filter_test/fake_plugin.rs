@@ -0,0 +1,43 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::outbound_filter::{FilterDecision, PatternFilter, RequestKind};
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn fake_outbound_filter_test() {
+  let scenario = FakeScenario::new()
+    .with_replies("answer", vec![json!({ "result": { "data": "Noted" } })])
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Noted"])]);
+  let harness = TestPluginHarness::new(scenario).await;
+  harness
+    .ollama_plugin
+    .set_outbound_filter(Arc::new(PatternFilter::pii()));
+
+  harness
+    .ollama_plugin
+    .ask_question("chat", "Email jane.doe@example.com the notes")
+    .await
+    .unwrap();
+  let requests = harness.handled_requests();
+  let request = requests.last().unwrap();
+  assert_eq!(request["method"], "answer");
+  assert_eq!(request["params"]["content"], "Email [EMAIL] the notes");
+
+  harness
+    .ollama_plugin
+    .set_outbound_filter(Arc::new(|_: &str, kind: RequestKind| match kind {
+      RequestKind::Question => FilterDecision::Block("questions are disabled".to_string()),
+      _ => FilterDecision::Allow,
+    }));
+  let sent = harness.handled_requests().len();
+  let err = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::BlockedByPolicy(reason) if reason == "questions are disabled")
+  );
+  assert_eq!(harness.handled_requests().len(), sent);
+}
@@ -0,0 +1,41 @@
+use af_local_ai::outbound_filter::{FilterDecision, OutboundFilter, PatternFilter, RequestKind};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn pii_filter_test() {
+  let filter = PatternFilter::pii();
+  assert_eq!(
+    filter.filter(
+      "Write to jane.doe@example.com with key sk-abcdefghijklmnopqrstu",
+      RequestKind::Question
+    ),
+    FilterDecision::Redact("Write to [EMAIL] with key [API_KEY]".to_string())
+  );
+  assert_eq!(
+    filter.filter("Pay with 4111 1111 1111 1111", RequestKind::Completion),
+    FilterDecision::Redact("Pay with [CARD_NUMBER]".to_string())
+  );
+  // Long numbers that fail the card checksum are kept.
+  assert_eq!(
+    filter.filter("Order 1234567890123456 shipped", RequestKind::DatabaseRow),
+    FilterDecision::Allow
+  );
+}
+
+#[test]
+fn blocking_pattern_filter_test() {
+  let filter = PatternFilter::new()
+    .with_pattern("project code", r"PRJ-\d{4}", "[PROJECT]")
+    .unwrap()
+    .block_on_match();
+  assert_eq!(
+    filter.filter("Status of PRJ-1234?", RequestKind::Question),
+    FilterDecision::Block("text contains project code".to_string())
+  );
+  assert_eq!(
+    filter.filter("Status of the project?", RequestKind::Question),
+    FilterDecision::Allow
+  );
+}
@@ -0,0 +1,52 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_plugin::error::PluginError;
+use semver::Version;
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_plugin_outdated_test() {
+  let system_info =
+    |version: &str| json!({ "result": { "data": { "version": version, "protocol_version": 2 } } });
+  let scenario = FakeScenario::new().with_replies("system_info", vec![system_info("v0.2.1+abc")]);
+  let harness = TestPluginHarness::new(scenario).await;
+  assert_eq!(
+    harness.ollama_plugin.plugin_version().await,
+    Some(Version::parse("0.2.1+abc").unwrap())
+  );
+
+  // Plugins older than the minimum version are stopped.
+  let config = harness
+    .config()
+    .with_minimum_plugin_version(Version::new(0, 3, 0));
+  let err = harness.ollama_plugin.init_plugin(config).await.unwrap_err();
+  match err {
+    PluginError::PluginOutdated {
+      found,
+      minimum,
+      download_hint,
+    } => {
+      assert_eq!(found, "v0.2.1+abc");
+      assert_eq!(minimum, "0.3.0");
+      assert!(download_hint.contains("releases"), "{}", download_hint);
+    },
+    err => panic!("unexpected error: {:?}", err),
+  }
+  assert!(harness.ollama_plugin.get_ai_plugin().await.is_err());
+
+  // Unless explicitly allowed.
+  let config = harness
+    .config()
+    .with_minimum_plugin_version(Version::new(0, 3, 0))
+    .allow_outdated(true);
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  assert!(harness.ollama_plugin.get_ai_plugin().await.is_ok());
+
+  // Versions that are not semver are let through.
+  let scenario = FakeScenario::new().with_replies("system_info", vec![system_info("fake")]);
+  harness.restart_with(scenario).await;
+  let config = harness
+    .config()
+    .with_minimum_plugin_version(Version::new(9, 0, 0));
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  assert_eq!(harness.ollama_plugin.plugin_version().await, None);
+}
@@ -0,0 +1,35 @@
+use af_local_ai::plugin_version::{parse_plugin_version, DEFAULT_MINIMUM_PLUGIN_VERSION};
+use semver::Version;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn parse_plugin_version_test() {
+  let version = |text: &str| parse_plugin_version(text).map(|version| version.to_string());
+  assert_eq!(version("0.2.1").as_deref(), Some("0.2.1"));
+  assert_eq!(version("v0.2.1").as_deref(), Some("0.2.1"));
+  assert_eq!(version(" V1.0.0\n").as_deref(), Some("1.0.0"));
+  assert_eq!(version("0.2.1-beta.1").as_deref(), Some("0.2.1-beta.1"));
+  assert_eq!(version("0.2.1+build.7").as_deref(), Some("0.2.1+build.7"));
+  assert_eq!(version("v1.2").as_deref(), Some("1.2.0"));
+  assert_eq!(version("2").as_deref(), Some("2.0.0"));
+  assert_eq!(version("1.2-rc.1").as_deref(), Some("1.2.0-rc.1"));
+  assert_eq!(version("fake"), None);
+  assert_eq!(version(""), None);
+  assert_eq!(version("v"), None);
+  assert_eq!(version("1.2.3.4"), None);
+}
+
+#[test]
+fn plugin_version_precedence_test() {
+  let parse = |text: &str| parse_plugin_version(text).unwrap();
+  let minimum = Version::new(0, 2, 0);
+  // Build metadata doesn't make a version newer or older.
+  assert!(parse("0.2.0+abc").cmp_precedence(&minimum).is_eq());
+  assert!(parse("0.2.0-beta").cmp_precedence(&minimum).is_lt());
+  assert!(parse("v0.10.0").cmp_precedence(&minimum).is_gt());
+  assert!(parse("0.1.9")
+    .cmp_precedence(&DEFAULT_MINIMUM_PLUGIN_VERSION)
+    .is_gt());
+}
@@ -0,0 +1,70 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_completion_stream;
+use af_local_ai::ai_ops::CompleteTextType;
+use af_local_ai::post_process::PostProcessor;
+use af_plugin::core::stream::StreamOptions;
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_completion_post_process_test() {
+  let fenced = answer_stream(&[
+    "```markdown\n",
+    "He and I were going",
+    " to the store.",
+    " We bought apples.\n",
+    "```",
+  ]);
+  let scenario = FakeScenario::new()
+    .with_replies("complete_text_v2", vec![fenced])
+    .with_replies(
+      "answer",
+      vec![json!({ "result": { "data": "\n```\nStill here.\n```\n" } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let post_process = vec![
+    PostProcessor::StripCodeFences,
+    PostProcessor::TrimWhitespace,
+  ];
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2_with_options(
+      "Me and him was going to the store. We buyed apples.",
+      CompleteTextType::SpellingAndGrammar as u8,
+      None,
+      None,
+      StreamOptions::default().with_post_process(post_process.clone()),
+    )
+    .await
+    .unwrap();
+  let (answer, _) = collect_completion_stream(stream).await;
+  assert_eq!(
+    answer,
+    "He and I were going to the store. We bought apples."
+  );
+
+  // The stream ends with the last sentence that fits.
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2_with_options(
+      "Me and him was going to the store. We buyed apples.",
+      CompleteTextType::MakeShorter as u8,
+      None,
+      None,
+      StreamOptions::default().with_post_process(vec![
+        PostProcessor::StripCodeFences,
+        PostProcessor::EnforceMaxChars(40),
+      ]),
+    )
+    .await
+    .unwrap();
+  let (answer, _) = collect_completion_stream(stream).await;
+  assert_eq!(answer, "He and I were going to the store.");
+
+  let answer = harness
+    .ollama_plugin
+    .ask_question_post_processed("chat", "are you there?", &post_process)
+    .await
+    .unwrap();
+  assert_eq!(answer, "Still here.");
+}
@@ -0,0 +1,125 @@
+use af_local_ai::post_process::{post_process_text, PostProcessPipeline, PostProcessor};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+/// Streams `text` one char at a time, checking that it is processed like the whole text.
+fn process(text: &str, processors: &[PostProcessor]) -> String {
+  let mut pipeline = PostProcessPipeline::new(processors);
+  let mut streamed = String::new();
+  for c in text.chars() {
+    let processed = pipeline.push(&c.to_string());
+    streamed.push_str(&processed.text);
+    if processed.done {
+      break;
+    }
+  }
+  streamed.push_str(&pipeline.finish());
+  assert_eq!(streamed, post_process_text(text, processors), "{:?}", text);
+  streamed
+}
+
+#[test]
+fn strip_code_fences_test() {
+  let strip = [PostProcessor::StripCodeFences];
+  assert_eq!(
+    process("```markdown\nHello **world**\n```", &strip),
+    "Hello **world**"
+  );
+  assert_eq!(process("\n~~~\nHello\n~~~\n\n", &strip), "Hello");
+  // Only the fence wrapping the answer is removed.
+  assert_eq!(
+    process("Run `ls`:\n```sh\nls\n```", &strip),
+    "Run `ls`:\n```sh\nls\n```"
+  );
+  assert_eq!(
+    process(
+      "```markdown\n# Steps\n```rust\nfn main() {}\n```\nDone.\n```",
+      &strip
+    ),
+    "# Steps\n```rust\nfn main() {}\n```\nDone."
+  );
+  // A longer outer fence isn't closed by the inner one.
+  assert_eq!(
+    process("````\n```\ncode\n```\n````", &strip),
+    "```\ncode\n```"
+  );
+  // The answer was cut before its closing fence.
+  assert_eq!(process("```\nHello\n", &strip), "Hello\n");
+  assert_eq!(process("``not a fence``", &strip), "``not a fence``");
+  assert_eq!(process("```", &strip), "```");
+}
+
+#[test]
+fn enforce_max_chars_test() {
+  let max = |max| [PostProcessor::EnforceMaxChars(max)];
+  assert_eq!(
+    process("One sentence. Two sentences. Three.", &max(30)),
+    "One sentence. Two sentences."
+  );
+  assert_eq!(process("Short.", &max(30)), "Short.");
+  // Sentences of Chinese end with full-width punctuation.
+  assert_eq!(process("你好。我很好！谢谢你。", &max(8)), "你好。我很好！");
+  // A first sentence too long is cut at the last word that fits.
+  assert_eq!(
+    process("This sentence is much longer than allowed.", &max(20)),
+    "This sentence is"
+  );
+  // Emoji aren't split, the family is 7 chars.
+  let family = "👨‍👩‍👧‍👦";
+  assert_eq!(process(&format!("{0}{0}{0}", family), &max(10)), family);
+  assert_eq!(process("👍🏽👍🏽", &max(3)), "👍🏽");
+}
+
+#[test]
+fn enforce_max_chars_ends_stream_test() {
+  let mut pipeline = PostProcessPipeline::new(&[PostProcessor::EnforceMaxChars(12)]);
+  let processed = pipeline.push("First one. ");
+  assert_eq!(processed.text, "");
+  assert!(!processed.done);
+  let processed = pipeline.push("Second one is too long");
+  assert_eq!(processed.text, "First one.");
+  assert!(processed.done);
+  assert!(pipeline.push(" and more.").done);
+  assert_eq!(pipeline.finish(), "");
+}
+
+#[test]
+fn ensure_single_paragraph_test() {
+  let single = [PostProcessor::EnsureSingleParagraph];
+  assert_eq!(
+    process("First line\nsecond line.\n\n  Next paragraph.\n", &single),
+    "First line second line. Next paragraph."
+  );
+  assert_eq!(process("\r\n\r\nKeep  spaces\r\n", &single), "Keep  spaces");
+  assert_eq!(
+    process("第一段。\n\n第二段。", &single),
+    "第一段。 第二段。"
+  );
+}
+
+#[test]
+fn trim_whitespace_test() {
+  let trim = [PostProcessor::TrimWhitespace];
+  assert_eq!(process("  \n Hello,  world \n\n", &trim), "Hello,  world");
+  assert_eq!(process(" \u{3000}你好\u{3000}", &trim), "你好");
+  assert_eq!(process("   ", &trim), "");
+}
+
+#[test]
+fn pipeline_order_test() {
+  let processors = [
+    PostProcessor::StripCodeFences,
+    PostProcessor::EnsureSingleParagraph,
+    PostProcessor::EnforceMaxChars(24),
+    PostProcessor::TrimWhitespace,
+  ];
+  assert_eq!(
+    process(
+      "```text\nThe cat sat.\nIt purred. Then it slept all day.\n```",
+      &processors
+    ),
+    "The cat sat. It purred."
+  );
+  assert_eq!(post_process_text("  text  ", &[]), "  text  ");
+}
@@ -0,0 +1,387 @@
+use crate::harness::{answer_stream, requests_of, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::ai_ops::{
+  ChatSettings, ContextGate, RagOptions, MAX_RAG_TOP_K, STREAM_METADATA_KEY,
+};
+use af_local_ai::outbound_filter::PatternFilter;
+use af_local_ai::prepared_request::QuestionRequestOptions;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_chat_rag_options_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new()).await;
+  let last_params = |method: &str| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .rev()
+      .find(|request| request["method"] == method)
+      .unwrap()["params"]
+      .clone()
+  };
+  let settings = |rag: RagOptions| ChatSettings {
+    rag,
+    ..Default::default()
+  };
+
+  // Chats are created as they always were by default.
+  harness.ollama_plugin.create_chat("default").await.unwrap();
+  assert_eq!(
+    last_params("create_chat"),
+    json!({ "chat_id": "default", "top_k": 2 })
+  );
+
+  // Plugins of the first protocol only get `top_k`.
+  harness
+    .ollama_plugin
+    .create_chat_with_settings(
+      "old",
+      settings(RagOptions::new(8).with_score_threshold(0.5)),
+    )
+    .await
+    .unwrap();
+  assert_eq!(
+    last_params("create_chat"),
+    json!({ "chat_id": "old", "top_k": 8 })
+  );
+
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "system_info",
+      vec![json!({ "result": { "data": { "version": "fake", "protocol_version": 3 } } })],
+    )
+    .with_replies(
+      "stream_answer_v2",
+      vec![
+        answer_stream(&["a"]),
+        answer_stream(&["b"]),
+        answer_stream(&["c"]),
+      ],
+    );
+  harness.restart_with(scenario).await;
+
+  harness.ollama_plugin.create_chat("default").await.unwrap();
+  assert_eq!(
+    last_params("create_chat"),
+    json!({ "chat_id": "default", "top_k": 2 })
+  );
+  let stream = harness
+    .ollama_plugin
+    .stream_question("default", "question", None, json!({}))
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  assert!(last_params("stream_answer_v2").get("rag").is_none());
+
+  harness
+    .ollama_plugin
+    .create_chat_with_settings(
+      "tuned",
+      settings(RagOptions::new(8).with_score_threshold(0.5)),
+    )
+    .await
+    .unwrap();
+  assert_eq!(
+    last_params("create_chat"),
+    json!({ "chat_id": "tuned", "top_k": 8, "score_threshold": 0.5 })
+  );
+  let stream = harness
+    .ollama_plugin
+    .stream_question("tuned", "question", None, json!({}))
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  assert_eq!(
+    last_params("stream_answer_v2")["rag"],
+    json!({ "top_k": 8, "score_threshold": 0.5 })
+  );
+
+  // The options of the question win over those of the chat.
+  let stream = harness
+    .ollama_plugin
+    .stream_question_with_rag(
+      "tuned",
+      "question",
+      None,
+      json!({}),
+      StreamOptions::default(),
+      RagOptions::new(4).with_max_context_tokens(1000),
+    )
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  assert_eq!(
+    last_params("stream_answer_v2")["rag"],
+    json!({ "top_k": 4, "score_threshold": 0.5, "max_context_tokens": 1000 })
+  );
+
+  let err = harness
+    .ollama_plugin
+    .create_chat_with_settings("empty", settings(RagOptions::new(0)))
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::InvalidRagOptions(_)),
+    "{:?}",
+    err
+  );
+
+  harness
+    .ollama_plugin
+    .create_chat_with_settings("greedy", settings(RagOptions::new(500)))
+    .await
+    .unwrap();
+  assert_eq!(last_params("create_chat")["top_k"], MAX_RAG_TOP_K);
+}
+
+#[tokio::test]
+async fn fake_strict_context_gate_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "system_info",
+      vec![json!({ "result": { "data": { "version": "fake", "protocol_version": 5 } } })],
+    )
+    .with_replies(
+      "similarity_search",
+      vec![json!({ "result": { "data": [
+        { "content": "Bananas are yellow", "score": 0.12 },
+      ] } })],
+    )
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Nonsense"])]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let metadata = HashMap::from([("chat_id".to_string(), json!("fruits"))]);
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are yellow", metadata)
+    .await
+    .unwrap();
+  let settings = ChatSettings {
+    context_gate: ContextGate {
+      min_score: 0.5,
+      strict: true,
+    },
+    ..Default::default()
+  };
+  harness
+    .ollama_plugin
+    .update_chat_settings("fruits", settings)
+    .await;
+
+  let err = harness
+    .ollama_plugin
+    .stream_question_with_options(
+      "fruits",
+      "How do I renew my passport?",
+      None,
+      json!({}),
+      StreamOptions::default().with_require_relevant_context(true),
+    )
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::NoRelevantContext { top_score: Some(score) } if score == 0.12),
+    "{:?}",
+    err
+  );
+
+  let requests = harness.handled_requests();
+  let search = requests
+    .iter()
+    .find(|request| request["method"] == "similarity_search")
+    .unwrap();
+  assert_eq!(search["params"]["filter"], json!({ "chat_id": "fruits" }));
+  assert_eq!(search["params"]["query"], "How do I renew my passport?");
+  assert!(!requests
+    .iter()
+    .any(|request| request["method"] == "stream_answer_v2"));
+}
+
+#[tokio::test]
+async fn fake_context_gate_fallback_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "system_info",
+      vec![json!({ "result": { "data": { "version": "fake", "protocol_version": 5 } } })],
+    )
+    .with_replies(
+      "similarity_search",
+      vec![
+        json!({ "result": { "data": [{ "content": "Bananas are yellow", "score": 0.12 }] } }),
+        json!({ "result": { "data": [{ "content": "Bananas are yellow", "score": 0.93 }] } }),
+      ],
+    )
+    .with_replies(
+      "stream_answer_v2",
+      vec![
+        answer_stream(&["Visit", " the office"]),
+        answer_stream(&["Yellow"]),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let ask = |question: &'static str| {
+    harness.ollama_plugin.stream_question_with_options(
+      "fruits",
+      question,
+      None,
+      json!({}),
+      StreamOptions::default().with_require_relevant_context(true),
+    )
+  };
+  let last_rag = || {
+    harness
+      .handled_requests()
+      .into_iter()
+      .filter(|request| request["method"] == "stream_answer_v2")
+      .last()
+      .unwrap()["params"]["rag"]
+      .clone()
+  };
+
+  // Nothing relevant: the answer is flagged and retrieval is skipped for this question only.
+  let frames = ask("How do I renew my passport?")
+    .await
+    .unwrap()
+    .collect::<Vec<_>>()
+    .await;
+  let frames = frames
+    .into_iter()
+    .map(|frame| frame.unwrap())
+    .collect::<Vec<_>>();
+  assert_eq!(
+    frames[0],
+    json!({ STREAM_METADATA_KEY: { "no_document_context": true } })
+  );
+  assert_eq!(
+    frames[1..],
+    [json!({ "1": "Visit" }), json!({ "1": " the office" })]
+  );
+  assert_eq!(last_rag()["skip_retrieval"], true);
+
+  let answer = collect_json_stream(ask("What color are bananas?").await.unwrap()).await;
+  assert_eq!(answer, "Yellow");
+  assert!(last_rag().get("skip_retrieval").is_none());
+}
+
+#[tokio::test]
+async fn fake_prepared_question_request_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "stream_answer_v2",
+    vec![answer_stream(&["Yellow"]), answer_stream(&["Yellow"])],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  harness
+    .ollama_plugin
+    .set_outbound_filter(Arc::new(PatternFilter::pii()));
+  let options = QuestionRequestOptions::default().with_metadata(json!({ "source": "panel" }));
+
+  let sent = harness.handled_requests().len();
+  let prepared = harness
+    .ollama_plugin
+    .build_question_request("fruits", "Ask jane.doe@example.com about bananas", options)
+    .await
+    .unwrap();
+  assert_eq!(prepared.message(), "Ask [EMAIL] about bananas");
+  assert!(prepared.redacted());
+  assert_eq!(prepared.model(), "fake-chat-model");
+  assert_eq!(
+    prepared.params()["params"]["metadata"],
+    json!({ "source": "panel" })
+  );
+  assert_eq!(prepared.estimated_tokens(), 7);
+  assert_eq!(prepared.params()["method"], "stream_answer_v2");
+  assert_eq!(
+    prepared.params()["params"]["data"]["content"],
+    "Ask [EMAIL] about bananas"
+  );
+  // Nothing is sent until the request is.
+  assert_eq!(harness.handled_requests().len(), sent);
+
+  let preview = prepared.params().clone();
+  let stream = harness.ollama_plugin.send_prepared(prepared).await.unwrap();
+  collect_json_stream(stream).await;
+  let mut sent = harness.handled_requests().pop().unwrap();
+  // Added by the RPC layer to every request.
+  sent.as_object_mut().unwrap().remove("plugin_id");
+  assert_eq!(sent, preview);
+
+  // `stream_question` builds its request the same way.
+  let stream = harness
+    .ollama_plugin
+    .stream_question(
+      "fruits",
+      "Ask jane.doe@example.com about bananas",
+      None,
+      json!({}),
+    )
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  let sent = harness.handled_requests().pop().unwrap();
+  assert_eq!(sent["params"]["data"], preview["params"]["data"]);
+}
+
+#[tokio::test]
+async fn fake_ask_question_with_sources_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "answer_with_sources",
+    vec![json!({ "result": { "data": {
+      "answer": "AppFlowy values honesty.",
+      "sources": [{ "metadata": { "source_id": "values" }, "content": "Honesty: we are honest." }]
+    } } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let result = harness
+    .ollama_plugin
+    .ask_question_with_sources("values", "What does AppFlowy value?")
+    .await
+    .unwrap();
+  assert_eq!(result.answer, "AppFlowy values honesty.");
+  assert_eq!(result.sources.len(), 1);
+  assert_eq!(result.sources[0].chunk_text, "Honesty: we are honest.");
+  assert_eq!(result.sources[0].score, None);
+  assert!(!result.sources[0].approximate);
+  let requests = requests_of(&harness, "answer_with_sources");
+  assert_eq!(requests[0]["params"]["chat_id"], "values");
+  assert!(requests_of(&harness, "answer").is_empty());
+  assert!(requests_of(&harness, "similarity_search").is_empty());
+}
+
+#[tokio::test]
+async fn fake_ask_question_with_sources_fallback_test() {
+  let scenario = FakeScenario::new().with_vector_store().with_replies(
+    "answer",
+    vec![json!({ "result": { "data": "Mission Driven and Honesty." } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let plugin = &harness.ollama_plugin;
+  for (chat_id, text) in [
+    (
+      "values",
+      "AppFlowy Values. Mission Driven: our mission is to enable everyone to unleash their potential.",
+    ),
+    ("fruits", "AppFlowy grows bananas."),
+  ] {
+    let metadata = HashMap::from([("chat_id".to_string(), json!(chat_id))]);
+    plugin.embed_text(text, metadata).await.unwrap();
+  }
+
+  let result = plugin
+    .ask_question_with_sources("values", "What are the AppFlowy values?")
+    .await
+    .unwrap();
+  assert_eq!(result.answer, "Mission Driven and Honesty.");
+  assert_eq!(result.sources.len(), 1);
+  assert!(result.sources[0].chunk_text.contains("our mission"));
+  assert!(result.sources[0].approximate);
+  let searches = requests_of(&harness, "similarity_search");
+  assert_eq!(searches.len(), 1);
+  assert_eq!(
+    searches[0]["params"]["filter"],
+    json!({ "chat_id": "values" })
+  );
+}
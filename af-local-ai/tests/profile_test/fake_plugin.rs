@@ -0,0 +1,62 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::profile::{ConfigChange, ConfigProfileStore};
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_apply_profile_test() {
+  let scenario = FakeScenario::new().with_replies("update_settings", vec![json!({ "result": {} })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let dir = tempfile::tempdir().unwrap();
+  let profiles = ConfigProfileStore::new(dir.path().to_path_buf());
+  let fast = harness.config();
+  let mut quality = harness.config();
+  quality.chat_model_name = "fake-big-model".to_string();
+  let mut rag = quality.clone();
+  rag.set_rag_enabled(&dir.path().join("vectors")).unwrap();
+  profiles.save_profile("fast", &fast).unwrap();
+  profiles.save_profile("quality", &quality).unwrap();
+  profiles.save_profile("rag", &rag).unwrap();
+
+  // Only the model differs: the running plugin switches without restarting.
+  let change = harness
+    .ollama_plugin
+    .apply_profile(&profiles, "quality")
+    .await
+    .unwrap();
+  assert_eq!(change, ConfigChange::ModelOnly);
+  assert_eq!(harness.initialize_params().len(), 1);
+  let update = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "update_settings")
+    .unwrap();
+  assert_eq!(update["params"]["model_name"], "fake-big-model");
+
+  let change = harness
+    .ollama_plugin
+    .apply_profile(&profiles, "quality")
+    .await
+    .unwrap();
+  assert_eq!(change, ConfigChange::Unchanged);
+
+  // Enabling RAG needs a restart.
+  let change = harness
+    .ollama_plugin
+    .apply_profile(&profiles, "rag")
+    .await
+    .unwrap();
+  assert_eq!(change, ConfigChange::Restart);
+  assert_eq!(harness.initialize_params().len(), 2);
+
+  // Plugins without `update_settings` are restarted with the new model.
+  harness.restart_with(FakeScenario::new()).await;
+  let change = harness
+    .ollama_plugin
+    .apply_profile(&profiles, "quality")
+    .await
+    .unwrap();
+  assert_eq!(change, ConfigChange::Restart);
+  let params = harness.initialize_params();
+  assert_eq!(params.len(), 4);
+  assert_eq!(params[3]["model_name"], "fake-big-model");
+}
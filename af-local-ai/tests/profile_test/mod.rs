@@ -0,0 +1,133 @@
+use af_local_ai::auth::OllamaAuth;
+use af_local_ai::ollama_plugin::OllamaPluginConfig;
+use af_local_ai::profile::{ConfigChange, ConfigProfileStore, ProfileSummary};
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+fn config(chat_model: &str) -> OllamaPluginConfig {
+  OllamaPluginConfig::new(
+    PathBuf::from("/opt/appflowy/af_ollama_plugin"),
+    String::new(),
+    chat_model.to_string(),
+    "nomic-embed-text".to_string(),
+    None,
+  )
+  .unwrap()
+}
+
+#[test]
+fn profile_round_trip_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let store = ConfigProfileStore::new(dir.path().join("profiles"));
+  assert!(store.list_profiles().unwrap().is_empty());
+
+  let fast = config("llama3.2:1b").with_warming_up_threshold(Duration::from_secs(1));
+  let mut quality = config("llama3.1:70b");
+  quality
+    .set_rag_enabled(&dir.path().join("vectors"))
+    .unwrap();
+  store.save_profile("fast", &fast).unwrap();
+  store.save_profile("quality", &quality).unwrap();
+
+  assert_eq!(store.load_profile("fast").unwrap(), fast);
+  assert_eq!(store.load_profile("quality").unwrap(), quality);
+  let names = store
+    .list_profiles()
+    .unwrap()
+    .into_iter()
+    .map(|profile| (profile.name, profile.rag_enabled))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    names,
+    vec![("fast".to_string(), false), ("quality".to_string(), true)]
+  );
+
+  store.delete_profile("fast").unwrap();
+  assert!(matches!(
+    store.load_profile("fast"),
+    Err(PluginError::ProfileNotFound(_))
+  ));
+  assert!(matches!(
+    store.save_profile("../fast", &fast),
+    Err(PluginError::InvalidProfileName(_))
+  ));
+}
+
+#[test]
+fn profile_without_secrets_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let store = ConfigProfileStore::new(dir.path().to_path_buf());
+  let config = config("llama3.2:1b").with_auth(OllamaAuth::Bearer("secret-token".to_string()));
+  store.save_profile("remote", &config).unwrap();
+
+  let content = std::fs::read_to_string(dir.path().join("remote.json")).unwrap();
+  assert!(!content.contains("secret-token"));
+  assert_eq!(store.load_profile("remote").unwrap().auth, None);
+}
+
+#[test]
+fn profile_with_unknown_fields_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let store = ConfigProfileStore::new(dir.path().to_path_buf());
+  let path = dir.path().join("future.json");
+  // Written by a newer version, with a field this one doesn't know and without ones it does.
+  std::fs::write(
+    &path,
+    json!({
+      "executable_path": "/opt/appflowy/af_ollama_plugin",
+      "chat_model_name": "llama3.2:1b",
+      "embedding_model_name": "nomic-embed-text",
+      "gpu_layers": 32,
+    })
+    .to_string(),
+  )
+  .unwrap();
+
+  let loaded = store.load_profile("future").unwrap();
+  assert_eq!(loaded, config("llama3.2:1b"));
+  assert_eq!(
+    store.list_profiles().unwrap(),
+    vec![ProfileSummary {
+      name: "future".to_string(),
+      chat_model_name: "llama3.2:1b".to_string(),
+      embedding_model_name: "nomic-embed-text".to_string(),
+      server_url: "http://localhost:11434".to_string(),
+      rag_enabled: false,
+    }]
+  );
+
+  // Saving over it keeps the field for the newer version.
+  store.save_profile("future", &loaded).unwrap();
+  let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+  assert_eq!(saved["gpu_layers"], 32);
+}
+
+#[test]
+fn config_change_test() {
+  let current = config("llama3.2:1b");
+  assert_eq!(
+    ConfigChange::between(&current, &current.clone()),
+    ConfigChange::Unchanged
+  );
+  assert_eq!(
+    ConfigChange::between(&current, &config("llama3.1:70b")),
+    ConfigChange::ModelOnly
+  );
+
+  let mut with_rag = config("llama3.1:70b");
+  with_rag.persist_directory = Some(PathBuf::from("/tmp/vectors"));
+  assert_eq!(
+    ConfigChange::between(&current, &with_rag),
+    ConfigChange::Restart
+  );
+  let verbose = config("llama3.2:1b").with_verbose(true);
+  assert_eq!(
+    ConfigChange::between(&current, &verbose),
+    ConfigChange::Restart
+  );
+}
@@ -0,0 +1,118 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::ai_ops::CompleteTextType;
+use serde_json::json;
+
+#[tokio::test]
+async fn fake_host_prompt_template_test() {
+  let completion = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .rev()
+      .find(|request| request["method"] == "complete_text_v2")
+      .unwrap()["params"]
+      .clone()
+  };
+  let text = "Photosynthesis turns light into chemical energy.";
+  let metadata = json!({ "response_language": "French", "tone": "playful" });
+
+  // The fake plugin reports no protocol version, so it has no prompt for Explain.
+  let scenario = FakeScenario::new().with_replies(
+    "complete_text_v2",
+    vec![answer_stream(&["Plants eat light."])],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(
+      text,
+      CompleteTextType::Explain as u8,
+      None,
+      Some(metadata.clone()),
+    )
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Plants eat light.");
+  let params = completion(&harness);
+  assert_eq!(params["completion_type"], CompleteTextType::Custom as u8);
+  assert_eq!(
+    params["text"],
+    format!(
+      "Explain the text below in French with a playful tone, so that someone unfamiliar with \
+      its subject understands it.\n\n{}",
+      text
+    )
+  );
+
+  // Types the plugin always had prompts for are sent as is.
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(text, CompleteTextType::MakeShorter as u8, None, None)
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  let params = completion(&harness);
+  assert_eq!(
+    params["completion_type"],
+    CompleteTextType::MakeShorter as u8
+  );
+  assert_eq!(params["text"], text);
+
+  // A plugin speaking the current protocol prompts Explain itself.
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "system_info",
+      vec![json!({ "result": { "data": { "version": "fake", "protocol_version": 4 } } })],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![answer_stream(&["Plants eat light."])],
+    );
+  harness.restart_with(scenario).await;
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(
+      text,
+      CompleteTextType::Explain as u8,
+      None,
+      Some(metadata.clone()),
+    )
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  let params = completion(&harness);
+  assert_eq!(params["completion_type"], CompleteTextType::Explain as u8);
+  assert_eq!(params["text"], text);
+
+  // Unless the caller overrides the prompt.
+  harness.ollama_plugin.set_prompt_template(
+    CompleteTextType::Explain,
+    "ELI5 in {language}, {tone}: {text} {unknown}".to_string(),
+  );
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(text, CompleteTextType::Explain as u8, None, Some(metadata))
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  let params = completion(&harness);
+  assert_eq!(params["completion_type"], CompleteTextType::Custom as u8);
+  assert_eq!(
+    params["text"],
+    format!("ELI5 in French, playful: {} {{unknown}}", text)
+  );
+  harness
+    .ollama_plugin
+    .reset_prompt_template(CompleteTextType::Explain);
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(text, CompleteTextType::Explain as u8, None, None)
+    .await
+    .unwrap();
+  collect_json_stream(stream).await;
+  assert_eq!(
+    completion(&harness)["completion_type"],
+    CompleteTextType::Explain as u8
+  );
+}
@@ -0,0 +1,72 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::rate_limit::RateLimit;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::time::Duration;
+
+#[tokio::test]
+async fn fake_rate_limit_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "answer",
+      vec![
+        json!({ "result": { "data": "first" } }),
+        json!({ "result": { "data": "second" } }),
+      ],
+    )
+    .with_replies("stream_answer_v2", vec![answer_stream(&["unused"])]);
+  let harness = TestPluginHarness::new(scenario).await;
+  assert!(harness
+    .ollama_plugin
+    .diagnostics()
+    .await
+    .rate_limit
+    .is_none());
+  harness
+    .ollama_plugin
+    .set_rate_limit(RateLimit::new(1, 1).with_fail_fast(true))
+    .unwrap();
+
+  let answer = harness
+    .ollama_plugin
+    .ask_question("chat", "what color are bananas?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "first");
+  let err = harness
+    .ollama_plugin
+    .ask_question("chat", "what color are apples?")
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::RateLimited { retry_after } if retry_after > Duration::from_secs(50))
+  );
+  // A stream takes its token when it starts.
+  let err = harness
+    .ollama_plugin
+    .stream_question("chat", "what color are limes?", None, json!({}))
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::RateLimited { .. }));
+  let state = harness
+    .ollama_plugin
+    .diagnostics()
+    .await
+    .rate_limit
+    .unwrap();
+  assert!(state.tokens < 1.0);
+
+  harness.ollama_plugin.clear_rate_limit();
+  let answer = harness
+    .ollama_plugin
+    .ask_question("chat", "what color are apples?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "second");
+  assert!(harness
+    .ollama_plugin
+    .diagnostics()
+    .await
+    .rate_limit
+    .is_none());
+}
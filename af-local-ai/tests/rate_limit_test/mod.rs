@@ -0,0 +1,159 @@
+use af_local_ai::rate_limit::{RateLimit, RateLimiter, TokenBucket};
+use af_local_ai::scheduler::Priority;
+use af_plugin::error::PluginError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn token_bucket_refill_test() {
+  let start = Instant::now();
+  let mut bucket = TokenBucket::new(RateLimit::new(60, 4), start);
+  assert_eq!(bucket.tokens(start), 4.0);
+  for _ in 0..4 {
+    bucket.try_take(Priority::Interactive, start).unwrap();
+  }
+  assert_eq!(
+    bucket.try_take(Priority::Interactive, start),
+    Err(Duration::from_secs(1))
+  );
+
+  // 60 requests per minute refill a token each second.
+  let half_second = start + Duration::from_millis(500);
+  assert_eq!(bucket.tokens(half_second), 0.5);
+  assert_eq!(
+    bucket.try_take(Priority::Interactive, half_second),
+    Err(Duration::from_millis(500))
+  );
+  let one_second = start + Duration::from_secs(1);
+  bucket.try_take(Priority::Interactive, one_second).unwrap();
+  assert_eq!(bucket.tokens(one_second), 0.0);
+
+  // The bucket never holds more than the burst.
+  assert_eq!(bucket.tokens(one_second + Duration::from_secs(3600)), 4.0);
+}
+
+#[test]
+fn token_bucket_interactive_reserve_test() {
+  let start = Instant::now();
+  let limit = RateLimit::new(60, 8);
+  assert_eq!(limit.interactive_reserve(), 2);
+  let mut bucket = TokenBucket::new(limit, start);
+  for _ in 0..6 {
+    bucket.try_take(Priority::Background, start).unwrap();
+  }
+  // Background requests wait until a token beyond the reserve is refilled.
+  assert_eq!(
+    bucket.try_take(Priority::Background, start),
+    Err(Duration::from_secs(1))
+  );
+  bucket.try_take(Priority::Interactive, start).unwrap();
+  bucket.try_take(Priority::Interactive, start).unwrap();
+  assert_eq!(
+    bucket.try_take(Priority::Interactive, start),
+    Err(Duration::from_secs(1))
+  );
+  assert_eq!(
+    bucket.try_take(Priority::Background, start),
+    Err(Duration::from_secs(3))
+  );
+}
+
+#[test]
+fn rate_limit_validate_test() {
+  let limiter = RateLimiter::default();
+  assert!(matches!(
+    limiter.set_limit(Some(RateLimit::new(0, 1))),
+    Err(PluginError::InvalidRateLimit(_))
+  ));
+  assert!(matches!(
+    limiter.set_limit(Some(RateLimit::new(60, 0))),
+    Err(PluginError::InvalidRateLimit(_))
+  ));
+  assert!(limiter.state().is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limiter_wait_order_test() {
+  let limiter = Arc::new(RateLimiter::default());
+  limiter.set_limit(Some(RateLimit::new(60, 1))).unwrap();
+  let start = Instant::now();
+  let finished = Arc::new(parking_lot::Mutex::new(Vec::new()));
+  let acquire = |id: usize| {
+    let limiter = limiter.clone();
+    let finished = finished.clone();
+    async move {
+      limiter.acquire(Priority::Interactive).await.unwrap();
+      finished.lock().push((id, start.elapsed()));
+    }
+  };
+  tokio::join!(acquire(0), acquire(1), acquire(2));
+
+  // Requests waiting for a token are let through in the order they came, one per refill.
+  assert_eq!(
+    *finished.lock(),
+    vec![
+      (0, Duration::ZERO),
+      (1, Duration::from_secs(1)),
+      (2, Duration::from_secs(2)),
+    ]
+  );
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limiter_interactive_skips_background_test() {
+  let limiter = Arc::new(RateLimiter::default());
+  limiter.set_limit(Some(RateLimit::new(60, 4))).unwrap();
+  for _ in 0..3 {
+    limiter.acquire(Priority::Background).await.unwrap();
+  }
+  let background = tokio::spawn({
+    let limiter = limiter.clone();
+    async move { limiter.acquire(Priority::Background).await }
+  });
+  tokio::task::yield_now().await;
+  let state = limiter.state().unwrap();
+  assert_eq!(state.waiting_background, 1);
+  assert_eq!(state.waiting_interactive, 0);
+
+  // The reserved token lets an interactive request through while the background one waits.
+  let start = Instant::now();
+  limiter.acquire(Priority::Interactive).await.unwrap();
+  assert_eq!(start.elapsed(), Duration::ZERO);
+  assert!(!background.is_finished());
+
+  background.await.unwrap().unwrap();
+  assert_eq!(start.elapsed(), Duration::from_secs(2));
+  assert_eq!(limiter.state().unwrap().waiting_background, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limiter_fail_fast_test() {
+  let limiter = RateLimiter::default();
+  limiter
+    .set_limit(Some(RateLimit::new(30, 1).with_fail_fast(true)))
+    .unwrap();
+  limiter.acquire(Priority::Interactive).await.unwrap();
+
+  // 30 requests per minute refill a token every 2 seconds.
+  let err = limiter.acquire(Priority::Interactive).await.unwrap_err();
+  assert!(matches!(
+    err,
+    PluginError::RateLimited { retry_after } if retry_after == Duration::from_secs(2)
+  ));
+  tokio::time::advance(Duration::from_millis(1500)).await;
+  let err = limiter.acquire(Priority::Interactive).await.unwrap_err();
+  assert!(matches!(
+    err,
+    PluginError::RateLimited { retry_after } if retry_after == Duration::from_millis(500)
+  ));
+  tokio::time::advance(Duration::from_millis(500)).await;
+  limiter.acquire(Priority::Interactive).await.unwrap();
+
+  limiter.set_limit(None).unwrap();
+  limiter.acquire(Priority::Interactive).await.unwrap();
+  assert!(limiter.state().is_none());
+}
@@ -0,0 +1,214 @@
+use crate::harness::{answer_stream, requests_of, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::ai_ops::RelatedQuestionOptions;
+use serde_json::json;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_related_question_prefetch_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![
+        answer_stream(&["Banana", " is", " a fruit"]),
+        json!({ "stream": [json!({ "1": "Apple" }).to_string()], "delay_ms": 500 }),
+      ],
+    )
+    .with_replies(
+      "related_question",
+      vec![
+        json!({ "result": { "data": [{ "content": "Where do bananas grow?" }] } }),
+        json!({ "result": { "data": [{ "content": "Are apples red?" }] } }),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  harness.ollama_plugin.enable_related_question_prefetch(true);
+  let related_requests = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .iter()
+      .filter(|request| request["method"] == "related_question")
+      .count()
+  };
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Banana is a fruit");
+  for _ in 0..50 {
+    if related_requests(&harness) == 1 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  assert_eq!(related_requests(&harness), 1);
+
+  // Served from the prefetched questions.
+  for _ in 0..2 {
+    let questions = harness
+      .ollama_plugin
+      .get_related_question("chat")
+      .await
+      .unwrap();
+    assert_eq!(questions, vec!["Where do bananas grow?"]);
+  }
+  assert_eq!(related_requests(&harness), 1);
+
+  // A new question drops the cached questions. Its answer is not consumed, so nothing is
+  // prefetched and the questions are fetched live.
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what is apple?", None, json!({}))
+    .await
+    .unwrap();
+  drop(stream);
+  let questions = harness
+    .ollama_plugin
+    .get_related_question("chat")
+    .await
+    .unwrap();
+  assert_eq!(questions, vec!["Are apples red?"]);
+  assert_eq!(related_requests(&harness), 2);
+}
+
+#[tokio::test]
+async fn fake_related_question_prefetch_newer_answer_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![
+        answer_stream(&["Banana", " is", " a fruit"]),
+        answer_stream(&["Apple", " is", " a fruit"]),
+      ],
+    )
+    .with_replies(
+      "related_question",
+      vec![
+        json!({
+          "result": { "data": [{ "content": "Where do bananas grow?" }] },
+          "delay_ms": 500,
+        }),
+        json!({ "result": { "data": [{ "content": "Are apples red?" }] } }),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  harness.ollama_plugin.enable_related_question_prefetch(true);
+  let related_requests = || requests_of(&harness, "related_question").len();
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Banana is a fruit");
+  for _ in 0..50 {
+    if related_requests() == 1 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  assert_eq!(related_requests(), 1);
+
+  // The second answer finishes while the questions of the first one are still being fetched.
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what is apple?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Apple is a fruit");
+  for _ in 0..2 {
+    let questions = harness
+      .ollama_plugin
+      .get_related_question("chat")
+      .await
+      .unwrap();
+    assert_eq!(questions, vec!["Are apples red?"]);
+  }
+  assert_eq!(related_requests(), 2);
+}
+
+#[tokio::test]
+async fn fake_related_questions_stream_test() {
+  let frame = |question: &str| json!({ "content": question }).to_string();
+  let scenario = FakeScenario::new().with_replies(
+    "related_question_stream",
+    vec![json!({
+      "stream": [
+        frame("Where do bananas grow?"),
+        frame("where do bananas grow? "),
+        frame(" "),
+        frame("Are bananas berries?"),
+        frame("How ripe is a yellow banana?"),
+      ],
+      "delay_ms": 10,
+    })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let stream = harness
+    .ollama_plugin
+    .get_related_questions_stream("chat", RelatedQuestionOptions::default())
+    .await
+    .unwrap();
+  let questions = stream.map(Result::unwrap).collect::<Vec<_>>().await;
+  assert_eq!(
+    questions,
+    vec![
+      "Where do bananas grow?",
+      "Are bananas berries?",
+      "How ripe is a yellow banana?",
+    ]
+  );
+
+  // The stream ends once the requested questions were sent.
+  let stream = harness
+    .ollama_plugin
+    .get_related_questions_stream("chat", RelatedQuestionOptions::new(2))
+    .await
+    .unwrap();
+  let questions = stream.map(Result::unwrap).collect::<Vec<_>>().await;
+  assert_eq!(
+    questions,
+    vec!["Where do bananas grow?", "Are bananas berries?"]
+  );
+  let request = harness
+    .handled_requests()
+    .into_iter()
+    .rfind(|request| request["method"] == "related_question_stream")
+    .unwrap();
+  assert_eq!(request["params"]["count"], 2);
+}
+
+#[tokio::test]
+async fn fake_related_questions_stream_fallback_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "related_question",
+    vec![json!({ "result": { "data": [
+      { "content": "Where do bananas grow?" },
+      { "content": "WHERE DO BANANAS GROW?" },
+      { "content": "Are bananas berries?" },
+    ] } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let stream = harness
+    .ollama_plugin
+    .get_related_questions_stream("chat", RelatedQuestionOptions::default())
+    .await
+    .unwrap();
+  let questions = stream.map(Result::unwrap).collect::<Vec<_>>().await;
+  assert_eq!(
+    questions,
+    vec!["Where do bananas grow?", "Are bananas berries?"]
+  );
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap().to_string())
+    .filter(|method| method.starts_with("related_question"))
+    .collect::<Vec<_>>();
+  assert_eq!(methods, vec!["related_question_stream", "related_question"]);
+}
@@ -0,0 +1,169 @@
+use crate::harness::{requests_of, FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::ChatSettings;
+use af_local_ai::embedding_index::content_hash;
+use af_local_ai::replay::{
+  ChatExport, ChatReplay, ExportedAttachment, ExportedTurn, ReplayOptions,
+};
+use serde_json::json;
+use std::collections::HashMap;
+
+fn recorded_chat() -> ChatExport {
+  let notes = "Plantains grow in Ghana.";
+  ChatExport {
+    chat_id: "recorded".to_string(),
+    settings: ChatSettings {
+      response_language: Some("English".to_string()),
+      ..Default::default()
+    },
+    attachments: vec![
+      ExportedAttachment {
+        source_id: "notes".to_string(),
+        name: Some("notes.md".to_string()),
+        content_hash: content_hash(notes, &HashMap::new()),
+        content: Some(notes.to_string()),
+      },
+      ExportedAttachment {
+        source_id: "edited".to_string(),
+        name: None,
+        content_hash: content_hash("Before the edit", &HashMap::new()),
+        content: Some("After the edit".to_string()),
+      },
+    ],
+    turns: vec![
+      ExportedTurn {
+        question: "what color are bananas?".to_string(),
+        answer: "Bananas are yellow".to_string(),
+      },
+      ExportedTurn {
+        question: "where do plantains grow?".to_string(),
+        answer: "In Ghana".to_string(),
+      },
+    ],
+  }
+}
+
+#[tokio::test]
+async fn fake_chat_replay_test() {
+  let answer = |question: &str, answer: &str| json!({ "when": { "content": question }, "result": { "data": answer } });
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies(
+      "answer",
+      vec![
+        answer("what color are bananas?", "Bananas are yellow"),
+        answer("where do plantains grow?", "Plantains grow in West Africa"),
+      ],
+    )
+    .with_replies(
+      "gen_embeddings",
+      vec![
+        json!({ "when": { "input": "In Ghana" }, "result": { "data": [[0.0, 1.0, 0.0]] } }),
+        json!({ "result": { "data": [[0.1, 0.2, 0.3]] } }),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let result = ChatReplay::from_export(recorded_chat())
+    .replay(&harness.ollama_plugin, ReplayOptions::default())
+    .await
+    .unwrap();
+  assert!(result.chat_id.starts_with("replay-recorded-"));
+  assert_eq!(result.skipped_attachments, vec!["edited".to_string()]);
+  let turns = result
+    .turns
+    .iter()
+    .map(|turn| {
+      (
+        turn.question.as_str(),
+        turn.original_answer.as_str(),
+        turn.new_answer.as_str(),
+      )
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    turns,
+    vec![
+      (
+        "what color are bananas?",
+        "Bananas are yellow",
+        "Bananas are yellow"
+      ),
+      (
+        "where do plantains grow?",
+        "In Ghana",
+        "Plantains grow in West Africa"
+      ),
+    ]
+  );
+  // The same answer embeds the same, a different one further apart.
+  let similarities = result
+    .turns
+    .iter()
+    .map(|turn| turn.similarity.unwrap())
+    .collect::<Vec<_>>();
+  assert!((similarities[0] - 1.0).abs() < 1e-9, "{:?}", similarities);
+  assert!(similarities[1] < 0.9, "{:?}", similarities);
+
+  // The questions were asked at a zero temperature in the replayed chat, which embedded the
+  // attachment whose text matches its hash.
+  let answers = requests_of(&harness, "answer");
+  assert_eq!(answers.len(), 2);
+  for request in &answers {
+    assert_eq!(request["params"]["chat_id"], result.chat_id.as_str());
+    assert_eq!(request["params"]["options"], json!({ "temperature": 0.0 }));
+  }
+  let embedded = requests_of(&harness, "embed_text");
+  assert_eq!(embedded.len(), 1);
+  assert_eq!(embedded[0]["params"]["metadata"]["source_id"], "notes");
+  assert_eq!(
+    embedded[0]["params"]["metadata"]["chat_id"],
+    result.chat_id.as_str()
+  );
+  // The replayed chat is closed, which deletes its attachments.
+  assert!(harness
+    .ollama_plugin
+    .list_chat_attachments(&result.chat_id)
+    .await
+    .is_empty());
+
+  let artifact = serde_json::to_value(&result).unwrap();
+  assert_eq!(
+    artifact["turns"][1]["new_answer"],
+    "Plantains grow in West Africa"
+  );
+  assert!(artifact["turns"][0]["similarity"].is_number());
+}
+
+#[tokio::test]
+async fn fake_chat_replay_without_embeddings_test() {
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies("answer", vec![json!({ "result": { "data": "Yellow" } })])
+    .with_replies(
+      "gen_embeddings",
+      vec![json!({ "error": { "code": 1, "message": "no embedding model" } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  // Attachments can't be embedded without an embedding model either.
+  let export = ChatExport {
+    attachments: vec![],
+    ..recorded_chat()
+  };
+  let options = ReplayOptions {
+    seed: Some(7),
+    temperature_zero: false,
+  };
+  let result = ChatReplay::from_export(export)
+    .replay(&harness.ollama_plugin, options)
+    .await
+    .unwrap();
+  assert_eq!(result.turns.len(), 2);
+  assert!(result.turns.iter().all(|turn| turn.new_answer == "Yellow"));
+  assert!(result.turns.iter().all(|turn| turn.similarity.is_none()));
+  // Embedding is given up on after the first failure.
+  assert_eq!(requests_of(&harness, "gen_embeddings").len(), 1);
+  assert!(requests_of(&harness, "answer")
+    .iter()
+    .all(|request| request["params"]["options"] == json!({ "seed": 7 })));
+}
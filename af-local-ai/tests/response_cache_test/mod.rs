@@ -0,0 +1,126 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::QuestionOptions;
+use af_local_ai::response_cache::{CacheConfig, CacheStats};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[tokio::test]
+async fn fake_response_cache_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "database_summary",
+      vec![json!({ "result": { "data": "A yellow fruit" } })],
+    )
+    .with_replies("answer", vec![json!({ "result": { "data": "Yellow" } })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let cache_dir = tempfile::tempdir().unwrap();
+  harness
+    .ollama_plugin
+    .enable_response_cache(CacheConfig {
+      dir: cache_dir.path().to_path_buf(),
+      max_entries: 10,
+      ttl: Duration::from_secs(60),
+    })
+    .unwrap();
+
+  let row = || {
+    HashMap::from([
+      ("name".to_string(), "banana".to_string()),
+      ("color".to_string(), "yellow".to_string()),
+    ])
+  };
+  // Concurrent calls with the same row share one request.
+  let (first, second) = tokio::join!(
+    harness.ollama_plugin.summary_database_row(row()),
+    harness.ollama_plugin.summary_database_row(row())
+  );
+  assert_eq!(first.unwrap(), "A yellow fruit");
+  assert_eq!(second.unwrap(), "A yellow fruit");
+  let summary = harness
+    .ollama_plugin
+    .summary_database_row(row())
+    .await
+    .unwrap();
+  assert_eq!(summary, "A yellow fruit");
+  assert_eq!(
+    harness.ollama_plugin.cache_stats(),
+    CacheStats { hits: 2, misses: 1 }
+  );
+
+  // Only questions asked with deterministic options are cached.
+  for _ in 0..2 {
+    harness
+      .ollama_plugin
+      .ask_question("chat", "what color?")
+      .await
+      .unwrap();
+    let options = QuestionOptions::default().with_temperature(0.0);
+    harness
+      .ollama_plugin
+      .ask_question_with_options("chat", "what color?", options)
+      .await
+      .unwrap();
+  }
+
+  let requests = harness.handled_requests();
+  let count = |method: &str| {
+    requests
+      .iter()
+      .filter(|request| request["method"] == method)
+      .count()
+  };
+  assert_eq!(count("database_summary"), 1);
+  assert_eq!(count("answer"), 3);
+  let options = requests
+    .iter()
+    .filter(|request| request["method"] == "answer")
+    .map(|request| request["params"]["options"].clone())
+    .collect::<Vec<_>>();
+  assert_eq!(
+    options,
+    vec![Value::Null, json!({ "temperature": 0.0 }), Value::Null]
+  );
+  assert_eq!(
+    harness.ollama_plugin.cache_stats(),
+    CacheStats { hits: 3, misses: 2 }
+  );
+}
+
+#[tokio::test]
+async fn fake_response_cache_expiry_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "database_summary",
+    vec![
+      json!({ "result": { "data": "A yellow fruit" } }),
+      json!({ "result": { "data": "A ripe yellow fruit" } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let cache_dir = tempfile::tempdir().unwrap();
+  harness
+    .ollama_plugin
+    .enable_response_cache(CacheConfig {
+      dir: cache_dir.path().to_path_buf(),
+      max_entries: 10,
+      ttl: Duration::from_millis(200),
+    })
+    .unwrap();
+
+  let row = || HashMap::from([("name".to_string(), "banana".to_string())]);
+  let summarize = || harness.ollama_plugin.summary_database_row(row());
+  assert_eq!(summarize().await.unwrap(), "A yellow fruit");
+  assert_eq!(summarize().await.unwrap(), "A yellow fruit");
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  assert_eq!(summarize().await.unwrap(), "A ripe yellow fruit");
+  assert_eq!(
+    harness.ollama_plugin.cache_stats(),
+    CacheStats { hits: 1, misses: 2 }
+  );
+  let summaries = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "database_summary")
+    .count();
+  assert_eq!(summaries, 2);
+}
@@ -0,0 +1,201 @@
+use crate::harness::{requests_of, FakeScenario, TestPluginHarness};
+use af_local_ai::retry::RetryPolicy;
+#[cfg(feature = "usage-tracking")]
+use af_local_ai::usage::TimeRange;
+use af_plugin::error::{PluginError, RemoteError};
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn fake_retry_transient_error_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "answer",
+    vec![
+      json!({ "error": { "code": -32001, "message": "model is loading" } }),
+      json!({ "error": { "code": 1, "message": "request timed out" } }),
+      json!({ "result": { "data": "Yellow" } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  #[cfg(feature = "usage-tracking")]
+  let dir = tempfile::tempdir().unwrap();
+  #[cfg(feature = "usage-tracking")]
+  harness
+    .ollama_plugin
+    .enable_usage_tracking(dir.path().join("usage.db"))
+    .unwrap();
+  harness
+    .ollama_plugin
+    .set_retry_policy(RetryPolicy::new(3, Duration::from_millis(10)));
+
+  let answer = harness
+    .ollama_plugin
+    .ask_question("fruits", "What color are bananas?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "Yellow");
+
+  // Every attempt is the same request, with the same trace id.
+  let requests = requests_of(&harness, "answer");
+  assert_eq!(requests.len(), 3);
+  let trace_id = requests[0]["params"]["trace_id"].as_str().unwrap();
+  assert!(requests.iter().all(|request| request == &requests[0]));
+  assert!(!trace_id.is_empty());
+
+  let retries = harness.ollama_plugin.diagnostics().await.retries;
+  assert_eq!(retries.retried_attempts, 2);
+  assert_eq!(retries.exhausted, 0);
+
+  #[cfg(feature = "usage-tracking")]
+  {
+    let summary = harness
+      .ollama_plugin
+      .usage_summary(TimeRange::last_days(1))
+      .await
+      .unwrap();
+    assert_eq!(summary.retries, 2);
+    assert_eq!(summary.failures, 0);
+  }
+}
+
+#[tokio::test]
+async fn fake_retry_permanent_error_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "database_summary",
+      vec![json!({ "error": { "code": -32602, "message": "row is empty" } })],
+    )
+    .with_replies(
+      "answer",
+      vec![json!({ "error": { "code": -32001, "message": "model is loading" } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  harness
+    .ollama_plugin
+    .set_retry_policy(RetryPolicy::new(3, Duration::from_millis(10)));
+
+  // Rejected params are never sent again.
+  let err = harness
+    .ollama_plugin
+    .summary_database_row(HashMap::from([("name".to_string(), "banana".to_string())]))
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(
+      err,
+      PluginError::RemoteError(RemoteError::InvalidParams { .. })
+    ),
+    "{:?}",
+    err
+  );
+  assert_eq!(requests_of(&harness, "database_summary").len(), 1);
+
+  // A transient error that doesn't go away fails after the last attempt.
+  let err = harness
+    .ollama_plugin
+    .ask_question("fruits", "What color are bananas?")
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(
+      err,
+      PluginError::RemoteError(RemoteError::ModelOverloaded { .. })
+    ),
+    "{:?}",
+    err
+  );
+  assert_eq!(requests_of(&harness, "answer").len(), 3);
+
+  let retries = harness.ollama_plugin.diagnostics().await.retries;
+  assert_eq!(retries.retried_attempts, 2);
+  assert_eq!(retries.exhausted, 1);
+
+  // Without a policy, failed requests fail right away.
+  harness.ollama_plugin.clear_retry_policy();
+  assert!(harness
+    .ollama_plugin
+    .ask_question("fruits", "What color are bananas?")
+    .await
+    .is_err());
+  assert_eq!(requests_of(&harness, "answer").len(), 4);
+}
+
+#[tokio::test]
+async fn fake_retry_deadline_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "answer",
+      vec![json!({ "error": { "code": -32001, "message": "model is loading" } })],
+    )
+    .with_replies(
+      "database_summary",
+      vec![json!({ "result": { "data": "A yellow fruit" }, "delay_ms": 2000 })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  // The wait before the third attempt would end past the deadline.
+  harness.ollama_plugin.set_retry_policy(
+    RetryPolicy::new(10, Duration::from_millis(100))
+      .with_deadline(Some(Duration::from_millis(250))),
+  );
+
+  let start = Instant::now();
+  let err = harness
+    .ollama_plugin
+    .ask_question("fruits", "What color are bananas?")
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(
+      err,
+      PluginError::RemoteError(RemoteError::ModelOverloaded { .. })
+    ),
+    "{:?}",
+    err
+  );
+  assert_eq!(requests_of(&harness, "answer").len(), 2);
+  assert!(start.elapsed() < Duration::from_millis(250));
+
+  // An attempt still running at the deadline times out.
+  let start = Instant::now();
+  let err = harness
+    .ollama_plugin
+    .summary_database_row(HashMap::from([("name".to_string(), "banana".to_string())]))
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::RequestTimeout(_)), "{:?}", err);
+  assert!(start.elapsed() < Duration::from_secs(1));
+  assert_eq!(requests_of(&harness, "database_summary").len(), 1);
+
+  let retries = harness.ollama_plugin.diagnostics().await.retries;
+  assert_eq!(retries.retried_attempts, 1);
+  assert_eq!(retries.exhausted, 2);
+}
+
+#[test]
+fn retry_local_timeout_only_resends_idempotent_methods_test() {
+  let policy = RetryPolicy::new(3, Duration::from_millis(10));
+  let timeout = PluginError::RequestTimeout(Duration::from_secs(1));
+  assert!(policy.may_resend("database_summary", &timeout));
+  assert!(policy.may_resend("similarity_search", &timeout));
+  // The plugin may still be working on these, so sending them again would repeat their change.
+  for method in [
+    "answer",
+    "append_message",
+    "embed_file",
+    "replace_history_prefix",
+    "truncate_chat",
+    "create_chat",
+  ] {
+    assert!(!policy.may_resend(method, &timeout), "{}", method);
+  }
+  // A reply of the plugin ends the request, so it is sent again whatever the method.
+  let overloaded = PluginError::RemoteError(RemoteError::ModelOverloaded {
+    message: "model is loading".to_string(),
+    data: None,
+  });
+  assert!(policy.may_resend("answer", &overloaded));
+
+  let policy = policy.with_idempotent_method("my_lookup");
+  assert!(policy.may_resend("my_lookup", &timeout));
+}
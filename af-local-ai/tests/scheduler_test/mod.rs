@@ -0,0 +1,110 @@
+use crate::util::LocalAITest;
+use af_local_ai::scheduler::{Priority, RequestScheduler};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+#[cfg_attr(
+  not(feature = "model-tests"),
+  ignore = "needs the plugin and models of .env"
+)]
+async fn ci_interactive_question_before_background_embeds_test() {
+  let test = Arc::new(LocalAITest::new().unwrap());
+  test.init_chat_plugin().await;
+  let chat_id = uuid::Uuid::new_v4().to_string();
+  test.ollama_plugin.create_chat(&chat_id).await.unwrap();
+
+  let mut embeds = tokio::task::JoinSet::new();
+  for i in 0..20 {
+    let test = test.clone();
+    embeds.spawn(async move {
+      let mut metadata = HashMap::new();
+      metadata.insert("id".to_string(), json!(uuid::Uuid::new_v4().to_string()));
+      let text = format!("AppFlowy background note number {}", i);
+      test
+        .ollama_plugin
+        .embed_text(&text, metadata)
+        .await
+        .unwrap();
+      Instant::now()
+    });
+  }
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  test
+    .ollama_plugin
+    .ask_question(&chat_id, "What is AppFlowy?")
+    .await
+    .unwrap();
+  let answered_at = Instant::now();
+
+  let mut last_embed = answered_at;
+  while let Some(finished_at) = embeds.join_next().await {
+    last_embed = last_embed.max(finished_at.unwrap());
+  }
+  assert!(answered_at < last_embed);
+}
+
+#[tokio::test]
+async fn interactive_request_overtakes_background_queue_test() {
+  let scheduler = RequestScheduler::new();
+  let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+  for i in 0..20 {
+    let scheduler = scheduler.clone();
+    let tx = tx.clone();
+    tokio::spawn(async move {
+      let _permit = scheduler.acquire(Priority::Background).await;
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      let _ = tx.send(format!("embed-{}", i));
+    });
+  }
+  tokio::time::sleep(Duration::from_millis(15)).await;
+  assert_eq!(scheduler.in_flight().1, 1);
+
+  let permit = scheduler.acquire(Priority::Interactive).await;
+  tx.send("question".to_string()).unwrap();
+  drop(permit);
+  drop(tx);
+
+  let mut order = vec![];
+  while let Some(name) = rx.recv().await {
+    order.push(name);
+  }
+  assert_eq!(order.len(), 21);
+  let question = order.iter().position(|name| name == "question").unwrap();
+  assert!(
+    question < 3,
+    "question answered at {}: {:?}",
+    question,
+    order
+  );
+}
+
+#[tokio::test]
+async fn background_waits_for_interactive_test() {
+  let scheduler = RequestScheduler::new();
+  let interactive = scheduler.acquire(Priority::Interactive).await;
+
+  let background = tokio::spawn({
+    let scheduler = scheduler.clone();
+    async move { scheduler.acquire(Priority::Background).await }
+  });
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(!background.is_finished());
+
+  // Interactive requests are never held back.
+  let second = scheduler.acquire(Priority::Interactive).await;
+  assert_eq!(scheduler.in_flight(), (2, 0));
+  drop(second);
+  drop(interactive);
+
+  let permit = tokio::time::timeout(Duration::from_secs(1), background)
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(permit.priority(), Priority::Background);
+  assert_eq!(scheduler.in_flight(), (0, 1));
+}
@@ -0,0 +1,229 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::embedding_ops::{SearchOptions, SearchResult};
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_similarity_search_pagination_test() {
+  // The fake plugin ignores the options and replies with every result, unsorted.
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![json!({ "result": { "data": [
+      { "content": "c", "score": 0.6 },
+      { "content": "a", "score": 0.9 },
+      { "content": "e", "score": 0.2 },
+      { "content": "b", "score": 0.8 },
+      { "content": "d", "score": 0.4 },
+    ] } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  for text in ["a", "b", "c", "d", "e"] {
+    harness
+      .ollama_plugin
+      .embed_text(text, HashMap::new())
+      .await
+      .unwrap();
+  }
+  let search = |options: SearchOptions| {
+    harness
+      .ollama_plugin
+      .similarity_search_with_options("query", HashMap::new(), options)
+  };
+  let contents = |results: &[SearchResult]| {
+    results
+      .iter()
+      .map(|result| result.content.clone())
+      .collect::<Vec<_>>()
+  };
+
+  let first = search(SearchOptions::new(2)).await.unwrap();
+  let second = search(SearchOptions::new(2).with_offset(2)).await.unwrap();
+  assert_eq!(contents(&first.results), vec!["a", "b"]);
+  assert_eq!(contents(&second.results), vec!["c", "d"]);
+  assert_eq!(first.total, Some(5));
+  assert_eq!(first.results[1].score, Some(0.8));
+
+  let params = harness
+    .handled_requests()
+    .into_iter()
+    .rev()
+    .find(|request| request["method"] == "similarity_search")
+    .unwrap()["params"]
+    .clone();
+  assert_eq!(params["top_k"], 2);
+  assert_eq!(params["offset"], 2);
+
+  let page = search(SearchOptions::new(2).with_min_score(0.5).with_offset(2))
+    .await
+    .unwrap();
+  assert_eq!(contents(&page.results), vec!["c"]);
+  assert_eq!(page.total, Some(3));
+
+  let page = search(SearchOptions::new(2).with_offset(10)).await.unwrap();
+  assert!(page.results.is_empty());
+}
+
+#[tokio::test]
+async fn fake_get_embeddings_test() {
+  // The second page packs its vector as little endian float32 values: [0.5, 0.25, 1.0].
+  let scenario = FakeScenario::new().with_replies(
+    "vs_get",
+    vec![
+      json!({ "result": { "data": {
+        "items": [{
+          "id": "apples",
+          "vector": [0.1, 0.2, 0.3],
+          "metadata": { "space": "garden", "object_id": "apples" },
+          "content_hash": "hash-apples",
+        }],
+        "next_cursor": "page-2",
+      } } }),
+      json!({ "result": { "data": {
+        "items": [{
+          "id": "pears",
+          "vector": { "dtype": "float32", "base64": "AAAAPwAAgD4AAIA/" },
+          "metadata": { "space": "garden", "object_id": "pears" },
+        }],
+        "next_cursor": null,
+      } } }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  for object_id in ["apples", "pears"] {
+    let metadata = HashMap::from([
+      ("space".to_string(), json!("garden")),
+      ("object_id".to_string(), json!(object_id)),
+    ]);
+    harness
+      .ollama_plugin
+      .embed_text(object_id, metadata)
+      .await
+      .unwrap();
+  }
+
+  let filter = HashMap::from([("space".to_string(), json!("garden"))]);
+  let embeddings = harness
+    .ollama_plugin
+    .get_embeddings(filter.clone(), 10)
+    .await
+    .unwrap();
+  assert_eq!(embeddings.len(), 2);
+  assert_eq!(embeddings[0].vector, vec![0.1, 0.2, 0.3]);
+  assert_eq!(embeddings[1].vector, vec![0.5, 0.25, 1.0]);
+  for embedding in &embeddings {
+    assert_eq!(embedding.metadata["space"], "garden");
+    assert_eq!(embedding.metadata["object_id"], embedding.id.as_str());
+  }
+  assert_eq!(embeddings[0].content_hash.as_deref(), Some("hash-apples"));
+  assert_eq!(embeddings[1].content_hash, None);
+
+  // The pages are read one after the other, each request resuming from the previous cursor.
+  let requests = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "vs_get")
+    .map(|request| request["params"].clone())
+    .collect::<Vec<_>>();
+  assert_eq!(requests.len(), 2);
+  assert_eq!(requests[0]["filter"], json!({ "space": "garden" }));
+  assert_eq!(requests[0]["cursor"], json!(null));
+  assert_eq!(requests[1]["cursor"], "page-2");
+
+  // The limit stops the reads early.
+  let embeddings = harness
+    .ollama_plugin
+    .get_embeddings(filter, 1)
+    .await
+    .unwrap();
+  assert_eq!(embeddings.len(), 1);
+}
+
+fn space_filter(space: &str) -> HashMap<String, serde_json::Value> {
+  HashMap::from([("space".to_string(), json!(space))])
+}
+
+#[tokio::test]
+async fn fake_similarity_search_stream_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![
+      json!({
+        "when": { "filter": { "space": "work" } },
+        "result": { "data": [
+          { "content": "quarterly roadmap", "score": 0.9 },
+          { "content": "shared glossary", "score": 0.5 },
+        ] },
+      }),
+      json!({
+        "when": { "filter": { "space": "home" } },
+        "delay_ms": 200,
+        "result": { "data": [
+          { "content": "grocery list", "score": 0.8 },
+          { "content": "shared glossary", "score": 0.5 },
+        ] },
+      }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let (_handle, stream) = harness
+    .ollama_plugin
+    .similarity_search_stream(
+      "plans",
+      vec![space_filter("work"), space_filter("home")],
+      SearchOptions::default(),
+    )
+    .await
+    .unwrap();
+  let results = stream
+    .map(Result::unwrap)
+    .map(|found| (found.filter_index, found.result.content))
+    .collect::<Vec<_>>()
+    .await;
+  // The glossary is in both spaces and is only sent with the faster one.
+  assert_eq!(
+    results,
+    vec![
+      (0, "quarterly roadmap".to_string()),
+      (0, "shared glossary".to_string()),
+      (1, "grocery list".to_string()),
+    ]
+  );
+}
+
+#[tokio::test]
+async fn fake_cancel_similarity_search_stream_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![
+      json!({
+        "when": { "filter": { "space": "work" } },
+        "result": { "data": [{ "content": "quarterly roadmap", "score": 0.9 }] },
+      }),
+      json!({
+        "when": { "filter": { "space": "archive" } },
+        "delay_ms": 10_000,
+        "result": { "data": [{ "content": "old roadmap", "score": 0.7 }] },
+      }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let (handle, mut stream) = harness
+    .ollama_plugin
+    .similarity_search_stream(
+      "roadmap",
+      vec![space_filter("work"), space_filter("archive")],
+      SearchOptions::default(),
+    )
+    .await
+    .unwrap();
+  let first = stream.next().await.unwrap().unwrap();
+  assert_eq!(first.filter_index, 0);
+
+  handle.cancel();
+  let rest = tokio::time::timeout(Duration::from_secs(2), stream.collect::<Vec<_>>())
+    .await
+    .expect("stream ends without waiting for the slow filter");
+  assert!(rest.is_empty());
+}
@@ -0,0 +1,114 @@
+use crate::harness::{requests_of, FakeScenario, TestPluginHarness};
+use af_local_ai::embedding_ops::SearchOptions;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn fake_semantic_search_test() {
+  let harness = TestPluginHarness::new(FakeScenario::new().with_vector_store()).await;
+  let plugin = &harness.ollama_plugin;
+  for text in [
+    "AppFlowy keeps your notes. Bananas turn yellow when they ripen. Keep them out of the fridge.",
+    "Apples grow in orchards. A ripe apple falls from the tree.",
+  ] {
+    plugin.embed_text(text, HashMap::new()).await.unwrap();
+  }
+
+  let hits = plugin
+    .semantic_search(
+      "when do bananas ripen in orchards",
+      SearchOptions::default(),
+    )
+    .await
+    .unwrap();
+  assert_eq!(hits.len(), 2);
+  let banana = hits
+    .iter()
+    .find(|hit| hit.snippet.contains("Bananas"))
+    .unwrap();
+  assert_eq!(banana.snippet, "Bananas turn yellow when they ripen.");
+  assert!(hits
+    .iter()
+    .any(|hit| hit.snippet == "Apples grow in orchards."));
+  for hit in &hits {
+    for range in &hit.highlight_ranges {
+      assert!(hit.snippet.is_char_boundary(range.start));
+      assert!(hit.snippet.is_char_boundary(range.end));
+    }
+  }
+  let words = banana
+    .highlight_ranges
+    .iter()
+    .map(|range| &banana.snippet[range.clone()])
+    .collect::<Vec<_>>();
+  assert_eq!(words, vec!["Bananas", "when", "ripen"]);
+
+  // An empty query isn't searched.
+  let searches = requests_of(&harness, "similarity_search").len();
+  assert!(plugin
+    .semantic_search("  ", SearchOptions::default())
+    .await
+    .unwrap()
+    .is_empty());
+  assert_eq!(requests_of(&harness, "similarity_search").len(), searches);
+}
+
+#[tokio::test]
+async fn fake_semantic_search_merge_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![json!({ "result": { "data": [
+      {
+        "content": "Fruits of the tropics. Mangoes are sweet.",
+        "score": 0.9,
+        "metadata": { "object_id": "fruits", "title": "Fruits" },
+      },
+      {
+        "content": "Bananas are yellow. They grow in bunches.",
+        "score": 0.7,
+        "metadata": { "object_id": "fruits" },
+      },
+      {
+        "content": "Yellow paint dries fast.",
+        "score": 0.8,
+        "metadata": { "source_id": "paint.pdf", "file_name": "paint.pdf" },
+      },
+    ] } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let hits = harness
+    .ollama_plugin
+    .semantic_search("yellow bananas", SearchOptions::default())
+    .await
+    .unwrap();
+  let hits = hits
+    .iter()
+    .map(|hit| {
+      (
+        hit.object_id.as_deref(),
+        hit.title.as_deref(),
+        hit.snippet.as_str(),
+        hit.score,
+      )
+    })
+    .collect::<Vec<_>>();
+  // The chunks of an object make one hit, with the best score and the best snippet.
+  assert_eq!(
+    hits,
+    vec![
+      (
+        Some("fruits"),
+        Some("Fruits"),
+        "Bananas are yellow.",
+        Some(0.9)
+      ),
+      (
+        Some("paint.pdf"),
+        Some("paint.pdf"),
+        "Yellow paint dries fast.",
+        Some(0.8)
+      ),
+    ]
+  );
+}
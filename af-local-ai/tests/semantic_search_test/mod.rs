@@ -0,0 +1,81 @@
+use af_local_ai::semantic_search::{best_snippet, SNIPPET_MAX_CHARS};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+fn highlighted(snippet: &af_local_ai::semantic_search::Snippet) -> Vec<&str> {
+  snippet
+    .highlight_ranges
+    .iter()
+    .map(|range| &snippet.text[range.clone()])
+    .collect()
+}
+
+#[test]
+fn best_snippet_sentence_test() {
+  let content = "AppFlowy is a workspace. Bananas turn yellow when they ripen. \
+                 Version 3.5 ships on Friday!";
+  let snippet = best_snippet(content, "when do bananas ripen");
+  assert_eq!(snippet.text, "Bananas turn yellow when they ripen.");
+  assert_eq!(highlighted(&snippet), vec!["Bananas", "when", "ripen"]);
+  assert_eq!(snippet.score, 3.0);
+
+  // Decimals don't end a sentence.
+  let snippet = best_snippet(content, "version friday");
+  assert_eq!(snippet.text, "Version 3.5 ships on Friday!");
+
+  // Without a match, the first sentence is kept.
+  let snippet = best_snippet(content, "kiwi");
+  assert_eq!(snippet.text, "AppFlowy is a workspace.");
+  assert!(snippet.highlight_ranges.is_empty());
+  assert_eq!(snippet.score, 0.0);
+}
+
+#[test]
+fn best_snippet_approximate_match_test() {
+  let content = "The banana harvest starts in May.\nA workspace keeps notes.";
+  // Plurals and typos still match, for less than an exact word.
+  let plural = best_snippet(content, "bananas");
+  assert_eq!(plural.text, "The banana harvest starts in May.");
+  assert_eq!(highlighted(&plural), vec!["banana"]);
+  assert_eq!(plural.score, 0.75);
+  let typo = best_snippet(content, "workspase");
+  assert_eq!(typo.text, "A workspace keeps notes.");
+  assert_eq!(typo.score, 0.5);
+  // Common words of the query are ignored.
+  assert_eq!(best_snippet(content, "the a").score, 0.0);
+}
+
+#[test]
+fn best_snippet_unicode_test() {
+  let content = "Café au lait 🍌 mit Bananen. 香蕉是黄色的。Ünïcödé BANANEN schmecken süß!";
+  for query in ["bananen süß", "香蕉是黄色的", "café", "ünïcödé"] {
+    let snippet = best_snippet(content, query);
+    assert!(!snippet.highlight_ranges.is_empty(), "{}", query);
+    for range in &snippet.highlight_ranges {
+      assert!(snippet.text.is_char_boundary(range.start), "{}", query);
+      assert!(snippet.text.is_char_boundary(range.end), "{}", query);
+    }
+  }
+  let snippet = best_snippet(content, "bananen süß");
+  assert_eq!(snippet.text, "Ünïcödé BANANEN schmecken süß!");
+  assert_eq!(highlighted(&snippet), vec!["BANANEN", "süß"]);
+  assert_eq!(best_snippet(content, "香蕉是黄色的").text, "香蕉是黄色的。");
+}
+
+#[test]
+fn best_snippet_long_sentence_test() {
+  let filler = "ü".repeat(30);
+  let content = format!(
+    "{} and {} before the banana, then {} after it",
+    filler,
+    filler,
+    [filler.as_str(); 10].join(" ")
+  );
+  let snippet = best_snippet(&content, "banana");
+  assert!(snippet.text.starts_with('…'));
+  assert!(snippet.text.ends_with('…'));
+  // Ellipses excluded.
+  assert_eq!(snippet.text.chars().count(), SNIPPET_MAX_CHARS + 2);
+  assert_eq!(highlighted(&snippet), vec!["banana"]);
+}
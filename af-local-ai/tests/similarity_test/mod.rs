@@ -0,0 +1,95 @@
+use af_local_ai::similarity::{cosine_similarity, flatten_embeddings, rerank, SimilarityError};
+
+fn assert_close(actual: f64, expected: f64) {
+  assert!(
+    (actual - expected).abs() < 1e-9,
+    "expected {}, got {}",
+    expected,
+    actual
+  );
+}
+
+#[test]
+fn cosine_similarity_of_orthogonal_vectors_test() {
+  assert_close(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).unwrap(), 0.0);
+  assert_close(
+    cosine_similarity(&[1.0, 2.0, 0.0], &[-2.0, 1.0, 5.0]).unwrap(),
+    0.0,
+  );
+}
+
+#[test]
+fn cosine_similarity_of_identical_vectors_test() {
+  assert_close(
+    cosine_similarity(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]).unwrap(),
+    1.0,
+  );
+  // Only the direction counts.
+  assert_close(
+    cosine_similarity(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]).unwrap(),
+    1.0,
+  );
+}
+
+#[test]
+fn cosine_similarity_of_opposite_vectors_test() {
+  assert_close(cosine_similarity(&[1.0, -2.0], &[-3.0, 6.0]).unwrap(), -1.0);
+}
+
+#[test]
+fn cosine_similarity_at_an_angle_test() {
+  // (3, 4) . (4, 3) = 24, and both have a norm of 5.
+  assert_close(cosine_similarity(&[3.0, 4.0], &[4.0, 3.0]).unwrap(), 0.96);
+}
+
+#[test]
+fn cosine_similarity_of_mismatched_lengths_test() {
+  assert_eq!(
+    cosine_similarity(&[1.0, 2.0, 3.0], &[1.0, 2.0]),
+    Err(SimilarityError::LengthMismatch { left: 3, right: 2 })
+  );
+}
+
+#[test]
+fn cosine_similarity_of_zero_vectors_test() {
+  assert_eq!(
+    cosine_similarity(&[0.0, 0.0], &[1.0, 2.0]),
+    Err(SimilarityError::ZeroVector)
+  );
+  assert_eq!(
+    cosine_similarity(&[], &[]),
+    Err(SimilarityError::ZeroVector)
+  );
+}
+
+#[test]
+fn flatten_embeddings_test() {
+  assert_eq!(
+    flatten_embeddings(vec![vec![1.0, 2.0], vec![], vec![3.0]]),
+    vec![1.0, 2.0, 3.0]
+  );
+}
+
+#[test]
+fn rerank_test() {
+  let candidates = vec![
+    ("orthogonal", vec![0.0, 1.0]),
+    ("identical", vec![2.0, 0.0]),
+    ("mismatched", vec![1.0, 0.0, 0.0]),
+    ("angled", vec![1.0, 1.0]),
+    ("opposite", vec![-1.0, 0.0]),
+    ("zero", vec![0.0, 0.0]),
+  ];
+
+  let ranked = rerank(&[1.0, 0.0], &candidates, 3);
+  let names = ranked.iter().map(|(name, _)| **name).collect::<Vec<_>>();
+  assert_eq!(names, vec!["identical", "angled", "orthogonal"]);
+  assert_close(ranked[0].1, 1.0);
+  assert_close(ranked[1].1, std::f64::consts::FRAC_1_SQRT_2);
+  assert_close(ranked[2].1, 0.0);
+
+  let all = rerank(&[1.0, 0.0], &candidates, 10);
+  assert_eq!(all.len(), 4);
+  assert_eq!(*all[3].0, "opposite");
+  assert!(rerank(&[1.0, 0.0], &candidates, 0).is_empty());
+}
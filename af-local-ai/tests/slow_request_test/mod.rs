@@ -0,0 +1,80 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::slow_request::OperationKind;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn fake_slow_request_test() {
+  let mut slow_stream = answer_stream(&["Bananas", " are", " yellow"]);
+  slow_stream["delay_ms"] = json!(60);
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "answer",
+      vec![
+        json!({ "result": { "data": "slow" }, "delay_ms": 300 }),
+        json!({ "result": { "data": "fast" } }),
+      ],
+    )
+    .with_replies("stream_answer_v2", vec![slow_stream]);
+  let harness = TestPluginHarness::new(scenario).await;
+  assert_eq!(
+    harness
+      .ollama_plugin
+      .slow_request_threshold(OperationKind::EmbedFile),
+    Duration::from_secs(30)
+  );
+  harness
+    .ollama_plugin
+    .set_slow_request_threshold(OperationKind::Chat, Duration::from_millis(150));
+  let reported = Arc::new(parking_lot::Mutex::new(Vec::new()));
+  let sink = reported.clone();
+  harness
+    .ollama_plugin
+    .on_slow_request(move |request| sink.lock().push(request.clone()));
+
+  let answer = harness
+    .ollama_plugin
+    .ask_question("chat", "what color are bananas?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "slow");
+  let answer = harness
+    .ollama_plugin
+    .ask_question("chat", "what color are bananas?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "fast");
+  {
+    let reported = reported.lock();
+    assert_eq!(reported.len(), 1);
+    assert_eq!(reported[0].method, "answer");
+    assert_eq!(reported[0].kind, OperationKind::Chat);
+    assert!(reported[0].duration >= Duration::from_millis(300));
+    assert!(reported[0].chat_hash.is_some());
+    assert!(reported[0].payload_bytes > 0);
+  }
+
+  // Streams are timed until their last frame.
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what color are bananas?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Bananas are yellow");
+  for _ in 0..50 {
+    if reported.lock().len() == 2 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+  let methods = reported
+    .lock()
+    .iter()
+    .map(|request| request.method.clone())
+    .collect::<Vec<_>>();
+  assert_eq!(methods, vec!["answer", "stream_answer_v2"]);
+  let diagnostics = harness.ollama_plugin.diagnostics().await;
+  assert_eq!(diagnostics.slow_requests, *reported.lock());
+}
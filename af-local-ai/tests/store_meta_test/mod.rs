@@ -0,0 +1,144 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_local_ai::embedding_ops::MigrationProgress;
+use af_local_ai::store_meta::{read_store_meta, write_store_meta, StoreMeta, StoreMigration};
+use af_plugin::error::PluginError;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio_stream::StreamExt;
+
+fn store_format_info(store_format_version: u32) -> Value {
+  json!({ "result": { "data": {
+    "version": "0.4.0",
+    "protocol_version": 6,
+    "store_format_version": store_format_version,
+  } } })
+}
+
+#[tokio::test]
+async fn fake_store_meta_test() {
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies("system_info", vec![store_format_info(2)]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let dir = tempfile::tempdir().unwrap();
+  let mut config = harness.config();
+  config.set_rag_enabled(&dir.path().to_path_buf()).unwrap();
+  let embedding_model = config.embedding_model_name.clone();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  assert_eq!(
+    read_store_meta(dir.path()).unwrap(),
+    Some(StoreMeta {
+      store_format_version: 2,
+      embedding_model,
+      created_by: "0.4.0".to_string(),
+    })
+  );
+  assert_eq!(harness.ollama_plugin.pending_store_migration(), None);
+  // Nothing to migrate, the stream ends at once.
+  let mut stream = harness.ollama_plugin.migrate_vector_store().await.unwrap();
+  assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn fake_store_migration_test() {
+  let progress = vec![
+    json!(json!({ "migrated": 1, "total": 2 }).to_string()),
+    json!(json!({ "migrated": 2, "total": 2 }).to_string()),
+  ];
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies("system_info", vec![store_format_info(2)])
+    .with_replies("vs_migrate", vec![json!({ "stream": progress })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let dir = tempfile::tempdir().unwrap();
+  let old_meta = StoreMeta {
+    store_format_version: 1,
+    embedding_model: "nomic-embed-text".to_string(),
+    created_by: "0.3.0".to_string(),
+  };
+  write_store_meta(dir.path(), &old_meta).unwrap();
+  let mut config = harness.config();
+  config.set_rag_enabled(&dir.path().to_path_buf()).unwrap();
+  let embedding_model = config.embedding_model_name.clone();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  assert_eq!(
+    harness.ollama_plugin.pending_store_migration(),
+    Some(StoreMigration { from: 1, to: 2 })
+  );
+  let search = harness
+    .ollama_plugin
+    .similarity_search("bananas", HashMap::new())
+    .await;
+  assert!(matches!(
+    search,
+    Err(PluginError::StoreNeedsMigration { from: 1, to: 2 })
+  ));
+
+  let stream = harness.ollama_plugin.migrate_vector_store().await.unwrap();
+  let frames = stream
+    .map(Result::unwrap)
+    .collect::<Vec<MigrationProgress>>()
+    .await;
+  assert_eq!(
+    frames,
+    vec![
+      MigrationProgress {
+        migrated: 1,
+        total: 2
+      },
+      MigrationProgress {
+        migrated: 2,
+        total: 2
+      },
+    ]
+  );
+  let meta = read_store_meta(dir.path()).unwrap().unwrap();
+  assert_eq!(meta.store_format_version, 2);
+  assert_eq!(meta.embedding_model, embedding_model);
+  assert_eq!(meta.created_by, "0.4.0");
+  assert_eq!(harness.ollama_plugin.pending_store_migration(), None);
+  harness
+    .ollama_plugin
+    .similarity_search("bananas", HashMap::new())
+    .await
+    .unwrap();
+
+  let migrate = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "vs_migrate")
+    .unwrap();
+  assert_eq!(migrate["params"], json!({ "from": 1, "to": 2 }));
+}
+
+#[tokio::test]
+async fn fake_store_needs_reindex_test() {
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies("system_info", vec![store_format_info(3)]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let dir = tempfile::tempdir().unwrap();
+  let old_meta = StoreMeta {
+    store_format_version: 1,
+    embedding_model: "nomic-embed-text".to_string(),
+    created_by: "0.2.0".to_string(),
+  };
+  write_store_meta(dir.path(), &old_meta).unwrap();
+  let mut config = harness.config();
+  config.set_rag_enabled(&dir.path().to_path_buf()).unwrap();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  // The plugin doesn't know `vs_migrate`.
+  let result = harness.ollama_plugin.migrate_vector_store().await;
+  assert!(matches!(
+    result,
+    Err(PluginError::StoreNeedsReindex { from: 1, to: 3 })
+  ));
+  assert_eq!(
+    harness.ollama_plugin.pending_store_migration(),
+    Some(StoreMigration { from: 1, to: 3 })
+  );
+  assert_eq!(read_store_meta(dir.path()).unwrap(), Some(old_meta));
+}
@@ -0,0 +1,566 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+#[cfg(unix)]
+use crate::util::is_process_alive;
+use crate::util::{collect_completion_stream, collect_json_stream};
+use af_local_ai::ai_ops::CompleteTextType;
+use af_local_ai::pausable::DEFAULT_PAUSE_BUFFER_BYTES;
+use af_plugin::core::plugin::RunningState;
+use af_plugin::core::stream::StreamOptions;
+use af_plugin::core::stream_error::StreamErrorKind;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn chat_stream_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["Banana", " is", " a fruit"])],
+    )
+    .with_replies(
+      "related_question",
+      vec![json!({ "result": { "data": [
+        { "content": "Where do bananas grow?" },
+        { "content": "Are bananas healthy?" },
+      ] } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  assert_eq!(
+    harness.ollama_plugin.plugin_info().await.unwrap().version,
+    "fake"
+  );
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Banana is a fruit");
+
+  let questions = harness
+    .ollama_plugin
+    .get_related_question("chat")
+    .await
+    .unwrap();
+  assert_eq!(
+    questions,
+    vec!["Where do bananas grow?", "Are bananas healthy?"]
+  );
+
+  let request = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "stream_answer_v2")
+    .unwrap();
+  assert_eq!(request["params"]["chat_id"], "chat");
+  assert_eq!(request["params"]["data"]["content"], "what is banana?");
+}
+
+#[tokio::test]
+async fn completion_text_v2_test() {
+  let frames = vec![
+    json!({ "1": "He and I were going" }).to_string(),
+    json!({ "1": " to the store", "4": "Fixed the subject." }).to_string(),
+  ];
+  let scenario =
+    FakeScenario::new().with_replies("complete_text_v2", vec![json!({ "stream": frames })]);
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(
+      "Me and him was going to the store",
+      CompleteTextType::SpellingAndGrammar as u8,
+      None,
+      None,
+    )
+    .await
+    .unwrap();
+  let (answer, comment) = collect_completion_stream(stream).await;
+  assert_eq!(answer, "He and I were going to the store");
+  assert_eq!(comment, "Fixed the subject.");
+}
+
+#[tokio::test]
+async fn stream_cancel_test() {
+  let words = [
+    "one", " two", " three", " four", " five", " six", " seven", " eight",
+  ];
+  let mut slow_stream = answer_stream(&words);
+  slow_stream["delay_ms"] = json!(50);
+  let scenario = FakeScenario::new()
+    .with_replies("stream_answer_v2", vec![slow_stream])
+    .with_replies(
+      "answer",
+      vec![json!({ "result": { "data": "still here" } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let mut stream = harness
+    .ollama_plugin
+    .stream_question("chat", "count to eight", None, json!({}))
+    .await
+    .unwrap();
+  let first = stream.next().await.unwrap().unwrap();
+  assert_eq!(first["1"], "one");
+  drop(stream);
+
+  // The rest of the cancelled answer is discarded without disturbing other requests.
+  let answer = harness
+    .ollama_plugin
+    .ask_question("chat", "are you there?")
+    .await
+    .unwrap();
+  assert_eq!(answer, "still here");
+  tokio::time::sleep(Duration::from_millis(500)).await;
+  assert!(harness
+    .ollama_plugin
+    .get_plugin_running_state()
+    .is_running());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn shutdown_all_test() {
+  let mut slow_stream = answer_stream(&["Once", " upon", " a", " time"]);
+  slow_stream["delay_ms"] = json!(1000);
+  let scenario = FakeScenario::new().with_replies("stream_answer_v2", vec![slow_stream]);
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let pid = harness
+    .ollama_plugin
+    .get_ai_plugin()
+    .await
+    .unwrap()
+    .upgrade()
+    .unwrap()
+    .process_id();
+  assert!(is_process_alive(pid));
+
+  let mut stream = harness
+    .ollama_plugin
+    .stream_question("chat", "write a long story about bananas", None, json!({}))
+    .await
+    .unwrap();
+  let _ = stream.next().await;
+
+  let report = harness
+    .plugin_manager
+    .shutdown_all(Duration::from_secs(5))
+    .await;
+  assert_eq!(report.exited.len() + report.killed.len(), 1);
+  assert!(!is_process_alive(pid));
+
+  // The stream was cancelled, so it must end instead of waiting for more data.
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while stream.next().await.is_some() {}
+  })
+  .await
+  .unwrap();
+}
+
+#[tokio::test]
+async fn disconnect_recovery_test() {
+  let mut broken_stream = answer_stream(&["Hello", " world", " again"]);
+  broken_stream["disconnect_after"] = json!(2);
+  let scenario = FakeScenario::new().with_replies("stream_answer_v2", vec![broken_stream]);
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let mut state = harness.ollama_plugin.subscribe_running_state();
+  let mut stream = harness
+    .ollama_plugin
+    .stream_question("chat", "hello", None, json!({}))
+    .await
+    .unwrap();
+  let mut answer = String::new();
+  let mut error = None;
+  while let Some(frame) = stream.next().await {
+    match frame {
+      Ok(frame) => answer.push_str(frame["1"].as_str().unwrap_or_default()),
+      Err(err) => error = Some(err),
+    }
+  }
+  assert_eq!(answer, "Hello world");
+  let error = error.unwrap();
+  assert_eq!(error.stream_error_kind(), Some(StreamErrorKind::PluginDied));
+  assert!(matches!(error.root_cause(), PluginError::PeerDisconnect));
+
+  // The plugin reports the unexpected stop, then stopped once its read loop exits.
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while let Some(state) = state.next().await {
+      if matches!(
+        state,
+        RunningState::UnexpectedStop { .. } | RunningState::Stopped { .. }
+      ) {
+        break;
+      }
+    }
+  })
+  .await
+  .unwrap();
+
+  let scenario =
+    FakeScenario::new().with_replies("stream_answer_v2", vec![answer_stream(&["Recovered"])]);
+  harness.restart_with(scenario).await;
+  let stream = harness
+    .ollama_plugin
+    .stream_question("chat", "hello", None, json!({}))
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "Recovered");
+}
+
+#[tokio::test]
+async fn fake_resume_interrupted_answer_test() {
+  let interrupted = json!({ "code": -32003, "message": "connection to ollama reset" });
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![json!({
+        "stream": [
+          json!({ "1": "Bananas grow" }).to_string(),
+          json!({ "1": " in tropical regions" }).to_string(),
+        ],
+        "error": interrupted,
+      })],
+    )
+    .with_replies(
+      "continue_answer",
+      vec![
+        // Repeats the end of the answer, which must not show up twice.
+        json!({
+          "stream": [
+            json!({ "1": "tropical" }).to_string(),
+            json!({ "1": " regions and" }).to_string(),
+          ],
+          "error": interrupted,
+        }),
+        answer_stream(&[" are picked green."]),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let options = StreamOptions::default().with_resume_on_error(true);
+  let stream = harness
+    .ollama_plugin
+    .stream_question_with_options("chat", "where do bananas grow?", None, json!({}), options)
+    .await
+    .unwrap();
+  assert_eq!(
+    collect_json_stream(stream).await,
+    "Bananas grow in tropical regions and are picked green."
+  );
+
+  let continuations = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "continue_answer")
+    .map(|request| request["params"].clone())
+    .collect::<Vec<_>>();
+  assert_eq!(continuations.len(), 2);
+  assert_eq!(continuations[0]["chat_id"], "chat");
+  assert_eq!(
+    continuations[0]["data"]["content"],
+    "where do bananas grow?"
+  );
+  assert_eq!(
+    continuations[0]["received"],
+    "Bananas grow in tropical regions"
+  );
+  assert_eq!(
+    continuations[1]["received"],
+    "Bananas grow in tropical regions and"
+  );
+}
+
+#[tokio::test]
+async fn fake_resume_gives_up_test() {
+  let interrupted = json!({
+    "stream": [json!({ "1": "Bananas" }).to_string()],
+    "error": { "code": -32003, "message": "connection to ollama reset" },
+  });
+  let scenario = FakeScenario::new()
+    .with_replies("stream_answer_v2", vec![interrupted.clone()])
+    .with_replies("continue_answer", vec![interrupted]);
+  let harness = TestPluginHarness::new(scenario).await;
+
+  // Gives up after two continuations.
+  let options = StreamOptions::default().with_resume_on_error(true);
+  let mut stream = harness
+    .ollama_plugin
+    .stream_question_with_options("chat", "what is banana?", None, json!({}), options)
+    .await
+    .unwrap();
+  let mut errors = 0;
+  while let Some(frame) = stream.next().await {
+    if frame.is_err() {
+      errors += 1;
+    }
+  }
+  assert_eq!(errors, 1);
+  let continuations = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .iter()
+      .filter(|request| request["method"] == "continue_answer")
+      .count()
+  };
+  assert_eq!(continuations(&harness), 2);
+
+  // Errors that are not transient end the stream right away.
+  let scenario = FakeScenario::new().with_replies(
+    "stream_answer_v2",
+    vec![json!({
+      "stream": [json!({ "1": "Bananas" }).to_string()],
+      "error": { "code": 1, "message": "model not found" },
+    })],
+  );
+  harness.restart_with(scenario).await;
+  let options = StreamOptions::default().with_resume_on_error(true);
+  let mut stream = harness
+    .ollama_plugin
+    .stream_question_with_options("chat", "what is banana?", None, json!({}), options)
+    .await
+    .unwrap();
+  let mut last = None;
+  while let Some(frame) = stream.next().await {
+    last = Some(frame);
+  }
+  assert!(last.unwrap().is_err());
+  assert_eq!(continuations(&harness), 2);
+}
+
+#[tokio::test]
+async fn fake_trace_id_test() {
+  let scenario = FakeScenario::new()
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Banana"])])
+    .with_replies("complete_text_v2", vec![answer_stream(&["Done"])])
+    .with_replies("answer", vec![json!({ "result": { "data": "Banana" } })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let last_trace_id = |method: &str| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .rev()
+      .find(|request| request["method"] == method)
+      .unwrap()["params"]["trace_id"]
+      .as_str()
+      .unwrap()
+      .to_string()
+  };
+
+  let answer = harness
+    .ollama_plugin
+    .stream_question_traced(
+      "chat",
+      "what is banana?",
+      None,
+      json!({}),
+      StreamOptions::default(),
+    )
+    .await
+    .unwrap();
+  let trace_id = answer.trace_id.clone();
+  assert_eq!(collect_json_stream(answer.into_inner()).await, "Banana");
+  assert_eq!(last_trace_id("stream_answer_v2"), trace_id);
+
+  let completion = harness
+    .ollama_plugin
+    .complete_text_v2_traced("hello", 1, None, None, StreamOptions::default())
+    .await
+    .unwrap();
+  let completion_trace_id = completion.trace_id.clone();
+  collect_completion_stream(completion.into_inner()).await;
+  assert_ne!(completion_trace_id, trace_id);
+  assert_eq!(last_trace_id("complete_text_v2"), completion_trace_id);
+
+  // Every operation gets its own id, even when it isn't returned.
+  harness
+    .ollama_plugin
+    .ask_question("chat", "what is banana?")
+    .await
+    .unwrap();
+  let ask_trace_id = last_trace_id("answer");
+  assert!(
+    uuid::Uuid::parse_str(&ask_trace_id).is_ok(),
+    "{}",
+    ask_trace_id
+  );
+  assert_ne!(ask_trace_id, trace_id);
+}
+
+#[tokio::test]
+async fn fake_pause_stream_test() {
+  let words = [
+    "one", " two", " three", " four", " five", " six", " seven", " eight",
+  ];
+  let mut slow_stream = answer_stream(&words);
+  slow_stream["delay_ms"] = json!(20);
+  let scenario = FakeScenario::new().with_replies(
+    "stream_answer_v2",
+    vec![slow_stream.clone(), slow_stream.clone(), slow_stream],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let baseline = harness
+    .ollama_plugin
+    .stream_question("chat", "count to eight", None, json!({}))
+    .await
+    .unwrap();
+  let baseline = collect_json_stream(baseline).await;
+  assert_eq!(baseline, words.concat());
+
+  for max_buffered_bytes in [DEFAULT_PAUSE_BUFFER_BYTES, 1] {
+    let mut stream = harness
+      .ollama_plugin
+      .stream_question_pausable(
+        "chat",
+        "count to eight",
+        None,
+        json!({}),
+        max_buffered_bytes,
+      )
+      .await
+      .unwrap();
+    let mut answer = String::new();
+    let first = stream.next().await.unwrap().unwrap();
+    answer.push_str(first["1"].as_str().unwrap());
+
+    // The plugin finishes the answer while nothing is yielded.
+    stream.pause();
+    assert!(
+      tokio::time::timeout(Duration::from_millis(400), stream.next())
+        .await
+        .is_err()
+    );
+    stream.resume();
+
+    let mut frames = 0;
+    while let Some(frame) = stream.next().await {
+      answer.push_str(frame.unwrap()["1"].as_str().unwrap());
+      frames += 1;
+    }
+    assert_eq!(answer, baseline, "buffer of {} bytes", max_buffered_bytes);
+    // Past the buffer size, the buffered frames are merged.
+    if max_buffered_bytes == 1 {
+      assert!(frames < words.len() - 1, "{} frames", frames);
+    }
+  }
+}
+
+#[tokio::test]
+async fn fake_stream_error_kind_test() {
+  let failing_stream = |frames: &[&str], error: serde_json::Value| {
+    let mut reply = answer_stream(frames);
+    reply["error"] = error;
+    reply
+  };
+  let scenario = FakeScenario::new().with_replies(
+    "stream_answer_v2",
+    vec![
+      failing_stream(
+        &["Hello"],
+        json!({ "code": -32603, "message": "the input length exceeds the context length" }),
+      ),
+      failing_stream(
+        &[],
+        json!({ "code": -32603, "message": "[Errno 111] Connection refused" }),
+      ),
+      json!({ "stream": ["{\"1\": \"Hel", "{\"1\":\"lo\"}"] }),
+      failing_stream(&[], json!({ "code": 1, "message": "model not loaded" })),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  for expected in [
+    StreamErrorKind::ContextLengthExceeded,
+    StreamErrorKind::OllamaUnreachable,
+    StreamErrorKind::MalformedFrame,
+    StreamErrorKind::Unknown,
+  ] {
+    let mut stream = harness
+      .ollama_plugin
+      .stream_question("chat", "hello", None, json!({}))
+      .await
+      .unwrap();
+    let mut kinds = vec![];
+    while let Some(frame) = stream.next().await {
+      if let Err(err) = frame {
+        kinds.push(err.stream_error_kind());
+      }
+    }
+    assert_eq!(kinds, vec![Some(expected)]);
+  }
+}
+
+#[tokio::test]
+async fn fake_keep_alive_test() {
+  let mut silent_answer = answer_stream(&["Bananas", " are yellow"]);
+  silent_answer["delay_ms"] = json!(500);
+  let scenario = FakeScenario::new()
+    .with_replies("stream_answer_v2", vec![silent_answer])
+    .with_replies("ping", vec![json!({ "result": {} })]);
+  let harness = TestPluginHarness::unstarted(scenario);
+  let config = harness
+    .config()
+    .with_warming_up_threshold(Duration::from_secs(10))
+    .with_keep_alive_interval(Some(Duration::from_millis(150)));
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let frames = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap()
+    .collect::<Vec<_>>()
+    .await
+    .into_iter()
+    .map(Result::unwrap)
+    .collect::<Vec<_>>();
+  let keep_alive = json!({ "0": { "keep_alive": true } });
+  assert_eq!(frames[0], keep_alive);
+  let answer = frames
+    .iter()
+    .filter(|frame| **frame != keep_alive)
+    .collect::<Vec<_>>();
+  assert_eq!(
+    answer,
+    vec![&json!({ "1": "Bananas" }), &json!({ "1": " are yellow" })]
+  );
+  let pings = harness
+    .handled_requests()
+    .into_iter()
+    .filter(|request| request["method"] == "ping")
+    .count();
+  assert!(pings >= 2, "{} pings", pings);
+}
+
+#[tokio::test]
+async fn fake_keep_alive_plugin_died_test() {
+  let mut silent_answer = answer_stream(&["Bananas"]);
+  silent_answer["delay_ms"] = json!(2000);
+  let scenario = FakeScenario::new()
+    .with_replies("stream_answer_v2", vec![silent_answer])
+    .with_replies("ping", vec![json!({ "result": {}, "delay_ms": 2000 })]);
+  let harness = TestPluginHarness::unstarted(scenario);
+  let config = harness
+    .config()
+    .with_warming_up_threshold(Duration::from_secs(10))
+    .with_keep_alive_interval(Some(Duration::from_millis(100)));
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let started = Instant::now();
+  let frames = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap()
+    .collect::<Vec<_>>()
+    .await;
+  // Two unanswered pings end the stream long before the answer comes.
+  assert!(started.elapsed() < Duration::from_millis(1500));
+  assert_eq!(frames.len(), 1);
+  let err = frames[0].as_ref().unwrap_err();
+  assert_eq!(err.stream_error_kind(), Some(StreamErrorKind::PluginDied));
+  assert!(matches!(err.root_cause(), PluginError::RequestTimeout(_)));
+}
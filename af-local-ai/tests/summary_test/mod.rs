@@ -0,0 +1,169 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::ai_ops::ChatSettings;
+use af_local_ai::summary::SummaryLength;
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::time::Duration;
+
+#[tokio::test]
+async fn fake_summarize_chat_test() {
+  let history = json!({ "result": { "data": [
+    { "role": "human", "content": "How often should I water a cactus?" },
+    { "role": "ai", "content": "Every two to three weeks." },
+  ] } });
+  let scenario = FakeScenario::new()
+    .with_replies("get_chat_history", vec![history.clone()])
+    .with_replies(
+      "chat_summary",
+      vec![json!({ "result": { "data": "Watering a cactus." } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let summary = harness
+    .ollama_plugin
+    .summarize_chat("chat", SummaryLength::Brief)
+    .await
+    .unwrap();
+  assert_eq!(summary, "Watering a cactus.");
+  let request = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "chat_summary")
+    .unwrap();
+  assert_eq!(request["params"]["chat_id"], "chat");
+  assert_eq!(request["params"]["length"], "brief");
+
+  // Without `chat_summary`, the history is summarized with a completion.
+  let scenario = FakeScenario::new()
+    .with_replies("get_chat_history", vec![history])
+    .with_replies(
+      "complete_text_v2",
+      vec![answer_stream(&["- Watering", " a cactus"])],
+    );
+  harness.restart_with(scenario).await;
+  let summary = harness
+    .ollama_plugin
+    .summarize_chat("chat", SummaryLength::Bullets)
+    .await
+    .unwrap();
+  assert_eq!(summary, "- Watering a cactus");
+  let stream = harness
+    .ollama_plugin
+    .summarize_chat_stream("chat", SummaryLength::Detailed)
+    .await
+    .unwrap();
+  assert_eq!(collect_json_stream(stream).await, "- Watering a cactus");
+  let prompt = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "complete_text_v2")
+    .unwrap()["params"]["text"]
+    .as_str()
+    .unwrap()
+    .to_string();
+  assert!(prompt.contains("bullet points"), "{}", prompt);
+  assert!(prompt.contains("human: How often should I water a cactus?"));
+
+  // An empty chat is rejected without asking the model.
+  let model_requests = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .iter()
+      .filter(|request| {
+        request["method"] == "chat_summary" || request["method"] == "complete_text_v2"
+      })
+      .count()
+  };
+  let before = model_requests(&harness);
+  let scenario = FakeScenario::new().with_replies(
+    "get_chat_history",
+    vec![json!({ "result": { "data": [] } })],
+  );
+  harness.restart_with(scenario).await;
+  let err = harness
+    .ollama_plugin
+    .summarize_chat("empty", SummaryLength::Brief)
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::EmptyChat { .. }), "{:?}", err);
+  assert_eq!(model_requests(&harness), before);
+}
+
+#[tokio::test]
+async fn fake_auto_summarize_chat_test() {
+  let history = (1..=12)
+    .flat_map(|turn| {
+      [
+        json!({ "role": "human", "content": format!("Question {}", turn) }),
+        json!({ "role": "ai", "content": format!("Answer {}", turn) }),
+      ]
+    })
+    .collect::<Vec<_>>();
+  let scenario = FakeScenario::new()
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Sure."])])
+    .with_replies(
+      "get_chat_history",
+      vec![json!({ "result": { "data": history } })],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![answer_stream(&["- The first questions"])],
+    )
+    .with_replies("replace_history_prefix", vec![json!({ "result": {} })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  harness
+    .ollama_plugin
+    .create_chat_with_settings(
+      "chat",
+      ChatSettings {
+        auto_summarize_after_turns: 10,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+  let replaced = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .into_iter()
+      .filter(|request| request["method"] == "replace_history_prefix")
+      .collect::<Vec<_>>()
+  };
+  for turn in 1..=12 {
+    let stream = harness
+      .ollama_plugin
+      .stream_question("chat", &format!("Question {}", turn), None, json!({}))
+      .await
+      .unwrap();
+    // The summary runs in the background, the answers are streamed as usual.
+    assert_eq!(collect_json_stream(stream).await, "Sure.");
+  }
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while replaced(&harness).is_empty() {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  })
+  .await
+  .unwrap();
+  tokio::time::sleep(Duration::from_millis(200)).await;
+
+  // Passing the threshold on the 11th turn summarizes the oldest 6 turns, the latest 5 are
+  // kept, so the 12th turn stays under the threshold.
+  let replaced = replaced(&harness);
+  assert_eq!(replaced.len(), 1, "{:?}", replaced);
+  assert_eq!(
+    replaced[0]["params"],
+    json!({ "chat_id": "chat", "turns": 6, "summary": "- The first questions" })
+  );
+  let prompt = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "complete_text_v2")
+    .unwrap()["params"]["text"]
+    .as_str()
+    .unwrap()
+    .to_string();
+  assert!(prompt.contains("human: Question 6"), "{}", prompt);
+  assert!(!prompt.contains("Question 7"), "{}", prompt);
+}
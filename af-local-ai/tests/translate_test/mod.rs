@@ -0,0 +1,192 @@
+use crate::harness::{answer_stream, requests_of, FakeScenario, TestPluginHarness};
+use af_local_ai::ai_ops::{
+  LocalAITranslateItem, LocalAITranslateRowData, LocalAITranslateRowResponse,
+};
+use af_local_ai::prepared_request::QuestionRequestOptions;
+use af_local_ai::translate::{translation_prompt, TranslateRowFrame, TranslatedFrame};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_translate_database_row_stream_test() {
+  let cell = |index: usize, title: &str, content: &str| {
+    json!(json!({ "index": index, "title": title, "content": content }).to_string())
+  };
+  // The plugin finishes the short cells first.
+  let scenario = FakeScenario::new().with_replies(
+    "database_translate_stream",
+    vec![json!({ "stream": [
+      cell(1, "评分", "8"),
+      cell(2, "完成阅读日期", "2023-02-10"),
+      cell(0, "书名", "原子习惯"),
+    ] })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let row = || LocalAITranslateRowData {
+    cells: vec![
+      LocalAITranslateItem {
+        title: "book name".to_string(),
+        content: "Atomic Habits".to_string(),
+      },
+      LocalAITranslateItem {
+        title: "score".to_string(),
+        content: "8".to_string(),
+      },
+      LocalAITranslateItem {
+        title: "finish reading at".to_string(),
+        content: "2023-02-10".to_string(),
+      },
+    ],
+    language: "chinese".to_string(),
+    include_header: false,
+  };
+  let expected = LocalAITranslateRowResponse {
+    items: vec![
+      HashMap::from([("书名".to_string(), "原子习惯".to_string())]),
+      HashMap::from([("评分".to_string(), "8".to_string())]),
+      HashMap::from([("完成阅读日期".to_string(), "2023-02-10".to_string())]),
+    ],
+  };
+
+  let frames = harness
+    .ollama_plugin
+    .translate_database_row_stream(row())
+    .await
+    .unwrap()
+    .map(Result::unwrap)
+    .collect::<Vec<_>>()
+    .await;
+  let indexes = frames
+    .iter()
+    .filter_map(|frame| match frame {
+      TranslateRowFrame::Cell(cell) => Some(cell.index),
+      TranslateRowFrame::Done(_) => None,
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(indexes, vec![0, 1, 2]);
+  assert_eq!(frames.len(), 4);
+  assert_eq!(frames[3], TranslateRowFrame::Done(expected.clone()));
+
+  // Without `database_translate_stream`, the row arrives in a single frame.
+  let scenario = FakeScenario::new().with_replies(
+    "database_translate",
+    vec![json!({ "result": { "data": expected } })],
+  );
+  harness.restart_with(scenario).await;
+  let frames = harness
+    .ollama_plugin
+    .translate_database_row_stream(row())
+    .await
+    .unwrap()
+    .map(Result::unwrap)
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(frames, vec![TranslateRowFrame::Done(expected)]);
+}
+
+/// A `complete_text_v2` reply translating `text` to French.
+fn french_translation(text: &str, translated: &str, delay_ms: u64) -> Value {
+  let mut reply = answer_stream(&[translated]);
+  reply["when"] = json!({ "text": translation_prompt(text, "French") });
+  reply["delay_ms"] = json!(delay_ms);
+  reply
+}
+
+#[tokio::test]
+async fn fake_stream_question_translated_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["Hello world. How", " are you?\n", "Bye"])],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![
+        french_translation("Hello world.", "Bonjour le monde.", 300),
+        french_translation("How are you?", "Comment ça va ?", 100),
+        french_translation("Bye", "Au revoir", 100),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let started = Instant::now();
+  let stream = harness
+    .ollama_plugin
+    .stream_question_translated(
+      "chat",
+      "say hello",
+      "French",
+      QuestionRequestOptions::default(),
+    )
+    .await
+    .unwrap();
+  let frames = stream.map(|frame| frame.unwrap()).collect::<Vec<_>>().await;
+
+  // The first segment is translated last, yet its frame comes first.
+  let pairs = frames
+    .iter()
+    .map(|frame| (frame.original.as_str(), frame.translated.as_str()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    pairs,
+    vec![
+      ("Hello world.", "Bonjour le monde."),
+      (" How are you?", " Comment ça va ?"),
+      ("\nBye", "\nAu revoir"),
+    ]
+  );
+  assert!(frames.iter().all(|frame| !frame.translation_failed));
+  // Two segments are translated at once, so the last one waits for the first.
+  assert!(started.elapsed() >= Duration::from_millis(400));
+  assert_eq!(requests_of(&harness, "complete_text_v2").len(), 3);
+}
+
+#[tokio::test]
+async fn fake_stream_question_translated_failure_test() {
+  let mut failing = json!({ "error": { "code": 1, "message": "model not loaded" } });
+  failing["when"] = json!({ "text": translation_prompt("First line.", "French") });
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![answer_stream(&["First line.\n", "Second line."])],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![
+        failing,
+        french_translation("Second line.", "Deuxième ligne.", 0),
+      ],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let stream = harness
+    .ollama_plugin
+    .stream_question_translated(
+      "chat",
+      "two lines please",
+      "French",
+      QuestionRequestOptions::default(),
+    )
+    .await
+    .unwrap();
+  let frames = stream.map(|frame| frame.unwrap()).collect::<Vec<_>>().await;
+
+  // The segment that failed to translate is sent as it is, and the stream goes on.
+  assert_eq!(
+    frames,
+    vec![
+      TranslatedFrame {
+        original: "First line.".to_string(),
+        translated: "First line.".to_string(),
+        translation_failed: true,
+      },
+      TranslatedFrame {
+        original: "\nSecond line.".to_string(),
+        translated: "\nDeuxième ligne.".to_string(),
+        translation_failed: false,
+      },
+    ]
+  );
+}
@@ -0,0 +1,102 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::{collect_completion_stream, collect_json_stream};
+use af_local_ai::ai_ops::CompleteTextType;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[tokio::test]
+async fn fake_usage_tracking_test() {
+  use af_local_ai::usage::{TimeRange, UsageKind};
+
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "stream_answer_v2",
+      vec![
+        answer_stream(&["Bananas ", "are yellow."]),
+        answer_stream(&["In the tropics."]),
+      ],
+    )
+    .with_replies(
+      "complete_text_v2",
+      vec![json!({ "stream": [json!({ "1": "Ripe bananas." }).to_string()] })],
+    )
+    .with_replies(
+      "similarity_search",
+      vec![json!({ "result": { "data": ["Bananas grow in the tropics."] } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let dir = tempfile::tempdir().unwrap();
+  let db_path = dir.path().join("usage").join("usage.db");
+  harness
+    .ollama_plugin
+    .enable_usage_tracking(db_path.clone())
+    .unwrap();
+
+  harness.ollama_plugin.create_chat("fruits").await.unwrap();
+  for question in ["What color are bananas?", "Where do they grow?"] {
+    let stream = harness
+      .ollama_plugin
+      .stream_question("fruits", question, None, json!({}))
+      .await
+      .unwrap();
+    collect_json_stream(stream).await;
+  }
+  let stream = harness
+    .ollama_plugin
+    .complete_text_v2(
+      "ripe banana",
+      CompleteTextType::ImproveWriting as u8,
+      None,
+      None,
+    )
+    .await
+    .unwrap();
+  collect_completion_stream(stream).await;
+  harness
+    .ollama_plugin
+    .similarity_search("banana", HashMap::new())
+    .await
+    .unwrap();
+  // The fake plugin has no reply for it.
+  assert!(harness
+    .ollama_plugin
+    .summary_database_row(HashMap::from([("name".to_string(), "banana".to_string())]))
+    .await
+    .is_err());
+
+  let summary = harness
+    .ollama_plugin
+    .usage_summary(TimeRange::last_days(1))
+    .await
+    .unwrap();
+  assert_eq!(
+    summary.per_kind_counts.into_iter().collect::<Vec<_>>(),
+    vec![
+      (UsageKind::Question, 2),
+      (UsageKind::Completion, 1),
+      (UsageKind::Search, 1),
+      (UsageKind::RowSummary, 1),
+    ]
+  );
+  assert_eq!(summary.failures, 1);
+  assert_eq!(summary.busiest_day.unwrap().operations, 5);
+
+  // Only lengths are stored.
+  let db = std::fs::read(&db_path).unwrap();
+  let db = String::from_utf8_lossy(&db);
+  assert!(!db.contains("What color are bananas?"));
+  assert!(!db.contains("fruits"));
+
+  // Ranges without operations are empty.
+  let summary = harness
+    .ollama_plugin
+    .usage_summary(TimeRange::new(
+      std::time::UNIX_EPOCH,
+      std::time::UNIX_EPOCH + Duration::from_secs(1),
+    ))
+    .await
+    .unwrap();
+  assert!(summary.per_kind_counts.is_empty());
+  assert_eq!(summary.busiest_day, None);
+}
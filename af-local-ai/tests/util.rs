@@ -1,12 +1,11 @@
-use af_local_ai::ollama_plugin::{OllamaAIPlugin, OllamaPluginConfig};
+use af_local_ai::ollama_plugin::{LogLevel, OllamaAIPlugin, OllamaPluginConfig};
+use af_local_ai::similarity::{cosine_similarity, flatten_embeddings};
 use af_plugin::error::PluginError;
 use af_plugin::manager::PluginManager;
 use anyhow::Result;
 
 use bytes::Bytes;
 use serde_json::{json, Value};
-use simsimd::SpatialSimilarity;
-use std::f64;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Once};
 use tokio_stream::wrappers::ReceiverStream;
@@ -18,6 +17,7 @@ use tracing_subscriber::EnvFilter;
 
 pub struct LocalAITest {
   config: LocalAIConfiguration,
+  pub plugin_manager: Arc<PluginManager>,
   pub ollama_plugin: OllamaAIPlugin,
 }
 
@@ -27,10 +27,11 @@ impl LocalAITest {
     setup_log();
 
     let config = LocalAIConfiguration::new()?;
-    let sidecar = Arc::new(PluginManager::new());
-    let ollama_plugin = OllamaAIPlugin::new(sidecar.clone());
+    let plugin_manager = Arc::new(PluginManager::new());
+    let ollama_plugin = OllamaAIPlugin::new(plugin_manager.clone());
     Ok(Self {
       config,
+      plugin_manager,
       ollama_plugin,
     })
   }
@@ -47,7 +48,7 @@ impl LocalAITest {
 
     let persist_dir = tempfile::tempdir().unwrap().path().to_path_buf();
     config.set_rag_enabled(&persist_dir).unwrap();
-    config.set_log_level("debug".to_string());
+    config.set_log_level(LogLevel::Debug);
 
     self.ollama_plugin.init_plugin(config).await.unwrap();
   }
@@ -83,30 +84,50 @@ impl LocalAITest {
 
   async fn get_flat_embedding(&self, text: &str) -> Vec<f64> {
     let embedding = self.ollama_plugin.generate_embedding(text).await.unwrap();
-    flatten_vec(embedding)
+    flatten_embeddings(embedding)
   }
 
   pub async fn calculate_similarity(&self, input: &str, expected: &str) -> f64 {
-    // Generate flattened embeddings for both inputs.
-    let mut left_vec = self.get_flat_embedding(input).await;
-    let mut right_vec = self.get_flat_embedding(expected).await;
-
-    // Ensure both vectors have the same length by truncating the longer one.
-    if left_vec.len() != right_vec.len() {
-      let min_len = std::cmp::min(left_vec.len(), right_vec.len());
-      left_vec.truncate(min_len);
-      right_vec.truncate(min_len);
-    }
-
-    // Compute the cosine distance (or angle) and then return the cosine similarity.
-    let angle = f64::cosine(&left_vec, &right_vec).expect("Vectors must be of the same length");
-    angle.cos()
+    let left_vec = self.get_flat_embedding(input).await;
+    let right_vec = self.get_flat_embedding(expected).await;
+    cosine_similarity(&left_vec, &right_vec).unwrap()
   }
 }
 
-// Function to flatten Vec<Vec<f64>> into Vec<f64>
-fn flatten_vec(vec: Vec<Vec<f64>>) -> Vec<f64> {
-  vec.into_iter().flatten().collect()
+/// Returns `true` if a process with the given pid is still alive.
+#[cfg(unix)]
+pub fn is_process_alive(pid: u32) -> bool {
+  std::process::Command::new("kill")
+    .args(["-0", &pid.to_string()])
+    .output()
+    .map(|output| output.status.success())
+    .unwrap_or(false)
+}
+
+/// A plugin that records every line it receives and answers each request with an empty result.
+#[cfg(unix)]
+pub fn write_fake_plugin(dir: &std::path::Path, log: &std::path::Path) -> std::path::PathBuf {
+  use std::os::unix::fs::PermissionsExt;
+
+  let script = dir.join("fake_plugin.sh");
+  std::fs::write(
+    &script,
+    format!(
+      r#"#!/bin/sh
+while IFS= read -r line; do
+  printf '%s\n' "$line" >> "{}"
+  id=$(printf '%s' "$line" | sed -n 's/^{{"id":\([0-9]*\).*/\1/p')
+  if [ -n "$id" ]; then
+    printf '{{"id":%s,"result":{{}}}}\n' "$id"
+  fi
+done
+"#,
+      log.display()
+    ),
+  )
+  .unwrap();
+  std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+  script
 }
 
 pub struct LocalAIConfiguration {
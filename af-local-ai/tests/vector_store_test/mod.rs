@@ -0,0 +1,455 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use crate::util::collect_json_stream;
+use af_local_ai::embedding_manifest::MismatchPolicy;
+use af_local_ai::vector_store::{ImportPolicy, VectorStoreStats, COMPACTION_MARKER_NAME};
+use af_plugin::core::orphan::{read_lock_state, write_lock_file, LockState};
+use af_plugin::error::PluginError;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn fake_vector_store_snapshot_test() {
+  let scenario = FakeScenario::new()
+    .with_replies(
+      "vs_export",
+      vec![json!({ "result": { "data": { "item_count": 2 } } })],
+    )
+    .with_replies("vs_reload", vec![json!({ "result": {} })]);
+  let harness = TestPluginHarness::new(scenario).await;
+  let persist_dir = tempfile::tempdir().unwrap();
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&persist_dir.path().to_path_buf())
+    .unwrap();
+  harness
+    .ollama_plugin
+    .init_plugin(config.clone())
+    .await
+    .unwrap();
+
+  // Stands in for the files of the vector store, one of them with a path longer than a tar name.
+  let segment_dir = persist_dir
+    .path()
+    .join("3f2a6c1e-8d4b-4f7a-9c2e-5b1d0e7a6f93")
+    .join("segment-with-a-rather-long-name-to-exceed-the-name-field");
+  std::fs::create_dir_all(&segment_dir).unwrap();
+  let data = (0..1500u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+  std::fs::write(segment_dir.join("data_level0.bin"), &data).unwrap();
+  std::fs::write(persist_dir.path().join("chroma.sqlite3"), b"sqlite").unwrap();
+
+  let snapshot_dir = tempfile::tempdir().unwrap();
+  let snapshot_path = snapshot_dir.path().join("vectors.tar.zst");
+  let snapshot = harness
+    .ollama_plugin
+    .export_vector_store(&snapshot_path)
+    .await
+    .unwrap();
+  assert_eq!(snapshot.model, "fake-embedding-model");
+  assert_eq!(snapshot.dimension, 3);
+  assert_eq!(snapshot.item_count, 2);
+
+  std::fs::remove_dir_all(persist_dir.path()).unwrap();
+  std::fs::create_dir_all(persist_dir.path()).unwrap();
+  std::fs::write(persist_dir.path().join("stale.bin"), b"stale").unwrap();
+  write_lock_file(persist_dir.path()).unwrap();
+  let imported = harness
+    .ollama_plugin
+    .import_vector_store(&snapshot_path, ImportPolicy::Validate)
+    .await
+    .unwrap();
+  assert_eq!(imported, snapshot);
+  assert_eq!(
+    std::fs::read(segment_dir.join("data_level0.bin")).unwrap(),
+    data
+  );
+  assert_eq!(
+    std::fs::read(persist_dir.path().join("chroma.sqlite3")).unwrap(),
+    b"sqlite"
+  );
+  assert!(!persist_dir.path().join("stale.bin").exists());
+  // The application keeps holding the lock of the replaced directory.
+  assert_eq!(
+    read_lock_state(persist_dir.path()).unwrap(),
+    LockState::Owned
+  );
+  for suffix in [".importing", ".previous"] {
+    let mut sibling = persist_dir.path().as_os_str().to_owned();
+    sibling.push(suffix);
+    assert!(!PathBuf::from(sibling).exists());
+  }
+  let reloads = harness
+    .handled_requests()
+    .iter()
+    .filter(|request| request["method"] == "vs_reload")
+    .count();
+  assert_eq!(reloads, 1);
+
+  // A snapshot of another embedding model is only imported when forced.
+  config.embedding_model_name = "other-embedding-model".to_string();
+  config.set_on_mismatch(MismatchPolicy::Ignore);
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  let err = harness
+    .ollama_plugin
+    .import_vector_store(&snapshot_path, ImportPolicy::Validate)
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::EmbeddingModelChanged { .. }));
+  harness
+    .ollama_plugin
+    .import_vector_store(&snapshot_path, ImportPolicy::Force)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn fake_vector_store_stats_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "vs_stats",
+    vec![json!({ "result": { "data": {
+      "total_documents": 2,
+      "total_chunks": 9,
+      "deleted_chunks": 4,
+    } } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let stats = harness.ollama_plugin.vector_store_stats().await.unwrap();
+  assert_eq!(
+    stats,
+    VectorStoreStats {
+      total_documents: 2,
+      total_chunks: 9,
+      disk_bytes: 0,
+      deleted_chunks: 4,
+    }
+  );
+
+  let persist_dir = tempfile::tempdir().unwrap();
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&persist_dir.path().to_path_buf())
+    .unwrap();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  std::fs::create_dir_all(persist_dir.path().join("segment")).unwrap();
+  std::fs::write(persist_dir.path().join("segment/data.bin"), [0; 1000]).unwrap();
+  std::fs::write(persist_dir.path().join("chroma.sqlite3"), [0; 24]).unwrap();
+  let disk_bytes = harness
+    .ollama_plugin
+    .vector_store_stats()
+    .await
+    .unwrap()
+    .disk_bytes;
+  // The lock file of the persist directory is counted too.
+  assert!((1024..1100).contains(&disk_bytes), "{}", disk_bytes);
+}
+
+#[tokio::test]
+async fn fake_compact_vector_store_test() {
+  let scenario = FakeScenario::new()
+    .with_replies("vs_compact", vec![json!({ "result": {}, "delay_ms": 300 })])
+    .with_replies("stream_answer_v2", vec![answer_stream(&["Yellow."])]);
+  let harness = TestPluginHarness::unstarted(scenario);
+  let persist_dir = tempfile::tempdir().unwrap();
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&persist_dir.path().to_path_buf())
+    .unwrap();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  // Loads the embedding model info before compacting, so embedding only waits for the store.
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are yellow", HashMap::new())
+    .await
+    .unwrap();
+
+  let started = std::time::Instant::now();
+  let compact = async {
+    let report = harness.ollama_plugin.compact_vector_store().await.unwrap();
+    (report, started.elapsed())
+  };
+  let embed = async {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    harness
+      .ollama_plugin
+      .embed_text("Cherries are red", HashMap::new())
+      .await
+      .unwrap();
+    started.elapsed()
+  };
+  let question = async {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let stream = harness
+      .ollama_plugin
+      .stream_question("fruits", "What color are bananas?", None, json!({}))
+      .await
+      .unwrap();
+    assert_eq!(collect_json_stream(stream).await, "Yellow.");
+    started.elapsed()
+  };
+  let ((report, compacted), embedded, answered) = tokio::join!(compact, embed, question);
+  assert!(report.duration >= Duration::from_millis(300));
+  // Embedding waits for the compaction, questions don't.
+  assert!(embedded >= compacted, "{:?} < {:?}", embedded, compacted);
+  assert!(answered < compacted, "{:?} >= {:?}", answered, compacted);
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+    .filter(|method| method == "vs_compact" || method == "embed_text")
+    .collect::<Vec<_>>();
+  assert_eq!(methods, ["embed_text", "vs_compact", "embed_text"]);
+  assert!(!persist_dir.path().join(COMPACTION_MARKER_NAME).exists());
+}
+
+#[tokio::test]
+async fn fake_interrupted_compaction_test() {
+  let scenario = FakeScenario::new().with_replies("vs_compact", vec![json!({ "result": {} })]);
+  let harness = TestPluginHarness::unstarted(scenario);
+  let persist_dir = tempfile::tempdir().unwrap();
+  // Left behind by a plugin that died mid-compaction.
+  std::fs::write(persist_dir.path().join(COMPACTION_MARKER_NAME), "0").unwrap();
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&persist_dir.path().to_path_buf())
+    .unwrap();
+  harness
+    .ollama_plugin
+    .init_plugin(config.clone())
+    .await
+    .unwrap();
+  let compactions = |harness: &TestPluginHarness| {
+    harness
+      .handled_requests()
+      .iter()
+      .filter(|request| request["method"] == "vs_compact")
+      .count()
+  };
+  assert_eq!(compactions(&harness), 1);
+  assert!(!persist_dir.path().join(COMPACTION_MARKER_NAME).exists());
+
+  // Nothing to finish on the next init.
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  assert_eq!(compactions(&harness), 1);
+}
+
+#[tokio::test]
+async fn fake_switch_vector_store_test() {
+  let slow_switch = json!({ "result": {}, "delay_ms": 500 });
+  let scenario = FakeScenario::new()
+    .with_vector_store()
+    .with_replies("vs_switch", vec![slow_switch])
+    .with_replies(
+      "answer",
+      vec![json!({ "result": { "data": "still here" } })],
+    );
+  let harness = TestPluginHarness::new(scenario).await;
+  let first_dir = tempfile::tempdir().unwrap();
+  let second_root = tempfile::tempdir().unwrap();
+  let second_dir = second_root.path().join("store");
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&first_dir.path().to_path_buf())
+    .unwrap();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+  harness
+    .ollama_plugin
+    .embed_text("Bananas are yellow", HashMap::new())
+    .await
+    .unwrap();
+
+  let (switched, (answer, answered_in), found) = tokio::join!(
+    harness
+      .ollama_plugin
+      .switch_vector_store(second_dir.clone()),
+    async {
+      tokio::time::sleep(Duration::from_millis(100)).await;
+      let started = Instant::now();
+      let answer = harness.ollama_plugin.ask_question("chat", "hello").await;
+      (answer, started.elapsed())
+    },
+    async {
+      tokio::time::sleep(Duration::from_millis(100)).await;
+      harness
+        .ollama_plugin
+        .similarity_search("bananas", HashMap::new())
+        .await
+    }
+  );
+  switched.unwrap();
+  // The chat is answered while the switch is in progress.
+  assert_eq!(answer.unwrap(), "still here");
+  assert!(answered_in < Duration::from_millis(300));
+  // The search waited for the switch and ran against the new, empty, directory.
+  assert!(found.unwrap().is_empty());
+  assert!(second_dir.exists());
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+    .collect::<Vec<_>>();
+  let switch = methods.iter().position(|method| method == "vs_switch");
+  let search = methods
+    .iter()
+    .position(|method| method == "similarity_search");
+  assert!(switch < search);
+
+  harness
+    .ollama_plugin
+    .switch_vector_store(first_dir.path().to_path_buf())
+    .await
+    .unwrap();
+  let found = harness
+    .ollama_plugin
+    .similarity_search("bananas", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(found, vec!["Bananas are yellow".to_string()]);
+  assert_eq!(harness.initialize_params().len(), 2);
+}
+
+#[tokio::test]
+async fn fake_switch_vector_store_fallback_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![json!({ "result": { "data": ["Bananas are yellow"] } })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let first_dir = tempfile::tempdir().unwrap();
+  let second_dir = tempfile::tempdir().unwrap();
+  let mut config = harness.config();
+  config
+    .set_rag_enabled(&first_dir.path().to_path_buf())
+    .unwrap();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  // The plugin has no `vs_switch`, it is initialized again with the new directory and the
+  // search queued in the meantime is sent to it.
+  let (switched, found) = tokio::join!(
+    harness
+      .ollama_plugin
+      .switch_vector_store(second_dir.path().to_path_buf()),
+    async {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      harness
+        .ollama_plugin
+        .similarity_search("bananas", HashMap::new())
+        .await
+    }
+  );
+  switched.unwrap();
+  assert_eq!(found.unwrap(), vec!["Bananas are yellow".to_string()]);
+  let params = harness.initialize_params();
+  assert_eq!(params.len(), 3);
+  assert_eq!(
+    params[2]["vectorstore_config"]["persist_directory"],
+    json!(second_dir.path())
+  );
+}
+
+#[tokio::test]
+async fn fake_read_only_store_test() {
+  let scenario = FakeScenario::new().with_replies(
+    "similarity_search",
+    vec![json!({ "result": { "data": ["Bananas are yellow"] } })],
+  );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let dir = tempfile::tempdir().unwrap();
+  let handbook = dir.path().join("handbook");
+  let mut config = harness.config().with_read_only(true);
+  config.set_rag_enabled(&handbook).unwrap();
+  assert!(!handbook.exists());
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let err = harness
+    .ollama_plugin
+    .embed_text("Apples are red", HashMap::new())
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::ReadOnlyStore));
+  let err = harness
+    .ollama_plugin
+    .embed_file("fruits", dir.path().join("fruits.txt"), None)
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::ReadOnlyStore));
+  let err = harness
+    .ollama_plugin
+    .remove_chat_attachment("fruits", "fruits.txt")
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::ReadOnlyStore));
+  let err = harness
+    .ollama_plugin
+    .close_chat("fruits", true)
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.downcast_ref::<PluginError>(),
+    Some(PluginError::ReadOnlyStore)
+  ));
+
+  let found = harness
+    .ollama_plugin
+    .similarity_search("bananas", HashMap::new())
+    .await
+    .unwrap();
+  assert_eq!(found, vec!["Bananas are yellow".to_string()]);
+  // The indexing operations were rejected before reaching the plugin.
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+    .collect::<Vec<_>>();
+  assert!(methods.contains(&"similarity_search".to_string()));
+  assert!(!methods.iter().any(|method| method == "embed_text"
+    || method == "embed_file"
+    || method == "delete_documents"));
+}
+
+#[tokio::test]
+async fn fake_read_only_store_overlay_test() {
+  let harness = TestPluginHarness::unstarted(FakeScenario::new());
+  let dir = tempfile::tempdir().unwrap();
+  let handbook = dir.path().join("handbook");
+  let overlay = dir.path().join("overlay");
+  let mut config = harness
+    .config()
+    .with_read_only(true)
+    .with_overlay_directory(overlay.clone());
+  config.set_rag_enabled(&handbook).unwrap();
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let params = harness.initialize_params();
+  assert_eq!(
+    params[0]["vectorstore_config"],
+    json!({
+      "model_name": "fake-embedding-model",
+      "persist_directory": handbook,
+      "read_only": true,
+    })
+  );
+  assert_eq!(
+    params[0]["overlay_vectorstore_config"],
+    json!({
+      "model_name": "fake-embedding-model",
+      "persist_directory": overlay,
+      "read_only": false,
+    })
+  );
+  assert!(!handbook.exists());
+  assert!(overlay.exists());
+
+  // User content is indexed into the overlay.
+  harness
+    .ollama_plugin
+    .embed_text("Apples are red", HashMap::new())
+    .await
+    .unwrap();
+  let methods = harness
+    .handled_requests()
+    .into_iter()
+    .map(|request| request["method"].as_str().unwrap_or_default().to_string())
+    .collect::<Vec<_>>();
+  assert!(methods.contains(&"embed_text".to_string()));
+}
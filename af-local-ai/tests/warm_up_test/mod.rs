@@ -0,0 +1,92 @@
+use crate::harness::{answer_stream, FakeScenario, TestPluginHarness};
+use af_local_ai::warm_up::WarmUpProgress;
+use serde_json::json;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn fake_warming_up_metadata_test() {
+  let mut slow_answer = answer_stream(&["Bananas", " are yellow"]);
+  slow_answer["delay_ms"] = json!(300);
+  let scenario = FakeScenario::new().with_replies(
+    "stream_answer_v2",
+    vec![slow_answer, answer_stream(&["Apples are red"])],
+  );
+  let harness = TestPluginHarness::unstarted(scenario);
+  let config = harness
+    .config()
+    .with_warming_up_threshold(Duration::from_millis(100));
+  harness.ollama_plugin.init_plugin(config).await.unwrap();
+
+  let frames = harness
+    .ollama_plugin
+    .stream_question("chat", "what is banana?", None, json!({}))
+    .await
+    .unwrap()
+    .collect::<Vec<_>>()
+    .await
+    .into_iter()
+    .map(Result::unwrap)
+    .collect::<Vec<_>>();
+  assert_eq!(frames[0], json!({ "0": { "warming_up": true } }));
+  assert_eq!(frames[1], json!({ "1": "Bananas" }));
+
+  // A model that answers in time gets no hint.
+  let frames = harness
+    .ollama_plugin
+    .stream_question("chat", "what is apple?", None, json!({}))
+    .await
+    .unwrap()
+    .collect::<Vec<_>>()
+    .await;
+  assert!(frames
+    .iter()
+    .all(|frame| frame.as_ref().unwrap().get("0").is_none()));
+}
+
+#[tokio::test]
+async fn fake_warm_up_model_test() {
+  let progress =
+    |completed: u64| json!(json!({ "completed": completed, "total": 100 }).to_string());
+  let scenario = FakeScenario::new().with_replies(
+    "warm_up",
+    vec![
+      json!({ "stream": [progress(40), progress(100)] }),
+      json!({ "stream": [json!(json!({ "status": "loaded" }).to_string())], "delay_ms": 2300 }),
+    ],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+
+  let events = harness
+    .ollama_plugin
+    .warm_up_model()
+    .await
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(
+    events,
+    vec![
+      WarmUpProgress::Started,
+      WarmUpProgress::Loading {
+        completed: 40,
+        total: 100
+      },
+      WarmUpProgress::Loading {
+        completed: 100,
+        total: 100
+      },
+      WarmUpProgress::Done,
+    ]
+  );
+
+  // Without progress reports, heartbeats tell that the model is still loading.
+  let events = harness
+    .ollama_plugin
+    .warm_up_model()
+    .await
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(events.first(), Some(&WarmUpProgress::Started));
+  assert!(matches!(events[1], WarmUpProgress::StillLoading { .. }));
+  assert_eq!(events.last(), Some(&WarmUpProgress::Done));
+}
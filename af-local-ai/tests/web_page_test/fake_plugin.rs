@@ -0,0 +1,148 @@
+use crate::harness::{FakeScenario, TestPluginHarness};
+use af_plugin::error::PluginError;
+use serde_json::json;
+
+/// Serves `routes`, a path to a status line, content type and body, until the test exits.
+fn serve_http(routes: Vec<(&'static str, &'static str, &'static str, String)>) -> String {
+  use std::io::{Read, Write};
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = format!("http://{}", listener.local_addr().unwrap());
+  std::thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      let mut request = Vec::new();
+      let mut buf = [0; 1024];
+      while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+          Ok(0) | Err(_) => break,
+          Ok(read) => request.extend_from_slice(&buf[..read]),
+        }
+      }
+      let request = String::from_utf8_lossy(&request);
+      let path = request.split_whitespace().nth(1).unwrap_or("/");
+      let response = match routes.iter().find(|(route, ..)| *route == path) {
+        Some((_, status, content_type, body)) => format!(
+          "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          status,
+          content_type,
+          body.len(),
+          body
+        ),
+        None => {
+          "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        },
+      };
+      let _ = stream.write_all(response.as_bytes());
+    }
+  });
+  address
+}
+
+#[tokio::test]
+async fn fake_embed_url_test() {
+  let page = "<html><head><title>Heron &amp; Co</title><style>p { color: red }</style></head>\
+    <body><nav>Home | About</nav><h1>Project Heron</h1>\
+    <p>The launch code of Project Heron is <b>4417</b>.</p>\
+    <script>var code = 1234;</script></body></html>";
+  let server = serve_http(vec![
+    (
+      "/heron",
+      "200 OK",
+      "text/html; charset=utf-8",
+      page.to_string(),
+    ),
+    ("/data.json", "200 OK", "application/json", "{}".to_string()),
+    (
+      "/loop",
+      "302 Found\r\nLocation: /loop",
+      "text/html",
+      String::new(),
+    ),
+  ]);
+  let scenario = FakeScenario::new().with_replies(
+    "answer",
+    vec![json!({
+      "result": { "data": "The launch code is 4417." },
+      "when": { "chat_id": "chat" },
+    })],
+  );
+  let harness = TestPluginHarness::new(scenario).await;
+  let url = format!("{}/heron", server);
+
+  harness
+    .ollama_plugin
+    .embed_url("chat", &url, None)
+    .await
+    .unwrap();
+  let request = harness
+    .handled_requests()
+    .into_iter()
+    .find(|request| request["method"] == "embed_text")
+    .unwrap();
+  let params = &request["params"];
+  assert_eq!(
+    params["input"],
+    json!("Project Heron\nThe launch code of Project Heron is 4417.")
+  );
+  assert_eq!(params["metadata"]["source_url"], json!(url));
+  assert_eq!(params["metadata"]["source_id"], json!(url));
+  assert_eq!(params["metadata"]["file_name"], json!("Heron & Co"));
+  let attachments = harness.ollama_plugin.list_chat_attachments("chat").await;
+  assert_eq!(attachments.len(), 1);
+  assert_eq!(attachments[0].source_id, url);
+
+  let answer = harness
+    .ollama_plugin
+    .ask_question("chat", "What is the launch code of Project Heron?")
+    .await
+    .unwrap();
+  assert!(answer.contains("4417"), "{}", answer);
+
+  let err = harness
+    .ollama_plugin
+    .embed_url("chat", &format!("{}/data.json", server), None)
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::UnsupportedContentType(ref content_type) if content_type == "application/json"),
+    "{:?}",
+    err
+  );
+  let err = harness
+    .ollama_plugin
+    .embed_url("chat", &format!("{}/missing", server), None)
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::HttpStatus { status: 404, .. }),
+    "{:?}",
+    err
+  );
+  let err = harness
+    .ollama_plugin
+    .embed_url("chat", &format!("{}/loop", server), None)
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::FetchFailed { ref reason, .. } if reason.contains("redirect loop")),
+    "{:?}",
+    err
+  );
+  let err = harness
+    .ollama_plugin
+    .embed_url("chat", "file:///etc/passwd", None)
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(err, PluginError::FetchFailed { ref reason, .. } if reason.contains("unsupported scheme")),
+    "{:?}",
+    err
+  );
+  assert_eq!(
+    harness
+      .ollama_plugin
+      .list_chat_attachments("chat")
+      .await
+      .len(),
+    1
+  );
+}
@@ -0,0 +1,36 @@
+use af_local_ai::web_page::{decode_entities, html_title, html_to_text};
+
+#[cfg(feature = "fake-plugin-tests")]
+mod fake_plugin;
+
+#[test]
+fn html_to_text_test() {
+  let html = r#"<!DOCTYPE html>
+<html>
+  <head><title>Notes</title><script>alert("hi")</script></head>
+  <body>
+    <header><a href="/">Home</a></header>
+    <!-- a comment with <p>markup</p> -->
+    <h1>Release   notes</h1>
+    <p>Fixed the <em>sync</em> of
+       large pages.<br/>Faster search.</p>
+    <ul><li>One</li><li>Two &amp; three</li></ul>
+    <FOOTER>Copyright</FOOTER>
+  </body>
+</html>"#;
+  assert_eq!(
+    html_to_text(html),
+    "Release notes\nFixed the sync of large pages.\nFaster search.\nOne\nTwo & three"
+  );
+  assert_eq!(html_title(html).as_deref(), Some("Notes"));
+  assert_eq!(html_title("<p>No title</p>"), None);
+  assert_eq!(html_to_text("plain text"), "plain text");
+  assert_eq!(html_to_text("<p>cut off <b"), "cut off");
+}
+
+#[test]
+fn decode_entities_test() {
+  assert_eq!(decode_entities("a &lt;b&gt; &quot;c&quot;"), "a <b> \"c\"");
+  assert_eq!(decode_entities("it&#39;s &#x263A;"), "it's \u{263A}");
+  assert_eq!(decode_entities("AT&T &unknown; &"), "AT&T &unknown; &");
+}
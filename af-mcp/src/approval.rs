@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use tracing::info;
+
+/// Tools denied without a [ToolApproval] handler, see [ToolApprovals::set_dangerous_patterns].
+pub const DEFAULT_DANGEROUS_TOOL_PATTERNS: &[&str] = &["write_*", "edit_*", "move_*"];
+
+/// A tool call asked for by the model, before it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallRequest {
+  pub namespace: String,
+  pub name: String,
+  pub arguments: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalDecision {
+  Allow,
+  /// Allows this call and the following calls of the same tool until the registry is dropped.
+  AllowAlwaysForTool,
+  /// Denies the call. The reason is reported to the model as the result of the tool.
+  Deny(String),
+}
+
+/// Asks the user whether a tool may run, see
+/// [McpToolRegistry::set_tool_approval_handler](crate::registry::McpToolRegistry::set_tool_approval_handler).
+#[async_trait]
+pub trait ToolApproval: Send + Sync {
+  async fn approve(&self, call: &ToolCallRequest) -> ApprovalDecision;
+}
+
+/// Decides which tool calls may run: the registered handler is asked before every call, except
+/// for the tools it allowed for the session. Without a handler, the tools matching a dangerous
+/// pattern are denied and the others allowed.
+pub struct ToolApprovals {
+  handler: RwLock<Option<Arc<dyn ToolApproval>>>,
+  dangerous_patterns: RwLock<Vec<String>>,
+  /// `(namespace, tool)` of the tools allowed with [ApprovalDecision::AllowAlwaysForTool].
+  always_allowed: RwLock<HashSet<(String, String)>>,
+}
+
+impl Default for ToolApprovals {
+  fn default() -> Self {
+    Self {
+      handler: Default::default(),
+      dangerous_patterns: RwLock::new(
+        DEFAULT_DANGEROUS_TOOL_PATTERNS
+          .iter()
+          .map(|pattern| pattern.to_string())
+          .collect(),
+      ),
+      always_allowed: Default::default(),
+    }
+  }
+}
+
+impl ToolApprovals {
+  pub fn set_handler(&self, handler: Arc<dyn ToolApproval>) {
+    *self.handler.write().unwrap() = Some(handler);
+  }
+
+  /// Replaces the patterns of the tools denied without a handler. A pattern matches the name of
+  /// the tool, without its namespace, and `*` matches any characters, as in `write_*`.
+  pub fn set_dangerous_patterns(&self, patterns: Vec<String>) {
+    *self.dangerous_patterns.write().unwrap() = patterns;
+  }
+
+  pub async fn approve(&self, call: &ToolCallRequest) -> ApprovalDecision {
+    let key = (call.namespace.clone(), call.name.clone());
+    if self.always_allowed.read().unwrap().contains(&key) {
+      return ApprovalDecision::Allow;
+    }
+    let handler = self.handler.read().unwrap().clone();
+    let decision = match handler {
+      Some(handler) => handler.approve(call).await,
+      None => self.default_decision(&call.name),
+    };
+    match &decision {
+      ApprovalDecision::AllowAlwaysForTool => {
+        self.always_allowed.write().unwrap().insert(key);
+      },
+      ApprovalDecision::Deny(reason) => {
+        info!(
+          "Denied call of tool {}.{}: {}",
+          call.namespace, call.name, reason
+        );
+      },
+      ApprovalDecision::Allow => {},
+    }
+    decision
+  }
+
+  fn default_decision(&self, name: &str) -> ApprovalDecision {
+    let dangerous = self
+      .dangerous_patterns
+      .read()
+      .unwrap()
+      .iter()
+      .any(|pattern| matches_pattern(pattern, name));
+    if dangerous {
+      ApprovalDecision::Deny(format!(
+        "{} needs the user's approval, but no approval handler is set",
+        name
+      ))
+    } else {
+      ApprovalDecision::Allow
+    }
+  }
+}
+
+/// The result sent back to the model for a denied call, in the format of a `tools/call`
+/// response, so the conversation goes on.
+pub fn denied_tool_result(reason: &str) -> Value {
+  json!({
+    "content": [{ "type": "text", "text": format!("Tool call denied: {}", reason) }],
+    "isError": true,
+  })
+}
+
+/// Matches `name` against `pattern`, where `*` matches any characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+  let mut parts = pattern.split('*');
+  let first = parts.next().unwrap_or_default();
+  let mut rest = match name.strip_prefix(first) {
+    Some(rest) => rest,
+    None => return false,
+  };
+  let parts = parts.collect::<Vec<_>>();
+  let (last, middle) = match parts.split_last() {
+    Some(split) => split,
+    // No `*`: the whole name must match.
+    None => return rest.is_empty(),
+  };
+  for part in middle {
+    match rest.find(part) {
+      Some(index) => rest = &rest[index + part.len()..],
+      None => return false,
+    }
+  }
+  rest.ends_with(last)
+}
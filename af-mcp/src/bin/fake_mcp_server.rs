@@ -0,0 +1,218 @@
+//! A minimal MCP server used by the `fake-server-tests` integration tests.
+//!
+//! It speaks newline delimited JSON RPC over stdio and exposes an `echo` tool answering with its
+//! `text` argument, two prompts, one per page of `prompts/list`:
+//!
+//! - `summarize`, taking a required `topic` and an optional `style`, answers with a user message
+//!   and an embedded resource.
+//! - `greet`, taking a required `name`, answers with a user and an assistant message.
+//!
+//! and two file resources, `Cargo.toml` and `src/lib.rs` of the directory it runs in, one per page
+//! of `resources/list`. `resources/read` answers with the text of any `file://` URI.
+//!
+//! Given `--no-prompts`, it doesn't advertise the prompts capability and rejects the prompt
+//! methods. Given `--hang`, it never answers. Given `--bad-init`, it answers `initialize` with a
+//! result that isn't valid MCP. Given `--print-env <name>`, its name is `fake-mcp-server` followed
+//! by the value of the environment variable `name` and the directory it runs in.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+fn main() {
+  let args = std::env::args().collect::<Vec<_>>();
+  let flag = |name: &str| args.iter().any(|arg| arg == name);
+  let prompts = !flag("--no-prompts");
+  let hang = flag("--hang");
+  let bad_init = flag("--bad-init");
+  let server_name = match args.iter().position(|arg| arg == "--print-env") {
+    Some(index) => format!(
+      "fake-mcp-server {} {}",
+      std::env::var(&args[index + 1]).unwrap_or_default(),
+      std::env::current_dir().unwrap().display()
+    ),
+    None => "fake-mcp-server".to_string(),
+  };
+  let stdin = std::io::stdin();
+  for line in stdin.lock().lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => break,
+    };
+    let request = match serde_json::from_str::<Value>(&line) {
+      Ok(request) => request,
+      Err(_) => continue,
+    };
+    // Notifications have no id and get no response.
+    let id = match request.get("id") {
+      Some(id) => id.clone(),
+      None => continue,
+    };
+    if hang {
+      continue;
+    }
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = &request["params"];
+    let result = match method {
+      "initialize" if bad_init => Ok(json!({ "protocolVersion": 1 })),
+      "initialize" => Ok(initialize(prompts, &server_name)),
+      "ping" => Ok(json!({})),
+      "tools/list" => Ok(list_tools()),
+      "tools/call" => call_tool(params),
+      "resources/list" => Ok(list_resources(params)),
+      "resources/read" => read_resource(params),
+      "prompts/list" if prompts => Ok(list_prompts(params)),
+      "prompts/get" if prompts => get_prompt(params),
+      _ => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    };
+    let response = match result {
+      Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+      Err((code, message)) => json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+      }),
+    };
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", response);
+    let _ = stdout.flush();
+  }
+}
+
+fn initialize(prompts: bool, server_name: &str) -> Value {
+  let mut capabilities = json!({ "tools": {}, "resources": {} });
+  if prompts {
+    capabilities["prompts"] = json!({ "listChanged": false });
+  }
+  json!({
+    "protocolVersion": "2024-11-05",
+    "capabilities": capabilities,
+    "serverInfo": { "name": server_name, "version": "0.1.0" },
+  })
+}
+
+fn list_tools() -> Value {
+  json!({
+    "tools": [{
+      "name": "echo",
+      "description": "Answers with its text",
+      "inputSchema": {
+        "type": "object",
+        "title": "EchoArguments",
+        "properties": { "text": { "title": "Text", "type": "string" } },
+        "required": ["text"],
+      },
+    }],
+  })
+}
+
+fn call_tool(params: &Value) -> Result<Value, (i64, String)> {
+  match params["name"].as_str().unwrap_or_default() {
+    "echo" => {
+      let text = params["arguments"]["text"]
+        .as_str()
+        .ok_or_else(|| (INVALID_PARAMS, "Missing required argument text".to_string()))?;
+      Ok(json!({ "content": [{ "type": "text", "text": text }] }))
+    },
+    name => Err((INVALID_PARAMS, format!("Unknown tool {}", name))),
+  }
+}
+
+fn list_resources(params: &Value) -> Value {
+  let resource = |path: &str, mime_type: &str| {
+    let path = std::fs::canonicalize(path).unwrap();
+    json!({
+      "uri": format!("file://{}", path.display()),
+      "name": path.file_name().unwrap().to_string_lossy(),
+      "mimeType": mime_type,
+    })
+  };
+  match params["cursor"].as_str() {
+    None => json!({
+      "resources": [resource("Cargo.toml", "text/x-toml")],
+      "nextCursor": "lib",
+    }),
+    Some(_) => json!({ "resources": [resource("src/lib.rs", "text/x-rust")] }),
+  }
+}
+
+fn read_resource(params: &Value) -> Result<Value, (i64, String)> {
+  let uri = params["uri"].as_str().unwrap_or_default();
+  let text = uri
+    .strip_prefix("file://")
+    .and_then(|path| std::fs::read_to_string(path).ok())
+    .ok_or_else(|| (INVALID_PARAMS, format!("Unknown resource {}", uri)))?;
+  Ok(json!({ "contents": [{ "uri": uri, "mimeType": "text/plain", "text": text }] }))
+}
+
+fn list_prompts(params: &Value) -> Value {
+  match params["cursor"].as_str() {
+    None => json!({
+      "prompts": [{
+        "name": "summarize",
+        "description": "Summarize a topic",
+        "arguments": [
+          { "name": "topic", "description": "What to summarize", "required": true },
+          { "name": "style", "description": "How to write the summary" },
+        ],
+      }],
+      "nextCursor": "greet",
+    }),
+    Some(_) => json!({
+      "prompts": [{
+        "name": "greet",
+        "arguments": [{ "name": "name", "required": true }],
+      }],
+    }),
+  }
+}
+
+fn get_prompt(params: &Value) -> Result<Value, (i64, String)> {
+  let argument = |name: &str| params["arguments"][name].as_str();
+  let required = |name: &str| {
+    argument(name).ok_or_else(|| {
+      (
+        INVALID_PARAMS,
+        format!("Missing required argument {}", name),
+      )
+    })
+  };
+  match params["name"].as_str().unwrap_or_default() {
+    "summarize" => {
+      let topic = required("topic")?;
+      let mut text = format!("Summarize what you know about {}.", topic);
+      if let Some(style) = argument("style") {
+        text.push_str(&format!(" Keep it {}.", style));
+      }
+      Ok(json!({
+        "description": "Summarize a topic",
+        "messages": [
+          { "role": "user", "content": { "type": "text", "text": text } },
+          {
+            "role": "user",
+            "content": {
+              "type": "resource",
+              "resource": {
+                "uri": format!("file:///notes/{}.md", topic),
+                "mimeType": "text/markdown",
+                "text": format!("Notes about {}.", topic),
+              },
+            },
+          },
+        ],
+      }))
+    },
+    "greet" => {
+      let name = required("name")?;
+      Ok(json!({
+        "messages": [
+          { "role": "user", "content": { "type": "text", "text": format!("Say hello to {}.", name) } },
+          { "role": "assistant", "content": { "type": "text", "text": format!("Hello {}!", name) } },
+        ],
+      }))
+    },
+    name => Err((INVALID_PARAMS, format!("Unknown prompt {}", name))),
+  }
+}
@@ -1,17 +1,201 @@
-use crate::entities::ToolsList;
-use anyhow::Result;
+use crate::entities::{
+  PromptResult, PromptsList, PromptsPage, ResourceContent, ResourceContents, ResourcesList,
+  ResourcesPage, ToolsList,
+};
+use anyhow::{anyhow, Result};
 use mcp_daemon::protocol::RequestOptions;
 use mcp_daemon::transport::{ClientStdioTransport, Transport};
-use mcp_daemon::types::Implementation;
+use mcp_daemon::types::{Implementation, InitializeResponse, ServerCapabilities};
 use mcp_daemon::Client;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::time::Duration;
-use tracing::{error, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
-#[derive(Debug, Clone)]
+/// Upper bound on the pages fetched by [MCPClient::list_resources] and [MCPClient::list_prompts],
+/// in case a server never stops returning a cursor.
+const MAX_LIST_PAGES: usize = 100;
+
+/// Commands that only start a server when told which one, e.g. `npx` needs the package.
+const LAUNCHERS: &[&str] = &[
+  "bun", "bunx", "deno", "docker", "node", "npx", "pnpx", "python", "python3", "uv", "uvx",
+];
+
+/// How to start an MCP server, e.g. one configured by the user in the settings. Serialized to be
+/// stored: the fields added after `server_cmd` and `args` have defaults, so configs stored
+/// before them still deserialize.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MCPServerConfig {
   pub server_cmd: String,
+  #[serde(default)]
   pub args: Vec<String>,
+  /// Variables added to the environment of the server, which otherwise inherits the one of this
+  /// process.
+  #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+  pub env: HashMap<String, String>,
+  /// Directory the server runs in, the one of this process when `None`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub working_dir: Option<PathBuf>,
+  #[serde(default)]
+  pub transport: TransportType,
+}
+
+/// How a client talks to its MCP server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum TransportType {
+  /// The server is a child process, spoken to on its stdin and stdout.
+  #[default]
+  Stdio,
+}
+
+/// Why [MCPServerConfig::validate] rejected a config.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+  #[error("MCP server command is empty")]
+  EmptyCommand,
+  #[error("MCP server command {0:?} is neither a file nor found on the PATH")]
+  CommandNotFound(String),
+  #[error("MCP server command {0:?} needs arguments telling it which server to run")]
+  MissingArgs(String),
+  #[error("Invalid name of MCP server environment variable {0:?}")]
+  InvalidEnvVar(String),
+  #[error("MCP server working directory {0:?} is not a directory")]
+  WorkingDirNotFound(PathBuf),
+}
+
+impl MCPServerConfig {
+  pub fn new(server_cmd: impl Into<String>, args: Vec<String>) -> Self {
+    Self {
+      server_cmd: server_cmd.into(),
+      args,
+      ..Default::default()
+    }
+  }
+
+  /// Checks the config without starting the server: the command is an existing file or is found
+  /// on the PATH, launchers such as `npx` have arguments, the names of the environment variables
+  /// are valid and the working directory exists. Use [MCPClient::test_connection] to check that
+  /// the server also starts.
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    if self.server_cmd.trim().is_empty() {
+      return Err(ConfigError::EmptyCommand);
+    }
+    if let Some(name) = self
+      .env
+      .keys()
+      .find(|name| name.is_empty() || name.contains('=') || name.contains('\0'))
+    {
+      return Err(ConfigError::InvalidEnvVar(name.clone()));
+    }
+    if let Some(working_dir) = &self.working_dir {
+      if !working_dir.is_dir() {
+        return Err(ConfigError::WorkingDirNotFound(working_dir.clone()));
+      }
+    }
+    if !self.command_exists() {
+      return Err(ConfigError::CommandNotFound(self.server_cmd.clone()));
+    }
+    let name = Path::new(&self.server_cmd)
+      .file_stem()
+      .and_then(|name| name.to_str())
+      .unwrap_or_default()
+      .to_lowercase();
+    if self.args.is_empty() && LAUNCHERS.contains(&name.as_str()) {
+      return Err(ConfigError::MissingArgs(self.server_cmd.clone()));
+    }
+    Ok(())
+  }
+
+  /// Whether `server_cmd` is a path to a file, relative ones from the working directory, or the
+  /// name of a file in a directory of the PATH of the server.
+  fn command_exists(&self) -> bool {
+    let command = Path::new(&self.server_cmd);
+    if command.components().count() > 1 {
+      return match (&self.working_dir, command.is_relative()) {
+        (Some(working_dir), true) => working_dir.join(command).is_file(),
+        _ => command.is_file(),
+      };
+    }
+    let path = match self.env.get("PATH") {
+      Some(path) => Some(path.into()),
+      None => std::env::var_os("PATH"),
+    };
+    let extensions: &[&str] = if cfg!(windows) {
+      &["", ".exe", ".cmd", ".bat"]
+    } else {
+      &[""]
+    };
+    path.iter().flat_map(std::env::split_paths).any(|dir| {
+      extensions.iter().any(|extension| {
+        dir
+          .join(format!("{}{}", self.server_cmd, extension))
+          .is_file()
+      })
+    })
+  }
+
+  /// The program and arguments the stdio transport starts. The transport can't set the
+  /// environment or the working directory of the server, so they are set by `env`, run from `sh`
+  /// for a working directory.
+  fn launch_command(&self) -> Result<(String, Vec<String>)> {
+    if self.env.is_empty() && self.working_dir.is_none() {
+      return Ok((self.server_cmd.clone(), self.args.clone()));
+    }
+    if !cfg!(unix) {
+      return Err(anyhow!(
+        "The environment and working directory of MCP servers are only supported on Unix"
+      ));
+    }
+    let mut env = self.env.iter().collect::<Vec<_>>();
+    env.sort();
+    let mut args = env
+      .into_iter()
+      .map(|(name, value)| format!("{}={}", name, value))
+      .collect::<Vec<_>>();
+    args.push(self.server_cmd.clone());
+    args.extend(self.args.iter().cloned());
+    match &self.working_dir {
+      Some(working_dir) => {
+        let mut sh_args = vec![
+          "-c".to_string(),
+          r#"cd -- "$0" && exec env "$@""#.to_string(),
+          working_dir.to_string_lossy().into_owned(),
+        ];
+        sh_args.extend(args);
+        Ok(("/bin/sh".to_string(), sh_args))
+      },
+      None => Ok(("env".to_string(), args)),
+    }
+  }
+}
+
+/// What [MCPClient::test_connection] found out about a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerProbe {
+  pub tools_count: usize,
+  /// The name the server gave when initialized.
+  pub server_name: String,
+  /// Time from starting the server to receiving its tools.
+  pub latency: Duration,
+}
+
+/// Why [MCPClient::test_connection] failed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProbeError {
+  /// The server process could not be started, e.g. its command doesn't exist.
+  #[error("Failed to start MCP server: {0}")]
+  SpawnFailed(String),
+  /// The server didn't initialize and list its tools in time, e.g. it waits for input or exited.
+  #[error("MCP server did not answer within {0:?}")]
+  InitTimeout(Duration),
+  /// The server answered with an error or with messages that aren't valid MCP.
+  #[error("MCP server protocol error: {0}")]
+  ProtocolError(String),
 }
 
 // https://modelcontextprotocol.io/docs/tools/inspector
@@ -21,6 +205,8 @@ pub struct MCPClient {
   pub client: Client<ClientStdioTransport>,
   pub transport: ClientStdioTransport,
   pub server_config: MCPServerConfig,
+  /// Sent by the server when initialized.
+  server_capabilities: Arc<RwLock<Option<ServerCapabilities>>>,
 }
 
 impl MCPClient {
@@ -30,19 +216,61 @@ impl MCPClient {
       config.server_cmd,
       config.args.join(" ")
     );
-    let args_str: Vec<&str> = config.args.iter().map(String::as_str).collect();
-    let transport = ClientStdioTransport::new(&config.server_cmd, &args_str)?;
+    let (program, args) = config.launch_command()?;
+    let args_str: Vec<&str> = args.iter().map(String::as_str).collect();
+    let transport = ClientStdioTransport::new(&program, &args_str)?;
     let client = Client::builder(transport.clone()).build();
     Ok(MCPClient {
       client,
       transport,
       server_config: config,
+      server_capabilities: Default::default(),
     })
   }
 
   pub async fn initialize(&self) -> Result<()> {
     self.transport.open().await?;
+    self.handshake().await?;
+    Ok(())
+  }
+
+  /// Starts the server of `config`, initializes it and lists its tools, then stops it, e.g. for
+  /// a "Test" button of the settings. `timeout` bounds the initialization and the listing.
+  pub async fn test_connection(
+    config: MCPServerConfig,
+    timeout: Duration,
+  ) -> Result<ServerProbe, ProbeError> {
+    let started = Instant::now();
+    let mut client = MCPClient::new_stdio(config)
+      .await
+      .map_err(|err| ProbeError::SpawnFailed(err.to_string()))?;
+    client
+      .transport
+      .open()
+      .await
+      .map_err(|err| ProbeError::SpawnFailed(err.to_string()))?;
+    let probe = tokio::time::timeout(timeout, client.probe(started))
+      .await
+      .unwrap_or(Err(ProbeError::InitTimeout(timeout)));
+    if let Err(err) = client.stop().await {
+      warn!("Failed to stop probed MCP server: {}", err);
+    }
+    probe
+  }
+
+  async fn probe(&self, started: Instant) -> Result<ServerProbe, ProbeError> {
+    let protocol_error = |err: anyhow::Error| ProbeError::ProtocolError(err.to_string());
+    let response = self.handshake().await.map_err(protocol_error)?;
+    let tools = self.list_tools().await.map_err(protocol_error)?;
+    Ok(ServerProbe {
+      tools_count: tools.tools.len(),
+      server_name: response.server_info.name,
+      latency: started.elapsed(),
+    })
+  }
 
+  /// Runs the client on the opened transport and initializes the server.
+  async fn handshake(&self) -> Result<InitializeResponse> {
     let cloned_client = self.client.clone();
     tokio::spawn(async move {
       if let Err(err) = cloned_client.start().await {
@@ -54,8 +282,9 @@ impl MCPClient {
       name: "mcp-client".to_string(),
       version: "0.0.1".to_string(),
     };
-    self.client.initialize(implementation).await?;
-    Ok(())
+    let response = self.client.initialize(implementation).await?;
+    *self.server_capabilities.write().unwrap() = Some(response.capabilities.clone());
+    Ok(response)
   }
 
   pub async fn ping(&self) -> Result<Value> {
@@ -99,6 +328,106 @@ impl MCPClient {
     Ok(resp)
   }
 
+  /// Send resources/list requests until the server has returned every page
+  pub async fn list_resources(&self) -> Result<ResourcesList> {
+    let mut resources = vec![];
+    let mut cursor: Option<String> = None;
+    for _ in 0..MAX_LIST_PAGES {
+      let params = cursor.as_ref().map(|cursor| json!({ "cursor": cursor }));
+      let resp = self
+        .client
+        .request("resources/list", params, Default::default())
+        .await?;
+      let page = serde_json::from_value::<ResourcesPage>(resp)?;
+      resources.extend(page.resources);
+
+      match page.next_cursor {
+        Some(next_cursor) if !next_cursor.is_empty() => cursor = Some(next_cursor),
+        _ => return Ok(ResourcesList { resources }),
+      }
+    }
+
+    warn!(
+      "Stop listing resources after {} pages, the list may be incomplete",
+      MAX_LIST_PAGES
+    );
+    Ok(ResourcesList { resources })
+  }
+
+  /// Send prompts/list requests until the server has returned every page. Servers that don't
+  /// advertise prompts when initialized have none.
+  pub async fn list_prompts(&self) -> Result<PromptsList> {
+    let mut prompts = vec![];
+    if !self.supports_prompts() {
+      return Ok(PromptsList { prompts });
+    }
+    let mut cursor: Option<String> = None;
+    for _ in 0..MAX_LIST_PAGES {
+      let params = cursor.as_ref().map(|cursor| json!({ "cursor": cursor }));
+      let resp = self
+        .client
+        .request("prompts/list", params, Default::default())
+        .await?;
+      let page = serde_json::from_value::<PromptsPage>(resp)?;
+      prompts.extend(page.prompts);
+
+      match page.next_cursor {
+        Some(next_cursor) if !next_cursor.is_empty() => cursor = Some(next_cursor),
+        _ => return Ok(PromptsList { prompts }),
+      }
+    }
+
+    warn!(
+      "Stop listing prompts after {} pages, the list may be incomplete",
+      MAX_LIST_PAGES
+    );
+    Ok(PromptsList { prompts })
+  }
+
+  /// Send a prompts/get request, with `arguments` mapping the names of the arguments of the
+  /// prompt to their string values.
+  pub async fn get_prompt(&self, name: &str, arguments: Option<Value>) -> Result<PromptResult> {
+    if !self.supports_prompts() {
+      return Err(anyhow!("MCP server has no prompts"));
+    }
+    let mut params = json!({ "name": name });
+    if let Some(arguments) = arguments {
+      params["arguments"] = arguments;
+    }
+    let resp = self
+      .client
+      .request("prompts/get", Some(params), Default::default())
+      .await?;
+    Ok(serde_json::from_value::<PromptResult>(resp)?)
+  }
+
+  fn supports_prompts(&self) -> bool {
+    self
+      .server_capabilities
+      .read()
+      .unwrap()
+      .as_ref()
+      .is_some_and(|capabilities| capabilities.prompts.is_some())
+  }
+
+  /// Send a resources/read request. Returns the first content the server sends for `uri`.
+  pub async fn read_resource(&self, uri: &str) -> Result<ResourceContent> {
+    let resp = self
+      .client
+      .request(
+        "resources/read",
+        Some(json!({ "uri": uri })),
+        Default::default(),
+      )
+      .await?;
+    let contents = serde_json::from_value::<ResourceContents>(resp)?;
+    contents
+      .contents
+      .into_iter()
+      .next()
+      .ok_or_else(|| anyhow!("Resource {} has no content", uri))
+  }
+
   pub async fn stop(&mut self) -> Result<()> {
     self.transport.close().await?;
     Ok(())
@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 use std::collections::HashMap;
 
@@ -42,3 +44,123 @@ pub struct Property {
   #[serde(rename = "type")]
   pub property_type: Option<String>,
 }
+
+// https://modelcontextprotocol.io/docs/concepts/resources
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResourcesList {
+  pub resources: Vec<Resource>,
+}
+
+/// One page of a `resources/list` response.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ResourcesPage {
+  #[serde(default)]
+  pub resources: Vec<Resource>,
+  #[serde(rename = "nextCursor", default)]
+  pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resource {
+  pub uri: String,
+  pub name: String,
+  #[serde(rename = "mimeType", default)]
+  pub mime_type: Option<String>,
+  #[serde(default)]
+  pub description: Option<String>,
+}
+
+/// The content of a resource. Text resources set `text`, binary resources set `blob`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResourceContent {
+  pub uri: String,
+  #[serde(rename = "mimeType", default)]
+  pub mime_type: Option<String>,
+  #[serde(default)]
+  pub text: Option<String>,
+  #[serde(default, deserialize_with = "deserialize_blob")]
+  pub blob: Option<Vec<u8>>,
+}
+
+/// The response of a `resources/read` request.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ResourceContents {
+  pub contents: Vec<ResourceContent>,
+}
+
+// https://modelcontextprotocol.io/docs/concepts/prompts
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptsList {
+  pub prompts: Vec<Prompt>,
+}
+
+/// One page of a `prompts/list` response.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PromptsPage {
+  #[serde(default)]
+  pub prompts: Vec<Prompt>,
+  #[serde(rename = "nextCursor", default)]
+  pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Prompt {
+  pub name: String,
+  #[serde(default)]
+  pub description: Option<String>,
+  #[serde(default)]
+  pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptArgument {
+  pub name: String,
+  #[serde(default)]
+  pub description: Option<String>,
+  #[serde(default)]
+  pub required: bool,
+}
+
+/// The response of a `prompts/get` request: the prompt with its arguments filled in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptResult {
+  #[serde(default)]
+  pub description: Option<String>,
+  pub messages: Vec<PromptMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptMessage {
+  /// `user` or `assistant`.
+  pub role: String,
+  pub content: PromptContent,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PromptContent {
+  Text {
+    text: String,
+  },
+  Image {
+    data: String,
+    #[serde(rename = "mimeType")]
+    mime_type: String,
+  },
+  Resource {
+    resource: ResourceContent,
+  },
+  /// Content of a type added after this client was written.
+  #[serde(other)]
+  Unknown,
+}
+
+fn deserialize_blob<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let blob = Option::<String>::deserialize(deserializer)?;
+  blob
+    .map(|blob| STANDARD.decode(blob).map_err(serde::de::Error::custom))
+    .transpose()
+}
@@ -1,2 +1,4 @@
+pub mod approval;
 pub mod client;
 pub mod entities;
+pub mod registry;
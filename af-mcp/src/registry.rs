@@ -0,0 +1,287 @@
+use crate::approval::{
+  denied_tool_result, ApprovalDecision, ToolApproval, ToolApprovals, ToolCallRequest,
+};
+use crate::client::{MCPClient, MCPServerConfig};
+use crate::entities::{Prompt, PromptContent, PromptResult, Tool};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Separates the namespace of a server from the name of its tool, as in `fs.read_file`.
+pub const NAMESPACE_SEPARATOR: char = '.';
+
+/// A tool of one of the servers of a [McpToolRegistry].
+#[derive(Debug)]
+pub struct NamespacedTool {
+  pub namespace: String,
+  pub tool: Tool,
+}
+
+impl NamespacedTool {
+  /// The name to call the tool with, see [McpToolRegistry::call].
+  pub fn name(&self) -> String {
+    format!(
+      "{}{}{}",
+      self.namespace, NAMESPACE_SEPARATOR, self.tool.name
+    )
+  }
+}
+
+/// A prompt of one of the servers of a [McpToolRegistry], which applications can offer as a
+/// slash command.
+#[derive(Debug)]
+pub struct NamespacedPrompt {
+  pub namespace: String,
+  pub prompt: Prompt,
+}
+
+impl NamespacedPrompt {
+  /// The name to resolve the prompt with, see [McpToolRegistry::resolve_prompt].
+  pub fn name(&self) -> String {
+    format!(
+      "{}{}{}",
+      self.namespace, NAMESPACE_SEPARATOR, self.prompt.name
+    )
+  }
+}
+
+/// The MCP servers the tools offered to the model come from. Each server has a namespace that
+/// prefixes the names of its tools, so two servers can both have a `search` tool.
+#[derive(Default)]
+pub struct McpToolRegistry {
+  clients: RwLock<BTreeMap<String, MCPClient>>,
+  approvals: ToolApprovals,
+}
+
+impl McpToolRegistry {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Starts the server of `config` and registers its tools under `namespace`, or under
+  /// [default_namespace] if none is given. Returns the namespace.
+  ///
+  /// Namespaces may not be empty or contain [NAMESPACE_SEPARATOR], and each can only be
+  /// registered once.
+  pub async fn register_mcp_server(
+    &self,
+    config: MCPServerConfig,
+    namespace: Option<String>,
+  ) -> Result<String> {
+    let namespace = namespace.unwrap_or_else(|| default_namespace(&config));
+    if namespace.is_empty() || namespace.contains(NAMESPACE_SEPARATOR) {
+      return Err(anyhow!(
+        "Invalid MCP namespace {:?}: it must be non-empty and not contain {:?}",
+        namespace,
+        NAMESPACE_SEPARATOR
+      ));
+    }
+    // Checked before starting the server, and again once it is running in case another
+    // registration of the namespace finished meanwhile.
+    self.ensure_available(&namespace)?;
+
+    let client = MCPClient::new_stdio(config).await?;
+    client.initialize().await?;
+    let collision = {
+      let mut clients = self.clients.write().unwrap();
+      if clients.contains_key(&namespace) {
+        Some(client)
+      } else {
+        clients.insert(namespace.clone(), client);
+        None
+      }
+    };
+    if let Some(mut client) = collision {
+      if let Err(err) = client.stop().await {
+        warn!("Failed to stop MCP server of {}: {}", namespace, err);
+      }
+      return Err(already_registered(&namespace));
+    }
+    info!("Registered MCP server under namespace {}", namespace);
+    Ok(namespace)
+  }
+
+  /// Stops the server registered under `namespace`.
+  pub async fn unregister_mcp_server(&self, namespace: &str) -> Result<()> {
+    let client = self.clients.write().unwrap().remove(namespace);
+    match client {
+      Some(mut client) => client.stop().await,
+      None => Err(anyhow!("No MCP server registered under {:?}", namespace)),
+    }
+  }
+
+  /// Asks `handler` before every tool call, see [ToolApprovals].
+  pub fn set_tool_approval_handler(&self, handler: Arc<dyn ToolApproval>) {
+    self.approvals.set_handler(handler);
+  }
+
+  /// See [ToolApprovals::set_dangerous_patterns].
+  pub fn set_dangerous_tool_patterns(&self, patterns: Vec<String>) {
+    self.approvals.set_dangerous_patterns(patterns);
+  }
+
+  /// The registered namespaces, sorted.
+  pub fn namespaces(&self) -> Vec<String> {
+    self.clients.read().unwrap().keys().cloned().collect()
+  }
+
+  /// The tools of every registered server, sorted by namespace.
+  pub async fn list_all_tools(&self) -> Result<Vec<NamespacedTool>> {
+    let mut tools = vec![];
+    for (namespace, client) in self.all_clients() {
+      for tool in client.list_tools().await?.tools {
+        tools.push(NamespacedTool {
+          namespace: namespace.clone(),
+          tool,
+        });
+      }
+    }
+    Ok(tools)
+  }
+
+  /// The prompts of every registered server, sorted by namespace. Servers without prompts are
+  /// skipped.
+  pub async fn list_all_prompts(&self) -> Result<Vec<NamespacedPrompt>> {
+    let mut prompts = vec![];
+    for (namespace, client) in self.all_clients() {
+      for prompt in client.list_prompts().await?.prompts {
+        prompts.push(NamespacedPrompt {
+          namespace: namespace.clone(),
+          prompt,
+        });
+      }
+    }
+    Ok(prompts)
+  }
+
+  /// Gets the prompt named `namespaced_name`, such as `notes.summarize`, filled in with
+  /// `arguments`, and flattens its messages into a single text to send as the message of
+  /// `stream_question` or `complete_text_v2`, see [flatten_prompt].
+  pub async fn resolve_prompt(
+    &self,
+    namespaced_name: &str,
+    arguments: Option<Value>,
+  ) -> Result<String> {
+    let (namespace, name) = split_namespace(namespaced_name, "Prompt")?;
+    let result = self.client(namespace)?.get_prompt(name, arguments).await?;
+    Ok(flatten_prompt(&result))
+  }
+
+  /// Calls the tool named `namespaced_name`, such as `fs.read_file`, on the server registered
+  /// under its namespace, once the call is approved. A denied call returns
+  /// [denied_tool_result] instead, to be sent back to the model like any other result.
+  pub async fn call(
+    &self,
+    namespaced_name: &str,
+    arguments: Option<Value>,
+    timeout: Option<Duration>,
+  ) -> Result<Value> {
+    let (namespace, name) = split_namespace(namespaced_name, "Tool")?;
+    let client = self.client(namespace)?;
+    let request = ToolCallRequest {
+      namespace: namespace.to_string(),
+      name: name.to_string(),
+      arguments,
+    };
+    if let ApprovalDecision::Deny(reason) = self.approvals.approve(&request).await {
+      return Ok(denied_tool_result(&reason));
+    }
+    client.call_tool(name, request.arguments, timeout).await
+  }
+
+  fn client(&self, namespace: &str) -> Result<MCPClient> {
+    self
+      .clients
+      .read()
+      .unwrap()
+      .get(namespace)
+      .cloned()
+      .ok_or_else(|| anyhow!("No MCP server registered under {:?}", namespace))
+  }
+
+  fn all_clients(&self) -> Vec<(String, MCPClient)> {
+    self
+      .clients
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(namespace, client)| (namespace.clone(), client.clone()))
+      .collect()
+  }
+
+  fn ensure_available(&self, namespace: &str) -> Result<()> {
+    if self.clients.read().unwrap().contains_key(namespace) {
+      return Err(already_registered(namespace));
+    }
+    Ok(())
+  }
+}
+
+/// The namespace of a server registered without one: the file name of its command, lowercased,
+/// with runs of other characters than letters and digits replaced by `-`. For example
+/// `/usr/local/bin/mcp_server.fs` becomes `mcp-server-fs`.
+pub fn default_namespace(config: &MCPServerConfig) -> String {
+  let name = Path::new(&config.server_cmd)
+    .file_name()
+    .and_then(|name| name.to_str())
+    .unwrap_or(&config.server_cmd);
+  let mut slug = String::new();
+  for c in name.chars() {
+    if c.is_alphanumeric() {
+      slug.extend(c.to_lowercase());
+    } else if !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+  }
+  let slug = slug.trim_end_matches('-');
+  if slug.is_empty() {
+    "mcp".to_string()
+  } else {
+    slug.to_string()
+  }
+}
+
+/// Joins the text of the messages of `result`, including the text of embedded resources, with
+/// blank lines. When the prompt has other messages than the user's, each message starts with its
+/// role, as in `Assistant: ...`. Images are left out.
+pub fn flatten_prompt(result: &PromptResult) -> String {
+  let label_roles = result.messages.iter().any(|message| message.role != "user");
+  result
+    .messages
+    .iter()
+    .filter_map(|message| {
+      let text = match &message.content {
+        PromptContent::Text { text } => text.as_str(),
+        PromptContent::Resource { resource } => resource.text.as_deref()?,
+        PromptContent::Image { .. } | PromptContent::Unknown => return None,
+      };
+      if !label_roles {
+        return Some(text.to_string());
+      }
+      let mut role = message.role.chars();
+      let role = role
+        .next()
+        .map(|first| first.to_uppercase().chain(role).collect::<String>())
+        .unwrap_or_default();
+      Some(format!("{}: {}", role, text))
+    })
+    .collect::<Vec<_>>()
+    .join("\n\n")
+}
+
+fn split_namespace<'a>(namespaced_name: &'a str, kind: &str) -> Result<(&'a str, &'a str)> {
+  namespaced_name
+    .split_once(NAMESPACE_SEPARATOR)
+    .ok_or_else(|| anyhow!("{} name {:?} has no namespace", kind, namespaced_name))
+}
+
+fn already_registered(namespace: &str) -> anyhow::Error {
+  anyhow!(
+    "An MCP server is already registered under namespace {:?}",
+    namespace
+  )
+}
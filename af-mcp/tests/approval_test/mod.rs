@@ -0,0 +1,164 @@
+use af_mcp::approval::{
+  denied_tool_result, ApprovalDecision, ToolApproval, ToolApprovals, ToolCallRequest,
+};
+use async_trait::async_trait;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Answers with the scripted decisions in order, and records the calls it was asked about.
+#[derive(Default)]
+struct ScriptedApproval {
+  decisions: Mutex<VecDeque<ApprovalDecision>>,
+  asked: Mutex<Vec<String>>,
+}
+
+impl ScriptedApproval {
+  fn new(decisions: Vec<ApprovalDecision>) -> Arc<Self> {
+    Arc::new(Self {
+      decisions: Mutex::new(decisions.into()),
+      asked: Default::default(),
+    })
+  }
+
+  fn asked(&self) -> Vec<String> {
+    self.asked.lock().unwrap().clone()
+  }
+}
+
+#[async_trait]
+impl ToolApproval for ScriptedApproval {
+  async fn approve(&self, call: &ToolCallRequest) -> ApprovalDecision {
+    self
+      .asked
+      .lock()
+      .unwrap()
+      .push(format!("{}.{}", call.namespace, call.name));
+    self
+      .decisions
+      .lock()
+      .unwrap()
+      .pop_front()
+      .expect("unexpected approval request")
+  }
+}
+
+fn call(namespace: &str, name: &str) -> ToolCallRequest {
+  ToolCallRequest {
+    namespace: namespace.to_string(),
+    name: name.to_string(),
+    arguments: Some(json!({ "path": "notes.md" })),
+  }
+}
+
+#[tokio::test]
+async fn handler_decisions_test() {
+  let approvals = ToolApprovals::default();
+  let handler = ScriptedApproval::new(vec![
+    ApprovalDecision::Allow,
+    ApprovalDecision::Allow,
+    ApprovalDecision::Deny("the user declined".to_string()),
+  ]);
+  approvals.set_handler(handler.clone());
+
+  // The handler is asked before every call, including tools that aren't dangerous.
+  assert_eq!(
+    approvals.approve(&call("fs", "read_file")).await,
+    ApprovalDecision::Allow
+  );
+  assert_eq!(
+    approvals.approve(&call("fs", "write_file")).await,
+    ApprovalDecision::Allow
+  );
+  assert_eq!(
+    approvals.approve(&call("fs", "write_file")).await,
+    ApprovalDecision::Deny("the user declined".to_string())
+  );
+  assert_eq!(
+    handler.asked(),
+    vec!["fs.read_file", "fs.write_file", "fs.write_file"]
+  );
+}
+
+#[tokio::test]
+async fn allow_always_is_cached_per_tool_test() {
+  let approvals = ToolApprovals::default();
+  let handler = ScriptedApproval::new(vec![
+    ApprovalDecision::AllowAlwaysForTool,
+    ApprovalDecision::Deny("not this one".to_string()),
+    ApprovalDecision::Deny("not this server".to_string()),
+  ]);
+  approvals.set_handler(handler.clone());
+
+  assert_eq!(
+    approvals.approve(&call("fs", "write_file")).await,
+    ApprovalDecision::AllowAlwaysForTool
+  );
+  for _ in 0..3 {
+    assert_eq!(
+      approvals.approve(&call("fs", "write_file")).await,
+      ApprovalDecision::Allow
+    );
+  }
+  // Other tools, and the same tool of another server, are still asked about.
+  assert!(matches!(
+    approvals.approve(&call("fs", "move_file")).await,
+    ApprovalDecision::Deny(_)
+  ));
+  assert!(matches!(
+    approvals.approve(&call("backup", "write_file")).await,
+    ApprovalDecision::Deny(_)
+  ));
+  assert_eq!(
+    handler.asked(),
+    vec!["fs.write_file", "fs.move_file", "backup.write_file"]
+  );
+}
+
+#[tokio::test]
+async fn default_decisions_test() {
+  let approvals = ToolApprovals::default();
+  for name in ["write_file", "edit_file", "move_file"] {
+    match approvals.approve(&call("fs", name)).await {
+      ApprovalDecision::Deny(reason) => assert!(reason.contains(name), "{}", reason),
+      decision => panic!("{} was not denied: {:?}", name, decision),
+    }
+  }
+  for name in ["read_file", "list_allowed_dirs", "rewrite_file"] {
+    assert_eq!(
+      approvals.approve(&call("fs", name)).await,
+      ApprovalDecision::Allow,
+      "{}",
+      name
+    );
+  }
+
+  approvals.set_dangerous_patterns(vec!["*_file".to_string(), "delete".to_string()]);
+  assert!(matches!(
+    approvals.approve(&call("fs", "read_file")).await,
+    ApprovalDecision::Deny(_)
+  ));
+  assert!(matches!(
+    approvals.approve(&call("fs", "delete")).await,
+    ApprovalDecision::Deny(_)
+  ));
+  assert_eq!(
+    approvals.approve(&call("fs", "delete_all")).await,
+    ApprovalDecision::Allow
+  );
+  assert_eq!(
+    approvals.approve(&call("fs", "write_dir")).await,
+    ApprovalDecision::Allow
+  );
+}
+
+#[test]
+fn denied_tool_result_test() {
+  let result = denied_tool_result("the user declined");
+  assert_eq!(result["isError"], true);
+  assert_eq!(result["content"][0]["type"], "text");
+  assert!(result["content"][0]["text"]
+    .as_str()
+    .unwrap()
+    .contains("the user declined"));
+}
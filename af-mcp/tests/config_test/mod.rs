@@ -0,0 +1,102 @@
+use af_mcp::client::{ConfigError, MCPServerConfig, TransportType};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[test]
+fn config_serde_round_trip_test() {
+  let config = MCPServerConfig {
+    server_cmd: "npx".to_string(),
+    args: vec![
+      "-y".to_string(),
+      "@modelcontextprotocol/server-memory".to_string(),
+    ],
+    env: HashMap::from([("MEMORY_FILE".to_string(), "/tmp/memory.json".to_string())]),
+    working_dir: Some(PathBuf::from("/tmp")),
+    transport: TransportType::Stdio,
+  };
+  let value = serde_json::to_value(&config).unwrap();
+  assert_eq!(value["transport"], "stdio");
+  assert_eq!(
+    serde_json::from_value::<MCPServerConfig>(value).unwrap(),
+    config
+  );
+
+  // Stored before the environment, working directory and transport were configurable.
+  let config = serde_json::from_value::<MCPServerConfig>(json!({
+    "server_cmd": "/usr/local/bin/mcp-server-fs",
+    "args": ["."],
+  }))
+  .unwrap();
+  assert_eq!(
+    config,
+    MCPServerConfig::new("/usr/local/bin/mcp-server-fs", vec![".".to_string()])
+  );
+  assert_eq!(
+    serde_json::to_value(&config).unwrap(),
+    json!({ "server_cmd": "/usr/local/bin/mcp-server-fs", "args": ["."], "transport": "stdio" })
+  );
+}
+
+#[test]
+fn config_validate_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let dir = dir.path();
+  let cargo = PathBuf::from(env!("CARGO"));
+  let config = |cmd: &str, args: &[&str]| {
+    MCPServerConfig::new(cmd, args.iter().map(|arg| arg.to_string()).collect())
+  };
+
+  assert_eq!(config(cargo.to_str().unwrap(), &[]).validate(), Ok(()));
+  assert_eq!(config("  ", &[]).validate(), Err(ConfigError::EmptyCommand));
+  assert_eq!(
+    config("/no/such/mcp-server", &[]).validate(),
+    Err(ConfigError::CommandNotFound(
+      "/no/such/mcp-server".to_string()
+    ))
+  );
+  assert_eq!(
+    config("no-such-mcp-server", &[]).validate(),
+    Err(ConfigError::CommandNotFound(
+      "no-such-mcp-server".to_string()
+    ))
+  );
+
+  // Found on the PATH of the server, which may differ from the one of this process.
+  let cargo_name = cargo.file_name().unwrap().to_str().unwrap();
+  let mut on_path = config(cargo_name, &[]);
+  on_path.env.insert(
+    "PATH".to_string(),
+    cargo.parent().unwrap().to_string_lossy().into_owned(),
+  );
+  assert_eq!(on_path.validate(), Ok(()));
+
+  // A launcher only starts a server when told which one.
+  let launcher = dir.join("npx");
+  std::fs::write(&launcher, "").unwrap();
+  let launcher = launcher.to_str().unwrap();
+  assert_eq!(
+    config(launcher, &[]).validate(),
+    Err(ConfigError::MissingArgs(launcher.to_string()))
+  );
+  assert_eq!(
+    config(launcher, &["-y", "server-memory"]).validate(),
+    Ok(())
+  );
+
+  let mut invalid_env = config(cargo.to_str().unwrap(), &[]);
+  invalid_env.env.insert("A=B".to_string(), "c".to_string());
+  assert_eq!(
+    invalid_env.validate(),
+    Err(ConfigError::InvalidEnvVar("A=B".to_string()))
+  );
+
+  let mut missing_dir = config(cargo.to_str().unwrap(), &[]);
+  missing_dir.working_dir = Some(dir.join("no-such-directory"));
+  assert_eq!(
+    missing_dir.validate(),
+    Err(ConfigError::WorkingDirNotFound(
+      dir.join("no-such-directory")
+    ))
+  );
+}
@@ -12,10 +12,7 @@ async fn connect_to_server() {
     panic!("MCP_SERVER_EXE_PATH environment variable is not set");
   }
 
-  let config = MCPServerConfig {
-    server_cmd: command,
-    args: vec![".".to_string()],
-  };
+  let config = MCPServerConfig::new(command, vec![".".to_string()]);
 
   let client = MCPClient::new_stdio(config)
     .await
@@ -1 +1,10 @@
+mod approval_test;
+mod config_test;
 mod connect_test;
+#[cfg(feature = "fake-server-tests")]
+mod probe_test;
+#[cfg(feature = "fake-server-tests")]
+mod prompt_test;
+mod registry_test;
+#[cfg(feature = "fake-server-tests")]
+mod resource_test;
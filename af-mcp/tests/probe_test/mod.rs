@@ -0,0 +1,62 @@
+use af_mcp::client::{MCPClient, MCPServerConfig, ProbeError};
+use std::time::Duration;
+
+fn fake_server(args: &[&str]) -> MCPServerConfig {
+  MCPServerConfig::new(
+    env!("CARGO_BIN_EXE_fake_mcp_server"),
+    args.iter().map(|arg| arg.to_string()).collect(),
+  )
+}
+
+#[tokio::test]
+async fn test_connection_test() {
+  let probe = MCPClient::test_connection(fake_server(&[]), Duration::from_secs(5))
+    .await
+    .unwrap();
+  assert_eq!(probe.tools_count, 1);
+  assert_eq!(probe.server_name, "fake-mcp-server");
+  assert!(probe.latency < Duration::from_secs(5));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_connection_env_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let mut config = fake_server(&["--print-env", "FAKE_MCP_GREETING"]);
+  config
+    .env
+    .insert("FAKE_MCP_GREETING".to_string(), "hello world".to_string());
+  config.working_dir = Some(dir.path().to_path_buf());
+  config.validate().unwrap();
+
+  let probe = MCPClient::test_connection(config, Duration::from_secs(5))
+    .await
+    .unwrap();
+  let working_dir = dir.path().canonicalize().unwrap();
+  assert_eq!(
+    probe.server_name,
+    format!("fake-mcp-server hello world {}", working_dir.display())
+  );
+}
+
+#[tokio::test]
+async fn test_connection_failures_test() {
+  let err = MCPClient::test_connection(
+    MCPServerConfig::new("/no/such/mcp-server", vec![]),
+    Duration::from_secs(5),
+  )
+  .await
+  .unwrap_err();
+  assert!(matches!(err, ProbeError::SpawnFailed(_)), "{:?}", err);
+
+  let timeout = Duration::from_millis(300);
+  let err = MCPClient::test_connection(fake_server(&["--hang"]), timeout)
+    .await
+    .unwrap_err();
+  assert_eq!(err, ProbeError::InitTimeout(timeout));
+
+  let err = MCPClient::test_connection(fake_server(&["--bad-init"]), Duration::from_secs(5))
+    .await
+    .unwrap_err();
+  assert!(matches!(err, ProbeError::ProtocolError(_)), "{:?}", err);
+}
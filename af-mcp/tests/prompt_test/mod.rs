@@ -0,0 +1,108 @@
+use af_mcp::client::{MCPClient, MCPServerConfig};
+use af_mcp::entities::PromptContent;
+use af_mcp::registry::McpToolRegistry;
+use serde_json::json;
+
+fn fake_server(args: &[&str]) -> MCPServerConfig {
+  MCPServerConfig::new(
+    env!("CARGO_BIN_EXE_fake_mcp_server"),
+    args.iter().map(|arg| arg.to_string()).collect(),
+  )
+}
+
+#[tokio::test]
+async fn list_and_get_prompts_test() {
+  let mut client = MCPClient::new_stdio(fake_server(&[])).await.unwrap();
+  client.initialize().await.unwrap();
+
+  // One prompt per page.
+  let prompts = client.list_prompts().await.unwrap().prompts;
+  let names = prompts
+    .iter()
+    .map(|prompt| prompt.name.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(names, vec!["summarize", "greet"]);
+  let arguments = &prompts[0].arguments;
+  assert_eq!(arguments.len(), 2);
+  assert_eq!(arguments[0].name, "topic");
+  assert!(arguments[0].required);
+  assert!(!arguments[1].required);
+
+  let result = client
+    .get_prompt("summarize", Some(json!({ "topic": "bananas" })))
+    .await
+    .unwrap();
+  assert_eq!(result.description.as_deref(), Some("Summarize a topic"));
+  assert_eq!(result.messages.len(), 2);
+  assert!(matches!(
+    &result.messages[0].content,
+    PromptContent::Text { text } if text == "Summarize what you know about bananas."
+  ));
+  assert!(matches!(
+    &result.messages[1].content,
+    PromptContent::Resource { resource } if resource.uri == "file:///notes/bananas.md"
+  ));
+
+  assert!(client.get_prompt("summarize", None).await.is_err());
+  client.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn server_without_prompts_test() {
+  let mut client = MCPClient::new_stdio(fake_server(&["--no-prompts"]))
+    .await
+    .unwrap();
+  client.initialize().await.unwrap();
+  assert!(client.list_prompts().await.unwrap().prompts.is_empty());
+  assert!(client.get_prompt("summarize", None).await.is_err());
+  client.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn resolve_prompt_test() {
+  let registry = McpToolRegistry::new();
+  registry
+    .register_mcp_server(fake_server(&[]), Some("notes".to_string()))
+    .await
+    .unwrap();
+  registry
+    .register_mcp_server(fake_server(&["--no-prompts"]), Some("plain".to_string()))
+    .await
+    .unwrap();
+
+  let names = registry
+    .list_all_prompts()
+    .await
+    .unwrap()
+    .iter()
+    .map(|prompt| prompt.name())
+    .collect::<Vec<_>>();
+  assert_eq!(names, vec!["notes.summarize", "notes.greet"]);
+
+  let text = registry
+    .resolve_prompt(
+      "notes.summarize",
+      Some(json!({ "topic": "bananas", "style": "short" })),
+    )
+    .await
+    .unwrap();
+  assert_eq!(
+    text,
+    "Summarize what you know about bananas. Keep it short.\n\nNotes about bananas."
+  );
+
+  let text = registry
+    .resolve_prompt("notes.greet", Some(json!({ "name": "Ada" })))
+    .await
+    .unwrap();
+  assert_eq!(text, "User: Say hello to Ada.\n\nAssistant: Hello Ada!");
+
+  assert!(registry.resolve_prompt("summarize", None).await.is_err());
+  assert!(registry
+    .resolve_prompt("missing.summarize", None)
+    .await
+    .is_err());
+  for namespace in ["notes", "plain"] {
+    registry.unregister_mcp_server(namespace).await.unwrap();
+  }
+}
@@ -0,0 +1,85 @@
+use af_mcp::client::MCPServerConfig;
+use af_mcp::registry::{default_namespace, McpToolRegistry};
+#[cfg(feature = "fake-server-tests")]
+use serde_json::json;
+
+#[cfg(feature = "fake-server-tests")]
+fn fake_server() -> MCPServerConfig {
+  MCPServerConfig::new(env!("CARGO_BIN_EXE_fake_mcp_server"), vec![])
+}
+
+#[test]
+fn default_namespace_test() {
+  let config = |cmd: &str| MCPServerConfig::new(cmd, vec![]);
+  assert_eq!(
+    default_namespace(&config("/usr/local/bin/mcp_server.fs")),
+    "mcp-server-fs"
+  );
+  assert_eq!(default_namespace(&config("npx")), "npx");
+  assert_eq!(default_namespace(&config("./My Server!")), "my-server");
+  assert_eq!(default_namespace(&config("...")), "mcp");
+}
+
+#[tokio::test]
+async fn invalid_namespace_test() {
+  let registry = McpToolRegistry::new();
+  for namespace in ["", "a.b"] {
+    let result = registry
+      .register_mcp_server(
+        MCPServerConfig::new("unused", vec![]),
+        Some(namespace.to_string()),
+      )
+      .await;
+    assert!(result.is_err(), "{:?}", namespace);
+  }
+  assert!(registry.namespaces().is_empty());
+}
+
+#[cfg(feature = "fake-server-tests")]
+#[tokio::test]
+async fn namespaced_tools_round_trip() {
+  let registry = McpToolRegistry::new();
+  for namespace in ["ns1", "ns2"] {
+    registry
+      .register_mcp_server(fake_server(), Some(namespace.to_string()))
+      .await
+      .unwrap();
+  }
+  assert_eq!(registry.namespaces(), vec!["ns1", "ns2"]);
+
+  let err = registry
+    .register_mcp_server(fake_server(), Some("ns1".to_string()))
+    .await
+    .unwrap_err();
+  assert!(err.to_string().contains("already registered"), "{}", err);
+
+  // Both servers have the same tools, which only differ by their namespace.
+  let tools = registry.list_all_tools().await.unwrap();
+  let names = tools.iter().map(|tool| tool.name()).collect::<Vec<_>>();
+  assert!(names.contains(&"ns1.echo".to_string()));
+  assert!(names.contains(&"ns2.echo".to_string()));
+
+  for namespace in ["ns1", "ns2"] {
+    let name = format!("{}.echo", namespace);
+    let resp = registry
+      .call(&name, Some(json!({ "text": namespace })), None)
+      .await
+      .unwrap();
+    assert_eq!(resp["content"][0]["text"], namespace);
+  }
+
+  // A stopped server doesn't affect the other one.
+  registry.unregister_mcp_server("ns1").await.unwrap();
+  assert!(registry
+    .call("ns1.echo", Some(json!({ "text": "ns1" })), None)
+    .await
+    .is_err());
+  registry
+    .call("ns2.echo", Some(json!({ "text": "ns2" })), None)
+    .await
+    .unwrap();
+  assert!(registry
+    .call("echo", Some(json!({ "text": "ns2" })), None)
+    .await
+    .is_err());
+}
@@ -0,0 +1,41 @@
+use af_mcp::client::{MCPClient, MCPServerConfig};
+
+#[tokio::test]
+async fn read_file_resource() {
+  let config = MCPServerConfig::new(env!("CARGO_BIN_EXE_fake_mcp_server"), vec![]);
+  let client = MCPClient::new_stdio(config)
+    .await
+    .expect("Failed to create MCPClient");
+  client.initialize().await.expect("Initialization failed");
+
+  // The fake server lists one resource per page.
+  let path = std::fs::canonicalize("Cargo.toml").unwrap();
+  let uri = format!("file://{}", path.display());
+  let resources = client
+    .list_resources()
+    .await
+    .expect("Listing resources failed")
+    .resources;
+  let uris = resources
+    .iter()
+    .map(|resource| resource.uri.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(uris.len(), 2);
+  assert_eq!(uris[0], uri);
+  assert_eq!(resources[0].name, "Cargo.toml");
+
+  // Read a file whose content we know and make sure it round-trips.
+  let expected = std::fs::read_to_string(&path).unwrap();
+  let content = client
+    .read_resource(&uri)
+    .await
+    .expect("Reading resource failed");
+
+  assert_eq!(content.uri, uri);
+  let text = match (content.text, content.blob) {
+    (Some(text), _) => text,
+    (None, Some(blob)) => String::from_utf8(blob).unwrap(),
+    (None, None) => panic!("resource has no content"),
+  };
+  assert_eq!(text, expected);
+}
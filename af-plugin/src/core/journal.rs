@@ -0,0 +1,262 @@
+use crate::util::redact_secrets;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, trace};
+
+const JOURNAL_FILE_NAME: &str = "rpc_journal.log";
+/// The journal is rotated once the current file would grow past this size.
+const MAX_JOURNAL_SIZE: u64 = 1024 * 1024;
+/// Number of journal files kept on disk, including the one being written.
+const MAX_JOURNAL_FILES: usize = 3;
+/// Params longer than this are truncated before they are written.
+const MAX_PARAMS_LEN: usize = 256;
+/// Records are dropped instead of blocking the RPC thread once this many are queued.
+const CHANNEL_CAPACITY: usize = 256;
+/// Key of the params holding the id of the operation a request belongs to, `TRACE_ID_KEY` in
+/// af-ai-protocol.
+const TRACE_ID_KEY: &str = "trace_id";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalRecord {
+  /// An outgoing request.
+  Request {
+    id: usize,
+    method: String,
+    params: String,
+    /// Kept apart from `params`, which may be truncated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+  },
+  /// The plugin disconnected unexpectedly.
+  Disconnect {
+    pending_ids: Vec<usize>,
+    from_state: String,
+    to_state: String,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+  /// Milliseconds since the Unix epoch.
+  pub timestamp: u64,
+  #[serde(flatten)]
+  pub record: JournalRecord,
+}
+
+/// The last requests sent before the plugin terminated unexpectedly.
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+  pub requests: Vec<JournalEntry>,
+  pub disconnect: JournalEntry,
+}
+
+enum JournalMessage {
+  Entry(JournalEntry),
+  Flush(mpsc::Sender<()>),
+}
+
+/// An append-only, size capped journal of the requests sent to a plugin.
+///
+/// Records are written by a background thread, so recording never blocks the RPC hot path.
+#[derive(Clone)]
+pub struct CrashJournal {
+  tx: SyncSender<JournalMessage>,
+}
+
+impl CrashJournal {
+  pub fn open<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    let writer = JournalWriter::open(dir)?;
+    let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    thread::Builder::new()
+      .name("rpc journal writer".to_string())
+      .spawn(move || writer.run(rx))?;
+    Ok(Self { tx })
+  }
+
+  /// Records the request `id`. A `handle` request is recorded with the method it carries in
+  /// its params, the operation the plugin runs.
+  pub fn record_request(&self, id: usize, method: &str, params: &JsonValue) {
+    let method = match params.get("method").and_then(|method| method.as_str()) {
+      Some(operation) if method == HANDLE_METHOD => operation,
+      _ => method,
+    };
+    self.record(JournalRecord::Request {
+      id,
+      method: method.to_string(),
+      params: truncate(redact_secrets(params).to_string(), MAX_PARAMS_LEN),
+      trace_id: trace_id(params),
+    });
+  }
+
+  pub fn record_disconnect(&self, pending_ids: Vec<usize>, from_state: String, to_state: String) {
+    self.record(JournalRecord::Disconnect {
+      pending_ids,
+      from_state,
+      to_state,
+    });
+  }
+
+  /// Blocks until every record queued so far has been written to disk.
+  pub fn flush(&self) {
+    let (tx, rx) = mpsc::channel();
+    if self.tx.send(JournalMessage::Flush(tx)).is_ok() {
+      let _ = rx.recv();
+    }
+  }
+
+  fn record(&self, record: JournalRecord) {
+    let entry = JournalEntry {
+      timestamp: now_millis(),
+      record,
+    };
+    if let Err(TrySendError::Full(_)) = self.tx.try_send(JournalMessage::Entry(entry)) {
+      trace!("[RPC] journal is busy, dropping record");
+    }
+  }
+}
+
+/// The trace id of a request, found in the params of the method it wraps for `handle` requests.
+/// The method of the requests carrying an operation in their params.
+const HANDLE_METHOD: &str = "handle";
+
+fn trace_id(params: &JsonValue) -> Option<String> {
+  params
+    .get("params")
+    .and_then(|params| params.get(TRACE_ID_KEY))
+    .or_else(|| params.get(TRACE_ID_KEY))
+    .and_then(|trace_id| trace_id.as_str())
+    .map(|trace_id| trace_id.to_string())
+}
+
+/// Reads the journal in `dir` and returns the last `max_requests` requests recorded before the
+/// most recent unexpected disconnect, or `None` if there was no such disconnect.
+pub fn read_crash_report(dir: &Path, max_requests: usize) -> io::Result<Option<CrashReport>> {
+  let mut entries = Vec::new();
+  for index in (0..MAX_JOURNAL_FILES).rev() {
+    let path = journal_file_path(dir, index);
+    if !path.exists() {
+      continue;
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+      if let Ok(entry) = serde_json::from_str::<JournalEntry>(&line?) {
+        entries.push(entry);
+      }
+    }
+  }
+
+  let disconnect_index = match entries
+    .iter()
+    .rposition(|entry| matches!(entry.record, JournalRecord::Disconnect { .. }))
+  {
+    Some(index) => index,
+    None => return Ok(None),
+  };
+  let disconnect = entries.remove(disconnect_index);
+  entries.truncate(disconnect_index);
+  let requests = entries
+    .into_iter()
+    .rev()
+    .filter(|entry| matches!(entry.record, JournalRecord::Request { .. }))
+    .take(max_requests)
+    .collect::<Vec<_>>()
+    .into_iter()
+    .rev()
+    .collect();
+  Ok(Some(CrashReport {
+    requests,
+    disconnect,
+  }))
+}
+
+struct JournalWriter {
+  dir: PathBuf,
+  file: File,
+  size: u64,
+}
+
+impl JournalWriter {
+  fn open(dir: PathBuf) -> io::Result<Self> {
+    let file = open_append(&journal_file_path(&dir, 0))?;
+    let size = file.metadata()?.len();
+    Ok(Self { dir, file, size })
+  }
+
+  fn run(mut self, rx: Receiver<JournalMessage>) {
+    while let Ok(message) = rx.recv() {
+      match message {
+        JournalMessage::Entry(entry) => {
+          if let Err(err) = self.write(&entry) {
+            error!("[RPC] failed to write journal: {:?}", err);
+          }
+        },
+        JournalMessage::Flush(ack) => {
+          let _ = self.file.flush();
+          let _ = ack.send(());
+        },
+      }
+    }
+  }
+
+  fn write(&mut self, entry: &JournalEntry) -> io::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    if self.size > 0 && self.size + line.len() as u64 > MAX_JOURNAL_SIZE {
+      self.rotate()?;
+    }
+    self.file.write_all(line.as_bytes())?;
+    self.size += line.len() as u64;
+    Ok(())
+  }
+
+  /// Shifts `rpc_journal.log.N` to `N + 1`, dropping the oldest file.
+  fn rotate(&mut self) -> io::Result<()> {
+    for index in (0..MAX_JOURNAL_FILES - 1).rev() {
+      let from = journal_file_path(&self.dir, index);
+      if from.exists() {
+        fs::rename(from, journal_file_path(&self.dir, index + 1))?;
+      }
+    }
+    self.file = open_append(&journal_file_path(&self.dir, 0))?;
+    self.size = 0;
+    Ok(())
+  }
+}
+
+fn journal_file_path(dir: &Path, index: usize) -> PathBuf {
+  match index {
+    0 => dir.join(JOURNAL_FILE_NAME),
+    index => dir.join(format!("{}.{}", JOURNAL_FILE_NAME, index)),
+  }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+  OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn truncate(mut s: String, max_len: usize) -> String {
+  if s.len() > max_len {
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+      end -= 1;
+    }
+    s.truncate(end);
+    s.push('…');
+  }
+  s
+}
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_millis() as u64)
+    .unwrap_or_default()
+}
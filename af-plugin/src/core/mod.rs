@@ -1,6 +1,16 @@
+pub mod binary;
+pub mod journal;
+pub mod orphan;
 pub mod parser;
 pub mod path;
 pub mod plugin;
+pub mod process_limits;
+pub mod resource_usage;
 pub mod rpc_loop;
 mod rpc_object;
 pub mod rpc_peer;
+pub mod state_machine;
+pub mod stream;
+pub mod stream_error;
+pub mod transport;
+pub mod writer;
@@ -0,0 +1,274 @@
+use crate::error::PluginError;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Name of the file holding the pid of the application that uses a persist directory.
+pub const LOCK_FILE_NAME: &str = "plugin.lock";
+
+/// The outcome of [PluginManager::reap_orphans](crate::manager::PluginManager::reap_orphans).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OrphanReport {
+  /// Plugin processes left behind by previous runs and killed.
+  pub killed: Vec<u32>,
+  /// Pid of the exited application whose lock file was removed.
+  pub stale_lock: Option<u32>,
+  /// Pid of another running application that uses the persist directory. Nothing is killed
+  /// then, as the plugins found may be its own.
+  pub locked_by: Option<u32>,
+}
+
+/// Who holds the lock file of a persist directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+  Free,
+  /// Held by the current process.
+  Owned,
+  /// Held by another running process.
+  Held(u32),
+  /// Left behind by a process that exited.
+  Stale(u32),
+}
+
+pub fn lock_path(persist_directory: &Path) -> PathBuf {
+  persist_directory.join(LOCK_FILE_NAME)
+}
+
+/// Records the current process as the user of `persist_directory`.
+pub fn write_lock_file(persist_directory: &Path) -> Result<(), PluginError> {
+  std::fs::write(lock_path(persist_directory), std::process::id().to_string())?;
+  Ok(())
+}
+
+/// Removes the lock file of `persist_directory` if the current process holds it.
+pub fn remove_lock_file(persist_directory: &Path) -> Result<(), PluginError> {
+  if read_lock_state(persist_directory)? != LockState::Owned {
+    return Ok(());
+  }
+  match std::fs::remove_file(lock_path(persist_directory)) {
+    Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+    _ => Ok(()),
+  }
+}
+
+pub fn read_lock_state(persist_directory: &Path) -> Result<LockState, PluginError> {
+  let path = lock_path(persist_directory);
+  if !path.exists() {
+    return Ok(LockState::Free);
+  }
+  let pid = match std::fs::read_to_string(&path)?.trim().parse::<u32>() {
+    Ok(pid) => pid,
+    Err(_) => {
+      warn!("[AI Plugin] ignore malformed lock file {:?}", path);
+      return Ok(LockState::Free);
+    },
+  };
+  Ok(if pid == std::process::id() {
+    LockState::Owned
+  } else if is_process_alive(pid) {
+    LockState::Held(pid)
+  } else {
+    LockState::Stale(pid)
+  })
+}
+
+/// Kills the orphaned processes running `executable`, see
+/// [PluginManager::reap_orphans](crate::manager::PluginManager::reap_orphans).
+pub(crate) fn reap_orphans(
+  executable: &Path,
+  persist_directory: Option<&Path>,
+) -> Result<OrphanReport, PluginError> {
+  let executable = std::fs::canonicalize(executable)?;
+  let mut report = OrphanReport::default();
+  if let Some(persist_directory) = persist_directory {
+    match read_lock_state(persist_directory)? {
+      LockState::Held(pid) => {
+        warn!(
+          "[AI Plugin] {:?} is used by running process {}, not reaping plugins",
+          persist_directory, pid
+        );
+        report.locked_by = Some(pid);
+        return Ok(report);
+      },
+      LockState::Stale(pid) => {
+        info!(
+          "[AI Plugin] remove lock of exited process {} from {:?}",
+          pid, persist_directory
+        );
+        std::fs::remove_file(lock_path(persist_directory))?;
+        report.stale_lock = Some(pid);
+      },
+      LockState::Free | LockState::Owned => {},
+    }
+  }
+
+  let current = std::process::id();
+  for process in list_processes()? {
+    if process.pid == current || !is_orphan(&process) {
+      continue;
+    }
+    // Matches the path the process was started from rather than its reported name, which can
+    // be truncated or changed by the process itself, or shared by another installation.
+    let exe = std::fs::canonicalize(&process.exe).unwrap_or_else(|_| process.exe.clone());
+    if exe != executable {
+      continue;
+    }
+    warn!(
+      "[AI Plugin] killing orphaned plugin {} ({:?})",
+      process.pid, process.exe
+    );
+    match kill_process(process.pid) {
+      Ok(()) => report.killed.push(process.pid),
+      Err(err) => warn!(
+        "[AI Plugin] failed to kill orphaned plugin {}: {}",
+        process.pid, err
+      ),
+    }
+  }
+  Ok(report)
+}
+
+/// Whether the application that started `process` exited. Its orphans are reparented to init,
+/// or launchd on macOS, while the plugins of a running application keep it as their parent.
+fn is_orphan(process: &ProcessEntry) -> bool {
+  process.parent_pid <= 1 || !is_process_alive(process.parent_pid)
+}
+
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+struct ProcessEntry {
+  pid: u32,
+  parent_pid: u32,
+  exe: PathBuf,
+}
+
+/// Processes whose executable can be read, which excludes exited ones and, usually, those of
+/// other users.
+#[cfg(target_os = "linux")]
+fn list_processes() -> Result<Vec<ProcessEntry>, PluginError> {
+  let mut processes = vec![];
+  for entry in std::fs::read_dir("/proc")? {
+    let entry = match entry {
+      Ok(entry) => entry,
+      Err(_) => continue,
+    };
+    let pid = match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+      Some(pid) => pid,
+      None => continue,
+    };
+    let exe = match std::fs::read_link(entry.path().join("exe")) {
+      Ok(exe) => exe,
+      Err(_) => continue,
+    };
+    // The executable of a plugin that was updated while it ran is reported as deleted.
+    let exe = match exe.to_str().and_then(|exe| exe.strip_suffix(" (deleted)")) {
+      Some(exe) => PathBuf::from(exe),
+      None => exe,
+    };
+    // The parent pid is the second field after the command name, which may contain spaces.
+    let parent_pid = std::fs::read_to_string(entry.path().join("stat"))
+      .ok()
+      .and_then(|stat| {
+        let fields = stat[stat.rfind(')')? + 1..].split_whitespace().nth(1)?;
+        fields.parse().ok()
+      });
+    if let Some(parent_pid) = parent_pid {
+      processes.push(ProcessEntry {
+        pid,
+        parent_pid,
+        exe,
+      });
+    }
+  }
+  Ok(processes)
+}
+
+#[cfg(target_os = "macos")]
+fn list_processes() -> Result<Vec<ProcessEntry>, PluginError> {
+  use std::ffi::OsStr;
+  use std::os::unix::ffi::OsStrExt;
+
+  /// `PROC_PIDPATHINFO_MAXSIZE` of `libproc.h`.
+  const MAX_PATH_SIZE: usize = 4096;
+
+  let count = unsafe { libc::proc_listallpids(std::ptr::null_mut(), 0) };
+  if count <= 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+  // Leaves room for processes started between the two calls.
+  let mut pids = vec![0 as libc::pid_t; count as usize + 32];
+  let size = (pids.len() * std::mem::size_of::<libc::pid_t>()) as libc::c_int;
+  let count = unsafe { libc::proc_listallpids(pids.as_mut_ptr() as *mut libc::c_void, size) };
+  if count <= 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+
+  let mut processes = vec![];
+  for pid in pids.into_iter().take(count as usize) {
+    let mut path = vec![0u8; MAX_PATH_SIZE];
+    let len = unsafe {
+      libc::proc_pidpath(
+        pid,
+        path.as_mut_ptr() as *mut libc::c_void,
+        MAX_PATH_SIZE as u32,
+      )
+    };
+    if len <= 0 {
+      continue;
+    }
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let written = unsafe {
+      libc::proc_pidinfo(
+        pid,
+        libc::PROC_PIDTBSDINFO,
+        0,
+        &mut info as *mut _ as *mut libc::c_void,
+        size,
+      )
+    };
+    if written != size {
+      continue;
+    }
+    processes.push(ProcessEntry {
+      pid: pid as u32,
+      parent_pid: info.pbi_ppid,
+      exe: PathBuf::from(OsStr::from_bytes(&path[..len as usize])),
+    });
+  }
+  Ok(processes)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn list_processes() -> Result<Vec<ProcessEntry>, PluginError> {
+  Err(PluginError::Unsupported(
+    "reaping orphaned plugins".to_string(),
+  ))
+}
+
+#[cfg(unix)]
+fn kill_process(pid: u32) -> Result<(), PluginError> {
+  if unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill_process(_pid: u32) -> Result<(), PluginError> {
+  Err(PluginError::Unsupported(
+    "reaping orphaned plugins".to_string(),
+  ))
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+  // Signal 0 only checks that the process exists. EPERM means it exists but belongs to another
+  // user.
+  let found = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+  found || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to tell, a lock is assumed to be held.
+#[cfg(not(unix))]
+fn is_process_alive(_pid: u32) -> bool {
+  true
+}
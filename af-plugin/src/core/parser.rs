@@ -1,16 +1,33 @@
+use crate::core::binary::{read_binary_frame, BinaryFrame, BINARY_FRAME_SENTINEL};
 use crate::core::rpc_object::RpcObject;
+use crate::core::stream::BackpressureReport;
 
 use crate::error::{ReadError, RemoteError};
 use serde_json::{json, Value as JsonValue};
 use std::io::BufRead;
 use tracing::error;
 
+/// Byte order mark some Windows tools write at the start of a UTF-8 stream.
+const BOM: char = '\u{feff}';
+
+/// A message read from the plugin.
+#[derive(Debug)]
+pub enum Frame {
+  Json(RpcObject),
+  Binary(BinaryFrame),
+}
+
 #[derive(Debug, Default)]
-pub struct MessageReader(String);
+pub struct MessageReader {
+  line: String,
+  /// Whether a line was read, so a byte order mark is only stripped from the first one.
+  started: bool,
+}
 
 impl MessageReader {
   /// Attempts to read the next line from the stream and parse it as
-  /// an RPC object.
+  /// an RPC object. Blank lines are skipped, and a byte order mark at the start of the stream
+  /// and `\r\n` line endings are tolerated.
   ///
   /// # Errors
   ///
@@ -18,21 +35,56 @@ impl MessageReader {
   /// I/O error, if the stream is closed, or if the message is not
   /// a valid JSON object.
   pub fn next<R: BufRead>(&mut self, reader: &mut R) -> Result<Option<RpcObject>, ReadError> {
-    self.0.clear();
-    match reader.read_line(&mut self.0) {
-      Ok(_) => {
-        if self.0.is_empty() {
-          Err(ReadError::Disconnect(
+    match self.next_frame(reader, false)? {
+      Some(Frame::Json(object)) => Ok(Some(object)),
+      Some(Frame::Binary(_)) | None => Ok(None),
+    }
+  }
+
+  /// Same as [MessageReader::next], also reading the
+  /// [binary frames](crate::core::binary::BinaryFrame) starting with [BINARY_FRAME_SENTINEL]
+  /// when `binary` is set. Otherwise such lines are read as text, like any line that is not
+  /// JSON.
+  pub fn next_frame<R: BufRead>(
+    &mut self,
+    reader: &mut R,
+    binary: bool,
+  ) -> Result<Option<Frame>, ReadError> {
+    loop {
+      if binary {
+        match reader.fill_buf() {
+          Ok(buf) if buf.first() == Some(&BINARY_FRAME_SENTINEL) => {
+            self.started = true;
+            return read_binary_frame(reader).map(|frame| Some(Frame::Binary(frame)));
+          },
+          // The end of the stream and errors are reported by `read_line`.
+          Ok(_) | Err(_) => {},
+        }
+      }
+      self.line.clear();
+      match reader.read_line(&mut self.line) {
+        // Only the end of the stream reads nothing, a blank line reads at least `\n`.
+        Ok(0) => {
+          return Err(ReadError::Disconnect(
             "stdout return empty line".to_string(),
           ))
-        } else {
-          self.parse(&self.0).map(Some)
-        }
-      },
-      Err(err) => {
-        tracing::trace!("[RPC] read line error: {:?}", err);
-        Ok(None)
-      },
+        },
+        Ok(_) => {
+          let mut line = self.line.as_str();
+          if !self.started {
+            self.started = true;
+            line = line.strip_prefix(BOM).unwrap_or(line);
+          }
+          if line.trim().is_empty() {
+            continue;
+          }
+          return self.parse(line).map(|object| Some(Frame::Json(object)));
+        },
+        Err(err) => {
+          tracing::trace!("[RPC] read line error: {:?}", err);
+          return Ok(None);
+        },
+      }
     }
   }
 
@@ -41,6 +93,7 @@ impl MessageReader {
   /// This should not be called directly unless you are writing tests.
   #[doc(hidden)]
   pub fn parse(&self, s: &str) -> Result<RpcObject, ReadError> {
+    let s = s.trim_end_matches(['\r', '\n']);
     match serde_json::from_str::<JsonValue>(s) {
       Ok(val) => {
         if val.is_object() {
@@ -70,6 +123,19 @@ pub enum Call<R> {
 pub trait ResponseParser {
   type ValueType: Send + Sync + 'static;
   fn parse_json(payload: JsonValue) -> Result<Self::ValueType, RemoteError>;
+
+  /// Merges `next` into `prev` and returns `None`, or hands `next` back when the two frames
+  /// can't be merged. Used by
+  /// [BackpressurePolicy::CoalesceText](crate::core::stream::BackpressurePolicy::CoalesceText).
+  fn coalesce(_prev: &mut Self::ValueType, next: Self::ValueType) -> Option<Self::ValueType> {
+    Some(next)
+  }
+
+  /// The last frame of a stream that had to drop or coalesce frames, if this kind of stream can
+  /// carry one.
+  fn backpressure_frame(_report: &BackpressureReport) -> Option<Self::ValueType> {
+    None
+  }
 }
 
 pub struct EmptyResponseParser;
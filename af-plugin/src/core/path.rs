@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use crate::error::PluginError;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 #[cfg(windows)]
@@ -21,14 +22,108 @@ pub fn install_path() -> Option<PathBuf> {
   return Some(PathBuf::from("/usr/local/bin"));
 }
 
+/// Whether a plugin executable can be started, see [check_executable].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginReadiness {
+  /// The executable to start, found at the configured path or via `PATH`.
+  Ready(PathBuf),
+  NotFound(PathBuf),
+  /// The path is a directory, or lacks the execute permission.
+  NotExecutable(PathBuf),
+  /// macOS quarantined the downloaded executable, so Gatekeeper won't run it.
+  Quarantined(PathBuf),
+}
+
+impl PluginReadiness {
+  pub fn is_ready(&self) -> bool {
+    matches!(self, PluginReadiness::Ready(_))
+  }
+
+  pub fn into_result(self) -> Result<PathBuf, PluginError> {
+    match self {
+      PluginReadiness::Ready(path) => Ok(path),
+      PluginReadiness::NotFound(path) => Err(PluginError::ExecutableNotFound(path)),
+      PluginReadiness::NotExecutable(path) => Err(PluginError::ExecutableNotExecutable(path)),
+      PluginReadiness::Quarantined(path) => Err(PluginError::QuarantinedExecutable(path)),
+    }
+  }
+}
+
+/// Checks the executable a plugin is started with: `exec_path` when it exists, otherwise
+/// `exec_command` resolved via `PATH`, like the plugin's process is spawned.
+pub fn check_executable(exec_path: &Path, exec_command: &str) -> PluginReadiness {
+  if exec_path.exists() {
+    return check_file(exec_path);
+  }
+  match find_in_path(exec_command) {
+    Some(path) => check_file(&path),
+    None => PluginReadiness::NotFound(exec_path.to_path_buf()),
+  }
+}
+
+/// Why the default plugin installation can't be started, if it can't.
 #[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
-pub fn is_plugin_ready() -> bool {
-  ollama_plugin_path().exists() || ollama_plugin_command_available()
+pub fn plugin_readiness() -> PluginReadiness {
+  match check_executable(&ollama_plugin_path(), "af_ollama_plugin") {
+    PluginReadiness::NotFound(_) if ollama_plugin_command_available() => {
+      PluginReadiness::Ready(PathBuf::from("af_ollama_plugin"))
+    },
+    readiness => readiness,
+  }
 }
 
 #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+pub fn plugin_readiness() -> PluginReadiness {
+  PluginReadiness::NotFound(ollama_plugin_path())
+}
+
 pub fn is_plugin_ready() -> bool {
-  false
+  plugin_readiness().is_ready()
+}
+
+fn check_file(path: &Path) -> PluginReadiness {
+  let metadata = match std::fs::metadata(path) {
+    Ok(metadata) => metadata,
+    Err(_) => return PluginReadiness::NotFound(path.to_path_buf()),
+  };
+  if !metadata.is_file() {
+    return PluginReadiness::NotExecutable(path.to_path_buf());
+  }
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    if metadata.permissions().mode() & 0o111 == 0 {
+      return PluginReadiness::NotExecutable(path.to_path_buf());
+    }
+  }
+  #[cfg(target_os = "macos")]
+  {
+    if let Ok(Some(_)) = xattr::get(path, "com.apple.quarantine") {
+      return PluginReadiness::Quarantined(path.to_path_buf());
+    }
+  }
+  PluginReadiness::Ready(path.to_path_buf())
+}
+
+/// The first file named `command` in the directories of `PATH`, or `command` itself when it
+/// is a path.
+fn find_in_path(command: &str) -> Option<PathBuf> {
+  if command.is_empty() {
+    return None;
+  }
+  let command = Path::new(command);
+  if command.components().count() > 1 {
+    return command.is_file().then(|| command.to_path_buf());
+  }
+  let names = if cfg!(windows) && command.extension().is_none() {
+    vec![command.to_path_buf(), command.with_extension("exe")]
+  } else {
+    vec![command.to_path_buf()]
+  };
+  let paths = std::env::var_os("PATH")?;
+  std::env::split_paths(&paths)
+    .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+    .find(|path| path.is_file())
 }
 
 #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
@@ -1,22 +1,36 @@
 use crate::error::PluginError;
 use crate::manager::WeakPluginState;
 use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::fs;
 use std::process::Command;
 
+use crate::core::binary::{BinaryHandler, BINARY_FRAMES_KEY};
+use crate::core::journal::CrashJournal;
 use crate::core::parser::ResponseParser;
+use crate::core::process_limits::{apply_process_limits, ProcessLimits};
+use crate::core::resource_usage::{sample_process, CpuTracker, ResourceUsage};
 use crate::core::rpc_loop::RpcLoop;
 use crate::core::rpc_peer::{CloneableCallback, OneShotCallback};
+use crate::core::state_machine::StateMachine;
+use crate::core::stream::{bounded_stream, StreamOptions};
+use crate::core::stream_error::stream_error;
+use crate::core::transport::{
+  PipeListener, RpcReader, RpcWriter, TransportKind, PIPE_CONNECT_TIMEOUT, RPC_PIPE_ARG,
+  RPC_PIPE_ENV,
+};
+use crate::core::writer::DEFAULT_WRITE_QUEUE_FRAMES;
+use crate::util::RedactedEnv;
 use anyhow::anyhow;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 use std::io::BufReader;
 use std::path::PathBuf;
-use std::process::{Child, Stdio};
+use std::process::{Child, ExitStatus, Stdio};
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::{watch, RwLock};
 use tokio_stream::wrappers::{ReceiverStream, WatchStream};
 
@@ -47,17 +61,42 @@ pub trait Peer: Send + Sync + 'static {
 
   fn stream_rpc_request(&self, method: &str, params: &JsonValue, f: CloneableCallback);
 
-  fn async_send_rpc_request(&self, method: &str, params: &JsonValue, f: Box<dyn OneShotCallback>);
+  /// Sends an RPC request whose response is passed to `f`, returning the id of the request.
+  fn async_send_rpc_request(
+    &self,
+    method: &str,
+    params: &JsonValue,
+    f: Box<dyn OneShotCallback>,
+  ) -> usize;
+
+  /// Forgets the request `id`, so its response is dropped if it ever arrives. The handler of
+  /// the request is not called.
+  fn cancel_request(&self, id: usize);
   /// Sends a synchronous RPC request to the peer and waits for the result.
   /// Returns the result of the request or an error.
   fn send_rpc_request(&self, method: &str, params: &JsonValue) -> Result<JsonValue, PluginError>;
 
+  /// Fails every outstanding request, including open streams, with [PluginError::PeerDisconnect].
+  fn cancel_pending_requests(&self);
+
   /// Checks if there is an incoming request pending, intended to reduce latency for bulk operations done in the background.
   fn request_is_pending(&self) -> bool;
 
   /// Schedules a timer to execute the handler's `idle` function after the specified `Instant`.
   /// Note: This is not a high-fidelity timer. Regular RPC messages will always take priority over idle tasks.
   fn schedule_timer(&self, after: Instant, token: usize);
+
+  /// Registers the handler of the binary frames sent by the peer, which are read from then on.
+  fn set_binary_handler(&self, handler: Arc<dyn BinaryHandler>);
+
+  /// Whether a binary handler is registered, so binary frames are offered at initialization.
+  fn binary_frames_offered(&self) -> bool;
+
+  /// Allows [Peer::send_binary], once the peer accepted binary frames.
+  fn accept_binary_frames(&self);
+
+  /// Sends a binary frame, see [BinaryFrame](crate::core::binary::BinaryFrame).
+  fn send_binary(&self, method_id: u16, payload: &[u8]) -> Result<(), PluginError>;
 }
 
 /// The `Peer` trait object.
@@ -67,7 +106,7 @@ pub struct RpcCtx {
   pub peer: RpcPeer,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RunningState {
   ReadyToConnect,
   /// The plugin is in the process of establishing a connection
@@ -87,9 +126,19 @@ pub enum RunningState {
   /// The plugin stopped unexpectedly
   UnexpectedStop {
     plugin_id: PluginId,
+    /// Likely cause of the stop, when one is known.
+    reason: Option<StopReason>,
   },
 }
 
+/// Why a plugin stopped unexpectedly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+  /// The process failed while its memory was limited to `max_memory_bytes`, most likely because
+  /// it went past the limit, see [ProcessLimits::max_memory_bytes].
+  MemoryLimit { max_memory_bytes: u64 },
+}
+
 impl RunningState {
   pub fn plugin_id(&self) -> Option<PluginId> {
     match self {
@@ -97,7 +146,7 @@ impl RunningState {
       RunningState::Connected { plugin_id } => Some(*plugin_id),
       RunningState::Running { plugin_id } => Some(*plugin_id),
       RunningState::Stopped { plugin_id } => Some(*plugin_id),
-      RunningState::UnexpectedStop { plugin_id } => Some(*plugin_id),
+      RunningState::UnexpectedStop { plugin_id, .. } => Some(*plugin_id),
       RunningState::ReadyToConnect => None,
     }
   }
@@ -114,7 +163,22 @@ impl RunningState {
   }
 }
 
-pub type RunningStateSender = Arc<watch::Sender<RunningState>>;
+/// Cancels the request `id` when dropped, see [Peer::cancel_request].
+struct PendingRequest<'a> {
+  peer: &'a RpcPeer,
+  id: Option<usize>,
+}
+
+impl Drop for PendingRequest<'_> {
+  fn drop(&mut self) {
+    if let Some(id) = self.id {
+      self.peer.cancel_request(id);
+    }
+  }
+}
+
+/// Shared by the plugin manager, the RPC peer and the plugin owner, see [StateMachine].
+pub type RunningStateSender = Arc<StateMachine>;
 pub type RunningStateReceiver = watch::Receiver<RunningState>;
 
 #[derive(Clone)]
@@ -122,9 +186,11 @@ pub struct Plugin {
   peer: RpcPeer,
   pub(crate) id: PluginId,
   pub(crate) name: String,
-  #[allow(dead_code)]
-  pub(crate) process: Arc<Child>,
+  pub(crate) process: Arc<Mutex<Child>>,
+  pid: u32,
+  cpu_tracker: Arc<Mutex<CpuTracker>>,
   pub(crate) running_state: RunningStateSender,
+  process_limits: ProcessLimits,
 }
 impl Drop for Plugin {
   fn drop(&mut self) {
@@ -140,17 +206,41 @@ impl Display for Plugin {
       "{}, plugin id: {:?}, process id: {}",
       self.name,
       self.id,
-      self.process.id()
+      self.process_id()
     )
   }
 }
 
 impl Plugin {
-  pub fn initialize(&self, value: JsonValue) -> Result<(), PluginError> {
-    self.peer.send_rpc_request("initialize", &value)?;
+  /// Initializes the plugin with `value`, offering binary frames if a handler was registered
+  /// with [Plugin::set_binary_handler].
+  pub fn initialize(&self, mut value: JsonValue) -> Result<(), PluginError> {
+    let offer_binary = self.peer.binary_frames_offered();
+    if offer_binary {
+      if let Some(params) = value.as_object_mut() {
+        params.insert(BINARY_FRAMES_KEY.to_string(), JsonValue::Bool(true));
+      }
+    }
+    let result = self.peer.send_rpc_request("initialize", &value)?;
+    if offer_binary && result.get(BINARY_FRAMES_KEY) == Some(&JsonValue::Bool(true)) {
+      info!("[AI plugin]: {} accepted binary frames", self.name);
+      self.peer.accept_binary_frames();
+    }
     Ok(())
   }
 
+  /// Registers the handler of the binary frames the plugin sends. Must be called before
+  /// [Plugin::initialize], which offers them to the plugin.
+  pub fn set_binary_handler<H: BinaryHandler + 'static>(&self, handler: H) {
+    self.peer.set_binary_handler(Arc::new(handler));
+  }
+
+  /// Sends raw bytes to the plugin for the method `method_id`. Fails with
+  /// [PluginError::BinaryFramesNotNegotiated] unless the plugin accepted binary frames.
+  pub fn send_binary(&self, method_id: u16, payload: &[u8]) -> Result<(), PluginError> {
+    self.peer.send_binary(method_id, payload)
+  }
+
   pub fn request(&self, method: &str, params: &JsonValue) -> Result<JsonValue, PluginError> {
     self.peer.send_rpc_request(method, params)
   }
@@ -161,36 +251,61 @@ impl Plugin {
     params: &JsonValue,
   ) -> Result<P::ValueType, PluginError> {
     let (tx, rx) = tokio::sync::oneshot::channel();
-    self.peer.async_send_rpc_request(
+    let id = self.peer.async_send_rpc_request(
       method,
       params,
       Box::new(move |result| {
         let _ = tx.send(result);
       }),
     );
-    let value = rx.await.map_err(|err| {
+    // A request dropped before its response, e.g. by a timeout, leaves no handler behind.
+    let mut pending = PendingRequest {
+      peer: &self.peer,
+      id: Some(id),
+    };
+    let value = rx.await;
+    pending.id = None;
+    let value = value.map_err(|err| {
       PluginError::Internal(anyhow!("error waiting for async response: {:?}", err))
     })??;
     let value = P::parse_json(value)?;
     Ok(value)
   }
 
-  pub fn stream_request<P: ResponseParser>(
+  /// Same as [Plugin::async_request], but gives up with [PluginError::RequestTimeout] when no
+  /// response arrives within `duration`. The request is then cancelled, so a late response is
+  /// dropped.
+  pub async fn timed_request<P: ResponseParser>(
+    &self,
+    method: &str,
+    params: &JsonValue,
+    duration: Duration,
+  ) -> Result<P::ValueType, PluginError> {
+    tokio::time::timeout(duration, self.async_request::<P>(method, params))
+      .await
+      .map_err(|_| PluginError::RequestTimeout(duration))?
+  }
+
+  /// Sends a streaming request. At most `options.capacity` frames are buffered for the consumer;
+  /// `options.policy` decides what happens to further frames until the consumer catches up.
+  /// Fails outside of a tokio runtime, see [bounded_stream].
+  pub fn stream_request<P: ResponseParser + 'static>(
     &self,
     method: &str,
     params: &JsonValue,
+    options: StreamOptions,
   ) -> Result<ReceiverStream<Result<P::ValueType, PluginError>>, PluginError> {
-    trace!("[AI plugin]: stream request: {:?}, {:?}", method, params);
-    let (tx, stream) = tokio::sync::mpsc::channel(100);
-    let stream = ReceiverStream::new(stream);
-    let callback = CloneableCallback::new(move |result| match result {
-      Ok(json) => {
-        let result = P::parse_json(json).map_err(PluginError::from);
-        let _ = tx.blocking_send(result);
-      },
-      Err(err) => {
-        let _ = tx.blocking_send(Err(err));
-      },
+    trace!(
+      "[AI plugin]: stream request: {:?}, {:?}, {:?}",
+      method,
+      params,
+      options
+    );
+    let (tx, stream) = bounded_stream::<P>(options)?;
+    let running_state = self.running_state.clone();
+    let callback = CloneableCallback::new(move |result: Result<JsonValue, PluginError>| {
+      let result = result.and_then(|json| P::parse_json(json).map_err(PluginError::from));
+      tx.send(result.map_err(|err| stream_error(err, &running_state.borrow())));
     });
     self.peer.stream_rpc_request(method, params, callback);
     Ok(stream)
@@ -205,13 +320,116 @@ impl Plugin {
   pub fn subscribe_running_state(&self) -> WatchStream<RunningState> {
     WatchStream::new(self.running_state.subscribe())
   }
+
+  pub fn process_id(&self) -> u32 {
+    self.pid
+  }
+
+  /// The [PluginConfig::process_limits] that could be applied to the plugin process.
+  pub fn process_limits(&self) -> &ProcessLimits {
+    &self.process_limits
+  }
+
+  /// Samples the memory and CPU used by the plugin process. CPU usage is measured over the time
+  /// elapsed since the previous call.
+  pub fn resource_usage(&self) -> Result<ResourceUsage, PluginError> {
+    let sample = sample_process(&self.process.lock())?;
+    let cpu_percent = self
+      .cpu_tracker
+      .lock()
+      .update(sample.cpu_time, Instant::now());
+    Ok(ResourceUsage {
+      rss_bytes: sample.rss_bytes,
+      cpu_percent,
+    })
+  }
+
+  /// Sends the shutdown request without waiting for the plugin to answer.
+  pub(crate) fn request_shutdown(&self) {
+    self
+      .peer
+      .async_send_rpc_request("shutdown", &json!({}), Box::new(|_| {}));
+  }
+
+  /// Stream callbacks block on their channel, so this must not run on an async worker thread.
+  pub(crate) fn cancel_pending_requests(&self) {
+    self.peer.cancel_pending_requests();
+  }
+
+  /// Returns `true` once the plugin process has exited. The exit status is reaped, so the
+  /// process does not linger as a zombie.
+  pub fn has_exited(&self) -> bool {
+    matches!(self.process.lock().try_wait(), Ok(Some(_)) | Err(_))
+  }
+
+  /// The exit status of the plugin process, `None` while it runs. Reaps the process like
+  /// [Plugin::has_exited].
+  pub fn exit_status(&self) -> Option<ExitStatus> {
+    self.process.lock().try_wait().ok().flatten()
+  }
+
+  pub(crate) fn kill(&self) -> std::io::Result<()> {
+    let mut process = self.process.lock();
+    process.kill()?;
+    process.wait()?;
+    Ok(())
+  }
 }
 
-#[derive(Debug)]
 pub struct PluginConfig {
   pub name: String,
   pub exec_path: PathBuf,
   pub exec_command: String,
+  /// When set, requests sent to the plugin are journaled in this directory.
+  pub crash_journal_dir: Option<PathBuf>,
+  /// Variables set in the environment of the plugin process, such as `OLLAMA_HOST`.
+  pub env: HashMap<String, String>,
+  /// Whether the plugin process starts from the environment of the host. Otherwise it only
+  /// has `env` and the variables setting its encoding.
+  pub inherit_env: bool,
+  /// Directory the plugin process runs in, the one of the host if not set.
+  pub working_dir: Option<PathBuf>,
+  /// Channel of the RPC messages, the stdio of the plugin process by default.
+  pub transport: TransportKind,
+  /// Frames waiting to be written to the plugin, beyond which sends fail instead of blocking
+  /// on a plugin that doesn't read its input.
+  pub write_queue_frames: usize,
+  /// Limits applied to the plugin process when it is spawned.
+  pub process_limits: ProcessLimits,
+}
+
+impl Default for PluginConfig {
+  fn default() -> Self {
+    Self {
+      name: String::new(),
+      exec_path: PathBuf::new(),
+      exec_command: String::new(),
+      crash_journal_dir: None,
+      env: HashMap::new(),
+      inherit_env: true,
+      working_dir: None,
+      transport: TransportKind::default(),
+      write_queue_frames: DEFAULT_WRITE_QUEUE_FRAMES,
+      process_limits: ProcessLimits::default(),
+    }
+  }
+}
+
+impl Debug for PluginConfig {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PluginConfig")
+      .field("name", &self.name)
+      .field("exec_path", &self.exec_path)
+      .field("exec_command", &self.exec_command)
+      .field("crash_journal_dir", &self.crash_journal_dir)
+      .field("env", &RedactedEnv(&self.env))
+      .field("inherit_env", &self.inherit_env)
+      .field("working_dir", &self.working_dir)
+      .field("transport", &self.transport)
+      .field("write_queue_frames", &self.write_queue_frames)
+      .field("process_limits", &self.process_limits)
+      .finish()
+  }
 }
 
 pub(crate) async fn start_plugin_process(
@@ -220,8 +438,13 @@ pub(crate) async fn start_plugin_process(
   state: WeakPluginState,
   running_state: RunningStateSender,
   running_plugins: Arc<RwLock<HashMap<String, PluginId>>>,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), PluginError> {
   trace!("start plugin process: {:?}, {:?}", id, plugin_config);
+  if let Some(working_dir) = plugin_config.working_dir.as_ref() {
+    if !working_dir.is_dir() {
+      return Err(PluginError::WorkingDirNotFound(working_dir.clone()));
+    }
+  }
   let (tx, ret) = tokio::sync::oneshot::channel();
 
   let (plugin_exit_tx, plugin_exit_rx) = tokio::sync::oneshot::channel();
@@ -229,7 +452,11 @@ pub(crate) async fn start_plugin_process(
   tokio::spawn(async move {
     if plugin_exit_rx.await.is_ok() {
       info!("Remove plugin from running list: {:?}", plugin_name);
-      let _ = running_plugins.write().await.remove(plugin_name.as_str());
+      // A plugin started under the same name since keeps its entry.
+      let mut running_plugins = running_plugins.write().await;
+      if running_plugins.get(plugin_name.as_str()) == Some(&id) {
+        running_plugins.remove(plugin_name.as_str());
+      }
     }
   });
 
@@ -277,6 +504,12 @@ pub(crate) async fn start_plugin_process(
         command.creation_flags(CREATE_NO_WINDOW);
       }
 
+      if !plugin_config.inherit_env {
+        command.env_clear();
+      }
+      if let Some(working_dir) = plugin_config.working_dir.as_ref() {
+        command.current_dir(working_dir);
+      }
       command.env("PYTHONIOENCODING", "utf-8");
       if cfg!(windows) {
         command.env("PYTHONUTF8", "1");
@@ -285,34 +518,112 @@ pub(crate) async fn start_plugin_process(
         command.env("LANG", "en_US.UTF-8");
         command.env("LC_ALL", "en_US.UTF-8");
       }
+      command.envs(&plugin_config.env);
+
+      let pipe = match plugin_config.transport {
+        TransportKind::Stdio => None,
+        TransportKind::NamedPipe => match PipeListener::bind(id) {
+          Ok(pipe) => Some(pipe),
+          Err(err) => {
+            error!("failed to create plugin pipe: {:?}", err);
+            let _ = tx.send(Err(std::io::Error::new(err.kind(), err.to_string())));
+            state.plugin_connect(Err(err));
+            return;
+          },
+        },
+      };
+      match pipe.as_ref() {
+        Some(pipe) => {
+          info!("[AI Plugin]: connect to plugin through {:?}", pipe.path());
+          command.arg(RPC_PIPE_ARG).arg(pipe.path());
+          command.env(RPC_PIPE_ENV, pipe.path());
+          // Whatever ends up in stdout is not part of the protocol.
+          command.stdin(Stdio::null()).stdout(Stdio::null());
+        },
+        None => {
+          command.stdin(Stdio::piped()).stdout(Stdio::piped());
+        },
+      }
 
-      let child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn();
+      let child = command.spawn();
       match child {
         Ok(mut child) => {
-          let child_stdin = child.stdin.take().unwrap();
-          let child_stdout = child.stdout.take().unwrap();
-          let mut looper = RpcLoop::new(child_stdin, running_state.clone());
-          let _ = running_state.send(RunningState::Connecting);
-
-          let peer: RpcPeer = Arc::new(looper.get_raw_peer());
+          let process_limits = apply_process_limits(&child, &plugin_config.process_limits);
+          let channel = match pipe {
+            Some(pipe) => pipe.accept(&mut child, PIPE_CONNECT_TIMEOUT),
+            None => Ok((
+              Box::new(child.stdout.take().unwrap()) as RpcReader,
+              Box::new(child.stdin.take().unwrap()) as RpcWriter,
+            )),
+          };
+          let (child_stdout, child_stdin) = match channel {
+            Ok(channel) => channel,
+            Err(err) => {
+              error!("failed to connect to plugin process: {:?}", err);
+              let _ = child.kill();
+              let _ = child.wait();
+              let _ = tx.send(Err(std::io::Error::new(err.kind(), err.to_string())));
+              state.plugin_connect(Err(err));
+              return;
+            },
+          };
+          let journal = plugin_config.crash_journal_dir.as_ref().and_then(|dir| {
+            match CrashJournal::open(dir) {
+              Ok(journal) => Some(journal),
+              Err(err) => {
+                error!("[RPC] failed to open crash journal at {:?}: {:?}", dir, err);
+                None
+              },
+            }
+          });
+          let looper = RpcLoop::with_write_queue(
+            child_stdin,
+            running_state.clone(),
+            journal,
+            plugin_config.write_queue_frames,
+          );
+          let mut looper = match looper {
+            Ok(looper) => looper,
+            Err(err) => {
+              error!("failed to start plugin writer: {:?}", err);
+              let _ = child.kill();
+              let _ = child.wait();
+              let _ = tx.send(Err(std::io::Error::new(err.kind(), err.to_string())));
+              state.plugin_connect(Err(err));
+              return;
+            },
+          };
+          running_state.connecting(id);
+
+          let raw_peer = looper.get_raw_peer();
+          let pid = child.id();
+          let process = Arc::new(Mutex::new(child));
+          if let Some(max_memory_bytes) = process_limits.max_memory_bytes {
+            let process = Arc::downgrade(&process);
+            raw_peer.set_stop_reason(Box::new(move || {
+              memory_limit_stop(&*process.upgrade()?, max_memory_bytes)
+            }));
+          }
+          let peer: RpcPeer = Arc::new(raw_peer);
           let name = plugin_config.name.clone();
           peer.send_rpc_notification("ping", &JsonValue::Array(Vec::new()));
 
           let plugin = Plugin {
             peer,
-            process: Arc::new(child),
+            pid,
+            process,
+            cpu_tracker: Default::default(),
             name,
             id,
             running_state: running_state.clone(),
+            process_limits,
           };
 
           let plugin_id = plugin.id;
           state.plugin_connect(Ok(plugin));
-          if let Err(err) = running_state.send(RunningState::Connected { plugin_id }) {
-            error!("failed to send connected state: {:?}", err);
-          }
+          running_state.transition(RunningState::Connected { plugin_id });
           // Notify the main thread that the plugin has started
-          let _ = tx.send(());
+          let _ = tx.send(Ok(()));
 
           let mut state = state;
           let err = looper.mainloop(
@@ -321,13 +632,13 @@ pub(crate) async fn start_plugin_process(
             || BufReader::with_capacity(4096, child_stdout),
             &mut state,
           );
-          let _ = running_state.send(RunningState::Stopped { plugin_id });
+          running_state.transition(RunningState::Stopped { plugin_id });
           let _ = plugin_exit_tx.send(());
           state.plugin_exit(id, err);
         },
         Err(err) => {
-          let _ = tx.send(());
           error!("failed to start plugin process: {:?}", err);
+          let _ = tx.send(Err(std::io::Error::new(err.kind(), err.to_string())));
           state.plugin_connect(Err(err));
         },
       }
@@ -337,10 +648,29 @@ pub(crate) async fn start_plugin_process(
     error!("[RPC] thread spawn failed for {:?}, {:?}", id, err);
     return Err(err.into());
   }
-  ret.await?;
+  ret
+    .await
+    .map_err(|err| PluginError::Internal(anyhow!("plugin host thread exited: {}", err)))??;
   Ok(())
 }
 
+/// Time a process whose output closed may take to exit before its exit status is given up on.
+const EXIT_STATUS_WAIT: Duration = Duration::from_millis(500);
+
+/// [StopReason::MemoryLimit] when `process` exited with a failure while its memory was limited.
+fn memory_limit_stop(process: &Mutex<Child>, max_memory_bytes: u64) -> Option<StopReason> {
+  let deadline = Instant::now() + EXIT_STATUS_WAIT;
+  loop {
+    match process.lock().try_wait() {
+      Ok(Some(status)) if status.success() => return None,
+      Ok(Some(_)) => return Some(StopReason::MemoryLimit { max_memory_bytes }),
+      Ok(None) if Instant::now() < deadline => {},
+      _ => return None,
+    }
+    thread::sleep(Duration::from_millis(20));
+  }
+}
+
 #[allow(dead_code)]
 #[cfg(unix)]
 async fn ensure_executable(exec_path: &std::path::Path) -> Result<(), anyhow::Error> {
@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::process::Child;
+use tracing::{info, warn};
+
+/// Limits applied to the plugin process when it is spawned, e.g. so heavy indexing doesn't make
+/// the whole machine sluggish. A limit the platform can't apply is logged and skipped, the
+/// process runs without it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessLimits {
+  /// Nice value of the process, from -20 to 19, higher running at a lower priority. Negative
+  /// values usually need elevated privileges. On Windows, positive values run the process at
+  /// below normal priority.
+  pub nice: Option<i32>,
+  /// Indexes of the CPUs the process may run on. Not supported on macOS.
+  pub cpu_affinity: Option<Vec<usize>>,
+  /// Memory the process may allocate. Allocations past it fail, which most plugins don't
+  /// survive, see [StopReason::MemoryLimit](crate::core::plugin::StopReason::MemoryLimit). Not
+  /// supported on macOS.
+  pub max_memory_bytes: Option<u64>,
+}
+
+impl ProcessLimits {
+  pub fn with_nice(mut self, nice: i32) -> Self {
+    self.nice = Some(nice);
+    self
+  }
+
+  pub fn with_cpu_affinity(mut self, cpus: Vec<usize>) -> Self {
+    self.cpu_affinity = Some(cpus);
+    self
+  }
+
+  pub fn with_max_memory_bytes(mut self, max_memory_bytes: u64) -> Self {
+    self.max_memory_bytes = Some(max_memory_bytes);
+    self
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.nice.is_none() && self.cpu_affinity.is_none() && self.max_memory_bytes.is_none()
+  }
+}
+
+/// Applies `limits` to `process`, returning the ones that could be applied.
+pub(crate) fn apply_process_limits(process: &Child, limits: &ProcessLimits) -> ProcessLimits {
+  let mut applied = ProcessLimits::default();
+  if let Some(nice) = limits.nice {
+    match set_nice(process, nice) {
+      Ok(()) => applied.nice = Some(nice),
+      Err(err) => warn!("[AI Plugin] can't set the nice value {}: {}", nice, err),
+    }
+  }
+  if let Some(cpus) = limits.cpu_affinity.as_ref() {
+    match set_cpu_affinity(process, cpus) {
+      Ok(()) => applied.cpu_affinity = Some(cpus.clone()),
+      Err(err) => warn!("[AI Plugin] can't set the CPU affinity {:?}: {}", cpus, err),
+    }
+  }
+  if let Some(max_memory_bytes) = limits.max_memory_bytes {
+    match set_max_memory(process, max_memory_bytes) {
+      Ok(()) => applied.max_memory_bytes = Some(max_memory_bytes),
+      Err(err) => warn!(
+        "[AI Plugin] can't limit the memory to {} bytes: {}",
+        max_memory_bytes, err
+      ),
+    }
+  }
+  if !applied.is_empty() {
+    info!(
+      "[AI Plugin] process {} runs with limits: {:?}",
+      process.id(),
+      applied
+    );
+  }
+  applied
+}
+
+#[cfg(unix)]
+fn set_nice(process: &Child, nice: i32) -> std::io::Result<()> {
+  let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, process.id() as libc::id_t, nice) };
+  if result != 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(process: &Child, cpus: &[usize]) -> std::io::Result<()> {
+  let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+  for &cpu in cpus {
+    if cpu >= libc::CPU_SETSIZE as usize {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("CPU {} is out of range", cpu),
+      ));
+    }
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+  }
+  let result = unsafe {
+    libc::sched_setaffinity(
+      process.id() as libc::pid_t,
+      std::mem::size_of::<libc::cpu_set_t>(),
+      &set,
+    )
+  };
+  if result != 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_max_memory(process: &Child, max_memory_bytes: u64) -> std::io::Result<()> {
+  let limit = libc::rlimit {
+    rlim_cur: max_memory_bytes as libc::rlim_t,
+    rlim_max: max_memory_bytes as libc::rlim_t,
+  };
+  let result = unsafe {
+    libc::prlimit(
+      process.id() as libc::pid_t,
+      libc::RLIMIT_AS,
+      &limit,
+      std::ptr::null_mut(),
+    )
+  };
+  if result != 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(windows)]
+fn set_nice(process: &Child, nice: i32) -> std::io::Result<()> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::System::Threading::{
+    SetPriorityClass, BELOW_NORMAL_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+  };
+
+  let class = match nice {
+    0 => NORMAL_PRIORITY_CLASS,
+    1.. => BELOW_NORMAL_PRIORITY_CLASS,
+    _ => return Err(unsupported("raising the priority")),
+  };
+  if unsafe { SetPriorityClass(process.as_raw_handle(), class) } == 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(windows)]
+fn set_cpu_affinity(process: &Child, cpus: &[usize]) -> std::io::Result<()> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::System::Threading::SetProcessAffinityMask;
+
+  let mut mask = 0usize;
+  for &cpu in cpus {
+    if cpu >= usize::BITS as usize {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("CPU {} is out of range", cpu),
+      ));
+    }
+    mask |= 1 << cpu;
+  }
+  if unsafe { SetProcessAffinityMask(process.as_raw_handle(), mask) } == 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+/// Puts the process in a job object limiting the memory it commits. The job outlives its handle
+/// for as long as the process runs.
+#[cfg(windows)]
+fn set_max_memory(process: &Child, max_memory_bytes: u64) -> std::io::Result<()> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::Foundation::CloseHandle;
+  use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+    SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+  };
+
+  let job = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+  if job.is_null() {
+    return Err(std::io::Error::last_os_error());
+  }
+  let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+  info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+  info.ProcessMemoryLimit = max_memory_bytes as usize;
+  let result = unsafe {
+    if SetInformationJobObject(
+      job,
+      JobObjectExtendedLimitInformation,
+      &info as *const _ as *const std::ffi::c_void,
+      std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+    ) == 0
+      || AssignProcessToJobObject(job, process.as_raw_handle()) == 0
+    {
+      Err(std::io::Error::last_os_error())
+    } else {
+      Ok(())
+    }
+  };
+  unsafe { CloseHandle(job) };
+  result
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_nice(_process: &Child, _nice: i32) -> std::io::Result<()> {
+  Err(unsupported("the nice value"))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_cpu_affinity(_process: &Child, _cpus: &[usize]) -> std::io::Result<()> {
+  Err(unsupported("the CPU affinity"))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_max_memory(_process: &Child, _max_memory_bytes: u64) -> std::io::Result<()> {
+  Err(unsupported("a memory limit"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported(what: &str) -> std::io::Error {
+  std::io::Error::new(
+    std::io::ErrorKind::Unsupported,
+    format!("{} is not supported on this platform", what),
+  )
+}
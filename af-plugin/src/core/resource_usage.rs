@@ -0,0 +1,161 @@
+use crate::error::PluginError;
+use serde::{Deserialize, Serialize};
+use std::process::Child;
+use std::time::{Duration, Instant};
+
+/// Memory and CPU used by a plugin process.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+  /// Physical memory used by the process.
+  pub rss_bytes: u64,
+  /// CPU used since the previous sample. 100 means one core fully busy, so the value can exceed
+  /// 100 on multi-core machines. The first sample of a process is always 0.
+  pub cpu_percent: f32,
+}
+
+/// Counters read from the operating system for a process.
+pub(crate) struct ProcessSample {
+  pub rss_bytes: u64,
+  /// Total CPU time, user and system, consumed by the process since it started.
+  pub cpu_time: Duration,
+}
+
+/// Turns the cumulative CPU time of successive samples into a usage percentage.
+#[derive(Debug, Default)]
+pub(crate) struct CpuTracker {
+  last: Option<(Duration, Instant)>,
+}
+
+impl CpuTracker {
+  pub fn update(&mut self, cpu_time: Duration, now: Instant) -> f32 {
+    let percent = match self.last {
+      Some((last_cpu_time, last_instant)) => {
+        let elapsed = now.saturating_duration_since(last_instant).as_secs_f64();
+        let used = cpu_time.saturating_sub(last_cpu_time).as_secs_f64();
+        if elapsed > 0.0 {
+          (used / elapsed * 100.0) as f32
+        } else {
+          0.0
+        }
+      },
+      None => 0.0,
+    };
+    self.last = Some((cpu_time, now));
+    percent
+  }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn sample_process(process: &Child) -> Result<ProcessSample, PluginError> {
+  use anyhow::anyhow;
+
+  let pid = process.id();
+  let status = std::fs::read_to_string(format!("/proc/{}/status", pid))?;
+  let rss_kb = status
+    .lines()
+    .find_map(|line| line.strip_prefix("VmRSS:"))
+    .and_then(|value| {
+      value
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()
+    })
+    .ok_or_else(|| anyhow!("VmRSS missing from /proc/{}/status", pid))?;
+
+  // The command name in the second field may contain spaces, so count fields from the closing
+  // parenthesis. utime and stime are the 14th and 15th fields of the line.
+  let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+  let fields = stat
+    .rfind(')')
+    .map(|pos| stat[pos + 1..].split_whitespace().collect::<Vec<_>>())
+    .unwrap_or_default();
+  let ticks = match (fields.get(11), fields.get(12)) {
+    (Some(utime), Some(stime)) => {
+      utime.parse::<u64>().unwrap_or(0) + stime.parse::<u64>().unwrap_or(0)
+    },
+    _ => return Err(anyhow!("malformed /proc/{}/stat", pid).into()),
+  };
+  let ticks_per_second = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+    tps if tps > 0 => tps as f64,
+    _ => 100.0,
+  };
+
+  Ok(ProcessSample {
+    rss_bytes: rss_kb * 1024,
+    cpu_time: Duration::from_secs_f64(ticks as f64 / ticks_per_second),
+  })
+}
+
+#[cfg(target_os = "macos")]
+#[allow(deprecated)]
+pub(crate) fn sample_process(process: &Child) -> Result<ProcessSample, PluginError> {
+  let mut info: libc::proc_taskinfo = unsafe { std::mem::zeroed() };
+  let size = std::mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+  let written = unsafe {
+    libc::proc_pidinfo(
+      process.id() as libc::c_int,
+      libc::PROC_PIDTASKINFO,
+      0,
+      &mut info as *mut _ as *mut libc::c_void,
+      size,
+    )
+  };
+  if written != size {
+    return Err(std::io::Error::last_os_error().into());
+  }
+
+  // CPU times are reported in Mach absolute time units, which are not nanoseconds on Apple
+  // Silicon.
+  let mut timebase = libc::mach_timebase_info { numer: 0, denom: 0 };
+  unsafe { libc::mach_timebase_info(&mut timebase) };
+  let ticks = (info.pti_total_user + info.pti_total_system) as u128;
+  let nanos = if timebase.denom == 0 {
+    ticks
+  } else {
+    ticks * timebase.numer as u128 / timebase.denom as u128
+  };
+
+  Ok(ProcessSample {
+    rss_bytes: info.pti_resident_size,
+    cpu_time: Duration::from_nanos(nanos as u64),
+  })
+}
+
+#[cfg(windows)]
+pub(crate) fn sample_process(process: &Child) -> Result<ProcessSample, PluginError> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::Foundation::FILETIME;
+  use windows_sys::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+  use windows_sys::Win32::System::Threading::GetProcessTimes;
+
+  let handle = process.as_raw_handle();
+  let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { std::mem::zeroed() };
+  counters.cb = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+  if unsafe { GetProcessMemoryInfo(handle, &mut counters, counters.cb) } == 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+
+  let mut creation: FILETIME = unsafe { std::mem::zeroed() };
+  let mut exit: FILETIME = unsafe { std::mem::zeroed() };
+  let mut kernel: FILETIME = unsafe { std::mem::zeroed() };
+  let mut user: FILETIME = unsafe { std::mem::zeroed() };
+  if unsafe { GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) } == 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+  // FILETIME counts 100 nanosecond intervals.
+  let intervals = |time: FILETIME| ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64;
+
+  Ok(ProcessSample {
+    rss_bytes: counters.WorkingSetSize as u64,
+    cpu_time: Duration::from_nanos((intervals(kernel) + intervals(user)) * 100),
+  })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub(crate) fn sample_process(_process: &Child) -> Result<ProcessSample, PluginError> {
+  Err(PluginError::Unsupported(
+    "process resource usage".to_string(),
+  ))
+}
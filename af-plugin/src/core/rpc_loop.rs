@@ -1,7 +1,9 @@
-use crate::core::parser::{Call, MessageReader};
-use crate::core::plugin::{PluginId, RpcCtx, RunningStateSender};
+use crate::core::journal::CrashJournal;
+use crate::core::parser::{Call, Frame, MessageReader};
+use crate::core::plugin::{Peer, PluginId, RpcCtx, RunningStateSender};
 use crate::core::rpc_object::RpcObject;
 use crate::core::rpc_peer::{RawPeer, ResponsePayload, RpcState};
+use crate::core::writer::DEFAULT_WRITE_QUEUE_FRAMES;
 use crate::error::{PluginError, ReadError, RemoteError};
 use serde::de::DeserializeOwned;
 
@@ -57,15 +59,35 @@ pub struct RpcLoop<W: Write + 'static> {
   peer: RawPeer<W>,
 }
 
-impl<W: Write + Send> RpcLoop<W> {
+impl<W: Write + Send + 'static> RpcLoop<W> {
   /// Creates a new `RpcLoop` with the given output stream (which is used for
   /// sending requests and notifications, as well as responses).
-  pub fn new(writer: W, running_state: RunningStateSender) -> Self {
-    let rpc_peer = RawPeer(Arc::new(RpcState::new(writer, running_state)));
-    RpcLoop {
+  pub fn new(writer: W, running_state: RunningStateSender) -> io::Result<Self> {
+    Self::with_journal(writer, running_state, None)
+  }
+
+  /// Same as [RpcLoop::new], with an optional journal for crash forensics.
+  pub fn with_journal(
+    writer: W,
+    running_state: RunningStateSender,
+    journal: Option<CrashJournal>,
+  ) -> io::Result<Self> {
+    Self::with_write_queue(writer, running_state, journal, DEFAULT_WRITE_QUEUE_FRAMES)
+  }
+
+  /// Same as [RpcLoop::with_journal], with up to `write_queue_frames` frames waiting to be
+  /// written to `writer` before sends fail.
+  pub fn with_write_queue(
+    writer: W,
+    running_state: RunningStateSender,
+    journal: Option<CrashJournal>,
+    write_queue_frames: usize,
+  ) -> io::Result<Self> {
+    let state = RpcState::with_journal(writer, running_state, journal, write_queue_frames)?;
+    Ok(RpcLoop {
       reader: MessageReader::default(),
-      peer: rpc_peer,
-    }
+      peer: RawPeer(state),
+    })
   }
 
   /// Gets a reference to the peer.
@@ -133,6 +155,7 @@ impl<W: Write + Send> RpcLoop<W> {
     let exit = crossbeam_utils::thread::scope(|scope| {
       let peer = self.get_raw_peer();
       peer.reset_needs_exit();
+      peer.set_plugin_id(*plugin_id);
 
       let ctx = RpcCtx {
         peer: Arc::new(peer.clone()),
@@ -152,8 +175,9 @@ impl<W: Write + Send> RpcLoop<W> {
             info!("[RPC] exit plugin read loop");
             break;
           }
-          let json = match self.reader.next(&mut stream) {
-            Ok(json) => json,
+          let binary = self.peer.binary_frames_offered();
+          let frame = match self.reader.next_frame(&mut stream, binary) {
+            Ok(frame) => frame,
             Err(err) => {
               if self.peer.0.is_blocking() {
                 self.peer.unexpected_disconnect(plugin_id, &err);
@@ -165,9 +189,10 @@ impl<W: Write + Send> RpcLoop<W> {
           };
           self.peer.notify_running(*plugin_id);
 
-          match json {
+          match frame {
             None => continue,
-            Some(json) => {
+            Some(Frame::Binary(frame)) => self.peer.handle_binary(frame),
+            Some(Frame::Json(json)) => {
               if json.is_shutdown() {
                 debug!("[RPC] received plugin process shutdown signal");
                 if self.peer.0.is_blocking() {
@@ -1,5 +1,6 @@
 use crate::core::parser::{Call, RequestId};
 use crate::core::rpc_peer::{Response, ResponsePayload};
+use crate::error::RemoteError;
 
 use serde::de::{DeserializeOwned, Error};
 use serde_json::Value;
@@ -53,6 +54,7 @@ impl RpcObject {
   /// - `Ok(Ok(ResponsePayload::Json(result)))`: If the response contains a valid "result".
   /// - `Ok(Ok(ResponsePayload::Streaming(data)))`: If the response contains streaming data of type "streaming".
   /// - `Ok(Ok(ResponsePayload::StreamEnd(json!({}))))`: If the response contains streaming data of type "end".
+  /// - `Ok(Err(RemoteError))`: If the response contains an "error" object.
   /// - `Err(String)`: If any validation or parsing errors occur.
   ///
   pub fn into_response(mut self) -> Result<Response, String> {
@@ -89,7 +91,7 @@ impl RpcObject {
     } else {
       // Handle the 'error' field
       let error = self.0.as_object_mut().unwrap().remove("error").unwrap();
-      Err(format!("Error handling response: {:?}", error))
+      Ok(Err(RemoteError::from_value(error)))
     }
   }
 
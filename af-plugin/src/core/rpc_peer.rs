@@ -1,15 +1,20 @@
-use crate::core::plugin::{Peer, PluginId, RunningState, RunningStateSender};
+use crate::core::binary::{write_binary_frame, BinaryFrame, BinaryHandler, BinaryState};
+use crate::core::journal::CrashJournal;
+use crate::core::plugin::{Peer, PluginId, RunningState, RunningStateSender, StopReason};
 use crate::core::rpc_object::RpcObject;
+use crate::core::writer::{FrameQueue, WriteObserver, DEFAULT_WRITE_QUEUE_FRAMES};
 use crate::error::{PluginError, ReadError, RemoteError};
+use crate::util::redact_secrets;
 use parking_lot::{Condvar, Mutex};
 use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{json, Value as JsonValue};
 use std::collections::{BTreeMap, BinaryHeap, VecDeque};
 use std::fmt::{Debug, Display};
 use std::io::Write;
+use std::marker::PhantomData;
 
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Weak};
 use std::time::{Duration, Instant};
 use std::{cmp, io};
 use tokio_stream::Stream;
@@ -18,6 +23,37 @@ use tracing::{error, info, trace, warn};
 pub struct PluginCommand<T> {
   pub plugin_id: PluginId,
   pub cmd: T,
+  /// When `true`, payloads that cannot carry `params.plugin_id` in place are wrapped as
+  /// `{ "cmd": <value>, "params": { "plugin_id": ... } }` instead of failing to serialize.
+  wrap_payload: bool,
+}
+
+impl<T> PluginCommand<T> {
+  /// Creates a command that serializes object payloads in place by inserting
+  /// `params.plugin_id`. Non-object payloads fail to serialize.
+  pub fn new(plugin_id: PluginId, cmd: T) -> Self {
+    Self {
+      plugin_id,
+      cmd,
+      wrap_payload: false,
+    }
+  }
+
+  /// Creates a command that wraps any payload which cannot carry `params.plugin_id` in place,
+  /// such as plain strings, numbers, or objects without an object `params` field.
+  ///
+  /// The plugin side must understand the wrapped form before this is used for outgoing commands.
+  pub fn new_wrapped(plugin_id: PluginId, cmd: T) -> Self {
+    Self {
+      plugin_id,
+      cmd,
+      wrap_payload: true,
+    }
+  }
+
+  pub fn is_wrapped(&self) -> bool {
+    self.wrap_payload
+  }
 }
 
 impl<T: Serialize> Serialize for PluginCommand<T> {
@@ -25,9 +61,37 @@ impl<T: Serialize> Serialize for PluginCommand<T> {
   where
     S: Serializer,
   {
-    let mut v = serde_json::to_value(&self.cmd).map_err(ser::Error::custom)?;
-    v["params"]["plugin_id"] = json!(self.plugin_id);
-    v.serialize(serializer)
+    let cmd = serde_json::to_value(&self.cmd).map_err(ser::Error::custom)?;
+    let plugin_id = json!(self.plugin_id);
+    let value = match cmd {
+      JsonValue::Object(mut map) => match map.get("params") {
+        // Keep the payload as-is and inject `params.plugin_id`, unless the result would be
+        // indistinguishable from a wrapped payload.
+        Some(JsonValue::Object(_)) if !(self.wrap_payload && is_wrapped_shape(&map, true)) => {
+          insert_plugin_id(&mut map, plugin_id);
+          JsonValue::Object(map)
+        },
+        _ if self.wrap_payload => wrap_payload(JsonValue::Object(map), plugin_id),
+        None | Some(JsonValue::Null) => {
+          insert_plugin_id(&mut map, plugin_id);
+          JsonValue::Object(map)
+        },
+        Some(other) => {
+          return Err(ser::Error::custom(format!(
+            "command params must be a JSON object, found: {}",
+            other
+          )))
+        },
+      },
+      other if self.wrap_payload => wrap_payload(other, plugin_id),
+      other => {
+        return Err(ser::Error::custom(format!(
+          "command must serialize to a JSON object, found: {}",
+          other
+        )))
+      },
+    };
+    value.serialize(serializer)
   }
 }
 
@@ -40,28 +104,96 @@ impl<'de, T: Deserialize<'de>> Deserialize<'de> for PluginCommand<T> {
     struct PluginIdHelper {
       plugin_id: PluginId,
     }
-    let v = JsonValue::deserialize(deserializer)?;
-    let plugin_id = PluginIdHelper::deserialize(&v)
-      .map_err(de::Error::custom)?
-      .plugin_id;
+    let mut v = JsonValue::deserialize(deserializer)?;
+
+    // Wrapped payload: { "cmd": <value>, "params": { "plugin_id": ... } }
+    if let Some(map) = v.as_object_mut().filter(|map| is_wrapped_shape(map, false)) {
+      let plugin_id = PluginIdHelper::deserialize(&map["params"])
+        .map_err(de::Error::custom)?
+        .plugin_id;
+      let cmd = map.remove("cmd").unwrap_or_default();
+      let cmd = T::deserialize(cmd).map_err(de::Error::custom)?;
+      return Ok(PluginCommand::new_wrapped(plugin_id, cmd));
+    }
+
+    // The plugin sends `plugin_id` at the top level, while commands serialized by the host carry
+    // it in `params.plugin_id`. Strip the injected field so the payload round-trips unchanged.
+    let plugin_id = match PluginIdHelper::deserialize(&v) {
+      Ok(helper) => helper.plugin_id,
+      Err(err) => {
+        let params = v
+          .get_mut("params")
+          .and_then(JsonValue::as_object_mut)
+          .ok_or_else(|| de::Error::custom(&err))?;
+        let plugin_id = params
+          .remove("plugin_id")
+          .ok_or_else(|| de::Error::custom(&err))?;
+        if params.is_empty() {
+          if let Some(map) = v.as_object_mut() {
+            map.remove("params");
+          }
+        }
+        PluginId::deserialize(plugin_id).map_err(de::Error::custom)?
+      },
+    };
     let cmd = T::deserialize(v).map_err(de::Error::custom)?;
-    Ok(PluginCommand { plugin_id, cmd })
+    Ok(PluginCommand::new(plugin_id, cmd))
   }
 }
 
+fn insert_plugin_id(map: &mut serde_json::Map<String, JsonValue>, plugin_id: JsonValue) {
+  let params = map.entry("params").or_insert(JsonValue::Null);
+  if params.is_null() {
+    *params = json!({});
+  }
+  if let Some(params) = params.as_object_mut() {
+    params.insert("plugin_id".to_string(), plugin_id);
+  }
+}
+
+fn wrap_payload(cmd: JsonValue, plugin_id: JsonValue) -> JsonValue {
+  json!({ "cmd": cmd, "params": { "plugin_id": plugin_id } })
+}
+
+/// Returns `true` if `map` has exactly the `cmd` and `params` fields, and `params` only holds
+/// `plugin_id`. When `pending_plugin_id` is set, `params` is checked as if `plugin_id` had
+/// already been inserted.
+fn is_wrapped_shape(map: &serde_json::Map<String, JsonValue>, pending_plugin_id: bool) -> bool {
+  if map.len() != 2 || !map.contains_key("cmd") {
+    return false;
+  }
+  match map.get("params").and_then(JsonValue::as_object) {
+    Some(params) if pending_plugin_id => {
+      params.is_empty() || (params.len() == 1 && params.contains_key("plugin_id"))
+    },
+    Some(params) => params.len() == 1 && params.contains_key("plugin_id"),
+    None => false,
+  }
+}
+
+type StopReasonProbe = Box<dyn Fn() -> Option<StopReason> + Send + Sync>;
+
 pub struct RpcState<W: Write> {
   rx_queue: Mutex<VecDeque<Result<RpcObject, ReadError>>>,
   rx_cvar: Condvar,
-  writer: Mutex<W>,
+  /// The writer is owned by the thread draining this queue.
+  writer: FrameQueue,
   request_id_counter: AtomicUsize,
   pending: Mutex<BTreeMap<usize, ResponseHandler>>,
   timers: Mutex<BinaryHeap<Timer>>,
   needs_exit: AtomicBool,
   is_blocking: AtomicBool,
   running_state: RunningStateSender,
+  journal: Option<CrashJournal>,
+  binary: BinaryState,
+  /// Plugin served by the main loop, disconnected when writing to it keeps failing.
+  plugin_id: Mutex<Option<PluginId>>,
+  /// Tells why the plugin stopped, when it stops unexpectedly.
+  stop_reason: Mutex<Option<StopReasonProbe>>,
+  _writer: PhantomData<fn() -> W>,
 }
 
-impl<W: Write> RpcState<W> {
+impl<W: Write + Send + 'static> RpcState<W> {
   /// Creates a new `RawPeer` instance.
   ///
   /// # Arguments
@@ -71,25 +203,78 @@ impl<W: Write> RpcState<W> {
   /// # Returns
   ///
   /// A new `RawPeer` instance wrapped in an `Arc`.
-  pub fn new(writer: W, running_state: RunningStateSender) -> Self {
-    RpcState {
+  pub fn new(writer: W, running_state: RunningStateSender) -> io::Result<Arc<Self>> {
+    Self::with_journal(writer, running_state, None, DEFAULT_WRITE_QUEUE_FRAMES)
+  }
+
+  /// Same as [RpcState::new], but records outgoing requests and unexpected disconnects in
+  /// `journal`. Up to `write_queue_frames` frames wait to be written to `writer`, see
+  /// [PluginConfig::write_queue_frames](crate::core::plugin::PluginConfig::write_queue_frames).
+  pub fn with_journal(
+    writer: W,
+    running_state: RunningStateSender,
+    journal: Option<CrashJournal>,
+    write_queue_frames: usize,
+  ) -> io::Result<Arc<Self>> {
+    let (queue, frames) = FrameQueue::new(write_queue_frames);
+    let state = Arc::new(RpcState {
       rx_queue: Mutex::new(VecDeque::new()),
       rx_cvar: Condvar::new(),
-      writer: Mutex::new(writer),
+      writer: queue,
       request_id_counter: AtomicUsize::new(0),
       pending: Mutex::new(BTreeMap::new()),
       timers: Mutex::new(BinaryHeap::new()),
       needs_exit: AtomicBool::new(false),
       is_blocking: Default::default(),
       running_state,
-    }
+      journal,
+      binary: Default::default(),
+      plugin_id: Default::default(),
+      stop_reason: Default::default(),
+      _writer: PhantomData,
+    });
+    frames.spawn_writer(writer, PeerWriteObserver(Arc::downgrade(&state)))?;
+    Ok(state)
   }
+}
 
+impl<W: Write> RpcState<W> {
   pub fn is_blocking(&self) -> bool {
     self.is_blocking.load(Ordering::Acquire)
   }
 }
 
+/// Reports the failed writes of the writer thread to the requests they carried.
+struct PeerWriteObserver<W: Write>(Weak<RpcState<W>>);
+
+impl<W: Write + Send + 'static> WriteObserver for PeerWriteObserver<W> {
+  fn is_closed(&self) -> bool {
+    self
+      .0
+      .upgrade()
+      .is_some_and(|state| state.needs_exit.load(Ordering::SeqCst))
+  }
+
+  fn write_failed(&self, request_id: Option<usize>, err: io::Error, persistent: bool) {
+    // Frames left in the queue of a dropped peer have no one to report to.
+    let Some(state) = self.0.upgrade() else {
+      return;
+    };
+    let peer = RawPeer(state);
+    let handler = request_id.and_then(|id| peer.0.pending.lock().remove(&id));
+    if persistent {
+      let plugin_id = *peer.0.plugin_id.lock();
+      match plugin_id {
+        Some(plugin_id) => peer.unexpected_disconnect(&plugin_id, &err),
+        None => warn!("[RPC] writing keeps failing before the main loop started"),
+      }
+    }
+    if let Some(handler) = handler {
+      handler.invoke(Err(PluginError::Io(err)));
+    }
+  }
+}
+
 pub struct RawPeer<W: Write + 'static>(pub(crate) Arc<RpcState<W>>);
 
 impl<W: Write + Send + 'static> Peer for RawPeer<W> {
@@ -112,8 +297,19 @@ impl<W: Write + Send + 'static> Peer for RawPeer<W> {
     self.send_rpc(method, params, ResponseHandler::StreamCallback(Arc::new(f)));
   }
 
-  fn async_send_rpc_request(&self, method: &str, params: &JsonValue, f: Box<dyn OneShotCallback>) {
-    self.send_rpc(method, params, ResponseHandler::Callback(f));
+  fn async_send_rpc_request(
+    &self,
+    method: &str,
+    params: &JsonValue,
+    f: Box<dyn OneShotCallback>,
+  ) -> usize {
+    self.send_rpc(method, params, ResponseHandler::Callback(f))
+  }
+
+  fn cancel_request(&self, id: usize) {
+    if self.0.pending.lock().remove(&id).is_some() {
+      trace!("[RPC] cancelled request: {}", id);
+    }
   }
 
   fn send_rpc_request(&self, method: &str, params: &JsonValue) -> Result<JsonValue, PluginError> {
@@ -125,6 +321,13 @@ impl<W: Write + Send + 'static> Peer for RawPeer<W> {
     result
   }
 
+  fn cancel_pending_requests(&self) {
+    let pending = std::mem::take(&mut *self.0.pending.lock());
+    for (_, callback) in pending {
+      callback.invoke(Err(PluginError::PeerDisconnect));
+    }
+  }
+
   fn request_is_pending(&self) -> bool {
     let queue = self.0.rx_queue.lock();
     !queue.is_empty()
@@ -136,6 +339,22 @@ impl<W: Write + Send + 'static> Peer for RawPeer<W> {
       token,
     });
   }
+
+  fn set_binary_handler(&self, handler: Arc<dyn BinaryHandler>) {
+    self.0.binary.set_handler(handler);
+  }
+
+  fn binary_frames_offered(&self) -> bool {
+    self.0.binary.is_offered()
+  }
+
+  fn accept_binary_frames(&self) {
+    self.0.binary.accept();
+  }
+
+  fn send_binary(&self, method_id: u16, payload: &[u8]) -> Result<(), PluginError> {
+    RawPeer::send_binary(self, method_id, payload)
+  }
 }
 
 impl<W: Write> RawPeer<W> {
@@ -151,11 +370,46 @@ impl<W: Write> RawPeer<W> {
   ///
   /// # Notes
   ///
-  /// This function serializes the JSON value, appends a newline, and writes it to the underlying writer.
+  /// This function serializes the JSON value, appends a newline, and queues it for the writer
+  /// thread. It fails right away when the write queue is full.
   fn send(&self, json: &JsonValue) -> Result<(), io::Error> {
-    let mut s = serde_json::to_string(json)?;
-    s.push('\n');
-    self.0.writer.lock().write_all(s.as_bytes())
+    self.send_frame(json, None)
+  }
+
+  /// Same as [RawPeer::send], for the frame of the request `request_id`, which fails when the
+  /// frame can't be written.
+  fn send_frame(&self, json: &JsonValue, request_id: Option<usize>) -> Result<(), io::Error> {
+    let mut bytes = serde_json::to_vec(json)?;
+    bytes.push(b'\n');
+    self.0.writer.push(bytes, request_id)
+  }
+
+  /// Waits up to `timeout` for the frames sent so far to be written to the plugin. Returns
+  /// `false` on timeout.
+  pub fn flush(&self, timeout: Duration) -> bool {
+    self.0.writer.flush(timeout)
+  }
+
+  /// Sends a [BinaryFrame] to the peer, which must have accepted binary frames at
+  /// initialization. The frame is written as a whole, between two JSON lines.
+  pub fn send_binary(&self, method_id: u16, payload: &[u8]) -> Result<(), PluginError> {
+    if !self.0.binary.is_accepted() {
+      return Err(PluginError::BinaryFramesNotNegotiated);
+    }
+    trace!(
+      "[RPC] binary frame: {} ({} bytes)",
+      method_id,
+      payload.len()
+    );
+    let mut bytes = Vec::with_capacity(payload.len() + 8);
+    write_binary_frame(&mut bytes, method_id, payload)?;
+    self.0.writer.push(bytes, None)?;
+    Ok(())
+  }
+
+  /// Hands a [BinaryFrame] read from the peer to the registered handler.
+  pub(crate) fn handle_binary(&self, frame: BinaryFrame) {
+    self.0.binary.dispatch(frame);
   }
 
   /// Sends a response to a previous RPC request.
@@ -197,23 +451,34 @@ impl<W: Write> RawPeer<W> {
   ///
   /// This function generates a unique ID for the request, stores the response handler,
   /// and sends the RPC request. If sending fails, it immediately invokes the response handler with an error.
-  fn send_rpc(&self, method: &str, params: &JsonValue, response_handler: ResponseHandler) {
-    trace!("[RPC] call:{} :{:?}", method, params);
+  /// Returns the ID, see [Peer::cancel_request].
+  fn send_rpc(&self, method: &str, params: &JsonValue, response_handler: ResponseHandler) -> usize {
+    trace!("[RPC] call:{} :{:?}", method, redact_secrets(params));
     let id = self.0.request_id_counter.fetch_add(1, Ordering::Relaxed);
 
+    if let Some(journal) = &self.0.journal {
+      journal.record_request(id, method, params);
+    }
+
     let msg = json!({
         "id": id,
         "method": method,
         "params": params,
     });
 
-    if let Err(e) = self.send(&msg) {
-      response_handler.invoke(Err(PluginError::Io(e)));
-      return;
+    // Register the handler before sending, a fast plugin may answer before `send` returns.
+    self.0.pending.lock().insert(id, response_handler);
+    let error = match self.send_frame(&msg, Some(id)) {
+      Err(e) => PluginError::Io(e),
+      // The peer disconnected after the pending requests were drained, nothing will answer.
+      Ok(_) if self.0.needs_exit.load(Ordering::SeqCst) => PluginError::PeerDisconnect,
+      Ok(_) => return id,
+    };
+    let response_handler = self.0.pending.lock().remove(&id);
+    if let Some(response_handler) = response_handler {
+      response_handler.invoke(Err(error));
     }
-
-    let mut pending = self.0.pending.lock();
-    pending.insert(id, response_handler);
+    id
   }
 
   /// Processes an incoming response to an RPC request.
@@ -351,26 +616,34 @@ impl<W: Write> RawPeer<W> {
 
   pub(crate) fn unexpected_disconnect<E: Debug>(&self, plugin_id: &PluginId, error: &E) {
     trace!("[RPC] disconnecting peer with error {:?}", error);
-    self.handle_disconnect(RunningState::UnexpectedStop {
+    let reason = self.0.stop_reason.lock().as_ref().and_then(|probe| probe());
+    let state = RunningState::UnexpectedStop {
       plugin_id: *plugin_id,
-    });
+      reason,
+    };
+    if let Some(journal) = &self.0.journal {
+      let pending_ids = self
+        .0
+        .pending
+        .try_lock()
+        .map(|pending| pending.keys().cloned().collect())
+        .unwrap_or_default();
+      let from_state = format!("{:?}", *self.0.running_state.borrow());
+      journal.record_disconnect(pending_ids, from_state, format!("{:?}", state));
+    }
+    self.handle_disconnect(state);
   }
 
   fn handle_disconnect(&self, state: RunningState) {
-    let _ = self.0.running_state.send(state);
-    let mut pending = self.0.pending.try_lock();
-    if let Some(pending) = pending.as_mut() {
-      let ids = pending.keys().cloned().collect::<Vec<_>>();
-      for id in &ids {
-        if let Some(callback) = pending.remove(id) {
-          callback.invoke(Err(PluginError::PeerDisconnect));
-        }
-      }
-    }
-    drop(pending);
-
+    self.0.running_state.transition(state);
+    // Marked before draining so a request registered concurrently is failed by `send_rpc`.
     trace!("[RPC] marking needs_exit");
     self.0.needs_exit.store(true, Ordering::SeqCst);
+
+    let pending = std::mem::take(&mut *self.0.pending.lock());
+    for (_, callback) in pending {
+      callback.invoke(Err(PluginError::PeerDisconnect));
+    }
   }
 
   /// Checks if the RPC system needs to exit.
@@ -378,19 +651,25 @@ impl<W: Write> RawPeer<W> {
     self.0.needs_exit.load(Ordering::Relaxed)
   }
 
+  /// Records the plugin served by the main loop.
+  pub(crate) fn set_plugin_id(&self, plugin_id: PluginId) {
+    *self.0.plugin_id.lock() = Some(plugin_id);
+  }
+
+  /// Sets `probe` to tell why the plugin stopped when it stops unexpectedly.
+  pub(crate) fn set_stop_reason(&self, probe: StopReasonProbe) {
+    *self.0.stop_reason.lock() = Some(probe);
+  }
+
   pub(crate) fn reset_needs_exit(&self) {
     self.0.needs_exit.store(false, Ordering::SeqCst);
   }
 
   pub(crate) fn notify_running(&self, plugin_id: PluginId) {
-    // if current running state is not equal to Running, we need to notify the plugin to start running.
-    let is_running = matches!(*self.0.running_state.borrow(), RunningState::Running { .. });
-    if !is_running {
-      let _ = self
-        .0
-        .running_state
-        .send(RunningState::Running { plugin_id });
-    }
+    self
+      .0
+      .running_state
+      .transition(RunningState::Running { plugin_id });
   }
 }
 
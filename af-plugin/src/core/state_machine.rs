@@ -0,0 +1,128 @@
+use crate::core::plugin::{PluginId, RunningState, RunningStateReceiver};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+use tokio::sync::watch;
+use tracing::{trace, warn};
+
+/// Transitions kept by [StateMachine::history].
+pub const TRANSITION_HISTORY_LEN: usize = 20;
+
+/// A change of [RunningState] sent to the subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateTransition {
+  pub from: RunningState,
+  pub to: RunningState,
+  pub at: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct MachineState {
+  /// Plugin whose states are accepted. States of another plugin, such as the late stop of the
+  /// process a restart replaced, are dropped.
+  plugin_id: Option<PluginId>,
+  history: VecDeque<StateTransition>,
+}
+
+/// The only writer of a [RunningState] channel. Subscribers see the states in the order
+/// `ReadyToConnect → Connecting → Connected → Running → Stopped | UnexpectedStop`, with a
+/// restart going back to `Connecting`:
+///
+/// * sending the current state again is a no-op,
+/// * a transition out of this order is logged and dropped.
+#[derive(Debug)]
+pub struct StateMachine {
+  sender: watch::Sender<RunningState>,
+  state: Mutex<MachineState>,
+}
+
+impl StateMachine {
+  pub fn new(initial: RunningState) -> Self {
+    Self::from(watch::channel(initial).0)
+  }
+
+  /// Moves to `state`. Returns whether the subscribers were notified.
+  pub fn transition(&self, state: RunningState) -> bool {
+    let mut machine = self.state.lock();
+    let current = self.sender.borrow().clone();
+    if current == state {
+      trace!("[AI Plugin] ignore duplicate running state: {:?}", state);
+      return false;
+    }
+    if let (Some(expected), Some(plugin_id)) = (machine.plugin_id, state.plugin_id()) {
+      if expected != plugin_id {
+        warn!(
+          "[AI Plugin] ignore running state {:?} of replaced plugin {:?}",
+          state, plugin_id
+        );
+        return false;
+      }
+    }
+    if !is_allowed(&current, &state) {
+      warn!(
+        "[AI Plugin] ignore invalid running state transition: {:?} -> {:?}",
+        current, state
+      );
+      return false;
+    }
+
+    if state.plugin_id().is_some() {
+      machine.plugin_id = state.plugin_id();
+    }
+    if machine.history.len() == TRANSITION_HISTORY_LEN {
+      machine.history.pop_front();
+    }
+    machine.history.push_back(StateTransition {
+      from: current,
+      to: state.clone(),
+      at: SystemTime::now(),
+    });
+    // Sent while holding the lock, so concurrent transitions are seen in the order they were
+    // validated.
+    self.sender.send_replace(state);
+    true
+  }
+
+  /// Moves to [RunningState::Connecting] for the process of `plugin_id`, whose states are the only
+  /// ones accepted from now on.
+  pub fn connecting(&self, plugin_id: PluginId) -> bool {
+    self.state.lock().plugin_id = Some(plugin_id);
+    self.transition(RunningState::Connecting)
+  }
+
+  pub fn borrow(&self) -> watch::Ref<'_, RunningState> {
+    self.sender.borrow()
+  }
+
+  pub fn subscribe(&self) -> RunningStateReceiver {
+    self.sender.subscribe()
+  }
+
+  /// The latest transitions, oldest first, up to [TRANSITION_HISTORY_LEN].
+  pub fn history(&self) -> Vec<StateTransition> {
+    self.state.lock().history.iter().cloned().collect()
+  }
+}
+
+impl From<watch::Sender<RunningState>> for StateMachine {
+  fn from(sender: watch::Sender<RunningState>) -> Self {
+    Self {
+      sender,
+      state: Default::default(),
+    }
+  }
+}
+
+fn is_allowed(from: &RunningState, to: &RunningState) -> bool {
+  use RunningState::*;
+  match to {
+    // A new process may be started whatever the state of the previous one.
+    Connecting => true,
+    Connected { .. } => matches!(from, Connecting),
+    Running { .. } => matches!(from, Connecting | Connected { .. }),
+    Stopped { .. } | UnexpectedStop { .. } => {
+      matches!(from, Connecting | Connected { .. } | Running { .. })
+    },
+    ReadyToConnect => false,
+  }
+}
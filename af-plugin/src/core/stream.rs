@@ -0,0 +1,282 @@
+use crate::core::parser::ResponseParser;
+use crate::error::PluginError;
+use anyhow::anyhow;
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::trace;
+
+/// Number of frames a stream buffers before its [BackpressurePolicy] kicks in.
+pub const DEFAULT_STREAM_CAPACITY: usize = 256;
+
+/// What a stream does when the plugin produces frames faster than the consumer reads them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+  /// Block the plugin's reader thread until the consumer catches up. No frame is lost, but the
+  /// plugin's other requests stall in the meantime.
+  #[default]
+  Block,
+  /// Drop the oldest buffered frame to make room for the new one.
+  DropOldest,
+  /// Merge the new frame into the last buffered one when the parser knows how to, see
+  /// [ResponseParser::coalesce]. Frames that can't be merged fall back to [BackpressurePolicy::Block].
+  CoalesceText,
+}
+
+/// Rewrites the answer of a completion as it streams, see [StreamOptions::post_process].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostProcessor {
+  /// Removes the code fence the answer starts with, and the one closing it at the end of the
+  /// answer. Fences inside the answer are kept.
+  StripCodeFences,
+  /// Cuts the answer at the last sentence ending within this many characters, ending the stream.
+  EnforceMaxChars(usize),
+  /// Joins the lines and paragraphs of the answer with spaces.
+  EnsureSingleParagraph,
+  /// Removes the whitespace at the start and end of the answer.
+  TrimWhitespace,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamOptions {
+  /// Maximum number of frames buffered between the plugin and the consumer.
+  pub capacity: usize,
+  pub policy: BackpressurePolicy,
+  /// Continue the answer with a new request when the stream fails with a transient error.
+  /// Only honored by callers that know how to continue, such as `stream_question`.
+  pub resume_on_error: bool,
+  /// Follow the answer with a frame of the words it changed in the original text. Only honored
+  /// by `complete_text_v2`.
+  pub compute_diff: bool,
+  /// Check that the embedded documents of the chat are relevant to the question before
+  /// answering from them. Only honored by `stream_question`.
+  pub require_relevant_context: bool,
+  /// Complete a text too long for the context window of the model in chunks, split on paragraph
+  /// breaks, instead of failing. Only honored by `complete_text_v2`.
+  pub auto_chunk: bool,
+  /// Rewrite the answer with each processor, in order. Only honored by `complete_text_v2`.
+  pub post_process: Vec<PostProcessor>,
+  /// Assemble the answer into the JSON object asked for by the format schema, sending the
+  /// fields as they complete instead of the answer text. Only honored by `stream_question`,
+  /// which needs a format schema for it.
+  pub assemble_json: bool,
+}
+
+impl Default for StreamOptions {
+  fn default() -> Self {
+    Self {
+      capacity: DEFAULT_STREAM_CAPACITY,
+      policy: BackpressurePolicy::default(),
+      resume_on_error: false,
+      compute_diff: false,
+      require_relevant_context: false,
+      auto_chunk: false,
+      post_process: vec![],
+      assemble_json: false,
+    }
+  }
+}
+
+impl StreamOptions {
+  pub fn with_capacity(mut self, capacity: usize) -> Self {
+    self.capacity = capacity.max(1);
+    self
+  }
+
+  pub fn with_policy(mut self, policy: BackpressurePolicy) -> Self {
+    self.policy = policy;
+    self
+  }
+
+  pub fn with_resume_on_error(mut self, resume_on_error: bool) -> Self {
+    self.resume_on_error = resume_on_error;
+    self
+  }
+
+  pub fn with_compute_diff(mut self, compute_diff: bool) -> Self {
+    self.compute_diff = compute_diff;
+    self
+  }
+
+  pub fn with_require_relevant_context(mut self, require: bool) -> Self {
+    self.require_relevant_context = require;
+    self
+  }
+
+  pub fn with_auto_chunk(mut self, auto_chunk: bool) -> Self {
+    self.auto_chunk = auto_chunk;
+    self
+  }
+
+  pub fn with_post_process(mut self, post_process: Vec<PostProcessor>) -> Self {
+    self.post_process = post_process;
+    self
+  }
+
+  pub fn with_assemble_json(mut self, assemble_json: bool) -> Self {
+    self.assemble_json = assemble_json;
+    self
+  }
+}
+
+/// Number of frames a stream dropped or merged because its consumer was too slow. Reported to
+/// the consumer in a final frame, see [ResponseParser::backpressure_frame].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackpressureReport {
+  pub dropped: usize,
+  pub coalesced: usize,
+}
+
+impl BackpressureReport {
+  pub fn is_empty(&self) -> bool {
+    self.dropped == 0 && self.coalesced == 0
+  }
+}
+
+type Frame<P> = Result<<P as ResponseParser>::ValueType, PluginError>;
+
+struct Queue<P: ResponseParser> {
+  frames: VecDeque<Frame<P>>,
+  report: BackpressureReport,
+  sender_closed: bool,
+  receiver_closed: bool,
+}
+
+struct Shared<P: ResponseParser> {
+  queue: Mutex<Queue<P>>,
+  not_full: Condvar,
+  not_empty: Notify,
+}
+
+/// The producing half of [bounded_stream]. Dropping it ends the stream once the buffered
+/// frames have been delivered.
+pub struct FrameSender<P: ResponseParser> {
+  shared: Arc<Shared<P>>,
+  options: StreamOptions,
+}
+
+impl<P: ResponseParser> FrameSender<P> {
+  /// Buffers a frame, applying the backpressure policy when the buffer is full. Must not be
+  /// called on an async worker thread because [BackpressurePolicy::Block] blocks the caller.
+  pub fn send(&self, frame: Frame<P>) {
+    let mut queue = self.shared.queue.lock();
+    if queue.receiver_closed {
+      return;
+    }
+
+    let mut frame = frame;
+    if queue.frames.len() >= self.options.capacity {
+      match self.options.policy {
+        BackpressurePolicy::Block => {},
+        BackpressurePolicy::DropOldest => {
+          queue.frames.pop_front();
+          queue.report.dropped += 1;
+        },
+        BackpressurePolicy::CoalesceText => {
+          let merged = match (queue.frames.back_mut(), frame) {
+            (Some(Ok(last)), Ok(value)) => P::coalesce(last, value).map(Ok),
+            (_, other) => Some(other),
+          };
+          match merged {
+            None => {
+              queue.report.coalesced += 1;
+              return;
+            },
+            Some(unmerged) => frame = unmerged,
+          }
+        },
+      }
+
+      while queue.frames.len() >= self.options.capacity && !queue.receiver_closed {
+        self.shared.not_full.wait(&mut queue);
+      }
+      if queue.receiver_closed {
+        return;
+      }
+    }
+
+    queue.frames.push_back(frame);
+    drop(queue);
+    self.shared.not_empty.notify_one();
+  }
+
+  /// Number of frames currently waiting for the consumer.
+  pub fn buffered(&self) -> usize {
+    self.shared.queue.lock().frames.len()
+  }
+}
+
+impl<P: ResponseParser> Drop for FrameSender<P> {
+  fn drop(&mut self) {
+    self.shared.queue.lock().sender_closed = true;
+    self.shared.not_empty.notify_one();
+  }
+}
+
+/// Creates a stream whose buffer never grows beyond `options.capacity` frames.
+///
+/// Frames are moved to the returned stream by a task spawned on the current tokio runtime, so
+/// this fails outside of one. When frames were dropped or coalesced, the stream ends with the
+/// frame built by [ResponseParser::backpressure_frame].
+pub fn bounded_stream<P: ResponseParser + 'static>(
+  options: StreamOptions,
+) -> Result<(FrameSender<P>, ReceiverStream<Frame<P>>), PluginError> {
+  let runtime = Handle::try_current()
+    .map_err(|err| PluginError::Internal(anyhow!("streams need a tokio runtime: {}", err)))?;
+  let capacity = options.capacity;
+  let options = options.with_capacity(capacity);
+  let shared = Arc::new(Shared {
+    queue: Mutex::new(Queue {
+      frames: VecDeque::with_capacity(options.capacity.min(DEFAULT_STREAM_CAPACITY)),
+      report: BackpressureReport::default(),
+      sender_closed: false,
+      receiver_closed: false,
+    }),
+    not_full: Condvar::new(),
+    not_empty: Notify::new(),
+  });
+
+  let (tx, rx) = tokio::sync::mpsc::channel(1);
+  let forward_shared = shared.clone();
+  runtime.spawn(async move {
+    let shared = forward_shared;
+    loop {
+      let (frame, report) = {
+        let mut queue = shared.queue.lock();
+        let frame = queue.frames.pop_front();
+        let report = (frame.is_none() && queue.sender_closed).then_some(queue.report);
+        (frame, report)
+      };
+
+      match (frame, report) {
+        (Some(frame), _) => {
+          shared.not_full.notify_one();
+          if tx.send(frame).await.is_err() {
+            break;
+          }
+        },
+        (None, Some(report)) => {
+          if !report.is_empty() {
+            trace!("[RPC] stream backpressure: {:?}", report);
+            if let Some(frame) = P::backpressure_frame(&report) {
+              let _ = tx.send(Ok(frame)).await;
+            }
+          }
+          break;
+        },
+        (None, None) => shared.not_empty.notified().await,
+      }
+    }
+
+    let mut queue = shared.queue.lock();
+    queue.receiver_closed = true;
+    queue.frames.clear();
+    shared.not_full.notify_all();
+  });
+
+  Ok((FrameSender { shared, options }, ReceiverStream::new(rx)))
+}
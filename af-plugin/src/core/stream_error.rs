@@ -0,0 +1,108 @@
+use crate::core::plugin::RunningState;
+use crate::error::{error_code, PluginError, RemoteError};
+use serde::{Deserialize, Serialize};
+
+/// Why a stream failed, so the UI can offer the right way out: retry, check that Ollama runs,
+/// or shorten the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamErrorKind {
+  /// The stream was stopped on purpose, by the host or the plugin.
+  Cancelled,
+  /// The plugin process exited or its connection broke.
+  PluginDied,
+  /// The plugin can't reach the Ollama server.
+  OllamaUnreachable,
+  /// The prompt doesn't fit in the context window of the model.
+  ContextLengthExceeded,
+  /// The plugin sent a frame that could not be parsed.
+  MalformedFrame,
+  Unknown,
+}
+
+/// Lowercase fragments of the messages the plugin and Ollama send when the prompt is too long.
+const CONTEXT_LENGTH_MESSAGES: &[&str] = &[
+  "context length",
+  "context window",
+  "maximum context",
+  "context_length_exceeded",
+  "prompt is too long",
+  "too many tokens",
+];
+
+/// Lowercase fragments of the messages sent when the Ollama server can't be reached, as written
+/// by httpx, requests and the Ollama client of the plugin.
+const UNREACHABLE_MESSAGES: &[&str] = &[
+  "connection refused",
+  "connecterror",
+  "all connection attempts failed",
+  "failed to connect",
+  "could not connect",
+  "max retries exceeded",
+  "name or service not known",
+  "ollama is not running",
+];
+
+/// Lowercase fragments of the messages sent for cancelled requests.
+const CANCELLED_MESSAGES: &[&str] = &["cancelled", "canceled"];
+
+/// Classifies an error received as a stream item. `state` is the running state of the plugin
+/// when the error was received: a disconnect of a plugin stopped on purpose is a cancellation.
+pub fn classify_error(err: &PluginError, state: &RunningState) -> StreamErrorKind {
+  match err {
+    PluginError::Stream { kind, .. } => *kind,
+    PluginError::PeerDisconnect | PluginError::PluginNotConnected | PluginError::Io(_) => {
+      match state {
+        RunningState::Stopped { .. } => StreamErrorKind::Cancelled,
+        _ => StreamErrorKind::PluginDied,
+      }
+    },
+    PluginError::InvalidResponse => StreamErrorKind::MalformedFrame,
+    PluginError::RemoteError(err) => classify_remote_error(err),
+    _ => StreamErrorKind::Unknown,
+  }
+}
+
+/// Classifies an error reported by the plugin, by its code and then by its message.
+pub fn classify_remote_error(err: &RemoteError) -> StreamErrorKind {
+  match err {
+    RemoteError::ContextTooLong { .. } => return StreamErrorKind::ContextLengthExceeded,
+    RemoteError::InvalidResponse(_) | RemoteError::ParseResponse(_) => {
+      return StreamErrorKind::MalformedFrame
+    },
+    _ => {},
+  }
+  if err.code() == Some(error_code::STREAM_INTERRUPTED) {
+    return StreamErrorKind::OllamaUnreachable;
+  }
+
+  let message = match err {
+    RemoteError::InvalidParams { message, .. }
+    | RemoteError::ModelOverloaded { message, .. }
+    | RemoteError::Internal { message, .. }
+    | RemoteError::Custom { message, .. } => message.to_lowercase(),
+    RemoteError::Unknown(value) => value.to_string().to_lowercase(),
+    _ => return StreamErrorKind::Unknown,
+  };
+  let contains_any = |fragments: &[&str]| fragments.iter().any(|f| message.contains(f));
+  if contains_any(CONTEXT_LENGTH_MESSAGES) {
+    StreamErrorKind::ContextLengthExceeded
+  } else if contains_any(UNREACHABLE_MESSAGES) {
+    StreamErrorKind::OllamaUnreachable
+  } else if contains_any(CANCELLED_MESSAGES) {
+    StreamErrorKind::Cancelled
+  } else {
+    StreamErrorKind::Unknown
+  }
+}
+
+/// Wraps an error received as a stream item into a [PluginError::Stream].
+pub(crate) fn stream_error(err: PluginError, state: &RunningState) -> PluginError {
+  match err {
+    PluginError::Stream { .. } => err,
+    err => PluginError::Stream {
+      kind: classify_error(&err, state),
+      source: Box::new(err),
+    },
+  }
+}
@@ -0,0 +1,328 @@
+use crate::core::plugin::PluginId;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Argument the path of the pipe follows on the command line of a plugin using
+/// [TransportKind::NamedPipe].
+pub const RPC_PIPE_ARG: &str = "--rpc-pipe";
+/// Environment variable holding the path of the pipe, for plugins that don't parse arguments.
+pub const RPC_PIPE_ENV: &str = "AF_PLUGIN_RPC_PIPE";
+/// Time a plugin has to connect to its pipe once started.
+pub const PIPE_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the host checks that a plugin that hasn't connected yet is still alive.
+const CONNECT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The channel the newline delimited JSON RPC messages go through.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+  /// The stdin and stdout of the plugin process.
+  #[default]
+  Stdio,
+  /// A Windows named pipe, or a Unix domain socket elsewhere, created by the host. The plugin
+  /// gets its path as the value of [RPC_PIPE_ARG] and in [RPC_PIPE_ENV], and must connect to it
+  /// within [PIPE_CONNECT_TIMEOUT]. Unlike stdout, it can't be written to by the software that
+  /// injects output into child processes, such as some antivirus products.
+  NamedPipe,
+}
+
+pub(crate) type RpcReader = Box<dyn Read + Send>;
+pub(crate) type RpcWriter = Box<dyn Write + Send>;
+
+static PIPE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The pipe of [TransportKind::NamedPipe]. The socket file is removed when it is dropped, so once
+/// the plugin connected or failed to.
+pub(crate) struct PipeListener {
+  path: PathBuf,
+  inner: imp::Listener,
+}
+
+impl PipeListener {
+  /// Creates a pipe with a name no other plugin process of this host uses.
+  pub(crate) fn bind(id: PluginId) -> io::Result<Self> {
+    let name = format!(
+      "af-plugin-{}-{}-{}",
+      std::process::id(),
+      id.0,
+      PIPE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = imp::pipe_path(&name);
+    let inner = imp::Listener::bind(&path)?;
+    Ok(Self { path, inner })
+  }
+
+  pub(crate) fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Waits for `child` to connect. Fails when it exits first, or doesn't connect within
+  /// `timeout`.
+  pub(crate) fn accept(
+    &self,
+    child: &mut Child,
+    timeout: Duration,
+  ) -> io::Result<(RpcReader, RpcWriter)> {
+    let deadline = Instant::now() + timeout;
+    loop {
+      if let Some(channel) = self.inner.try_accept(CONNECT_POLL_INTERVAL)? {
+        return Ok(channel);
+      }
+      if let Some(status) = child.try_wait()? {
+        return Err(io::Error::new(
+          io::ErrorKind::BrokenPipe,
+          format!("plugin exited before connecting to its pipe: {}", status),
+        ));
+      }
+      if Instant::now() >= deadline {
+        return Err(io::Error::new(
+          io::ErrorKind::TimedOut,
+          format!(
+            "plugin didn't connect to {:?} within {:?}",
+            self.path, timeout
+          ),
+        ));
+      }
+    }
+  }
+}
+
+impl Drop for PipeListener {
+  fn drop(&mut self) {
+    self.inner.close(&self.path);
+  }
+}
+
+#[cfg(unix)]
+mod imp {
+  use super::{RpcReader, RpcWriter};
+  use std::io;
+  use std::os::unix::fs::PermissionsExt;
+  use std::os::unix::net::UnixListener;
+  use std::path::{Path, PathBuf};
+  use std::time::Duration;
+
+  pub(super) fn pipe_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}.sock", name))
+  }
+
+  pub(super) struct Listener(UnixListener);
+
+  impl Listener {
+    pub(super) fn bind(path: &Path) -> io::Result<Self> {
+      // Left behind by a host that crashed before removing it.
+      let _ = std::fs::remove_file(path);
+      let listener = UnixListener::bind(path)?;
+      std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+      listener.set_nonblocking(true)?;
+      Ok(Self(listener))
+    }
+
+    pub(super) fn try_accept(&self, wait: Duration) -> io::Result<Option<(RpcReader, RpcWriter)>> {
+      match self.0.accept() {
+        Ok((stream, _)) => {
+          stream.set_nonblocking(false)?;
+          let writer = stream.try_clone()?;
+          Ok(Some((Box::new(stream), Box::new(writer))))
+        },
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+          std::thread::sleep(wait);
+          Ok(None)
+        },
+        Err(err) => Err(err),
+      }
+    }
+
+    pub(super) fn close(&self, path: &Path) {
+      let _ = std::fs::remove_file(path);
+    }
+  }
+}
+
+#[cfg(windows)]
+mod imp {
+  use super::{RpcReader, RpcWriter};
+  use std::io::{self, Read, Write};
+  use std::os::windows::ffi::OsStrExt;
+  use std::path::{Path, PathBuf};
+  use std::ptr::{null, null_mut};
+  use std::sync::Arc;
+  use std::time::Duration;
+  use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED, HANDLE,
+    INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
+  };
+  use windows_sys::Win32::Storage::FileSystem::{
+    ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX,
+  };
+  use windows_sys::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+    PIPE_TYPE_BYTE, PIPE_WAIT,
+  };
+  use windows_sys::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+  use windows_sys::Win32::System::IO::{CancelIo, GetOverlappedResult, OVERLAPPED};
+
+  const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+
+  pub(super) fn pipe_path(name: &str) -> PathBuf {
+    PathBuf::from(format!(r"\\.\pipe\{}", name))
+  }
+
+  struct OwnedHandle(HANDLE);
+
+  // The handle is only used through the Win32 calls below, which are thread safe.
+  unsafe impl Send for OwnedHandle {}
+  unsafe impl Sync for OwnedHandle {}
+
+  impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+      unsafe { CloseHandle(self.0) };
+    }
+  }
+
+  /// An overlapped operation waited for with its own event. The pipe is opened for overlapped
+  /// I/O, so a read blocked waiting for the plugin doesn't block the writes to it.
+  struct Overlapped {
+    overlapped: OVERLAPPED,
+    event: OwnedHandle,
+  }
+
+  impl Overlapped {
+    fn new() -> io::Result<Self> {
+      let event = unsafe { CreateEventW(null(), 1, 0, null()) };
+      if event.is_null() {
+        return Err(io::Error::last_os_error());
+      }
+      let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+      overlapped.hEvent = event;
+      Ok(Self {
+        overlapped,
+        event: OwnedHandle(event),
+      })
+    }
+
+    /// Waits for the operation started with `started`, returning the number of bytes
+    /// transferred.
+    fn wait(&mut self, pipe: HANDLE, started: i32) -> io::Result<u32> {
+      if started == 0 {
+        let err = unsafe { GetLastError() };
+        if err != ERROR_IO_PENDING {
+          return Err(io::Error::from_raw_os_error(err as i32));
+        }
+      }
+      self.finish(pipe)
+    }
+
+    /// Waits for the pending operation to complete or be cancelled.
+    fn finish(&mut self, pipe: HANDLE) -> io::Result<u32> {
+      let mut transferred = 0;
+      if unsafe { GetOverlappedResult(pipe, &self.overlapped, &mut transferred, 1) } == 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(transferred)
+    }
+  }
+
+  pub(super) struct Listener(Arc<OwnedHandle>);
+
+  impl Listener {
+    pub(super) fn bind(path: &Path) -> io::Result<Self> {
+      let name = path
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect::<Vec<_>>();
+      let handle = unsafe {
+        CreateNamedPipeW(
+          name.as_ptr(),
+          PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE | FILE_FLAG_OVERLAPPED,
+          PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+          1,
+          PIPE_BUFFER_SIZE,
+          PIPE_BUFFER_SIZE,
+          0,
+          null(),
+        )
+      };
+      if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(Self(Arc::new(OwnedHandle(handle))))
+    }
+
+    pub(super) fn try_accept(&self, wait: Duration) -> io::Result<Option<(RpcReader, RpcWriter)>> {
+      let pipe = self.0 .0;
+      let mut connect = Overlapped::new()?;
+      if unsafe { ConnectNamedPipe(pipe, &mut connect.overlapped) } == 0 {
+        match unsafe { GetLastError() } {
+          ERROR_PIPE_CONNECTED => {},
+          ERROR_IO_PENDING => {
+            if unsafe { WaitForSingleObject(connect.event.0, wait.as_millis() as u32) }
+              != WAIT_OBJECT_0
+            {
+              unsafe { CancelIo(pipe) };
+              // Waits for the cancellation, so `connect` outlives the operation.
+              let _ = connect.finish(pipe);
+              return Ok(None);
+            }
+            connect.finish(pipe)?;
+          },
+          err => return Err(io::Error::from_raw_os_error(err as i32)),
+        }
+      }
+      Ok(Some((
+        Box::new(PipeStream(self.0.clone())),
+        Box::new(PipeStream(self.0.clone())),
+      )))
+    }
+
+    pub(super) fn close(&self, _path: &Path) {
+      // The pipe goes away with its last handle.
+    }
+  }
+
+  struct PipeStream(Arc<OwnedHandle>);
+
+  impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      let pipe = self.0 .0;
+      let mut read = Overlapped::new()?;
+      let len = buf.len().min(u32::MAX as usize) as u32;
+      let started = unsafe {
+        ReadFile(
+          pipe,
+          buf.as_mut_ptr(),
+          len,
+          null_mut(),
+          &mut read.overlapped,
+        )
+      };
+      match read.wait(pipe, started) {
+        Ok(read) => Ok(read as usize),
+        // The plugin closed its end.
+        Err(err) if err.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+        Err(err) => Err(err),
+      }
+    }
+  }
+
+  impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      let pipe = self.0 .0;
+      let mut write = Overlapped::new()?;
+      let len = buf.len().min(u32::MAX as usize) as u32;
+      let started =
+        unsafe { WriteFile(pipe, buf.as_ptr(), len, null_mut(), &mut write.overlapped) };
+      write.wait(pipe, started).map(|written| written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,152 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, trace};
+
+/// Frames waiting to be written to a plugin before sends start failing, see
+/// [PluginConfig::write_queue_frames](crate::core::plugin::PluginConfig::write_queue_frames).
+pub const DEFAULT_WRITE_QUEUE_FRAMES: usize = 1024;
+
+/// Failed writes in a row after which the plugin is considered gone.
+const MAX_CONSECUTIVE_WRITE_ERRORS: usize = 3;
+
+pub(crate) enum OutboundFrame {
+  /// Bytes of a JSON line or a binary frame, with the id of the request they carry if any.
+  Data {
+    bytes: Vec<u8>,
+    request_id: Option<usize>,
+  },
+  /// Answered once the frames queued before are written.
+  Flush(SyncSender<()>),
+}
+
+/// What the writer thread reports to the peer.
+pub(crate) trait WriteObserver: Send + 'static {
+  /// Whether queued frames are dropped instead of written, once the peer disconnected.
+  fn is_closed(&self) -> bool;
+
+  /// Called when a frame can't be written. `persistent` is set when the channel looks broken.
+  fn write_failed(&self, request_id: Option<usize>, err: io::Error, persistent: bool);
+}
+
+/// Bounded queue of the frames sent to a plugin, written in order by a dedicated thread so
+/// senders never wait on a full pipe.
+///
+/// Once the queue is dropped, the frames still queued are written before the thread exits.
+/// Frames queued after the peer disconnected are dropped, see [WriteObserver::is_closed].
+pub(crate) struct FrameQueue {
+  tx: SyncSender<OutboundFrame>,
+}
+
+/// Receiving end of a [FrameQueue], until its writer thread is started.
+pub(crate) struct QueuedFrames(Receiver<OutboundFrame>);
+
+impl QueuedFrames {
+  /// Starts the thread writing the queued frames to `writer`.
+  pub(crate) fn spawn_writer<W, O>(self, writer: W, observer: O) -> io::Result<()>
+  where
+    W: Write + Send + 'static,
+    O: WriteObserver,
+  {
+    thread::Builder::new()
+      .name("plugin writer".to_string())
+      .spawn(move || write_frames(writer, self.0, observer))?;
+    Ok(())
+  }
+}
+
+impl FrameQueue {
+  pub(crate) fn new(capacity: usize) -> (Self, QueuedFrames) {
+    let (tx, rx) = mpsc::sync_channel(capacity.max(1));
+    (Self { tx }, QueuedFrames(rx))
+  }
+
+  /// Queues `bytes` without waiting. Fails with [io::ErrorKind::WouldBlock] when the queue is
+  /// full.
+  pub(crate) fn push(&self, bytes: Vec<u8>, request_id: Option<usize>) -> io::Result<()> {
+    self.send(OutboundFrame::Data { bytes, request_id })
+  }
+
+  /// Waits up to `timeout` for the frames queued so far to be written. Returns `false` on
+  /// timeout.
+  pub(crate) fn flush(&self, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::sync_channel(1);
+    let mut frame = OutboundFrame::Flush(tx);
+    // Waits for room in a full queue, within the same timeout.
+    loop {
+      match self.tx.try_send(frame) {
+        Ok(()) => break,
+        Err(TrySendError::Full(returned)) => {
+          if Instant::now() >= deadline {
+            return false;
+          }
+          frame = returned;
+          thread::sleep(Duration::from_millis(1));
+        },
+        Err(TrySendError::Disconnected(_)) => return true,
+      }
+    }
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    !matches!(
+      rx.recv_timeout(remaining),
+      Err(mpsc::RecvTimeoutError::Timeout)
+    )
+  }
+
+  fn send(&self, frame: OutboundFrame) -> io::Result<()> {
+    self.tx.try_send(frame).map_err(|err| match err {
+      TrySendError::Full(_) => io::Error::new(io::ErrorKind::WouldBlock, "write queue is full"),
+      TrySendError::Disconnected(_) => {
+        io::Error::new(io::ErrorKind::BrokenPipe, "writer thread exited")
+      },
+    })
+  }
+}
+
+fn write_frames<W: Write, O: WriteObserver>(
+  mut writer: W,
+  rx: Receiver<OutboundFrame>,
+  observer: O,
+) {
+  let mut errors = 0;
+  for frame in rx {
+    match frame {
+      OutboundFrame::Flush(done) => {
+        let _ = writer.flush();
+        let _ = done.send(());
+      },
+      OutboundFrame::Data { bytes, request_id } => {
+        if observer.is_closed() {
+          trace!(
+            "[RPC] peer disconnected, dropping frame of {:?}",
+            request_id
+          );
+          continue;
+        }
+        match writer.write_all(&bytes) {
+          Ok(()) => errors = 0,
+          Err(err) => {
+            errors += 1;
+            let persistent = errors >= MAX_CONSECUTIVE_WRITE_ERRORS || is_broken_pipe(&err);
+            error!(
+              "[RPC] failed to write frame of {:?}: {}, persistent: {}",
+              request_id, err, persistent
+            );
+            observer.write_failed(request_id, err, persistent);
+          },
+        }
+      },
+    }
+  }
+  let _ = writer.flush();
+  trace!("[RPC] writer thread exited");
+}
+
+fn is_broken_pipe(err: &io::Error) -> bool {
+  matches!(
+    err.kind(),
+    io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::UnexpectedEof
+  )
+}
@@ -1,5 +1,8 @@
+use crate::core::stream_error::StreamErrorKind;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{json, Value as JsonValue};
+use std::path::PathBuf;
+use std::time::Duration;
 use std::{fmt, io};
 
 /// The error type of `tauri-utils`.
@@ -24,10 +27,232 @@ pub enum PluginError {
   #[error("Plugin is initializing.")]
   InProgress,
 
+  /// An initialization run by another caller failed, with the message of its error.
+  #[error("Plugin initialization failed: {0}")]
+  InitFailed(String),
+
+  /// The plugin is not running yet, and the caller asked not to wait for it.
+  #[error("Plugin is not ready.")]
+  NotReady,
+
+  /// The plugin did not answer within the caller-provided time budget.
+  #[error("Request timed out after {0:?}")]
+  RequestTimeout(Duration),
+
+  /// The plugin does not implement the requested method, usually because it is too old.
+  #[error("Plugin does not support method: {method}")]
+  UnsupportedMethod { method: String },
+
+  /// The chat has no messages to work with.
+  #[error("Chat {chat_id} has no messages")]
+  EmptyChat { chat_id: String },
+
+  /// The persist directory holds vectors produced by a different embedding model.
+  #[error("Embedding model changed from {stored} to {configured}")]
+  EmbeddingModelChanged { stored: String, configured: String },
+
+  /// The vectors have another dimension than the ones of the configured embedding model.
+  #[error("Embedding dimension changed from {stored} to {configured}")]
+  EmbeddingDimensionChanged { stored: usize, configured: usize },
+
+  /// The vector store was written in a store format the plugin can't open nor migrate, so its
+  /// content must be indexed again, see `OllamaAIPlugin::migrate_vector_store` in af-local-ai.
+  #[error("Vector store format {from} can't be migrated to {to}, it must be indexed again")]
+  StoreNeedsReindex { from: u32, to: u32 },
+
+  /// The vector store was written in an older store format and is not used until it is migrated,
+  /// see `OllamaAIPlugin::migrate_vector_store` in af-local-ai.
+  #[error("Vector store format {from} must be migrated to {to}")]
+  StoreNeedsMigration { from: u32, to: u32 },
+
+  #[error("Invalid log level: {0}")]
+  InvalidLogLevel(String),
+
+  /// The plugin speaks an older protocol than the host requires.
+  #[error("Plugin speaks protocol {found}, at least {required} is required")]
+  IncompatiblePlugin { required: u32, found: u32 },
+
+  /// The plugin reported a version older than the minimum of the host, see
+  /// `OllamaPluginConfig::with_minimum_plugin_version` in af-local-ai.
+  #[error("Plugin {found} is outdated, at least {minimum} is required. {download_hint}")]
+  PluginOutdated {
+    found: String,
+    minimum: String,
+    /// Tells the user where to get a newer plugin.
+    download_hint: String,
+  },
+
+  /// A model the host asked for is not one of the models of the Ollama server.
+  #[error("Model not found: {0}")]
+  ModelNotFound(String),
+
+  /// The outbound filter refused to send the text, see `OutboundFilter` in af-local-ai.
+  #[error("Blocked by policy: {0}")]
+  BlockedByPolicy(String),
+
+  /// A blocking call was made from within a Tokio runtime, where it would panic.
+  #[error("Blocking call made from within an async runtime")]
+  BlockingInAsyncContext,
+
+  /// The operation is not available on the current platform.
+  #[error("{0} is not supported on this platform")]
+  Unsupported(String),
+
+  /// Neither the configured executable nor the command exists.
+  #[error("Plugin executable not found: {0:?}")]
+  ExecutableNotFound(PathBuf),
+
+  /// The executable is a directory, or lacks the execute permission.
+  #[error("Plugin executable is not executable: {0:?}")]
+  ExecutableNotExecutable(PathBuf),
+
+  /// macOS Gatekeeper refuses to run the downloaded executable until it is approved.
+  #[error(
+    "Plugin executable {0:?} is quarantined by macOS, open it once from Finder or run `xattr -d com.apple.quarantine {0:?}`"
+  )]
+  QuarantinedExecutable(PathBuf),
+
+  /// The working directory of the plugin process doesn't exist.
+  #[error("Plugin working directory not found: {0:?}")]
+  WorkingDirNotFound(PathBuf),
+
+  /// Another running application uses the persist directory.
+  #[error("Persist directory is used by process {0}")]
+  PersistDirectoryLocked(u32),
+
+  /// An indexing operation on a read-only vector store without a writable overlay, see
+  /// `OllamaPluginConfig::with_read_only` in af-local-ai.
+  #[error("Vector store is read-only")]
+  ReadOnlyStore,
+
+  /// The model is known not to support a feature the request asks for, such as a JSON format,
+  /// see `OllamaAIPlugin::probe_model_capabilities` in af-local-ai. The request is not sent.
+  #[error("Model {model} does not support {feature}")]
+  UnsupportedByModel { model: String, feature: String },
+
+  /// No config profile of that name was saved.
+  #[error("Profile not found: {0}")]
+  ProfileNotFound(String),
+
+  /// Profile names may only contain letters, digits, spaces, `-` and `_`.
+  #[error("Invalid profile name: {0:?}")]
+  InvalidProfileName(String),
+
+  /// Binary frames were sent to a plugin that did not accept them at initialization, see
+  /// `BinaryFrame`.
+  #[error("Plugin did not negotiate binary frames")]
+  BinaryFramesNotNegotiated,
+
+  /// Retrieval options of a chat or question that can't be used, see `RagOptions::validate`.
+  #[error("Invalid RAG options: {0}")]
+  InvalidRagOptions(String),
+
+  /// Nothing embedded in the chat is relevant enough to the question, which the caller asked
+  /// not to answer without document context, see `ContextGate` in af-local-ai.
+  #[error("No relevant context for the question, best score: {top_score:?}")]
+  NoRelevantContext { top_score: Option<f64> },
+
+  /// The text of a completion doesn't fit in the context window of the model, and the caller
+  /// didn't ask to complete it in chunks, see `StreamOptions::auto_chunk`.
+  #[error("Input too long: about {estimated_tokens} tokens, the limit is {limit}")]
+  InputTooLong { estimated_tokens: u32, limit: u32 },
+
+  /// Stream options that can't be used together, or without an argument they need.
+  #[error("Invalid stream options: {0}")]
+  InvalidStreamOptions(String),
+
+  /// A structured answer that isn't valid JSON or doesn't follow the schema it was asked for,
+  /// see `StreamOptions::assemble_json`. `raw` is the text of the answer.
+  #[error("Answer doesn't match the schema: {reason}")]
+  SchemaValidationFailed { reason: String, raw: String },
+
+  /// A request beyond the rate limit set with `OllamaAIPlugin::set_rate_limit` in af-local-ai,
+  /// which asked to fail instead of waiting. A token is available after `retry_after`.
+  #[error("Rate limited, retry after {retry_after:?}")]
+  RateLimited { retry_after: Duration },
+
+  /// A rate limit that lets no request through.
+  #[error("Invalid rate limit: {0}")]
+  InvalidRateLimit(String),
+
+  /// A similarity threshold outside of 0.0 to 1.0.
+  #[error("Invalid similarity threshold: {0}, expected 0.0 to 1.0")]
+  InvalidThreshold(f64),
+
+  /// A chat message without content, see `OllamaAIPlugin::append_chat_message` in af-local-ai.
+  #[error("Chat message is empty")]
+  EmptyMessage,
+
+  /// An embedding plugin sharing the process of a chat plugin was used as one with its own
+  /// process, or the other way around, see `EmbeddingPlugin::attached` in af-local-ai.
+  #[error("Embedding plugin mode mismatch: {0}")]
+  EmbeddingModeMismatch(String),
+
+  /// A fetched web page is not HTML, with the content type it was served with, see
+  /// `OllamaAIPlugin::embed_url` in af-local-ai.
+  #[error("Unsupported content type: {0}")]
+  UnsupportedContentType(String),
+
+  /// A web page could not be fetched, e.g. because of its scheme, a redirect loop, its size or a
+  /// timeout.
+  #[error("Failed to fetch {url}: {reason}")]
+  FetchFailed { url: String, reason: String },
+
+  /// A web page was answered with another status than 200.
+  #[error("Fetching {url} returned status {status}")]
+  HttpStatus { url: String, status: u16 },
+
+  /// An error received as an item of a stream, with the kind of failure it is.
+  #[error("Stream failed ({kind:?}): {source}")]
+  Stream {
+    kind: StreamErrorKind,
+    source: Box<PluginError>,
+  },
+
   #[error(transparent)]
   Internal(#[from] anyhow::Error),
 }
 
+impl PluginError {
+  /// Returns the error reported by the plugin, if this is one.
+  pub fn remote_error(&self) -> Option<&RemoteError> {
+    match self.root_cause() {
+      PluginError::RemoteError(err) => Some(err),
+      _ => None,
+    }
+  }
+
+  /// The error wrapped by a [PluginError::Stream], or this error.
+  pub fn root_cause(&self) -> &PluginError {
+    match self {
+      PluginError::Stream { source, .. } => source.root_cause(),
+      err => err,
+    }
+  }
+
+  /// The kind of failure, for errors received as stream items.
+  pub fn stream_error_kind(&self) -> Option<StreamErrorKind> {
+    match self {
+      PluginError::Stream { kind, .. } => Some(*kind),
+      _ => None,
+    }
+  }
+}
+
+/// JSON-RPC error codes with a dedicated [RemoteError] variant.
+pub mod error_code {
+  pub const INVALID_REQUEST: i64 = -32600;
+  pub const METHOD_NOT_FOUND: i64 = -32601;
+  pub const INVALID_PARAMS: i64 = -32602;
+  pub const INTERNAL_ERROR: i64 = -32603;
+  /// The model is busy with other requests, try again later.
+  pub const MODEL_OVERLOADED: i64 = -32001;
+  /// The prompt doesn't fit in the context window of the model.
+  pub const CONTEXT_TOO_LONG: i64 = -32002;
+  /// The plugin lost its connection to the model while streaming. The answer can be continued.
+  pub const STREAM_INTERRUPTED: i64 = -32003;
+}
+
 #[derive(Debug)]
 pub enum ReadError {
   /// An error occurred in the underlying stream
@@ -56,6 +281,35 @@ pub enum RemoteError {
 
   #[error("Parse response: {0}")]
   ParseResponse(JsonValue),
+
+  /// The plugin rejected the parameters of the request.
+  #[error("Invalid params: {message}")]
+  InvalidParams {
+    message: String,
+    data: Option<JsonValue>,
+  },
+
+  /// The model is busy; the request may succeed when retried later.
+  #[error("Model overloaded: {message}")]
+  ModelOverloaded {
+    message: String,
+    data: Option<JsonValue>,
+  },
+
+  /// The prompt is longer than the context window of the model.
+  #[error("Context too long: {message}")]
+  ContextTooLong {
+    message: String,
+    data: Option<JsonValue>,
+  },
+
+  /// The plugin failed while handling the request.
+  #[error("Plugin internal error: {message}")]
+  Internal {
+    message: String,
+    data: Option<JsonValue>,
+  },
+
   /// A custom error, defined by the client.
   #[error("Custom error: {message}")]
   Custom {
@@ -71,6 +325,93 @@ pub enum RemoteError {
   Unknown(JsonValue),
 }
 
+impl RemoteError {
+  /// Builds the error described by a JSON-RPC error object. Objects without a code and a message
+  /// become [RemoteError::Unknown].
+  pub fn from_value(value: JsonValue) -> RemoteError {
+    let resp = match ErrorHelper::deserialize(&value) {
+      Ok(resp) => resp,
+      Err(_) => return RemoteError::Unknown(value),
+    };
+
+    let ErrorHelper {
+      code,
+      message,
+      data,
+    } = resp;
+    match code {
+      error_code::INVALID_REQUEST => RemoteError::InvalidRequest(data),
+      error_code::INVALID_PARAMS => RemoteError::InvalidParams { message, data },
+      error_code::INTERNAL_ERROR => RemoteError::Internal { message, data },
+      error_code::MODEL_OVERLOADED => RemoteError::ModelOverloaded { message, data },
+      error_code::CONTEXT_TOO_LONG => RemoteError::ContextTooLong { message, data },
+      _ => RemoteError::Custom {
+        code,
+        message,
+        data,
+      },
+    }
+  }
+
+  /// The JSON-RPC code of the error, if the plugin reported one.
+  pub fn code(&self) -> Option<i64> {
+    match self {
+      RemoteError::InvalidRequest(_) => Some(error_code::INVALID_REQUEST),
+      RemoteError::InvalidParams { .. } => Some(error_code::INVALID_PARAMS),
+      RemoteError::ModelOverloaded { .. } => Some(error_code::MODEL_OVERLOADED),
+      RemoteError::ContextTooLong { .. } => Some(error_code::CONTEXT_TOO_LONG),
+      RemoteError::Internal { .. } => Some(error_code::INTERNAL_ERROR),
+      RemoteError::Custom { code, .. } => Some(*code),
+      RemoteError::InvalidResponse(_) | RemoteError::ParseResponse(_) | RemoteError::Unknown(_) => {
+        None
+      },
+    }
+  }
+
+  /// The `data` the plugin attached to the error.
+  pub fn data(&self) -> Option<&JsonValue> {
+    match self {
+      RemoteError::InvalidRequest(data)
+      | RemoteError::InvalidParams { data, .. }
+      | RemoteError::ModelOverloaded { data, .. }
+      | RemoteError::ContextTooLong { data, .. }
+      | RemoteError::Internal { data, .. }
+      | RemoteError::Custom { data, .. } => data.as_ref(),
+      RemoteError::InvalidResponse(_) | RemoteError::ParseResponse(_) | RemoteError::Unknown(_) => {
+        None
+      },
+    }
+  }
+
+  /// Returns `true` if the same request may succeed when sent again later.
+  pub fn is_retryable(&self) -> bool {
+    matches!(self, RemoteError::ModelOverloaded { .. })
+  }
+
+  /// Returns `true` if the peer rejected the request because it doesn't know the method.
+  pub fn is_method_not_found(&self) -> bool {
+    let message = match self {
+      RemoteError::Custom { code, .. } if *code == error_code::METHOD_NOT_FOUND => return true,
+      RemoteError::InvalidParams { message, .. }
+      | RemoteError::Internal { message, .. }
+      | RemoteError::Custom { message, .. } => message.to_lowercase(),
+      _ => return false,
+    };
+    message.contains("method not found") || message.contains("unknown method")
+  }
+
+  /// Returns `true` if the peer rejected the request because what it would create already
+  /// exists, e.g. a chat created twice.
+  pub fn is_already_exists(&self) -> bool {
+    match self {
+      RemoteError::InvalidParams { message, .. }
+      | RemoteError::Internal { message, .. }
+      | RemoteError::Custom { message, .. } => message.to_lowercase().contains("already exists"),
+      _ => false,
+    }
+  }
+}
+
 impl ReadError {
   /// Returns `true` iff this is the `ReadError::Disconnect` variant.
   pub fn is_disconnect(&self) -> bool {
@@ -128,19 +469,7 @@ impl<'de> Deserialize<'de> for RemoteError {
     D: Deserializer<'de>,
   {
     let v = JsonValue::deserialize(deserializer)?;
-    let resp = match ErrorHelper::deserialize(&v) {
-      Ok(resp) => resp,
-      Err(_) => return Ok(RemoteError::Unknown(v)),
-    };
-
-    Ok(match resp.code {
-      -32600 => RemoteError::InvalidRequest(resp.data),
-      _ => RemoteError::Custom {
-        code: resp.code,
-        message: resp.message,
-        data: resp.data,
-      },
-    })
+    Ok(RemoteError::from_value(v))
   }
 }
 
@@ -150,7 +479,23 @@ impl Serialize for RemoteError {
     S: Serializer,
   {
     let (code, message, data) = match self {
-      RemoteError::InvalidRequest(ref d) => (-32600, "Invalid request".to_string(), d.clone()),
+      RemoteError::InvalidRequest(ref d) => (
+        error_code::INVALID_REQUEST,
+        "Invalid request".to_string(),
+        d.clone(),
+      ),
+      RemoteError::InvalidParams { message, data } => {
+        (error_code::INVALID_PARAMS, message.clone(), data.clone())
+      },
+      RemoteError::ModelOverloaded { message, data } => {
+        (error_code::MODEL_OVERLOADED, message.clone(), data.clone())
+      },
+      RemoteError::ContextTooLong { message, data } => {
+        (error_code::CONTEXT_TOO_LONG, message.clone(), data.clone())
+      },
+      RemoteError::Internal { message, data } => {
+        (error_code::INTERNAL_ERROR, message.clone(), data.clone())
+      },
       RemoteError::Custom {
         code,
         ref message,
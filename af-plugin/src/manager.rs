@@ -1,3 +1,4 @@
+use crate::core::orphan::{reap_orphans, remove_lock_file, write_lock_file, OrphanReport};
 use crate::core::parser::ResponseParser;
 use crate::core::plugin::{
   start_plugin_process, Plugin, PluginConfig, PluginId, RpcCtx, RunningStateSender,
@@ -8,20 +9,48 @@ use crate::error::{PluginError, ReadError, RemoteError};
 use anyhow::anyhow;
 use parking_lot::Mutex;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::path::{Path, PathBuf};
 
-use crate::util::{get_operating_system, OperatingSystem};
+use crate::util::{get_operating_system, redact_secrets, OperatingSystem};
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::{error, info, instrument, trace};
+use tracing::{error, info, instrument, trace, warn};
+
+/// How often `shutdown_all` and `teardown_plugin` check whether the plugin processes have
+/// exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The outcome of [PluginManager::shutdown_all].
+#[derive(Debug, Default, Clone)]
+pub struct ShutdownReport {
+  /// Plugins whose process exited on its own after the shutdown request.
+  pub exited: Vec<PluginId>,
+  /// Plugins whose process was still alive when the timeout elapsed and had to be killed.
+  pub killed: Vec<PluginId>,
+}
+
+/// The outcome of [PluginManager::teardown_plugin].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TeardownReport {
+  /// The process exited on its own within the grace period, with a success status.
+  pub exited_cleanly: bool,
+  /// Time from the shutdown request until the process was gone.
+  pub waited: Duration,
+  /// The process was still alive when the grace period elapsed and had to be killed.
+  pub forced: bool,
+}
 
 pub struct PluginManager {
   state: Arc<Mutex<PluginState>>,
   plugin_id_counter: Arc<AtomicI64>,
   operating_system: OperatingSystem,
   running_plugins: Arc<RwLock<HashMap<String, PluginId>>>,
+  /// Persist directories whose lock file was written by [Self::lock_persist_directory].
+  locked_directories: Arc<Mutex<HashSet<PathBuf>>>,
 }
 
 impl Default for PluginManager {
@@ -34,11 +63,12 @@ impl PluginManager {
   pub fn new() -> Self {
     PluginManager {
       state: Arc::new(Mutex::new(PluginState {
-        plugins: Vec::new(),
+        plugins: HashMap::new(),
       })),
       plugin_id_counter: Arc::new(Default::default()),
       operating_system: get_operating_system(),
       running_plugins: Arc::new(Default::default()),
+      locked_directories: Arc::new(Default::default()),
     }
   }
 
@@ -63,23 +93,40 @@ impl PluginManager {
     drop(write_guard);
 
     let weak_state = WeakPluginState(Arc::downgrade(&self.state));
-    start_plugin_process(
+    let name = plugin_info.name.clone();
+    if let Err(err) = start_plugin_process(
       plugin_info,
       plugin_id,
       weak_state,
       running_state,
       self.running_plugins.clone(),
     )
-    .await?;
+    .await
+    {
+      self.forget_running_plugin(&name, plugin_id).await;
+      return Err(err);
+    }
     Ok(plugin_id)
   }
 
+  /// Whether a plugin named `name` was created and not removed since.
+  pub async fn is_plugin_running(&self, name: &str) -> bool {
+    self.running_plugins.read().await.contains_key(name)
+  }
+
+  /// Removes `name` from the running plugins, unless another plugin took the name since.
+  async fn forget_running_plugin(&self, name: &str, plugin_id: PluginId) {
+    let mut running_plugins = self.running_plugins.write().await;
+    if running_plugins.get(name) == Some(&plugin_id) {
+      running_plugins.remove(name);
+    }
+  }
+
   pub async fn get_plugin(&self, plugin_id: PluginId) -> Result<Weak<Plugin>, PluginError> {
     let state = self.state.lock();
     let plugin = state
       .plugins
-      .iter()
-      .find(|p| p.id == plugin_id)
+      .get(&plugin_id)
       .ok_or(PluginError::PluginNotConnected)?;
     Ok(Arc::downgrade(plugin))
   }
@@ -94,7 +141,75 @@ impl PluginManager {
 
     info!("[AI Plugin] removing plugin {:?}", id);
     self.state.lock().disconnect_plugin(id, Ok(()));
+    self.forget_plugin_id(id).await;
 
+    info!("[AI Plugin]: did remove plugin {:?}", id);
+    Ok(())
+  }
+
+  /// Removes the plugin `id` and returns once its process is gone: the plugin is asked to shut
+  /// down, and killed if its process is still alive after `grace`. Outstanding requests and
+  /// streams are cancelled.
+  ///
+  /// A plugin that was already removed is reported as neither exited cleanly nor forced.
+  #[instrument(skip(self), err)]
+  pub async fn teardown_plugin(
+    &self,
+    id: PluginId,
+    grace: Duration,
+  ) -> Result<TeardownReport, PluginError> {
+    if self.operating_system.is_not_desktop() {
+      return Err(PluginError::Internal(anyhow!(
+        "plugin not supported on this platform"
+      )));
+    }
+
+    let started = Instant::now();
+    let plugin = self.state.lock().take_plugin(id);
+    self.forget_plugin_id(id).await;
+    let plugin = match plugin {
+      Some(plugin) => plugin,
+      None => return Ok(TeardownReport::default()),
+    };
+
+    info!("[AI Plugin] tearing down plugin {:?}", id);
+    let shutdown_plugin = plugin.clone();
+    let _ = tokio::task::spawn_blocking(move || {
+      shutdown_plugin.cancel_pending_requests();
+      shutdown_plugin.request_shutdown();
+    })
+    .await;
+
+    let deadline = started + grace;
+    while !plugin.has_exited() && Instant::now() < deadline {
+      tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+    let status = plugin.exit_status();
+    let forced = status.is_none();
+    // Dropping the plugin asks it to shut down again while it runs, which blocks.
+    let _ = tokio::task::spawn_blocking(move || {
+      if forced {
+        warn!("[AI Plugin] force killing plugin: {}", plugin);
+        if let Err(err) = plugin.kill() {
+          error!(
+            "[AI Plugin] failed to kill plugin {:?}: {:?}",
+            plugin.id, err
+          );
+        }
+      }
+    })
+    .await;
+
+    let report = TeardownReport {
+      exited_cleanly: status.is_some_and(|status| status.success()),
+      waited: started.elapsed(),
+      forced,
+    };
+    info!("[AI Plugin] teardown report of {:?}: {:?}", id, report);
+    Ok(report)
+  }
+
+  async fn forget_plugin_id(&self, id: PluginId) {
     let mut running_plugins = self.running_plugins.write().await;
     let key_to_remove = running_plugins
       .iter()
@@ -103,17 +218,128 @@ impl PluginManager {
     if let Some(name) = key_to_remove {
       running_plugins.remove(&name);
     }
+  }
 
-    info!("[AI Plugin]: did remove plugin {:?}", id);
+  /// Kills the processes of `executable` left behind by previous runs of the application, e.g.
+  /// after it crashed, as they keep the persist directory locked. Only orphans, whose
+  /// application exited, are killed: the plugins of the current process and of other running
+  /// applications are kept, as are the processes of other executables with the same file name.
+  ///
+  /// When `persist_directory` is locked by another running application, nothing is killed. A
+  /// lock left by an exited one is removed.
+  pub async fn reap_orphans(
+    &self,
+    executable: &Path,
+    persist_directory: Option<&Path>,
+  ) -> Result<OrphanReport, PluginError> {
+    let executable = executable.to_path_buf();
+    let persist_directory = persist_directory.map(Path::to_path_buf);
+    tokio::task::spawn_blocking(move || reap_orphans(&executable, persist_directory.as_deref()))
+      .await
+      .map_err(|err| PluginError::Internal(err.into()))?
+  }
+
+  /// Records the current process as the user of `persist_directory` in its lock file, see
+  /// [Self::reap_orphans]. The lock is removed by [Self::unlock_persist_directory] or
+  /// [Self::shutdown_all].
+  pub fn lock_persist_directory(&self, persist_directory: &Path) -> Result<(), PluginError> {
+    write_lock_file(persist_directory)?;
+    self
+      .locked_directories
+      .lock()
+      .insert(persist_directory.to_path_buf());
     Ok(())
   }
 
+  /// Removes the lock file written by [Self::lock_persist_directory], once no plugin uses
+  /// `persist_directory` anymore.
+  pub fn unlock_persist_directory(&self, persist_directory: &Path) {
+    self.locked_directories.lock().remove(persist_directory);
+    if let Err(err) = remove_lock_file(persist_directory) {
+      warn!(
+        "[AI Plugin] failed to remove the lock of {:?}: {}",
+        persist_directory, err
+      );
+    }
+  }
+
+  fn unlock_all_persist_directories(&self) {
+    let directories = std::mem::take(&mut *self.locked_directories.lock());
+    for directory in directories {
+      self.unlock_persist_directory(&directory);
+    }
+  }
+
+  /// Shuts down every plugin, typically when the application exits.
+  ///
+  /// Outstanding requests and streams are cancelled, each plugin is asked to shut down, and any
+  /// process still alive after `timeout` is killed. The persist directories are unlocked once
+  /// the processes are gone.
+  #[instrument(skip(self))]
+  pub async fn shutdown_all(&self, timeout: Duration) -> ShutdownReport {
+    let plugins = std::mem::take(&mut self.state.lock().plugins)
+      .into_values()
+      .collect::<Vec<_>>();
+    self.running_plugins.write().await.clear();
+    if plugins.is_empty() {
+      self.unlock_all_persist_directories();
+      return ShutdownReport::default();
+    }
+
+    info!("[AI Plugin] shutting down {} plugin(s)", plugins.len());
+    let cancel_plugins = plugins.clone();
+    let _ = tokio::task::spawn_blocking(move || {
+      for plugin in cancel_plugins {
+        plugin.cancel_pending_requests();
+        plugin.request_shutdown();
+      }
+    })
+    .await;
+
+    let deadline = Instant::now() + timeout;
+    let mut report = ShutdownReport::default();
+    let mut alive = plugins;
+    loop {
+      let (exited, remaining): (Vec<_>, Vec<_>) =
+        alive.into_iter().partition(|plugin| plugin.has_exited());
+      report.exited.extend(exited.iter().map(|plugin| plugin.id));
+      alive = remaining;
+      if alive.is_empty() || Instant::now() >= deadline {
+        break;
+      }
+      tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+
+    let killed = tokio::task::spawn_blocking(move || {
+      alive
+        .into_iter()
+        .map(|plugin| {
+          warn!("[AI Plugin] force killing plugin: {}", plugin);
+          if let Err(err) = plugin.kill() {
+            error!(
+              "[AI Plugin] failed to kill plugin {:?}: {:?}",
+              plugin.id, err
+            );
+          }
+          plugin.id
+        })
+        .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+    report.killed = killed;
+    self.unlock_all_persist_directories();
+
+    info!("[AI Plugin] shutdown report: {:?}", report);
+    report
+  }
+
   pub async fn init_plugin(
     &self,
     id: PluginId,
     init_params: Value,
   ) -> Result<Arc<Plugin>, PluginError> {
-    trace!("init plugin: {:?}, {:?}", id, init_params);
+    trace!("init plugin: {:?}, {:?}", id, redact_secrets(&init_params));
     if self.operating_system.is_not_desktop() {
       return Err(PluginError::Internal(anyhow!(
         "plugin not supported on this platform"
@@ -125,8 +351,24 @@ impl PluginManager {
       .await?
       .upgrade()
       .ok_or_else(|| PluginError::PluginNotConnected)?;
-    plugin.initialize(init_params)?;
-    Ok(plugin.clone())
+    // Initializing waits for the plugin to answer, which must not block the runtime.
+    let initialized = {
+      let plugin = plugin.clone();
+      tokio::task::spawn_blocking(move || plugin.initialize(init_params))
+        .await
+        .map_err(|err| PluginError::Internal(anyhow!("initialize task failed: {}", err)))?
+    };
+    if let Err(err) = initialized {
+      // Lets the caller create the plugin again.
+      error!(
+        "[AI Plugin] failed to initialize plugin {:?}: {:?}",
+        id, err
+      );
+      drop(plugin);
+      self.remove_plugin(id).await?;
+      return Err(err);
+    }
+    Ok(plugin)
   }
 
   pub async fn send_request<P: ResponseParser>(
@@ -161,8 +403,27 @@ impl PluginManager {
   }
 }
 
+impl Drop for PluginManager {
+  /// Last resort for callers that never ran [PluginManager::shutdown_all]: kill the remaining
+  /// plugin processes without waiting for them to exit gracefully.
+  fn drop(&mut self) {
+    let state = self.state.lock();
+    for plugin in state.plugins.values() {
+      if !plugin.has_exited() {
+        if let Err(err) = plugin.process.lock().kill() {
+          error!(
+            "[AI Plugin] failed to kill plugin {:?}: {:?}",
+            plugin.id, err
+          );
+        }
+      }
+    }
+  }
+}
+
 pub struct PluginState {
-  plugins: Vec<Arc<Plugin>>,
+  /// Connected plugins by id, looked up by every request.
+  plugins: HashMap<PluginId, Arc<Plugin>>,
 }
 
 impl PluginState {
@@ -170,7 +431,7 @@ impl PluginState {
     match plugin {
       Ok(plugin) => {
         info!("[RPC] {} connected", plugin);
-        self.plugins.push(Arc::new(plugin));
+        self.plugins.insert(plugin.id, Arc::new(plugin));
       },
       Err(err) => {
         error!("plugin failed to connect: {:?}", err);
@@ -178,6 +439,11 @@ impl PluginState {
     }
   }
 
+  /// Removes the plugin `id` without asking it to shut down.
+  fn take_plugin(&mut self, id: PluginId) -> Option<Arc<Plugin>> {
+    self.plugins.remove(&id)
+  }
+
   pub fn disconnect_plugin(
     &mut self,
     id: PluginId,
@@ -187,15 +453,9 @@ impl PluginState {
       error!("[RPC] plugin {:?} exited with result {:?}", id, err)
     }
 
-    let running_idx = self.plugins.iter().position(|p| p.id == id);
-    match running_idx {
-      Some(idx) => {
-        let plugin = self.plugins.remove(idx);
-        plugin.shutdown();
-        Some(plugin)
-      },
-      None => None,
-    }
+    let plugin = self.plugins.remove(&id)?;
+    plugin.shutdown();
+    Some(plugin)
   }
 }
 
@@ -221,6 +481,9 @@ impl WeakPluginState {
 }
 
 impl Handler for WeakPluginState {
+  /// Requests from the plugin carry `plugin_id` at the top level. A plain string command may also
+  /// arrive in the wrapped form `{ "cmd": "...", "params": { "plugin_id": ... } }`, which only
+  /// plugins that produce `PluginCommand::new_wrapped` payloads will send.
   type Request = PluginCommand<String>;
 
   fn handle_request(
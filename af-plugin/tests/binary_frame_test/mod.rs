@@ -0,0 +1,183 @@
+use af_plugin::core::binary::{write_binary_frame, BinaryFrame, BINARY_FRAME_SENTINEL};
+use af_plugin::core::parser::{Frame, MessageReader};
+use af_plugin::core::plugin::{Peer, PluginId, RpcCtx, RunningState};
+use af_plugin::core::rpc_loop::{Handler, RpcLoop};
+use af_plugin::core::rpc_peer::{CloneableCallback, PluginCommand, ResponsePayload};
+use af_plugin::error::{PluginError, RemoteError};
+use parking_lot::Mutex;
+use serde_json::json;
+use std::io::{BufReader, Cursor, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::watch;
+
+struct NoopHandler;
+
+impl Handler for NoopHandler {
+  type Request = PluginCommand<String>;
+
+  fn handle_request(
+    &mut self,
+    _ctx: &RpcCtx,
+    _rpc: Self::Request,
+  ) -> Result<ResponsePayload, RemoteError> {
+    Ok(ResponsePayload::empty_json())
+  }
+}
+
+/// What the host received from the plugin, in order.
+#[derive(Debug, PartialEq)]
+enum Received {
+  Json(serde_json::Value),
+  Binary(BinaryFrame),
+  Error(String),
+}
+
+/// Runs a host loop reading what `write_plugin` writes to the other end of a pipe, with a
+/// request pending and a binary handler registered, until the plugin closes its end.
+fn run_host(write_plugin: impl FnOnce(&mut UnixStream) + Send + 'static) -> Vec<Received> {
+  let (host, mut plugin) = UnixStream::pair().unwrap();
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  let mut looper = RpcLoop::new(Vec::new(), Arc::new(running_state.into())).unwrap();
+  let peer = looper.get_raw_peer();
+  let received = Arc::new(Mutex::new(vec![]));
+
+  let frames = received.clone();
+  peer.set_binary_handler(Arc::new(move |frame| {
+    frames.lock().push(Received::Binary(frame));
+  }));
+  let responses = received.clone();
+  peer.stream_rpc_request(
+    "dictate",
+    &json!({}),
+    CloneableCallback::new(move |result: Result<serde_json::Value, PluginError>| {
+      responses.lock().push(match result {
+        Ok(value) => Received::Json(value),
+        Err(err) => Received::Error(err.to_string()),
+      });
+    }),
+  );
+
+  let writer = thread::spawn(move || {
+    write_plugin(&mut plugin);
+    // Dropping the stream closes the pipe.
+  });
+  let result = looper.mainloop(
+    "test",
+    &PluginId::from(1),
+    || BufReader::new(host),
+    &mut NoopHandler,
+  );
+  writer.join().unwrap();
+  assert!(result.is_ok(), "{:?}", result);
+
+  let received = std::mem::take(&mut *received.lock());
+  received
+}
+
+fn stream_frame(data: serde_json::Value) -> String {
+  format!(
+    "{}\n",
+    json!({ "id": 0, "result": { "stream": { "has_more": true, "data": data } } })
+  )
+}
+
+#[test]
+fn interleaved_json_and_binary_frames_test() {
+  let received = run_host(|plugin| {
+    plugin
+      .write_all(stream_frame(json!("hello")).as_bytes())
+      .unwrap();
+    // Payloads may hold newlines and the sentinel itself.
+    write_binary_frame(plugin, 7, &[1, b'\n', BINARY_FRAME_SENTINEL, 255]).unwrap();
+    write_binary_frame(plugin, 8, &[]).unwrap();
+    plugin
+      .write_all(stream_frame(json!("world")).as_bytes())
+      .unwrap();
+    write_binary_frame(plugin, 7, &[42; 10_000]).unwrap();
+  });
+
+  assert_eq!(received.len(), 6, "{:?}", received);
+  assert_eq!(received[0], Received::Json(json!("hello")));
+  assert_eq!(
+    received[1],
+    Received::Binary(BinaryFrame {
+      method_id: 7,
+      payload: vec![1, b'\n', BINARY_FRAME_SENTINEL, 255],
+    })
+  );
+  assert_eq!(
+    received[2],
+    Received::Binary(BinaryFrame {
+      method_id: 8,
+      payload: vec![],
+    })
+  );
+  assert_eq!(received[3], Received::Json(json!("world")));
+  assert_eq!(
+    received[4],
+    Received::Binary(BinaryFrame {
+      method_id: 7,
+      payload: vec![42; 10_000],
+    })
+  );
+  // The plugin closed the pipe with the stream still open.
+  assert_eq!(
+    received[5],
+    Received::Error(PluginError::PeerDisconnect.to_string())
+  );
+}
+
+#[test]
+fn torn_binary_frame_disconnects_test() {
+  let received = run_host(|plugin| {
+    plugin
+      .write_all(stream_frame(json!("hello")).as_bytes())
+      .unwrap();
+    let mut frame = vec![];
+    write_binary_frame(&mut frame, 7, &[42; 100]).unwrap();
+    // The plugin dies in the middle of the frame.
+    plugin.write_all(&frame[..20]).unwrap();
+  });
+
+  assert_eq!(
+    received,
+    vec![
+      Received::Json(json!("hello")),
+      Received::Error(PluginError::PeerDisconnect.to_string()),
+    ]
+  );
+}
+
+#[test]
+fn sentinel_is_text_without_binary_frames_test() {
+  let mut input = vec![BINARY_FRAME_SENTINEL];
+  input.extend_from_slice(b"not a frame\n{\"id\":1,\"result\":1}\n");
+  let mut reader = MessageReader::default();
+  let mut stream = Cursor::new(input);
+
+  // Plugins that never negotiated binary frames are read as JSON lines only.
+  match reader.next_frame(&mut stream, false).unwrap() {
+    Some(Frame::Json(object)) => assert!(object.0.get("message").is_some()),
+    frame => panic!("unexpected frame: {:?}", frame),
+  }
+  match reader.next_frame(&mut stream, false).unwrap() {
+    Some(Frame::Json(object)) => assert_eq!(object.get_id(), Some(1)),
+    frame => panic!("unexpected frame: {:?}", frame),
+  }
+}
+
+#[test]
+fn send_binary_requires_negotiation_test() {
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  let looper = RpcLoop::new(Vec::new(), Arc::new(running_state.into())).unwrap();
+  let peer = looper.get_raw_peer();
+
+  assert!(matches!(
+    peer.send_binary(1, b"audio"),
+    Err(PluginError::BinaryFramesNotNegotiated)
+  ));
+  peer.accept_binary_frames();
+  peer.send_binary(1, b"audio").unwrap();
+}
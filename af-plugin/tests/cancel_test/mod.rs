@@ -0,0 +1,65 @@
+use af_plugin::core::plugin::{Peer, PluginId, RpcCtx, RunningState};
+use af_plugin::core::rpc_loop::{Handler, RpcLoop};
+use af_plugin::core::rpc_peer::{PluginCommand, ResponsePayload};
+use af_plugin::error::RemoteError;
+use serde_json::json;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+struct NoopHandler;
+
+impl Handler for NoopHandler {
+  type Request = PluginCommand<String>;
+
+  fn handle_request(
+    &mut self,
+    _ctx: &RpcCtx,
+    _rpc: Self::Request,
+  ) -> Result<ResponsePayload, RemoteError> {
+    Ok(ResponsePayload::empty_json())
+  }
+}
+
+#[test]
+fn cancelled_request_ignores_late_response_test() {
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  let mut looper = RpcLoop::new(Vec::new(), Arc::new(running_state.into())).unwrap();
+  let peer = looper.get_raw_peer();
+
+  let cancelled = Arc::new(AtomicBool::new(false));
+  let answered = Arc::new(AtomicBool::new(false));
+  let id = {
+    let cancelled = cancelled.clone();
+    peer.async_send_rpc_request(
+      "quick_complete",
+      &json!({}),
+      Box::new(move |_| cancelled.store(true, Ordering::SeqCst)),
+    )
+  };
+  let next_id = {
+    let answered = answered.clone();
+    peer.async_send_rpc_request(
+      "quick_complete",
+      &json!({}),
+      Box::new(move |_| answered.store(true, Ordering::SeqCst)),
+    )
+  };
+  assert_ne!(id, next_id);
+  peer.cancel_request(id);
+
+  let responses = format!(
+    "{}\n{}\n",
+    json!({ "id": id, "result": "late" }),
+    json!({ "id": next_id, "result": "on time" })
+  );
+  let _ = looper.mainloop(
+    "test",
+    &PluginId::from(1),
+    || Cursor::new(responses.into_bytes()),
+    &mut NoopHandler,
+  );
+  assert!(!cancelled.load(Ordering::SeqCst));
+  assert!(answered.load(Ordering::SeqCst));
+}
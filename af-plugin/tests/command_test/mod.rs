@@ -0,0 +1,89 @@
+use af_plugin::core::plugin::PluginId;
+use af_plugin::core::rpc_peer::PluginCommand;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt::Debug;
+
+fn round_trip<T>(command: PluginCommand<T>) -> (Value, PluginCommand<T>)
+where
+  T: Serialize + DeserializeOwned,
+{
+  let value = serde_json::to_value(&command).unwrap();
+  let decoded = serde_json::from_value::<PluginCommand<T>>(value.clone()).unwrap();
+  (value, decoded)
+}
+
+fn assert_round_trip<T>(command: PluginCommand<T>) -> Value
+where
+  T: Serialize + DeserializeOwned + Clone + PartialEq + Debug,
+{
+  let plugin_id = command.plugin_id;
+  let cmd = command.cmd.clone();
+  let (value, decoded) = round_trip(command);
+  assert_eq!(decoded.plugin_id, plugin_id);
+  assert_eq!(decoded.cmd, cmd);
+  value
+}
+
+#[test]
+fn object_with_params_test() {
+  let cmd = json!({ "method": "answer", "params": { "chat_id": "1" } });
+  let value = assert_round_trip(PluginCommand::new(PluginId::from(3), cmd.clone()));
+  assert_eq!(value["params"]["plugin_id"], json!(3));
+  assert_eq!(value["params"]["chat_id"], json!("1"));
+
+  let value = assert_round_trip(PluginCommand::new_wrapped(PluginId::from(3), cmd));
+  assert!(value.get("cmd").is_none());
+}
+
+#[test]
+fn object_without_params_test() {
+  let cmd = json!({ "method": "ping" });
+  let value = assert_round_trip(PluginCommand::new(PluginId::from(4), cmd.clone()));
+  assert_eq!(value["params"]["plugin_id"], json!(4));
+
+  let value = assert_round_trip(PluginCommand::new_wrapped(PluginId::from(4), cmd.clone()));
+  assert_eq!(value["cmd"], cmd);
+  assert_eq!(value["params"]["plugin_id"], json!(4));
+}
+
+#[test]
+fn string_command_test() {
+  let command = PluginCommand::new(PluginId::from(5), "shutdown".to_string());
+  assert!(serde_json::to_value(&command).is_err());
+
+  let command = PluginCommand::new_wrapped(PluginId::from(5), "shutdown".to_string());
+  let value = assert_round_trip(command);
+  assert_eq!(
+    value,
+    json!({ "cmd": "shutdown", "params": { "plugin_id": 5 } })
+  );
+}
+
+#[test]
+fn numeric_command_test() {
+  let command = PluginCommand::new(PluginId::from(6), 42_u64);
+  assert!(serde_json::to_value(&command).is_err());
+
+  let (value, decoded) = round_trip(PluginCommand::new_wrapped(PluginId::from(6), 42_u64));
+  assert_eq!(value["cmd"], json!(42));
+  assert_eq!(decoded.plugin_id, PluginId::from(6));
+  assert_eq!(decoded.cmd, 42);
+  assert!(decoded.is_wrapped());
+}
+
+#[test]
+fn non_object_params_test() {
+  let cmd = json!({ "method": "answer", "params": ["a", "b"] });
+  assert!(serde_json::to_value(PluginCommand::new(PluginId::from(7), cmd.clone())).is_err());
+  assert_round_trip(PluginCommand::new_wrapped(PluginId::from(7), cmd));
+}
+
+#[test]
+fn top_level_plugin_id_test() {
+  let value = json!({ "plugin_id": 8, "method": "log" });
+  let decoded = serde_json::from_value::<PluginCommand<Value>>(value.clone()).unwrap();
+  assert_eq!(decoded.plugin_id, PluginId::from(8));
+  assert_eq!(decoded.cmd, value);
+}
@@ -0,0 +1,143 @@
+use af_plugin::core::journal::{read_crash_report, CrashJournal, JournalRecord};
+use af_plugin::core::plugin::{Peer, PluginId, RpcCtx, RunningState};
+use af_plugin::core::rpc_loop::{Handler, RpcLoop};
+use af_plugin::core::rpc_peer::{PluginCommand, ResponsePayload};
+use af_plugin::error::RemoteError;
+use af_plugin::util::redact_secrets;
+use serde_json::json;
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+struct NoopHandler;
+
+impl Handler for NoopHandler {
+  type Request = PluginCommand<String>;
+
+  fn handle_request(
+    &mut self,
+    _ctx: &RpcCtx,
+    _rpc: Self::Request,
+  ) -> Result<ResponsePayload, RemoteError> {
+    Ok(ResponsePayload::empty_json())
+  }
+}
+
+#[test]
+fn crash_report_contains_in_flight_request_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let journal = CrashJournal::open(dir.path()).unwrap();
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  let mut looper = RpcLoop::with_journal(
+    Vec::new(),
+    Arc::new(running_state.into()),
+    Some(journal.clone()),
+  )
+  .unwrap();
+
+  let peer = looper.get_raw_peer();
+  // Operations are sent as `handle` requests, like AIPluginOperation does.
+  peer.async_send_rpc_request(
+    "handle",
+    &json!({ "method": "answer", "params": { "chat_id": "1" } }),
+    Box::new(|_| {}),
+  );
+
+  // An empty stdout means the plugin went away while the request was in flight.
+  let plugin_id = PluginId::from(1);
+  let _ = looper.mainloop(
+    "test",
+    &plugin_id,
+    || Cursor::new(Vec::new()),
+    &mut NoopHandler,
+  );
+  journal.flush();
+
+  let report = read_crash_report(dir.path(), 10).unwrap().unwrap();
+  assert_eq!(report.requests.len(), 1);
+  match &report.requests[0].record {
+    JournalRecord::Request {
+      id, method, params, ..
+    } => {
+      assert_eq!(*id, 0);
+      assert_eq!(method, "answer");
+      assert!(params.contains("chat_id"));
+    },
+    record => panic!("unexpected record: {:?}", record),
+  }
+  match &report.disconnect.record {
+    JournalRecord::Disconnect {
+      pending_ids,
+      to_state,
+      ..
+    } => {
+      assert_eq!(pending_ids, &vec![0]);
+      assert!(to_state.contains("UnexpectedStop"));
+    },
+    record => panic!("unexpected record: {:?}", record),
+  }
+}
+
+#[test]
+fn no_crash_report_without_disconnect_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let journal = CrashJournal::open(dir.path()).unwrap();
+  journal.record_request(0, "answer", &json!({}));
+  journal.flush();
+  assert!(read_crash_report(dir.path(), 10).unwrap().is_none());
+}
+
+#[test]
+fn journal_redacts_secrets_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let journal = CrashJournal::open(dir.path()).unwrap();
+  let params = json!({
+    "server_url": "https://ollama.example.com",
+    "auth": { "type": "bearer", "token": "secret-token" },
+  });
+  journal.record_request(0, "initialize", &params);
+  journal.record_disconnect(vec![0], "Running".to_string(), "UnexpectedStop".to_string());
+  journal.flush();
+
+  let report = read_crash_report(dir.path(), 10).unwrap().unwrap();
+  match &report.requests[0].record {
+    JournalRecord::Request { params, .. } => {
+      assert!(!params.contains("secret-token"), "{}", params);
+      assert!(params.contains("***"), "{}", params);
+      assert!(params.contains("https://ollama.example.com"), "{}", params);
+    },
+    record => panic!("unexpected record: {:?}", record),
+  }
+
+  assert_eq!(
+    redact_secrets(&params),
+    json!({
+      "server_url": "https://ollama.example.com",
+      "auth": { "type": "bearer", "token": "***" },
+    })
+  );
+}
+
+#[test]
+fn journal_keeps_trace_id_of_long_request_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let journal = CrashJournal::open(dir.path()).unwrap();
+  let params = json!({
+    "method": "stream_answer_v2",
+    "params": { "data": { "content": "a".repeat(1024) }, "trace_id": "trace-1" },
+  });
+  journal.record_request(0, "handle", &params);
+  journal.record_disconnect(vec![0], "Running".to_string(), "UnexpectedStop".to_string());
+  journal.flush();
+
+  let report = read_crash_report(dir.path(), 10).unwrap().unwrap();
+  match &report.requests[0].record {
+    JournalRecord::Request {
+      params, trace_id, ..
+    } => {
+      assert!(!params.contains("trace-1"), "{}", params);
+      assert_eq!(trace_id.as_deref(), Some("trace-1"));
+    },
+    record => panic!("unexpected record: {:?}", record),
+  }
+}
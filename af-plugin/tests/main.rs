@@ -0,0 +1,17 @@
+#[cfg(unix)]
+mod binary_frame_test;
+mod cancel_test;
+mod command_test;
+mod journal_test;
+mod manager_test;
+#[cfg(target_os = "linux")]
+mod orphan_test;
+mod parser_test;
+mod path_test;
+mod remote_error_test;
+#[cfg(unix)]
+mod resource_usage_test;
+mod state_machine_test;
+mod stream_error_test;
+mod stream_test;
+mod writer_test;
@@ -0,0 +1,58 @@
+use af_plugin::core::plugin::{PluginConfig, RunningState};
+use af_plugin::core::transport::TransportKind;
+use af_plugin::error::PluginError;
+use af_plugin::manager::PluginManager;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[tokio::test]
+async fn failed_spawn_does_not_leave_plugin_running_test() {
+  let plugin_manager = PluginManager::new();
+  let dir = tempfile::tempdir().unwrap();
+  let config = || PluginConfig {
+    name: "missing_plugin".to_string(),
+    exec_path: dir.path().join("af_missing_plugin"),
+    exec_command: "af_missing_plugin_command".to_string(),
+    crash_journal_dir: None,
+    ..Default::default()
+  };
+
+  for _ in 0..2 {
+    let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+    let err = plugin_manager
+      .create_plugin(config(), Arc::new(running_state.into()))
+      .await
+      .unwrap_err();
+    assert!(matches!(err, PluginError::Io(_)), "{:?}", err);
+    assert!(!plugin_manager.is_plugin_running("missing_plugin").await);
+  }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn named_pipe_removed_when_plugin_never_connects_test() {
+  let plugin_manager = PluginManager::new();
+  let config = PluginConfig {
+    name: "silent_plugin".to_string(),
+    exec_path: "/bin/sh".into(),
+    exec_command: String::new(),
+    transport: TransportKind::NamedPipe,
+    ..Default::default()
+  };
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  // `sh --rpc-pipe <path>` fails on the unknown option, without connecting.
+  let err = plugin_manager
+    .create_plugin(config, Arc::new(running_state.into()))
+    .await
+    .unwrap_err();
+  assert!(matches!(err, PluginError::Io(_)), "{:?}", err);
+  assert!(!plugin_manager.is_plugin_running("silent_plugin").await);
+
+  let prefix = format!("af-plugin-{}-", std::process::id());
+  let leftovers = std::fs::read_dir(std::env::temp_dir())
+    .unwrap()
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+    .count();
+  assert_eq!(leftovers, 0);
+}
@@ -0,0 +1,199 @@
+use af_plugin::core::orphan::{read_lock_state, write_lock_file, LockState, LOCK_FILE_NAME};
+use af_plugin::manager::PluginManager;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Whether `pid` is still running. Killed orphans are reparented to init, which may not reap
+/// them in containers, so zombies count as exited.
+fn is_running(pid: u32) -> bool {
+  match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+    Ok(stat) => !stat[stat.rfind(')').unwrap() + 1..]
+      .trim_start()
+      .starts_with('Z'),
+    Err(_) => false,
+  }
+}
+
+/// Starts `exec_path` in the background of a shell that exits right away, so the process is not
+/// a child of the test, like a plugin left behind by a crashed application.
+fn spawn_orphan(exec_path: &Path) -> u32 {
+  let output = Command::new("sh")
+    .arg("-c")
+    .arg(format!(
+      "'{}' 30 >/dev/null 2>&1 & echo $!",
+      exec_path.display()
+    ))
+    .output()
+    .unwrap();
+  String::from_utf8(output.stdout)
+    .unwrap()
+    .trim()
+    .parse()
+    .unwrap()
+}
+
+#[tokio::test]
+async fn reap_orphaned_plugin_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let name = format!("af_orphan_test_{}", std::process::id());
+  let exec_path = dir.path().join(&name);
+  std::fs::copy("/bin/sleep", &exec_path).unwrap();
+
+  let orphan = spawn_orphan(&exec_path);
+  let mut child = Command::new(&exec_path)
+    .arg("30")
+    .stdout(Stdio::null())
+    .spawn()
+    .unwrap();
+  let unrelated = spawn_orphan(Path::new("/bin/sleep"));
+  // Another installation of an executable with the same name.
+  let other_dir = tempfile::tempdir().unwrap();
+  let other_exec_path = other_dir.path().join(&name);
+  std::fs::copy("/bin/sleep", &other_exec_path).unwrap();
+  let other = spawn_orphan(&other_exec_path);
+
+  let report = PluginManager::new()
+    .reap_orphans(&exec_path, None)
+    .await
+    .unwrap();
+  assert_eq!(report.killed, vec![orphan]);
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert!(!is_running(orphan));
+  // Processes started by the current one, and other executables, are kept.
+  assert!(child.try_wait().unwrap().is_none());
+  assert!(is_running(unrelated));
+  assert!(is_running(other));
+
+  child.kill().unwrap();
+  let _ = child.wait();
+  Command::new("kill")
+    .args(["-9", &unrelated.to_string(), &other.to_string()])
+    .status()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn keep_plugin_of_running_parent_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let exec_path = dir
+    .path()
+    .join(format!("af_parent_test_{}", std::process::id()));
+  std::fs::copy("/bin/sleep", &exec_path).unwrap();
+
+  // The shell stands for another running application that started the plugin.
+  let mut application = Command::new("sh")
+    .arg("-c")
+    .arg(format!(
+      "'{}' 30 >/dev/null 2>&1 & echo $!; wait",
+      exec_path.display()
+    ))
+    .stdout(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut line = String::new();
+  BufReader::new(application.stdout.take().unwrap())
+    .read_line(&mut line)
+    .unwrap();
+  let plugin: u32 = line.trim().parse().unwrap();
+
+  let report = PluginManager::new()
+    .reap_orphans(&exec_path, None)
+    .await
+    .unwrap();
+  assert!(report.killed.is_empty());
+  assert!(is_running(plugin));
+
+  Command::new("kill")
+    .args(["-9", &plugin.to_string()])
+    .status()
+    .unwrap();
+  application.wait().unwrap();
+}
+
+#[tokio::test]
+async fn keep_plugins_of_running_application_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let name = format!("af_locked_test_{}", std::process::id());
+  let exec_path = dir.path().join(&name);
+  std::fs::copy("/bin/sleep", &exec_path).unwrap();
+  let orphan = spawn_orphan(&exec_path);
+
+  // Another application, still running, uses the persist directory.
+  let persist_directory = dir.path().join("vectors");
+  std::fs::create_dir_all(&persist_directory).unwrap();
+  let mut application = Command::new("sleep").arg("30").spawn().unwrap();
+  std::fs::write(
+    persist_directory.join(LOCK_FILE_NAME),
+    application.id().to_string(),
+  )
+  .unwrap();
+
+  let report = PluginManager::new()
+    .reap_orphans(&exec_path, Some(&persist_directory))
+    .await
+    .unwrap();
+  assert_eq!(report.locked_by, Some(application.id()));
+  assert!(report.killed.is_empty());
+  assert!(is_running(orphan));
+
+  // Once it exited, its lock is stale and the orphan is reaped.
+  application.kill().unwrap();
+  application.wait().unwrap();
+  let report = PluginManager::new()
+    .reap_orphans(&exec_path, Some(&persist_directory))
+    .await
+    .unwrap();
+  assert_eq!(report.stale_lock, Some(application.id()));
+  assert_eq!(report.killed, vec![orphan]);
+  assert_eq!(
+    read_lock_state(&persist_directory).unwrap(),
+    LockState::Free
+  );
+}
+
+#[test]
+fn lock_file_test() {
+  let dir = tempfile::tempdir().unwrap();
+  assert_eq!(read_lock_state(dir.path()).unwrap(), LockState::Free);
+  write_lock_file(dir.path()).unwrap();
+  assert_eq!(read_lock_state(dir.path()).unwrap(), LockState::Owned);
+
+  let mut exited = Command::new("true").spawn().unwrap();
+  exited.wait().unwrap();
+  std::fs::write(dir.path().join(LOCK_FILE_NAME), exited.id().to_string()).unwrap();
+  assert_eq!(
+    read_lock_state(dir.path()).unwrap(),
+    LockState::Stale(exited.id())
+  );
+}
+
+#[tokio::test]
+async fn shutdown_removes_lock_file_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let manager = PluginManager::new();
+  manager.lock_persist_directory(dir.path()).unwrap();
+  assert_eq!(read_lock_state(dir.path()).unwrap(), LockState::Owned);
+  manager.unlock_persist_directory(dir.path());
+  assert_eq!(read_lock_state(dir.path()).unwrap(), LockState::Free);
+
+  manager.lock_persist_directory(dir.path()).unwrap();
+  manager.shutdown_all(Duration::from_secs(1)).await;
+  assert_eq!(read_lock_state(dir.path()).unwrap(), LockState::Free);
+
+  // The lock of another application is left alone.
+  let mut application = Command::new("sleep").arg("30").spawn().unwrap();
+  std::fs::write(
+    dir.path().join(LOCK_FILE_NAME),
+    application.id().to_string(),
+  )
+  .unwrap();
+  manager.unlock_persist_directory(dir.path());
+  assert_eq!(
+    read_lock_state(dir.path()).unwrap(),
+    LockState::Held(application.id())
+  );
+  application.kill().unwrap();
+  application.wait().unwrap();
+}
@@ -0,0 +1,54 @@
+use af_plugin::core::parser::MessageReader;
+use af_plugin::error::ReadError;
+use serde_json::json;
+use std::io::Cursor;
+
+#[test]
+fn read_bom_and_crlf_frames_test() {
+  let input = "\u{feff}{\"id\":1,\"result\":{\"a\":1}}\r\n{\"method\":\"log\",\"params\":{}}\r\n";
+  let mut reader = MessageReader::default();
+  let mut stream = Cursor::new(input.as_bytes());
+
+  let first = reader.next(&mut stream).unwrap().unwrap();
+  assert!(first.is_response());
+  assert_eq!(first.get_id(), Some(1));
+  assert_eq!(first.0, json!({ "id": 1, "result": { "a": 1 } }));
+
+  let second = reader.next(&mut stream).unwrap().unwrap();
+  assert_eq!(second.get_method(), Some("log"));
+
+  assert!(matches!(
+    reader.next(&mut stream),
+    Err(ReadError::Disconnect(_))
+  ));
+}
+
+#[test]
+fn skip_blank_lines_test() {
+  let input = "\r\n{\"id\":1,\"result\":1}\n\n  \r\n\t\n{\"id\":2,\"result\":2}\r\n\r\n";
+  let mut reader = MessageReader::default();
+  let mut stream = Cursor::new(input.as_bytes());
+
+  for id in [1, 2] {
+    let object = reader.next(&mut stream).unwrap().unwrap();
+    assert_eq!(object.get_id(), Some(id));
+  }
+  // Only the end of the input is a disconnect, not the blank lines before it.
+  assert!(matches!(
+    reader.next(&mut stream),
+    Err(ReadError::Disconnect(_))
+  ));
+}
+
+#[test]
+fn bom_only_stripped_from_first_line_test() {
+  let input = "{\"id\":1,\"result\":1}\n\u{feff}{\"id\":2,\"result\":2}\n";
+  let mut reader = MessageReader::default();
+  let mut stream = Cursor::new(input.as_bytes());
+
+  assert_eq!(reader.next(&mut stream).unwrap().unwrap().get_id(), Some(1));
+  // A mark in the middle of the stream is not a byte order mark, the line is logged.
+  let object = reader.next(&mut stream).unwrap().unwrap();
+  assert!(object.get_id().is_none());
+  assert!(object.0.get("message").is_some());
+}
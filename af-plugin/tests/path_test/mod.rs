@@ -0,0 +1,55 @@
+use af_plugin::core::path::{check_executable, PluginReadiness};
+use af_plugin::error::PluginError;
+
+#[test]
+fn missing_executable_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let exec_path = dir.path().join("af_ollama_plugin");
+  let readiness = check_executable(&exec_path, "af_ollama_plugin_that_does_not_exist");
+  assert_eq!(readiness, PluginReadiness::NotFound(exec_path.clone()));
+  assert!(matches!(
+    readiness.into_result(),
+    Err(PluginError::ExecutableNotFound(path)) if path == exec_path
+  ));
+
+  // A directory is found, but can't be started.
+  let readiness = check_executable(dir.path(), "");
+  assert_eq!(
+    readiness,
+    PluginReadiness::NotExecutable(dir.path().to_path_buf())
+  );
+}
+
+#[cfg(unix)]
+#[test]
+fn non_executable_file_test() {
+  use std::os::unix::fs::PermissionsExt;
+
+  let dir = tempfile::tempdir().unwrap();
+  let exec_path = dir.path().join("af_ollama_plugin");
+  std::fs::write(&exec_path, "#!/bin/sh\n").unwrap();
+  std::fs::set_permissions(&exec_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+  let readiness = check_executable(&exec_path, "");
+  assert!(!readiness.is_ready());
+  assert!(matches!(
+    readiness.into_result(),
+    Err(PluginError::ExecutableNotExecutable(path)) if path == exec_path
+  ));
+
+  std::fs::set_permissions(&exec_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+  assert_eq!(
+    check_executable(&exec_path, ""),
+    PluginReadiness::Ready(exec_path.clone())
+  );
+}
+
+#[cfg(unix)]
+#[test]
+fn command_in_path_test() {
+  let dir = tempfile::tempdir().unwrap();
+  let readiness = check_executable(&dir.path().join("missing"), "sh");
+  match readiness {
+    PluginReadiness::Ready(path) => assert!(path.ends_with("sh"), "{:?}", path),
+    readiness => panic!("{:?}", readiness),
+  }
+}
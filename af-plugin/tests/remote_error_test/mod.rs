@@ -0,0 +1,120 @@
+use af_plugin::core::parser::MessageReader;
+use af_plugin::error::{error_code, PluginError, RemoteError};
+use serde_json::json;
+use std::io::Cursor;
+
+fn remote_error(line: &str) -> RemoteError {
+  let reader = MessageReader::default();
+  let response = reader.parse(line).unwrap().into_response().unwrap();
+  match response {
+    Err(err) => err,
+    Ok(payload) => panic!("expected an error, got {}", payload),
+  }
+}
+
+#[test]
+fn typed_remote_error_test() {
+  let err = remote_error(
+    r#"{"id":1,"error":{"code":-32001,"message":"model overloaded","data":{"retry_after":5}}}"#,
+  );
+  assert!(matches!(
+    err,
+    RemoteError::ModelOverloaded { ref message, ref data }
+      if message == "model overloaded" && data == &Some(json!({ "retry_after": 5 }))
+  ));
+  assert!(err.is_retryable());
+  assert_eq!(err.code(), Some(error_code::MODEL_OVERLOADED));
+
+  let err = remote_error(r#"{"id":2,"error":{"code":-32602,"message":"chat_id is required"}}"#);
+  assert!(
+    matches!(err, RemoteError::InvalidParams { ref message, data: None } if message == "chat_id is required")
+  );
+  assert!(!err.is_retryable());
+
+  let err = remote_error(
+    r#"{"id":3,"error":{"code":-32002,"message":"prompt too long","data":{"tokens":9000,"limit":4096}}}"#,
+  );
+  assert!(matches!(err, RemoteError::ContextTooLong { .. }));
+  assert_eq!(err.data().unwrap()["limit"], 4096);
+
+  let err = remote_error(r#"{"id":4,"error":{"code":-32603,"message":"division by zero"}}"#);
+  assert!(
+    matches!(err, RemoteError::Internal { ref message, .. } if message == "division by zero")
+  );
+
+  let err = remote_error(r#"{"id":5,"error":{"code":-32601,"message":"Method not found"}}"#);
+  assert!(matches!(err, RemoteError::Custom { code: -32601, .. }));
+  assert!(err.is_method_not_found());
+
+  // Peers answering an unknown method with a standard code still name it in the message.
+  let err = remote_error(r#"{"id":5,"error":{"code":-32603,"message":"Unknown method: answer"}}"#);
+  assert!(matches!(err, RemoteError::Internal { .. }));
+  assert!(err.is_method_not_found());
+  let err =
+    remote_error(r#"{"id":5,"error":{"code":-32602,"message":"method not found: answer"}}"#);
+  assert!(matches!(err, RemoteError::InvalidParams { .. }));
+  assert!(err.is_method_not_found());
+  let err = remote_error(r#"{"id":5,"error":{"code":-32603,"message":"division by zero"}}"#);
+  assert!(!err.is_method_not_found());
+
+  // Errors that aren't JSON-RPC error objects are kept as they are.
+  let err = remote_error(r#"{"id":6,"error":"something went wrong"}"#);
+  assert!(matches!(err, RemoteError::Unknown(ref value) if value == "something went wrong"));
+  assert_eq!(err.code(), None);
+}
+
+#[test]
+fn remote_error_from_stream_test() {
+  let lines = concat!(
+    r#"{"id":7,"result":{"stream":{"has_more":true,"data":"partial"}}}"#,
+    "\n",
+    r#"{"id":7,"error":{"code":-32002,"message":"context window exceeded"}}"#,
+    "\n",
+  );
+  let mut cursor = Cursor::new(lines.as_bytes());
+  let mut reader = MessageReader::default();
+
+  let frame = reader.next(&mut cursor).unwrap().unwrap();
+  assert!(frame.into_response().unwrap().unwrap().is_stream());
+
+  let frame = reader.next(&mut cursor).unwrap().unwrap();
+  let err = PluginError::from(frame.into_response().unwrap().unwrap_err());
+  assert!(matches!(
+    err.remote_error(),
+    Some(RemoteError::ContextTooLong { message, .. }) if message == "context window exceeded"
+  ));
+}
+
+#[test]
+fn remote_error_round_trip_test() {
+  let errors = vec![
+    RemoteError::InvalidParams {
+      message: "bad".to_string(),
+      data: Some(json!({ "field": "chat_id" })),
+    },
+    RemoteError::ModelOverloaded {
+      message: "busy".to_string(),
+      data: None,
+    },
+    RemoteError::ContextTooLong {
+      message: "long".to_string(),
+      data: None,
+    },
+    RemoteError::Internal {
+      message: "oops".to_string(),
+      data: None,
+    },
+    RemoteError::Custom {
+      code: 42,
+      message: "custom".to_string(),
+      data: None,
+    },
+  ];
+  for err in errors {
+    let value = serde_json::to_value(&err).unwrap();
+    assert_eq!(value["code"], err.code().unwrap());
+    let parsed = RemoteError::from_value(value);
+    assert_eq!(parsed.to_string(), err.to_string());
+    assert_eq!(parsed.data(), err.data());
+  }
+}
@@ -0,0 +1,41 @@
+use af_plugin::core::plugin::{PluginConfig, RunningState};
+use af_plugin::manager::PluginManager;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resource_usage_of_started_plugin_test() {
+  let plugin_manager = PluginManager::new();
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  // Any long-lived process will do: cat keeps running until its stdin is closed.
+  let config = PluginConfig {
+    name: "resource_usage_test".to_string(),
+    exec_path: PathBuf::from("/bin/cat"),
+    exec_command: "cat".to_string(),
+    crash_journal_dir: None,
+    ..Default::default()
+  };
+  let plugin_id = plugin_manager
+    .create_plugin(config, Arc::new(running_state.into()))
+    .await
+    .unwrap();
+  let plugin = plugin_manager
+    .get_plugin(plugin_id)
+    .await
+    .unwrap()
+    .upgrade()
+    .unwrap();
+
+  let usage = plugin.resource_usage().unwrap();
+  assert!(usage.rss_bytes > 0);
+  assert_eq!(usage.cpu_percent, 0.0);
+
+  let usage = plugin.resource_usage().unwrap();
+  assert!(usage.rss_bytes > 0);
+  assert!(usage.cpu_percent >= 0.0);
+
+  drop(plugin);
+  plugin_manager.shutdown_all(Duration::from_secs(1)).await;
+}
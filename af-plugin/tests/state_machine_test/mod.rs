@@ -0,0 +1,135 @@
+use af_plugin::core::plugin::{PluginId, RunningState};
+use af_plugin::core::state_machine::{StateMachine, TRANSITION_HISTORY_LEN};
+
+/// Sends `states` in order and returns those the subscribers were notified of.
+fn emitted(machine: &StateMachine, states: &[RunningState]) -> Vec<RunningState> {
+  let mut rx = machine.subscribe();
+  let mut emitted = vec![];
+  for state in states {
+    machine.transition(state.clone());
+    if rx.has_changed().unwrap() {
+      emitted.push(rx.borrow_and_update().clone());
+    }
+  }
+  emitted
+}
+
+#[test]
+fn valid_transitions_test() {
+  let plugin_id = PluginId::from(1);
+  let machine = StateMachine::new(RunningState::ReadyToConnect);
+  let states = [
+    RunningState::Connecting,
+    RunningState::Connected { plugin_id },
+    RunningState::Running { plugin_id },
+    RunningState::Stopped { plugin_id },
+    // Restarted.
+    RunningState::Connecting,
+    RunningState::Running { plugin_id },
+    RunningState::UnexpectedStop {
+      plugin_id,
+      reason: None,
+    },
+  ];
+  assert_eq!(emitted(&machine, &states), states);
+
+  let history = machine.history();
+  assert_eq!(history.len(), states.len());
+  assert_eq!(history[0].from, RunningState::ReadyToConnect);
+  assert_eq!(history[0].to, RunningState::Connecting);
+  assert!(history.windows(2).all(|pair| pair[0].to == pair[1].from));
+  assert!(history.windows(2).all(|pair| pair[0].at <= pair[1].at));
+}
+
+#[test]
+fn duplicate_transitions_test() {
+  let plugin_id = PluginId::from(1);
+  let machine = StateMachine::new(RunningState::ReadyToConnect);
+  let emitted = emitted(
+    &machine,
+    &[
+      RunningState::Connecting,
+      RunningState::Connecting,
+      RunningState::Running { plugin_id },
+      // `notify_running` racing with the init task.
+      RunningState::Running { plugin_id },
+      RunningState::Stopped { plugin_id },
+      // The stop of `shutdown` followed by the one of the exiting read loop.
+      RunningState::Stopped { plugin_id },
+    ],
+  );
+  assert_eq!(
+    emitted,
+    vec![
+      RunningState::Connecting,
+      RunningState::Running { plugin_id },
+      RunningState::Stopped { plugin_id },
+    ]
+  );
+  assert_eq!(machine.history().len(), 3);
+}
+
+#[test]
+fn invalid_transitions_test() {
+  let plugin_id = PluginId::from(1);
+  let machine = StateMachine::new(RunningState::ReadyToConnect);
+  let emitted = emitted(
+    &machine,
+    &[
+      RunningState::Running { plugin_id },
+      RunningState::Connecting,
+      RunningState::Running { plugin_id },
+      RunningState::Stopped { plugin_id },
+      // Running after Stopped, and a stop after the other.
+      RunningState::Running { plugin_id },
+      RunningState::UnexpectedStop {
+        plugin_id,
+        reason: None,
+      },
+      RunningState::Connected { plugin_id },
+      RunningState::ReadyToConnect,
+    ],
+  );
+  assert_eq!(
+    emitted,
+    vec![
+      RunningState::Connecting,
+      RunningState::Running { plugin_id },
+      RunningState::Stopped { plugin_id },
+    ]
+  );
+  assert_eq!(*machine.borrow(), RunningState::Stopped { plugin_id });
+}
+
+#[test]
+fn replaced_plugin_transitions_test() {
+  let old = PluginId::from(1);
+  let new = PluginId::from(2);
+  let machine = StateMachine::new(RunningState::ReadyToConnect);
+  assert!(machine.connecting(old));
+  assert!(machine.transition(RunningState::Running { plugin_id: old }));
+
+  // The process of `new` starts before the one of `old` reports its stop.
+  assert!(machine.connecting(new));
+  assert!(!machine.transition(RunningState::Stopped { plugin_id: old }));
+  assert!(machine.transition(RunningState::Connected { plugin_id: new }));
+  assert!(!machine.transition(RunningState::Running { plugin_id: old }));
+  assert!(machine.transition(RunningState::Running { plugin_id: new }));
+  assert_eq!(*machine.borrow(), RunningState::Running { plugin_id: new });
+}
+
+#[test]
+fn history_is_bounded_test() {
+  let plugin_id = PluginId::from(1);
+  let machine = StateMachine::new(RunningState::ReadyToConnect);
+  for _ in 0..TRANSITION_HISTORY_LEN {
+    machine.transition(RunningState::Connecting);
+    machine.transition(RunningState::Running { plugin_id });
+  }
+  let history = machine.history();
+  assert_eq!(history.len(), TRANSITION_HISTORY_LEN);
+  assert_eq!(
+    history.last().unwrap().to,
+    RunningState::Running { plugin_id }
+  );
+}
@@ -0,0 +1,115 @@
+use af_plugin::core::plugin::{PluginId, RunningState};
+use af_plugin::core::stream_error::{classify_error, classify_remote_error, StreamErrorKind};
+use af_plugin::error::{PluginError, RemoteError};
+use serde_json::json;
+
+fn classify(error: serde_json::Value) -> StreamErrorKind {
+  classify_remote_error(&RemoteError::from_value(error))
+}
+
+#[test]
+fn classify_context_overflow_test() {
+  for error in [
+    json!({ "code": -32002, "message": "prompt too long" }),
+    // Ollama, relayed by the plugin as an internal error.
+    json!({ "code": -32603, "message": "the input length exceeds the context length" }),
+    json!({ "code": -32603, "message": "ResponseError: input length exceeds maximum context length (status code: 400)" }),
+    // llama.cpp runners of older Ollama versions.
+    json!({ "code": -32603, "message": "requested tokens (5000) exceed context window of 4096" }),
+    json!({ "code": 1, "message": "Prompt is too long" }),
+  ] {
+    assert_eq!(
+      classify(error.clone()),
+      StreamErrorKind::ContextLengthExceeded,
+      "{}",
+      error
+    );
+  }
+}
+
+#[test]
+fn classify_ollama_unreachable_test() {
+  for error in [
+    // The plugin lost the model mid-answer.
+    json!({ "code": -32003, "message": "stream interrupted" }),
+    json!({ "code": -32603, "message": "[Errno 111] Connection refused" }),
+    json!({ "code": -32603, "message": "httpx.ConnectError: All connection attempts failed" }),
+    json!({ "code": 1, "message": "Failed to connect to Ollama. Please check that Ollama is downloaded, running and accessible." }),
+    json!({ "code": -32603, "message": "HTTPConnectionPool(host='localhost', port=11434): Max retries exceeded with url: /api/chat" }),
+  ] {
+    assert_eq!(
+      classify(error.clone()),
+      StreamErrorKind::OllamaUnreachable,
+      "{}",
+      error
+    );
+  }
+}
+
+#[test]
+fn classify_other_remote_errors_test() {
+  assert_eq!(
+    classify(json!({ "code": -32603, "message": "Task was cancelled" })),
+    StreamErrorKind::Cancelled
+  );
+  assert_eq!(
+    classify(json!({ "code": -32603, "message": "division by zero" })),
+    StreamErrorKind::Unknown
+  );
+  assert_eq!(
+    classify(json!("context length exceeded")),
+    StreamErrorKind::ContextLengthExceeded
+  );
+  assert_eq!(
+    classify_remote_error(&RemoteError::ParseResponse(json!(42))),
+    StreamErrorKind::MalformedFrame
+  );
+}
+
+#[test]
+fn classify_disconnect_test() {
+  let plugin_id = PluginId::from(1);
+  assert_eq!(
+    classify_error(
+      &PluginError::PeerDisconnect,
+      &RunningState::UnexpectedStop {
+        plugin_id,
+        reason: None,
+      }
+    ),
+    StreamErrorKind::PluginDied
+  );
+  assert_eq!(
+    classify_error(
+      &PluginError::PeerDisconnect,
+      &RunningState::Running { plugin_id }
+    ),
+    StreamErrorKind::PluginDied
+  );
+  // The plugin was stopped on purpose.
+  assert_eq!(
+    classify_error(
+      &PluginError::PeerDisconnect,
+      &RunningState::Stopped { plugin_id }
+    ),
+    StreamErrorKind::Cancelled
+  );
+  assert_eq!(
+    classify_error(
+      &PluginError::InvalidResponse,
+      &RunningState::Running { plugin_id }
+    ),
+    StreamErrorKind::MalformedFrame
+  );
+
+  let err = PluginError::Stream {
+    kind: StreamErrorKind::PluginDied,
+    source: Box::new(PluginError::PeerDisconnect),
+  };
+  assert_eq!(err.stream_error_kind(), Some(StreamErrorKind::PluginDied));
+  assert!(matches!(err.root_cause(), PluginError::PeerDisconnect));
+  assert_eq!(
+    classify_error(&err, &RunningState::Stopped { plugin_id }),
+    StreamErrorKind::PluginDied
+  );
+}
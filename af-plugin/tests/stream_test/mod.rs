@@ -0,0 +1,142 @@
+use af_plugin::core::parser::ResponseParser;
+use af_plugin::core::stream::{
+  bounded_stream, BackpressurePolicy, BackpressureReport, FrameSender, StreamOptions,
+};
+use af_plugin::error::{PluginError, RemoteError};
+use serde_json::Value as JsonValue;
+use std::thread;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+const REPORT_PREFIX: &str = "backpressure:";
+
+struct TextParser;
+
+impl ResponseParser for TextParser {
+  type ValueType = String;
+
+  fn parse_json(payload: JsonValue) -> Result<Self::ValueType, RemoteError> {
+    payload
+      .as_str()
+      .map(String::from)
+      .ok_or(RemoteError::ParseResponse(payload))
+  }
+
+  fn coalesce(prev: &mut Self::ValueType, next: Self::ValueType) -> Option<Self::ValueType> {
+    prev.push_str(&next);
+    None
+  }
+
+  fn backpressure_frame(report: &BackpressureReport) -> Option<Self::ValueType> {
+    Some(format!(
+      "{}{}",
+      REPORT_PREFIX,
+      serde_json::to_string(report).unwrap()
+    ))
+  }
+}
+
+fn text_frames(count: usize) -> Vec<String> {
+  (0..count).map(|i| format!("word{} ", i)).collect()
+}
+
+/// Sends every frame from a plain thread, like the plugin's reader thread does, and returns the
+/// largest number of frames that were ever buffered.
+fn spawn_producer(tx: FrameSender<TextParser>, frames: Vec<String>) -> thread::JoinHandle<usize> {
+  thread::spawn(move || {
+    let mut max_buffered = 0;
+    for frame in frames {
+      tx.send(Ok(frame));
+      max_buffered = max_buffered.max(tx.buffered());
+    }
+    max_buffered
+  })
+}
+
+/// Reads the stream slowly and splits the received frames from the backpressure report.
+async fn slow_consume(
+  mut stream: tokio_stream::wrappers::ReceiverStream<Result<String, PluginError>>,
+) -> (Vec<String>, Option<BackpressureReport>) {
+  let mut frames = vec![];
+  let mut report = None;
+  while let Some(frame) = stream.next().await {
+    let frame = frame.unwrap();
+    match frame.strip_prefix(REPORT_PREFIX) {
+      Some(json) => report = Some(serde_json::from_str(json).unwrap()),
+      None => frames.push(frame),
+    }
+    tokio::time::sleep(Duration::from_millis(1)).await;
+  }
+  (frames, report)
+}
+
+#[tokio::test]
+async fn coalesce_text_matches_unbounded_stream_test() {
+  let frames = text_frames(2000);
+  let expected = frames.concat();
+  let options = StreamOptions::default()
+    .with_capacity(8)
+    .with_policy(BackpressurePolicy::CoalesceText);
+  let (tx, stream) = bounded_stream::<TextParser>(options).unwrap();
+  let producer = spawn_producer(tx, frames);
+
+  let (received, report) = slow_consume(stream).await;
+  let max_buffered = producer.join().unwrap();
+  assert!(max_buffered <= 8, "max buffered: {}", max_buffered);
+  assert!(received.len() < 2000);
+  assert_eq!(received.concat(), expected);
+
+  let report = report.unwrap();
+  assert_eq!(report.dropped, 0);
+  assert_eq!(report.coalesced + received.len(), 2000);
+}
+
+#[tokio::test]
+async fn drop_oldest_reports_dropped_frames_test() {
+  let options = StreamOptions::default()
+    .with_capacity(4)
+    .with_policy(BackpressurePolicy::DropOldest);
+  let (tx, stream) = bounded_stream::<TextParser>(options).unwrap();
+  let producer = spawn_producer(tx, text_frames(500));
+
+  let (received, report) = slow_consume(stream).await;
+  let max_buffered = producer.join().unwrap();
+  assert!(max_buffered <= 4, "max buffered: {}", max_buffered);
+
+  let report = report.unwrap();
+  assert!(report.dropped > 0);
+  assert_eq!(report.coalesced, 0);
+  assert_eq!(report.dropped + received.len(), 500);
+  assert_eq!(received.last().unwrap(), "word499 ");
+}
+
+#[tokio::test]
+async fn block_policy_delivers_every_frame_test() {
+  let frames = text_frames(200);
+  let options = StreamOptions::default().with_capacity(4);
+  let (tx, stream) = bounded_stream::<TextParser>(options).unwrap();
+  let producer = spawn_producer(tx, frames.clone());
+
+  let (received, report) = slow_consume(stream).await;
+  let max_buffered = producer.join().unwrap();
+  assert!(max_buffered <= 4, "max buffered: {}", max_buffered);
+  assert_eq!(received, frames);
+  assert!(report.is_none());
+}
+
+#[tokio::test]
+async fn dropping_stream_unblocks_producer_test() {
+  let options = StreamOptions::default().with_capacity(2);
+  let (tx, stream) = bounded_stream::<TextParser>(options).unwrap();
+  drop(stream);
+  let producer = spawn_producer(tx, text_frames(100));
+  tokio::task::spawn_blocking(move || producer.join().unwrap())
+    .await
+    .unwrap();
+}
+
+#[test]
+fn bounded_stream_outside_runtime_test() {
+  let result = bounded_stream::<TextParser>(StreamOptions::default());
+  assert!(matches!(result, Err(PluginError::Internal(_))));
+}
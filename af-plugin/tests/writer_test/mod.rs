@@ -0,0 +1,149 @@
+use af_plugin::core::plugin::{Peer, RunningState};
+use af_plugin::core::rpc_loop::RpcLoop;
+use af_plugin::error::PluginError;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::io::{self, Write};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Takes `delay` for each write, like the stdin of a plugin busy running a model, and fails the
+/// writes containing `fail_on`.
+#[derive(Clone)]
+struct SlowWriter {
+  delay: Duration,
+  fail_on: Option<&'static str>,
+  written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SlowWriter {
+  fn new(delay: Duration) -> Self {
+    Self {
+      delay,
+      fail_on: None,
+      written: Default::default(),
+    }
+  }
+
+  fn lines(&self) -> Vec<String> {
+    String::from_utf8(self.written.lock().clone())
+      .unwrap()
+      .lines()
+      .map(str::to_string)
+      .collect()
+  }
+}
+
+impl Write for SlowWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    std::thread::sleep(self.delay);
+    if let Some(fail_on) = self.fail_on {
+      if String::from_utf8_lossy(buf).contains(fail_on) {
+        return Err(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+      }
+    }
+    self.written.lock().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn looper(writer: SlowWriter, write_queue_frames: usize) -> RpcLoop<SlowWriter> {
+  let (running_state, _rx) = watch::channel(RunningState::ReadyToConnect);
+  RpcLoop::with_write_queue(
+    writer,
+    Arc::new(running_state.into()),
+    None,
+    write_queue_frames,
+  )
+  .unwrap()
+}
+
+#[test]
+fn senders_do_not_wait_for_slow_writer_test() {
+  let writer = SlowWriter::new(Duration::from_millis(100));
+  let looper = looper(writer.clone(), 16);
+  let peer = looper.get_raw_peer();
+
+  let start = Instant::now();
+  for index in 0..5 {
+    peer.send_rpc_notification("progress", &json!({ "index": index }));
+  }
+  peer.async_send_rpc_request("answer", &json!({}), Box::new(|_| {}));
+  assert!(
+    start.elapsed() < Duration::from_millis(100),
+    "{:?}",
+    start.elapsed()
+  );
+
+  // The frames are written in the order they were sent.
+  assert!(peer.flush(Duration::from_secs(5)));
+  let lines = writer.lines();
+  assert_eq!(lines.len(), 6);
+  assert!(lines[0].contains(r#""index":0"#), "{}", lines[0]);
+  assert!(lines[5].contains(r#""method":"answer""#), "{}", lines[5]);
+}
+
+#[test]
+fn full_write_queue_fails_request_test() {
+  let writer = SlowWriter::new(Duration::from_millis(200));
+  let looper = looper(writer.clone(), 1);
+  let peer = looper.get_raw_peer();
+
+  let (tx, rx) = mpsc::channel();
+  for index in 0..3 {
+    let tx = tx.clone();
+    peer.async_send_rpc_request(
+      "answer",
+      &json!({ "index": index }),
+      Box::new(move |result| {
+        let _ = tx.send((index, result));
+      }),
+    );
+    if index == 0 {
+      // Lets the writer thread start writing the first frame.
+      std::thread::sleep(Duration::from_millis(50));
+    }
+  }
+  // The first frame is being written and the second fills the queue.
+  let (index, result) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+  assert_eq!(index, 2);
+  match result {
+    Err(PluginError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+    result => panic!("unexpected result: {:?}", result),
+  }
+}
+
+#[test]
+fn write_error_fails_its_request_test() {
+  let mut writer = SlowWriter::new(Duration::from_millis(10));
+  writer.fail_on = Some("broken");
+  let looper = looper(writer.clone(), 16);
+  let peer = looper.get_raw_peer();
+
+  let (tx, rx) = mpsc::channel();
+  for method in ["first", "broken", "last"] {
+    let tx = tx.clone();
+    peer.async_send_rpc_request(
+      method,
+      &json!({}),
+      Box::new(move |result| {
+        let _ = tx.send((method, result));
+      }),
+    );
+  }
+  let (method, result) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+  assert_eq!(method, "broken");
+  assert!(matches!(result, Err(PluginError::Io(_))), "{:?}", result);
+
+  // The other requests were written and wait for their answer.
+  assert!(peer.flush(Duration::from_secs(5)));
+  assert!(rx.try_recv().is_err());
+  let lines = writer.lines();
+  assert_eq!(lines.len(), 2);
+  assert!(lines[1].contains(r#""method":"last""#), "{}", lines[1]);
+}